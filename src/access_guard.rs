@@ -0,0 +1,169 @@
+use crate::*;
+/// Grants temporary read access to [`Pages`], restoring their original protection when dropped. Created using
+/// [`Pages::read_guard`].
+pub struct ReadGuard<'a, R: ReadPremisionMarker, W: WritePremisionMarker, E: ExecPremisionMarker> {
+    pages: &'a mut Pages<R, W, E>,
+}
+/// Grants temporary read and write access to [`Pages`], restoring their original protection when dropped. Created using
+/// [`Pages::write_guard`].
+pub struct WriteGuard<'a, R: ReadPremisionMarker, W: WritePremisionMarker, E: ExecPremisionMarker> {
+    pages: &'a mut Pages<R, W, E>,
+}
+impl<R: ReadPremisionMarker, W: WritePremisionMarker, E: ExecPremisionMarker> Pages<R, W, E> {
+    /// Makes this [`Pages`] readable until the returned [`ReadGuard`] is dropped. Other permissions are left unchanged.
+    /// Original protection is restored even if the guard is dropped during unwinding, so data can be kept inaccessible
+    /// by default, and only exposed for as long as it is needed.
+    /// # Panics
+    /// Panics if kernel can't/refuses to change protection of this [`Pages`].
+    /// # Examples
+    /// ```
+    /// # use memory_pages::*;
+    /// let mut secret:Pages<DenyRead,DenyWrite,DenyExec> = Pages::new(0x1000);
+    /// {
+    ///     let key = secret.read_guard();
+    ///     assert_eq!(key[0], 0);
+    /// }
+    /// // `secret` is inaccessible again.
+    /// ```
+    pub fn read_guard(&mut self) -> ReadGuard<'_, R, W, E> {
+        if !R::allow_read() {
+            self.protect_as::<AllowRead, W, E>();
+        }
+        ReadGuard { pages: self }
+    }
+    /// Makes this [`Pages`] readable and writable until the returned [`WriteGuard`] is dropped. Execution is denied
+    /// while the guard exists, so pages are never both writable and executable. Original protection is restored even
+    /// if the guard is dropped during unwinding, but code in executable pages which is rejected by the installed
+    /// [`CodeVerifier`] is left read-only and not executable instead.
+    /// # Panics
+    /// Panics if kernel can't/refuses to change protection of this [`Pages`].
+    /// # Examples
+    /// ```
+    /// # use memory_pages::*;
+    /// let mut secret:Pages<DenyRead,DenyWrite,DenyExec> = Pages::new(0x1000);
+    /// secret.write_guard()[..4].copy_from_slice(b"key!");
+    /// assert_eq!(&secret.read_guard()[..4], b"key!");
+    /// ```
+    pub fn write_guard(&mut self) -> WriteGuard<'_, R, W, E> {
+        if !Self::is_read_write() {
+            self.protect_as::<AllowRead, AllowWrite, DenyExec>();
+        }
+        WriteGuard { pages: self }
+    }
+    fn is_read_write() -> bool {
+        R::allow_read() && W::allow_write() && !E::allow_exec()
+    }
+    /// Restores protection described by the type of this [`Pages`] after a [`WriteGuard`] is released.
+    /// # Panics
+    /// Panics if kernel can't/refuses to change protection of this [`Pages`], unless a panic is already in progress.
+    pub(crate) fn restore_after_write(&self) {
+        // Failing to restore protection leaves the pages writable, but never executable.
+        if !Self::is_read_write() && !self.try_set_prot() {
+            protection_not_restored();
+        }
+        if E::allow_exec() {
+            flush_icache_range(self.ptr.as_ptr(), self.len);
+            // Without kernel support, other threads see new code once they are interrupted, e.g. by the scheduler.
+            let _ = sync_code_across_threads();
+        }
+    }
+    /// Checks code written into executable [`Pages`] while they were writable, before they become executable again.
+    /// Rejected code is left read-only and not executable.
+    #[cfg(any(feature = "allow_exec", doc, test))]
+    pub(crate) fn verify_written_code(&self) -> Result<(), VerificationError> {
+        let res = code_verifier::verify_code(self.ptr.as_ptr(), self.len);
+        if res.is_err()
+            && dyn_pages::protect_range_unchecked(self.ptr.as_ptr(), self.len, Protection::Read)
+                .is_err()
+        {
+            protection_not_restored();
+        }
+        res
+    }
+}
+/// Reports that protection of guarded [`Pages`] could not be restored. Panicking while a guard is dropped during
+/// unwinding would abort, so the pages are silently left accessible in that case.
+fn protection_not_restored() {
+    if !std::thread::panicking() {
+        panic!("Failed to restore memory protection mode!");
+    }
+}
+impl<'a, R: ReadPremisionMarker, W: WritePremisionMarker, E: ExecPremisionMarker>
+    WriteGuard<'a, R, W, E>
+{
+    /// Releases this guard without restoring original protection of its [`Pages`].
+    pub(crate) fn into_pages(self) -> &'a mut Pages<R, W, E> {
+        let this = std::mem::ManuallyDrop::new(self);
+        // `this` is never dropped, so the reference can be moved out of it.
+        unsafe { std::ptr::read(&this.pages) }
+    }
+}
+impl<R: ReadPremisionMarker, W: WritePremisionMarker, E: ExecPremisionMarker> Deref
+    for ReadGuard<'_, R, W, E>
+{
+    type Target = [u8];
+    fn deref(&self) -> &[u8] {
+        unsafe { std::slice::from_raw_parts(self.pages.ptr.as_ptr(), self.pages.len) }
+    }
+}
+impl<R: ReadPremisionMarker, W: WritePremisionMarker, E: ExecPremisionMarker> Drop
+    for ReadGuard<'_, R, W, E>
+{
+    fn drop(&mut self) {
+        if !R::allow_read() && !self.pages.try_set_prot() {
+            protection_not_restored();
+        }
+    }
+}
+impl<R: ReadPremisionMarker, W: WritePremisionMarker, E: ExecPremisionMarker> Deref
+    for WriteGuard<'_, R, W, E>
+{
+    type Target = [u8];
+    fn deref(&self) -> &[u8] {
+        unsafe { std::slice::from_raw_parts(self.pages.ptr.as_ptr(), self.pages.len) }
+    }
+}
+impl<R: ReadPremisionMarker, W: WritePremisionMarker, E: ExecPremisionMarker> DerefMut
+    for WriteGuard<'_, R, W, E>
+{
+    fn deref_mut(&mut self) -> &mut [u8] {
+        unsafe { std::slice::from_raw_parts_mut(self.pages.ptr.as_ptr(), self.pages.len) }
+    }
+}
+impl<R: ReadPremisionMarker, W: WritePremisionMarker, E: ExecPremisionMarker> Drop
+    for WriteGuard<'_, R, W, E>
+{
+    fn drop(&mut self) {
+        // Panicking while the guard is dropped during unwinding would abort, so code rejected by the verifier is left
+        // read-only instead.
+        #[cfg(any(feature = "allow_exec", doc, test))]
+        if E::allow_exec() && self.pages.verify_written_code().is_err() {
+            return;
+        }
+        self.pages.restore_after_write();
+    }
+}
+#[cfg(test)]
+mod test {
+    use super::*;
+    #[test]
+    fn test_guards_nest_sequentially() {
+        let mut pages: Pages<DenyRead, DenyWrite, DenyExec> = Pages::new(0x2000);
+        for _ in 0..4 {
+            let mut write = pages.write_guard();
+            write[0x1FFF] += 1;
+        }
+        assert_eq!(pages.read_guard()[0x1FFF], 4);
+    }
+    #[test]
+    fn test_guard_restores_on_panic() {
+        let mut pages: Pages<AllowRead, DenyWrite, DenyExec> = Pages::new(0x1000);
+        let res = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+            let mut write = pages.write_guard();
+            write[0] = 3;
+            panic!("Failed while holding a guard!");
+        }));
+        assert!(res.is_err());
+        assert_eq!(pages[0], 3);
+    }
+}
@@ -0,0 +1,248 @@
+use crate::*;
+use std::cell::{Cell, RefCell};
+use std::fmt::{Debug, Formatter};
+use std::rc::Rc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex, Weak};
+/// Memory use of a single thread's arena, as of the last time the thread used it.
+#[derive(Default)]
+struct ThreadStats {
+    allocated: AtomicUsize,
+    committed: AtomicUsize,
+}
+/// State of an [`ArenaRegistry`] shared with the threads using it.
+struct Shared {
+    arena_size: usize,
+    /// Bumped to request all arenas to be reset.
+    epoch: AtomicUsize,
+    /// Bumped to request all arenas to be reset, and to return their memory to the kernel.
+    decommit_epoch: AtomicUsize,
+    /// Statistics of all threads which used the registry. Entries of exited threads are dead.
+    threads: Mutex<Vec<Weak<ThreadStats>>>,
+}
+/// Arena of one thread, for one registry.
+struct LocalArena {
+    registry: Weak<Shared>,
+    arena: RefCell<PageArena>,
+    /// Epochs of the registry as of the last reset of `arena`.
+    epoch: Cell<usize>,
+    decommit_epoch: Cell<usize>,
+    stats: Arc<ThreadStats>,
+}
+thread_local! {
+    static LOCAL_ARENAS: RefCell<Vec<Rc<LocalArena>>> = const { RefCell::new(Vec::new()) };
+}
+/// Totals of memory used by all arenas of an [`ArenaRegistry`]. Returned by [`ArenaRegistry::stats`].
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct ArenaStats {
+    /// Number of live threads which have an arena.
+    pub threads: usize,
+    /// Number of bytes handed out by all arenas since their last reset.
+    pub allocated_bytes: usize,
+    /// Number of bytes backed by memory in all arenas.
+    pub committed_bytes: usize,
+}
+/// Hands out a separate [`PageArena`] to each thread, so that threads of a server can allocate scratch memory for a
+/// request without ever contending on a lock. Each thread's arena is created the first time it calls
+/// [`Self::with_arena`], and freed when the thread exits.
+///
+/// The registry keeps track of all arenas, so their memory use can be inspected with [`Self::stats`], and all of them
+/// can be reset at once with [`Self::reset_all`], e.g. at the end of a frame. Resetting is lazy: each arena resets
+/// itself the next time its thread enters it, so no thread ever touches another one's arena. Allocations can't escape
+/// the closure passed to [`Self::with_arena`], so they are never used after their arena is reset.
+/// # Examples
+/// ```
+/// # use memory_pages::*;
+/// // 1 GiB of address space per thread, only backed by memory once used.
+/// let scratch = ArenaRegistry::new(0x4000_0000);
+/// std::thread::scope(|s| {
+///     for request in 0..4 {
+///         let scratch = &scratch;
+///         s.spawn(move || {
+///             let len = scratch.with_arena(|arena| {
+///                 let response = arena.alloc_str(&format!("response to request {request}"));
+///                 response.len()
+///             });
+///             assert_eq!(len, 21);
+///         });
+///     }
+/// });
+/// // Frees everything allocated by all threads, once they enter their arenas again.
+/// scratch.reset_all();
+/// ```
+pub struct ArenaRegistry {
+    shared: Arc<Shared>,
+}
+impl ArenaRegistry {
+    /// Creates a new [`ArenaRegistry`], in which each thread's arena reserves `arena_size` bytes of address space. No
+    /// arenas are created until threads use them.
+    #[must_use]
+    pub fn new(arena_size: usize) -> Self {
+        Self {
+            shared: Arc::new(Shared {
+                arena_size,
+                epoch: AtomicUsize::new(0),
+                decommit_epoch: AtomicUsize::new(0),
+                threads: Mutex::new(Vec::new()),
+            }),
+        }
+    }
+    /// Returns the number of bytes of address space each arena reserves.
+    #[must_use]
+    pub fn arena_size(&self) -> usize {
+        self.shared.arena_size
+    }
+    /// Calls `f` with the arena of the current thread, creating it if this thread never used this registry before. If
+    /// a reset was requested since the arena was last used, it is reset first, unless this thread is already inside
+    /// [`Self::with_arena`] of this registry, in which case the reset is postponed until it leaves.
+    /// # Panics
+    /// Panics if kernel can't/refuses to reserve address space for a new arena.
+    pub fn with_arena<R>(&self, f: impl FnOnce(&PageArena) -> R) -> R {
+        let local = self.local_arena();
+        let epoch = self.shared.epoch.load(Ordering::Acquire);
+        let decommit_epoch = self.shared.decommit_epoch.load(Ordering::Acquire);
+        let stale = local.epoch.get() != epoch || local.decommit_epoch.get() != decommit_epoch;
+        if stale {
+            // Arena is only borrowed mutably if no allocations from it are alive.
+            if let Ok(mut arena) = local.arena.try_borrow_mut() {
+                if local.decommit_epoch.get() == decommit_epoch {
+                    arena.reset();
+                } else {
+                    arena.reset_decommit();
+                }
+                local.epoch.set(epoch);
+                local.decommit_epoch.set(decommit_epoch);
+            }
+        }
+        let arena = local.arena.borrow();
+        let res = f(&arena);
+        local
+            .stats
+            .allocated
+            .store(arena.allocated_bytes(), Ordering::Relaxed);
+        local
+            .stats
+            .committed
+            .store(arena.committed_bytes(), Ordering::Relaxed);
+        res
+    }
+    /// Requests all arenas to be reset. Each arena frees everything allocated in it the next time its thread calls
+    /// [`Self::with_arena`], keeping its committed pages for reuse.
+    pub fn reset_all(&self) {
+        self.shared.epoch.fetch_add(1, Ordering::Release);
+    }
+    /// Requests all arenas to be reset, like [`Self::reset_all`], and to return all of their memory to the kernel.
+    pub fn reset_all_decommit(&self) {
+        self.shared.decommit_epoch.fetch_add(1, Ordering::Release);
+    }
+    /// Returns memory use of all arenas of live threads, as of the last time each of them left [`Self::with_arena`].
+    #[must_use]
+    pub fn stats(&self) -> ArenaStats {
+        let mut threads = self
+            .shared
+            .threads
+            .lock()
+            .unwrap_or_else(std::sync::PoisonError::into_inner);
+        threads.retain(|stats| stats.strong_count() > 0);
+        threads
+            .iter()
+            .filter_map(Weak::upgrade)
+            .fold(ArenaStats::default(), |total, stats| ArenaStats {
+                threads: total.threads + 1,
+                allocated_bytes: total.allocated_bytes + stats.allocated.load(Ordering::Relaxed),
+                committed_bytes: total.committed_bytes + stats.committed.load(Ordering::Relaxed),
+            })
+    }
+    /// Returns the arena of the current thread, creating and registering it if needed.
+    fn local_arena(&self) -> Rc<LocalArena> {
+        LOCAL_ARENAS.with(|arenas| {
+            let mut arenas = arenas.borrow_mut();
+            if let Some(local) = arenas
+                .iter()
+                .find(|local| std::ptr::eq(local.registry.as_ptr(), Arc::as_ptr(&self.shared)))
+            {
+                return local.clone();
+            }
+            // Arenas of dropped registries are released lazily, whenever a new arena is created.
+            arenas.retain(|local| local.registry.strong_count() > 0);
+            let stats = Arc::new(ThreadStats::default());
+            self.shared
+                .threads
+                .lock()
+                .unwrap_or_else(std::sync::PoisonError::into_inner)
+                .push(Arc::downgrade(&stats));
+            let local = Rc::new(LocalArena {
+                registry: Arc::downgrade(&self.shared),
+                arena: RefCell::new(PageArena::new(self.shared.arena_size)),
+                epoch: Cell::new(self.shared.epoch.load(Ordering::Acquire)),
+                decommit_epoch: Cell::new(self.shared.decommit_epoch.load(Ordering::Acquire)),
+                stats,
+            });
+            arenas.push(local.clone());
+            local
+        })
+    }
+}
+impl Debug for ArenaRegistry {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("ArenaRegistry")
+            .field("arena_size", &self.arena_size())
+            .field("stats", &self.stats())
+            .finish()
+    }
+}
+#[cfg(test)]
+mod test {
+    use super::*;
+    #[test]
+    fn test_registry_stats_and_reset() {
+        let registry = ArenaRegistry::new(0x100_000);
+        let barrier = std::sync::Barrier::new(4);
+        std::thread::scope(|s| {
+            for thread in 0..3 {
+                let (registry, barrier) = (&registry, &barrier);
+                s.spawn(move || {
+                    registry.with_arena(|arena| arena.alloc_slice_copy(&[0_u8; 0x1000]).len());
+                    barrier.wait();
+                    // The main thread requests a reset here.
+                    barrier.wait();
+                    let allocated = registry.with_arena(|arena| {
+                        let before = arena.allocated_bytes();
+                        arena.alloc(thread as u64);
+                        before
+                    });
+                    assert_eq!(allocated, 0);
+                    barrier.wait();
+                    // Arenas are freed once their threads exit, so they must stay alive until they are counted.
+                    barrier.wait();
+                });
+            }
+            barrier.wait();
+            let stats = registry.stats();
+            assert_eq!(stats.threads, 3);
+            assert_eq!(stats.allocated_bytes, 0x3000);
+            registry.reset_all_decommit();
+            barrier.wait();
+            barrier.wait();
+            assert_eq!(registry.stats().allocated_bytes, 3 * 8);
+            barrier.wait();
+        });
+    }
+    #[test]
+    fn test_registry_nested_reset_postponed() {
+        let registry = ArenaRegistry::new(0x10_000);
+        registry.with_arena(|outer| {
+            let value = outer.alloc(7_u32);
+            registry.reset_all();
+            // The outer allocation is still alive, so the arena can't be reset yet.
+            registry.with_arena(|inner| assert_eq!(inner.allocated_bytes(), 4));
+            assert_eq!(*value, 7);
+        });
+        assert_eq!(registry.with_arena(|arena| arena.allocated_bytes()), 0);
+        // Each registry has its own arena.
+        let other = ArenaRegistry::new(0x10_000);
+        other.with_arena(|arena| *arena.alloc(1_u8));
+        assert_eq!(registry.with_arena(|arena| arena.allocated_bytes()), 0);
+        assert_eq!(other.stats().allocated_bytes, 1);
+    }
+}
@@ -0,0 +1,148 @@
+use crate::*;
+use std::sync::Arc;
+/// A region of memory shared by all [`Pages`] allocated in a single batch. Released when the last of them is dropped.
+pub(crate) struct BatchRegion {
+    ptr: *mut u8,
+    len: usize,
+}
+// `BatchRegion` is only used to release the region it owns, which may happen on any thread.
+unsafe impl Send for BatchRegion {}
+unsafe impl Sync for BatchRegion {}
+impl<R: ReadPremisionMarker, W: WritePremisionMarker, E: ExecPremisionMarker> Pages<R, W, E> {
+    /// Allocates multiple [`Pages`] with lengths `lengths` using a single system call. Each of returned [`Pages`] has its
+    /// length rounded up to the next page boundary, just like when allocated with [`Self::new`], and can be used and
+    /// dropped independently of the others. Memory of dropped [`Pages`] is returned to the kernel right away, while the
+    /// address space is released when all [`Pages`] from a batch are dropped.
+    ///
+    /// For workloads making thousands of small allocations, this is considerably faster than calling [`Self::new`]
+    /// for each of them.
+    /// # Panics
+    /// Panics when a 0-sized allocation is attempted, or if kernel can't/refuses to allocate requested Pages.
+    /// # Examples
+    ///```
+    /// # use memory_pages::*;
+    /// let mut batch:Vec<Pages<AllowRead,AllowWrite,DenyExec>> = Pages::new_batch(&[0x1000, 0x1234, 0x4000]);
+    /// assert_eq!(batch[1].len(), 0x2000);
+    /// batch[2][0x3FFF] = 3;
+    /// // Dropping one of the pages does not affect the others.
+    /// batch.remove(0);
+    /// assert_eq!(batch[1][0x3FFF], 3);
+    ///```
+    #[must_use]
+    pub fn new_batch(lengths: &[usize]) -> Vec<Self> {
+        assert!(
+            lengths.iter().all(|length| *length != 0),
+            "0 - sized allcations are not allowed!"
+        );
+        if lengths.is_empty() {
+            return Vec::new();
+        }
+        let total = lengths
+            .iter()
+            .try_fold(0_usize, |total, length| {
+                total.checked_add(align_up(*length))
+            })
+            .expect("Total length of batch allocation overflows!");
+        let region = std::mem::ManuallyDrop::new(Self::new(total));
+        let shared = Arc::new(BatchRegion {
+            ptr: region.ptr.as_ptr(),
+            len: region.len,
+        });
+        let mut offset = 0;
+        lengths
+            .iter()
+            .map(|length| {
+                let len = align_up(*length);
+                let pages = Self {
+                    ptr: unsafe { region.ptr.add(offset) },
+                    len,
+                    backing: Backing::Batch(shared.clone()),
+                    read: PhantomData,
+                    write: PhantomData,
+                    exec: PhantomData,
+                };
+                offset += len;
+                pages
+            })
+            .collect()
+    }
+}
+impl Drop for BatchRegion {
+    fn drop(&mut self) {
+        #[cfg(target_family = "unix")]
+        unsafe {
+            let res = munmap(self.ptr.cast::<c_void>(), self.len);
+            if res == -1 {
+                let err = errno_msg();
+                panic!("Unampping batch region failed. Reason:{err}");
+            }
+        }
+        #[cfg(target_family = "windows")]
+        unsafe {
+            let res = VirtualFree(self.ptr.cast::<winapi::ctypes::c_void>(), 0, MEM_RELEASE);
+            if res == 0 {
+                let err = winapi::um::errhandlingapi::GetLastError();
+                panic!("Releasing batch region using VirtualFree failed with error code:{err}!");
+            }
+        }
+    }
+}
+impl BatchRegion {
+    /// Returns memory behind a part of this region to the kernel, keeping its address space reserved.
+    pub(crate) fn release_part(&self, ptr: *mut u8, len: usize) {
+        debug_assert!(ptr >= self.ptr && ptr as usize + len <= self.ptr as usize + self.len);
+        #[cfg(target_family = "unix")]
+        unsafe {
+            const MADV_DONTNEED: c_int = 4;
+            madvise(ptr.cast::<c_void>(), len, MADV_DONTNEED);
+        }
+        #[cfg(target_family = "windows")]
+        unsafe {
+            VirtualFree(ptr.cast::<winapi::ctypes::c_void>(), len, MEM_DECOMMIT);
+        }
+    }
+}
+#[cfg(test)]
+mod test {
+    use super::*;
+    #[test]
+    fn test_batch_independent() {
+        let lengths: Vec<usize> = (1..=256).map(|i| i * 0x100).collect();
+        let mut batch: Vec<Pages<AllowRead, AllowWrite, DenyExec>> = Pages::new_batch(&lengths);
+        for (i, pages) in batch.iter_mut().enumerate() {
+            assert!(pages.len() >= lengths[i]);
+            pages.fill(i as u8);
+        }
+        // Drop every other allocation.
+        let kept: Vec<_> = batch
+            .into_iter()
+            .enumerate()
+            .filter(|(i, _)| i % 2 == 1)
+            .collect();
+        for (i, pages) in &kept {
+            assert!(pages.iter().all(|b| *b == *i as u8));
+        }
+    }
+    #[test]
+    fn test_batch_prot_change() {
+        let mut batch: Vec<Pages<AllowRead, AllowWrite, DenyExec>> =
+            Pages::new_batch(&[0x1000, 0x1000]);
+        let second = batch.pop().unwrap();
+        batch[0][0] = 1;
+        let second = second.deny_write();
+        assert_eq!(second[0], 0);
+        // Changing permissions of one allocation does not affect the others.
+        batch[0][1] = 2;
+        assert_eq!(batch[0][1], 2);
+    }
+    #[test]
+    fn test_batch_resize() {
+        let mut batch: Vec<Pages<AllowRead, AllowWrite, DenyExec>> =
+            Pages::new_batch(&[0x1000, 0x1000]);
+        batch[0][0xFFF] = 5;
+        batch[0].resize(0x3000);
+        assert_eq!(batch[0][0xFFF], 5);
+        batch[1][0] = 6;
+        assert_eq!(batch[1][0], 6);
+    }
+}
@@ -0,0 +1,137 @@
+//! Support for AArch64 branch target identification (BTI) and pointer authentication (PAC), which distributions
+//! increasingly enforce on executable memory.
+//!
+//! Once [`enable_bti`] is called, executable [`crate::Pages`] are guarded by BTI, so indirect branches into them must
+//! land on a BTI instruction, or on `PACIASP`, which doubles as one. Jitted functions called through
+//! [`crate::FnRef`]s should start with [`BranchTarget::Call`] landing pads, and code reached by computed jumps with
+//! [`BranchTarget::Jump`] ones. Landing pads and PAC instructions live in the hint space, so they do nothing on CPUs
+//! without those features, and can be emitted unconditionally.
+#[cfg(all(target_os = "linux", target_arch = "aarch64"))]
+use crate::*;
+use std::sync::atomic::{AtomicBool, Ordering};
+/// Set when executable pages should be guarded by BTI.
+static BTI: AtomicBool = AtomicBool::new(false);
+/// `PACIASP`, which signs the return address in the link register. Placed at function entry, also acts as a
+/// [`BranchTarget::Call`] landing pad.
+pub const PACIASP: u32 = 0xD503_233F;
+/// `AUTIASP`, which authenticates the return address signed by [`PACIASP`]. Placed right before the function returns.
+pub const AUTIASP: u32 = 0xD503_23BF;
+/// Kind of indirect branch which may land on a BTI landing pad.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum BranchTarget {
+    /// Target of indirect calls (`BLR`), e.g. function entry points.
+    Call,
+    /// Target of indirect jumps (`BR`), e.g. jump tables.
+    Jump,
+    /// Target of both indirect calls and jumps.
+    CallOrJump,
+}
+impl BranchTarget {
+    /// Returns the encoding of the `BTI` landing pad for this kind of branch.
+    /// # Examples
+    /// ```
+    /// # use memory_pages::branch_protection::*;
+    /// // `BTI c`
+    /// assert_eq!(BranchTarget::Call.instruction(), 0xD503_245F);
+    /// ```
+    #[must_use]
+    pub const fn instruction(self) -> u32 {
+        match self {
+            Self::Call => 0xD503_245F,
+            Self::Jump => 0xD503_249F,
+            Self::CallOrJump => 0xD503_24DF,
+        }
+    }
+}
+/// Guards all pages made executable from now on with BTI, if the CPU and kernel support it. Pages which are already
+/// executable become guarded the next time their protection changes. Returns whether BTI is enabled.
+///
+/// Only supported on AArch64 Linux, does nothing and returns false on other systems.
+/// # Examples
+/// ```
+/// # use memory_pages::branch_protection::*;
+/// if enable_bti() {
+///     // Jitted functions must now start with a landing pad.
+///     assert!(bti_enabled());
+/// }
+/// ```
+pub fn enable_bti() -> bool {
+    #[cfg(all(target_os = "linux", target_arch = "aarch64"))]
+    {
+        extern "C" {
+            fn getauxval(kind: std::ffi::c_ulong) -> std::ffi::c_ulong;
+        }
+        const AT_HWCAP2: std::ffi::c_ulong = 26;
+        const HWCAP2_BTI: std::ffi::c_ulong = 1 << 17;
+        if unsafe { getauxval(AT_HWCAP2) } & HWCAP2_BTI != 0 {
+            BTI.store(true, Ordering::Relaxed);
+        }
+    }
+    bti_enabled()
+}
+/// Checks if executable pages are guarded by BTI, as enabled with [`enable_bti`].
+#[must_use]
+pub fn bti_enabled() -> bool {
+    BTI.load(Ordering::Relaxed)
+}
+/// Adds `PROT_BTI` to protection `mask`, if it allows execution and BTI is enabled.
+#[cfg(target_family = "unix")]
+pub(crate) fn with_bti(mask: std::ffi::c_int) -> std::ffi::c_int {
+    #[cfg(all(target_os = "linux", target_arch = "aarch64"))]
+    {
+        const PROT_EXEC: c_int = 0x4;
+        const PROT_BTI: c_int = 0x10;
+        if mask & PROT_EXEC != 0 && bti_enabled() {
+            return mask | PROT_BTI;
+        }
+    }
+    mask
+}
+/// Removes the pointer authentication code from `address`, e.g. a return address read from the stack of code compiled
+/// with PAC, leaving just the address. Returns `address` unchanged on other architectures, or when PAC is unsupported.
+/// # Examples
+/// ```
+/// # use memory_pages::branch_protection::*;
+/// let address = strip_pac as usize;
+/// assert_eq!(strip_pac(address), address);
+/// ```
+#[must_use]
+pub fn strip_pac(address: usize) -> usize {
+    #[cfg(target_arch = "aarch64")]
+    {
+        let mut address = address;
+        // `XPACLRI`, encoded as a hint, so it assembles without PAC enabled.
+        unsafe {
+            std::arch::asm!(
+                "hint #7",
+                inout("x30") address,
+                options(nomem, nostack, preserves_flags)
+            );
+        }
+        address
+    }
+    #[cfg(not(target_arch = "aarch64"))]
+    address
+}
+#[cfg(test)]
+mod test {
+    use super::*;
+    #[test]
+    fn test_bti_mask() {
+        // Enabling BTI where it is supported would break other tests, which jit code without landing pads.
+        #[cfg(not(all(target_os = "linux", target_arch = "aarch64")))]
+        assert!(!enable_bti());
+        assert!(!bti_enabled());
+        // Pages which can't execute never need a guard.
+        #[cfg(target_family = "unix")]
+        assert_eq!(with_bti(1 | 2), 1 | 2);
+        // Landing pads are hints, like `NOP`.
+        for target in [
+            BranchTarget::Call,
+            BranchTarget::Jump,
+            BranchTarget::CallOrJump,
+        ] {
+            assert_eq!(target.instruction() & 0xFFFF_F01F, 0xD503_201F);
+        }
+    }
+}
@@ -0,0 +1,279 @@
+use crate::*;
+use std::sync::atomic::{AtomicBool, AtomicU32, AtomicU8, AtomicUsize, Ordering};
+/// `INT3`, which raises a breakpoint exception.
+#[cfg(target_arch = "x86_64")]
+const BREAKPOINT: &[u8] = &[0xCC];
+/// `BRK #0`, which raises a breakpoint exception.
+#[cfg(target_arch = "aarch64")]
+const BREAKPOINT: &[u8] = &0xD420_0000_u32.to_le_bytes();
+/// Maximal number of simultaneously inserted breakpoints.
+const MAX_BREAKPOINTS: usize = 64;
+const SLOT_FREE: u8 = 0;
+const SLOT_BUSY: u8 = 1;
+const SLOT_ACTIVE: u8 = 2;
+/// Inserted breakpoint. Breakpoints are looked up from inside signal handlers, so they are kept in a fixed table,
+/// which never needs to be locked.
+struct Slot {
+    state: AtomicU8,
+    address: AtomicUsize,
+    /// Bytes overwritten by the breakpoint instruction.
+    original: AtomicU32,
+    /// Whether the pages containing the breakpoint are readable, as well as executable.
+    readable: AtomicBool,
+}
+#[allow(clippy::declare_interior_mutable_const)]
+const EMPTY_SLOT: Slot = Slot {
+    state: AtomicU8::new(SLOT_FREE),
+    address: AtomicUsize::new(0),
+    original: AtomicU32::new(0),
+    readable: AtomicBool::new(false),
+};
+static SLOTS: [Slot; MAX_BREAKPOINTS] = [EMPTY_SLOT; MAX_BREAKPOINTS];
+/// Number of slots which are not free, so pages can skip searching for breakpoints when none are inserted.
+static LIVE: AtomicUsize = AtomicUsize::new(0);
+/// Returns the slot of a breakpoint at `address`, which is in use or being removed.
+fn find(address: usize) -> Option<&'static Slot> {
+    if LIVE.load(Ordering::Acquire) == 0 {
+        return None;
+    }
+    SLOTS.iter().find(|slot| {
+        slot.state.load(Ordering::Acquire) != SLOT_FREE
+            && slot.address.load(Ordering::Relaxed) == address
+    })
+}
+fn release(slot: &Slot) {
+    slot.state.store(SLOT_FREE, Ordering::Release);
+    LIVE.fetch_sub(1, Ordering::AcqRel);
+}
+impl<R: ReadPremisionMarker> Pages<R, DenyWrite, AllowExec> {
+    /// Inserts a breakpoint instruction (`INT3` on x86_64, `BRK #0` on AArch64) at `offset`, recording the instruction
+    /// it overwrites. Returns false if there already is a breakpoint at `offset`.
+    ///
+    /// When the breakpoint is hit inside [`Pages`] trapped with [`Pages::trap_faults`], the handler is called with a
+    /// [`Fault`] whose access is [`FaultAccess::Breakpoint`]. If it returns [`FaultAction::Retry`], the breakpoint is
+    /// removed and execution resumes at `offset`, running the original instruction. Hits are reported on Linux, and on
+    /// Windows on x86_64. Otherwise, or if the pages are not trapped, they are handled as if this crate was not there,
+    /// which usually terminates the process or stops it in a debugger.
+    ///
+    /// Pages are made writable while the breakpoint is inserted, or removed after a hit, so code inside them must not
+    /// run on other threads at that time.
+    /// # Panics
+    /// Panics if the breakpoint does not fit inside this [`Pages`], if `offset` is not aligned to 4 bytes on AArch64,
+    /// if maximal number of breakpoints is exceeded, if the installed [`CodeVerifier`] rejects the patched code, or if
+    /// kernel can't/refuses to change protection of the pages.
+    /// # Examples
+    /// ```
+    /// # use memory_pages::*;
+    /// fn on_hit(fault: &Fault) -> FaultAction {
+    ///     assert_eq!(fault.access, FaultAccess::Breakpoint);
+    ///     FaultAction::Retry
+    /// }
+    /// let mut memory: Pages<AllowRead, AllowWrite, DenyExec> = Pages::new(0x1000);
+    /// // X86_64 assembly for `mov eax, 7; ret`.
+    /// memory.get_mut(..6).unwrap().copy_from_slice(&[0xB8, 7, 0, 0, 0, 0xC3]);
+    /// let mut memory = memory.set_protected_exec();
+    /// assert!(memory.insert_breakpoint(0));
+    /// # #[cfg(all(target_arch = "x86_64", any(target_os = "linux", target_family = "windows")))]
+    /// # {
+    /// let trap = memory.trap_faults(on_hit).unwrap();
+    /// let seven: FnRef<unsafe extern "C" fn() -> u32> = unsafe { memory.get_fn(0) };
+    /// assert_eq!(unsafe { seven.call(()) }, 7);
+    /// assert_eq!(trap.fault_count(), 1);
+    /// drop(trap);
+    /// // The breakpoint was removed by the hit.
+    /// assert!(!memory.remove_breakpoint(0));
+    /// # }
+    /// ```
+    pub fn insert_breakpoint(&mut self, offset: usize) -> bool {
+        assert!(
+            offset
+                .checked_add(BREAKPOINT.len())
+                .is_some_and(|end| end <= self.len),
+            "Breakpoint at {offset:#x} is past the end of Pages!"
+        );
+        assert!(
+            offset.is_multiple_of(BREAKPOINT.len()),
+            "Breakpoint at {offset:#x} is not aligned to {} bytes!",
+            BREAKPOINT.len()
+        );
+        let address = self.ptr.as_ptr() as usize + offset;
+        if find(address).is_some() {
+            return false;
+        }
+        LIVE.fetch_add(1, Ordering::AcqRel);
+        let Some(slot) = SLOTS.iter().find(|slot| {
+            slot.state
+                .compare_exchange(SLOT_FREE, SLOT_BUSY, Ordering::Acquire, Ordering::Relaxed)
+                .is_ok()
+        }) else {
+            LIVE.fetch_sub(1, Ordering::AcqRel);
+            panic!("Maximal capacity of breakpoints exceeded!");
+        };
+        slot.address.store(address, Ordering::Relaxed);
+        slot.readable.store(R::allow_read(), Ordering::Relaxed);
+        self.with_writable(|memory| {
+            let code = &mut memory[offset..offset + BREAKPOINT.len()];
+            let mut original = [0; 4];
+            original[..code.len()].copy_from_slice(code);
+            slot.original
+                .store(u32::from_le_bytes(original), Ordering::Relaxed);
+            // Pages can't execute until they stop being writable, so the breakpoint can't be hit before it is active.
+            slot.state.store(SLOT_ACTIVE, Ordering::Release);
+            code.copy_from_slice(BREAKPOINT);
+        });
+        true
+    }
+    /// Removes the breakpoint at `offset`, inserted with [`Self::insert_breakpoint`], restoring the instruction it
+    /// overwrote. Returns false if there is no breakpoint at `offset`, e.g. because it was already removed by a hit.
+    /// # Panics
+    /// Panics if the installed [`CodeVerifier`] rejects the restored code, or if kernel can't/refuses to change
+    /// protection of the pages.
+    pub fn remove_breakpoint(&mut self, offset: usize) -> bool {
+        let address = self.ptr.as_ptr() as usize + offset;
+        let Some(slot) = find(address) else {
+            return false;
+        };
+        if slot
+            .state
+            .compare_exchange(SLOT_ACTIVE, SLOT_BUSY, Ordering::Acquire, Ordering::Relaxed)
+            .is_err()
+        {
+            // Breakpoint is being removed by a hit on another thread.
+            return false;
+        }
+        let original = slot.original.load(Ordering::Relaxed).to_le_bytes();
+        self.with_writable(|memory| {
+            memory[offset..offset + BREAKPOINT.len()]
+                .copy_from_slice(&original[..BREAKPOINT.len()]);
+        });
+        release(slot);
+        true
+    }
+    /// Checks if there is a breakpoint at `offset`, inserted with [`Self::insert_breakpoint`].
+    #[must_use]
+    pub fn has_breakpoint(&self, offset: usize) -> bool {
+        find(self.ptr.as_ptr() as usize + offset).is_some()
+    }
+}
+/// Forgets breakpoints inside `len` bytes starting at `ptr`, which are about to be unmapped.
+pub(crate) fn forget_range(ptr: *mut u8, len: usize) {
+    if LIVE.load(Ordering::Acquire) == 0 {
+        return;
+    }
+    let range = ptr as usize..ptr as usize + len;
+    for slot in &SLOTS {
+        if range.contains(&slot.address.load(Ordering::Relaxed))
+            && slot
+                .state
+                .compare_exchange(SLOT_ACTIVE, SLOT_BUSY, Ordering::Acquire, Ordering::Relaxed)
+                .is_ok()
+        {
+            release(slot);
+        }
+    }
+}
+/// Reports a hit of the breakpoint instruction at `address` to the trap containing it. Returns [`None`] if there is no
+/// such breakpoint or trap. If the handler decides to retry, the breakpoint is removed, and the instruction at
+/// `address` should be executed again. Runs inside a signal handler.
+#[cfg(any(
+    target_os = "linux",
+    all(target_family = "windows", target_arch = "x86_64")
+))]
+pub(crate) fn on_breakpoint(address: usize) -> Option<FaultAction> {
+    use crate::dyn_pages::protect_range_unchecked;
+    use crate::fault_trap::dispatch;
+    use crate::page_math::{align_down, PAGE_SIZE};
+    let slot = find(address)?;
+    if slot
+        .state
+        .compare_exchange(SLOT_ACTIVE, SLOT_BUSY, Ordering::Acquire, Ordering::Relaxed)
+        .is_err()
+    {
+        // Breakpoint is being removed on another thread, after which the original instruction can run.
+        while slot.state.load(Ordering::Acquire) == SLOT_BUSY
+            && slot.address.load(Ordering::Relaxed) == address
+        {
+            std::hint::spin_loop();
+        }
+        return Some(FaultAction::Retry);
+    }
+    let action = dispatch(address, FaultAccess::Breakpoint);
+    if action != Some(FaultAction::Retry) {
+        slot.state.store(SLOT_ACTIVE, Ordering::Release);
+        return action;
+    }
+    let original = slot.original.load(Ordering::Relaxed).to_le_bytes();
+    let exec = if slot.readable.load(Ordering::Relaxed) {
+        Protection::ReadExec
+    } else {
+        Protection::Exec
+    };
+    // A breakpoint never crosses a page boundary, since it is a single byte, or an aligned word.
+    let page = align_down(address) as *mut u8;
+    if protect_range_unchecked(page, PAGE_SIZE, Protection::ReadWrite).is_err() {
+        slot.state.store(SLOT_ACTIVE, Ordering::Release);
+        return Some(FaultAction::Crash);
+    }
+    unsafe {
+        std::ptr::copy_nonoverlapping(original.as_ptr(), address as *mut u8, BREAKPOINT.len());
+    }
+    icache::flush_icache_range(address as *const u8, BREAKPOINT.len());
+    // Other threads may be about to run the restored instruction too. `membarrier` is just a system call, so it is safe
+    // to use inside a signal handler.
+    let _ = icache::sync_code_across_threads();
+    let restored = protect_range_unchecked(page, PAGE_SIZE, exec).is_ok();
+    release(slot);
+    Some(if restored {
+        FaultAction::Retry
+    } else {
+        FaultAction::Crash
+    })
+}
+#[cfg(test)]
+mod test {
+    use super::*;
+    #[cfg(all(
+        target_arch = "x86_64",
+        any(target_os = "linux", target_family = "windows")
+    ))]
+    fn resume(fault: &Fault) -> FaultAction {
+        assert_eq!(fault.access, FaultAccess::Breakpoint);
+        assert_eq!(fault.offset, 0x10);
+        FaultAction::Retry
+    }
+    #[test]
+    fn test_breakpoints() {
+        let mut memory: Pages<AllowRead, AllowWrite, DenyExec> = Pages::new(0x1000);
+        // MOV EAX, 5; RET
+        let code = [0xB8, 5, 0, 0, 0, 0xC3];
+        memory.get_mut(0x10..0x16).unwrap().copy_from_slice(&code);
+        let mut memory = memory.set_protected_exec();
+        assert!(memory.insert_breakpoint(0x10));
+        assert!(!memory.insert_breakpoint(0x10));
+        assert!(memory.has_breakpoint(0x10));
+        assert_eq!(memory.deref()[0x10..0x10 + BREAKPOINT.len()], *BREAKPOINT);
+        assert!(memory.remove_breakpoint(0x10));
+        assert!(!memory.remove_breakpoint(0x10));
+        assert_eq!(memory.deref()[0x10..0x16], code);
+        #[cfg(all(
+            target_arch = "x86_64",
+            any(target_os = "linux", target_family = "windows")
+        ))]
+        {
+            assert!(memory.insert_breakpoint(0x10));
+            let trap = memory.trap_faults(resume).unwrap();
+            let f: FnRef<unsafe extern "C" fn() -> u32> = unsafe { memory.get_fn(0x10) };
+            assert_eq!(unsafe { f.call(()) }, 5);
+            // Breakpoint is gone after the hit, so the second call runs undisturbed.
+            assert_eq!(unsafe { f.call(()) }, 5);
+            assert_eq!(trap.fault_count(), 1);
+            drop(trap);
+            assert!(!memory.has_breakpoint(0x10));
+            assert_eq!(memory.deref()[0x10..0x16], code);
+        }
+        assert!(memory.insert_breakpoint(0x20));
+        let address = memory.get_ptr(0x20) as usize;
+        drop(memory);
+        assert!(find(address).is_none());
+    }
+}
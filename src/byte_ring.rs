@@ -0,0 +1,290 @@
+use crate::paged_deque::{Mirror, GRANULARITY};
+use std::fmt::{Debug, Formatter};
+use std::io;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+/// Keeps an index on its own cache line, so that the producer and the consumer don't slow each other down.
+#[repr(align(128))]
+struct CachePadded(AtomicUsize);
+/// State shared by both ends of a ring buffer.
+struct Ring {
+    mirror: Mirror,
+    /// Total number of bytes ever consumed, modulo twice the capacity. Only written by the consumer.
+    head: CachePadded,
+    /// Total number of bytes ever committed, modulo twice the capacity. Only written by the producer.
+    tail: CachePadded,
+}
+impl Ring {
+    fn capacity(&self) -> usize {
+        self.mirror.len
+    }
+    /// Returns a pointer to byte at `position`, which is contiguous with the next [`Self::capacity`] bytes.
+    fn at(&self, position: usize) -> *mut u8 {
+        unsafe { self.mirror.ptr.as_ptr().add(position % self.capacity()) }
+    }
+    /// Returns the number of bytes from `head` to `tail`. Positions are kept modulo twice the capacity, instead of
+    /// wrapping around `usize`, which is not a multiple of the capacity, so a full ring can be told apart from an empty
+    /// one.
+    fn distance(&self, head: usize, tail: usize) -> usize {
+        (tail + 2 * self.capacity() - head) % (2 * self.capacity())
+    }
+    /// Returns `position` moved forward by `len` bytes.
+    fn advance(&self, position: usize, len: usize) -> usize {
+        (position + len) % (2 * self.capacity())
+    }
+}
+/// Creates a lock-free, single-producer single-consumer byte ring buffer, with capacity of at least `capacity` bytes,
+/// and returns its two ends. The ring is mapped twice, back to back, so both free space and pending data can always be
+/// accessed as a single contiguous slice, even when they wrap around the end of the ring. This lets data be written
+/// and parsed in place, without ever splitting a message in two.
+/// # Panics
+/// Panics if the capacity overflows, or if kernel can't/refuses to map the ring.
+/// # Examples
+/// ```
+/// # use memory_pages::*;
+/// let (mut producer, mut consumer) = byte_ring(0x1000);
+/// let sender = std::thread::spawn(move ||{
+///     for i in 0..0x1000_u32{
+///         // Wait for enough free space.
+///         while producer.write_slice().len() < 4{
+///             std::thread::yield_now();
+///         }
+///         producer.write_slice()[..4].copy_from_slice(&i.to_le_bytes());
+///         producer.commit(4);
+///     }
+/// });
+/// for i in 0..0x1000_u32{
+///     while consumer.read_slice().len() < 4{
+///         std::thread::yield_now();
+///     }
+///     assert_eq!(consumer.read_slice()[..4], i.to_le_bytes());
+///     consumer.consume(4);
+/// }
+/// sender.join().unwrap();
+/// ```
+#[must_use]
+pub fn byte_ring(capacity: usize) -> (RingProducer, RingConsumer) {
+    let bytes = capacity
+        .max(1)
+        .div_ceil(GRANULARITY)
+        .checked_mul(GRANULARITY)
+        .filter(|bytes| isize::try_from(*bytes).is_ok_and(|bytes| bytes.checked_mul(2).is_some()))
+        .unwrap_or_else(|| panic!("Capacity of byte ring overflowed!"));
+    let mirror =
+        Mirror::new(bytes).unwrap_or_else(|err| panic!("Mapping byte ring failed:'{err}'!"));
+    let ring = Arc::new(Ring {
+        mirror,
+        head: CachePadded(AtomicUsize::new(0)),
+        tail: CachePadded(AtomicUsize::new(0)),
+    });
+    (
+        RingProducer {
+            ring: ring.clone(),
+            tail: 0,
+            head: 0,
+        },
+        RingConsumer {
+            ring,
+            head: 0,
+            tail: 0,
+        },
+    )
+}
+/// Writing end of a ring buffer created by [`byte_ring`].
+pub struct RingProducer {
+    ring: Arc<Ring>,
+    /// Local copy of the tail, which only this end writes.
+    tail: usize,
+    /// Head as of the last [`RingProducer::write_slice`]. Free space can only grow since then.
+    head: usize,
+}
+impl RingProducer {
+    /// Returns the capacity of the ring, in bytes.
+    #[must_use]
+    pub fn capacity(&self) -> usize {
+        self.ring.capacity()
+    }
+    /// Returns all free space in the ring, as a single contiguous slice. Bytes written to it are only visible to the
+    /// consumer once they are [`Self::commit`]ted.
+    #[must_use]
+    pub fn write_slice(&mut self) -> &mut [u8] {
+        self.head = self.ring.head.0.load(Ordering::Acquire);
+        let free = self.capacity() - self.ring.distance(self.head, self.tail);
+        // Only the producer accesses free space, until it is committed.
+        unsafe { std::slice::from_raw_parts_mut(self.ring.at(self.tail), free) }
+    }
+    /// Makes the first `len` bytes of [`Self::write_slice`] visible to the consumer.
+    /// # Panics
+    /// Panics if `len` exceeds the free space.
+    pub fn commit(&mut self, len: usize) {
+        let mut free = self.capacity() - self.ring.distance(self.head, self.tail);
+        if len > free {
+            self.head = self.ring.head.0.load(Ordering::Acquire);
+            free = self.capacity() - self.ring.distance(self.head, self.tail);
+        }
+        assert!(
+            len <= free,
+            "can't commit {len} bytes, only {free} are free!"
+        );
+        self.tail = self.ring.advance(self.tail, len);
+        self.ring.tail.0.store(self.tail, Ordering::Release);
+    }
+    /// Copies as much of `data` as fits into the ring, commits it, and returns the number of bytes copied.
+    pub fn push_slice(&mut self, data: &[u8]) -> usize {
+        let free = self.write_slice();
+        let len = free.len().min(data.len());
+        free[..len].copy_from_slice(&data[..len]);
+        self.commit(len);
+        len
+    }
+    /// Checks if the consumer was dropped, in which case nothing will ever be read again.
+    #[must_use]
+    pub fn is_abandoned(&self) -> bool {
+        Arc::strong_count(&self.ring) == 1
+    }
+}
+impl io::Write for RingProducer {
+    /// Writes as much of `buf` as fits. Returns [`io::ErrorKind::WouldBlock`] if the ring is full.
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self.push_slice(buf) {
+            0 if !buf.is_empty() => Err(io::ErrorKind::WouldBlock.into()),
+            written => Ok(written),
+        }
+    }
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+impl Debug for RingProducer {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("RingProducer")
+            .field("capacity", &self.capacity())
+            .field("tail", &self.tail)
+            .finish()
+    }
+}
+/// Reading end of a ring buffer created by [`byte_ring`].
+pub struct RingConsumer {
+    ring: Arc<Ring>,
+    /// Local copy of the head, which only this end writes.
+    head: usize,
+    /// Tail as of the last [`RingConsumer::read_slice`]. Pending data can only grow since then.
+    tail: usize,
+}
+impl RingConsumer {
+    /// Returns the capacity of the ring, in bytes.
+    #[must_use]
+    pub fn capacity(&self) -> usize {
+        self.ring.capacity()
+    }
+    /// Returns all data committed by the producer and not consumed yet, as a single contiguous slice.
+    #[must_use]
+    pub fn read_slice(&mut self) -> &[u8] {
+        self.tail = self.ring.tail.0.load(Ordering::Acquire);
+        let pending = self.ring.distance(self.head, self.tail);
+        // Committed data is never modified by the producer, until it is consumed.
+        unsafe { std::slice::from_raw_parts(self.ring.at(self.head), pending) }
+    }
+    /// Frees the first `len` bytes of [`Self::read_slice`], so that the producer can reuse them.
+    /// # Panics
+    /// Panics if `len` exceeds the pending data.
+    pub fn consume(&mut self, len: usize) {
+        let mut pending = self.ring.distance(self.head, self.tail);
+        if len > pending {
+            self.tail = self.ring.tail.0.load(Ordering::Acquire);
+            pending = self.ring.distance(self.head, self.tail);
+        }
+        assert!(
+            len <= pending,
+            "can't consume {len} bytes, only {pending} are pending!"
+        );
+        self.head = self.ring.advance(self.head, len);
+        self.ring.head.0.store(self.head, Ordering::Release);
+    }
+    /// Copies as much pending data as fits into `data`, consumes it, and returns the number of bytes copied.
+    pub fn pop_slice(&mut self, data: &mut [u8]) -> usize {
+        let pending = self.read_slice();
+        let len = pending.len().min(data.len());
+        data[..len].copy_from_slice(&pending[..len]);
+        self.consume(len);
+        len
+    }
+    /// Checks if the producer was dropped, in which case no more data will ever be committed.
+    #[must_use]
+    pub fn is_abandoned(&self) -> bool {
+        Arc::strong_count(&self.ring) == 1
+    }
+}
+impl io::Read for RingConsumer {
+    /// Reads as much pending data as fits into `buf`. Returns [`io::ErrorKind::WouldBlock`] if the ring is empty, or
+    /// `Ok(0)` once it is empty and the producer was dropped.
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        // Checked first, so that data committed right before the producer was dropped is not lost.
+        let abandoned = self.is_abandoned();
+        match self.pop_slice(buf) {
+            0 if !buf.is_empty() && !abandoned => Err(io::ErrorKind::WouldBlock.into()),
+            read => Ok(read),
+        }
+    }
+}
+impl Debug for RingConsumer {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("RingConsumer")
+            .field("capacity", &self.capacity())
+            .field("head", &self.head)
+            .finish()
+    }
+}
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::io::{Read, Write};
+    #[test]
+    fn test_ring_wraps_contiguously() {
+        let (mut producer, mut consumer) = byte_ring(1);
+        let capacity = producer.capacity();
+        assert_eq!(capacity, GRANULARITY);
+        assert_eq!(producer.push_slice(&vec![1; capacity - 3]), capacity - 3);
+        consumer.consume(capacity - 3);
+        // Free space wraps around the end of the ring, but is still contiguous.
+        let free = producer.write_slice();
+        assert_eq!(free.len(), capacity);
+        free.iter_mut()
+            .enumerate()
+            .for_each(|(i, byte)| *byte = i as u8);
+        producer.commit(capacity);
+        assert_eq!(
+            producer.write(&[0]).unwrap_err().kind(),
+            io::ErrorKind::WouldBlock
+        );
+        let pending = consumer.read_slice();
+        assert!(pending.iter().enumerate().all(|(i, byte)| *byte == i as u8));
+        consumer.consume(capacity);
+        drop(producer);
+        assert!(consumer.is_abandoned());
+        assert_eq!(consumer.read(&mut [0; 4]).unwrap(), 0);
+    }
+    #[test]
+    fn test_ring_threads() {
+        let (mut producer, mut consumer) = byte_ring(0x1000);
+        let data: Vec<u8> = (0..0x100_000_u32).map(|i| (i * 7) as u8).collect();
+        let expected = data.clone();
+        let sender = std::thread::spawn(move || {
+            let mut sent = 0;
+            while sent < data.len() {
+                sent += producer.push_slice(&data[sent..]);
+            }
+        });
+        let mut received = Vec::new();
+        let mut buf = [0; 0x777];
+        loop {
+            match consumer.read(&mut buf) {
+                Ok(0) => break,
+                Ok(read) => received.extend_from_slice(&buf[..read]),
+                Err(_) => std::thread::yield_now(),
+            }
+        }
+        sender.join().unwrap();
+        assert_eq!(received, expected);
+    }
+}
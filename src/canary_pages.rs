@@ -0,0 +1,147 @@
+use crate::*;
+/// Pattern filling the slack of [`CanaryPages`], repeated every 4 bytes.
+const CANARY: [u8; 4] = [0xDE, 0xAD, 0xBE, 0xEF];
+/// [`Pages`] which fill the slack between the requested length and the page-rounded length with a known byte pattern.
+/// Only the requested length is accessible through safe API, so any change to the pattern means that something wrote
+/// past the end of the data, which can be detected with [`Self::check_canaries`].
+///
+/// If the requested length is already page aligned there is no slack, and so no canaries either.
+/// # Examples
+/// ```
+/// # use memory_pages::*;
+/// let mut memory:CanaryPages<AllowRead,AllowWrite,DenyExec> = CanaryPages::new(100);
+/// assert_eq!(memory.len(), 100);
+/// memory[99] = 1;
+/// assert!(memory.check_canaries().is_ok());
+/// // Simulate an off-by-one write.
+/// unsafe{memory.as_mut_ptr().add(100).write(1)};
+/// assert_eq!(memory.check_canaries(), Err(CanaryError{offset:100}));
+/// ```
+pub struct CanaryPages<R: ReadPremisionMarker, W: WritePremisionMarker, E: ExecPremisionMarker> {
+    pages: Pages<R, W, E>,
+    requested: usize,
+}
+/// Error returned by [`CanaryPages::check_canaries`] when the canary pattern was overwritten.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct CanaryError {
+    /// Offset of the first overwritten byte, from the beginning of [`CanaryPages`].
+    pub offset: usize,
+}
+impl std::fmt::Display for CanaryError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "canary at offset {} was overwritten", self.offset)
+    }
+}
+impl std::error::Error for CanaryError {}
+impl<R: ReadPremisionMarker, W: WritePremisionMarker, E: ExecPremisionMarker> CanaryPages<R, W, E> {
+    /// Allocates new [`CanaryPages`] of size `length`, filling the slack up to the next page boundary with canaries.
+    /// # Panics
+    /// Panics when a 0-sized allocation is attempted, or if kernel can't/refuses to allocate requested Pages.
+    #[must_use]
+    pub fn new(length: usize) -> Self {
+        let mut pages: Pages<AllowRead, AllowWrite, DenyExec> = Pages::new(length);
+        for (offset, byte) in pages.iter_mut().enumerate().skip(length) {
+            *byte = CANARY[offset % CANARY.len()];
+        }
+        let res = Self {
+            pages: pages.into_prot(),
+            requested: length,
+        };
+        res.set_slack_poisoned(true);
+        res
+    }
+    /// Returns the requested length of this [`CanaryPages`], which excludes the canaries.
+    #[must_use]
+    pub fn len(&self) -> usize {
+        self.requested
+    }
+    /// Always returns false, because [`CanaryPages`] can't be empty.
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        false
+    }
+    /// Returns a pointer to the beginning of this [`CanaryPages`]. Dereferencing it is only allowed in ways current
+    /// protection of pages allows.
+    #[must_use]
+    pub fn as_mut_ptr(&mut self) -> *mut u8 {
+        self.pages.ptr.as_ptr()
+    }
+    /// Checks if all the canaries are intact.
+    /// # Errors
+    /// Returns the offset of the first overwritten canary byte, if there is one.
+    pub fn check_canaries(&mut self) -> Result<(), CanaryError> {
+        let requested = self.requested;
+        self.set_slack_poisoned(false);
+        let data = self.pages.read_guard();
+        let res = match data
+            .iter()
+            .enumerate()
+            .skip(requested)
+            .find(|(offset, byte)| **byte != CANARY[offset % CANARY.len()])
+        {
+            Some((offset, _)) => Err(CanaryError { offset }),
+            None => Ok(()),
+        };
+        drop(data);
+        self.set_slack_poisoned(true);
+        res
+    }
+    /// Turns this [`CanaryPages`] into [`Pages`], which expose the whole page-rounded length, including canaries.
+    #[must_use]
+    pub fn into_pages(self) -> Pages<R, W, E> {
+        self.set_slack_poisoned(false);
+        let this = std::mem::ManuallyDrop::new(self);
+        // `this` is never dropped, so ownership of pages can be safely moved out of it.
+        unsafe { std::ptr::read(&this.pages) }
+    }
+    /// Poisons or unpoisons the slack, so that sanitizers report accesses to canaries.
+    fn set_slack_poisoned(&self, poisoned: bool) {
+        let slack = unsafe { self.pages.ptr.as_ptr().add(self.requested) };
+        let slack_len = self.pages.len - self.requested;
+        if poisoned {
+            sanitizer::poison(slack, slack_len);
+        } else {
+            sanitizer::unpoison(slack, slack_len);
+        }
+    }
+}
+impl<R: ReadPremisionMarker, W: WritePremisionMarker, E: ExecPremisionMarker> Drop
+    for CanaryPages<R, W, E>
+{
+    fn drop(&mut self) {
+        // Address range may be reused by a later mapping, which must not inherit poisoned state.
+        self.set_slack_poisoned(false);
+    }
+}
+impl<W: WritePremisionMarker, E: ExecPremisionMarker> Deref for CanaryPages<AllowRead, W, E> {
+    type Target = [u8];
+    fn deref(&self) -> &[u8] {
+        unsafe { std::slice::from_raw_parts(self.pages.ptr.as_ptr(), self.requested) }
+    }
+}
+impl<E: ExecPremisionMarker> DerefMut for CanaryPages<AllowRead, AllowWrite, E> {
+    fn deref_mut(&mut self) -> &mut [u8] {
+        unsafe { std::slice::from_raw_parts_mut(self.pages.ptr.as_ptr(), self.requested) }
+    }
+}
+#[cfg(test)]
+mod test {
+    use super::*;
+    #[test]
+    fn test_canaries_protected() {
+        let mut memory: CanaryPages<DenyRead, DenyWrite, DenyExec> = CanaryPages::new(0x1001);
+        assert!(memory.check_canaries().is_ok());
+        let pages = memory.into_pages();
+        assert_eq!(pages.len, 0x2000);
+    }
+    #[test]
+    // Writing to the slack is reported by AddressSanitizer before canaries can be checked.
+    #[cfg(not(feature = "asan"))]
+    fn test_canaries_last_byte() {
+        let mut memory: CanaryPages<AllowRead, AllowWrite, DenyExec> = CanaryPages::new(10);
+        memory.fill(0xDE);
+        assert!(memory.check_canaries().is_ok());
+        unsafe { memory.as_mut_ptr().add(0xFFF).write(0) };
+        assert_eq!(memory.check_canaries(), Err(CanaryError { offset: 0xFFF }));
+    }
+}
@@ -0,0 +1,492 @@
+use crate::*;
+use std::fmt::{Debug, Formatter};
+/// Position in code emitted into a [`CodeBuffer`], which can be referenced before it is known. Created using
+/// [`CodeBuffer::new_label`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct Label(u32);
+/// Encoding of a reference to a [`Label`], patched in by [`CodeBuffer::finalize`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum FixupKind {
+    /// 32-bit little-endian offset of the label, relative to the end of the field, as used by x86 jumps and calls.
+    Rel32,
+    /// 64-bit little-endian absolute address of the label in the finalized code.
+    Abs64,
+}
+/// Error returned by [`CodeBuffer::finalize`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CodeError {
+    /// A label was referenced, but never bound to a position.
+    UnboundLabel(Label),
+    /// A label is too far away from a [`FixupKind::Rel32`] reference to it.
+    FixupOutOfRange(Label),
+    /// A function was named with the same name as another, or starts at the same offset as another named function.
+    DuplicateFunction(Label),
+    /// A label marked as a call target is not aligned to 16 bytes.
+    MisalignedCallTarget(Label),
+}
+impl std::fmt::Display for CodeError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::UnboundLabel(label) => write!(f, "{label:?} was referenced, but never bound"),
+            Self::FixupOutOfRange(label) => {
+                write!(
+                    f,
+                    "{label:?} is out of range of a 32-bit relative reference"
+                )
+            }
+            Self::DuplicateFunction(label) => {
+                write!(f, "function at {label:?} has the name or offset of another")
+            }
+            Self::MisalignedCallTarget(label) => {
+                write!(f, "call target {label:?} is not aligned to 16 bytes")
+            }
+        }
+    }
+}
+impl std::error::Error for CodeError {}
+/// A reference to a label, waiting to be patched in.
+struct Fixup {
+    offset: usize,
+    label: Label,
+    kind: FixupKind,
+}
+/// Builder of machine code, stored in writable [`Pages`] which grow as needed. Code is emitted sequentially, and can
+/// reference [`Label`]s before they are bound to a position, e.g. to jump forward. [`Self::finalize`] patches all
+/// references, makes the pages executable and read-only, and flushes the instruction cache, returning
+/// [`FinalizedCode`] from which functions at bound labels can be obtained.
+/// # Examples
+/// ```
+/// # use memory_pages::*;
+/// let mut code = CodeBuffer::new(0x1000);
+/// let answer = code.new_label();
+/// let entry = code.new_label();
+/// code.bind(entry);
+/// // x86_64 assembly for `jmp answer`.
+/// code.emit_u8(0xE9);
+/// code.emit_fixup(answer, FixupKind::Rel32);
+/// code.align_to(16, 0xCC);
+/// code.bind(answer);
+/// // x86_64 assembly for `mov eax, 42; ret`.
+/// code.emit_u8(0xB8);
+/// code.emit_u32(42);
+/// code.emit_u8(0xC3);
+/// let code = code.finalize().unwrap();
+/// assert_eq!(code.label_offset(answer), Some(16));
+/// # #[cfg(target_arch = "x86_64")]
+/// # {
+/// let f: FnRef<unsafe extern "C" fn() -> u32> = unsafe { code.get_fn(entry) };
+/// assert_eq!(unsafe { f.call(()) }, 42);
+/// # }
+/// ```
+pub struct CodeBuffer {
+    pages: Pages<AllowRead, AllowWrite, DenyExec>,
+    len: usize,
+    /// Offsets of labels, or `None` for labels not bound yet.
+    labels: Vec<Option<usize>>,
+    fixups: Vec<Fixup>,
+    /// Labels at which named functions start.
+    functions: Vec<(Label, String)>,
+    /// Labels which are targets of indirect calls.
+    call_targets: Vec<Label>,
+}
+impl CodeBuffer {
+    /// Creates a new, empty [`CodeBuffer`], with room for at least `capacity` bytes of code.
+    /// # Panics
+    /// Panics if `capacity` is 0, or if kernel can't/refuses to allocate pages.
+    #[must_use]
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            pages: Pages::new(capacity),
+            len: 0,
+            labels: Vec::new(),
+            fixups: Vec::new(),
+            functions: Vec::new(),
+            call_targets: Vec::new(),
+        }
+    }
+    /// Returns the number of bytes emitted so far, which is also the offset of the next emitted byte.
+    #[must_use]
+    pub fn len(&self) -> usize {
+        self.len
+    }
+    /// Checks if no code was emitted yet.
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+    /// Returns the number of bytes which can be emitted before the buffer grows.
+    #[must_use]
+    pub fn capacity(&self) -> usize {
+        self.pages.len()
+    }
+    /// Returns the code emitted so far. References to labels are not patched in until [`Self::finalize`].
+    #[must_use]
+    pub fn code(&self) -> &[u8] {
+        &self.pages.deref()[..self.len]
+    }
+    /// Appends `bytes` to the code.
+    /// # Panics
+    /// Panics if kernel can't/refuses to grow the buffer.
+    pub fn emit_bytes(&mut self, bytes: &[u8]) {
+        let end = self
+            .len
+            .checked_add(bytes.len())
+            .unwrap_or_else(|| panic!("Capacity of CodeBuffer overflowed!"));
+        if end > self.pages.len() {
+            self.pages.resize(end.max(self.pages.len() * 2));
+        }
+        self.pages.deref_mut()[self.len..end].copy_from_slice(bytes);
+        self.len = end;
+    }
+    /// Appends a single byte to the code.
+    /// # Panics
+    /// Panics if kernel can't/refuses to grow the buffer.
+    pub fn emit_u8(&mut self, value: u8) {
+        self.emit_bytes(&[value]);
+    }
+    /// Appends a little-endian `u16` to the code.
+    /// # Panics
+    /// Panics if kernel can't/refuses to grow the buffer.
+    pub fn emit_u16(&mut self, value: u16) {
+        self.emit_bytes(&value.to_le_bytes());
+    }
+    /// Appends a little-endian `u32` to the code.
+    /// # Panics
+    /// Panics if kernel can't/refuses to grow the buffer.
+    pub fn emit_u32(&mut self, value: u32) {
+        self.emit_bytes(&value.to_le_bytes());
+    }
+    /// Appends a little-endian `u64` to the code.
+    /// # Panics
+    /// Panics if kernel can't/refuses to grow the buffer.
+    pub fn emit_u64(&mut self, value: u64) {
+        self.emit_bytes(&value.to_le_bytes());
+    }
+    /// Appends an AArch64 BTI landing pad for `target`, which indirect branches into code guarded by BTI must land on.
+    /// See [`branch_protection`] for details.
+    /// # Panics
+    /// Panics if kernel can't/refuses to grow the buffer.
+    pub fn emit_landing_pad(&mut self, target: branch_protection::BranchTarget) {
+        self.emit_u32(target.instruction());
+    }
+    /// Appends `fill` bytes until the length of the code is a multiple of `align`.
+    /// # Panics
+    /// Panics if `align` is not a power of two, or if kernel can't/refuses to grow the buffer.
+    pub fn align_to(&mut self, align: usize, fill: u8) {
+        assert!(align.is_power_of_two(), "alignment must be a power of two!");
+        while !self.len.is_multiple_of(align) {
+            self.emit_u8(fill);
+        }
+    }
+    /// Creates a new label, not bound to any position yet.
+    /// # Panics
+    /// Panics if more than `u32::MAX` labels are created.
+    pub fn new_label(&mut self) -> Label {
+        let label = u32::try_from(self.labels.len())
+            .unwrap_or_else(|_| panic!("Maximal capacity of CodeBuffer labels exceeded!"));
+        self.labels.push(None);
+        Label(label)
+    }
+    /// Binds `label` to the current end of the code.
+    /// # Panics
+    /// Panics if `label` was already bound, or was created by another [`CodeBuffer`].
+    pub fn bind(&mut self, label: Label) {
+        let slot = self
+            .labels
+            .get_mut(label.0 as usize)
+            .unwrap_or_else(|| panic!("{label:?} does not belong to this CodeBuffer!"));
+        assert!(slot.is_none(), "{label:?} was already bound!");
+        *slot = Some(self.len);
+    }
+    /// Returns the offset `label` is bound to, or `None` if it is not bound yet.
+    #[must_use]
+    pub fn label_offset(&self, label: Label) -> Option<usize> {
+        self.labels.get(label.0 as usize).copied().flatten()
+    }
+    /// Names the function starting at `label`, which ends where the next named function starts, or at the end of the
+    /// code. Names are used by [`FinalizedCode::symbols`], and recorded in the [`PerfMap`] enabled with
+    /// [`PerfMap::enable_global`].
+    /// # Panics
+    /// Panics if `label` was created by another [`CodeBuffer`].
+    pub fn name_function(&mut self, label: Label, name: impl Into<String>) {
+        assert!(
+            (label.0 as usize) < self.labels.len(),
+            "{label:?} does not belong to this CodeBuffer!"
+        );
+        self.functions.push((label, name.into()));
+    }
+    /// Marks `label` as a target of indirect calls, e.g. an entry point called through a [`FnRef`]. On Windows, such
+    /// targets are registered with Control Flow Guard by [`Self::finalize`], using
+    /// [`Pages::set_valid_call_targets`], so calling them does not kill the process. The label must be bound to an
+    /// offset aligned to 16 bytes.
+    /// # Panics
+    /// Panics if `label` was created by another [`CodeBuffer`].
+    pub fn mark_call_target(&mut self, label: Label) {
+        assert!(
+            (label.0 as usize) < self.labels.len(),
+            "{label:?} does not belong to this CodeBuffer!"
+        );
+        self.call_targets.push(label);
+    }
+    /// Appends a placeholder for a reference to `label`, which is patched in by [`Self::finalize`], once all labels are
+    /// bound.
+    /// # Panics
+    /// Panics if `label` was created by another [`CodeBuffer`], or if kernel can't/refuses to grow the buffer.
+    pub fn emit_fixup(&mut self, label: Label, kind: FixupKind) {
+        assert!(
+            (label.0 as usize) < self.labels.len(),
+            "{label:?} does not belong to this CodeBuffer!"
+        );
+        self.fixups.push(Fixup {
+            offset: self.len,
+            label,
+            kind,
+        });
+        match kind {
+            FixupKind::Rel32 => self.emit_u32(0),
+            FixupKind::Abs64 => self.emit_u64(0),
+        }
+    }
+    /// Patches all references to labels, then makes the code executable and read-only, flushing the instruction cache.
+    /// Named functions are recorded in the [`PerfMap`] enabled with [`PerfMap::enable_global`], and on Linux in the
+    /// `JitDump` enabled with `JitDump::enable_global`, ignoring errors, since they only affect profiling.
+    /// # Errors
+    /// Returns an error if a referenced label, a named function or a call target was never bound, if a relative
+    /// reference can't reach its label, if two named functions share a name or an offset, or if a call target is not
+    /// aligned to 16 bytes.
+    /// # Panics
+    /// Panics if kernel can't/refuses to change protection of the code, or to register its call targets.
+    pub fn finalize(mut self) -> Result<FinalizedCode, CodeError> {
+        // Pages never move from now on, so absolute addresses are known.
+        let base = self.pages.ptr.as_ptr() as u64;
+        for fixup in &self.fixups {
+            let target =
+                self.labels[fixup.label.0 as usize].ok_or(CodeError::UnboundLabel(fixup.label))?;
+            match fixup.kind {
+                FixupKind::Rel32 => {
+                    let next = fixup.offset + 4;
+                    let rel = i32::try_from(target as i64 - next as i64)
+                        .map_err(|_| CodeError::FixupOutOfRange(fixup.label))?;
+                    self.pages.deref_mut()[fixup.offset..next].copy_from_slice(&rel.to_le_bytes());
+                }
+                FixupKind::Abs64 => {
+                    let address = base + target as u64;
+                    self.pages.deref_mut()[fixup.offset..fixup.offset + 8]
+                        .copy_from_slice(&address.to_le_bytes());
+                }
+            }
+        }
+        let mut functions = Vec::with_capacity(self.functions.len());
+        for (label, name) in self.functions {
+            let offset = self.labels[label.0 as usize].ok_or(CodeError::UnboundLabel(label))?;
+            functions.push((offset, name, label));
+        }
+        functions.sort_by_key(|(offset, _, _)| *offset);
+        let mut symbols = CodeSymbols::new();
+        let ends: Vec<usize> = functions
+            .iter()
+            .skip(1)
+            .map(|(offset, _, _)| *offset)
+            .collect();
+        for ((offset, name, label), end) in functions
+            .into_iter()
+            .zip(ends.into_iter().chain([self.len]))
+        {
+            let shares_offset = symbols
+                .iter()
+                .next_back()
+                .is_some_and(|last| last.offset == offset);
+            if shares_offset || symbols.get(&name).is_some() {
+                return Err(CodeError::DuplicateFunction(label));
+            }
+            symbols.register(name, offset, end - offset);
+        }
+        let mut call_targets = Vec::with_capacity(self.call_targets.len());
+        for label in self.call_targets {
+            let offset = self.labels[label.0 as usize].ok_or(CodeError::UnboundLabel(label))?;
+            if !offset.is_multiple_of(16) {
+                return Err(CodeError::MisalignedCallTarget(label));
+            }
+            call_targets.push(offset);
+        }
+        let code = FinalizedCode {
+            pages: self.pages.set_protected_exec(),
+            len: self.len,
+            labels: self.labels,
+            symbols,
+        };
+        if let Err(err) = code.pages.set_valid_call_targets(&call_targets) {
+            panic!("Registering call targets failed:'{err}'!");
+        }
+        if let Some(map) = PerfMap::global() {
+            let _ = map.record(&code.symbols());
+        }
+        #[cfg(target_os = "linux")]
+        if let Some(dump) = JitDump::global() {
+            let base = code.code().as_ptr() as usize;
+            for symbol in code.symbols() {
+                let offset = symbol.address as usize - base;
+                let function = &code.code()[offset..offset + symbol.size];
+                let _ = dump.record_load(symbol.name, function, &[]);
+            }
+        }
+        Ok(code)
+    }
+}
+impl Debug for CodeBuffer {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("CodeBuffer")
+            .field("len", &self.len)
+            .field("labels", &self.labels.len())
+            .field("fixups", &self.fixups.len())
+            .finish()
+    }
+}
+/// Executable, read-only code, built using a [`CodeBuffer`].
+pub struct FinalizedCode {
+    pages: Pages<AllowRead, DenyWrite, AllowExec>,
+    len: usize,
+    labels: Vec<Option<usize>>,
+    /// Functions named with [`CodeBuffer::name_function`].
+    symbols: CodeSymbols,
+}
+impl FinalizedCode {
+    /// Returns the finalized code.
+    #[must_use]
+    pub fn code(&self) -> &[u8] {
+        &self.pages.deref()[..self.len]
+    }
+    /// Returns the offset `label` is bound to, or `None` if it was never bound.
+    #[must_use]
+    pub fn label_offset(&self, label: Label) -> Option<usize> {
+        self.labels.get(label.0 as usize).copied().flatten()
+    }
+    /// Returns the address, size and name of each function named with [`CodeBuffer::name_function`], sorted by address.
+    #[must_use]
+    pub fn symbols(&self) -> Vec<JitSymbol<'_>> {
+        self.symbols.jit_symbols(self.code().as_ptr())
+    }
+    /// Returns the functions named with [`CodeBuffer::name_function`], by their offsets.
+    #[must_use]
+    pub fn code_symbols(&self) -> &CodeSymbols {
+        &self.symbols
+    }
+    /// Gets function `name`, named with [`CodeBuffer::name_function`], or `None` if there is no such function.
+    /// # Safety
+    /// The bytes of the function must represent native instructions creating a function with a matching signature to
+    /// function pointer type F.
+    #[must_use]
+    pub unsafe fn get_named_fn<F>(&self, name: &str) -> Option<FnRef<'_, F>>
+    where
+        F: ExternFnPtr + Copy + std::fmt::Pointer + Sized,
+    {
+        self.symbols.get_fn(&self.pages, name)
+    }
+    /// Gets a function whose entry point is at `label`. Function must be an `extern "C" fn`.
+    /// # Safety
+    /// The bytes at `label` must represent native instructions creating a function with a matching signature to function
+    /// pointer type F.
+    /// # Panics
+    /// Panics if `label` was never bound, or was created by another [`CodeBuffer`].
+    #[must_use]
+    pub unsafe fn get_fn<F>(&self, label: Label) -> FnRef<'_, F>
+    where
+        F: ExternFnPtr + Copy + std::fmt::Pointer + Sized,
+    {
+        let offset = self
+            .label_offset(label)
+            .unwrap_or_else(|| panic!("{label:?} is not bound!"));
+        self.pages.get_fn(offset)
+    }
+}
+impl Debug for FinalizedCode {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("FinalizedCode")
+            .field("ptr", &self.pages.ptr)
+            .field("len", &self.len)
+            .finish()
+    }
+}
+#[cfg(test)]
+mod test {
+    use super::*;
+    #[test]
+    fn test_code_buffer_fixups() {
+        let mut code = CodeBuffer::new(0x1000);
+        let (data, back, unbound) = (code.new_label(), code.new_label(), code.new_label());
+        code.bind(back);
+        code.emit_fixup(data, FixupKind::Abs64);
+        code.emit_fixup(back, FixupKind::Rel32);
+        // Grows past the initial capacity.
+        code.emit_bytes(&[0x90; 0x1800]);
+        code.align_to(0x100, 0);
+        code.bind(data);
+        code.emit_u64(u64::MAX);
+        assert_eq!(code.len(), 0x1908);
+        let mut unfinished = CodeBuffer::new(0x10);
+        let label = unfinished.new_label();
+        unfinished.emit_fixup(label, FixupKind::Rel32);
+        assert_eq!(
+            unfinished.finalize().unwrap_err(),
+            CodeError::UnboundLabel(label)
+        );
+        let code = code.finalize().unwrap();
+        let base = code.code().as_ptr() as u64;
+        assert_eq!(code.code()[..8], (base + 0x1900).to_le_bytes());
+        assert_eq!(code.code()[8..12], (-12_i32).to_le_bytes());
+        assert_eq!(code.label_offset(unbound), None);
+    }
+    #[test]
+    fn test_code_buffer_named_functions() {
+        let mut code = CodeBuffer::new(0x100);
+        let (first, second) = (code.new_label(), code.new_label());
+        code.bind(first);
+        code.name_function(first, "first");
+        code.emit_bytes(&[0xC3; 4]);
+        code.bind(second);
+        code.name_function(second, "second");
+        code.emit_u8(0xC3);
+        let code = code.finalize().unwrap();
+        let symbols = code.code_symbols();
+        assert_eq!(symbols.get("first").unwrap().len, 4);
+        assert_eq!(symbols.symbol_at(4).unwrap().name, "second");
+        let f: Option<FnRef<unsafe extern "C" fn()>> = unsafe { code.get_named_fn("second") };
+        assert_eq!(f.unwrap().offset(), 4);
+        let mut duplicate = CodeBuffer::new(0x100);
+        let (first, alias_of_first) = (duplicate.new_label(), duplicate.new_label());
+        duplicate.bind(first);
+        duplicate.bind(alias_of_first);
+        duplicate.name_function(first, "first");
+        duplicate.name_function(alias_of_first, "alias");
+        duplicate.emit_u8(0xC3);
+        assert!(matches!(
+            duplicate.finalize(),
+            Err(CodeError::DuplicateFunction(_))
+        ));
+    }
+    #[test]
+    fn test_code_buffer_call_targets() {
+        let mut code = CodeBuffer::new(0x100);
+        let (entry, misaligned) = (code.new_label(), code.new_label());
+        code.bind(entry);
+        code.mark_call_target(entry);
+        code.emit_u8(0xC3);
+        code.bind(misaligned);
+        code.mark_call_target(misaligned);
+        code.emit_u8(0xC3);
+        assert_eq!(
+            code.finalize().unwrap_err(),
+            CodeError::MisalignedCallTarget(misaligned)
+        );
+        let mut code = CodeBuffer::new(0x100);
+        let (entry, aligned) = (code.new_label(), code.new_label());
+        code.bind(entry);
+        code.mark_call_target(entry);
+        code.emit_u8(0xC3);
+        code.align_to(16, 0xCC);
+        code.bind(aligned);
+        code.mark_call_target(aligned);
+        code.emit_u8(0xC3);
+        assert_eq!(code.finalize().unwrap().label_offset(aligned), Some(16));
+    }
+}
@@ -0,0 +1,326 @@
+use crate::*;
+use std::fmt::{Debug, Display, Formatter};
+/// Round constants of SHA-256.
+const K: [u32; 64] = [
+    0x428a_2f98,
+    0x7137_4491,
+    0xb5c0_fbcf,
+    0xe9b5_dba5,
+    0x3956_c25b,
+    0x59f1_11f1,
+    0x923f_82a4,
+    0xab1c_5ed5,
+    0xd807_aa98,
+    0x1283_5b01,
+    0x2431_85be,
+    0x550c_7dc3,
+    0x72be_5d74,
+    0x80de_b1fe,
+    0x9bdc_06a7,
+    0xc19b_f174,
+    0xe49b_69c1,
+    0xefbe_4786,
+    0x0fc1_9dc6,
+    0x240c_a1cc,
+    0x2de9_2c6f,
+    0x4a74_84aa,
+    0x5cb0_a9dc,
+    0x76f9_88da,
+    0x983e_5152,
+    0xa831_c66d,
+    0xb003_27c8,
+    0xbf59_7fc7,
+    0xc6e0_0bf3,
+    0xd5a7_9147,
+    0x06ca_6351,
+    0x1429_2967,
+    0x27b7_0a85,
+    0x2e1b_2138,
+    0x4d2c_6dfc,
+    0x5338_0d13,
+    0x650a_7354,
+    0x766a_0abb,
+    0x81c2_c92e,
+    0x9272_2c85,
+    0xa2bf_e8a1,
+    0xa81a_664b,
+    0xc24b_8b70,
+    0xc76c_51a3,
+    0xd192_e819,
+    0xd699_0624,
+    0xf40e_3585,
+    0x106a_a070,
+    0x19a4_c116,
+    0x1e37_6c08,
+    0x2748_774c,
+    0x34b0_bcb5,
+    0x391c_0cb3,
+    0x4ed8_aa4a,
+    0x5b9c_ca4f,
+    0x682e_6ff3,
+    0x748f_82ee,
+    0x78a5_636f,
+    0x84c8_7814,
+    0x8cc7_0208,
+    0x90be_fffa,
+    0xa450_6ceb,
+    0xbef9_a3f7,
+    0xc671_78f2,
+];
+/// SHA-256 digest of machine code, used to check that code made executable is exactly the code that was expected.
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+pub struct CodeDigest([u8; 32]);
+impl CodeDigest {
+    /// Computes the SHA-256 digest of `code`.
+    /// # Examples
+    /// ```
+    /// # use memory_pages::*;
+    /// let digest = CodeDigest::of(b"abc");
+    /// assert_eq!(
+    ///     digest.to_string(),
+    ///     "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad"
+    /// );
+    /// ```
+    #[must_use]
+    pub fn of(code: &[u8]) -> Self {
+        let mut state: [u32; 8] = [
+            0x6a09_e667,
+            0xbb67_ae85,
+            0x3c6e_f372,
+            0xa54f_f53a,
+            0x510e_527f,
+            0x9b05_688c,
+            0x1f83_d9ab,
+            0x5be0_cd19,
+        ];
+        let mut chunks = code.chunks_exact(64);
+        for block in chunks.by_ref() {
+            compress(&mut state, block);
+        }
+        // The message is padded with a 1 bit, zeros, and its length in bits, to a multiple of 64 bytes.
+        let rest = chunks.remainder();
+        let mut tail = [0; 128];
+        tail[..rest.len()].copy_from_slice(rest);
+        tail[rest.len()] = 0x80;
+        let tail_len = if rest.len() < 56 { 64 } else { 128 };
+        let bits = (code.len() as u64).wrapping_mul(8);
+        tail[tail_len - 8..tail_len].copy_from_slice(&bits.to_be_bytes());
+        for block in tail[..tail_len].chunks_exact(64) {
+            compress(&mut state, block);
+        }
+        let mut digest = [0; 32];
+        for (bytes, word) in digest.chunks_exact_mut(4).zip(state) {
+            bytes.copy_from_slice(&word.to_be_bytes());
+        }
+        Self(digest)
+    }
+    /// Creates a [`CodeDigest`] from its bytes, e.g. a digest of code computed ahead of time.
+    #[must_use]
+    pub const fn from_bytes(bytes: [u8; 32]) -> Self {
+        Self(bytes)
+    }
+    /// Returns the bytes of this [`CodeDigest`].
+    #[must_use]
+    pub const fn as_bytes(&self) -> &[u8; 32] {
+        &self.0
+    }
+}
+/// Processes a single 64 byte block of the message.
+fn compress(state: &mut [u32; 8], block: &[u8]) {
+    let mut w = [0_u32; 64];
+    for (word, bytes) in w.iter_mut().zip(block.chunks_exact(4)) {
+        *word = u32::from_be_bytes(bytes.try_into().expect("chunk has 4 bytes"));
+    }
+    for i in 16..64 {
+        let s0 = w[i - 15].rotate_right(7) ^ w[i - 15].rotate_right(18) ^ (w[i - 15] >> 3);
+        let s1 = w[i - 2].rotate_right(17) ^ w[i - 2].rotate_right(19) ^ (w[i - 2] >> 10);
+        w[i] = w[i - 16]
+            .wrapping_add(s0)
+            .wrapping_add(w[i - 7])
+            .wrapping_add(s1);
+    }
+    let [mut a, mut b, mut c, mut d, mut e, mut f, mut g, mut h] = *state;
+    for i in 0..64 {
+        let s1 = e.rotate_right(6) ^ e.rotate_right(11) ^ e.rotate_right(25);
+        let ch = (e & f) ^ (!e & g);
+        let t1 = h
+            .wrapping_add(s1)
+            .wrapping_add(ch)
+            .wrapping_add(K[i])
+            .wrapping_add(w[i]);
+        let s0 = a.rotate_right(2) ^ a.rotate_right(13) ^ a.rotate_right(22);
+        let maj = (a & b) ^ (a & c) ^ (b & c);
+        let t2 = s0.wrapping_add(maj);
+        h = g;
+        g = f;
+        f = e;
+        e = d.wrapping_add(t1);
+        d = c;
+        c = b;
+        b = a;
+        a = t1.wrapping_add(t2);
+    }
+    for (word, value) in state.iter_mut().zip([a, b, c, d, e, f, g, h]) {
+        *word = word.wrapping_add(value);
+    }
+}
+impl Display for CodeDigest {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        for byte in self.0 {
+            write!(f, "{byte:02x}")?;
+        }
+        Ok(())
+    }
+}
+impl Debug for CodeDigest {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "CodeDigest({self})")
+    }
+}
+/// Error returned by [`Pages::finalize_code`] when the code does not match the expected digest.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct DigestMismatch {
+    /// Digest the code was expected to have.
+    pub expected: CodeDigest,
+    /// Digest the code actually has.
+    pub actual: CodeDigest,
+}
+impl Display for DigestMismatch {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "code has digest {}, but {} was expected",
+            self.actual, self.expected
+        )
+    }
+}
+impl std::error::Error for DigestMismatch {}
+/// A single function inside read-only, executable [`Pages`], created using [`Pages::finalize_code`].
+pub struct FinalizedFn<F: ExternFnPtr> {
+    pages: Pages<AllowRead, DenyWrite, AllowExec>,
+    entry: usize,
+    digest: CodeDigest,
+    pd: PhantomData<F>,
+}
+impl<F: ExternFnPtr + Copy + std::fmt::Pointer> FinalizedFn<F> {
+    /// Returns the entry point of the function.
+    #[must_use]
+    pub fn entry(&self) -> FnRef<'_, F> {
+        // Caller of `finalize_code` guaranteed the code at entry matches `F`.
+        unsafe { self.pages.get_fn(self.entry) }
+    }
+}
+impl<F: ExternFnPtr> FinalizedFn<F> {
+    /// Returns the digest of the code, as computed after it was made read-only.
+    #[must_use]
+    pub fn digest(&self) -> &CodeDigest {
+        &self.digest
+    }
+    /// Returns the [`Pages`] containing the code.
+    #[must_use]
+    pub fn pages(&self) -> &Pages<AllowRead, DenyWrite, AllowExec> {
+        &self.pages
+    }
+    /// Returns the [`Pages`] containing the code, dropping the function.
+    #[must_use]
+    pub fn into_pages(self) -> Pages<AllowRead, DenyWrite, AllowExec> {
+        self.pages
+    }
+}
+impl<F: ExternFnPtr> Debug for FinalizedFn<F> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("FinalizedFn")
+            .field("ptr", &self.pages.ptr)
+            .field("entry", &self.entry)
+            .field("digest", &self.digest)
+            .finish()
+    }
+}
+impl<W: WritePremisionMarker, E: ExecPremisionMarker> Pages<AllowRead, W, E> {
+    /// Turns the first `code_len` bytes of this [`Pages`] into a function starting at offset `entry`, in a single step:
+    /// makes the pages read-only and executable, flushing the instruction cache, computes the digest of the code, and
+    /// checks that it matches `expected`, if one is given. Doing all of that at once leaves no window in which the code
+    /// could be modified after it was checked, or executed before.
+    ///
+    /// The digest is computed after the pages become read-only, so it always describes the code which is going to run.
+    /// # Safety
+    /// The bytes at `entry` must represent native instructions creating a function with a matching signature to
+    /// function pointer type F.
+    /// # Errors
+    /// Returns an error if the digest of the code does not match `expected`. The pages are freed in that case, and no
+    /// code inside them can ever run.
+    /// # Panics
+    /// Panics if `entry` is not inside the code, if `code_len` is larger than this [`Pages`], or if kernel
+    /// can't/refuses to change protection of the pages.
+    /// # Examples
+    /// ```
+    /// # use memory_pages::*;
+    /// let mut memory: Pages<AllowRead, AllowWrite, DenyExec> = Pages::new(0x1000);
+    /// // X86_64 assembly for `mov eax, 7; ret`.
+    /// let code = [0xB8, 7, 0, 0, 0, 0xC3];
+    /// memory.get_mut(..code.len()).unwrap().copy_from_slice(&code);
+    /// let expected = CodeDigest::of(&code);
+    /// let seven = unsafe {
+    ///     memory.finalize_code::<unsafe extern "C" fn() -> u32>(code.len(), 0, Some(expected))
+    /// }
+    /// .unwrap();
+    /// # #[cfg(target_arch = "x86_64")]
+    /// assert_eq!(unsafe { seven.entry().call(()) }, 7);
+    /// ```
+    pub unsafe fn finalize_code<F: ExternFnPtr>(
+        self,
+        code_len: usize,
+        entry: usize,
+        expected: Option<CodeDigest>,
+    ) -> Result<FinalizedFn<F>, DigestMismatch> {
+        assert!(code_len <= self.len, "Code is longer than Pages!");
+        assert!(entry < code_len, "Entry point is outside of the code!");
+        let pages: Pages<AllowRead, DenyWrite, AllowExec> = self.into_prot();
+        let digest = CodeDigest::of(&pages.deref()[..code_len]);
+        if let Some(expected) = expected {
+            if expected != digest {
+                return Err(DigestMismatch {
+                    expected,
+                    actual: digest,
+                });
+            }
+        }
+        Ok(FinalizedFn {
+            pages,
+            entry,
+            digest,
+            pd: PhantomData,
+        })
+    }
+}
+#[cfg(test)]
+mod test {
+    use super::*;
+    #[test]
+    fn test_sha256_vectors() {
+        assert_eq!(
+            CodeDigest::of(b"").to_string(),
+            "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855"
+        );
+        // Padding spills into a second block.
+        assert_eq!(
+            CodeDigest::of(b"abcdbcdecdefdefgefghfghighijhijkijkljklmklmnlmnomnopnopq").to_string(),
+            "248d6a61d20638b8e5c026930c3e6039a33ce45964ff2167f6ecedd419db06c1"
+        );
+        let million = vec![b'a'; 1_000_000];
+        assert_eq!(
+            CodeDigest::of(&million).to_string(),
+            "cdc76e5c9914fb9281a1c7e284d73e67f1809a48a497200e046d39ccc7112cd0"
+        );
+    }
+    #[test]
+    fn test_finalize_code_mismatch() {
+        let mut memory: Pages<AllowRead, AllowWrite, DenyExec> = Pages::new(0x1000);
+        memory[0] = 0xC3;
+        let wrong = CodeDigest::of(&[0x90]);
+        let err = unsafe { memory.finalize_code::<unsafe extern "C" fn()>(1, 0, Some(wrong)) }
+            .unwrap_err();
+        assert_eq!(err.expected, wrong);
+        assert_eq!(err.actual, CodeDigest::of(&[0xC3]));
+    }
+}
@@ -0,0 +1,332 @@
+use crate::*;
+use std::fmt::{Debug, Display, Formatter};
+/// Encoding of a relocated field, patched in by [`CodeLoader::load`]. In the descriptions, `S` is the address of the
+/// target, `A` the addend, and `P` the address of the field.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum RelocKind {
+    /// 64-bit little-endian absolute address `S + A`.
+    Abs64,
+    /// 32-bit little-endian absolute address `S + A`, which must fit in 32 bits unsigned.
+    Abs32,
+    /// 32-bit little-endian relative offset `S + A - P`, as used by x86 jumps and calls. Since those are relative to
+    /// the end of the field, their addend is usually -4.
+    Rel32,
+    /// 26-bit word offset `(S + A - P) / 4`, placed in the low bits of an existing AArch64 `B` or `BL` instruction.
+    Branch26,
+}
+/// What a [`Relocation`] refers to.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub enum RelocTarget {
+    /// A symbol, looked up among entry points of the code, and then using the resolver passed to
+    /// [`CodeLoader::load`].
+    Symbol(String),
+    /// Offset inside the loaded code.
+    Local(usize),
+}
+/// A field inside code, which must be patched with the address of its target once the code is placed in memory.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct Relocation {
+    /// Offset of the field, from the start of the code.
+    pub offset: usize,
+    /// Encoding of the field.
+    pub kind: RelocKind,
+    /// What the field refers to.
+    pub target: RelocTarget,
+    /// Value added to the address of the target.
+    pub addend: i64,
+}
+/// Error returned by [`CodeLoader::load`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum LoadError {
+    /// A symbol is neither an entry point, nor known to the resolver.
+    UnresolvedSymbol(String),
+    /// A relocated field at this offset extends past the end of the code.
+    RelocationOutOfBounds(usize),
+    /// A value does not fit in the relocated field at this offset, or is not aligned as the field requires.
+    RelocationOutOfRange(usize),
+    /// Two entry points share this name, or one of them starts at the same offset as another.
+    DuplicateEntryPoint(String),
+    /// An entry point, or a local relocation target, at this offset is past the end of the code.
+    OffsetOutOfBounds(usize),
+}
+impl Display for LoadError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::UnresolvedSymbol(name) => write!(f, "symbol `{name}` can't be resolved"),
+            Self::RelocationOutOfBounds(offset) => {
+                write!(f, "relocation at {offset:#x} is past the end of code")
+            }
+            Self::RelocationOutOfRange(offset) => {
+                write!(f, "value of relocation at {offset:#x} does not fit")
+            }
+            Self::DuplicateEntryPoint(name) => {
+                write!(f, "entry point `{name}` is defined more than once")
+            }
+            Self::OffsetOutOfBounds(offset) => {
+                write!(f, "offset {offset:#x} is past the end of code")
+            }
+        }
+    }
+}
+impl std::error::Error for LoadError {}
+/// Loader of position-dependent machine code, e.g. produced ahead of time by a compiler. Takes the code, a table of
+/// [`Relocation`]s and named entry points, copies the code into executable [`Pages`], and patches in the addresses of
+/// symbols it references, returning [`LoadedCode`] from which entry points can be obtained.
+/// # Examples
+/// ```
+/// # use memory_pages::*;
+/// extern "C" fn add_one(x: u64) -> u64 {
+///     x + 1
+/// }
+/// // x86_64 assembly for `movabs rax, add_one; jmp rax`, so arguments pass through unchanged.
+/// let code = [0x48, 0xB8, 0, 0, 0, 0, 0, 0, 0, 0, 0xFF, 0xE0];
+/// let mut loader = CodeLoader::new(&code);
+/// loader.relocate(2, RelocKind::Abs64, RelocTarget::Symbol("add_one".into()), 0);
+/// loader.entry_point("tail_call", 0);
+/// let code = loader
+///     .load(|name| (name == "add_one").then_some(add_one as extern "C" fn(u64) -> u64 as usize))
+///     .unwrap();
+/// # #[cfg(target_arch = "x86_64")]
+/// # {
+/// let f: FnRef<unsafe extern "C" fn(u64) -> u64> = unsafe { code.get_fn("tail_call") }.unwrap();
+/// assert_eq!(unsafe { f.call(41) }, 42);
+/// # }
+/// ```
+pub struct CodeLoader<'a> {
+    code: &'a [u8],
+    relocations: Vec<Relocation>,
+    /// Names and offsets of entry points.
+    entry_points: Vec<(String, usize)>,
+}
+impl<'a> CodeLoader<'a> {
+    /// Creates a [`CodeLoader`] of `code`.
+    /// # Panics
+    /// Panics if `code` is empty.
+    #[must_use]
+    pub fn new(code: &'a [u8]) -> Self {
+        assert!(!code.is_empty(), "can't load empty code!");
+        Self {
+            code,
+            relocations: Vec::new(),
+            entry_points: Vec::new(),
+        }
+    }
+    /// Adds a relocation of the field at `offset`, which refers to `target`.
+    pub fn relocate(&mut self, offset: usize, kind: RelocKind, target: RelocTarget, addend: i64) {
+        self.add_relocation(Relocation {
+            offset,
+            kind,
+            target,
+            addend,
+        });
+    }
+    /// Adds `relocation`, e.g. read from a relocation table.
+    pub fn add_relocation(&mut self, relocation: Relocation) {
+        self.relocations.push(relocation);
+    }
+    /// Defines entry point `name`, at `offset`. Entry points can be obtained from [`LoadedCode`], and are also symbols
+    /// relocations can refer to.
+    pub fn entry_point(&mut self, name: impl Into<String>, offset: usize) {
+        self.entry_points.push((name.into(), offset));
+    }
+    /// Copies the code into executable, read-only [`Pages`], applying all relocations, and flushes the instruction
+    /// cache. Symbols which are not entry points of the code are looked up using `resolver`, which returns their
+    /// addresses, or `None` for unknown ones.
+    /// # Errors
+    /// Returns an error if a symbol can't be resolved, if a relocated value does not fit its field, if two entry points
+    /// share a name, or if a relocation, local target or entry point is past the end of the code.
+    /// # Panics
+    /// Panics if kernel can't/refuses to allocate or change protection of the pages.
+    pub fn load(
+        self,
+        mut resolver: impl FnMut(&str) -> Option<usize>,
+    ) -> Result<LoadedCode, LoadError> {
+        let len = self.code.len();
+        let mut entry_points = self.entry_points;
+        entry_points.sort_by_key(|(_, offset)| *offset);
+        let mut symbols = CodeSymbols::new();
+        let ends: Vec<usize> = entry_points
+            .iter()
+            .skip(1)
+            .map(|(_, offset)| *offset)
+            .chain([len])
+            .collect();
+        for ((name, offset), end) in entry_points.into_iter().zip(ends) {
+            if offset >= len {
+                return Err(LoadError::OffsetOutOfBounds(offset));
+            }
+            let shares_offset = symbols
+                .iter()
+                .next_back()
+                .is_some_and(|last| last.offset == offset);
+            if shares_offset || symbols.get(&name).is_some() {
+                return Err(LoadError::DuplicateEntryPoint(name));
+            }
+            symbols.register(name, offset, end - offset);
+        }
+        let mut pages: Pages<AllowRead, AllowWrite, DenyExec> = Pages::new(len);
+        pages.deref_mut()[..len].copy_from_slice(self.code);
+        // Pages never move from now on, so absolute addresses are known.
+        let base = pages.ptr.as_ptr() as usize;
+        for relocation in &self.relocations {
+            let offset = relocation.offset;
+            let width = if relocation.kind == RelocKind::Abs64 {
+                8
+            } else {
+                4
+            };
+            let end = offset
+                .checked_add(width)
+                .filter(|end| *end <= len)
+                .ok_or(LoadError::RelocationOutOfBounds(offset))?;
+            let target = match &relocation.target {
+                RelocTarget::Local(local) if *local <= len => base + local,
+                RelocTarget::Local(local) => return Err(LoadError::OffsetOutOfBounds(*local)),
+                RelocTarget::Symbol(name) => match symbols.get(name) {
+                    Some(symbol) => base + symbol.offset,
+                    None => {
+                        resolver(name).ok_or_else(|| LoadError::UnresolvedSymbol(name.clone()))?
+                    }
+                },
+            };
+            let value = (target as i64).wrapping_add(relocation.addend);
+            let relative = value.wrapping_sub((base + offset) as i64);
+            let out_of_range = || LoadError::RelocationOutOfRange(offset);
+            let field: &[u8] = match relocation.kind {
+                RelocKind::Abs64 => &value.to_le_bytes(),
+                RelocKind::Abs32 => &u32::try_from(value)
+                    .map_err(|_| out_of_range())?
+                    .to_le_bytes(),
+                RelocKind::Rel32 => &i32::try_from(relative)
+                    .map_err(|_| out_of_range())?
+                    .to_le_bytes(),
+                RelocKind::Branch26 => {
+                    let words = relative / 4;
+                    if relative % 4 != 0 || !(-(1 << 25)..1 << 25).contains(&words) {
+                        return Err(out_of_range());
+                    }
+                    let instruction = &pages.deref()[offset..end];
+                    let instruction = u32::from_le_bytes(instruction.try_into().expect("4 bytes"));
+                    &((instruction & !0x03FF_FFFF) | (words as u32 & 0x03FF_FFFF)).to_le_bytes()
+                }
+            };
+            pages.deref_mut()[offset..end].copy_from_slice(field);
+        }
+        Ok(LoadedCode {
+            pages: pages.set_protected_exec(),
+            len,
+            symbols,
+        })
+    }
+}
+impl Debug for CodeLoader<'_> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("CodeLoader")
+            .field("len", &self.code.len())
+            .field("relocations", &self.relocations.len())
+            .field("entry_points", &self.entry_points.len())
+            .finish()
+    }
+}
+/// Executable, read-only code, loaded using a [`CodeLoader`].
+pub struct LoadedCode {
+    pages: Pages<AllowRead, DenyWrite, AllowExec>,
+    len: usize,
+    /// Entry points, by their offsets.
+    symbols: CodeSymbols,
+}
+impl LoadedCode {
+    /// Returns the loaded code, with relocations applied.
+    #[must_use]
+    pub fn code(&self) -> &[u8] {
+        &self.pages.deref()[..self.len]
+    }
+    /// Returns the entry points of the code, by their offsets.
+    #[must_use]
+    pub fn code_symbols(&self) -> &CodeSymbols {
+        &self.symbols
+    }
+    /// Returns the address, size and name of each entry point, sorted by address.
+    #[must_use]
+    pub fn symbols(&self) -> Vec<JitSymbol<'_>> {
+        self.symbols.jit_symbols(self.code().as_ptr())
+    }
+    /// Gets entry point `name`, or `None` if there is no such entry point.
+    /// # Safety
+    /// The bytes at the entry point must represent native instructions creating a function with a matching signature to
+    /// function pointer type F.
+    #[must_use]
+    pub unsafe fn get_fn<F>(&self, name: &str) -> Option<FnRef<'_, F>>
+    where
+        F: ExternFnPtr + Copy + std::fmt::Pointer + Sized,
+    {
+        self.symbols.get_fn(&self.pages, name)
+    }
+}
+impl Debug for LoadedCode {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("LoadedCode")
+            .field("ptr", &self.pages.ptr)
+            .field("len", &self.len)
+            .finish()
+    }
+}
+#[cfg(test)]
+mod test {
+    use super::*;
+    #[test]
+    fn test_code_loader_relocations() {
+        let mut code = vec![0; 0x20];
+        // `BL` with an empty offset.
+        code[0x10..0x14].copy_from_slice(&0x9400_0000_u32.to_le_bytes());
+        let mut loader = CodeLoader::new(&code);
+        loader.entry_point("start", 0);
+        loader.entry_point("branch", 0x10);
+        loader.relocate(0, RelocKind::Abs64, RelocTarget::Local(0x18), 2);
+        loader.relocate(
+            8,
+            RelocKind::Rel32,
+            RelocTarget::Symbol("branch".into()),
+            -4,
+        );
+        loader.relocate(
+            0x10,
+            RelocKind::Branch26,
+            RelocTarget::Symbol("start".into()),
+            0,
+        );
+        loader.relocate(
+            0x18,
+            RelocKind::Abs32,
+            RelocTarget::Symbol("external".into()),
+            1,
+        );
+        let loaded = loader
+            .load(|name| (name == "external").then_some(0x1234))
+            .unwrap();
+        let base = loaded.code().as_ptr() as u64;
+        let code = loaded.code();
+        assert_eq!(code[..8], (base + 0x1A).to_le_bytes());
+        assert_eq!(code[8..12], 4_i32.to_le_bytes());
+        assert_eq!(code[0x10..0x14], 0x97FF_FFFC_u32.to_le_bytes());
+        assert_eq!(code[0x18..0x1C], 0x1235_u32.to_le_bytes());
+        assert_eq!(loaded.code_symbols().get("start").unwrap().len, 0x10);
+        let mut loader = CodeLoader::new(code);
+        loader.relocate(0x1E, RelocKind::Abs32, RelocTarget::Local(0), 0);
+        assert_eq!(
+            loader.load(|_| None).unwrap_err(),
+            LoadError::RelocationOutOfBounds(0x1E)
+        );
+        let mut loader = CodeLoader::new(code);
+        loader.relocate(
+            0,
+            RelocKind::Abs64,
+            RelocTarget::Symbol("missing".into()),
+            0,
+        );
+        assert_eq!(
+            loader.load(|_| None).unwrap_err(),
+            LoadError::UnresolvedSymbol("missing".into())
+        );
+    }
+}
@@ -0,0 +1,174 @@
+use crate::*;
+use std::collections::{BTreeMap, HashMap};
+/// A function inside executable memory registered in [`CodeSymbols`].
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct CodeSymbol {
+    /// Name of the function.
+    pub name: String,
+    /// Offset of the first instruction of the function, from the start of the executable memory.
+    pub offset: usize,
+    /// Size of the function, in bytes.
+    pub len: usize,
+}
+/// Registry of functions inside executable memory, mapping names to offsets. Functions are registered as they are
+/// emitted, and can later be looked up by name as typed [`FnRef`]s, or by offset, e.g. to name the function a fault
+/// happened in. [`Self::jit_symbols`] describes them for [`PerfMap`] and [`GdbJitRegistration`].
+/// # Examples
+/// ```
+/// # use memory_pages::*;
+/// let mut memory: Pages<AllowRead, AllowWrite, DenyExec> = Pages::new(0x1000);
+/// let mut symbols = CodeSymbols::new();
+/// // X86_64 assembly for `mov eax, 7; ret` and `ret`.
+/// memory.get_mut(..6).unwrap().copy_from_slice(&[0xB8, 7, 0, 0, 0, 0xC3]);
+/// symbols.register("seven", 0, 6);
+/// memory[6] = 0xC3;
+/// symbols.register("nop", 6, 1);
+/// let memory = memory.set_protected_exec();
+/// assert_eq!(symbols.symbol_at(3).unwrap().name, "seven");
+/// # #[cfg(target_arch = "x86_64")]
+/// # {
+/// let seven: FnRef<unsafe extern "C" fn() -> u32> = unsafe { symbols.get_fn(&memory, "seven") }.unwrap();
+/// assert_eq!(unsafe { seven.call(()) }, 7);
+/// # }
+/// ```
+#[derive(Clone, Debug, Default)]
+pub struct CodeSymbols {
+    /// Symbols, keyed by their offsets.
+    symbols: BTreeMap<usize, CodeSymbol>,
+    /// Offsets of symbols, keyed by their names.
+    by_name: HashMap<String, usize>,
+}
+impl CodeSymbols {
+    /// Creates a new, empty [`CodeSymbols`].
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+    /// Registers function `name`, which starts at `offset` and is `len` bytes long.
+    /// # Panics
+    /// Panics if a function with the same name or offset is already registered, or if `offset + len` overflows.
+    pub fn register(&mut self, name: impl Into<String>, offset: usize, len: usize) {
+        let name = name.into();
+        assert!(
+            offset.checked_add(len).is_some(),
+            "Symbol `{name}` ends past the end of the address space!"
+        );
+        assert!(
+            !self.by_name.contains_key(&name),
+            "Symbol `{name}` is already registered!"
+        );
+        assert!(
+            !self.symbols.contains_key(&offset),
+            "A symbol at {offset:#x} is already registered!"
+        );
+        self.by_name.insert(name.clone(), offset);
+        self.symbols
+            .insert(offset, CodeSymbol { name, offset, len });
+    }
+    /// Removes function `name`, returning it, or `None` if it was not registered.
+    pub fn unregister(&mut self, name: &str) -> Option<CodeSymbol> {
+        let offset = self.by_name.remove(name)?;
+        self.symbols.remove(&offset)
+    }
+    /// Returns the number of registered functions.
+    #[must_use]
+    pub fn len(&self) -> usize {
+        self.symbols.len()
+    }
+    /// Checks if no functions are registered.
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.symbols.is_empty()
+    }
+    /// Returns function `name`, or `None` if it is not registered.
+    #[must_use]
+    pub fn get(&self, name: &str) -> Option<&CodeSymbol> {
+        self.by_name
+            .get(name)
+            .and_then(|offset| self.symbols.get(offset))
+    }
+    /// Returns the function containing the instruction at `offset`, or `None` if no function contains it.
+    #[must_use]
+    pub fn symbol_at(&self, offset: usize) -> Option<&CodeSymbol> {
+        self.symbols
+            .range(..=offset)
+            .next_back()
+            .map(|(_, symbol)| symbol)
+            .filter(|symbol| offset < symbol.offset + symbol.len)
+    }
+    /// Returns an iterator over all registered functions, sorted by offset.
+    pub fn iter(&self) -> impl DoubleEndedIterator<Item = &CodeSymbol> + ExactSizeIterator {
+        self.symbols.values()
+    }
+    /// Gets function `name` from `pages`, or `None` if it is not registered.
+    /// # Safety
+    /// The bytes at offset of the function must represent native instructions creating a function with a matching
+    /// signature to function pointer type F.
+    /// # Panics
+    /// Panics if the function starts past the end of `pages`.
+    #[must_use]
+    pub unsafe fn get_fn<'a, F, R: ReadPremisionMarker, W: WritePremisionMarker>(
+        &self,
+        pages: &'a Pages<R, W, AllowExec>,
+        name: &str,
+    ) -> Option<FnRef<'a, F>>
+    where
+        F: ExternFnPtr + Copy + std::fmt::Pointer + Sized,
+    {
+        self.get(name).map(|symbol| pages.get_fn(symbol.offset))
+    }
+    /// Marks all registered functions inside `pages` as valid targets of indirect calls, using
+    /// [`Pages::set_valid_call_targets`].
+    /// # Errors
+    /// Returns an error if the kernel refuses to mark the targets as valid.
+    /// # Panics
+    /// Panics if a function does not start at an offset aligned to 16 bytes, or starts past the end of `pages`.
+    pub fn set_valid_call_targets<R: ReadPremisionMarker, W: WritePremisionMarker>(
+        &self,
+        pages: &Pages<R, W, AllowExec>,
+    ) -> std::io::Result<()> {
+        let offsets: Vec<usize> = self.symbols.keys().copied().collect();
+        pages.set_valid_call_targets(&offsets)
+    }
+    /// Returns the name, address and size of each registered function placed in memory starting at `base`, sorted by
+    /// address, for use with [`PerfMap::record`] or [`GdbJitRegistration::register_functions`].
+    #[must_use]
+    pub fn jit_symbols(&self, base: *const u8) -> Vec<JitSymbol<'_>> {
+        self.iter()
+            .map(|symbol| JitSymbol {
+                name: &symbol.name,
+                address: base.wrapping_add(symbol.offset),
+                size: symbol.len,
+            })
+            .collect()
+    }
+}
+#[cfg(test)]
+mod test {
+    use super::*;
+    #[test]
+    fn test_code_symbols() {
+        let mut symbols = CodeSymbols::new();
+        symbols.register("b", 0x20, 0x10);
+        symbols.register("a", 0, 0x10);
+        assert_eq!(symbols.len(), 2);
+        assert_eq!(symbols.get("b").unwrap().offset, 0x20);
+        assert_eq!(symbols.symbol_at(0xF).unwrap().name, "a");
+        // Gaps between functions belong to none of them.
+        assert_eq!(symbols.symbol_at(0x10), None);
+        assert_eq!(symbols.symbol_at(0x2F).unwrap().name, "b");
+        assert_eq!(symbols.symbol_at(0x30), None);
+        let base = 0x1000 as *const u8;
+        let jit = symbols.jit_symbols(base);
+        assert_eq!(jit[0].name, "a");
+        assert_eq!(jit[1].address, base.wrapping_add(0x20));
+        assert_eq!(symbols.unregister("a").unwrap().len, 0x10);
+        assert_eq!(symbols.symbol_at(0), None);
+        assert!(symbols.unregister("a").is_none());
+        symbols.register("a", 0x40, 1);
+        assert_eq!(
+            symbols.iter().map(|s| s.name.as_str()).collect::<Vec<_>>(),
+            ["b", "a"]
+        );
+    }
+}
@@ -0,0 +1,187 @@
+use crate::dyn_pages::protect_range_unchecked;
+use crate::*;
+use std::collections::HashSet;
+use std::fmt::{Display, Formatter};
+use std::sync::OnceLock;
+/// Verifier installed with [`set_code_verifier`].
+static VERIFIER: OnceLock<Box<dyn CodeVerifier>> = OnceLock::new();
+/// Policy deciding which code may become executable, e.g. by checking it against a list of known digests, or verifying
+/// its signature. Once installed with [`set_code_verifier`], the verifier checks the contents of [`Pages`] every time
+/// they become executable: when their type changes from one with [`DenyExec`] to one with [`AllowExec`], when
+/// non-executable [`DynPages`] are made executable, and when executable [`Pages`] made writable with
+/// [`Pages::with_writable`] or [`Pages::write_guard`] become executable again, which includes code placed by
+/// [`JitAllocator`] and breakpoints. Pages are made read-only before they are verified, so code can't change between
+/// being checked and running. Rejected code is left read-only and not executable, [`DynPages::set_permissions`]
+/// returns an error, [`WriteGuard`]s are dropped silently, and all other transitions panic.
+///
+/// Freshly allocated executable pages hold no code, and are not verified. Neither is code in [`JitDualMap`], or in
+/// [`JitPages`] on macOS, since it becomes executable without any change of protection.
+///
+/// Closures taking the code and returning a [`Result`] are verifiers too.
+/// # Examples
+/// ```
+/// # use memory_pages::*;
+/// // Only code starting with `ENDBR64` may execute.
+/// assert!(set_code_verifier(|code: &[u8]| {
+///     if code.starts_with(&[0xF3, 0x0F, 0x1E, 0xFA]) {
+///         Ok(())
+///     } else {
+///         Err(VerificationError::Rejected("missing landing pad".into()))
+///     }
+/// }));
+/// let mut memory: Pages<AllowRead, AllowWrite, DenyExec> = Pages::new(0x1000);
+/// // X86_64 assembly for `endbr64; ret`.
+/// memory.get_mut(..5).unwrap().copy_from_slice(&[0xF3, 0x0F, 0x1E, 0xFA, 0xC3]);
+/// let memory = memory.set_protected_exec();
+/// ```
+pub trait CodeVerifier: Send + Sync {
+    /// Checks if `code`, the whole contents of [`Pages`] about to become executable, may execute.
+    /// # Errors
+    /// Returns an error if `code` must not execute.
+    fn verify(&self, code: &[u8]) -> Result<(), VerificationError>;
+}
+impl<F: Fn(&[u8]) -> Result<(), VerificationError> + Send + Sync> CodeVerifier for F {
+    fn verify(&self, code: &[u8]) -> Result<(), VerificationError> {
+        self(code)
+    }
+}
+/// Error returned when code is not allowed to become executable.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum VerificationError {
+    /// No verifier is installed, but the `require_code_verifier` feature is enabled.
+    NoVerifier,
+    /// Code has a digest missing from a [`DigestAllowList`].
+    UnknownDigest(CodeDigest),
+    /// Code was rejected by a verifier, for the given reason.
+    Rejected(String),
+}
+impl Display for VerificationError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::NoVerifier => write!(f, "code can't execute, since no verifier is installed"),
+            Self::UnknownDigest(digest) => write!(f, "code with digest {digest} is not allowed"),
+            Self::Rejected(reason) => write!(f, "code was rejected: {reason}"),
+        }
+    }
+}
+impl std::error::Error for VerificationError {}
+/// [`CodeVerifier`] which only allows code whose [`CodeDigest`] is on a list. Digests are computed over the whole
+/// contents of the pages, including the padding after the end of the code.
+/// # Examples
+/// ```
+/// # use memory_pages::*;
+/// let mut memory: Pages<AllowRead, AllowWrite, DenyExec> = Pages::new(0x1000);
+/// memory[0] = 0xC3;
+/// let allowed: DigestAllowList = [CodeDigest::of(&memory)].into_iter().collect();
+/// assert!(allowed.verify(&memory).is_ok());
+/// memory[0] = 0x90;
+/// assert!(allowed.verify(&memory).is_err());
+/// ```
+#[derive(Clone, Debug, Default)]
+pub struct DigestAllowList {
+    digests: HashSet<CodeDigest>,
+}
+impl DigestAllowList {
+    /// Creates a new, empty [`DigestAllowList`], which rejects all code.
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+    /// Allows code with `digest` to execute.
+    pub fn allow(&mut self, digest: CodeDigest) {
+        self.digests.insert(digest);
+    }
+    /// Checks if code with `digest` may execute.
+    #[must_use]
+    pub fn contains(&self, digest: &CodeDigest) -> bool {
+        self.digests.contains(digest)
+    }
+}
+impl FromIterator<CodeDigest> for DigestAllowList {
+    fn from_iter<T: IntoIterator<Item = CodeDigest>>(iter: T) -> Self {
+        Self {
+            digests: iter.into_iter().collect(),
+        }
+    }
+}
+impl CodeVerifier for DigestAllowList {
+    fn verify(&self, code: &[u8]) -> Result<(), VerificationError> {
+        let digest = CodeDigest::of(code);
+        if self.contains(&digest) {
+            Ok(())
+        } else {
+            Err(VerificationError::UnknownDigest(digest))
+        }
+    }
+}
+/// Installs `verifier`, which from now on checks all code before it becomes executable, as described in
+/// [`CodeVerifier`]. A verifier can only be installed once, and is never replaced, so code running later can't weaken
+/// the policy. Returns false if a verifier was already installed.
+///
+/// With the `require_code_verifier` feature enabled, no code may become executable until a verifier is installed.
+#[must_use]
+pub fn set_code_verifier(verifier: impl CodeVerifier + 'static) -> bool {
+    VERIFIER.set(Box::new(verifier)).is_ok()
+}
+/// Checks if the `len` bytes starting at `ptr`, which are about to become executable, may execute. Leaves them
+/// read-only if a verifier is installed.
+/// # Panics
+/// Panics if kernel can't/refuses to change protection of the bytes.
+pub(crate) fn verify_code(ptr: *mut u8, len: usize) -> Result<(), VerificationError> {
+    let Some(verifier) = VERIFIER.get() else {
+        if cfg!(feature = "require_code_verifier") {
+            return Err(VerificationError::NoVerifier);
+        }
+        return Ok(());
+    };
+    // Other threads could otherwise modify the code after it was checked.
+    if let Err(err) = protect_range_unchecked(ptr, len, Protection::Read) {
+        panic!("Failed to change memory protection mode:'{err}'!");
+    }
+    verifier.verify(unsafe { std::slice::from_raw_parts(ptr, len) })
+}
+#[cfg(test)]
+mod test {
+    use super::*;
+    /// Code starting with this is rejected by the verifier installed by tests.
+    const REJECTED: &[u8] = b"REJECTED";
+    #[test]
+    fn test_code_verifier() {
+        let installed = set_code_verifier(|code: &[u8]| {
+            if code.starts_with(REJECTED) {
+                Err(VerificationError::Rejected("marked as rejected".into()))
+            } else {
+                Ok(())
+            }
+        });
+        assert!(installed);
+        assert!(!set_code_verifier(DigestAllowList::new()));
+        let mut memory: Pages<AllowRead, AllowWrite, DenyExec> = Pages::new(0x1000);
+        memory[0] = 0xC3;
+        let mut memory = memory.set_protected_exec();
+        let res = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+            memory.with_writable(|code| code[..REJECTED.len()].copy_from_slice(REJECTED));
+        }));
+        assert!(res.is_err());
+        // Rejected code is left read-only.
+        assert_eq!(memory.get(..REJECTED.len()).unwrap(), REJECTED);
+        #[cfg(feature = "allow_exec")]
+        {
+            let mut memory = DynPages::new(0x1000, Protection::ReadWrite).unwrap();
+            memory
+                .get_mut(0..REJECTED.len())
+                .unwrap()
+                .copy_from_slice(REJECTED);
+            let res = memory.set_permissions(Protection::ReadExec);
+            assert!(matches!(res, Err(ProtectionError::Verification(_))));
+            assert_eq!(memory.protection(), Protection::Read);
+        }
+        let mut allowed = DigestAllowList::new();
+        allowed.allow(CodeDigest::of(&[0xC3]));
+        assert!(allowed.verify(&[0xC3]).is_ok());
+        assert_eq!(
+            allowed.verify(&[0x90]),
+            Err(VerificationError::UnknownDigest(CodeDigest::of(&[0x90])))
+        );
+    }
+}
@@ -0,0 +1,269 @@
+use crate::*;
+use std::fmt::{Debug, Formatter};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::Mutex;
+/// An append-only vector, which many threads can push to at once. Like [`StablePagedVec`], it reserves address space
+/// for its maximal capacity up front, so elements never move, and references to them stay valid for as long as the
+/// vector is borrowed, even while other threads push to it.
+///
+/// Pushing never waits for other pushing threads: it claims a slot with an atomic operation, and takes a lock only when
+/// more pages need to be committed. Each slot is marked once its element is written, which makes the element visible
+/// to [`Self::get`] right away. [`Self::len`] and [`Self::as_slice`] only cover the prefix of slots which are all
+/// written, and whichever thread finishes writing last extends it, so readers always see a fully initialized prefix of
+/// the vector.
+/// # Examples
+/// ```
+/// # use memory_pages::*;
+/// let edges:ConcurrentPagedVec<(u32, u32)> = ConcurrentPagedVec::new(0x100_000);
+/// std::thread::scope(|s|{
+///     for thread in 0..4{
+///         let edges = &edges;
+///         s.spawn(move ||{
+///             for i in 0..0x1000{
+///                 edges.push((thread, i));
+///             }
+///         });
+///     }
+/// });
+/// assert_eq!(edges.len(), 0x4000);
+/// assert_eq!(edges.as_slice().iter().filter(|(thread, _)| *thread == 2).count(), 0x1000);
+/// ```
+pub struct ConcurrentPagedVec<T> {
+    /// Reserved address space. Only locked to commit more of it.
+    memory: Mutex<Reservation>,
+    elements: NonNull<T>,
+    /// One flag per slot, set once its element is written.
+    ready: NonNull<AtomicBool>,
+    max_capacity: usize,
+    /// Number of slots at the beginning of `memory` that are committed.
+    committed: AtomicUsize,
+    /// Number of slots claimed by pushing threads, including ones not written yet.
+    claimed: AtomicUsize,
+    /// Number of published elements. All elements before it are initialized.
+    len: AtomicUsize,
+    pd: PhantomData<T>,
+}
+/// Address space reserved by a [`ConcurrentPagedVec`].
+struct Reservation {
+    /// Elements, unless they are zero-sized.
+    elements: Option<SparsePages>,
+    ready: SparsePages,
+}
+// Pushing moves `T` into the vector from other threads, and reading shares it between threads.
+unsafe impl<T: Send> Send for ConcurrentPagedVec<T> {}
+unsafe impl<T: Send + Sync> Sync for ConcurrentPagedVec<T> {}
+impl<T> ConcurrentPagedVec<T> {
+    /// Creates a new, empty [`ConcurrentPagedVec`], reserving address space for `max_capacity` elements without
+    /// committing any of it. Besides its element, each slot takes a byte marking if the element was written, so even
+    /// zero-sized elements take address space.
+    /// # Panics
+    /// Panics if size of `max_capacity` elements exceeds `isize::MAX` bytes, or if kernel can't/refuses to reserve
+    /// address space.
+    #[must_use]
+    pub fn new(max_capacity: usize) -> Self {
+        let bytes = PagedVec::<T>::bytes_for(max_capacity)
+            .unwrap_or_else(|err| panic!("Reserving ConcurrentPagedVec failed:'{err}'!"));
+        let (mut elements, max_capacity) = match std::mem::size_of::<T>() {
+            0 => (None, max_capacity),
+            size => {
+                let memory = SparsePages::new(bytes.max(1));
+                let max_capacity = memory.len() / size;
+                (Some(memory), max_capacity)
+            }
+        };
+        let mut ready = SparsePages::new(max_capacity.max(1));
+        let elements_ptr = elements.as_mut().map_or(NonNull::dangling(), |elements| {
+            NonNull::new(elements.as_mut_ptr().cast()).expect("reservation is never null")
+        });
+        let ready_ptr = NonNull::new(ready.as_mut_ptr().cast()).expect("reservation is never null");
+        Self {
+            max_capacity,
+            memory: Mutex::new(Reservation { elements, ready }),
+            elements: elements_ptr,
+            ready: ready_ptr,
+            committed: AtomicUsize::new(0),
+            claimed: AtomicUsize::new(0),
+            len: AtomicUsize::new(0),
+            pd: PhantomData,
+        }
+    }
+    /// Returns the number of published elements. Other threads may push more elements right after this returns.
+    #[must_use]
+    pub fn len(&self) -> usize {
+        self.len.load(Ordering::Acquire)
+    }
+    /// Checks if no elements were published yet.
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+    /// Returns the number of elements this [`ConcurrentPagedVec`] can ever hold.
+    #[must_use]
+    pub fn max_capacity(&self) -> usize {
+        self.max_capacity
+    }
+    /// Appends `t` to the end of this [`ConcurrentPagedVec`], and returns its index.
+    /// # Panics
+    /// Panics if the maximal capacity is already reached, or if kernel can't/refuses to commit more pages.
+    pub fn push(&self, t: T) -> usize {
+        self.try_push(t)
+            .unwrap_or_else(|_| panic!("Maximal capacity of ConcurrentPagedVec exceeded!"))
+    }
+    /// Appends `t` to the end of this [`ConcurrentPagedVec`] and returns its index, or returns `t` back if the maximal
+    /// capacity is already reached. The element can be accessed using [`Self::get`] right away, but is only a part of
+    /// [`Self::as_slice`] once all elements before it are written too.
+    /// # Errors
+    /// Returns `t` if there is no space left for it.
+    /// # Panics
+    /// Panics if kernel can't/refuses to commit more pages.
+    pub fn try_push(&self, t: T) -> Result<usize, T> {
+        let mut index = self.claimed.load(Ordering::Acquire);
+        loop {
+            if index >= self.max_capacity {
+                return Err(t);
+            }
+            // Slots are committed before they are claimed, so a panic while committing can't leave a claimed slot
+            // which is never written.
+            self.commit_for(index + 1);
+            match self.claimed.compare_exchange_weak(
+                index,
+                index + 1,
+                Ordering::AcqRel,
+                Ordering::Acquire,
+            ) {
+                Ok(_) => break,
+                Err(claimed) => index = claimed,
+            }
+        }
+        unsafe { self.elements.as_ptr().add(index).write(t) };
+        self.ready(index).store(true, Ordering::SeqCst);
+        self.publish();
+        Ok(index)
+    }
+    /// Extends the published prefix past all slots which are written. Each pushing thread does this after marking its
+    /// own slot, so elements written by threads which finished earlier are published by the last one to finish.
+    fn publish(&self) {
+        let mut len = self.len.load(Ordering::SeqCst);
+        // Slots which were not claimed yet may not be committed.
+        while len < self.claimed.load(Ordering::Acquire) && self.ready(len).load(Ordering::SeqCst) {
+            match self
+                .len
+                .compare_exchange_weak(len, len + 1, Ordering::SeqCst, Ordering::SeqCst)
+            {
+                Ok(_) => len += 1,
+                Err(published) => len = published,
+            }
+        }
+    }
+    /// Returns the flag marking if the element in slot `index` is written. The slot must be committed.
+    fn ready(&self, index: usize) -> &AtomicBool {
+        unsafe { &*self.ready.as_ptr().add(index) }
+    }
+    /// Returns a reference to the element at `index`, or `None` if it was not written yet.
+    #[must_use]
+    pub fn get(&self, index: usize) -> Option<&T> {
+        if index >= self.claimed.load(Ordering::Acquire)
+            || !self.ready(index).load(Ordering::Acquire)
+        {
+            return None;
+        }
+        Some(unsafe { &*self.elements.as_ptr().add(index) })
+    }
+    /// Returns a snapshot of all elements published so far. Elements pushed later are not a part of it, but it stays
+    /// valid while they are being pushed.
+    #[must_use]
+    pub fn as_slice(&self) -> &[T] {
+        unsafe { std::slice::from_raw_parts(self.elements.as_ptr(), self.len()) }
+    }
+    /// Returns all elements as a mutable slice. Exclusive access guarantees no pushes are in progress.
+    #[must_use]
+    pub fn as_mut_slice(&mut self) -> &mut [T] {
+        let len = *self.len.get_mut();
+        unsafe { std::slice::from_raw_parts_mut(self.elements.as_ptr(), len) }
+    }
+    /// Makes sure pages holding the first `len` slots are committed.
+    fn commit_for(&self, len: usize) {
+        if len <= self.committed.load(Ordering::Acquire) {
+            return;
+        }
+        let mut memory = self
+            .memory
+            .lock()
+            .unwrap_or_else(std::sync::PoisonError::into_inner);
+        // Another thread may have committed the pages while this one was waiting for the lock.
+        let committed = self.committed.load(Ordering::Acquire);
+        if len <= committed {
+            return;
+        }
+        let slots = len.max(committed.saturating_mul(2)).min(self.max_capacity);
+        if let Some(elements) = &mut memory.elements {
+            let size = std::mem::size_of::<T>();
+            elements.commit(committed * size, (slots - committed) * size);
+        }
+        memory.ready.commit(committed, slots - committed);
+        self.committed.store(slots, Ordering::Release);
+    }
+}
+impl<T: Debug> Debug for ConcurrentPagedVec<T> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_list().entries(self.as_slice()).finish()
+    }
+}
+impl<T> Drop for ConcurrentPagedVec<T> {
+    fn drop(&mut self) {
+        unsafe { std::ptr::drop_in_place(self.as_mut_slice()) };
+    }
+}
+#[cfg(test)]
+mod test {
+    use super::*;
+    #[test]
+    fn test_concurrent_push() {
+        let vec: ConcurrentPagedVec<String> = ConcurrentPagedVec::new(0x10_000);
+        std::thread::scope(|s| {
+            for thread in 0..8 {
+                let vec = &vec;
+                s.spawn(move || {
+                    for i in 0..0x800 {
+                        let index = vec.push(format!("{thread}:{i}"));
+                        assert_eq!(vec.get(index), Some(&format!("{thread}:{i}")));
+                    }
+                });
+            }
+            // Snapshots taken while pushing is in progress are fully initialized.
+            let vec = &vec;
+            s.spawn(move || {
+                for _ in 0..0x100 {
+                    assert!(vec.as_slice().iter().all(|s| s.contains(':')));
+                }
+            });
+        });
+        let mut all: Vec<&String> = vec.as_slice().iter().collect();
+        assert_eq!(all.len(), 0x4000);
+        all.sort();
+        all.dedup();
+        assert_eq!(all.len(), 0x4000);
+    }
+    #[test]
+    fn test_concurrent_zero_sized() {
+        let vec: ConcurrentPagedVec<()> = ConcurrentPagedVec::new(0x1000);
+        std::thread::scope(|s| {
+            for _ in 0..4 {
+                s.spawn(|| (0..0x400).for_each(|_| _ = vec.push(())));
+            }
+        });
+        assert_eq!(vec.len(), 0x1000);
+        assert_eq!(vec.try_push(()), Err(()));
+    }
+    #[test]
+    fn test_concurrent_max_capacity() {
+        let mut vec: ConcurrentPagedVec<u64> = ConcurrentPagedVec::new(0x200);
+        for i in 0..0x200 {
+            assert_eq!(vec.push(i), i as usize);
+        }
+        assert_eq!(vec.try_push(0x200), Err(0x200));
+        vec.as_mut_slice()[0] = 7;
+        assert_eq!(vec.get(0), Some(&7));
+        assert_eq!(vec.len(), vec.max_capacity());
+    }
+}
@@ -0,0 +1,118 @@
+use crate::*;
+use std::io;
+/// Granularity at which Control Flow Guard tracks valid call targets.
+const CALL_TARGET_ALIGN: usize = 16;
+/// Checks if the current process is protected by Windows Control Flow Guard (CFG), which kills the process when an
+/// indirect call lands on an address not marked as a valid call target. Always false on other systems.
+/// # Examples
+/// ```
+/// # use memory_pages::*;
+/// if !cfg!(target_family = "windows") {
+///     assert!(!control_flow_guard_enabled());
+/// }
+/// ```
+#[must_use]
+pub fn control_flow_guard_enabled() -> bool {
+    #[cfg(target_family = "windows")]
+    {
+        use winapi::um::processthreadsapi::{GetCurrentProcess, GetProcessMitigationPolicy};
+        use winapi::um::winnt::{
+            ProcessControlFlowGuardPolicy, PROCESS_MITIGATION_CONTROL_FLOW_GUARD_POLICY,
+        };
+        let mut policy = PROCESS_MITIGATION_CONTROL_FLOW_GUARD_POLICY { Flags: 0 };
+        let res = unsafe {
+            GetProcessMitigationPolicy(
+                GetCurrentProcess(),
+                ProcessControlFlowGuardPolicy,
+                std::ptr::addr_of_mut!(policy).cast(),
+                std::mem::size_of_val(&policy),
+            )
+        };
+        res != 0 && policy.EnableControlFlowGuard() != 0
+    }
+    #[cfg(not(target_family = "windows"))]
+    false
+}
+impl<R: ReadPremisionMarker, W: WritePremisionMarker> Pages<R, W, AllowExec> {
+    /// Marks functions at `offsets` as valid targets of indirect calls, so calling them does not get the process killed
+    /// by Control Flow Guard. Functions must start at offsets aligned to 16 bytes, since that is the granularity at
+    /// which call targets are tracked.
+    ///
+    /// Does nothing on systems other than Windows, or in processes without Control Flow Guard.
+    /// # Errors
+    /// Returns an error if the kernel refuses to mark the targets as valid.
+    /// # Panics
+    /// Panics if an offset is not aligned to 16 bytes, or is past the end of this [`Pages`].
+    /// # Examples
+    /// ```
+    /// # use memory_pages::*;
+    /// let mut memory: Pages<AllowRead, AllowWrite, DenyExec> = Pages::new(0x1000);
+    /// // X86_64 assembly instruction `RET`, at two entry points.
+    /// memory[0] = 0xC3;
+    /// memory[0x20] = 0xC3;
+    /// let memory = memory.set_protected_exec();
+    /// memory.set_valid_call_targets(&[0, 0x20]).unwrap();
+    /// ```
+    pub fn set_valid_call_targets(&self, offsets: &[usize]) -> io::Result<()> {
+        for &offset in offsets {
+            assert!(
+                offset.is_multiple_of(CALL_TARGET_ALIGN),
+                "Call target at {offset:#x} is not aligned to {CALL_TARGET_ALIGN} bytes!"
+            );
+            assert!(
+                offset < self.len,
+                "Call target at {offset:#x} is past the end of Pages!"
+            );
+        }
+        #[cfg(target_family = "windows")]
+        if control_flow_guard_enabled() {
+            return set_valid_call_targets(self.ptr.as_ptr(), self.len, offsets);
+        }
+        Ok(())
+    }
+}
+/// Marks `offsets` from `start`, inside a single allocation `len` bytes long, as valid call targets.
+#[cfg(target_family = "windows")]
+fn set_valid_call_targets(start: *mut u8, len: usize, offsets: &[usize]) -> io::Result<()> {
+    use winapi::shared::minwindef::{BOOL, ULONG};
+    use winapi::shared::ntdef::{HANDLE, PVOID};
+    use winapi::um::libloaderapi::{GetModuleHandleW, GetProcAddress};
+    use winapi::um::processthreadsapi::GetCurrentProcess;
+    use winapi::um::winnt::{CFG_CALL_TARGET_INFO, CFG_CALL_TARGET_VALID};
+    type SetProcessValidCallTargets =
+        unsafe extern "system" fn(HANDLE, PVOID, usize, ULONG, *mut CFG_CALL_TARGET_INFO) -> BOOL;
+    let count = ULONG::try_from(offsets.len())
+        .unwrap_or_else(|_| panic!("Maximal capacity of call targets exceeded!"));
+    // Not every import library exports the function, and older systems lack it, so it is looked up at runtime.
+    let module: Vec<u16> = "kernelbase.dll\0".encode_utf16().collect();
+    let function = unsafe {
+        GetProcAddress(
+            GetModuleHandleW(module.as_ptr()),
+            c"SetProcessValidCallTargets".as_ptr(),
+        )
+    };
+    if function.is_null() {
+        return Err(io::Error::last_os_error());
+    }
+    let function: SetProcessValidCallTargets = unsafe { std::mem::transmute(function) };
+    let mut targets: Vec<CFG_CALL_TARGET_INFO> = offsets
+        .iter()
+        .map(|&offset| CFG_CALL_TARGET_INFO {
+            Offset: offset,
+            Flags: CFG_CALL_TARGET_VALID,
+        })
+        .collect();
+    let res = unsafe {
+        function(
+            GetCurrentProcess(),
+            start.cast(),
+            len,
+            count,
+            targets.as_mut_ptr(),
+        )
+    };
+    if res == 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
@@ -0,0 +1,325 @@
+use crate::*;
+use std::ops::Range;
+/// Set of permissions [`DynPages`] may have, chosen at runtime.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Protection {
+    /// Pages can't be accessed at all.
+    NoAccess,
+    /// Pages can only be read from.
+    Read,
+    /// Pages can be read from and written into.
+    ReadWrite,
+    /// Pages can only be executed. Requires the `allow_exec` feature.
+    Exec,
+    /// Pages can be read from and executed. Requires the `allow_exec` feature.
+    ReadExec,
+    /// Pages can be read from, written into and executed. Requires the `allow_exec` feature, and is always rejected
+    /// when the `deny_xw` feature is enabled.
+    ReadWriteExec,
+}
+impl Protection {
+    /// Checks if this protection allows reads.
+    #[must_use]
+    pub fn allows_read(self) -> bool {
+        !matches!(self, Self::NoAccess | Self::Exec)
+    }
+    /// Checks if this protection allows writes.
+    #[must_use]
+    pub fn allows_write(self) -> bool {
+        matches!(self, Self::ReadWrite | Self::ReadWriteExec)
+    }
+    /// Checks if this protection allows execution.
+    #[must_use]
+    pub fn allows_exec(self) -> bool {
+        matches!(self, Self::Exec | Self::ReadExec | Self::ReadWriteExec)
+    }
+    /// Checks if this protection may be set, given the enabled features.
+    fn validate(self) -> Result<(), ProtectionError> {
+        if self.allows_exec() && !cfg!(feature = "allow_exec") {
+            return Err(ProtectionError::ExecNotAllowed);
+        }
+        if self.allows_exec() && self.allows_write() && cfg!(feature = "deny_xw") {
+            return Err(ProtectionError::WriteExecDenied);
+        }
+        Ok(())
+    }
+    #[cfg(target_family = "unix")]
+    pub(crate) fn bitmask(self) -> c_int {
+        (self.allows_read() as c_int * AllowRead::bitmask())
+            | (self.allows_write() as c_int * AllowWrite::bitmask())
+            | (self.allows_exec() as c_int * 0x4)
+    }
+    #[cfg(target_family = "windows")]
+    pub(crate) fn fl_protect(self) -> u32 {
+        match self {
+            Self::NoAccess => PAGE_NOACCESS,
+            Self::Read => PAGE_READONLY,
+            Self::ReadWrite => PAGE_READWRITE,
+            Self::Exec => PAGE_EXECUTE,
+            Self::ReadExec => PAGE_EXECUTE_READ,
+            Self::ReadWriteExec => PAGE_EXECUTE_READWRITE,
+        }
+    }
+}
+/// Sets protection of `len` bytes starting at page-aligned `ptr` to `protection`.
+pub(crate) fn protect_range(
+    ptr: *mut u8,
+    len: usize,
+    protection: Protection,
+) -> Result<(), ProtectionError> {
+    protection.validate()?;
+    protect_range_unchecked(ptr, len, protection)
+}
+/// Sets protection of `len` bytes starting at page-aligned `ptr` to `protection`, even if enabled features forbid it.
+/// Used for pages whose type already allows that protection.
+pub(crate) fn protect_range_unchecked(
+    ptr: *mut u8,
+    len: usize,
+    protection: Protection,
+) -> Result<(), ProtectionError> {
+    #[cfg(target_family = "unix")]
+    let mask = branch_protection::with_bti(protection.bitmask());
+    #[cfg(target_family = "unix")]
+    let res = unsafe { mprotect(ptr.cast::<c_void>(), len, mask) } != -1;
+    #[cfg(target_family = "windows")]
+    let res = {
+        let mut _old: u32 = 0;
+        let res = unsafe {
+            VirtualProtect(
+                ptr.cast::<winapi::ctypes::c_void>(),
+                len,
+                protection.fl_protect(),
+                &mut _old as *mut _,
+            )
+        };
+        res != 0
+    };
+    if !res {
+        return Err(ProtectionError::Os(std::io::Error::last_os_error()));
+    }
+    Ok(())
+}
+/// Error returned when permissions of [`DynPages`] can't be changed.
+#[derive(Debug)]
+pub enum ProtectionError {
+    /// Execution was requested, but the `allow_exec` feature is not enabled.
+    ExecNotAllowed,
+    /// Both writes and execution were requested, but the `deny_xw` feature is enabled.
+    WriteExecDenied,
+    /// Kernel refused to change the protection.
+    Os(std::io::Error),
+    /// Execution was requested, but the code was rejected by the installed [`CodeVerifier`].
+    #[cfg(any(feature = "allow_exec", doc, test))]
+    Verification(VerificationError),
+}
+impl std::fmt::Display for ProtectionError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::ExecNotAllowed => write!(f, "execution requires the `allow_exec` feature"),
+            Self::WriteExecDenied => write!(
+                f,
+                "pages can't be both writable and executable with the `deny_xw` feature"
+            ),
+            Self::Os(err) => write!(f, "failed to change memory protection: {err}"),
+            #[cfg(any(feature = "allow_exec", doc, test))]
+            Self::Verification(err) => write!(f, "code can't be made executable: {err}"),
+        }
+    }
+}
+impl std::error::Error for ProtectionError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Self::Os(err) => Some(err),
+            #[cfg(any(feature = "allow_exec", doc, test))]
+            Self::Verification(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+/// [`DynPages`] are [`Pages`] whose permissions are checked at runtime instead of being encoded in their type. Useful
+/// when permissions are not known at compile time, e.g. when they are read from a config file.
+/// # Examples
+/// ```
+/// # use memory_pages::*;
+/// let mut memory = DynPages::new(0x1000, Protection::ReadWrite).unwrap();
+/// memory.get_mut(0..4).unwrap().copy_from_slice(&[1, 2, 3, 4]);
+/// memory.set_permissions(Protection::Read).unwrap();
+/// // Writes are no longer allowed, but data can still be read.
+/// assert!(memory.get_mut(0..4).is_none());
+/// assert_eq!(memory.get(0..4).unwrap(), &[1, 2, 3, 4]);
+/// ```
+pub struct DynPages {
+    // Type of `pages` does not describe their actual protection, which is stored in `protection` instead.
+    pages: Pages<DenyRead, DenyWrite, DenyExec>,
+    protection: Protection,
+}
+impl DynPages {
+    /// Allocates new [`DynPages`] of size at least `length`, rounded up to next page boundary, with `protection` set.
+    /// # Errors
+    /// Returns an error if `protection` may not be set.
+    /// # Panics
+    /// Panics when a 0-sized allocation is attempted, or if kernel can't/refuses to allocate requested Pages.
+    pub fn new(length: usize, protection: Protection) -> Result<Self, ProtectionError> {
+        protection.validate()?;
+        let mut res = Self {
+            pages: Pages::new(length),
+            protection: Protection::NoAccess,
+        };
+        res.set_permissions(protection)?;
+        Ok(res)
+    }
+    /// Returns the current protection of this [`DynPages`].
+    #[must_use]
+    pub fn protection(&self) -> Protection {
+        self.protection
+    }
+    /// Changes protection of this [`DynPages`] to `protection`, in place.
+    /// # Errors
+    /// Returns an error if `protection` may not be set, if kernel refuses to change it, or if the code is rejected by the
+    /// installed [`CodeVerifier`] when it becomes executable. Protection is left unchanged on error, except for rejected
+    /// code, which is left read-only.
+    /// # Examples
+    /// ```
+    /// # use memory_pages::*;
+    /// let mut memory = DynPages::new(0x1000, Protection::NoAccess).unwrap();
+    /// assert!(memory.get(0..1).is_none());
+    /// memory.set_permissions(Protection::Read).unwrap();
+    /// assert_eq!(memory.get(0..1).unwrap(), &[0]);
+    /// ```
+    pub fn set_permissions(&mut self, protection: Protection) -> Result<(), ProtectionError> {
+        protection.validate()?;
+        if protection == self.protection {
+            return Ok(());
+        }
+        #[cfg(any(feature = "allow_exec", doc, test))]
+        if protection.allows_exec() && !self.protection.allows_exec() {
+            if let Err(err) = code_verifier::verify_code(self.pages.ptr.as_ptr(), self.pages.len) {
+                // Verifier leaves the code read-only.
+                if err != VerificationError::NoVerifier {
+                    self.protection = Protection::Read;
+                }
+                return Err(ProtectionError::Verification(err));
+            }
+        }
+        protect_range(self.pages.ptr.as_ptr(), self.pages.len, protection)?;
+        self.protection = protection;
+        if protection.allows_exec() {
+            flush_icache_range(self.pages.ptr.as_ptr(), self.pages.len);
+        }
+        Ok(())
+    }
+    /// Returns the length of this [`DynPages`], in bytes.
+    #[must_use]
+    pub fn len(&self) -> usize {
+        self.pages.len
+    }
+    /// Always returns false, because [`DynPages`] can't be empty.
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        false
+    }
+    /// Returns a pointer to the beginning of this [`DynPages`]. It may only be used in ways allowed by current protection.
+    #[must_use]
+    pub fn as_ptr(&self) -> *const u8 {
+        self.pages.ptr.as_ptr()
+    }
+    /// Gets the data in `range`, if reads are allowed and `range` is in bounds.
+    #[must_use]
+    pub fn get(&self, range: Range<usize>) -> Option<&[u8]> {
+        if !self.protection.allows_read() || range.start > range.end || range.end > self.len() {
+            return None;
+        }
+        Some(unsafe {
+            std::slice::from_raw_parts(self.pages.ptr.as_ptr().add(range.start), range.len())
+        })
+    }
+    /// Mutably gets the data in `range`, if writes are allowed and `range` is in bounds.
+    #[must_use]
+    pub fn get_mut(&mut self, range: Range<usize>) -> Option<&mut [u8]> {
+        if !self.protection.allows_write() || range.start > range.end || range.end > self.len() {
+            return None;
+        }
+        Some(unsafe {
+            std::slice::from_raw_parts_mut(self.pages.ptr.as_ptr().add(range.start), range.len())
+        })
+    }
+    /// Turns this [`DynPages`] into [`Pages`] with permissions described by their type.
+    /// # Panics
+    /// Panics if kernel can't/refuses to change protection, or if code becoming executable is rejected by the installed
+    /// [`CodeVerifier`].
+    /// # Examples
+    /// ```
+    /// # use memory_pages::*;
+    /// let mut memory = DynPages::new(0x1000, Protection::ReadWrite).unwrap();
+    /// memory.get_mut(0..1).unwrap()[0] = 7;
+    /// let memory:Pages<AllowRead,DenyWrite,DenyExec> = memory.into_pages();
+    /// assert_eq!(memory[0], 7);
+    /// ```
+    #[must_use]
+    pub fn into_pages<R: ReadPremisionMarker, W: WritePremisionMarker, E: ExecPremisionMarker>(
+        self,
+    ) -> Pages<R, W, E> {
+        // The actual protection may differ from the one described by the type of `self.pages`, so it must always be set.
+        let pages: Pages<R, W, E> = self.pages.retype();
+        #[cfg(any(feature = "allow_exec", doc, test))]
+        if E::allow_exec() && !self.protection.allows_exec() {
+            pages.verify_new_code();
+        }
+        pages.set_prot();
+        pages
+    }
+}
+impl<R: ReadPremisionMarker, W: WritePremisionMarker, E: ExecPremisionMarker> Pages<R, W, E> {
+    /// Turns this [`Pages`] into [`DynPages`], which keep track of their permissions at runtime.
+    /// # Examples
+    /// ```
+    /// # use memory_pages::*;
+    /// let memory:Pages<AllowRead,DenyWrite,DenyExec> = Pages::new(0x1000);
+    /// let memory = memory.into_dyn();
+    /// assert_eq!(memory.protection(), Protection::Read);
+    /// ```
+    #[must_use]
+    pub fn into_dyn(self) -> DynPages {
+        let protection = match (R::allow_read(), W::allow_write(), E::allow_exec()) {
+            (_, true, true) => Protection::ReadWriteExec,
+            (true, false, true) => Protection::ReadExec,
+            (false, false, true) => Protection::Exec,
+            (_, true, false) => Protection::ReadWrite,
+            (true, false, false) => Protection::Read,
+            (false, false, false) => Protection::NoAccess,
+        };
+        DynPages {
+            pages: self.retype(),
+            protection,
+        }
+    }
+}
+#[cfg(test)]
+mod test {
+    use super::*;
+    #[test]
+    fn test_set_permissions() {
+        let mut memory = DynPages::new(0x2000, Protection::Read).unwrap();
+        assert!(memory.get_mut(0..1).is_none());
+        memory.set_permissions(Protection::ReadWrite).unwrap();
+        memory.get_mut(0x1FFF..0x2000).unwrap()[0] = 9;
+        memory.set_permissions(Protection::NoAccess).unwrap();
+        assert!(memory.get(0..1).is_none());
+        let memory: Pages<AllowRead, AllowWrite, DenyExec> = memory.into_pages();
+        assert_eq!(memory[0x1FFF], 9);
+        let memory = memory.into_dyn();
+        assert_eq!(memory.protection(), Protection::ReadWrite);
+        assert!(memory.get(0x1000..0x2001).is_none());
+    }
+    #[test]
+    #[cfg(not(feature = "allow_exec"))]
+    fn test_exec_rejected() {
+        let mut memory = DynPages::new(0x1000, Protection::ReadWrite).unwrap();
+        assert!(matches!(
+            memory.set_permissions(Protection::ReadExec),
+            Err(ProtectionError::ExecNotAllowed)
+        ));
+        // Protection is left unchanged on error.
+        assert_eq!(memory.protection(), Protection::ReadWrite);
+    }
+}
@@ -0,0 +1,155 @@
+use crate::*;
+use std::mem::ManuallyDrop;
+/// Read-only, executable [`Pages`], which can never be made writable again. Created using [`Pages::seal`].
+///
+/// [`ExecSealed`] has no methods which could restore write access, so code inside it can't change for the rest of the
+/// program, e.g. after a JIT finished compiling everything at startup. Where the kernel supports it (Linux 6.10 and
+/// newer, on 64 bit systems), the mapping is also sealed with `mseal`, so even code bypassing this crate can't make it
+/// writable, remap or unmap it. Since sealed mappings can't be unmapped, memory of such [`ExecSealed`] is leaked when
+/// it is dropped.
+/// # Examples
+/// ```
+/// # use memory_pages::*;
+/// let mut memory: Pages<AllowRead, AllowWrite, DenyExec> = Pages::new(0x1000);
+/// // X86_64 assembly for `mov eax, 7; ret`.
+/// memory.get_mut(..6).unwrap().copy_from_slice(&[0xB8, 7, 0, 0, 0, 0xC3]);
+/// let code = memory.set_protected_exec().seal();
+/// # #[cfg(target_arch = "x86_64")]
+/// # {
+/// let seven: FnRef<unsafe extern "C" fn() -> u32> = unsafe { code.get_fn(0) };
+/// assert_eq!(unsafe { seven.call(()) }, 7);
+/// # }
+/// ```
+/// There is no way to get writable [`Pages`] back out of [`ExecSealed`].
+/// ```compile_fail
+/// # use memory_pages::*;
+/// let memory: Pages<AllowRead, AllowWrite, DenyExec> = Pages::new(0x1000);
+/// let code = memory.set_protected_exec().seal();
+/// let memory = code.allow_write_no_exec();
+/// ```
+pub struct ExecSealed {
+    pages: ManuallyDrop<Pages<AllowRead, DenyWrite, AllowExec>>,
+    kernel_sealed: bool,
+}
+impl<R: ReadPremisionMarker> Pages<R, DenyWrite, AllowExec> {
+    /// Makes this [`Pages`] permanently read-only and executable, turning them into [`ExecSealed`]. The mapping is
+    /// sealed by the kernel where supported, see [`ExecSealed::is_kernel_sealed`].
+    /// # Panics
+    /// Panics if kernel can't/refuses to change protection of the pages.
+    #[must_use]
+    pub fn seal(self) -> ExecSealed {
+        let pages: Pages<AllowRead, DenyWrite, AllowExec> = self.into_prot();
+        // Memory of pages backed by files or shared regions is released by their owners, which sealing would break.
+        let kernel_sealed = matches!(pages.backing, Backing::Anonymous)
+            && mseal(pages.ptr.as_ptr(), pages.len).is_ok();
+        ExecSealed {
+            pages: ManuallyDrop::new(pages),
+            kernel_sealed,
+        }
+    }
+}
+impl ExecSealed {
+    /// Checks if the mapping is sealed by the kernel, so its protection can't be changed even by code outside of this
+    /// crate. If false, code is only protected by the lack of methods restoring write access.
+    #[must_use]
+    pub fn is_kernel_sealed(&self) -> bool {
+        self.kernel_sealed
+    }
+    /// Returns a pointer to data at `offset`. Data behind it may only be read from or executed.
+    /// # Panics
+    /// Panics if offset larger than length of [`ExecSealed`].
+    #[must_use]
+    pub fn get_ptr(&self, offset: usize) -> *const u8 {
+        self.pages.get_ptr(offset)
+    }
+    /// Gets a function pointer at `offset`. Function must be an `extern "C" fn`.
+    /// # Safety
+    /// The bytes at `offset` must represent native instructions creating a function with a matching signature to
+    /// function pointer type F.
+    /// # Panics
+    /// Panics if offset larger than length of [`ExecSealed`].
+    #[must_use]
+    pub unsafe fn get_fn<F>(&self, offset: usize) -> FnRef<'_, F>
+    where
+        F: ExternFnPtr + Copy + std::fmt::Pointer + Sized,
+    {
+        self.pages.get_fn(offset)
+    }
+    /// Marks functions at `offsets` as valid targets of indirect calls, as described in
+    /// [`Pages::set_valid_call_targets`].
+    /// # Errors
+    /// Returns an error if the kernel refuses to mark the targets as valid.
+    /// # Panics
+    /// Panics if an offset is not aligned to 16 bytes, or is past the end of this [`ExecSealed`].
+    pub fn set_valid_call_targets(&self, offsets: &[usize]) -> std::io::Result<()> {
+        self.pages.set_valid_call_targets(offsets)
+    }
+}
+impl Deref for ExecSealed {
+    type Target = [u8];
+    fn deref(&self) -> &Self::Target {
+        &self.pages
+    }
+}
+impl AsRef<[u8]> for ExecSealed {
+    fn as_ref(&self) -> &[u8] {
+        self
+    }
+}
+impl std::fmt::Debug for ExecSealed {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("ExecSealed")
+            .field("ptr", &self.pages.ptr)
+            .field("len", &self.pages.len)
+            .field("kernel_sealed", &self.kernel_sealed)
+            .finish()
+    }
+}
+impl Drop for ExecSealed {
+    fn drop(&mut self) {
+        // Kernel refuses to unmap sealed mappings, so they are leaked.
+        if !self.kernel_sealed {
+            unsafe { ManuallyDrop::drop(&mut self.pages) };
+        }
+    }
+}
+/// Seals the `len` bytes starting at `ptr` with `mseal`, so their protection can never change.
+#[cfg(all(target_os = "linux", target_pointer_width = "64"))]
+fn mseal(ptr: *mut u8, len: usize) -> std::io::Result<()> {
+    use std::ffi::{c_long, c_ulong};
+    extern "C" {
+        fn syscall(number: c_long, ...) -> c_long;
+    }
+    // Number of `mseal` is the same on all architectures.
+    const SYS_MSEAL: c_long = 462;
+    let res = unsafe { syscall(SYS_MSEAL, ptr, len, 0 as c_ulong) };
+    if res != 0 {
+        return Err(std::io::Error::last_os_error());
+    }
+    Ok(())
+}
+#[cfg(not(all(target_os = "linux", target_pointer_width = "64")))]
+fn mseal(_ptr: *mut u8, _len: usize) -> std::io::Result<()> {
+    Err(std::io::Error::new(
+        std::io::ErrorKind::Unsupported,
+        "sealing memory is not supported on this platform",
+    ))
+}
+#[cfg(test)]
+mod test {
+    use super::*;
+    #[test]
+    fn test_exec_sealed() {
+        let mut memory: Pages<AllowRead, AllowWrite, DenyExec> = Pages::new(0x2000);
+        memory[0] = 0xC3;
+        let code = memory.set_protected_exec().seal();
+        assert_eq!(code.len(), 0x2000);
+        assert_eq!(code[0], 0xC3);
+        #[cfg(target_family = "unix")]
+        if code.is_kernel_sealed() {
+            // Sealed mappings can't be made writable even behind the back of this crate.
+            let res = unsafe { mprotect(code.get_ptr(0) as *mut c_void, code.len(), 0x3) };
+            assert_eq!(res, -1);
+        }
+    }
+}
@@ -1,198 +1,74 @@
 pub trait ExternFnPtr {}
-impl<Ret> ExternFnPtr for unsafe extern "C" fn() -> Ret {}
-impl<Arg1, Ret> ExternFnPtr for unsafe extern "C" fn(Arg1) -> Ret {}
-
-impl<Arg1, Arg2, Ret> ExternFnPtr for unsafe extern "C" fn(Arg1, Arg2) -> Ret {}
-
-impl<Arg1, Arg2, Arg3, Ret> ExternFnPtr for unsafe extern "C" fn(Arg1, Arg2, Arg3) -> Ret {}
-impl<Arg1, Arg2, Arg3, Arg4, Ret> ExternFnPtr
-    for unsafe extern "C" fn(Arg1, Arg2, Arg3, Arg4) -> Ret
-{
+/// Invokes `$mac!($abi, (Arg1, ..., ArgN))` for every number of arguments `N` from 0 to 16.
+macro_rules! arity_ladder {
+    ($mac:ident, $abi:literal) => {
+        $mac!($abi, ());
+        $mac!($abi, (Arg1));
+        $mac!($abi, (Arg1, Arg2));
+        $mac!($abi, (Arg1, Arg2, Arg3));
+        $mac!($abi, (Arg1, Arg2, Arg3, Arg4));
+        $mac!($abi, (Arg1, Arg2, Arg3, Arg4, Arg5));
+        $mac!($abi, (Arg1, Arg2, Arg3, Arg4, Arg5, Arg6));
+        $mac!($abi, (Arg1, Arg2, Arg3, Arg4, Arg5, Arg6, Arg7));
+        $mac!($abi, (Arg1, Arg2, Arg3, Arg4, Arg5, Arg6, Arg7, Arg8));
+        $mac!($abi, (Arg1, Arg2, Arg3, Arg4, Arg5, Arg6, Arg7, Arg8, Arg9));
+        $mac!(
+            $abi,
+            (Arg1, Arg2, Arg3, Arg4, Arg5, Arg6, Arg7, Arg8, Arg9, Arg10)
+        );
+        $mac!(
+            $abi,
+            (Arg1, Arg2, Arg3, Arg4, Arg5, Arg6, Arg7, Arg8, Arg9, Arg10, Arg11)
+        );
+        $mac!(
+            $abi,
+            (Arg1, Arg2, Arg3, Arg4, Arg5, Arg6, Arg7, Arg8, Arg9, Arg10, Arg11, Arg12)
+        );
+        $mac!(
+            $abi,
+            (Arg1, Arg2, Arg3, Arg4, Arg5, Arg6, Arg7, Arg8, Arg9, Arg10, Arg11, Arg12, Arg13)
+        );
+        $mac!(
+            $abi,
+            (
+                Arg1, Arg2, Arg3, Arg4, Arg5, Arg6, Arg7, Arg8, Arg9, Arg10, Arg11, Arg12, Arg13,
+                Arg14
+            )
+        );
+        $mac!(
+            $abi,
+            (
+                Arg1, Arg2, Arg3, Arg4, Arg5, Arg6, Arg7, Arg8, Arg9, Arg10, Arg11, Arg12, Arg13,
+                Arg14, Arg15
+            )
+        );
+        $mac!(
+            $abi,
+            (
+                Arg1, Arg2, Arg3, Arg4, Arg5, Arg6, Arg7, Arg8, Arg9, Arg10, Arg11, Arg12, Arg13,
+                Arg14, Arg15, Arg16
+            )
+        );
+    };
 }
-impl<Arg1, Arg2, Arg3, Arg4, Arg5, Ret> ExternFnPtr
-    for unsafe extern "C" fn(Arg1, Arg2, Arg3, Arg4, Arg5) -> Ret
-{
+/// Invokes [`arity_ladder`] for every calling convention supported by the target.
+macro_rules! abi_ladder {
+    ($mac:ident) => {
+        $crate::extern_fn_ptr::arity_ladder!($mac, "C");
+        $crate::extern_fn_ptr::arity_ladder!($mac, "C-unwind");
+        $crate::extern_fn_ptr::arity_ladder!($mac, "system");
+        #[cfg(target_arch = "x86_64")]
+        $crate::extern_fn_ptr::arity_ladder!($mac, "sysv64");
+        #[cfg(target_arch = "x86_64")]
+        $crate::extern_fn_ptr::arity_ladder!($mac, "win64");
+        #[cfg(target_arch = "arm")]
+        $crate::extern_fn_ptr::arity_ladder!($mac, "aapcs");
+    };
 }
-impl<Arg1, Arg2, Arg3, Arg4, Arg5, Arg6, Ret> ExternFnPtr
-    for unsafe extern "C" fn(Arg1, Arg2, Arg3, Arg4, Arg5, Arg6) -> Ret
-{
-}
-impl<Arg1, Arg2, Arg3, Arg4, Arg5, Arg6, Arg7, Ret> ExternFnPtr
-    for unsafe extern "C" fn(Arg1, Arg2, Arg3, Arg4, Arg5, Arg6, Arg7) -> Ret
-{
-}
-impl<Arg1, Arg2, Arg3, Arg4, Arg5, Arg6, Arg7, Arg8, Ret> ExternFnPtr
-    for unsafe extern "C" fn(Arg1, Arg2, Arg3, Arg4, Arg5, Arg6, Arg7, Arg8) -> Ret
-{
-}
-impl<Arg1, Arg2, Arg3, Arg4, Arg5, Arg6, Arg7, Arg8, Arg9, Ret> ExternFnPtr
-    for unsafe extern "C" fn(Arg1, Arg2, Arg3, Arg4, Arg5, Arg6, Arg7, Arg8, Arg9) -> Ret
-{
-}
-impl<Arg1, Arg2, Arg3, Arg4, Arg5, Arg6, Arg7, Arg8, Arg9, Arg10, Ret> ExternFnPtr
-    for unsafe extern "C" fn(Arg1, Arg2, Arg3, Arg4, Arg5, Arg6, Arg7, Arg8, Arg9, Arg10) -> Ret
-{
-}
-impl<Arg1, Arg2, Arg3, Arg4, Arg5, Arg6, Arg7, Arg8, Arg9, Arg10, Arg11, Ret> ExternFnPtr
-    for unsafe extern "C" fn(
-        Arg1,
-        Arg2,
-        Arg3,
-        Arg4,
-        Arg5,
-        Arg6,
-        Arg7,
-        Arg8,
-        Arg9,
-        Arg10,
-        Arg11,
-    ) -> Ret
-{
-}
-impl<Arg1, Arg2, Arg3, Arg4, Arg5, Arg6, Arg7, Arg8, Arg9, Arg10, Arg11, Arg12, Ret> ExternFnPtr
-    for unsafe extern "C" fn(
-        Arg1,
-        Arg2,
-        Arg3,
-        Arg4,
-        Arg5,
-        Arg6,
-        Arg7,
-        Arg8,
-        Arg9,
-        Arg10,
-        Arg11,
-        Arg12,
-    ) -> Ret
-{
-}
-impl<Arg1, Arg2, Arg3, Arg4, Arg5, Arg6, Arg7, Arg8, Arg9, Arg10, Arg11, Arg12, Arg13, Ret>
-    ExternFnPtr
-    for unsafe extern "C" fn(
-        Arg1,
-        Arg2,
-        Arg3,
-        Arg4,
-        Arg5,
-        Arg6,
-        Arg7,
-        Arg8,
-        Arg9,
-        Arg10,
-        Arg11,
-        Arg12,
-        Arg13,
-    ) -> Ret
-{
-}
-impl<
-        Arg1,
-        Arg2,
-        Arg3,
-        Arg4,
-        Arg5,
-        Arg6,
-        Arg7,
-        Arg8,
-        Arg9,
-        Arg10,
-        Arg11,
-        Arg12,
-        Arg13,
-        Arg14,
-        Ret,
-    > ExternFnPtr
-    for unsafe extern "C" fn(
-        Arg1,
-        Arg2,
-        Arg3,
-        Arg4,
-        Arg5,
-        Arg6,
-        Arg7,
-        Arg8,
-        Arg9,
-        Arg10,
-        Arg11,
-        Arg12,
-        Arg13,
-        Arg14,
-    ) -> Ret
-{
-}
-impl<
-        Arg1,
-        Arg2,
-        Arg3,
-        Arg4,
-        Arg5,
-        Arg6,
-        Arg7,
-        Arg8,
-        Arg9,
-        Arg10,
-        Arg11,
-        Arg12,
-        Arg13,
-        Arg14,
-        Arg15,
-        Ret,
-    > ExternFnPtr
-    for unsafe extern "C" fn(
-        Arg1,
-        Arg2,
-        Arg3,
-        Arg4,
-        Arg5,
-        Arg6,
-        Arg7,
-        Arg8,
-        Arg9,
-        Arg10,
-        Arg11,
-        Arg12,
-        Arg13,
-        Arg14,
-        Arg15,
-    ) -> Ret
-{
-}
-impl<
-        Arg1,
-        Arg2,
-        Arg3,
-        Arg4,
-        Arg5,
-        Arg6,
-        Arg7,
-        Arg8,
-        Arg9,
-        Arg10,
-        Arg11,
-        Arg12,
-        Arg13,
-        Arg14,
-        Arg15,
-        Arg16,
-        Ret,
-    > ExternFnPtr
-    for unsafe extern "C" fn(
-        Arg1,
-        Arg2,
-        Arg3,
-        Arg4,
-        Arg5,
-        Arg6,
-        Arg7,
-        Arg8,
-        Arg9,
-        Arg10,
-        Arg11,
-        Arg12,
-        Arg13,
-        Arg14,
-        Arg15,
-        Arg16,
-    ) -> Ret
-{
+pub(crate) use {abi_ladder, arity_ladder};
+macro_rules! extern_fn_ptr {
+    ($abi:literal, ($($arg:ident),*)) => {
+        impl<$($arg,)* Ret> ExternFnPtr for unsafe extern $abi fn($($arg),*) -> Ret {}
+    };
 }
+abi_ladder!(extern_fn_ptr);
@@ -0,0 +1,579 @@
+//! Catching access faults(SIGSEGV/SIGBUS on unix, access violations on Windows) inside [`Pages`], instead of crashing.
+use crate::dyn_pages::protect_range;
+use crate::page_math::{align_down, PAGE_SIZE};
+use crate::*;
+use std::sync::atomic::{AtomicU8, AtomicUsize, Ordering};
+/// Kind of access which caused a [`Fault`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FaultAccess {
+    /// Data was read.
+    Read,
+    /// Data was written.
+    Write,
+    /// Instructions were fetched.
+    Exec,
+    /// A breakpoint inserted with [`Pages::insert_breakpoint`] was hit. Returning [`FaultAction::Retry`] removes the
+    /// breakpoint, and runs the instruction it replaced.
+    Breakpoint,
+    /// Kind of access is not reported by this platform. Accesses are only told apart on Windows, and on Linux on x86_64
+    /// and AArch64.
+    Unknown,
+}
+/// Description of an access fault inside trapped [`Pages`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Fault {
+    /// Address which was accessed.
+    pub address: *const u8,
+    /// Offset of the accessed address from the beginning of trapped [`Pages`].
+    pub offset: usize,
+    /// Kind of access which caused the fault.
+    pub access: FaultAccess,
+    region: *mut u8,
+    region_len: usize,
+    /// Value passed when the trap was registered, used by handlers internal to this crate.
+    pub(crate) context: usize,
+}
+impl Fault {
+    /// Changes protection of the page containing [`Self::address`]. Handlers use this to materialize pages lazily,
+    /// before retrying the access with [`FaultAction::Retry`].
+    /// # Errors
+    /// Returns an error if `protection` may not be set, or if kernel refuses to change it.
+    pub fn set_page_protection(&self, protection: Protection) -> Result<(), ProtectionError> {
+        let page = align_down(self.offset);
+        debug_assert!(page < self.region_len);
+        protect_range(unsafe { self.region.add(page) }, PAGE_SIZE, protection)
+    }
+}
+/// What should happen after a fault handler returns.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FaultAction {
+    /// The faulting access is executed again. Handler must make it succeed, e.g. using [`Fault::set_page_protection`],
+    /// or it will fault again.
+    Retry,
+    /// The fault is passed to the handler which was installed before, which usually terminates the process.
+    Crash,
+}
+/// Handles faults inside trapped [`Pages`]. It runs inside a signal handler, so it may only do async-signal-safe work:
+/// no allocation, locking or I/O.
+pub type FaultHandler = fn(&Fault) -> FaultAction;
+/// Error returned when [`Pages`] can't be trapped.
+#[derive(Debug)]
+pub enum TrapError {
+    /// Maximal number of simultaneously trapped [`Pages`] has been reached.
+    TooManyTraps,
+    /// Kernel refused to install the fault handler.
+    Os(std::io::Error),
+}
+impl std::fmt::Display for TrapError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::TooManyTraps => write!(f, "too many pages are trapped at once"),
+            Self::Os(err) => write!(f, "failed to install fault handler: {err}"),
+        }
+    }
+}
+impl std::error::Error for TrapError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Self::Os(err) => Some(err),
+            Self::TooManyTraps => None,
+        }
+    }
+}
+/// Maximal number of simultaneously trapped [`Pages`].
+const MAX_TRAPS: usize = 64;
+const SLOT_FREE: u8 = 0;
+const SLOT_BUSY: u8 = 1;
+const SLOT_ACTIVE: u8 = 2;
+/// Registered trap. The fault handler only ever reads slots, so it never needs to lock.
+struct Slot {
+    state: AtomicU8,
+    /// Number of fault handlers reading this slot. It is only freed once there are none.
+    readers: AtomicUsize,
+    start: AtomicUsize,
+    len: AtomicUsize,
+    handler: AtomicUsize,
+    context: AtomicUsize,
+    faults: AtomicUsize,
+    last_address: AtomicUsize,
+}
+#[allow(clippy::declare_interior_mutable_const)]
+const EMPTY_SLOT: Slot = Slot {
+    state: AtomicU8::new(SLOT_FREE),
+    readers: AtomicUsize::new(0),
+    start: AtomicUsize::new(0),
+    len: AtomicUsize::new(0),
+    handler: AtomicUsize::new(0),
+    context: AtomicUsize::new(0),
+    faults: AtomicUsize::new(0),
+    last_address: AtomicUsize::new(0),
+};
+static SLOTS: [Slot; MAX_TRAPS] = [EMPTY_SLOT; MAX_TRAPS];
+/// Keeps faults inside [`Pages`] trapped, until dropped. Created using [`Pages::trap_faults`].
+pub struct FaultTrap<'a> {
+    slot: &'static Slot,
+    pages: PhantomData<&'a ()>,
+}
+impl<R: ReadPremisionMarker, W: WritePremisionMarker, E: ExecPremisionMarker> Pages<R, W, E> {
+    /// Traps access faults inside this [`Pages`]: instead of crashing the process, they are reported to `handler`,
+    /// which decides if the access should be retried. This allows detecting guard page hits, or materializing pages
+    /// lazily, on first access.
+    /// # Errors
+    /// Returns an error if too many [`Pages`] are already trapped, or if the fault handler can't be installed.
+    /// # Examples
+    /// Materializing pages on first access.
+    /// ```
+    /// # use memory_pages::*;
+    /// fn materialize(fault:&Fault) -> FaultAction{
+    ///     match fault.set_page_protection(Protection::ReadWrite){
+    ///         Ok(()) => FaultAction::Retry,
+    ///         Err(_) => FaultAction::Crash,
+    ///     }
+    /// }
+    /// let memory = Pages::new_guard(0x4000);
+    /// let trap = memory.trap_faults(materialize).unwrap();
+    /// let ptr = memory.get_ptr_unchecked();
+    /// // Page is made accessible by `materialize`, instead of the write crashing the process.
+    /// unsafe{ptr.add(0x2000).write(7)};
+    /// assert_eq!(unsafe{ptr.add(0x2000).read()}, 7);
+    /// assert_eq!(trap.fault_count(), 1);
+    /// ```
+    pub fn trap_faults(&self, handler: FaultHandler) -> Result<FaultTrap<'_>, TrapError> {
+        trap_range(self.ptr.as_ptr(), self.len, handler, 0)
+    }
+}
+/// Traps faults inside `len` bytes starting at `ptr`, passing `context` to `handler` with each of them. Caller must ensure
+/// the range stays mapped for as long as the returned trap exists.
+pub(crate) fn trap_range(
+    ptr: *mut u8,
+    len: usize,
+    handler: FaultHandler,
+    context: usize,
+) -> Result<FaultTrap<'static>, TrapError> {
+    install_handler()?;
+    let slot = SLOTS
+        .iter()
+        .find(|slot| {
+            slot.state
+                .compare_exchange(SLOT_FREE, SLOT_BUSY, Ordering::Acquire, Ordering::Relaxed)
+                .is_ok()
+        })
+        .ok_or(TrapError::TooManyTraps)?;
+    slot.start.store(ptr as usize, Ordering::Relaxed);
+    slot.len.store(len, Ordering::Relaxed);
+    slot.handler.store(handler as usize, Ordering::Relaxed);
+    slot.context.store(context, Ordering::Relaxed);
+    slot.faults.store(0, Ordering::Relaxed);
+    slot.last_address.store(0, Ordering::Relaxed);
+    slot.state.store(SLOT_ACTIVE, Ordering::Release);
+    Ok(FaultTrap {
+        slot,
+        pages: PhantomData,
+    })
+}
+impl FaultTrap<'_> {
+    /// Returns the number of faults reported to the handler.
+    #[must_use]
+    pub fn fault_count(&self) -> usize {
+        self.slot.faults.load(Ordering::Acquire)
+    }
+    /// Returns the address which caused the most recent fault, if any faults occurred.
+    #[must_use]
+    pub fn last_fault_address(&self) -> Option<*const u8> {
+        match self.slot.last_address.load(Ordering::Acquire) {
+            0 => None,
+            address => Some(address as *const u8),
+        }
+    }
+}
+impl Drop for FaultTrap<'_> {
+    fn drop(&mut self) {
+        // Handlers which found the slot before it stopped being active may still be reading it, or running the handler
+        // on the trapped pages, so the slot can only be reused once they are done.
+        self.slot.state.store(SLOT_BUSY, Ordering::SeqCst);
+        while self.slot.readers.load(Ordering::SeqCst) != 0 {
+            std::thread::yield_now();
+        }
+        self.slot.state.store(SLOT_FREE, Ordering::Release);
+    }
+}
+/// Looks for a trap containing `address` and runs its handler. Returns [`None`] if `address` is not trapped.
+pub(crate) fn dispatch(address: usize, access: FaultAccess) -> Option<FaultAction> {
+    let slot = SLOTS.iter().find(|slot| {
+        if slot.state.load(Ordering::Acquire) != SLOT_ACTIVE {
+            return false;
+        }
+        // The slot is only read after it is marked as read, and checked to still be active, so it can't be freed in
+        // between.
+        slot.readers.fetch_add(1, Ordering::SeqCst);
+        let found = slot.state.load(Ordering::SeqCst) == SLOT_ACTIVE && {
+            let start = slot.start.load(Ordering::Relaxed);
+            (start..start + slot.len.load(Ordering::Relaxed)).contains(&address)
+        };
+        if !found {
+            slot.readers.fetch_sub(1, Ordering::Release);
+        }
+        found
+    })?;
+    let start = slot.start.load(Ordering::Relaxed);
+    let fault = Fault {
+        address: address as *const u8,
+        offset: address - start,
+        access,
+        region: start as *mut u8,
+        region_len: slot.len.load(Ordering::Relaxed),
+        context: slot.context.load(Ordering::Relaxed),
+    };
+    slot.faults.fetch_add(1, Ordering::AcqRel);
+    slot.last_address.store(address, Ordering::Release);
+    let handler: FaultHandler =
+        unsafe { std::mem::transmute(slot.handler.load(Ordering::Relaxed)) };
+    let action = handler(&fault);
+    slot.readers.fetch_sub(1, Ordering::Release);
+    Some(action)
+}
+#[cfg(target_family = "unix")]
+mod native {
+    use super::*;
+    use std::sync::OnceLock;
+    #[cfg(target_os = "linux")]
+    #[repr(C)]
+    struct SigAction {
+        sa_sigaction: usize,
+        sa_mask: [u64; 16],
+        sa_flags: c_int,
+        sa_restorer: usize,
+    }
+    #[cfg(target_os = "macos")]
+    #[repr(C)]
+    struct SigAction {
+        sa_sigaction: usize,
+        sa_mask: u32,
+        sa_flags: c_int,
+    }
+    #[cfg(target_os = "linux")]
+    #[repr(C)]
+    struct SigInfo {
+        si_signo: c_int,
+        si_errno: c_int,
+        si_code: c_int,
+        #[cfg(target_pointer_width = "64")]
+        _pad: c_int,
+        si_addr: *mut c_void,
+    }
+    #[cfg(target_os = "macos")]
+    #[repr(C)]
+    struct SigInfo {
+        si_signo: c_int,
+        si_errno: c_int,
+        si_code: c_int,
+        si_pid: c_int,
+        si_uid: u32,
+        si_status: c_int,
+        si_addr: *mut c_void,
+    }
+    extern "C" {
+        fn sigaction(signum: c_int, act: *const SigAction, oldact: *mut SigAction) -> c_int;
+        fn raise(signum: c_int) -> c_int;
+    }
+    const SIGSEGV: c_int = 11;
+    const SIGTRAP: c_int = 5;
+    #[cfg(target_os = "linux")]
+    const SIGBUS: c_int = 7;
+    #[cfg(target_os = "macos")]
+    const SIGBUS: c_int = 10;
+    #[cfg(target_os = "linux")]
+    const SA_SIGINFO: c_int = 0x4;
+    #[cfg(target_os = "macos")]
+    const SA_SIGINFO: c_int = 0x40;
+    #[cfg(target_os = "linux")]
+    const SA_ONSTACK: c_int = 0x0800_0000;
+    #[cfg(target_os = "macos")]
+    const SA_ONSTACK: c_int = 0x1;
+    const SIG_DFL: usize = 0;
+    const SIG_IGN: usize = 1;
+    /// Handlers which were installed before ours, to which unrelated faults are passed.
+    struct PrevActions {
+        segv: SigAction,
+        bus: SigAction,
+        trap: SigAction,
+    }
+    static PREV: OnceLock<Result<PrevActions, i32>> = OnceLock::new();
+    pub(super) fn install_handler() -> Result<(), TrapError> {
+        match PREV.get_or_init(|| unsafe {
+            let mut prev: PrevActions = std::mem::zeroed();
+            let mut action: SigAction = std::mem::zeroed();
+            action.sa_sigaction = on_fault as *const () as usize;
+            action.sa_flags = SA_SIGINFO | SA_ONSTACK;
+            if sigaction(SIGSEGV, &action, &mut prev.segv) == -1
+                || sigaction(SIGBUS, &action, &mut prev.bus) == -1
+                || sigaction(SIGTRAP, &action, &mut prev.trap) == -1
+            {
+                return Err(erno());
+            }
+            Ok(prev)
+        }) {
+            Ok(_) => Ok(()),
+            Err(code) => Err(TrapError::Os(std::io::Error::from_raw_os_error(*code))),
+        }
+    }
+    /// Handles a hit of a breakpoint inserted with [`Pages::insert_breakpoint`]. Returns true if execution should resume.
+    #[cfg(all(
+        target_os = "linux",
+        any(feature = "allow_exec", doc, test),
+        any(target_arch = "x86_64", target_arch = "aarch64")
+    ))]
+    unsafe fn on_breakpoint(info: *mut SigInfo, context: *mut c_void) -> bool {
+        #[cfg(target_arch = "x86_64")]
+        {
+            let _ = info;
+            /// Offset of `uc_mcontext.gregs[REG_RIP]` inside `ucontext_t`.
+            const UC_RIP: usize = 168;
+            // `INT3` reports no address, and leaves the instruction pointer right past itself.
+            let rip = context.cast::<u8>().add(UC_RIP).cast::<usize>();
+            let address = rip.read() - 1;
+            if breakpoint::on_breakpoint(address) != Some(FaultAction::Retry) {
+                return false;
+            }
+            rip.write(address);
+            true
+        }
+        #[cfg(target_arch = "aarch64")]
+        {
+            let _ = context;
+            // `BRK` reports its own address, and leaves the instruction pointer at itself.
+            breakpoint::on_breakpoint((*info).si_addr as usize) == Some(FaultAction::Retry)
+        }
+    }
+    /// Handles a hit of a breakpoint inserted with [`Pages::insert_breakpoint`]. Returns true if execution should resume.
+    #[cfg(not(all(
+        target_os = "linux",
+        any(feature = "allow_exec", doc, test),
+        any(target_arch = "x86_64", target_arch = "aarch64")
+    )))]
+    unsafe fn on_breakpoint(_info: *mut SigInfo, _context: *mut c_void) -> bool {
+        false
+    }
+    /// Decodes the kind of access which caused a fault from the page fault error code saved in `context`.
+    #[cfg(all(target_os = "linux", target_arch = "x86_64"))]
+    unsafe fn fault_access(context: *mut c_void) -> FaultAccess {
+        /// Offset of `uc_mcontext.gregs[REG_ERR]` inside `ucontext_t`.
+        const UC_ERR: usize = 192;
+        const ERR_WRITE: usize = 0x2;
+        const ERR_INSTRUCTION_FETCH: usize = 0x10;
+        let err = context.cast::<u8>().add(UC_ERR).cast::<usize>().read();
+        if err & ERR_INSTRUCTION_FETCH != 0 {
+            FaultAccess::Exec
+        } else if err & ERR_WRITE != 0 {
+            FaultAccess::Write
+        } else {
+            FaultAccess::Read
+        }
+    }
+    /// Decodes the kind of access which caused a fault from the exception syndrome register, which the kernel saves in
+    /// one of the records following the registers in `context`.
+    #[cfg(all(target_os = "linux", target_arch = "aarch64"))]
+    unsafe fn fault_access(context: *mut c_void) -> FaultAccess {
+        /// Offset of `uc_mcontext.__reserved` inside `ucontext_t`.
+        const UC_RESERVED: usize = 464;
+        const RESERVED_LEN: usize = 4096;
+        const ESR_MAGIC: u32 = 0x4553_5201;
+        const EC_INSTRUCTION_ABORT: [u64; 2] = [0x20, 0x21];
+        const EC_DATA_ABORT: [u64; 2] = [0x24, 0x25];
+        /// Set in data aborts caused by writes.
+        const ESR_WNR: u64 = 1 << 6;
+        let mut offset = 0;
+        while offset + 16 <= RESERVED_LEN {
+            let record = context.cast::<u8>().add(UC_RESERVED + offset);
+            let magic = record.cast::<u32>().read();
+            let size = record.add(4).cast::<u32>().read() as usize;
+            if magic == 0 || size == 0 {
+                break;
+            }
+            if magic == ESR_MAGIC {
+                let esr = record.add(8).cast::<u64>().read();
+                let class = esr >> 26;
+                return if EC_INSTRUCTION_ABORT.contains(&class) {
+                    FaultAccess::Exec
+                } else if !EC_DATA_ABORT.contains(&class) {
+                    FaultAccess::Unknown
+                } else if esr & ESR_WNR != 0 {
+                    FaultAccess::Write
+                } else {
+                    FaultAccess::Read
+                };
+            }
+            offset += size;
+        }
+        FaultAccess::Unknown
+    }
+    #[cfg(not(all(
+        target_os = "linux",
+        any(target_arch = "x86_64", target_arch = "aarch64")
+    )))]
+    unsafe fn fault_access(_context: *mut c_void) -> FaultAccess {
+        FaultAccess::Unknown
+    }
+    unsafe extern "C" fn on_fault(signum: c_int, info: *mut SigInfo, context: *mut c_void) {
+        if signum == SIGTRAP {
+            if on_breakpoint(info, context) {
+                return;
+            }
+        } else if dispatch((*info).si_addr as usize, fault_access(context))
+            == Some(FaultAction::Retry)
+        {
+            return;
+        }
+        let Some(Ok(prev)) = PREV.get() else {
+            return;
+        };
+        let prev = match signum {
+            SIGBUS => &prev.bus,
+            SIGTRAP => &prev.trap,
+            _ => &prev.segv,
+        };
+        match prev.sa_sigaction {
+            // Restoring the default action and returning re-executes the faulting access, which is then handled by it.
+            SIG_DFL | SIG_IGN => {
+                let mut default: SigAction = std::mem::zeroed();
+                default.sa_sigaction = SIG_DFL;
+                sigaction(signum, &default, std::ptr::null_mut());
+                // Breakpoints may be past the instruction pointer, and never run again, so the signal is raised instead.
+                // It stays blocked until this handler returns.
+                if signum == SIGTRAP {
+                    raise(signum);
+                }
+            }
+            handler if prev.sa_flags & SA_SIGINFO != 0 => {
+                let handler: unsafe extern "C" fn(c_int, *mut SigInfo, *mut c_void) =
+                    std::mem::transmute(handler);
+                handler(signum, info, context);
+            }
+            handler => {
+                let handler: unsafe extern "C" fn(c_int) = std::mem::transmute(handler);
+                handler(signum);
+            }
+        }
+    }
+}
+#[cfg(target_family = "windows")]
+mod native {
+    use super::*;
+    use std::sync::OnceLock;
+    use winapi::um::winnt::EXCEPTION_POINTERS;
+    const EXCEPTION_ACCESS_VIOLATION: u32 = 0xC000_0005;
+    #[cfg(all(any(feature = "allow_exec", doc, test), target_arch = "x86_64"))]
+    const EXCEPTION_BREAKPOINT: u32 = 0x8000_0003;
+    const EXCEPTION_CONTINUE_EXECUTION: i32 = -1;
+    const EXCEPTION_CONTINUE_SEARCH: i32 = 0;
+    static HANDLER: OnceLock<Result<usize, u32>> = OnceLock::new();
+    pub(super) fn install_handler() -> Result<(), TrapError> {
+        match HANDLER.get_or_init(|| {
+            let handle = unsafe {
+                winapi::um::errhandlingapi::AddVectoredExceptionHandler(1, Some(on_fault))
+            };
+            if handle.is_null() {
+                Err(unsafe { winapi::um::errhandlingapi::GetLastError() })
+            } else {
+                Ok(handle as usize)
+            }
+        }) {
+            Ok(_) => Ok(()),
+            Err(code) => Err(TrapError::Os(std::io::Error::from_raw_os_error(
+                *code as i32,
+            ))),
+        }
+    }
+    unsafe extern "system" fn on_fault(info: *mut EXCEPTION_POINTERS) -> i32 {
+        let record = &*(*info).ExceptionRecord;
+        #[cfg(all(any(feature = "allow_exec", doc, test), target_arch = "x86_64"))]
+        if record.ExceptionCode == EXCEPTION_BREAKPOINT {
+            let address = record.ExceptionAddress as usize;
+            if breakpoint::on_breakpoint(address) != Some(FaultAction::Retry) {
+                return EXCEPTION_CONTINUE_SEARCH;
+            }
+            // Run the instruction which replaced the breakpoint.
+            (*(*info).ContextRecord).Rip = address as u64;
+            return EXCEPTION_CONTINUE_EXECUTION;
+        }
+        if record.ExceptionCode != EXCEPTION_ACCESS_VIOLATION || record.NumberParameters < 2 {
+            return EXCEPTION_CONTINUE_SEARCH;
+        }
+        let access = match record.ExceptionInformation[0] {
+            0 => FaultAccess::Read,
+            1 => FaultAccess::Write,
+            8 => FaultAccess::Exec,
+            _ => FaultAccess::Unknown,
+        };
+        match dispatch(record.ExceptionInformation[1], access) {
+            Some(FaultAction::Retry) => EXCEPTION_CONTINUE_EXECUTION,
+            _ => EXCEPTION_CONTINUE_SEARCH,
+        }
+    }
+}
+use native::install_handler;
+#[cfg(test)]
+mod test {
+    use super::*;
+    fn make_readable(fault: &Fault) -> FaultAction {
+        match fault.set_page_protection(Protection::Read) {
+            Ok(()) => FaultAction::Retry,
+            Err(_) => FaultAction::Crash,
+        }
+    }
+    #[test]
+    fn test_trap_lazy_pages() {
+        let memory = Pages::new_guard(0x4000);
+        let trap = memory.trap_faults(make_readable).unwrap();
+        let ptr = memory.get_ptr_unchecked();
+        assert_eq!(trap.last_fault_address(), None);
+        for page in 0..4 {
+            assert_eq!(unsafe { ptr.add(page * 0x1000 + 8).read_volatile() }, 0);
+        }
+        // Each page faults only once, since it stays readable afterwards.
+        assert_eq!(unsafe { ptr.add(0x3000).read_volatile() }, 0);
+        assert_eq!(trap.fault_count(), 4);
+        assert_eq!(
+            trap.last_fault_address(),
+            Some(unsafe { ptr.add(0x3008) }.cast_const())
+        );
+    }
+    #[cfg(any(
+        target_family = "windows",
+        all(
+            target_os = "linux",
+            any(target_arch = "x86_64", target_arch = "aarch64")
+        )
+    ))]
+    #[test]
+    fn test_trap_access_kind() {
+        use std::sync::Mutex;
+        /// Accesses reported to `record_access`, which can't capture anything.
+        static ACCESSES: Mutex<Vec<FaultAccess>> = Mutex::new(Vec::new());
+        fn record_access(fault: &Fault) -> FaultAction {
+            // Faults are only raised by the thread running the test, never while it holds the lock.
+            ACCESSES.lock().unwrap().push(fault.access);
+            match fault.set_page_protection(Protection::ReadWrite) {
+                Ok(()) => FaultAction::Retry,
+                Err(_) => FaultAction::Crash,
+            }
+        }
+        let memory = Pages::new_guard(0x2000);
+        let trap = memory.trap_faults(record_access).unwrap();
+        let ptr = memory.get_ptr_unchecked();
+        assert_eq!(unsafe { ptr.read_volatile() }, 0);
+        unsafe { ptr.add(0x1000).write_volatile(1) };
+        drop(trap);
+        assert_eq!(
+            *ACCESSES.lock().unwrap(),
+            [FaultAccess::Read, FaultAccess::Write]
+        );
+    }
+    #[test]
+    fn test_trap_slots_released() {
+        let memory = Pages::new_guard(0x1000);
+        for _ in 0..(MAX_TRAPS * 2) {
+            let _trap = memory.trap_faults(make_readable).unwrap();
+        }
+    }
+}
@@ -0,0 +1,346 @@
+use crate::*;
+use std::fs::File;
+use std::io;
+/// Decides if flushing file-backed [`Pages`] waits for the data to be written into the file.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FlushMode {
+    /// Waits until all modified data is written into the file. Use it to mark durability boundaries.
+    Sync,
+    /// Schedules modified data to be written into the file, and returns without waiting for it.
+    Async,
+}
+pub(crate) struct FileBacking {
+    pub(crate) file: File,
+    pub(crate) flush_on_drop: bool,
+    #[cfg(target_family = "windows")]
+    pub(crate) mapping: winapi::um::winnt::HANDLE,
+}
+#[cfg(target_os = "linux")]
+const MS_SYNC: c_int = 0x4;
+#[cfg(any(target_os = "macos", target_os = "ios"))]
+const MS_SYNC: c_int = 0x10;
+#[cfg(all(
+    target_family = "unix",
+    not(any(target_os = "linux", target_os = "macos", target_os = "ios"))
+))]
+const MS_SYNC: c_int = 0x0;
+#[cfg(target_family = "unix")]
+const MS_ASYNC: c_int = 0x1;
+impl<R: ReadPremisionMarker, W: WritePremisionMarker, E: ExecPremisionMarker> Pages<R, W, E> {
+    /// Maps first `length` bytes of `file` into memory, rounding up to the next page boundary. Changes made to returned
+    /// [`Pages`] are written back into the `file`. If the `file` is shorter than `length`, it is extended to `length`.
+    /// Bytes past `length` in the last page are accessible, but never written back into the `file`.
+    ///
+    /// Permissions of [`Pages`] must be allowed by the way `file` was opened: mapping a file opened as read-only into
+    /// [`Pages`] with [`AllowWrite`] will fail.
+    /// # Errors
+    /// Returns an error if `length` is 0, if the `file` could not be extended, or if the kernel refuses to map it.
+    /// # Examples
+    /// ```
+    /// # use memory_pages::*;
+    /// # let path = std::env::temp_dir().join("memory_pages_doc_map_file");
+    /// let file = std::fs::OpenOptions::new().read(true).write(true).create(true).truncate(true).open(&path).unwrap();
+    /// let mut memory:Pages<AllowRead,AllowWrite,DenyExec> = Pages::map_file(&file, 0x1000).unwrap();
+    /// memory[0] = 0xFF;
+    /// // Ensure changes made to memory are written back into the file.
+    /// memory.flush(FlushMode::Sync).unwrap();
+    /// # drop(memory);
+    /// assert_eq!(std::fs::read(&path).unwrap()[0], 0xFF);
+    /// # std::fs::remove_file(&path).unwrap();
+    /// ```
+    pub fn map_file(file: &File, length: usize) -> io::Result<Self> {
+        if length == 0 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "0 - sized file mappings are not allowed!",
+            ));
+        }
+        if file.metadata()?.len() < length as u64 {
+            file.set_len(length as u64)?;
+        }
+        let len = align_up(length);
+        let file = file.try_clone()?;
+        Self::map_file_native(file, length, len)
+    }
+    #[cfg(target_family = "unix")]
+    fn map_file_native(file: File, _length: usize, len: usize) -> io::Result<Self> {
+        use std::os::unix::io::AsRawFd;
+        let ptr = unsafe {
+            mmap(
+                std::ptr::null_mut(),
+                len,
+                Self::bitmask(),
+                MAP_SHARED,
+                file.as_raw_fd(),
+                0,
+            )
+        };
+        if ptr == MAP_FAILED {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(Self {
+            ptr: non_null(ptr),
+            len,
+            backing: Backing::File(Box::new(FileBacking {
+                file,
+                flush_on_drop: false,
+            })),
+            read: PhantomData,
+            write: PhantomData,
+            exec: PhantomData,
+        })
+    }
+    #[cfg(target_family = "windows")]
+    fn map_file_native(file: File, length: usize, len: usize) -> io::Result<Self> {
+        use std::os::windows::io::AsRawHandle;
+        use winapi::um::handleapi::CloseHandle;
+        use winapi::um::winnt::HANDLE;
+        // The mapping object must allow every permission the view may be later changed to.
+        let (max_prot, access) = match (W::allow_write(), E::allow_exec()) {
+            (false, false) => (PAGE_READONLY, FILE_MAP_READ),
+            (true, false) => (PAGE_READWRITE, FILE_MAP_READ | FILE_MAP_WRITE),
+            (false, true) => (PAGE_EXECUTE_READ, FILE_MAP_READ | FILE_MAP_EXECUTE),
+            (true, true) => (
+                PAGE_EXECUTE_READWRITE,
+                FILE_MAP_READ | FILE_MAP_WRITE | FILE_MAP_EXECUTE,
+            ),
+        };
+        let mapping = unsafe {
+            CreateFileMappingW(
+                file.as_raw_handle() as HANDLE,
+                std::ptr::null_mut(),
+                max_prot,
+                0,
+                0,
+                std::ptr::null(),
+            )
+        };
+        if mapping.is_null() {
+            return Err(io::Error::last_os_error());
+        }
+        let ptr = unsafe { MapViewOfFile(mapping, access, 0, 0, length) };
+        let Some(ptr) = NonNull::new(ptr.cast::<u8>()) else {
+            let err = io::Error::last_os_error();
+            unsafe { CloseHandle(mapping) };
+            return Err(err);
+        };
+        let res = Self {
+            ptr,
+            len,
+            backing: Backing::File(Box::new(FileBacking {
+                file,
+                flush_on_drop: false,
+                mapping,
+            })),
+            read: PhantomData,
+            write: PhantomData,
+            exec: PhantomData,
+        };
+        if !R::allow_read() {
+            res.set_prot();
+        }
+        Ok(res)
+    }
+    /// Checks if this [`Pages`] were created by mapping a file.
+    /// # Examples
+    /// ```
+    /// # use memory_pages::*;
+    /// let memory:Pages<AllowRead,AllowWrite,DenyExec> = Pages::new(0x1000);
+    /// assert!(!memory.is_file_backed());
+    /// ```
+    #[must_use]
+    pub fn is_file_backed(&self) -> bool {
+        matches!(self.backing, Backing::File(_))
+    }
+    /// Decides if file-backed [`Pages`] should be synchronously flushed when they are dropped. Off by default, because
+    /// the kernel writes the modified data back into the file on its own, just at a time of its choosing. Since errors can't
+    /// be reported from `drop`, call [`Self::flush`] explicitly if you need to handle them. Does nothing for [`Pages`] not
+    /// backed by a file.
+    pub fn set_flush_on_drop(&mut self, flush_on_drop: bool) {
+        if let Backing::File(file) = &mut self.backing {
+            file.flush_on_drop = flush_on_drop;
+        }
+    }
+    /// Writes all data modified inside file-backed [`Pages`] back into the file. See [`FlushMode`] for the differences
+    /// between synchronous and asynchronous flushes. Does nothing for [`Pages`] not backed by a file.
+    /// # Errors
+    /// Returns an error if the kernel fails to write the data back.
+    pub fn flush(&self, mode: FlushMode) -> io::Result<()> {
+        self.flush_range(0, self.len, mode)
+    }
+    /// Writes data modified inside file-backed [`Pages`], in the region starting at page `beginning` is in, and continuing
+    /// till `beginning + length`, back into the file. Does nothing for [`Pages`] not backed by a file.
+    /// # Errors
+    /// Returns an error if the kernel fails to write the data back.
+    /// # Examples
+    /// ```
+    /// # use memory_pages::*;
+    /// # let path = std::env::temp_dir().join("memory_pages_doc_flush_range");
+    /// let file = std::fs::OpenOptions::new().read(true).write(true).create(true).truncate(true).open(&path).unwrap();
+    /// let mut memory:Pages<AllowRead,AllowWrite,DenyExec> = Pages::map_file(&file, 0x4000).unwrap();
+    /// memory[0x2010] = 0xFF;
+    /// // Only the page containing the modified byte needs to be written back.
+    /// memory.flush_range(0x2010, 1, FlushMode::Async).unwrap();
+    /// # std::fs::remove_file(&path).unwrap();
+    /// ```
+    pub fn flush_range(&self, beginning: usize, length: usize, mode: FlushMode) -> io::Result<()> {
+        let Backing::File(_file) = &self.backing else {
+            return Ok(());
+        };
+        let start = page_math::align_down(beginning.min(self.len));
+        let end = beginning.saturating_add(length).min(self.len);
+        if end <= start {
+            return Ok(());
+        }
+        #[cfg(target_family = "unix")]
+        {
+            let flags = match mode {
+                FlushMode::Sync => MS_SYNC,
+                FlushMode::Async => MS_ASYNC,
+            };
+            let res = unsafe {
+                msync(
+                    self.ptr.as_ptr().add(start).cast::<c_void>(),
+                    end - start,
+                    flags,
+                )
+            };
+            if res == -1 {
+                return Err(io::Error::last_os_error());
+            }
+        }
+        #[cfg(target_family = "windows")]
+        {
+            use std::os::windows::io::AsRawHandle;
+            let res = unsafe {
+                FlushViewOfFile(
+                    self.ptr
+                        .as_ptr()
+                        .add(start)
+                        .cast::<winapi::ctypes::c_void>(),
+                    end - start,
+                )
+            };
+            if res == 0 {
+                return Err(io::Error::last_os_error());
+            }
+            if mode == FlushMode::Sync {
+                let handle = _file.file.as_raw_handle() as winapi::um::winnt::HANDLE;
+                if unsafe { winapi::um::fileapi::FlushFileBuffers(handle) } == 0 {
+                    return Err(io::Error::last_os_error());
+                }
+            }
+        }
+        Ok(())
+    }
+    /// Remaps file-backed [`Pages`] with `new_size`, extending or truncating the file to match. Windows refuses to
+    /// truncate files which are mapped, so there files are only ever extended.
+    pub(crate) fn resize_file(&mut self, new_size: usize) -> io::Result<()> {
+        let Backing::File(file) = &self.backing else {
+            unreachable!("resize_file called on Pages not backed by a file!");
+        };
+        let flush_on_drop = file.flush_on_drop;
+        let file = file.file.try_clone()?;
+        let mut remapped = Self::map_file(&file, new_size)?;
+        remapped.set_flush_on_drop(flush_on_drop);
+        // The old mapping must be gone before truncating, or flushing it could write past the new end of the file.
+        *self = remapped;
+        #[cfg(target_family = "unix")]
+        if file.metadata()?.len() > new_size as u64 {
+            file.set_len(new_size as u64)?;
+        }
+        Ok(())
+    }
+}
+#[cfg(target_family = "windows")]
+pub(crate) fn unmap_file_view(ptr: *mut u8, file: &FileBacking) {
+    unsafe {
+        if UnmapViewOfFile(ptr.cast::<winapi::ctypes::c_void>()) == 0 {
+            let err = winapi::um::errhandlingapi::GetLastError();
+            panic!("Unmapping file view using UnmapViewOfFile failed with error code:{err}!");
+        }
+        winapi::um::handleapi::CloseHandle(file.mapping);
+    }
+}
+#[cfg(test)]
+mod test {
+    use super::*;
+    fn temp_file(name: &str) -> (std::path::PathBuf, File) {
+        let path = std::env::temp_dir().join(format!("memory_pages_{name}_{}", std::process::id()));
+        let file = std::fs::OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(&path)
+            .unwrap();
+        (path, file)
+    }
+    #[test]
+    fn test_map_file_extends() {
+        let (path, file) = temp_file("extends");
+        let pages: Pages<AllowRead, AllowWrite, DenyExec> = Pages::map_file(&file, 0x1234).unwrap();
+        assert_eq!(pages.len(), 0x2000);
+        assert_eq!(file.metadata().unwrap().len(), 0x1234);
+        drop(pages);
+        std::fs::remove_file(path).unwrap();
+    }
+    #[test]
+    fn test_flush_on_drop() {
+        let (path, file) = temp_file("flush_on_drop");
+        let mut pages: Pages<AllowRead, AllowWrite, DenyExec> =
+            Pages::map_file(&file, 0x2000).unwrap();
+        pages.set_flush_on_drop(true);
+        for i in 0..0x2000 {
+            pages[i] = i as u8;
+        }
+        drop(pages);
+        let data = std::fs::read(&path).unwrap();
+        assert!(data.iter().enumerate().all(|(i, b)| *b == i as u8));
+        std::fs::remove_file(path).unwrap();
+    }
+    #[test]
+    fn test_map_existing_read_only() {
+        let (path, _) = temp_file("read_only");
+        std::fs::write(&path, b"memory_pages").unwrap();
+        let file = File::open(&path).unwrap();
+        let pages: Pages<AllowRead, DenyWrite, DenyExec> = Pages::map_file(&file, 12).unwrap();
+        assert_eq!(pages.get(..12), Some(&b"memory_pages"[..]));
+        // Read-only files can't be mapped as writable.
+        assert!(Pages::<AllowRead, AllowWrite, DenyExec>::map_file(&file, 12).is_err());
+        drop(pages);
+        std::fs::remove_file(path).unwrap();
+    }
+    #[test]
+    fn test_resize_file() {
+        let (path, file) = temp_file("resize");
+        let mut pages: Pages<AllowRead, AllowWrite, DenyExec> =
+            Pages::map_file(&file, 0x1000).unwrap();
+        pages[0] = 1;
+        pages.resize(0x3000);
+        pages[0x2FFF] = 2;
+        pages.flush(FlushMode::Sync).unwrap();
+        let data = std::fs::read(&path).unwrap();
+        assert_eq!(data.len(), 0x3000);
+        assert_eq!((data[0], data[0x2FFF]), (1, 2));
+        drop(pages);
+        std::fs::remove_file(path).unwrap();
+    }
+    #[cfg(target_family = "unix")]
+    #[test]
+    fn test_shrink_file() {
+        let (path, file) = temp_file("shrink");
+        let mut pages: Pages<AllowRead, AllowWrite, DenyExec> =
+            Pages::map_file(&file, 0x3000).unwrap();
+        pages[0xFFF] = 1;
+        pages.resize(0x1000);
+        assert_eq!(pages.len(), 0x1000);
+        assert_eq!(pages[0xFFF], 1);
+        pages.flush(FlushMode::Sync).unwrap();
+        let data = std::fs::read(&path).unwrap();
+        assert_eq!(data.len(), 0x1000);
+        assert_eq!(data[0xFFF], 1);
+        drop(pages);
+        std::fs::remove_file(path).unwrap();
+    }
+}
@@ -2,20 +2,112 @@ use crate::*;
 /// A reference to a function inside [`Pages`]. It enforces that it may never outlive the [`Pages`] it is contained in,
 /// preventing lifetime related errors. Additionally, it enforces that if [`Pages`] permissions are changes, all [`FnRef`]
 /// referencing it will be invalidated, preventing exploits related to page permissions.
+///
+/// Unwinding out of a function with an `extern "C"` signature aborts the process. Functions which may let panics or
+/// exceptions propagate through them must be referenced with an `extern "C-unwind"` signature instead.
 pub struct FnRef<'a, F: ExternFnPtr> {
     fnc: F,
+    /// Offset of the function from the start of the executable memory it was taken from.
+    offset: usize,
+    /// Length of the executable memory the function was taken from.
+    region_len: usize,
     pd: PhantomData<&'a ()>,
 }
 impl<'a, F: ExternFnPtr> FnRef<'a, F> {
     pub(crate) fn new<R: ReadPremisionMarker, W: WritePremisionMarker>(
         fnc: F,
-        _page: &'a Pages<R, W, AllowExec>,
+        page: &'a Pages<R, W, AllowExec>,
+        offset: usize,
     ) -> Self {
         Self {
             fnc,
+            offset,
+            region_len: page.len,
             pd: PhantomData,
         }
     }
+    /// Creates a [`FnRef`] to a function at `offset` inside `region_len` bytes of executable memory owned by `owner`,
+    /// other than [`Pages`].
+    pub(crate) fn borrowing<T: ?Sized>(
+        fnc: F,
+        _owner: &'a T,
+        offset: usize,
+        region_len: usize,
+    ) -> Self {
+        Self {
+            fnc,
+            offset,
+            region_len,
+            pd: PhantomData,
+        }
+    }
+    /// Returns the address of the first instruction of the function.
+    /// # Examples
+    /// ```
+    /// # use memory_pages::*;
+    /// let memory: Pages<AllowRead, DenyWrite, AllowExec> = Pages::new(0x4000);
+    /// let function: FnRef<unsafe extern "C" fn()> = unsafe { memory.get_fn(0x10) };
+    /// assert_eq!(function.address(), memory.get_fn_ptr(0x10));
+    /// assert_eq!(function.offset(), 0x10);
+    /// assert_eq!(function.region_len(), 0x4000);
+    /// ```
+    #[must_use]
+    pub fn address(&self) -> *const () {
+        // `ExternFnPtr` is only implemented for function pointers, which have the same layout as data pointers.
+        unsafe { *std::ptr::addr_of!(self.fnc).cast::<*const ()>() }
+    }
+    /// Returns the offset of the function from the start of the [`Pages`] (or other executable memory) it was taken
+    /// from.
+    #[must_use]
+    pub fn offset(&self) -> usize {
+        self.offset
+    }
+    /// Returns the length of the [`Pages`] (or other executable memory) the function was taken from.
+    #[must_use]
+    pub fn region_len(&self) -> usize {
+        self.region_len
+    }
+    /// Returns the address of the start of the [`Pages`] (or other executable memory) the function was taken from.
+    #[must_use]
+    pub fn region_start(&self) -> *const () {
+        self.address().cast::<u8>().wrapping_sub(self.offset).cast()
+    }
+}
+impl<F: ExternFnPtr + Clone> Clone for FnRef<'_, F> {
+    fn clone(&self) -> Self {
+        Self {
+            fnc: self.fnc.clone(),
+            offset: self.offset,
+            region_len: self.region_len,
+            pd: PhantomData,
+        }
+    }
+}
+impl<F: ExternFnPtr + Copy> Copy for FnRef<'_, F> {}
+impl<F: ExternFnPtr> std::fmt::Debug for FnRef<'_, F> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("FnRef")
+            .field("address", &self.address())
+            .field("offset", &self.offset)
+            .field("region_len", &self.region_len)
+            .finish()
+    }
+}
+impl<F: ExternFnPtr> std::fmt::Pointer for FnRef<'_, F> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        std::fmt::Pointer::fmt(&self.address(), f)
+    }
+}
+impl<F: ExternFnPtr> PartialEq for FnRef<'_, F> {
+    fn eq(&self, other: &Self) -> bool {
+        self.address() == other.address()
+    }
+}
+impl<F: ExternFnPtr> Eq for FnRef<'_, F> {}
+impl<F: ExternFnPtr> std::hash::Hash for FnRef<'_, F> {
+    fn hash<H: std::hash::Hasher>(&self, state: &mut H) {
+        self.address().hash(state);
+    }
 }
 impl<'a, F: ExternFnPtr + Copy> FnRef<'a, F> {
     /// Returns the internal function.
@@ -42,539 +134,132 @@ pub trait UnsafeCallable<Args> {
     /// Return type of represented function
     type Ret;
     /// Calls the underlying function.
+    /// # Safety
+    /// Nothing is known about the called function, so it is up to the user to ensure calling it with `args` is safe.
     unsafe fn call(&self, args: Args) -> Self::Ret;
 }
-impl<'a, Ret> UnsafeCallable<()> for FnRef<'a, unsafe extern "C" fn() -> Ret> {
-    type Ret = Ret;
-    unsafe fn call(&self, _args: ()) -> Ret {
-        (self.fnc)()
-    }
-}
-impl<'a, Ret, Arg1> UnsafeCallable<Arg1> for FnRef<'a, unsafe extern "C" fn(Arg1) -> Ret> {
-    type Ret = Ret;
-    unsafe fn call(&self, args: Arg1) -> Ret {
-        (self.fnc)(args)
-    }
-}
-impl<'a, Ret, Arg1, Arg2> UnsafeCallable<(Arg1, Arg2)>
-    for FnRef<'a, unsafe extern "C" fn(Arg1, Arg2) -> Ret>
-{
-    type Ret = Ret;
-    unsafe fn call(&self, args: (Arg1, Arg2)) -> Ret {
-        (self.fnc)(args.0, args.1)
-    }
-}
-impl<'a, Ret, Arg1, Arg2, Arg3> UnsafeCallable<(Arg1, Arg2, Arg3)>
-    for FnRef<'a, unsafe extern "C" fn(Arg1, Arg2, Arg3) -> Ret>
-{
-    type Ret = Ret;
-    unsafe fn call(&self, args: (Arg1, Arg2, Arg3)) -> Ret {
-        (self.fnc)(args.0, args.1, args.2)
-    }
-}
-impl<'a, Ret, Arg1, Arg2, Arg3, Arg4> UnsafeCallable<(Arg1, Arg2, Arg3, Arg4)>
-    for FnRef<'a, unsafe extern "C" fn(Arg1, Arg2, Arg3, Arg4) -> Ret>
-{
-    type Ret = Ret;
-    unsafe fn call(&self, args: (Arg1, Arg2, Arg3, Arg4)) -> Ret {
-        (self.fnc)(args.0, args.1, args.2, args.3)
-    }
-}
-impl<'a, Ret, Arg1, Arg2, Arg3, Arg4, Arg5> UnsafeCallable<(Arg1, Arg2, Arg3, Arg4, Arg5)>
-    for FnRef<'a, unsafe extern "C" fn(Arg1, Arg2, Arg3, Arg4, Arg5) -> Ret>
-{
-    type Ret = Ret;
-    unsafe fn call(&self, args: (Arg1, Arg2, Arg3, Arg4, Arg5)) -> Ret {
-        (self.fnc)(args.0, args.1, args.2, args.3, args.4)
-    }
-}
-impl<'a, Ret, Arg1, Arg2, Arg3, Arg4, Arg5, Arg6>
-    UnsafeCallable<(Arg1, Arg2, Arg3, Arg4, Arg5, Arg6)>
-    for FnRef<'a, unsafe extern "C" fn(Arg1, Arg2, Arg3, Arg4, Arg5, Arg6) -> Ret>
-{
-    type Ret = Ret;
-    unsafe fn call(&self, args: (Arg1, Arg2, Arg3, Arg4, Arg5, Arg6)) -> Ret {
-        (self.fnc)(args.0, args.1, args.2, args.3, args.4, args.5)
-    }
-}
-impl<'a, Ret, Arg1, Arg2, Arg3, Arg4, Arg5, Arg6, Arg7>
-    UnsafeCallable<(Arg1, Arg2, Arg3, Arg4, Arg5, Arg6, Arg7)>
-    for FnRef<'a, unsafe extern "C" fn(Arg1, Arg2, Arg3, Arg4, Arg5, Arg6, Arg7) -> Ret>
-{
-    type Ret = Ret;
-    unsafe fn call(&self, args: (Arg1, Arg2, Arg3, Arg4, Arg5, Arg6, Arg7)) -> Ret {
-        (self.fnc)(args.0, args.1, args.2, args.3, args.4, args.5, args.6)
-    }
-}
-impl<'a, Ret, Arg1, Arg2, Arg3, Arg4, Arg5, Arg6, Arg7, Arg8>
-    UnsafeCallable<(Arg1, Arg2, Arg3, Arg4, Arg5, Arg6, Arg7, Arg8)>
-    for FnRef<'a, unsafe extern "C" fn(Arg1, Arg2, Arg3, Arg4, Arg5, Arg6, Arg7, Arg8) -> Ret>
-{
-    type Ret = Ret;
-    unsafe fn call(&self, args: (Arg1, Arg2, Arg3, Arg4, Arg5, Arg6, Arg7, Arg8)) -> Ret {
-        (self.fnc)(
-            args.0, args.1, args.2, args.3, args.4, args.5, args.6, args.7,
-        )
-    }
-}
-impl<'a, Ret, Arg1, Arg2, Arg3, Arg4, Arg5, Arg6, Arg7, Arg8, Arg9>
-    UnsafeCallable<(Arg1, Arg2, Arg3, Arg4, Arg5, Arg6, Arg7, Arg8, Arg9)>
-    for FnRef<'a, unsafe extern "C" fn(Arg1, Arg2, Arg3, Arg4, Arg5, Arg6, Arg7, Arg8, Arg9) -> Ret>
-{
-    type Ret = Ret;
-    unsafe fn call(&self, args: (Arg1, Arg2, Arg3, Arg4, Arg5, Arg6, Arg7, Arg8, Arg9)) -> Ret {
-        (self.fnc)(
-            args.0, args.1, args.2, args.3, args.4, args.5, args.6, args.7, args.8,
-        )
-    }
+macro_rules! unsafe_callable {
+    ($abi:literal, ()) => {
+        impl<'a, Ret> UnsafeCallable<()> for FnRef<'a, unsafe extern $abi fn() -> Ret> {
+            type Ret = Ret;
+            unsafe fn call(&self, _args: ()) -> Ret {
+                (self.fnc)()
+            }
+        }
+    };
+    // A single argument is passed as is, not as a 1-element tuple.
+    ($abi:literal, ($arg:ident)) => {
+        impl<'a, Ret, $arg> UnsafeCallable<$arg> for FnRef<'a, unsafe extern $abi fn($arg) -> Ret> {
+            type Ret = Ret;
+            unsafe fn call(&self, args: $arg) -> Ret {
+                (self.fnc)(args)
+            }
+        }
+    };
+    ($abi:literal, ($($arg:ident),+)) => {
+        impl<'a, Ret, $($arg),+> UnsafeCallable<($($arg,)+)>
+            for FnRef<'a, unsafe extern $abi fn($($arg),+) -> Ret>
+        {
+            type Ret = Ret;
+            #[allow(non_snake_case)]
+            unsafe fn call(&self, ($($arg,)+): ($($arg,)+)) -> Ret {
+                (self.fnc)($($arg),+)
+            }
+        }
+    };
 }
-impl<'a, Ret, Arg1, Arg2, Arg3, Arg4, Arg5, Arg6, Arg7, Arg8, Arg9, Arg10>
-    UnsafeCallable<(Arg1, Arg2, Arg3, Arg4, Arg5, Arg6, Arg7, Arg8, Arg9, Arg10)>
-    for FnRef<
-        'a,
-        unsafe extern "C" fn(Arg1, Arg2, Arg3, Arg4, Arg5, Arg6, Arg7, Arg8, Arg9, Arg10) -> Ret,
-    >
-{
-    type Ret = Ret;
-    unsafe fn call(
-        &self,
-        args: (Arg1, Arg2, Arg3, Arg4, Arg5, Arg6, Arg7, Arg8, Arg9, Arg10),
-    ) -> Ret {
-        (self.fnc)(
-            args.0, args.1, args.2, args.3, args.4, args.5, args.6, args.7, args.8, args.9,
-        )
-    }
+crate::extern_fn_ptr::abi_ladder!(unsafe_callable);
+/// A [`FnRef`] to a function which is safe to call with any arguments, created with [`FnRef::assume_safe`]. With the
+/// nightly `fn_traits` feature, it implements [`Fn`], so jitted functions can be passed to generic code expecting
+/// closures.
+/// # Examples
+/// ```
+/// # use memory_pages::*;
+/// let mut memory: Pages<AllowRead, AllowWrite, DenyExec> = Pages::new(0x4000);
+/// // X86_64 assembly instruction `RET`
+/// memory[0] = 0xC3;
+/// let memory = memory.set_protected_exec();
+/// let nop: FnRef<unsafe extern "C" fn()> = unsafe { memory.get_fn(0) };
+/// // `RET` does nothing, so it is safe to call with any arguments.
+/// let nop = unsafe { nop.assume_safe() };
+/// nop.call(());
+/// ```
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct SafeFnRef<'a, F: ExternFnPtr> {
+    fnc: FnRef<'a, F>,
 }
-impl<'a, Ret, Arg1, Arg2, Arg3, Arg4, Arg5, Arg6, Arg7, Arg8, Arg9, Arg10, Arg11>
-    UnsafeCallable<(
-        Arg1,
-        Arg2,
-        Arg3,
-        Arg4,
-        Arg5,
-        Arg6,
-        Arg7,
-        Arg8,
-        Arg9,
-        Arg10,
-        Arg11,
-    )>
-    for FnRef<
-        'a,
-        unsafe extern "C" fn(
-            Arg1,
-            Arg2,
-            Arg3,
-            Arg4,
-            Arg5,
-            Arg6,
-            Arg7,
-            Arg8,
-            Arg9,
-            Arg10,
-            Arg11,
-        ) -> Ret,
-    >
-{
-    type Ret = Ret;
-    unsafe fn call(
-        &self,
-        args: (
-            Arg1,
-            Arg2,
-            Arg3,
-            Arg4,
-            Arg5,
-            Arg6,
-            Arg7,
-            Arg8,
-            Arg9,
-            Arg10,
-            Arg11,
-        ),
-    ) -> Ret {
-        (self.fnc)(
-            args.0, args.1, args.2, args.3, args.4, args.5, args.6, args.7, args.8, args.9, args.10,
-        )
+impl<'a, F: ExternFnPtr> FnRef<'a, F> {
+    /// Marks the referenced function as safe to call.
+    /// # Safety
+    /// Calling the function with any arguments must not cause undefined behaviour, for as long as the returned
+    /// [`SafeFnRef`] lives.
+    #[must_use]
+    pub unsafe fn assume_safe(self) -> SafeFnRef<'a, F> {
+        SafeFnRef { fnc: self }
     }
 }
-impl<'a, Ret, Arg1, Arg2, Arg3, Arg4, Arg5, Arg6, Arg7, Arg8, Arg9, Arg10, Arg11, Arg12>
-    UnsafeCallable<(
-        Arg1,
-        Arg2,
-        Arg3,
-        Arg4,
-        Arg5,
-        Arg6,
-        Arg7,
-        Arg8,
-        Arg9,
-        Arg10,
-        Arg11,
-        Arg12,
-    )>
-    for FnRef<
-        'a,
-        unsafe extern "C" fn(
-            Arg1,
-            Arg2,
-            Arg3,
-            Arg4,
-            Arg5,
-            Arg6,
-            Arg7,
-            Arg8,
-            Arg9,
-            Arg10,
-            Arg11,
-            Arg12,
-        ) -> Ret,
-    >
-{
-    type Ret = Ret;
-    unsafe fn call(
-        &self,
-        args: (
-            Arg1,
-            Arg2,
-            Arg3,
-            Arg4,
-            Arg5,
-            Arg6,
-            Arg7,
-            Arg8,
-            Arg9,
-            Arg10,
-            Arg11,
-            Arg12,
-        ),
-    ) -> Ret {
-        (self.fnc)(
-            args.0, args.1, args.2, args.3, args.4, args.5, args.6, args.7, args.8, args.9,
-            args.10, args.11,
-        )
+impl<'a, F: ExternFnPtr> SafeFnRef<'a, F> {
+    /// Calls the underlying function.
+    pub fn call<Args>(&self, args: Args) -> <FnRef<'a, F> as UnsafeCallable<Args>>::Ret
+    where
+        FnRef<'a, F>: UnsafeCallable<Args>,
+    {
+        // Caller of `assume_safe` guarantees calling the function is safe.
+        unsafe { self.fnc.call(args) }
     }
-}
-impl<'a, Ret, Arg1, Arg2, Arg3, Arg4, Arg5, Arg6, Arg7, Arg8, Arg9, Arg10, Arg11, Arg12, Arg13>
-    UnsafeCallable<(
-        Arg1,
-        Arg2,
-        Arg3,
-        Arg4,
-        Arg5,
-        Arg6,
-        Arg7,
-        Arg8,
-        Arg9,
-        Arg10,
-        Arg11,
-        Arg12,
-        Arg13,
-    )>
-    for FnRef<
-        'a,
-        unsafe extern "C" fn(
-            Arg1,
-            Arg2,
-            Arg3,
-            Arg4,
-            Arg5,
-            Arg6,
-            Arg7,
-            Arg8,
-            Arg9,
-            Arg10,
-            Arg11,
-            Arg12,
-            Arg13,
-        ) -> Ret,
-    >
-{
-    type Ret = Ret;
-    unsafe fn call(
-        &self,
-        args: (
-            Arg1,
-            Arg2,
-            Arg3,
-            Arg4,
-            Arg5,
-            Arg6,
-            Arg7,
-            Arg8,
-            Arg9,
-            Arg10,
-            Arg11,
-            Arg12,
-            Arg13,
-        ),
-    ) -> Ret {
-        (self.fnc)(
-            args.0, args.1, args.2, args.3, args.4, args.5, args.6, args.7, args.8, args.9,
-            args.10, args.11, args.12,
-        )
+    /// Returns the underlying [`FnRef`].
+    #[must_use]
+    pub fn into_inner(self) -> FnRef<'a, F> {
+        self.fnc
     }
 }
-impl<
-        'a,
-        Ret,
-        Arg1,
-        Arg2,
-        Arg3,
-        Arg4,
-        Arg5,
-        Arg6,
-        Arg7,
-        Arg8,
-        Arg9,
-        Arg10,
-        Arg11,
-        Arg12,
-        Arg13,
-        Arg14,
-    >
-    UnsafeCallable<(
-        Arg1,
-        Arg2,
-        Arg3,
-        Arg4,
-        Arg5,
-        Arg6,
-        Arg7,
-        Arg8,
-        Arg9,
-        Arg10,
-        Arg11,
-        Arg12,
-        Arg13,
-        Arg14,
-    )>
-    for FnRef<
-        'a,
-        unsafe extern "C" fn(
-            Arg1,
-            Arg2,
-            Arg3,
-            Arg4,
-            Arg5,
-            Arg6,
-            Arg7,
-            Arg8,
-            Arg9,
-            Arg10,
-            Arg11,
-            Arg12,
-            Arg13,
-            Arg14,
-        ) -> Ret,
-    >
-{
-    type Ret = Ret;
-    unsafe fn call(
-        &self,
-        args: (
-            Arg1,
-            Arg2,
-            Arg3,
-            Arg4,
-            Arg5,
-            Arg6,
-            Arg7,
-            Arg8,
-            Arg9,
-            Arg10,
-            Arg11,
-            Arg12,
-            Arg13,
-            Arg14,
-        ),
-    ) -> Ret {
-        (self.fnc)(
-            args.0, args.1, args.2, args.3, args.4, args.5, args.6, args.7, args.8, args.9,
-            args.10, args.11, args.12, args.13,
-        )
-    }
+#[cfg(feature = "fn_traits")]
+macro_rules! fn_traits {
+    ($abi:literal, ($($arg:ident),*)) => {
+        impl<'a, Ret, $($arg),*> FnOnce<($($arg,)*)> for SafeFnRef<'a, unsafe extern $abi fn($($arg),*) -> Ret> {
+            type Output = Ret;
+            extern "rust-call" fn call_once(self, args: ($($arg,)*)) -> Ret {
+                Fn::call(&self, args)
+            }
+        }
+        impl<'a, Ret, $($arg),*> FnMut<($($arg,)*)> for SafeFnRef<'a, unsafe extern $abi fn($($arg),*) -> Ret> {
+            extern "rust-call" fn call_mut(&mut self, args: ($($arg,)*)) -> Ret {
+                Fn::call(self, args)
+            }
+        }
+        impl<'a, Ret, $($arg),*> Fn<($($arg,)*)> for SafeFnRef<'a, unsafe extern $abi fn($($arg),*) -> Ret> {
+            #[allow(non_snake_case)]
+            extern "rust-call" fn call(&self, ($($arg,)*): ($($arg,)*)) -> Ret {
+                // Caller of `assume_safe` guarantees calling the function is safe.
+                unsafe { (self.fnc.fnc)($($arg),*) }
+            }
+        }
+    };
 }
-impl<
-        'a,
-        Ret,
-        Arg1,
-        Arg2,
-        Arg3,
-        Arg4,
-        Arg5,
-        Arg6,
-        Arg7,
-        Arg8,
-        Arg9,
-        Arg10,
-        Arg11,
-        Arg12,
-        Arg13,
-        Arg14,
-        Arg15,
-    >
-    UnsafeCallable<(
-        Arg1,
-        Arg2,
-        Arg3,
-        Arg4,
-        Arg5,
-        Arg6,
-        Arg7,
-        Arg8,
-        Arg9,
-        Arg10,
-        Arg11,
-        Arg12,
-        Arg13,
-        Arg14,
-        Arg15,
-    )>
-    for FnRef<
-        'a,
-        unsafe extern "C" fn(
-            Arg1,
-            Arg2,
-            Arg3,
-            Arg4,
-            Arg5,
-            Arg6,
-            Arg7,
-            Arg8,
-            Arg9,
-            Arg10,
-            Arg11,
-            Arg12,
-            Arg13,
-            Arg14,
-            Arg15,
-        ) -> Ret,
-    >
-{
-    type Ret = Ret;
-    unsafe fn call(
-        &self,
-        args: (
-            Arg1,
-            Arg2,
-            Arg3,
-            Arg4,
-            Arg5,
-            Arg6,
-            Arg7,
-            Arg8,
-            Arg9,
-            Arg10,
-            Arg11,
-            Arg12,
-            Arg13,
-            Arg14,
-            Arg15,
-        ),
-    ) -> Ret {
-        (self.fnc)(
-            args.0, args.1, args.2, args.3, args.4, args.5, args.6, args.7, args.8, args.9,
-            args.10, args.11, args.12, args.13, args.14,
-        )
+#[cfg(feature = "fn_traits")]
+crate::extern_fn_ptr::abi_ladder!(fn_traits);
+#[cfg(all(test, feature = "fn_traits", target_arch = "x86_64"))]
+mod test {
+    use super::*;
+    fn sum_with(add: impl Fn(u64, u64) -> u64, values: &[u64]) -> u64 {
+        values.iter().fold(0, |sum, &value| add(sum, value))
     }
-}
-impl<
-        'a,
-        Ret,
-        Arg1,
-        Arg2,
-        Arg3,
-        Arg4,
-        Arg5,
-        Arg6,
-        Arg7,
-        Arg8,
-        Arg9,
-        Arg10,
-        Arg11,
-        Arg12,
-        Arg13,
-        Arg14,
-        Arg15,
-        Arg16,
-    >
-    UnsafeCallable<(
-        Arg1,
-        Arg2,
-        Arg3,
-        Arg4,
-        Arg5,
-        Arg6,
-        Arg7,
-        Arg8,
-        Arg9,
-        Arg10,
-        Arg11,
-        Arg12,
-        Arg13,
-        Arg14,
-        Arg15,
-        Arg16,
-    )>
-    for FnRef<
-        'a,
-        unsafe extern "C" fn(
-            Arg1,
-            Arg2,
-            Arg3,
-            Arg4,
-            Arg5,
-            Arg6,
-            Arg7,
-            Arg8,
-            Arg9,
-            Arg10,
-            Arg11,
-            Arg12,
-            Arg13,
-            Arg14,
-            Arg15,
-            Arg16,
-        ) -> Ret,
-    >
-{
-    type Ret = Ret;
-    unsafe fn call(
-        &self,
-        args: (
-            Arg1,
-            Arg2,
-            Arg3,
-            Arg4,
-            Arg5,
-            Arg6,
-            Arg7,
-            Arg8,
-            Arg9,
-            Arg10,
-            Arg11,
-            Arg12,
-            Arg13,
-            Arg14,
-            Arg15,
-            Arg16,
-        ),
-    ) -> Ret {
-        (self.fnc)(
-            args.0, args.1, args.2, args.3, args.4, args.5, args.6, args.7, args.8, args.9,
-            args.10, args.11, args.12, args.13, args.14, args.15,
-        )
+    #[test]
+    fn test_fn_traits() {
+        let mut memory: Pages<AllowRead, AllowWrite, DenyExec> = Pages::new(0x1000);
+        let add: &[u8] = if cfg!(target_family = "windows") {
+            // LEA RAX, [RCX + RDX]; RET
+            &[0x48, 0x8d, 0x04, 0x11, 0xC3]
+        } else {
+            // LEA RAX, [RDI + RSI]; RET
+            &[0x48, 0x8d, 0x04, 0x37, 0xC3]
+        };
+        memory.get_mut(..5).unwrap().copy_from_slice(add);
+        let memory = memory.set_protected_exec();
+        let add: FnRef<unsafe extern "C" fn(u64, u64) -> u64> = unsafe { memory.get_fn(0) };
+        let add = unsafe { add.assume_safe() };
+        assert_eq!(add(2, 3), 5);
+        assert_eq!(sum_with(add, &[1, 2, 3, 4]), 10);
+        assert_eq!(sum_with(add, &[5, 6]), 11);
     }
 }
-
-/*
-#[cfg(feature = "fn_traits")]
-impl<Args,F:ExternFnPtr> std::ops::FnOnce<Args> for &FnRef<'_,F>
-    where for<'a> FnRef<'a,F>:UnsafeCallable<Args>, Args: std::marker::Tuple
-    {
-    type Output = <Self as UnsafeCallable<Args>>::Ret;
-    extern "rust-call" fn call_once(&self,args:Args)->Self::Output{
-        self.call(args)
-    }
-}*/
@@ -0,0 +1,265 @@
+//! Control over how [`Pages`] behave when the process forks.
+use crate::*;
+use std::io;
+#[cfg(target_os = "linux")]
+fn advise(ptr: *mut u8, len: usize, advice: c_int) -> io::Result<()> {
+    if unsafe { madvise(ptr.cast(), len, advice) } != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+impl<R: ReadPremisionMarker, W: WritePremisionMarker, E: ExecPremisionMarker> Pages<R, W, E> {
+    /// Marks this [`Pages`] to be wiped in children created using `fork`. The child will see the pages filled with
+    /// zeroes, while the parent keeps its data. This prevents secrets, like keys, from leaking into subprocesses spawned
+    /// by other parts of the program. The advice is kept when [`Pages`] are resized, unless they were allocated using
+    /// [`Pages::new_batch`].
+    /// # Errors
+    /// Returns an error on platforms other than Linux (4.14 or newer), and for [`Pages`] backed by a file.
+    /// # Examples
+    /// ```
+    /// # use memory_pages::*;
+    /// let mut key:Pages<AllowRead,AllowWrite,DenyExec> = Pages::new(0x1000);
+    /// # #[cfg(target_os = "linux")]
+    /// key.advise_wipe_on_fork().unwrap();
+    /// ```
+    pub fn advise_wipe_on_fork(&mut self) -> io::Result<()> {
+        #[cfg(target_os = "linux")]
+        {
+            const MADV_WIPEONFORK: c_int = 18;
+            advise(self.ptr.as_ptr(), self.len, MADV_WIPEONFORK)
+        }
+        #[cfg(not(target_os = "linux"))]
+        Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            "wiping pages on fork is not supported on this platform",
+        ))
+    }
+    /// Excludes this [`Pages`] from children created using `fork`, so that the mapping is not duplicated into them at
+    /// all. For very large regions this avoids copying their page tables on every `fork`, which makes spawning
+    /// subprocesses much cheaper. The pages are not mapped in the child, so it must not access them.
+    /// # Errors
+    /// Returns an error on platforms other than Linux and macOS.
+    /// # Examples
+    /// ```
+    /// # use memory_pages::*;
+    /// let mut vec = PagedVec::<u64>::new(0x100_000);
+    /// # #[cfg(any(target_os = "linux", target_os = "macos"))]
+    /// vec.advise_dont_fork().unwrap();
+    /// ```
+    pub fn advise_dont_fork(&mut self) -> io::Result<()> {
+        #[cfg(target_os = "linux")]
+        {
+            const MADV_DONTFORK: c_int = 10;
+            advise(self.ptr.as_ptr(), self.len, MADV_DONTFORK)
+        }
+        #[cfg(target_os = "macos")]
+        {
+            extern "C" {
+                fn minherit(addr: *mut c_void, len: usize, inherit: c_int) -> c_int;
+            }
+            const VM_INHERIT_NONE: c_int = 2;
+            if unsafe { minherit(self.ptr.as_ptr().cast(), self.len, VM_INHERIT_NONE) } != 0 {
+                return Err(io::Error::last_os_error());
+            }
+            Ok(())
+        }
+        #[cfg(not(any(target_os = "linux", target_os = "macos")))]
+        Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            "excluding pages from fork is not supported on this platform",
+        ))
+    }
+}
+#[cfg(target_family = "unix")]
+mod hooks {
+    use crate::*;
+    use std::io;
+    use std::sync::atomic::{AtomicU8, AtomicUsize, Ordering};
+    use std::sync::OnceLock;
+    /// Function called in the child process after `fork`, with the pointer to and length of [`Pages`] it was registered
+    /// for. It runs before `fork` returns in the child, while the child only has one thread, so it should only do
+    /// async-signal-safe work, like resetting a lock or marking shared data as poisoned.
+    pub type ForkHandler = fn(ptr: *mut u8, len: usize);
+    /// Error returned when a [`ForkHandler`] can't be registered.
+    #[derive(Debug)]
+    pub enum ForkHookError {
+        /// Maximal number of simultaneously registered fork hooks has been reached.
+        TooManyHooks,
+        /// `pthread_atfork` failed.
+        Os(io::Error),
+    }
+    impl std::fmt::Display for ForkHookError {
+        fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+            match self {
+                Self::TooManyHooks => write!(f, "too many fork hooks are registered at once"),
+                Self::Os(err) => write!(f, "failed to register fork handler: {err}"),
+            }
+        }
+    }
+    impl std::error::Error for ForkHookError {
+        fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+            match self {
+                Self::Os(err) => Some(err),
+                Self::TooManyHooks => None,
+            }
+        }
+    }
+    /// Maximal number of simultaneously registered fork hooks.
+    const MAX_HOOKS: usize = 64;
+    const SLOT_FREE: u8 = 0;
+    const SLOT_BUSY: u8 = 1;
+    const SLOT_ACTIVE: u8 = 2;
+    /// Registered hook. Slots are only ever read in the child, so running hooks never needs to lock.
+    struct Slot {
+        state: AtomicU8,
+        start: AtomicUsize,
+        len: AtomicUsize,
+        handler: AtomicUsize,
+    }
+    #[allow(clippy::declare_interior_mutable_const)]
+    const EMPTY_SLOT: Slot = Slot {
+        state: AtomicU8::new(SLOT_FREE),
+        start: AtomicUsize::new(0),
+        len: AtomicUsize::new(0),
+        handler: AtomicUsize::new(0),
+    };
+    static SLOTS: [Slot; MAX_HOOKS] = [EMPTY_SLOT; MAX_HOOKS];
+    /// Keeps a [`ForkHandler`] registered, until dropped. Created using [`Pages::on_fork_child`].
+    pub struct ForkHook<'a> {
+        slot: &'static Slot,
+        pages: PhantomData<&'a ()>,
+    }
+    impl Drop for ForkHook<'_> {
+        fn drop(&mut self) {
+            self.slot.state.store(SLOT_FREE, Ordering::Release);
+        }
+    }
+    extern "C" {
+        fn pthread_atfork(
+            prepare: Option<unsafe extern "C" fn()>,
+            parent: Option<unsafe extern "C" fn()>,
+            child: Option<unsafe extern "C" fn()>,
+        ) -> c_int;
+    }
+    /// Runs all registered hooks in the child.
+    unsafe extern "C" fn run_child_hooks() {
+        for slot in SLOTS
+            .iter()
+            .filter(|slot| slot.state.load(Ordering::Acquire) == SLOT_ACTIVE)
+        {
+            let handler: ForkHandler = std::mem::transmute(slot.handler.load(Ordering::Relaxed));
+            handler(
+                slot.start.load(Ordering::Relaxed) as *mut u8,
+                slot.len.load(Ordering::Relaxed),
+            );
+        }
+    }
+    static INSTALLED: OnceLock<c_int> = OnceLock::new();
+    impl<R: ReadPremisionMarker, W: WritePremisionMarker, E: ExecPremisionMarker> Pages<R, W, E> {
+        /// Registers `handler` to be called in the child process each time the process forks, for as long as the returned
+        /// [`ForkHook`] exists. This allows data shared between processes, like locks or reference counts, to re-initialize
+        /// or poison itself in the child, instead of being left in a state only meaningful to the parent.
+        ///
+        /// `handler` is called regardless of protection of this [`Pages`]. If it needs to access them, it must change
+        /// protection first.
+        /// # Errors
+        /// Returns an error if too many hooks are already registered, or if `pthread_atfork` fails.
+        /// # Examples
+        /// ```
+        /// # use memory_pages::*;
+        /// fn reset_lock(ptr:*mut u8, _len:usize){
+        ///     // Thread holding the lock does not exist in the child.
+        ///     unsafe{ptr.write(0)};
+        /// }
+        /// let mut shared:Pages<AllowRead,AllowWrite,DenyExec> = Pages::new(0x1000);
+        /// let hook = shared.on_fork_child(reset_lock).unwrap();
+        /// ```
+        pub fn on_fork_child(&self, handler: ForkHandler) -> Result<ForkHook<'_>, ForkHookError> {
+            let res = *INSTALLED
+                .get_or_init(|| unsafe { pthread_atfork(None, None, Some(run_child_hooks)) });
+            if res != 0 {
+                return Err(ForkHookError::Os(io::Error::from_raw_os_error(res)));
+            }
+            let slot = SLOTS
+                .iter()
+                .find(|slot| {
+                    slot.state
+                        .compare_exchange(
+                            SLOT_FREE,
+                            SLOT_BUSY,
+                            Ordering::Acquire,
+                            Ordering::Relaxed,
+                        )
+                        .is_ok()
+                })
+                .ok_or(ForkHookError::TooManyHooks)?;
+            slot.start
+                .store(self.ptr.as_ptr() as usize, Ordering::Relaxed);
+            slot.len.store(self.len, Ordering::Relaxed);
+            slot.handler.store(handler as usize, Ordering::Relaxed);
+            slot.state.store(SLOT_ACTIVE, Ordering::Release);
+            Ok(ForkHook {
+                slot,
+                pages: PhantomData,
+            })
+        }
+    }
+}
+#[cfg(target_family = "unix")]
+pub use hooks::*;
+#[cfg(all(test, target_os = "linux"))]
+mod test {
+    use super::*;
+    extern "C" {
+        fn fork() -> c_int;
+        fn waitpid(pid: c_int, status: *mut c_int, options: c_int) -> c_int;
+        fn _exit(status: c_int) -> !;
+    }
+    /// Forks, and returns the exit status of the child running `child`.
+    fn in_child(child: impl FnOnce() -> c_int) -> c_int {
+        let pid = unsafe { fork() };
+        assert!(pid >= 0, "fork failed");
+        if pid == 0 {
+            // Only async-signal-safe operations are allowed here, since other test threads are not in the child.
+            unsafe { _exit(child()) };
+        }
+        let mut status = 0;
+        assert_eq!(unsafe { waitpid(pid, &mut status, 0) }, pid);
+        // WEXITSTATUS
+        (status >> 8) & 0xFF
+    }
+    #[test]
+    fn test_wipe_on_fork() {
+        let mut key: Pages<AllowRead, AllowWrite, DenyExec> = Pages::new(0x2000);
+        key.fill(0xAA);
+        key.advise_wipe_on_fork().unwrap();
+        let status = in_child(|| c_int::from(key.iter().any(|byte| *byte != 0)));
+        assert_eq!(status, 0);
+        assert!(key.iter().all(|byte| *byte == 0xAA));
+    }
+    #[test]
+    fn test_fork_hook() {
+        fn poison(ptr: *mut u8, len: usize) {
+            unsafe { ptr.add(len - 1).write(0xFF) };
+        }
+        let shared: Pages<AllowRead, AllowWrite, DenyExec> = Pages::new(0x1000);
+        let hook = shared.on_fork_child(poison).unwrap();
+        let status = in_child(|| c_int::from(shared[0xFFF] != 0xFF));
+        assert_eq!(status, 0);
+        assert_eq!(shared[0xFFF], 0);
+        drop(hook);
+        let status = in_child(|| c_int::from(shared[0xFFF] != 0));
+        assert_eq!(status, 0);
+    }
+    #[test]
+    fn test_dont_fork() {
+        let mut pages: Pages<AllowRead, AllowWrite, DenyExec> = Pages::new(0x2000);
+        pages.advise_dont_fork().unwrap();
+        // The range is not mapped in the child, so changing its protection fails.
+        let status = in_child(|| {
+            c_int::from(unsafe { mprotect(pages.ptr.as_ptr().cast(), pages.len, 0) } == 0)
+        });
+        assert_eq!(status, 0);
+        pages[0] = 1;
+    }
+}
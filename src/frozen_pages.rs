@@ -0,0 +1,186 @@
+use crate::*;
+/// Read-only [`Pages`], which can never be made writable again. Created using [`Pages::freeze`].
+///
+/// Since data inside [`FrozenPages`] can't change, they can be freely shared between threads, which makes them a good
+/// fit for "build once, share forever" data, like interned tables.
+/// # Examples
+/// ```
+/// # use memory_pages::*;
+/// let mut table:Pages<AllowRead,AllowWrite,DenyExec> = Pages::new(0x1000);
+/// table[0] = 42;
+/// let table = std::sync::Arc::new(table.freeze());
+/// let shared = table.clone();
+/// std::thread::spawn(move ||assert_eq!(shared[0], 42)).join().unwrap();
+/// ```
+/// There is no way to get writable [`Pages`] back out of [`FrozenPages`].
+/// ```compile_fail
+/// # use memory_pages::*;
+/// let table:Pages<AllowRead,AllowWrite,DenyExec> = Pages::new(0x1000);
+/// let table = table.freeze();
+/// let mut table = table.allow_write();
+/// ```
+pub struct FrozenPages {
+    pages: Pages<AllowRead, DenyWrite, DenyExec>,
+}
+impl<R: ReadPremisionMarker, W: WritePremisionMarker, E: ExecPremisionMarker> Pages<R, W, E> {
+    /// Makes this [`Pages`] permanently read-only, turning them into [`FrozenPages`].
+    /// # Examples
+    /// ```
+    /// # use memory_pages::*;
+    /// let memory:Pages<AllowRead,AllowWrite,DenyExec> = Pages::new(0x1000);
+    /// let frozen = memory.freeze();
+    /// assert_eq!(frozen.len(), 0x1000);
+    /// ```
+    #[must_use]
+    pub fn freeze(self) -> FrozenPages {
+        FrozenPages {
+            pages: self.into_prot(),
+        }
+    }
+}
+impl FrozenPages {
+    /// Returns a pointer to data at `offset`. Data behind it may only be read from.
+    /// # Panics
+    /// Panics if offset larger than length of [`FrozenPages`].
+    #[must_use]
+    pub fn get_ptr(&self, offset: usize) -> *const u8 {
+        self.pages.get_ptr(offset)
+    }
+}
+impl Deref for FrozenPages {
+    type Target = [u8];
+    fn deref(&self) -> &Self::Target {
+        &self.pages
+    }
+}
+impl Borrow<[u8]> for FrozenPages {
+    fn borrow(&self) -> &[u8] {
+        self
+    }
+}
+impl AsRef<[u8]> for FrozenPages {
+    fn as_ref(&self) -> &[u8] {
+        self
+    }
+}
+/// Read-only [`PagedVec`], whose memory is write-protected by hardware. Created using [`PagedVec::freeze`].
+///
+/// Elements can't be modified, not even through interior mutability, which makes [`FrozenPagedVec`] a good fit for
+/// large data sets loaded once and then shared between threads. Mutating an element containing a `Cell`, a `Mutex` or
+/// an atomic crashes the process.
+/// # Examples
+/// ```
+/// # use memory_pages::*;
+/// let table:PagedVec<u64> = (0..0x1000).collect();
+/// let table = std::sync::Arc::new(table.freeze());
+/// let shared = table.clone();
+/// std::thread::spawn(move ||assert_eq!(shared[0x123], 0x123)).join().unwrap();
+/// ```
+pub struct FrozenPagedVec<T> {
+    data: Option<Pages<AllowRead, DenyWrite, DenyExec>>,
+    len: usize,
+    pd: PhantomData<T>,
+}
+impl<T> PagedVec<T> {
+    /// Makes memory of this [`PagedVec`] read-only, turning it into a [`FrozenPagedVec`].
+    /// # Examples
+    /// ```
+    /// # use memory_pages::*;
+    /// let vec:PagedVec<u32> = (0..4).collect();
+    /// let frozen = vec.freeze();
+    /// assert_eq!(&frozen[..], &[0, 1, 2, 3]);
+    /// ```
+    #[must_use]
+    pub fn freeze(self) -> FrozenPagedVec<T> {
+        let (data, len) = self.into_parts();
+        FrozenPagedVec {
+            data: data.map(Pages::into_prot),
+            len,
+            pd: PhantomData,
+        }
+    }
+}
+impl<T> FrozenPagedVec<T> {
+    fn elements(&self) -> *mut T {
+        self.data
+            .as_ref()
+            .map_or(NonNull::dangling().as_ptr(), |data| {
+                data.get_ptr_unchecked().cast::<T>()
+            })
+    }
+}
+impl<T> Deref for FrozenPagedVec<T> {
+    type Target = [T];
+    fn deref(&self) -> &[T] {
+        unsafe { std::slice::from_raw_parts(self.elements(), self.len) }
+    }
+}
+impl<T> Borrow<[T]> for FrozenPagedVec<T> {
+    fn borrow(&self) -> &[T] {
+        self
+    }
+}
+impl<T> AsRef<[T]> for FrozenPagedVec<T> {
+    fn as_ref(&self) -> &[T] {
+        self
+    }
+}
+impl<T: std::fmt::Debug> std::fmt::Debug for FrozenPagedVec<T> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_list().entries(self.iter()).finish()
+    }
+}
+impl<T> Drop for FrozenPagedVec<T> {
+    fn drop(&mut self) {
+        let Some(data) = self.data.take() else {
+            return;
+        };
+        let used = self.len * std::mem::size_of::<T>();
+        // Address range may be reused by a later mapping, which must not inherit poisoned state.
+        sanitizer::unpoison(
+            data.get_ptr_unchecked().wrapping_add(used),
+            data.len() - used,
+        );
+        if !std::mem::needs_drop::<T>() {
+            return;
+        }
+        // Elements must be writable to be dropped.
+        let data: Pages<AllowRead, AllowWrite, DenyExec> = data.into_prot();
+        let elements =
+            std::ptr::slice_from_raw_parts_mut(data.get_ptr_unchecked().cast::<T>(), self.len);
+        unsafe { std::ptr::drop_in_place(elements) };
+    }
+}
+#[cfg(test)]
+mod test {
+    use super::*;
+    #[test]
+    fn test_freeze_shared() {
+        let mut pages: Pages<AllowRead, AllowWrite, DenyExec> = Pages::new(0x2000);
+        for (i, byte) in pages.iter_mut().enumerate() {
+            *byte = i as u8;
+        }
+        let frozen = std::sync::Arc::new(pages.freeze());
+        let threads: Vec<_> = (0..4)
+            .map(|_| {
+                let frozen = frozen.clone();
+                std::thread::spawn(move || {
+                    frozen.iter().enumerate().all(|(i, byte)| *byte == i as u8)
+                })
+            })
+            .collect();
+        for thread in threads {
+            assert!(thread.join().unwrap());
+        }
+    }
+    #[test]
+    fn test_freeze_vec_drops() {
+        let vec: PagedVec<String> = (0..0x100).map(|i| i.to_string()).collect();
+        let frozen = vec.freeze();
+        assert_eq!(frozen[0xFF], "255");
+        assert_eq!(frozen.len(), 0x100);
+        drop(frozen);
+        let empty = PagedVec::<String>::new_empty().freeze();
+        assert!(empty.is_empty());
+    }
+}
@@ -0,0 +1,290 @@
+use std::fmt::{Debug, Formatter};
+use std::sync::Mutex;
+/// Entry of the list of symbol files read by debuggers, laid out as the GDB JIT interface requires.
+#[repr(C)]
+struct JitCodeEntry {
+    next_entry: *mut JitCodeEntry,
+    prev_entry: *mut JitCodeEntry,
+    symfile_addr: *const u8,
+    symfile_size: u64,
+}
+/// Head of the list of symbol files, which debuggers look up by name.
+#[repr(C)]
+struct JitDescriptor {
+    version: u32,
+    action_flag: u32,
+    relevant_entry: *mut JitCodeEntry,
+    first_entry: *mut JitCodeEntry,
+}
+const JIT_NOACTION: u32 = 0;
+const JIT_REGISTER_FN: u32 = 1;
+const JIT_UNREGISTER_FN: u32 = 2;
+#[no_mangle]
+#[allow(non_upper_case_globals)]
+static mut __jit_debug_descriptor: JitDescriptor = JitDescriptor {
+    version: 1,
+    action_flag: JIT_NOACTION,
+    relevant_entry: std::ptr::null_mut(),
+    first_entry: std::ptr::null_mut(),
+};
+/// Debuggers place a breakpoint in this function, and read the descriptor each time it is called.
+#[no_mangle]
+#[inline(never)]
+extern "C" fn __jit_debug_register_code() {
+    // A volatile read keeps calls to this function from being optimized away.
+    unsafe { std::ptr::read_volatile(std::ptr::addr_of!(__jit_debug_descriptor.action_flag)) };
+}
+/// Serializes changes to the descriptor, which is shared by the whole process.
+static DESCRIPTOR_LOCK: Mutex<()> = Mutex::new(());
+/// A function inside executable memory, described by its name, address and size.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct JitSymbol<'a> {
+    /// Name of the function.
+    pub name: &'a str,
+    /// Address of the first instruction of the function.
+    pub address: *const u8,
+    /// Size of the function, in bytes.
+    pub size: usize,
+}
+/// A symbol file describing jitted code, registered with debuggers using the GDB JIT interface for as long as this
+/// [`GdbJitRegistration`] lives. Both gdb and lldb read it when code is registered, so functions inside anonymous
+/// executable pages show up in backtraces and can have breakpoints set on them by name.
+///
+/// Registration defines the `__jit_debug_descriptor` and `__jit_debug_register_code` symbols debuggers look for, so
+/// it can't be used together with other libraries defining them, like LLVM.
+/// # Examples
+/// ```
+/// # use memory_pages::*;
+/// let mut code = JitPages::new(0x1000);
+/// code.jit_write_scope(|code| code[0] = 0xC3);
+/// // `break jitted_nop` in gdb now stops inside of the pages.
+/// let registration = GdbJitRegistration::register_functions(&[JitSymbol {
+///     name: "jitted_nop",
+///     address: code.as_ptr(),
+///     size: 1,
+/// }]);
+/// assert_eq!(&registration.symfile()[..4], b"\x7FELF");
+/// ```
+pub struct GdbJitRegistration {
+    /// Boxed, because debuggers keep pointers to it.
+    entry: Box<JitCodeEntry>,
+    symfile: Box<[u8]>,
+}
+// The entry is only accessed under `DESCRIPTOR_LOCK`, and the symbol file is never modified.
+unsafe impl Send for GdbJitRegistration {}
+unsafe impl Sync for GdbJitRegistration {}
+impl GdbJitRegistration {
+    /// Registers `symfile`, an in-memory object file in a format the debugger understands, e.g. ELF with DWARF debug
+    /// info.
+    #[must_use]
+    pub fn register(symfile: impl Into<Box<[u8]>>) -> Self {
+        let symfile = symfile.into();
+        let mut entry = Box::new(JitCodeEntry {
+            next_entry: std::ptr::null_mut(),
+            prev_entry: std::ptr::null_mut(),
+            symfile_addr: symfile.as_ptr(),
+            symfile_size: symfile.len() as u64,
+        });
+        let _lock = DESCRIPTOR_LOCK
+            .lock()
+            .unwrap_or_else(std::sync::PoisonError::into_inner);
+        unsafe {
+            let descriptor = std::ptr::addr_of_mut!(__jit_debug_descriptor);
+            entry.next_entry = (*descriptor).first_entry;
+            let entry_ptr: *mut JitCodeEntry = &mut *entry;
+            if let Some(first) = (*descriptor).first_entry.as_mut() {
+                first.prev_entry = entry_ptr;
+            }
+            (*descriptor).first_entry = entry_ptr;
+            (*descriptor).relevant_entry = entry_ptr;
+            (*descriptor).action_flag = JIT_REGISTER_FN;
+            __jit_debug_register_code();
+            (*descriptor).action_flag = JIT_NOACTION;
+        }
+        Self { entry, symfile }
+    }
+    /// Registers an ELF symbol file naming `functions`, without any other debug info.
+    /// # Panics
+    /// Panics if `functions` is empty.
+    #[cfg(target_pointer_width = "64")]
+    #[must_use]
+    pub fn register_functions(functions: &[JitSymbol]) -> Self {
+        Self::register(elf_symfile(functions))
+    }
+    /// Returns the registered symbol file.
+    #[must_use]
+    pub fn symfile(&self) -> &[u8] {
+        &self.symfile
+    }
+}
+impl Drop for GdbJitRegistration {
+    fn drop(&mut self) {
+        let _lock = DESCRIPTOR_LOCK
+            .lock()
+            .unwrap_or_else(std::sync::PoisonError::into_inner);
+        unsafe {
+            let descriptor = std::ptr::addr_of_mut!(__jit_debug_descriptor);
+            let entry_ptr: *mut JitCodeEntry = &mut *self.entry;
+            if let Some(prev) = self.entry.prev_entry.as_mut() {
+                prev.next_entry = self.entry.next_entry;
+            } else {
+                (*descriptor).first_entry = self.entry.next_entry;
+            }
+            if let Some(next) = self.entry.next_entry.as_mut() {
+                next.prev_entry = self.entry.prev_entry;
+            }
+            (*descriptor).relevant_entry = entry_ptr;
+            (*descriptor).action_flag = JIT_UNREGISTER_FN;
+            __jit_debug_register_code();
+            (*descriptor).action_flag = JIT_NOACTION;
+            (*descriptor).relevant_entry = std::ptr::null_mut();
+        }
+    }
+}
+impl Debug for GdbJitRegistration {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("GdbJitRegistration")
+            .field("symfile_size", &self.symfile.len())
+            .finish()
+    }
+}
+/// ELF machine of the current target.
+#[cfg_attr(not(target_pointer_width = "64"), allow(dead_code))]
+pub(crate) const ELF_MACHINE: u16 = if cfg!(target_arch = "x86_64") {
+    62
+} else if cfg!(target_arch = "x86") {
+    3
+} else if cfg!(target_arch = "arm") {
+    40
+} else if cfg!(target_arch = "aarch64") {
+    183
+} else if cfg!(target_arch = "riscv64") {
+    243
+} else if cfg!(target_arch = "powerpc64") {
+    21
+} else {
+    0
+};
+/// Builds an ELF file with a symbol for each function, all placed in a `.text` section without contents.
+#[cfg(target_pointer_width = "64")]
+fn elf_symfile(functions: &[JitSymbol]) -> Vec<u8> {
+    const HEADER_SIZE: usize = 64;
+    const SECTION_HEADER_SIZE: usize = 64;
+    const SYMBOL_SIZE: usize = 24;
+    assert!(!functions.is_empty(), "no functions to register!");
+    let start = functions
+        .iter()
+        .map(|f| f.address as u64)
+        .min()
+        .unwrap_or(0);
+    let end = functions
+        .iter()
+        .map(|f| f.address as u64 + f.size as u64)
+        .max()
+        .unwrap_or(0);
+    let mut strtab = vec![0_u8];
+    let mut symtab = vec![0_u8; SYMBOL_SIZE];
+    for function in functions {
+        let name = strtab.len() as u32;
+        strtab.extend_from_slice(function.name.as_bytes());
+        strtab.push(0);
+        symtab.extend_from_slice(&name.to_ne_bytes());
+        // Global function, in `.text`.
+        symtab.extend_from_slice(&[0x12, 0]);
+        symtab.extend_from_slice(&1_u16.to_ne_bytes());
+        symtab.extend_from_slice(&(function.address as u64).to_ne_bytes());
+        symtab.extend_from_slice(&(function.size as u64).to_ne_bytes());
+    }
+    let shstrtab = b"\0.text\0.symtab\0.strtab\0.shstrtab\0";
+    let symtab_offset = HEADER_SIZE;
+    let strtab_offset = symtab_offset + symtab.len();
+    let shstrtab_offset = strtab_offset + strtab.len();
+    let section_headers = (shstrtab_offset + shstrtab.len()).next_multiple_of(8);
+    let mut elf = Vec::with_capacity(section_headers + 5 * SECTION_HEADER_SIZE);
+    elf.extend_from_slice(b"\x7FELF");
+    let data = if cfg!(target_endian = "little") { 1 } else { 2 };
+    elf.extend_from_slice(&[2, data, 1, 0, 0, 0, 0, 0, 0, 0, 0, 0]);
+    // Executable, with absolute addresses.
+    elf.extend_from_slice(&2_u16.to_ne_bytes());
+    elf.extend_from_slice(&ELF_MACHINE.to_ne_bytes());
+    elf.extend_from_slice(&1_u32.to_ne_bytes());
+    // No entry point, and no program headers.
+    elf.extend_from_slice(&[0; 16]);
+    elf.extend_from_slice(&(section_headers as u64).to_ne_bytes());
+    elf.extend_from_slice(&0_u32.to_ne_bytes());
+    for half in [HEADER_SIZE, 0, 0, SECTION_HEADER_SIZE, 5, 4] {
+        elf.extend_from_slice(&(half as u16).to_ne_bytes());
+    }
+    elf.extend_from_slice(&symtab);
+    elf.extend_from_slice(&strtab);
+    elf.extend_from_slice(shstrtab);
+    elf.resize(section_headers, 0);
+    // Name and type of a section, followed by its flags, address, offset, size, link, info, alignment and entry size.
+    let mut section =
+        |name: u32,
+         kind: u32,
+         [flags, addr, offset, size, link, info, align, entsize]: [u64; 8]| {
+            elf.extend_from_slice(&name.to_ne_bytes());
+            elf.extend_from_slice(&kind.to_ne_bytes());
+            for field in [flags, addr, offset, size] {
+                elf.extend_from_slice(&field.to_ne_bytes());
+            }
+            for field in [link, info] {
+                elf.extend_from_slice(&(field as u32).to_ne_bytes());
+            }
+            for field in [align, entsize] {
+                elf.extend_from_slice(&field.to_ne_bytes());
+            }
+        };
+    section(0, 0, [0; 8]);
+    // `.text` is allocated and executable, but has no contents in the file.
+    section(1, 8, [0x6, start, 0, end - start, 0, 0, 16, 0]);
+    let (offset, size) = (symtab_offset as u64, symtab.len() as u64);
+    section(7, 2, [0, 0, offset, size, 3, 1, 8, SYMBOL_SIZE as u64]);
+    let (offset, size) = (strtab_offset as u64, strtab.len() as u64);
+    section(15, 3, [0, 0, offset, size, 0, 0, 1, 0]);
+    let (offset, size) = (shstrtab_offset as u64, shstrtab.len() as u64);
+    section(23, 3, [0, 0, offset, size, 0, 0, 1, 0]);
+    elf
+}
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::*;
+    #[test]
+    fn test_gdb_jit_registration_list() {
+        let code = JitPages::new(0x1000);
+        let symbols = [
+            JitSymbol {
+                name: "first",
+                address: code.as_ptr(),
+                size: 0x10,
+            },
+            JitSymbol {
+                name: "second",
+                address: code.as_ptr().wrapping_add(0x10),
+                size: 0x20,
+            },
+        ];
+        let first = GdbJitRegistration::register_functions(&symbols);
+        let second = GdbJitRegistration::register(first.symfile().to_vec());
+        let elf = first.symfile();
+        let section_headers = u64::from_ne_bytes(elf[40..48].try_into().unwrap()) as usize;
+        assert_eq!(elf.len(), section_headers + 5 * 64);
+        assert_eq!(
+            &elf[64 + 24 + 8..64 + 24 + 16],
+            (code.as_ptr() as u64).to_ne_bytes()
+        );
+        let descriptor = std::ptr::addr_of!(__jit_debug_descriptor);
+        let _lock = DESCRIPTOR_LOCK.lock().unwrap();
+        unsafe {
+            assert!(std::ptr::eq((*descriptor).first_entry, &*second.entry));
+            assert!(std::ptr::eq(second.entry.next_entry, &*first.entry));
+        }
+        drop(_lock);
+        drop(second);
+        unsafe { assert!(std::ptr::eq((*descriptor).first_entry, &*first.entry)) };
+        drop(first);
+        unsafe { assert!((*descriptor).first_entry.is_null()) };
+    }
+}
@@ -0,0 +1,104 @@
+use crate::dyn_pages::protect_range;
+use crate::page_math::{align_down, align_up};
+use crate::*;
+/// [`Pages`] which can't be accessed at all, used to reserve address space or to trap stray accesses.
+pub type GuardPages = Pages<DenyRead, DenyWrite, DenyExec>;
+impl Pages<DenyRead, DenyWrite, DenyExec> {
+    /// Allocates a new no-access region of size at least `length`, rounded up to next page boundary. Any access to it
+    /// causes a segfault, which makes it useful for guard regions fencing off other memory, like a custom stack.
+    /// Inaccessible pages are never backed by physical memory, until parts of them are made accessible with
+    /// [`Self::unguard`], or the whole region is converted using methods like [`Self::allow_read`].
+    /// # Panics
+    /// Panics when a 0-sized allocation is attempted, or if kernel can't/refuses to allocate requested Pages.
+    /// # Examples
+    /// ```
+    /// # use memory_pages::*;
+    /// let guard = Pages::new_guard(0x4000);
+    /// assert_eq!(guard.len(), 0x4000);
+    /// // Guard pages can be converted just like any other pages.
+    /// let memory = guard.allow_read();
+    /// assert_eq!(memory[0], 0);
+    /// ```
+    #[must_use]
+    pub fn new_guard(length: usize) -> Self {
+        Self::new(length)
+    }
+    /// Returns the length of this [`GuardPages`], in bytes.
+    #[must_use]
+    pub fn len(&self) -> usize {
+        self.len
+    }
+    /// Always returns false, because [`GuardPages`] can't be empty.
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        false
+    }
+    /// Makes the pages starting at the page `beginning` is in, and continuing till page `beginning + length` is in,
+    /// accessible with `protection`, and returns a pointer to the first of them. The rest of this region stays inaccessible,
+    /// so accesses overrunning the returned range still cause a segfault.
+    /// # Errors
+    /// Returns an error if `protection` may not be set, or if kernel refuses to change it.
+    /// # Panics
+    /// Panics if the range is out of bounds.
+    /// # Examples
+    /// A stack of 4 pages, fenced by a guard page on each side.
+    /// ```
+    /// # use memory_pages::*;
+    /// let mut stack = Pages::new_guard(0x6000);
+    /// let bottom = stack.unguard(0x1000, 0x4000, Protection::ReadWrite).unwrap();
+    /// unsafe { bottom.add(0x3FFF).write(1) };
+    /// assert_eq!(unsafe { bottom.add(0x3FFF).read() }, 1);
+    /// ```
+    pub fn unguard(
+        &mut self,
+        beginning: usize,
+        length: usize,
+        protection: Protection,
+    ) -> Result<*mut u8, ProtectionError> {
+        let (ptr, len) = self.page_span(beginning, length);
+        protect_range(ptr, len, protection)?;
+        Ok(ptr)
+    }
+    /// Makes the pages starting at the page `beginning` is in, and continuing till page `beginning + length` is in,
+    /// inaccessible again. Data inside them is kept, and will be visible once they are unguarded again.
+    /// # Errors
+    /// Returns an error if kernel refuses to change protection.
+    /// # Panics
+    /// Panics if the range is out of bounds.
+    pub fn reguard(&mut self, beginning: usize, length: usize) -> Result<(), ProtectionError> {
+        let (ptr, len) = self.page_span(beginning, length);
+        protect_range(ptr, len, Protection::NoAccess)
+    }
+    fn page_span(&self, beginning: usize, length: usize) -> (*mut u8, usize) {
+        let end = beginning
+            .checked_add(length)
+            .filter(|end| *end <= self.len)
+            .unwrap_or_else(|| panic!("range {beginning}+{length} out of bounds!"));
+        let start = align_down(beginning);
+        (
+            unsafe { self.ptr.as_ptr().add(start) },
+            align_up(end) - start,
+        )
+    }
+}
+#[cfg(test)]
+mod test {
+    use super::*;
+    #[test]
+    fn test_unguard_reguard() {
+        let mut guard = Pages::new_guard(0x3000);
+        let ptr = guard.unguard(0x1800, 0x10, Protection::ReadWrite).unwrap();
+        // Only the page containing the range is made accessible.
+        assert_eq!(ptr, unsafe { guard.ptr.as_ptr().add(0x1000) });
+        unsafe { ptr.write(5) };
+        guard.reguard(0x1000, 0x1000).unwrap();
+        let memory = guard.allow_read();
+        assert_eq!(memory[0x1000], 5);
+    }
+    #[test]
+    #[should_panic]
+    fn test_unguard_out_of_bounds() {
+        let mut guard = Pages::new_guard(0x1000);
+        let _ = guard.unguard(0x800, 0x801, Protection::Read);
+    }
+}
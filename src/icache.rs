@@ -0,0 +1,211 @@
+use crate::*;
+use std::ops::Range;
+impl<R: ReadPremisionMarker, W: WritePremisionMarker, E: ExecPremisionMarker> Pages<R, W, E> {
+    /// Makes instructions written into `range` of this [`Pages`] visible to instruction fetches. Architectures like
+    /// ARM and AArch64 don't keep their instruction caches coherent with data caches, so freshly written code may
+    /// otherwise execute stale bytes. On x86 this is a no-op.
+    ///
+    /// Changing permissions of [`Pages`] to allow execution, or writing into executable [`Pages`] through
+    /// [`Self::write_guard`] or [`Self::with_writable`], already flushes the whole [`Pages`], so this is only needed when
+    /// code is modified in [`Pages`] that are both writable and executable.
+    ///
+    /// Flushing only guarantees that threads which start executing the code afterwards see new instructions. When
+    /// patching code other threads may be running at the same time, also call [`sync_code_across_threads`], which the
+    /// automatic flushes above already do.
+    /// # Panics
+    /// Panics if `range` is out of bounds.
+    /// # Examples
+    /// ```
+    /// # use memory_pages::*;
+    /// let mut code: Pages<AllowRead, AllowWrite, DenyExec> = Pages::new(0x1000);
+    /// code[0] = 0xC3;
+    /// code.flush_icache(0..1);
+    /// ```
+    pub fn flush_icache(&self, range: Range<usize>) {
+        assert!(
+            range.start <= range.end && range.end <= self.len,
+            "range {range:?} out of bounds of {} bytes!",
+            self.len
+        );
+        flush_icache_range(unsafe { self.ptr.as_ptr().add(range.start) }, range.len());
+    }
+}
+/// Makes all other threads of the process discard instructions they may have already fetched, so code modified by this
+/// thread is safe for them to execute, without them ever running a mix of old and new instructions. Needed when code is
+/// patched while other threads may be running it, e.g. by a JIT replacing functions with faster tiers. Instruction cache
+/// of the modified code must be flushed first, see [`Pages::flush_icache`].
+///
+/// Uses `membarrier` with `MEMBARRIER_CMD_PRIVATE_EXPEDITED_SYNC_CORE` on Linux, falling back to a plain expedited
+/// memory barrier on kernels which can't synchronize cores, and `FlushProcessWriteBuffers` on Windows.
+/// [`JitDualMap::write`], [`Pages::with_writable`], dropping a [`WriteGuard`] of executable [`Pages`], and inserting or
+/// removing breakpoints all call this on their own.
+/// # Errors
+/// Returns an error if the kernel supports no way of synchronizing threads, or on other systems.
+/// # Examples
+/// ```
+/// # use memory_pages::*;
+/// # #[cfg(any(target_os = "linux", target_family = "windows"))]
+/// sync_code_across_threads().unwrap();
+/// ```
+pub fn sync_code_across_threads() -> std::io::Result<()> {
+    #[cfg(target_os = "linux")]
+    {
+        use std::ffi::c_long;
+        use std::sync::atomic::{AtomicI32, Ordering};
+        extern "C" {
+            fn syscall(number: c_long, ...) -> c_long;
+        }
+        #[cfg(target_arch = "x86_64")]
+        const SYS_MEMBARRIER: Option<c_long> = Some(324);
+        #[cfg(target_arch = "x86")]
+        const SYS_MEMBARRIER: Option<c_long> = Some(375);
+        #[cfg(target_arch = "arm")]
+        const SYS_MEMBARRIER: Option<c_long> = Some(389);
+        // Architectures using the generic system call table.
+        #[cfg(any(
+            target_arch = "aarch64",
+            target_arch = "riscv64",
+            target_arch = "loongarch64"
+        ))]
+        const SYS_MEMBARRIER: Option<c_long> = Some(283);
+        #[cfg(not(any(
+            target_arch = "x86_64",
+            target_arch = "x86",
+            target_arch = "arm",
+            target_arch = "aarch64",
+            target_arch = "riscv64",
+            target_arch = "loongarch64"
+        )))]
+        const SYS_MEMBARRIER: Option<c_long> = None;
+        const MEMBARRIER_CMD_PRIVATE_EXPEDITED: c_int = 1 << 3;
+        const MEMBARRIER_CMD_PRIVATE_EXPEDITED_SYNC_CORE: c_int = 1 << 5;
+        const UNKNOWN: i32 = -1;
+        const UNSUPPORTED: i32 = 0;
+        /// Command this process registered for, as registering is needed once before use.
+        static COMMAND: AtomicI32 = AtomicI32::new(UNKNOWN);
+        let Some(sys_membarrier) = SYS_MEMBARRIER else {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::Unsupported,
+                "membarrier is not supported on this architecture",
+            ));
+        };
+        let membarrier = |command: c_int| unsafe { syscall(sys_membarrier, command, 0, 0) };
+        let mut command = COMMAND.load(Ordering::Acquire);
+        if command == UNKNOWN {
+            // Registration commands are the next bit after the commands they register for.
+            command = [
+                MEMBARRIER_CMD_PRIVATE_EXPEDITED_SYNC_CORE,
+                MEMBARRIER_CMD_PRIVATE_EXPEDITED,
+            ]
+            .into_iter()
+            .find(|&command| membarrier(command << 1) == 0)
+            .unwrap_or(UNSUPPORTED);
+            COMMAND.store(command, Ordering::Release);
+        }
+        if command == UNSUPPORTED {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::Unsupported,
+                "kernel does not support expedited membarrier",
+            ));
+        }
+        if membarrier(command) != 0 {
+            return Err(std::io::Error::last_os_error());
+        }
+        Ok(())
+    }
+    #[cfg(target_family = "windows")]
+    {
+        unsafe { winapi::um::processthreadsapi::FlushProcessWriteBuffers() };
+        Ok(())
+    }
+    #[cfg(not(any(target_os = "linux", target_family = "windows")))]
+    Err(std::io::Error::new(
+        std::io::ErrorKind::Unsupported,
+        "synchronizing code across threads is not supported on this platform",
+    ))
+}
+/// Makes instructions in `len` bytes starting at `ptr` visible to instruction fetches. The range must be mapped.
+#[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+pub(crate) fn flush_icache_range(_ptr: *const u8, _len: usize) {
+    // Instruction caches of x86 are coherent with data caches.
+}
+#[cfg(all(
+    target_arch = "aarch64",
+    not(any(target_family = "windows", target_vendor = "apple"))
+))]
+pub(crate) fn flush_icache_range(ptr: *const u8, len: usize) {
+    use std::arch::asm;
+    if len == 0 {
+        return;
+    }
+    // Cache line sizes, in 4-byte words, are encoded as log2 in the cache type register.
+    let ctr: u64;
+    unsafe { asm!("mrs {}, ctr_el0", out(reg) ctr, options(nomem, nostack)) };
+    let dline = 4 << ((ctr >> 16) & 0xF);
+    let iline = 4 << (ctr & 0xF);
+    let (start, end) = (ptr as usize, ptr as usize + len);
+    // Writes are cleaned to the point of unification, and only then stale instructions invalidated.
+    for line in (start & !(dline - 1)..end).step_by(dline) {
+        unsafe { asm!("dc cvau, {}", in(reg) line, options(nostack)) };
+    }
+    unsafe { asm!("dsb ish", options(nostack)) };
+    for line in (start & !(iline - 1)..end).step_by(iline) {
+        unsafe { asm!("ic ivau, {}", in(reg) line, options(nostack)) };
+    }
+    unsafe { asm!("dsb ish", "isb", options(nostack)) };
+}
+#[cfg(all(
+    target_vendor = "apple",
+    not(any(target_arch = "x86", target_arch = "x86_64"))
+))]
+pub(crate) fn flush_icache_range(ptr: *const u8, len: usize) {
+    extern "C" {
+        fn sys_icache_invalidate(start: *mut c_void, len: usize);
+    }
+    unsafe { sys_icache_invalidate(ptr.cast_mut().cast(), len) };
+}
+#[cfg(all(
+    target_family = "windows",
+    not(any(target_arch = "x86", target_arch = "x86_64"))
+))]
+pub(crate) fn flush_icache_range(ptr: *const u8, len: usize) {
+    use winapi::um::processthreadsapi::{FlushInstructionCache, GetCurrentProcess};
+    // Flushing only fails for invalid process handles, and the current one is always valid.
+    unsafe { FlushInstructionCache(GetCurrentProcess(), ptr.cast(), len) };
+}
+#[cfg(all(
+    target_family = "unix",
+    not(any(
+        target_arch = "x86",
+        target_arch = "x86_64",
+        target_arch = "aarch64",
+        target_vendor = "apple"
+    ))
+))]
+pub(crate) fn flush_icache_range(ptr: *const u8, len: usize) {
+    // Provided by compiler runtime libraries on every architecture that needs it.
+    extern "C" {
+        fn __clear_cache(start: *mut c_char, end: *mut c_char);
+    }
+    let start = ptr.cast_mut().cast::<c_char>();
+    unsafe { __clear_cache(start, start.wrapping_add(len)) };
+}
+#[cfg(test)]
+mod test {
+    use super::*;
+    #[test]
+    fn test_flush_icache_bounds() {
+        let pages: Pages<AllowRead, AllowWrite, DenyExec> = Pages::new(0x3000);
+        pages.flush_icache(0..0x3000);
+        pages.flush_icache(0x1234..0x1234);
+        let res = std::panic::catch_unwind(|| pages.flush_icache(0x2000..0x3001));
+        assert!(res.is_err());
+    }
+    #[test]
+    #[cfg(any(target_os = "linux", target_family = "windows"))]
+    fn test_sync_code_across_threads() {
+        // Registration happens on the first call only.
+        sync_code_across_threads().unwrap();
+        sync_code_across_threads().unwrap();
+    }
+}
@@ -0,0 +1,362 @@
+use crate::dyn_pages::protect_range_unchecked;
+use crate::*;
+use std::collections::HashMap;
+use std::fmt::{Debug, Formatter};
+/// Smallest block handed out by a [`JitAllocator`]. Blocks are powers of two, at least this large.
+const MIN_BLOCK: usize = 16;
+/// Instruction which traps when executed, repeated to fill unused code.
+#[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+const TRAP: [u8; 4] = [0xCC; 4];
+/// Instruction which traps when executed, repeated to fill unused code.
+#[cfg(target_arch = "aarch64")]
+const TRAP: [u8; 4] = 0xD420_0000_u32.to_le_bytes();
+/// Instruction which traps when executed, repeated to fill unused code.
+#[cfg(not(any(target_arch = "x86", target_arch = "x86_64", target_arch = "aarch64")))]
+const TRAP: [u8; 4] = [0; 4];
+/// Fills `code`, which starts at `offset` from a 4-byte aligned address, with trap instructions.
+fn fill_trap(code: &mut [u8], offset: usize) {
+    for (i, byte) in code.iter_mut().enumerate() {
+        *byte = TRAP[(offset + i) % TRAP.len()];
+    }
+}
+/// Code allocated by a [`JitAllocator`], which can be freed using [`JitAllocator::free`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct JitAllocation {
+    chunk: usize,
+    offset: usize,
+    len: usize,
+}
+impl JitAllocation {
+    /// Returns the length of the allocated code.
+    #[must_use]
+    pub fn len(&self) -> usize {
+        self.len
+    }
+    /// Checks if the allocated code is empty. Always false, since empty code can't be allocated.
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+}
+/// A single executable mapping, blocks of which are handed out.
+struct Chunk {
+    pages: Pages<AllowRead, DenyWrite, AllowExec>,
+    /// Offset past the last block ever carved out of this chunk.
+    bump: usize,
+    /// Total size of blocks currently in use.
+    used: usize,
+}
+/// Allocator of many small pieces of code inside a few executable mappings, for JITs which compile, and later free,
+/// many functions. Allocating whole [`Pages`] for each function wastes most of their memory, and needs a system call
+/// per function.
+///
+/// Code is placed in power-of-two sized blocks, aligned to their size (or to the page size, for larger blocks). Freed
+/// blocks are kept on a free list for their size, and reused by later allocations of the same size. Bytes of a block
+/// past the end of its code are filled with trap instructions, as are freed blocks, so stray jumps into them crash
+/// instead of executing stale code. Freed blocks spanning whole pages are decommitted and made inaccessible instead,
+/// and are only filled again once they are reused. Mappings with no code left in them are unmapped.
+///
+/// Mappings are made writable (and not executable) while code is written into or freed from them, so functions
+/// from this allocator must not run on other threads during [`Self::allocate`] or [`Self::free`]. Borrow rules prevent
+/// that as long as functions are only called through [`FnRef`]s from [`Self::get_fn`].
+/// # Examples
+/// ```
+/// # use memory_pages::*;
+/// let mut allocator = JitAllocator::new();
+/// // X86_64 assembly for `mov eax, 7; ret`.
+/// let seven = allocator.allocate(&[0xB8, 7, 0, 0, 0, 0xC3], 16);
+/// # #[cfg(target_arch = "x86_64")]
+/// # {
+/// let f: FnRef<unsafe extern "C" fn() -> u32> = unsafe { allocator.get_fn(seven) };
+/// assert_eq!(unsafe { f.call(()) }, 7);
+/// # }
+/// allocator.free(seven);
+/// assert_eq!(allocator.mapped_len(), 0);
+/// ```
+pub struct JitAllocator {
+    chunks: Vec<Option<Chunk>>,
+    /// Free blocks of each size class, as chunk indices and offsets. Blocks of class `n` are `MIN_BLOCK << n` bytes long.
+    free_lists: Vec<Vec<(usize, usize)>>,
+    /// Size classes of allocated blocks, keyed by their chunk indices and offsets.
+    live: HashMap<(usize, usize), u32>,
+    chunk_size: usize,
+}
+impl JitAllocator {
+    /// Creates a new [`JitAllocator`], which maps 64 KiB of executable memory at a time.
+    #[must_use]
+    pub fn new() -> Self {
+        Self::with_chunk_size(0x10000)
+    }
+    /// Creates a new [`JitAllocator`], which maps `chunk_size` bytes of executable memory at a time. Code larger than
+    /// that gets a mapping of its own.
+    /// # Panics
+    /// Panics if `chunk_size` is 0.
+    #[must_use]
+    pub fn with_chunk_size(chunk_size: usize) -> Self {
+        assert!(chunk_size > 0, "chunk size can't be 0!");
+        Self {
+            chunks: Vec::new(),
+            free_lists: Vec::new(),
+            live: HashMap::new(),
+            chunk_size,
+        }
+    }
+    /// Copies `code` into executable memory, at an address aligned to `align`, and flushes the instruction cache. The
+    /// start of the code is registered with Control Flow Guard, as described in [`Pages::set_valid_call_targets`].
+    /// # Panics
+    /// Panics if `code` is empty, if `align` is not a power of two or is larger than a page, if the installed
+    /// [`CodeVerifier`] rejects the mapping holding the code, or if kernel can't/refuses to allocate or change
+    /// protection of pages.
+    pub fn allocate(&mut self, code: &[u8], align: usize) -> JitAllocation {
+        assert!(!code.is_empty(), "can't allocate empty code!");
+        assert!(align.is_power_of_two(), "alignment must be a power of two!");
+        assert!(
+            align <= page_math::PAGE_SIZE,
+            "alignment can't be larger than a page!"
+        );
+        let block = code
+            .len()
+            .max(align)
+            .max(MIN_BLOCK)
+            .checked_next_power_of_two()
+            .unwrap_or_else(|| panic!("Capacity of JitAllocator overflowed!"));
+        let class = block.trailing_zeros() - MIN_BLOCK.trailing_zeros();
+        let (chunk, offset) = self
+            .free_lists
+            .get_mut(class as usize)
+            .and_then(Vec::pop)
+            .unwrap_or_else(|| self.carve(block));
+        let chunk_ref = self.chunks[chunk]
+            .as_mut()
+            .expect("free blocks are in live chunks");
+        chunk_ref.pages.with_writable(|memory| {
+            let (used, padding) = memory[offset..offset + block].split_at_mut(code.len());
+            used.copy_from_slice(code);
+            fill_trap(padding, offset + code.len());
+        });
+        // Blocks are aligned to at least 16 bytes, as call targets must be.
+        if let Err(err) = chunk_ref.pages.set_valid_call_targets(&[offset]) {
+            panic!("Registering call target failed:'{err}'!");
+        }
+        chunk_ref.used += block;
+        self.protect_decommitted(chunk);
+        self.live.insert((chunk, offset), class);
+        JitAllocation {
+            chunk,
+            offset,
+            len: code.len(),
+        }
+    }
+    /// Returns a block of `block` bytes which was never used before, mapping a new chunk if no chunk has room for it.
+    fn carve(&mut self, block: usize) -> (usize, usize) {
+        let align = block.min(page_math::PAGE_SIZE);
+        for (index, chunk) in self.chunks.iter_mut().enumerate() {
+            let Some(chunk) = chunk else { continue };
+            let offset = chunk.bump.next_multiple_of(align);
+            if offset + block <= chunk.pages.len() {
+                chunk.bump = offset + block;
+                return (index, offset);
+            }
+        }
+        let mut pages: Pages<AllowRead, AllowWrite, DenyExec> =
+            Pages::new(self.chunk_size.max(block));
+        fill_trap(&mut pages, 0);
+        let chunk = Chunk {
+            pages: pages.set_protected_exec(),
+            bump: block,
+            used: 0,
+        };
+        // Slots of unmapped chunks are reused, so indices stay small.
+        let index = if let Some(index) = self.chunks.iter().position(Option::is_none) {
+            self.chunks[index] = Some(chunk);
+            index
+        } else {
+            self.chunks.push(Some(chunk));
+            self.chunks.len() - 1
+        };
+        (index, 0)
+    }
+    /// Frees code in `allocation`, filling it with trap instructions. Blocks spanning whole pages are decommitted first.
+    /// Unmaps the mapping the code was in, if no other code is left in it.
+    /// # Panics
+    /// Panics if `allocation` was already freed, or was made by another [`JitAllocator`], or if kernel can't/refuses to
+    /// change protection of pages.
+    pub fn free(&mut self, allocation: JitAllocation) {
+        let class = self
+            .live
+            .remove(&(allocation.chunk, allocation.offset))
+            .unwrap_or_else(|| panic!("{allocation:?} is not allocated by this JitAllocator!"));
+        let block = MIN_BLOCK << class;
+        let slot = &mut self.chunks[allocation.chunk];
+        let chunk = slot.as_mut().expect("allocations are in live chunks");
+        chunk.used -= block;
+        if chunk.used == 0 {
+            *slot = None;
+            for list in &mut self.free_lists {
+                list.retain(|(chunk, _)| *chunk != allocation.chunk);
+            }
+            return;
+        }
+        if block >= page_math::PAGE_SIZE {
+            // Blocks this large are page aligned, so no other code shares their pages. Writing trap instructions would
+            // commit the pages again, so they are made inaccessible instead, which traps just as well.
+            chunk.pages.decommit(allocation.offset, block);
+        } else {
+            chunk.pages.with_writable(|memory| {
+                let offset = allocation.offset;
+                fill_trap(&mut memory[offset..offset + block], offset);
+            });
+        }
+        let class = class as usize;
+        if self.free_lists.len() <= class {
+            self.free_lists.resize_with(class + 1, Vec::new);
+        }
+        self.free_lists[class].push((allocation.chunk, allocation.offset));
+        self.protect_decommitted(allocation.chunk);
+    }
+    /// Makes all free, decommitted blocks of chunk `index` inaccessible. Making the chunk writable and then executable
+    /// again changes protection of all of its pages, so this is needed after each change.
+    /// # Panics
+    /// Panics if kernel can't/refuses to change protection of the pages.
+    fn protect_decommitted(&self, index: usize) {
+        let Some(chunk) = &self.chunks[index] else {
+            return;
+        };
+        let first_decommitted = (page_math::PAGE_SIZE / MIN_BLOCK).trailing_zeros() as usize;
+        for (class, list) in self.free_lists.iter().enumerate().skip(first_decommitted) {
+            let block = MIN_BLOCK << class;
+            for &(_, offset) in list.iter().filter(|(chunk, _)| *chunk == index) {
+                let ptr = chunk.pages.get_ptr_unchecked().wrapping_add(offset);
+                if let Err(err) = protect_range_unchecked(ptr, block, Protection::NoAccess) {
+                    panic!("Failed to change memory protection mode:'{err}'!");
+                }
+            }
+        }
+    }
+    /// Returns the code in `allocation`.
+    /// # Panics
+    /// Panics if `allocation` was freed, or was made by another [`JitAllocator`].
+    #[must_use]
+    pub fn code(&self, allocation: JitAllocation) -> &[u8] {
+        let pages = self.pages(allocation);
+        &pages.deref()[allocation.offset..allocation.offset + allocation.len]
+    }
+    /// Gets a function whose entry point is the start of `allocation`. Function must be an `extern "C" fn`.
+    /// # Safety
+    /// The allocated code must represent native instructions creating a function with a matching signature to function
+    /// pointer type F.
+    /// # Panics
+    /// Panics if `allocation` was freed, or was made by another [`JitAllocator`].
+    #[must_use]
+    pub unsafe fn get_fn<F>(&self, allocation: JitAllocation) -> FnRef<'_, F>
+    where
+        F: ExternFnPtr + Copy + std::fmt::Pointer + Sized,
+    {
+        self.pages(allocation).get_fn(allocation.offset)
+    }
+    /// Returns the number of allocations which were not freed yet.
+    #[must_use]
+    pub fn allocation_count(&self) -> usize {
+        self.live.len()
+    }
+    /// Returns the total length of executable mappings of this [`JitAllocator`].
+    #[must_use]
+    pub fn mapped_len(&self) -> usize {
+        self.chunks
+            .iter()
+            .flatten()
+            .map(|chunk| chunk.pages.len())
+            .sum()
+    }
+    fn pages(&self, allocation: JitAllocation) -> &Pages<AllowRead, DenyWrite, AllowExec> {
+        assert!(
+            self.live
+                .contains_key(&(allocation.chunk, allocation.offset)),
+            "{allocation:?} is not allocated by this JitAllocator!"
+        );
+        &self.chunks[allocation.chunk]
+            .as_ref()
+            .expect("allocations are in live chunks")
+            .pages
+    }
+}
+impl Default for JitAllocator {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+impl Debug for JitAllocator {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("JitAllocator")
+            .field("allocations", &self.live.len())
+            .field("mapped_len", &self.mapped_len())
+            .finish()
+    }
+}
+#[cfg(test)]
+mod test {
+    use super::*;
+    #[test]
+    fn test_jit_allocator_reuse() {
+        let mut allocator = JitAllocator::with_chunk_size(0x1000);
+        // MOV EAX, imm32; RET
+        let ret = |value: u32| {
+            let mut code = vec![0xB8];
+            code.extend_from_slice(&value.to_le_bytes());
+            code.push(0xC3);
+            code
+        };
+        let functions: Vec<_> = (0..64).map(|i| allocator.allocate(&ret(i), 16)).collect();
+        // 64 functions of 16 bytes each fit in a single page.
+        assert_eq!(allocator.mapped_len(), 0x1000);
+        let large = allocator.allocate(&[0x90; 0x1800], 16);
+        assert_eq!(allocator.mapped_len(), 0x3000);
+        #[cfg(target_arch = "x86_64")]
+        for (i, &function) in functions.iter().enumerate() {
+            let f: FnRef<unsafe extern "C" fn() -> u32> = unsafe { allocator.get_fn(function) };
+            assert_eq!(unsafe { f.call(()) }, i as u32);
+        }
+        let freed = functions[3];
+        allocator.free(freed);
+        let reused = allocator.allocate(&ret(100), 1);
+        assert_eq!(
+            allocator.code(reused).as_ptr(),
+            unsafe { allocator.get_fn::<unsafe extern "C" fn()>(functions[2]) }
+                .address()
+                .cast::<u8>()
+                .wrapping_add(16)
+        );
+        // Rest of the block traps.
+        let padding = unsafe { std::slice::from_raw_parts(allocator.code(reused).as_ptr(), 16) };
+        assert!(padding[6..].iter().all(|&byte| byte == TRAP[0]));
+        allocator.free(large);
+        assert_eq!(allocator.mapped_len(), 0x1000);
+        allocator.free(reused);
+        for &function in functions.iter().filter(|&&f| f != freed) {
+            allocator.free(function);
+        }
+        assert_eq!(allocator.allocation_count(), 0);
+        assert_eq!(allocator.mapped_len(), 0);
+    }
+    #[test]
+    fn test_jit_allocator_reuses_decommitted() {
+        let mut allocator = JitAllocator::with_chunk_size(0x10000);
+        let kept = allocator.allocate(&[0xC3], 16);
+        let large = allocator.allocate(&[0x90; 0x2000], 16);
+        allocator.free(large);
+        // Other blocks of the chunk can still be patched while the freed pages are inaccessible.
+        let small = allocator.allocate(&[0xC3], 16);
+        let reused = allocator.allocate(&[0x90; 0x1800], 16);
+        assert_eq!(reused.offset, large.offset);
+        let pages = &allocator.chunks[reused.chunk].as_ref().unwrap().pages;
+        let block = &pages.deref()[reused.offset..reused.offset + 0x2000];
+        assert!(block[..0x1800].iter().all(|&byte| byte == 0x90));
+        // Decommitted pages read back as zeroes, so the rest of the block must be filled with traps again.
+        assert!(block[0x1800..]
+            .chunks(4)
+            .all(|instruction| instruction == TRAP));
+        for allocation in [kept, small, reused] {
+            allocator.free(allocation);
+        }
+    }
+}
@@ -0,0 +1,253 @@
+use crate::*;
+use std::fmt::{Debug, Formatter};
+use std::io;
+use std::ops::Range;
+/// Memory for code emitted at runtime, mapped twice: once readable and writable, and once readable and executable.
+/// Both views share the same physical pages, so code written through [`Self::write`] can be executed through
+/// [`Self::get_fn`], without ever changing protection of either view. No address in the process is ever both writable
+/// and executable, and since the writable view is at an unrelated address, finding the executable code does not reveal
+/// where it can be modified.
+///
+/// Instruction cache is flushed each time [`Self::write`] returns, so newly written code is always visible to execution.
+/// # Examples
+/// ```
+/// # use memory_pages::*;
+/// let mut code = JitDualMap::new(0x1000);
+/// code.write(|code| {
+///     // X86_64 assembly instruction `RET`
+///     code[0] = 0xC3;
+/// });
+/// assert_eq!(code.executable()[0], 0xC3);
+/// assert_ne!(code.writable_ptr().cast_const(), code.executable_ptr());
+/// # #[cfg(target_arch = "x86_64")]
+/// # {
+/// let nop: FnRef<unsafe extern "C" fn()> = unsafe { code.get_fn(0) };
+/// unsafe { nop.call(()) };
+/// # }
+/// ```
+pub struct JitDualMap {
+    writable: NonNull<u8>,
+    executable: NonNull<u8>,
+    len: usize,
+}
+// `JitDualMap` uniquely owns both of its views, like `Pages` do.
+unsafe impl Send for JitDualMap {}
+unsafe impl Sync for JitDualMap {}
+impl JitDualMap {
+    /// Allocates a new [`JitDualMap`] of size at least `length`, rounded up to the next page boundary.
+    /// # Panics
+    /// Panics when a 0-sized allocation is attempted, or if kernel can't/refuses to create or map the shared memory
+    /// object.
+    #[must_use]
+    pub fn new(length: usize) -> Self {
+        assert_ne!(length, 0, "0 - sized allcations are not allowed!");
+        Self::map(page_math::align_up(length))
+            .unwrap_or_else(|err| panic!("Allocating JitDualMap failed:'{err}'!"))
+    }
+    #[cfg(target_family = "unix")]
+    fn map(len: usize) -> io::Result<Self> {
+        use std::os::unix::io::AsRawFd;
+        let file = crate::paged_deque::shared_memory()?;
+        file.set_len(len as u64)?;
+        let map = |prot| {
+            let ptr = unsafe {
+                mmap(
+                    std::ptr::null_mut(),
+                    len,
+                    prot,
+                    MAP_SHARED,
+                    file.as_raw_fd(),
+                    0,
+                )
+            };
+            if ptr == MAP_FAILED {
+                return Err(io::Error::last_os_error());
+            }
+            Ok(non_null(ptr))
+        };
+        let writable = map(AllowRead::bitmask() | AllowWrite::bitmask())?;
+        let executable = match map(branch_protection::with_bti(
+            AllowRead::bitmask() | AllowExec::bitmask(),
+        )) {
+            Ok(executable) => executable,
+            Err(err) => {
+                unsafe { munmap(writable.as_ptr().cast(), len) };
+                return Err(err);
+            }
+        };
+        // Views keep the shared memory object alive.
+        Ok(Self {
+            writable,
+            executable,
+            len,
+        })
+    }
+    #[cfg(target_family = "windows")]
+    fn map(len: usize) -> io::Result<Self> {
+        use winapi::um::handleapi::{CloseHandle, INVALID_HANDLE_VALUE};
+        use winapi::um::winnt::PAGE_EXECUTE_READWRITE;
+        let mapping = unsafe {
+            CreateFileMappingW(
+                INVALID_HANDLE_VALUE,
+                std::ptr::null_mut(),
+                PAGE_EXECUTE_READWRITE,
+                (len as u64 >> 32) as u32,
+                len as u32,
+                std::ptr::null(),
+            )
+        };
+        if mapping.is_null() {
+            return Err(io::Error::last_os_error());
+        }
+        let writable = unsafe { MapViewOfFile(mapping, FILE_MAP_WRITE, 0, 0, len) };
+        let executable =
+            unsafe { MapViewOfFile(mapping, FILE_MAP_READ | FILE_MAP_EXECUTE, 0, 0, len) };
+        let res = match (
+            NonNull::new(writable.cast()),
+            NonNull::new(executable.cast()),
+        ) {
+            (Some(writable), Some(executable)) => Ok(Self {
+                writable,
+                executable,
+                len,
+            }),
+            _ => {
+                let err = io::Error::last_os_error();
+                for view in [writable, executable] {
+                    if !view.is_null() {
+                        unsafe { UnmapViewOfFile(view) };
+                    }
+                }
+                Err(err)
+            }
+        };
+        // Views keep the mapping alive.
+        unsafe { CloseHandle(mapping) };
+        res
+    }
+    /// Returns the length of this [`JitDualMap`], in bytes.
+    #[must_use]
+    pub fn len(&self) -> usize {
+        self.len
+    }
+    /// Always returns false, because [`JitDualMap`] can't be empty.
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        false
+    }
+    /// Calls `f` with the writable view of this [`JitDualMap`], and then flushes instruction cache of the executable
+    /// view, even if `f` panics. Other threads running code in the executable view are then synchronized using
+    /// [`sync_code_across_threads`], so they never execute a mix of old and new instructions.
+    pub fn write<T>(&mut self, f: impl FnOnce(&mut [u8]) -> T) -> T {
+        /// Flushes instruction cache when dropped, also during unwinding.
+        struct Flush<'a>(&'a JitDualMap);
+        impl Drop for Flush<'_> {
+            fn drop(&mut self) {
+                self.0.flush_icache(0..self.0.len);
+                // Without kernel support, other threads see new code once they are interrupted, e.g. by the scheduler.
+                let _ = sync_code_across_threads();
+            }
+        }
+        let flush = Flush(self);
+        f(unsafe { std::slice::from_raw_parts_mut(flush.0.writable.as_ptr(), flush.0.len) })
+    }
+    /// Makes code written into `range` of the writable view visible to execution. Only needed after writing through
+    /// [`Self::writable_ptr`], since [`Self::write`] flushes the instruction cache on its own. If other threads may be
+    /// running the modified code, call [`sync_code_across_threads`] afterwards.
+    /// # Panics
+    /// Panics if `range` is out of bounds.
+    pub fn flush_icache(&self, range: Range<usize>) {
+        assert!(
+            range.start <= range.end && range.end <= self.len,
+            "range {range:?} out of bounds of {} bytes!",
+            self.len
+        );
+        flush_icache_range(
+            unsafe { self.executable.as_ptr().add(range.start) },
+            range.len(),
+        );
+    }
+    /// Returns the executable view of this [`JitDualMap`].
+    #[must_use]
+    pub fn executable(&self) -> &[u8] {
+        unsafe { std::slice::from_raw_parts(self.executable.as_ptr(), self.len) }
+    }
+    /// Returns a pointer to the beginning of the writable view. Code may be written through it while other threads
+    /// execute code in the executable view, if the caller ensures they never execute the bytes being written.
+    #[must_use]
+    pub fn writable_ptr(&self) -> *mut u8 {
+        self.writable.as_ptr()
+    }
+    /// Returns a pointer to the beginning of the executable view. The byte at offset `n` is the same as the byte at
+    /// offset `n` of the writable view.
+    #[must_use]
+    pub fn executable_ptr(&self) -> *const u8 {
+        self.executable.as_ptr()
+    }
+    /// Gets a pointer to function at offset in the executable view of this [`JitDualMap`]. Function must be an
+    /// `extern "C" fn`.
+    /// # Safety
+    /// The bytes at offset must represent native instructions creating a function with a matching signature to function pointer
+    /// type F.
+    /// # Panics
+    /// Will panic if offset larger than length.
+    #[must_use]
+    pub unsafe fn get_fn<F>(&self, offset: usize) -> FnRef<'_, F>
+    where
+        F: ExternFnPtr + Copy + std::fmt::Pointer + Sized,
+    {
+        let fn_ptr: *const () = std::ptr::addr_of!(self.executable()[offset]).cast();
+        let f: F = *(std::ptr::addr_of!(fn_ptr).cast::<F>());
+        FnRef::borrowing(f, self, offset, self.len)
+    }
+}
+impl Drop for JitDualMap {
+    #[cfg(target_family = "unix")]
+    fn drop(&mut self) {
+        for view in [self.writable, self.executable] {
+            if unsafe { munmap(view.as_ptr().cast(), self.len) } == -1 {
+                let err = errno_msg();
+                panic!("Unmapping JitDualMap failed:'{err}'!");
+            }
+        }
+    }
+    #[cfg(target_family = "windows")]
+    fn drop(&mut self) {
+        for view in [self.writable, self.executable] {
+            if unsafe { UnmapViewOfFile(view.as_ptr().cast()) } == 0 {
+                let err = unsafe { winapi::um::errhandlingapi::GetLastError() };
+                panic!("Unmapping JitDualMap failed with error code:{err}!");
+            }
+        }
+    }
+}
+impl Debug for JitDualMap {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("JitDualMap")
+            .field("writable", &self.writable)
+            .field("executable", &self.executable)
+            .field("len", &self.len)
+            .finish()
+    }
+}
+#[cfg(test)]
+mod test {
+    use super::*;
+    #[test]
+    fn test_dual_map_views_share_memory() {
+        let mut code = JitDualMap::new(0x2800);
+        assert_eq!(code.len(), 0x3000);
+        code.write(|code| code[0x2FFF] = 7);
+        assert_eq!(code.executable()[0x2FFF], 7);
+        unsafe { code.writable_ptr().add(0x1000).write(9) };
+        code.flush_icache(0x1000..0x1001);
+        assert_eq!(code.executable()[0x1000], 9);
+        // x86_64 assembly for `mov eax, 42; ret`.
+        #[cfg(target_arch = "x86_64")]
+        {
+            code.write(|code| code[..6].copy_from_slice(&[0xB8, 42, 0, 0, 0, 0xC3]));
+            let answer: FnRef<unsafe extern "C" fn() -> u32> = unsafe { code.get_fn(0) };
+            assert_eq!(unsafe { answer.call(()) }, 42);
+        }
+    }
+}
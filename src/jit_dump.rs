@@ -0,0 +1,241 @@
+use crate::gdb_jit::ELF_MACHINE;
+use crate::*;
+use std::fmt::{Debug, Formatter};
+use std::fs::File;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Mutex, OnceLock};
+extern "C" {
+    fn clock_gettime(clock: c_int, time: *mut [std::ffi::c_long; 2]) -> c_int;
+    fn gettid() -> c_int;
+}
+const CLOCK_MONOTONIC: c_int = 1;
+const JITDUMP_MAGIC: u32 = 0x4A69_5444;
+const JIT_CODE_LOAD: u32 = 0;
+const JIT_CODE_DEBUG_INFO: u32 = 2;
+/// Jitdump enabled with [`JitDump::enable_global`].
+static GLOBAL: OnceLock<JitDump> = OnceLock::new();
+/// Maps a position in jitted code to a line of source code it was compiled from.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct JitLineInfo<'a> {
+    /// Address of the first instruction compiled from the line.
+    pub address: *const u8,
+    /// Name of the source file.
+    pub file: &'a str,
+    /// Line inside of the source file, starting at 1.
+    pub line: u32,
+}
+/// A jitdump file, which records the code of jitted functions as it is loaded, together with optional line info.
+/// Running `perf inject --jit` on a profile recorded with `perf record -k mono` turns the dump into ELF files, so
+/// `perf report` and `perf annotate` show the names, disassembly and source lines of jitted functions.
+///
+/// `perf` notices the dump because the file is mapped as executable while it is written. Once
+/// [`Self::enable_global`] is called, each [`CodeBuffer::finalize`] records all functions named with
+/// [`CodeBuffer::name_function`] in that dump.
+/// # Examples
+/// ```
+/// # use memory_pages::*;
+/// let dump = JitDump::create(std::env::temp_dir()).unwrap();
+/// let mut code = JitPages::new(0x1000);
+/// code.jit_write_scope(|code| code[0] = 0xC3);
+/// let line = JitLineInfo {
+///     address: code.as_ptr(),
+///     file: "script.js",
+///     line: 1,
+/// };
+/// dump.record_load("jitted_nop", &code[..1], &[line]).unwrap();
+/// # std::fs::remove_file(dump.path()).unwrap();
+/// ```
+pub struct JitDump {
+    path: PathBuf,
+    /// File, and the index of the next loaded function.
+    file: Mutex<(File, u64)>,
+    /// Executable mapping of the file, which marks it for `perf`.
+    marker: NonNull<u8>,
+}
+// The marker mapping is never accessed.
+unsafe impl Send for JitDump {}
+unsafe impl Sync for JitDump {}
+impl JitDump {
+    /// Creates a jitdump file named `jit-<pid>.dump` in `dir`.
+    /// # Errors
+    /// Returns an error if the file can't be created, written or mapped.
+    pub fn create(dir: impl AsRef<Path>) -> io::Result<Self> {
+        use std::os::unix::io::AsRawFd;
+        let path = dir
+            .as_ref()
+            .join(format!("jit-{}.dump", std::process::id()));
+        let mut file = File::options()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(&path)?;
+        let mut header = Vec::with_capacity(40);
+        for field in [
+            JITDUMP_MAGIC,
+            1,
+            40,
+            u32::from(ELF_MACHINE),
+            0,
+            std::process::id(),
+        ] {
+            header.extend_from_slice(&field.to_ne_bytes());
+        }
+        header.extend_from_slice(&timestamp().to_ne_bytes());
+        header.extend_from_slice(&0_u64.to_ne_bytes());
+        file.write_all(&header)?;
+        let ptr = unsafe {
+            mmap(
+                std::ptr::null_mut(),
+                page_math::PAGE_SIZE,
+                AllowRead::bitmask() | AllowExec::bitmask(),
+                MAP_PRIVATE,
+                file.as_raw_fd(),
+                0,
+            )
+        };
+        if ptr == MAP_FAILED {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(Self {
+            path,
+            file: Mutex::new((file, 0)),
+            marker: non_null(ptr),
+        })
+    }
+    /// Enables recording of functions finalized by all [`CodeBuffer`]s in a jitdump in `dir`, and returns that dump.
+    /// Does nothing if recording is already enabled.
+    /// # Errors
+    /// Returns an error if the file can't be created, written or mapped.
+    pub fn enable_global(dir: impl AsRef<Path>) -> io::Result<&'static JitDump> {
+        if let Some(dump) = GLOBAL.get() {
+            return Ok(dump);
+        }
+        let dump = Self::create(dir)?;
+        // Another thread may have enabled recording in the meantime, in which case its dump is used.
+        Ok(GLOBAL.get_or_init(|| dump))
+    }
+    /// Returns the dump enabled with [`Self::enable_global`], or `None` if recording is not enabled.
+    #[must_use]
+    pub fn global() -> Option<&'static JitDump> {
+        GLOBAL.get()
+    }
+    /// Returns the path of this [`JitDump`].
+    #[must_use]
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+    /// Records that function `name`, whose code is `code`, was loaded at the address of `code`. `lines` map
+    /// instructions of the function to source lines, and may be empty.
+    /// # Errors
+    /// Returns an error if writing to the file fails.
+    pub fn record_load(&self, name: &str, code: &[u8], lines: &[JitLineInfo]) -> io::Result<()> {
+        let address = code.as_ptr() as u64;
+        let timestamp = timestamp();
+        let mut records = Vec::new();
+        if !lines.is_empty() {
+            let size = 32
+                + lines
+                    .iter()
+                    .map(|line| 16 + line.file.len() + 1)
+                    .sum::<usize>();
+            record_header(&mut records, JIT_CODE_DEBUG_INFO, size, timestamp);
+            records.extend_from_slice(&address.to_ne_bytes());
+            records.extend_from_slice(&(lines.len() as u64).to_ne_bytes());
+            for line in lines {
+                records.extend_from_slice(&(line.address as u64).to_ne_bytes());
+                records.extend_from_slice(&line.line.to_ne_bytes());
+                // Discriminator, distinguishing blocks on the same line.
+                records.extend_from_slice(&0_u32.to_ne_bytes());
+                records.extend_from_slice(line.file.as_bytes());
+                records.push(0);
+            }
+        }
+        let size = 56 + name.len() + 1 + code.len();
+        let mut file = self
+            .file
+            .lock()
+            .unwrap_or_else(std::sync::PoisonError::into_inner);
+        let (file, index) = &mut *file;
+        record_header(&mut records, JIT_CODE_LOAD, size, timestamp);
+        let tid = unsafe { gettid() } as u32;
+        records.extend_from_slice(&std::process::id().to_ne_bytes());
+        records.extend_from_slice(&tid.to_ne_bytes());
+        for field in [address, address, code.len() as u64, *index] {
+            records.extend_from_slice(&field.to_ne_bytes());
+        }
+        records.extend_from_slice(name.as_bytes());
+        records.push(0);
+        records.extend_from_slice(code);
+        file.write_all(&records)?;
+        *index += 1;
+        Ok(())
+    }
+}
+/// Appends the header of a record of type `id`, `size` bytes long including the header.
+fn record_header(records: &mut Vec<u8>, id: u32, size: usize, timestamp: u64) {
+    records.extend_from_slice(&id.to_ne_bytes());
+    records.extend_from_slice(&(size as u32).to_ne_bytes());
+    records.extend_from_slice(&timestamp.to_ne_bytes());
+}
+/// Returns the current time, using the same clock as `perf record -k mono`.
+fn timestamp() -> u64 {
+    let mut time = [0; 2];
+    unsafe { clock_gettime(CLOCK_MONOTONIC, &mut time) };
+    time[0] as u64 * 1_000_000_000 + time[1] as u64
+}
+impl Drop for JitDump {
+    fn drop(&mut self) {
+        if unsafe { munmap(self.marker.as_ptr().cast(), page_math::PAGE_SIZE) } == -1 {
+            let err = errno_msg();
+            panic!("Unmapping JitDump marker failed:'{err}'!");
+        }
+    }
+}
+impl Debug for JitDump {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("JitDump").field("path", &self.path).finish()
+    }
+}
+#[cfg(test)]
+mod test {
+    use super::*;
+    #[test]
+    fn test_jit_dump_records() {
+        let dir = std::env::temp_dir().join(format!("jit-dump-test-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let dump = JitDump::create(&dir).unwrap();
+        let code = [0x90, 0x90, 0xC3];
+        let line = JitLineInfo {
+            address: code[2..].as_ptr(),
+            file: "a.js",
+            line: 7,
+        };
+        dump.record_load("f", &code, &[line]).unwrap();
+        dump.record_load("g", &code[..1], &[]).unwrap();
+        let bytes = std::fs::read(dump.path()).unwrap();
+        let u32_at = |at: usize| u32::from_ne_bytes(bytes[at..at + 4].try_into().unwrap());
+        assert_eq!(u32_at(0), JITDUMP_MAGIC);
+        // Debug info comes before the load of the function it describes.
+        let mut offset = 40;
+        let mut records = Vec::new();
+        while offset < bytes.len() {
+            records.push((u32_at(offset), u32_at(offset + 4)));
+            offset += u32_at(offset + 4) as usize;
+        }
+        assert_eq!(offset, bytes.len());
+        assert_eq!(
+            records,
+            [
+                (JIT_CODE_DEBUG_INFO, 53),
+                (JIT_CODE_LOAD, 61),
+                (JIT_CODE_LOAD, 59)
+            ]
+        );
+        // Code bytes end the load record.
+        assert_eq!(bytes[40 + 53 + 58..40 + 53 + 61], code);
+        drop(dump);
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
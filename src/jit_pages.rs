@@ -0,0 +1,145 @@
+use crate::*;
+use std::fmt::{Debug, Formatter};
+#[cfg(target_os = "macos")]
+extern "C" {
+    fn pthread_jit_write_protect_np(enabled: c_int);
+}
+/// Executable [`Pages`] meant for code emitted at runtime, which can be written into only inside of
+/// [`Self::jit_write_scope`].
+///
+/// On macOS, the hardened runtime forbids changing protection of executable memory, so the pages are allocated with
+/// `MAP_JIT`, and writes are enabled only for the current thread, using `pthread_jit_write_protect_np`. Other threads
+/// can keep executing code inside the pages while it is being written. On all other systems, protection of the pages is
+/// flipped between readable and writable, and readable and executable, so they are never both writable and executable.
+///
+/// Instruction cache is flushed each time [`Self::jit_write_scope`] returns, so newly written code is always visible to
+/// execution.
+/// # Examples
+/// ```
+/// # use memory_pages::*;
+/// let mut code = JitPages::new(0x1000);
+/// code.jit_write_scope(|code| {
+///     // X86_64 assembly instruction `RET`
+///     code[0] = 0xC3;
+/// });
+/// assert_eq!(code[0], 0xC3);
+/// # #[cfg(target_arch = "x86_64")]
+/// # {
+/// let nop: FnRef<unsafe extern "C" fn()> = unsafe { code.get_fn(0) };
+/// unsafe { nop.call(()) };
+/// # }
+/// ```
+pub struct JitPages {
+    /// On macOS, the mapping is actually readable, writable and executable, but writes are disabled per thread.
+    pages: Pages<AllowRead, DenyWrite, AllowExec>,
+}
+impl JitPages {
+    /// Allocates new [`JitPages`] of size at least `length`, rounded up to the next page boundary.
+    /// # Panics
+    /// Panics when a 0-sized allocation is attempted, or if kernel can't/refuses to allocate executable pages.
+    #[must_use]
+    pub fn new(length: usize) -> Self {
+        #[cfg(target_os = "macos")]
+        let pages = Pages::<AllowRead, AllowWrite, AllowExec>::new_native(
+            length,
+            MapOptions {
+                jit: true,
+                ..MapOptions::default()
+            },
+        )
+        .retype();
+        #[cfg(not(target_os = "macos"))]
+        let pages = Pages::new(length);
+        Self { pages }
+    }
+    /// Returns the length of this [`JitPages`], in bytes.
+    #[must_use]
+    pub fn len(&self) -> usize {
+        self.pages.len()
+    }
+    /// Always returns false, because [`JitPages`] can't be empty.
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        false
+    }
+    /// Makes this [`JitPages`] writable by the current thread, calls `f` with their contents, and then makes them
+    /// executable again, even if `f` panics. Instruction cache is flushed afterwards.
+    /// # Panics
+    /// Panics if kernel can't/refuses to change protection of this [`JitPages`].
+    pub fn jit_write_scope<T>(&mut self, f: impl FnOnce(&mut [u8]) -> T) -> T {
+        #[cfg(target_os = "macos")]
+        {
+            /// Re-enables write protection of the current thread when dropped, also during unwinding.
+            struct WriteProtect<'a>(&'a JitPages);
+            impl Drop for WriteProtect<'_> {
+                fn drop(&mut self) {
+                    unsafe { pthread_jit_write_protect_np(1) };
+                    flush_icache_range(self.0.pages.ptr.as_ptr(), self.0.pages.len);
+                }
+            }
+            unsafe { pthread_jit_write_protect_np(0) };
+            let guard = WriteProtect(self);
+            let data = unsafe {
+                std::slice::from_raw_parts_mut(guard.0.pages.ptr.as_ptr(), guard.0.len())
+            };
+            f(data)
+        }
+        // `WriteGuard` flushes instruction cache when restoring execution.
+        #[cfg(not(target_os = "macos"))]
+        self.pages.with_writable(f)
+    }
+    /// Gets a pointer to function at offset in [`JitPages`]. Function must be an `extern "C" fn`.
+    /// # Safety
+    /// The bytes at offset must represent native instructions creating a function with a matching signature to function pointer
+    /// type F.
+    /// # Panics
+    /// Will panic if offset larger than length.
+    #[must_use]
+    pub unsafe fn get_fn<F>(&self, offset: usize) -> FnRef<'_, F>
+    where
+        F: ExternFnPtr + Copy + std::fmt::Pointer + Sized,
+    {
+        self.pages.get_fn(offset)
+    }
+}
+impl Deref for JitPages {
+    type Target = [u8];
+    fn deref(&self) -> &[u8] {
+        &self.pages
+    }
+}
+impl Debug for JitPages {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("JitPages")
+            .field("ptr", &self.pages.ptr)
+            .field("len", &self.pages.len)
+            .finish()
+    }
+}
+#[cfg(test)]
+mod test {
+    use super::*;
+    #[test]
+    fn test_jit_write_scope() {
+        let mut code = JitPages::new(0x1800);
+        assert_eq!(code.len(), 0x2000);
+        let res = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+            code.jit_write_scope(|code| {
+                code[0x1FFF] = 1;
+                panic!("emitting failed");
+            })
+        }));
+        assert!(res.is_err());
+        // Pages are executable again after a panic, and keep what was written.
+        #[cfg(target_arch = "x86_64")]
+        {
+            let written = code.jit_write_scope(|code| {
+                code[0] = 0xC3;
+                code[0x1FFF]
+            });
+            assert_eq!(written, 1);
+            let nop: FnRef<unsafe extern "C" fn()> = unsafe { code.get_fn(0) };
+            unsafe { nop.call(()) };
+        }
+    }
+}
@@ -1,57 +1,327 @@
 #![cfg_attr(feature = "fn_traits", feature(fn_traits))]
 #![cfg_attr(feature = "fn_traits", feature(unboxed_closures))]
+#![cfg_attr(feature = "allocator_api", feature(allocator_api))]
 //! `memory_pages` is a small crate providing a cross-platform API to request pages from kernel with certain permission modes
 //! set(read,write,execute). It provides an very safe API to aid in many use cases, mainly:
 //! 1. Speeds up operating on large data sets: [`PagedVec`] provides allocation speed advantages over standard [`Vec`] for large data.
-//! types.
+//!    types.
 //! 2. Page alignment guarantee. Since the API returns memory pages, the first address inside [`Pages`] must be aligned to a page boundary. This means, that with a bit of careful selection of type sizes(powers of 2), a substantial speedup can be occurred(structures can be guaranteed to always reside entirely within 1 page). Those sorts of guarantees are not normally given by allocators.
 //! 3. Simplifies dealing with page permissions and allows for additional levels of safety: Pages with [`DenyWrite`] cannot be
-//! written into without their permissions being changed, which allows for certain kinds of bugs to cause segfaults insted of overwriting data.
+//!    written into without their permissions being changed, which allows for certain kinds of bugs to cause segfaults insted of overwriting data.
 //! 4. Simplifies JITs - while dealing with memory pages is simple compared to difficulty of the task, which is writing a
-//! Just-In-Time compiler, this crate abstracts the platform specific differences away and adds additional measures to prevent
-//! some security issues, allowing you to focus on writing the compiler itself, without worrying about those low-level details.
+//!    Just-In-Time compiler, this crate abstracts the platform specific differences away and adds additional measures to prevent
+//!    some security issues, allowing you to focus on writing the compiler itself, without worrying about those low-level details.
 //! # Features
 //! `allow_exec` - this feature allows access to everything related to executing code inside allocated pages. Off by default.
 //! `deny_xw` - default feature that prevents allowing both `eXecution` and `Write` permissions on a page. This is an additional security feature that prevents accidental misuse of the API-s locked behind `allow_exec` feature. Does noting without it, but is really usefull when `allow_exec` enabled.
+//! `asan` - poisons unused capacity of [`PagedVec`] and slack of [`CanaryPages`] using AddressSanitizer, so that accesses to it are reported. Requires building with `-Zsanitizer=address`.
+//! `fn_traits` - implements [`Fn`] for [`SafeFnRef`], so jitted functions can be passed to code expecting closures. Requires a nightly compiler.
+//! `allocator_api` - provides [`PageAllocator`], an implementation of the unstable `Allocator` trait. Requires a nightly compiler.
+//! `require_code_verifier` - forbids making any code executable until a [`CodeVerifier`] is installed using [`set_code_verifier`].
+//! `valgrind` - marks the same memory as `asan` inaccessible using Valgrind client requests. Only supported on `x86_64`, does nothing on other architectures.
 #![warn(missing_docs)]
 #![warn(rustdoc::missing_doc_code_examples)]
 
+mod access_guard;
+mod arena_registry;
+mod batch_pages;
+pub mod branch_protection;
+#[cfg(all(
+    any(feature = "allow_exec", doc, test),
+    any(target_arch = "x86_64", target_arch = "aarch64")
+))]
+mod breakpoint;
+mod byte_ring;
+mod canary_pages;
+#[cfg(any(feature = "allow_exec", doc, test))]
+mod code_buffer;
+#[cfg(any(feature = "allow_exec", doc, test))]
+mod code_digest;
+#[cfg(any(feature = "allow_exec", doc, test))]
+mod code_loader;
+#[cfg(any(feature = "allow_exec", doc, test))]
+mod code_symbols;
+#[cfg(any(feature = "allow_exec", doc, test))]
+mod code_verifier;
+mod concurrent_paged_vec;
+#[cfg(any(feature = "allow_exec", doc, test))]
+mod control_flow_guard;
+mod dyn_pages;
+#[cfg(any(feature = "allow_exec", doc, test))]
+mod exec_sealed;
 #[cfg(any(feature = "allow_exec", doc, test))]
 mod extern_fn_ptr;
+#[cfg(any(target_os = "linux", target_os = "macos", target_family = "windows"))]
+mod fault_trap;
+mod file_pages;
+mod fork;
+mod frozen_pages;
+#[cfg(any(feature = "allow_exec", doc, test))]
+mod gdb_jit;
+mod guard_pages;
+mod icache;
+mod page_aligned;
+#[cfg(feature = "allocator_api")]
+mod page_allocator;
+mod page_arena;
+pub mod page_math;
+mod paged_b_tree_map;
+mod paged_bit_set;
+mod paged_cow;
+mod paged_deque;
+mod paged_gen_arena;
+mod paged_hash_map;
+mod paged_log;
+mod paged_matrix;
+mod paged_queue;
+mod paged_slab;
+mod paged_stack;
+mod paged_string;
 mod paged_vec;
+mod paged_vec_builder;
+mod pages_global_alloc;
+#[cfg(any(feature = "allow_exec", doc, test))]
+mod perf_map;
+mod persistent_paged_vec;
+mod pod;
+mod prefetch;
+#[cfg(feature = "rayon")]
+mod rayon_impl;
+mod sanitizer;
+pub mod secure;
+mod segmented_paged_vec;
+#[cfg(feature = "serde")]
+mod serde_impl;
+mod shared_queue;
+mod sparse_pages;
+mod stable_paged_vec;
+mod string_interner;
+#[cfg(all(
+    any(feature = "allow_exec", doc, test),
+    any(target_arch = "x86_64", target_arch = "aarch64")
+))]
+mod trampoline;
+#[cfg(all(
+    any(feature = "allow_exec", doc, test),
+    any(target_family = "unix", target_arch = "x86_64")
+))]
+mod unwind_registration;
+mod write_combined_pages;
+#[cfg(any(target_os = "linux", target_os = "macos", target_family = "windows"))]
+mod write_trace;
+mod zeroable;
 #[cfg(any(feature = "allow_exec", doc, test))]
 use core::fmt::Pointer;
 #[cfg(any(feature = "allow_exec", doc, test))]
 mod fn_ref;
 #[cfg(any(feature = "allow_exec", doc, test))]
+mod jit_allocator;
+#[cfg(any(feature = "allow_exec", doc, test))]
+mod jit_dual_map;
+#[cfg(all(any(feature = "allow_exec", doc, test), target_os = "linux"))]
+mod jit_dump;
+#[cfg(any(feature = "allow_exec", doc, test))]
+mod jit_pages;
+#[doc(inline)]
+pub use access_guard::*;
+#[doc(inline)]
+pub use arena_registry::*;
+use batch_pages::BatchRegion;
+#[doc(inline)]
+pub use byte_ring::*;
+#[doc(inline)]
+pub use canary_pages::*;
+#[doc(inline)]
+#[cfg(any(feature = "allow_exec", doc, test))]
+pub use code_buffer::*;
+#[doc(inline)]
+#[cfg(any(feature = "allow_exec", doc, test))]
+pub use code_digest::*;
+#[doc(inline)]
+#[cfg(any(feature = "allow_exec", doc, test))]
+pub use code_loader::*;
+#[doc(inline)]
+#[cfg(any(feature = "allow_exec", doc, test))]
+pub use code_symbols::*;
+#[doc(inline)]
+#[cfg(any(feature = "allow_exec", doc, test))]
+pub use code_verifier::*;
+#[doc(inline)]
+pub use concurrent_paged_vec::*;
+#[doc(inline)]
+#[cfg(any(feature = "allow_exec", doc, test))]
+pub use control_flow_guard::*;
+#[doc(inline)]
+pub use dyn_pages::*;
+#[doc(inline)]
+#[cfg(any(feature = "allow_exec", doc, test))]
+pub use exec_sealed::*;
+#[cfg(any(feature = "allow_exec", doc, test))]
 use extern_fn_ptr::ExternFnPtr;
 #[doc(inline)]
+#[cfg(any(target_os = "linux", target_os = "macos", target_family = "windows"))]
+pub use fault_trap::*;
+#[doc(inline)]
+pub use file_pages::*;
+#[doc(inline)]
 #[cfg(any(feature = "allow_exec", doc, test))]
 pub use fn_ref::*;
 #[doc(inline)]
+#[cfg(target_family = "unix")]
+pub use fork::*;
+#[doc(inline)]
+pub use frozen_pages::*;
+#[doc(inline)]
+#[cfg(any(feature = "allow_exec", doc, test))]
+pub use gdb_jit::*;
+#[doc(inline)]
+pub use guard_pages::*;
+use icache::flush_icache_range;
+#[doc(inline)]
+pub use icache::sync_code_across_threads;
+#[doc(inline)]
+#[cfg(any(feature = "allow_exec", doc, test))]
+pub use jit_allocator::*;
+#[doc(inline)]
+#[cfg(any(feature = "allow_exec", doc, test))]
+pub use jit_dual_map::*;
+#[doc(inline)]
+#[cfg(all(any(feature = "allow_exec", doc, test), target_os = "linux"))]
+pub use jit_dump::*;
+#[doc(inline)]
+#[cfg(any(feature = "allow_exec", doc, test))]
+pub use jit_pages::*;
+#[doc(inline)]
+pub use page_aligned::*;
+#[doc(inline)]
+#[cfg(feature = "allocator_api")]
+pub use page_allocator::*;
+#[doc(inline)]
+pub use page_arena::*;
+use page_math::align_up;
+#[doc(inline)]
+pub use paged_b_tree_map::*;
+#[doc(inline)]
+pub use paged_bit_set::*;
+#[doc(inline)]
+pub use paged_cow::*;
+#[doc(inline)]
+pub use paged_deque::*;
+#[doc(inline)]
+pub use paged_gen_arena::*;
+#[doc(inline)]
+pub use paged_hash_map::*;
+#[doc(inline)]
+pub use paged_log::*;
+#[doc(inline)]
+pub use paged_matrix::*;
+#[doc(inline)]
+pub use paged_queue::*;
+#[doc(inline)]
+pub use paged_slab::*;
+#[doc(inline)]
+pub use paged_stack::*;
+#[doc(inline)]
+pub use paged_string::*;
+#[doc(inline)]
 pub use paged_vec::*;
+#[doc(inline)]
+pub use paged_vec_builder::*;
+#[doc(inline)]
+pub use pages_global_alloc::*;
+#[doc(inline)]
+#[cfg(any(feature = "allow_exec", doc, test))]
+pub use perf_map::*;
+#[doc(inline)]
+pub use persistent_paged_vec::*;
+#[doc(inline)]
+pub use pod::*;
+#[doc(inline)]
+pub use prefetch::*;
+#[doc(inline)]
+pub use segmented_paged_vec::*;
+#[doc(inline)]
+pub use shared_queue::*;
+#[doc(inline)]
+pub use sparse_pages::*;
+#[doc(inline)]
+pub use stable_paged_vec::*;
 use std::borrow::{Borrow, BorrowMut};
 use std::marker::PhantomData;
 use std::ops::{Deref, DerefMut};
+use std::ptr::NonNull;
+#[doc(inline)]
+pub use string_interner::*;
+#[doc(inline)]
+#[cfg(all(
+    any(feature = "allow_exec", doc, test),
+    any(target_arch = "x86_64", target_arch = "aarch64")
+))]
+pub use trampoline::*;
+#[doc(inline)]
+#[cfg(all(
+    any(feature = "allow_exec", doc, test),
+    any(target_family = "unix", target_arch = "x86_64")
+))]
+pub use unwind_registration::*;
 #[cfg(target_family = "windows")]
 use winapi::um::memoryapi::*;
 #[cfg(target_family = "windows")]
 use winapi::um::winnt::{
-    MEM_COMMIT, MEM_RELEASE, PAGE_EXECUTE, PAGE_EXECUTE_READ, PAGE_EXECUTE_READWRITE,
-    PAGE_NOACCESS, PAGE_READONLY, PAGE_READWRITE,
+    MEM_COMMIT, MEM_DECOMMIT, MEM_RELEASE, MEM_RESERVE, PAGE_EXECUTE, PAGE_EXECUTE_READ,
+    PAGE_EXECUTE_READWRITE, PAGE_NOACCESS, PAGE_READONLY, PAGE_READWRITE, PAGE_WRITECOMBINE,
 };
-const fn next_page_boundary(size: usize) -> usize {
-    ((size + PAGE_SIZE - 1) / PAGE_SIZE) * PAGE_SIZE
-}
-const PAGE_SIZE: usize = 0x1000;
+#[doc(inline)]
+pub use write_combined_pages::*;
+#[doc(inline)]
+#[cfg(any(target_os = "linux", target_os = "macos", target_family = "windows"))]
+pub use write_trace::*;
+#[doc(inline)]
+pub use zeroable::*;
 #[cfg(target_family = "unix")]
 const MAP_ANYNOMUS: c_int = 0x20;
+/// Value returned by `mmap` and `mremap` on failure.
+#[cfg(target_family = "unix")]
+const MAP_FAILED: *mut c_void = usize::MAX as *mut c_void;
+/// Converts a pointer returned by a successful `mmap` or `mremap` call into [`NonNull`].
+#[cfg(target_family = "unix")]
+fn non_null(ptr: *mut c_void) -> NonNull<u8> {
+    // Kernel never places mappings at address 0 unless explicitly asked to.
+    NonNull::new(ptr.cast::<u8>()).expect("mmap returned a null pointer!")
+}
+#[cfg(target_family = "unix")]
+const MAP_SHARED: c_int = 0x1;
 #[cfg(target_family = "unix")]
 const MAP_PRIVATE: c_int = 0x2;
 #[cfg(target_family = "unix")]
 const NO_FILE: c_int = -1;
+#[cfg(target_os = "linux")]
+const MAP_NORESERVE: c_int = 0x4000;
+// Other systems do not reserve swap for mappings ahead of time, so there is nothing to opt out of.
+#[cfg(all(target_family = "unix", not(target_os = "linux")))]
+const MAP_NORESERVE: c_int = 0x0;
+#[cfg(target_os = "macos")]
+const MAP_JIT: c_int = 0x800;
+// Only macOS requires executable memory to be specially marked.
+#[cfg(all(target_family = "unix", not(target_os = "macos")))]
+const MAP_JIT: c_int = 0x0;
+/// Extra options used when mapping new [`Pages`].
+#[derive(Clone, Copy, Default)]
+struct MapOptions {
+    /// Pages are shared with forked child processes.
+    #[cfg_attr(target_family = "windows", allow(dead_code))]
+    shared: bool,
+    /// Pages do not reserve swap space ahead of time.
+    no_reserve: bool,
+    /// Writes to pages are combined instead of cached.
+    #[cfg_attr(target_family = "unix", allow(dead_code))]
+    write_combine: bool,
+    /// Pages can have writes toggled per thread, as required by the macOS hardened runtime.
+    #[cfg_attr(not(target_os = "macos"), allow(dead_code))]
+    jit: bool,
+}
 #[cfg(target_family = "unix")]
-use std::ffi::{c_int, c_void};
+use std::ffi::{c_char, c_int, c_void};
 #[cfg(target_family = "unix")]
 extern "C" {
     fn mmap(
@@ -64,14 +334,21 @@ extern "C" {
     ) -> *mut c_void;
     fn munmap(addr: *mut c_void, length: usize) -> c_int;
     fn mprotect(addr: *mut c_void, len: usize, prot: c_int) -> c_int;
-    fn strerror(errnum: c_int) -> *const i8;
-    fn mremap(old_addr: *mut c_void, old_size: usize, new_size: usize, flags: c_int)
-        -> *mut c_void;
+    fn strerror(errnum: c_int) -> *const c_char;
+    fn mremap(
+        old_addr: *mut c_void,
+        old_size: usize,
+        new_size: usize,
+        flags: c_int,
+        ...
+    ) -> *mut c_void;
     fn posix_madvise(addr: *mut c_void, length: usize, advice: c_int) -> c_int;
+    fn madvise(addr: *mut c_void, length: usize, advice: c_int) -> c_int;
+    fn msync(addr: *mut c_void, length: usize, flags: c_int) -> c_int;
 }
 /// Marks if a [`Pages`] can be read from.
 pub trait ReadPremisionMarker {
-    #[cfg(all(target_family = "unix"))]
+    #[cfg(target_family = "unix")]
     #[doc(hidden)]
     fn bitmask() -> c_int;
     #[doc(hidden)]
@@ -168,12 +445,32 @@ impl ExecPremisionMarker for DenyExec {
 }
 /// [`Pages`] represents a collection of pages acquired from the kernel. Those pages share a common set of permissions and are laid out contiguously in the memory. The permissions on given [`Pages`] may be changed at runtime.
 pub struct Pages<R: ReadPremisionMarker, W: WritePremisionMarker, E: ExecPremisionMarker> {
-    ptr: *mut u8,
+    ptr: NonNull<u8>,
     len: usize,
+    backing: Backing,
     read: PhantomData<R>,
     write: PhantomData<W>,
     exec: PhantomData<E>,
 }
+// `Pages` uniquely own memory behind `ptr`, just like a `Box<[u8]>` does, so they can be sent to other threads. Shared
+// references only allow reading that memory, so they can be shared between threads too.
+unsafe impl<R: ReadPremisionMarker, W: WritePremisionMarker, E: ExecPremisionMarker> Send
+    for Pages<R, W, E>
+{
+}
+unsafe impl<R: ReadPremisionMarker, W: WritePremisionMarker, E: ExecPremisionMarker> Sync
+    for Pages<R, W, E>
+{
+}
+/// Describes where the memory behind [`Pages`] comes from, and how it should be released.
+enum Backing {
+    /// Anonymous memory, returned directly to the kernel on drop.
+    Anonymous,
+    /// Memory mapped from a file.
+    File(Box<FileBacking>),
+    /// Part of a region shared with other [`Pages`] allocated in the same batch.
+    Batch(std::sync::Arc<BatchRegion>),
+}
 #[cfg(target_family = "unix")]
 fn erno() -> c_int {
     #[cfg(any(target_os = "linux", target_os = "redox"))]
@@ -252,7 +549,59 @@ impl<R: ReadPremisionMarker, W: WritePremisionMarker, E: ExecPremisionMarker> Pa
     ///```
     #[must_use]
     pub fn new(length: usize) -> Self {
-        Self::new_native(length)
+        Self::new_native(length, MapOptions::default())
+    }
+    /// Allocates new [`Pages`] of size at least length, which are shared with child processes created using `fork`.
+    /// Normal [`Pages`] are copied-on-write into the child, so changes made after forking are never seen by the other
+    /// process. Shared [`Pages`] refer to the same physical memory in both processes, so all changes are visible to each of them.
+    /// On systems without `fork`(Windows) shared [`Pages`] behave exactly like [`Pages`] created with [`Self::new`].
+    /// # Panics
+    /// Panics when a 0-sized allocation is attempted, or if kernel can't/refuses to allocate requested Pages(Should never happen).
+    /// # Examples
+    ///```
+    /// # use memory_pages::*;
+    /// let mut memory:Pages<AllowRead,AllowWrite,DenyExec> = Pages::new_shared(0x4000);
+    /// // Shared pages can be used just like normal ones.
+    /// memory[0] = 64;
+    /// assert_eq!(memory[0],64);
+    /// assert_eq!(memory.len(),0x4000);
+    ///```
+    #[must_use]
+    pub fn new_shared(length: usize) -> Self {
+        Self::new_native(
+            length,
+            MapOptions {
+                shared: true,
+                ..MapOptions::default()
+            },
+        )
+    }
+    /// Allocates new [`Pages`] of size at least length, without reserving swap space for them. This allows for creating
+    /// extremely large(hundreds of GB) mappings, out of which only a small part is ever used, e.g. to model a sparse
+    /// address space. Physical memory is assigned to each page the first time it is accessed.
+    /// # Beware
+    /// Since no space is reserved, the kernel may run out of memory when the pages are accessed, killing the process.
+    /// On Windows, accessible pages always count towards the commit limit. Use [`SparsePages`] to explicitly control which
+    /// parts of a huge mapping are committed on every platform.
+    /// # Panics
+    /// Panics when a 0-sized allocation is attempted, or if kernel can't/refuses to allocate requested Pages.
+    /// # Examples
+    ///```
+    /// # use memory_pages::*;
+    /// // 4 GB of address space, which only uses memory for pages that were touched.
+    /// let mut memory:Pages<AllowRead,AllowWrite,DenyExec> = Pages::new_sparse(0x1_0000_0000);
+    /// memory[0xFFFF_0000] = 1;
+    /// assert_eq!(memory[0xFFFF_0000],1);
+    ///```
+    #[must_use]
+    pub fn new_sparse(length: usize) -> Self {
+        Self::new_native(
+            length,
+            MapOptions {
+                no_reserve: true,
+                ..MapOptions::default()
+            },
+        )
     }
     /// Advises this [`Pages`] that `used` bytes are going to be in use soon.
     /// # Beware
@@ -263,7 +612,11 @@ impl<R: ReadPremisionMarker, W: WritePremisionMarker, E: ExecPremisionMarker> Pa
         unsafe {
             let ad_len = self.len.min(used);
             const POSIX_MADV_WILLNEED: c_int = 3;
-            posix_madvise(self.ptr as *mut c_void, ad_len, POSIX_MADV_WILLNEED);
+            posix_madvise(
+                self.ptr.as_ptr().cast::<c_void>(),
+                ad_len,
+                POSIX_MADV_WILLNEED,
+            );
         }
     }
     /// Advises this [`Pages`] that it is going to be accessed sequentially.
@@ -274,7 +627,11 @@ impl<R: ReadPremisionMarker, W: WritePremisionMarker, E: ExecPremisionMarker> Pa
         #[cfg(target_family = "unix")]
         unsafe {
             const POSIX_MADV_SEQUENTIAL: c_int = 2;
-            posix_madvise(self.ptr as *mut c_void, self.len, POSIX_MADV_SEQUENTIAL);
+            posix_madvise(
+                self.ptr.as_ptr().cast::<c_void>(),
+                self.len,
+                POSIX_MADV_SEQUENTIAL,
+            );
         }
     }
     /// Advises this [`Pages`] that it is going to be accessed randomly.
@@ -285,100 +642,241 @@ impl<R: ReadPremisionMarker, W: WritePremisionMarker, E: ExecPremisionMarker> Pa
         #[cfg(target_family = "unix")]
         unsafe {
             const POSIX_MADV_RANDOM: c_int = 1;
-            posix_madvise(self.ptr as *mut c_void, self.len, POSIX_MADV_RANDOM);
+            posix_madvise(
+                self.ptr.as_ptr().cast::<c_void>(),
+                self.len,
+                POSIX_MADV_RANDOM,
+            );
+        }
+    }
+    /// Advises the kernel that this [`Pages`] may be deduplicated with other memory of identical contents, using Kernel
+    /// Samepage Merging. Useful when many large buffers hold near-identical data, like per-tenant copies of a reference
+    /// data set. Merged pages are copied again on the first write to them.
+    /// # Errors
+    /// Returns an error on platforms other than Linux, on kernels built without KSM support, and for [`Pages`] backed
+    /// by a file.
+    /// # Examples
+    /// ```
+    /// # use memory_pages::*;
+    /// let mut copy:Pages<AllowRead,AllowWrite,DenyExec> = Pages::new(0x100_000);
+    /// // Fails if kernel does not support merging, but the pages are usable either way.
+    /// let _ = copy.advise_mergeable();
+    /// copy[0] = 1;
+    /// ```
+    pub fn advise_mergeable(&mut self) -> std::io::Result<()> {
+        #[cfg(target_os = "linux")]
+        {
+            const MADV_MERGEABLE: c_int = 12;
+            if unsafe { madvise(self.ptr.as_ptr().cast(), self.len, MADV_MERGEABLE) } != 0 {
+                return Err(std::io::Error::last_os_error());
+            }
+            Ok(())
         }
+        #[cfg(not(target_os = "linux"))]
+        Err(std::io::Error::new(
+            std::io::ErrorKind::Unsupported,
+            "merging pages is not supported on this platform",
+        ))
+    }
+    /// Allocates new [`Pages`], panicking if the kernel refuses to.
+    fn new_native(length: usize, options: MapOptions) -> Self {
+        Self::try_new_native(length, options)
+            .unwrap_or_else(|err| panic!("Allocating pages failed:'{err}'!"))
     }
     #[cfg(target_family = "windows")]
-    fn new_native(length: usize) -> Self {
+    fn try_new_native(length: usize, options: MapOptions) -> std::io::Result<Self> {
         assert_ne!(length, 0, "0 - sized allcations are not allowed!");
-        let len = next_page_boundary(length);
-        let ptr =
-            unsafe { VirtualAlloc(std::ptr::null_mut(), length, MEM_COMMIT, Self::flProtect()) }
-                .cast::<u8>();
-        if ptr.is_null(){
-            let err = unsafe { winapi::um::errhandlingapi::GetLastError() };
-            panic!("Allocation using VirtualAlloc failed with error code:{err}!");
-        }
-        Self {
+        let len = align_up(length);
+        // Inaccessible pages never need to be backed by memory, so they can just reserve address space.
+        let no_access = !(R::allow_read() || W::allow_write() || E::allow_exec());
+        let alloc_type = if options.no_reserve && no_access {
+            MEM_RESERVE
+        } else {
+            MEM_COMMIT
+        };
+        let fl_protect = if options.write_combine {
+            Self::flProtect() | PAGE_WRITECOMBINE
+        } else {
+            Self::flProtect()
+        };
+        let ptr = unsafe { VirtualAlloc(std::ptr::null_mut(), length, alloc_type, fl_protect) };
+        let Some(ptr) = NonNull::new(ptr.cast::<u8>()) else {
+            return Err(std::io::Error::last_os_error());
+        };
+        Ok(Self {
             ptr,
             len,
+            backing: Backing::Anonymous,
             read: PhantomData,
             write: PhantomData,
             exec: PhantomData,
-        }
+        })
     }
     #[cfg(target_family = "unix")]
-    fn new_native(length: usize) -> Self {
+    fn try_new_native(length: usize, options: MapOptions) -> std::io::Result<Self> {
         assert_ne!(length, 0, "0 - sized allcations are not allowed!");
-        let len = next_page_boundary(length);
-        let prot_mask = Self::bitmask();
+        let len = align_up(length);
+        let prot_mask = branch_protection::with_bti(Self::bitmask());
+        let sharing = if options.shared {
+            MAP_SHARED
+        } else {
+            MAP_PRIVATE
+        };
+        let reserve = if options.no_reserve { MAP_NORESERVE } else { 0 };
+        let jit = if options.jit { MAP_JIT } else { 0 };
         let ptr = unsafe {
             mmap(
                 std::ptr::null_mut(),
                 len,
                 prot_mask,
-                MAP_ANYNOMUS | MAP_PRIVATE,
+                MAP_ANYNOMUS | sharing | reserve | jit,
                 NO_FILE,
                 0,
             )
+        };
+        if ptr == MAP_FAILED {
+            return Err(std::io::Error::last_os_error());
         }
-        .cast::<u8>();
-        if ptr as usize == usize::MAX {
-            let erno = errno_msg();
-            panic!("mmap error, erno:{erno:?}!");
-        }
-        Self {
-            ptr,
+        Ok(Self {
+            ptr: non_null(ptr),
             len,
+            backing: Backing::Anonymous,
             read: PhantomData,
             write: PhantomData,
             exec: PhantomData,
-        }
+        })
     }
     #[cfg(target_family = "unix")]
-    fn set_prot(&mut self) {
-        let mask = Self::bitmask();
-        if unsafe { mprotect(self.ptr.cast::<c_void>(), self.len, mask) } != -1 && erno() != 0 {
-            let err = errno_msg();
+    fn protect(&self, mask: c_int) {
+        if let Err(err) = self.try_protect(mask) {
             panic!("Failed to change memory protection mode:'{err}'!");
         }
     }
+    #[cfg(target_family = "unix")]
+    fn try_protect(&self, mask: c_int) -> Result<(), String> {
+        let mask = branch_protection::with_bti(mask);
+        if unsafe { mprotect(self.ptr.as_ptr().cast::<c_void>(), self.len, mask) } == -1 {
+            return Err(errno_msg());
+        }
+        Ok(())
+    }
+    #[cfg(target_family = "windows")]
+    fn protect(&self, fl_protect: u32) {
+        if let Err(err) = self.try_protect(fl_protect) {
+            panic!("Changing memory protection using using VirtualProtect failed with error code:{err}!");
+        }
+    }
     #[cfg(target_family = "windows")]
-    fn set_prot(&mut self) {
+    fn try_protect(&self, fl_protect: u32) -> Result<(), u32> {
         let mut _old: u32 = 0;
         let res = unsafe {
             winapi::um::memoryapi::VirtualProtect(
-                self.ptr.cast::<winapi::ctypes::c_void>(),
+                self.ptr.as_ptr().cast::<winapi::ctypes::c_void>(),
                 self.len,
-                Self::flProtect(),
+                fl_protect,
                 &mut _old as *mut _,
             )
         };
         if res == 0 {
-            let err = unsafe { winapi::um::errhandlingapi::GetLastError() };
-            panic!("Changing memory protection using using VirtualProtect failed with error code:{err}!");
+            return Err(unsafe { winapi::um::errhandlingapi::GetLastError() });
         }
+        Ok(())
     }
-    fn into_prot<TR: ReadPremisionMarker, TW: WritePremisionMarker, TE: ExecPremisionMarker>(
+    /// Sets actual protection of this [`Pages`] to the one described by type of `Pages<TR, TW, TE>`.
+    fn protect_as<TR: ReadPremisionMarker, TW: WritePremisionMarker, TE: ExecPremisionMarker>(
+        &self,
+    ) {
+        #[cfg(target_family = "unix")]
+        self.protect(Pages::<TR, TW, TE>::bitmask());
+        #[cfg(target_family = "windows")]
+        self.protect(Pages::<TR, TW, TE>::flProtect());
+    }
+    fn set_prot(&self) {
+        self.protect_as::<R, W, E>();
+    }
+    /// Restores protection described by the type of this [`Pages`], without ever panicking. Returns false if kernel
+    /// refused to change it.
+    fn try_set_prot(&self) -> bool {
+        #[cfg(target_family = "unix")]
+        let res = self.try_protect(Self::bitmask());
+        #[cfg(target_family = "windows")]
+        let res = self.try_protect(Self::flProtect());
+        res.is_ok()
+    }
+    /// Checks if code inside this [`Pages`], which are about to become executable, may execute.
+    /// # Panics
+    /// Panics if the installed [`CodeVerifier`] rejects the code.
+    #[cfg(any(feature = "allow_exec", doc, test))]
+    fn verify_new_code(&self) {
+        if let Err(err) = code_verifier::verify_code(self.ptr.as_ptr(), self.len) {
+            panic!("Verifying code failed:'{err}'!");
+        }
+    }
+    /// Temporarily makes this [`Pages`] readable and writable, calls `f` with their contents, and then restores their
+    /// original protection, even if `f` panics. Execution is always denied while `f` runs, so this is a safe way to patch
+    /// code inside executable [`Pages`] without ever having them both writable and executable. Patched code is checked
+    /// by the installed [`CodeVerifier`] before it becomes executable again, and other threads are then synchronized
+    /// using [`sync_code_across_threads`], so they never execute a mix of old and new instructions.
+    /// # Panics
+    /// Panics if kernel can't/refuses to change protection of this [`Pages`], or if the installed [`CodeVerifier`]
+    /// rejects the patched code, which is then left read-only and not executable.
+    /// # Examples
+    ///```
+    /// # use memory_pages::*;
+    /// let mut memory:Pages<AllowRead,DenyWrite,DenyExec> = Pages::new(0x1000);
+    /// memory.with_writable(|data| data[0x10] = 0xC3);
+    /// // `memory` is read-only again, but keeps the changes made inside the closure.
+    /// assert_eq!(memory[0x10], 0xC3);
+    ///```
+    pub fn with_writable<T>(&mut self, f: impl FnOnce(&mut [u8]) -> T) -> T {
+        let mut guard = self.write_guard();
+        let res = f(&mut guard);
+        // Unlike the guard, this is not dropped during unwinding, so rejected code can be reported by panicking.
+        let pages = guard.into_pages();
+        #[cfg(any(feature = "allow_exec", doc, test))]
+        if E::allow_exec() {
+            if let Err(err) = pages.verify_written_code() {
+                panic!("Verifying code failed:'{err}'!");
+            }
+        }
+        pages.restore_after_write();
+        res
+    }
+    /// Changes the type of this [`Pages`], without changing their actual protection.
+    fn retype<TR: ReadPremisionMarker, TW: WritePremisionMarker, TE: ExecPremisionMarker>(
         self,
     ) -> Pages<TR, TW, TE> {
-        let mut res = Pages {
-            ptr: self.ptr,
-            len: self.len,
+        let this = std::mem::ManuallyDrop::new(self);
+        Pages {
+            ptr: this.ptr,
+            len: this.len,
+            // `this` is never dropped, so ownership of backing can be safely moved out of it.
+            backing: unsafe { std::ptr::read(&this.backing) },
             read: PhantomData,
             write: PhantomData,
             exec: PhantomData,
-        };
-        std::mem::forget(self);
-        #[cfg(target_family = "unix")]
-        if Self::bitmask() == (Pages::<TR, TW, TE>::bitmask()) {
-            return res;
         }
+    }
+    fn into_prot<TR: ReadPremisionMarker, TW: WritePremisionMarker, TE: ExecPremisionMarker>(
+        self,
+    ) -> Pages<TR, TW, TE> {
+        let res: Pages<TR, TW, TE> = self.retype();
+        #[cfg(target_family = "unix")]
+        let changed = Self::bitmask() != (Pages::<TR, TW, TE>::bitmask());
         #[cfg(target_family = "windows")]
-        if Self::flProtect() == (Pages::<TR, TW, TE>::flProtect()) {
-            return res;
+        let changed = Self::flProtect() != (Pages::<TR, TW, TE>::flProtect());
+        // Only code which was not executable before is verified, so pages which already are can be patched freely.
+        #[cfg(any(feature = "allow_exec", doc, test))]
+        if TE::allow_exec() && !E::allow_exec() {
+            res.verify_new_code();
+        }
+        if changed {
+            res.set_prot();
+        }
+        // Code may have been written while the pages were writable, and must not execute stale cached instructions.
+        if TE::allow_exec() {
+            flush_icache_range(res.ptr.as_ptr(), res.len);
         }
-        res.set_prot();
         res
     }
     /// Releases physical memory pages behind the region starting at page `beginning` is in, and continuing till page `beginning + length` is in. Those pages will be given backing the next time they are accessed.
@@ -389,7 +887,7 @@ impl<R: ReadPremisionMarker, W: WritePremisionMarker, E: ExecPremisionMarker> Pa
         #[cfg(target_os = "windows")]
         unsafe {
             let res = DiscardVirtualMemory(
-                (self.ptr as usize + beginning) as *mut winapi::ctypes::c_void,
+                (self.ptr.as_ptr() as usize + beginning) as *mut winapi::ctypes::c_void,
                 decommit_len,
             );
             if (res != 0) && cfg!(debug_assertions) {
@@ -398,17 +896,21 @@ impl<R: ReadPremisionMarker, W: WritePremisionMarker, E: ExecPremisionMarker> Pa
         }
         #[cfg(target_family = "unix")]
         unsafe {
+            // `posix_madvise` is not allowed to discard data, so glibc ignores `POSIX_MADV_DONTNEED`, and only `madvise`
+            // actually releases the pages.
             const MADV_DONTNEED: c_int = 4;
-            posix_madvise(
-                (self.ptr as usize + beginning) as *mut c_void,
-                decommit_len,
+            let start = page_math::align_down(beginning);
+            madvise(
+                self.ptr.as_ptr().add(start).cast::<c_void>(),
+                align_up(beginning + decommit_len) - start,
                 MADV_DONTNEED,
             );
         }
     }
 }
 impl<E: ExecPremisionMarker> Pages<AllowRead, AllowWrite, E> {
-    /// Changes the size of this [`Pages`]
+    /// Changes the size of this [`Pages`]. [`Pages`] mapped from a file are remapped, and the file is extended or truncated
+    /// to the new size(truncated only on unix-like systems).
     /// # Waring
     /// ## Pointer invalidation
     /// *Rust mutable borrow rules prevent this from happening in safe code. This section only concerns pointers to
@@ -427,25 +929,122 @@ impl<E: ExecPremisionMarker> Pages<AllowRead, AllowWrite, E> {
     /// assert!(prev_len < pages.len());
     /// ```
     pub fn resize(&mut self, new_size: usize) {
-        #[cfg(target_family = "unix")]
-        unsafe {
-            const MREMAP_MAYMOVE: c_int = 1;
-            let ptr = mremap(self.ptr as *mut c_void, self.len, new_size, MREMAP_MAYMOVE);
-            if ptr as usize == usize::MAX {
-                let erno = errno_msg();
-                panic!("mmap error, erno:{erno:?}!");
-            }
-            self.ptr = ptr as *mut u8;
-            self.len = new_size;
+        self.try_resize(new_size)
+            .unwrap_or_else(|err| panic!("Resizing pages failed:'{err}'!"));
+    }
+    /// Works like [`Self::resize`], but returns an error instead of panicking if new pages can't be allocated. This
+    /// [`Pages`] is left unchanged on error.
+    pub(crate) fn try_resize(&mut self, new_size: usize) -> std::io::Result<()> {
+        match self.backing {
+            Backing::File(_) => self.resize_file(new_size),
+            #[cfg(target_family = "unix")]
+            Backing::Anonymous => unsafe {
+                const MREMAP_MAYMOVE: c_int = 1;
+                let ptr = mremap(
+                    self.ptr.as_ptr().cast::<c_void>(),
+                    self.len,
+                    new_size,
+                    MREMAP_MAYMOVE,
+                );
+                if ptr != MAP_FAILED {
+                    self.ptr = non_null(ptr);
+                    self.len = new_size;
+                    return Ok(());
+                }
+                // Pages made up of multiple mappings, like ones other pages were moved into, can't be grown in place.
+                const EFAULT: c_int = 14;
+                let err = std::io::Error::last_os_error();
+                if err.raw_os_error() != Some(EFAULT) {
+                    return Err(err);
+                }
+                self.resize_copy(new_size)
+            },
+            // Batched pages can't be moved out of their region, so they must be copied.
+            _ => self.resize_copy(new_size),
         }
-        #[cfg(not(target_family = "unix"))]
-        {
-            let mut copy = Self::new(new_size);
-            let copy_size = copy.len().min(self.len());
-            copy.split_at_mut(copy_size)
-                .0
-                .copy_from_slice(self.split_at_mut(copy_size).0);
-            *self = copy;
+    }
+    /// Resizes this [`Pages`] by copying them into newly allocated pages.
+    fn resize_copy(&mut self, new_size: usize) -> std::io::Result<()> {
+        let mut copy = Self::try_new_native(new_size, MapOptions::default())?;
+        let copy_size = copy.len().min(self.len());
+        copy.split_at_mut(copy_size)
+            .0
+            .copy_from_slice(self.split_at_mut(copy_size).0);
+        *self = copy;
+        Ok(())
+    }
+    /// Moves the pages of this [`Pages`] to `dest`, replacing any pages already mapped there, without copying any data.
+    /// Caller must ensure `dest` is page aligned and that `dest..dest + self.len()` is owned by it. Returns `self` back
+    /// if the pages can't be moved.
+    #[cfg(target_os = "linux")]
+    pub(crate) fn move_to(self, dest: *mut u8) -> Result<(), Self> {
+        const MREMAP_MAYMOVE: c_int = 1;
+        const MREMAP_FIXED: c_int = 2;
+        if !matches!(self.backing, Backing::Anonymous) {
+            return Err(self);
+        }
+        let len = align_up(self.len);
+        let res = unsafe {
+            mremap(
+                self.ptr.as_ptr().cast::<c_void>(),
+                len,
+                len,
+                MREMAP_MAYMOVE | MREMAP_FIXED,
+                dest.cast::<c_void>(),
+            )
+        };
+        if res == MAP_FAILED {
+            return Err(self);
+        }
+        // Pages are no longer mapped at their old address, so they must not be unmapped there.
+        std::mem::forget(self);
+        Ok(())
+    }
+    /// Splits this [`Pages`] in two at `at`, returning the pages after it, and leaving this [`Pages`] with length `at`.
+    /// On unix anonymous pages are split in place, without copying or remapping any data. Other pages are copied.
+    /// # Panics
+    /// Panics if `at` is not page aligned, or if it is not between 0 and length of this [`Pages`], exclusive.
+    /// # Example
+    /// ```
+    /// # use memory_pages::*;
+    /// let mut pages:Pages<AllowRead,AllowWrite,DenyExec> = Pages::new(0x3000);
+    /// pages[0x2000] = 2;
+    /// let tail = pages.split_off(0x2000);
+    /// assert_eq!(pages.len(), 0x2000);
+    /// assert_eq!(tail.len(), 0x1000);
+    /// assert_eq!(tail[0], 2);
+    /// ```
+    #[must_use]
+    pub fn split_off(&mut self, at: usize) -> Self {
+        assert!(
+            at.is_multiple_of(page_math::PAGE_SIZE) && 0 < at && at < self.len,
+            "can't split pages of length {} at {at}!",
+            self.len
+        );
+        match self.backing {
+            // Each part of an anonymous mapping can be unmapped separately, so the tail can be simply handed over.
+            #[cfg(target_family = "unix")]
+            Backing::Anonymous => {
+                let tail = Self {
+                    ptr: unsafe { self.ptr.add(at) },
+                    len: self.len - at,
+                    backing: Backing::Anonymous,
+                    read: PhantomData,
+                    write: PhantomData,
+                    exec: PhantomData,
+                };
+                self.len = at;
+                tail
+            }
+            _ => {
+                let mut tail = Self::new(self.len - at);
+                let tail_len = self.len - at;
+                tail.split_at_mut(tail_len)
+                    .0
+                    .copy_from_slice(self.split_at(at).1);
+                self.resize(at);
+                tail
+            }
         }
     }
 }
@@ -466,12 +1065,12 @@ impl<W: WritePremisionMarker, E: ExecPremisionMarker> Borrow<[u8]> for Pages<All
 impl<W: WritePremisionMarker, E: ExecPremisionMarker> Deref for Pages<AllowRead, W, E> {
     type Target = [u8];
     fn deref(&self) -> &Self::Target {
-        unsafe { std::slice::from_raw_parts(self.ptr, self.len) }
+        unsafe { std::slice::from_raw_parts(self.ptr.as_ptr(), self.len) }
     }
 }
 impl<E: ExecPremisionMarker> DerefMut for Pages<AllowRead, AllowWrite, E> {
     fn deref_mut(&mut self) -> &mut Self::Target {
-        unsafe { std::slice::from_raw_parts_mut(self.ptr, self.len) }
+        unsafe { std::slice::from_raw_parts_mut(self.ptr.as_ptr(), self.len) }
     }
 }
 impl<E: ExecPremisionMarker> BorrowMut<[u8]> for Pages<AllowRead, AllowWrite, E> {
@@ -481,7 +1080,7 @@ impl<E: ExecPremisionMarker> BorrowMut<[u8]> for Pages<AllowRead, AllowWrite, E>
 }
 impl<E: ExecPremisionMarker> std::ops::IndexMut<usize> for Pages<AllowRead, AllowWrite, E> {
     fn index_mut(&mut self, index: usize) -> &mut u8 {
-        unsafe { &mut std::slice::from_raw_parts_mut(self.ptr, self.len)[index] }
+        unsafe { &mut std::slice::from_raw_parts_mut(self.ptr.as_ptr(), self.len)[index] }
     }
 }
 impl<R: ReadPremisionMarker, W: WritePremisionMarker, E: ExecPremisionMarker> Pages<R, W, E> {
@@ -575,6 +1174,8 @@ impl<R: ReadPremisionMarker, W: WritePremisionMarker, E: ExecPremisionMarker> Pa
     /// This should **NEVER** be set if not needed, because if used improperly, it may lead to Arbitrary Code Execution
     /// exploits. Use *only* if you know what you are doing. [`Self::set_protected_exec`] is a safer alternative, that prevents
     /// most ways an ACE exploit could occur.
+    /// # Panics
+    /// Panics if the code inside this [`Pages`] is rejected by the installed [`CodeVerifier`].
     #[must_use]
     #[cfg(any(feature = "allow_exec", doc, test))]
     pub fn allow_exec(self) -> Pages<R, W, AllowExec> {
@@ -582,7 +1183,10 @@ impl<R: ReadPremisionMarker, W: WritePremisionMarker, E: ExecPremisionMarker> Pa
     }
     /// Sets the permission on [`Pages`] to [`AllowExec`] and [`DenyWrite`] to prevent changing of instructions inside      
     /// [`Pages`]. To re-enable writes, use [`Self::allow_write_no_exec`] to ensure both [`AllowExec`] and [`AllowExec`] are
-    /// never set at the same time.
+    /// never set at the same time. Instruction cache is flushed, so code written before is visible to execution. To
+    /// forbid writes for good, turn the result into [`ExecSealed`] using [`Pages::seal`].
+    /// # Panics
+    /// Panics if the code inside this [`Pages`] is rejected by the installed [`CodeVerifier`].
     #[must_use]
     #[cfg(any(feature = "allow_exec", doc, test))]
     pub fn set_protected_exec(self) -> Pages<R, DenyWrite, AllowExec> {
@@ -595,6 +1199,14 @@ impl<R: ReadPremisionMarker, W: WritePremisionMarker, E: ExecPremisionMarker> Pa
         self.into_prot()
     }
 }
+impl<R: ReadPremisionMarker, W: WritePremisionMarker, E: ExecPremisionMarker> Pages<R, W, E> {
+    /// Returns a pointer to the beginning of this [`Pages`], regardless of their permissions. Dereferencing it is only
+    /// allowed in ways current protection of pages allows.
+    #[must_use]
+    pub fn get_ptr_unchecked(&self) -> *mut u8 {
+        self.ptr.as_ptr()
+    }
+}
 impl<W: WritePremisionMarker, E: ExecPremisionMarker> Pages<AllowRead, W, E> {
     /// Sets the [`AllowRead`], making data inside page readable.
     /// # Panics
@@ -603,6 +1215,24 @@ impl<W: WritePremisionMarker, E: ExecPremisionMarker> Pages<AllowRead, W, E> {
     pub fn get_ptr(&self, offset: usize) -> *const u8 {
         std::ptr::addr_of!(self[offset])
     }
+    /// Returns an iterator over pages of this [`Pages`], each as a slice of bytes. The last slice may be shorter than a
+    /// page, if length of this [`Pages`] is not page aligned.
+    /// # Examples
+    /// ```
+    /// # use memory_pages::*;
+    /// let memory:Pages<AllowRead,AllowWrite,DenyExec> = Pages::new(0x3000);
+    /// assert!(memory.page_chunks().all(|page|page.len() == 0x1000));
+    /// assert_eq!(memory.page_chunks().count(), 3);
+    /// ```
+    pub fn page_chunks(&self) -> page_math::PageRanges<'_, u8> {
+        page_math::page_ranges(self)
+    }
+}
+impl<E: ExecPremisionMarker> Pages<AllowRead, AllowWrite, E> {
+    /// Works exactly like [`Self::page_chunks`], but yields mutable slices.
+    pub fn page_chunks_mut(&mut self) -> page_math::PageRangesMut<'_, u8> {
+        page_math::page_ranges_mut(self)
+    }
 }
 impl<R: ReadPremisionMarker, E: ExecPremisionMarker> Pages<R, AllowWrite, E> {
     /// Gets a pointer to data inside page at `offset`.
@@ -610,7 +1240,9 @@ impl<R: ReadPremisionMarker, E: ExecPremisionMarker> Pages<R, AllowWrite, E> {
     /// This pointer may be only written into, and while reading data from it may work on some systems, it is an UB which may cause crashes.
     pub fn get_ptr_mut(&mut self, offset: usize) -> *mut u8 {
         unsafe {
-            std::ptr::addr_of_mut!(std::slice::from_raw_parts_mut(self.ptr, self.len)[offset])
+            std::ptr::addr_of_mut!(
+                std::slice::from_raw_parts_mut(self.ptr.as_ptr(), self.len)[offset]
+            )
         }
     }
 }
@@ -646,7 +1278,10 @@ impl<R: ReadPremisionMarker, W: WritePremisionMarker> Pages<R, W, AllowExec> {
     ///```
     #[must_use]
     pub fn get_fn_ptr(&self, offset: usize) -> *const () {
-        unsafe { std::ptr::addr_of!(std::slice::from_raw_parts(self.ptr, self.len)[offset]).cast() }
+        unsafe {
+            std::ptr::addr_of!(std::slice::from_raw_parts(self.ptr.as_ptr(), self.len)[offset])
+                .cast()
+        }
     }
     /// Gets a pointer to function at offset in [`Pages`]. Function must be an `extern "C" fn`.
     /// # Safety
@@ -684,23 +1319,47 @@ impl<R: ReadPremisionMarker, W: WritePremisionMarker> Pages<R, W, AllowExec> {
     /// unsafe{assert_eq!(add.call((43,34)),77)};
     /// ```
     #[must_use]
-    pub unsafe fn get_fn<F: ExternFnPtr>(&self, offset: usize) -> FnRef<F>
+    pub unsafe fn get_fn<F>(&self, offset: usize) -> FnRef<'_, F>
     where
-        F: Copy + Pointer + Sized,
+        F: ExternFnPtr + Copy + Pointer + Sized,
     {
         let fn_ptr = self.get_fn_ptr(offset);
         let f: F = *(std::ptr::addr_of!(fn_ptr).cast::<F>());
         let _ = fn_ptr;
-        FnRef::new(f, self)
+        FnRef::new(f, self, offset)
     }
 }
 impl<R: ReadPremisionMarker, W: WritePremisionMarker, E: ExecPremisionMarker> Drop
     for Pages<R, W, E>
 {
     fn drop(&mut self) {
+        #[cfg(all(
+            any(feature = "allow_exec", doc, test),
+            any(target_arch = "x86_64", target_arch = "aarch64")
+        ))]
+        breakpoint::forget_range(self.ptr.as_ptr(), self.len);
+        match &self.backing {
+            Backing::File(file) => {
+                if file.flush_on_drop {
+                    // Errors can't be reported from `drop`, call `Pages::flush` to handle them.
+                    let _ = self.flush(FlushMode::Sync);
+                }
+                #[cfg(target_family = "windows")]
+                {
+                    file_pages::unmap_file_view(self.ptr.as_ptr(), file);
+                    return;
+                }
+            }
+            Backing::Batch(region) => {
+                // The whole region is released once the last `Pages` referencing it is dropped.
+                region.release_part(self.ptr.as_ptr(), self.len);
+                return;
+            }
+            Backing::Anonymous => (),
+        }
         #[cfg(target_family = "unix")]
         unsafe {
-            let res = munmap(self.ptr.cast::<c_void>(), self.len);
+            let res = munmap(self.ptr.as_ptr().cast::<c_void>(), self.len);
             if res == -1 {
                 let err = errno_msg();
                 panic!("Unampping memory Pages failed. Reason:{err}");
@@ -708,7 +1367,11 @@ impl<R: ReadPremisionMarker, W: WritePremisionMarker, E: ExecPremisionMarker> Dr
         }
         #[cfg(target_family = "windows")]
         unsafe {
-            let res = VirtualFree(self.ptr.cast::<winapi::ctypes::c_void>(), 0, MEM_RELEASE);
+            let res = VirtualFree(
+                self.ptr.as_ptr().cast::<winapi::ctypes::c_void>(),
+                0,
+                MEM_RELEASE,
+            );
             if res == 0 {
                 let err = winapi::um::errhandlingapi::GetLastError();
                 panic!("Allocation using VirtualFree failed with error code:{err}!");
@@ -792,10 +1455,59 @@ mod test {
         }
     }
     #[test]
+    fn test_niche() {
+        fn assert_send_sync<T: Send + Sync>() {}
+        assert_send_sync::<Pages<AllowRead, AllowWrite, DenyExec>>();
+        assert_send_sync::<PagedVec<u64>>();
+        assert_send_sync::<PagedBTreeMap<u64, String>>();
+        assert_eq!(
+            std::mem::size_of::<Option<Pages<AllowRead, AllowWrite, DenyExec>>>(),
+            std::mem::size_of::<Pages<AllowRead, AllowWrite, DenyExec>>()
+        );
+        assert_eq!(
+            std::mem::size_of::<Option<PagedVec<u64>>>(),
+            std::mem::size_of::<PagedVec<u64>>()
+        );
+    }
+    #[test]
+    #[cfg(target_os = "linux")]
+    fn test_advise_mergeable() {
+        let mut memory: Pages<AllowRead, AllowWrite, DenyExec> = Pages::new(0x4000);
+        memory.fill(0x11);
+        let res = memory.advise_mergeable();
+        if std::path::Path::new("/sys/kernel/mm/ksm").exists() {
+            res.unwrap();
+        }
+        assert!(memory.iter().all(|byte| *byte == 0x11));
+    }
+    #[test]
+    #[cfg(target_os = "linux")]
+    fn test_shared_fork() {
+        extern "C" {
+            fn fork() -> c_int;
+            fn waitpid(pid: c_int, status: *mut c_int, options: c_int) -> c_int;
+            fn _exit(status: c_int) -> !;
+        }
+        let mut shared: Pages<AllowRead, AllowWrite, DenyExec> = Pages::new_shared(256);
+        let mut private: Pages<AllowRead, AllowWrite, DenyExec> = Pages::new(256);
+        let pid = unsafe { fork() };
+        assert_ne!(pid, -1);
+        if pid == 0 {
+            shared[0] = 0xAB;
+            private[0] = 0xAB;
+            unsafe { _exit(0) };
+        }
+        let mut status = 0;
+        assert_eq!(unsafe { waitpid(pid, &mut status, 0) }, pid);
+        // Only the shared pages see changes made by the child.
+        assert_eq!(shared[0], 0xAB);
+        assert_eq!(private[0], 0);
+    }
+    #[test]
     fn test_allow_read() {
         let pages: Pages<DenyRead, DenyWrite, DenyExec> = Pages::new(256);
         let pages = pages.allow_read();
-        let rf: &[u8] = &pages;
+        let _rf: &[u8] = &pages;
     }
     #[test]
     fn test_allow_write() {
@@ -837,4 +1549,108 @@ mod test {
             }
         }
     }
+    /// Reads protection flags(like `r-xp`) of the mapping containing `ptr` from `/proc/self/maps`.
+    #[cfg(target_os = "linux")]
+    fn mapping_prot(ptr: *const u8) -> String {
+        let maps = std::fs::read_to_string("/proc/self/maps").unwrap();
+        maps.lines()
+            .find_map(|line| {
+                let (range, rest) = line.split_once(' ')?;
+                let (start, end) = range.split_once('-')?;
+                let start = usize::from_str_radix(start, 16).ok()?;
+                let end = usize::from_str_radix(end, 16).ok()?;
+                (start..end)
+                    .contains(&(ptr as usize))
+                    .then(|| rest[..4].to_string())
+            })
+            .unwrap()
+    }
+    #[test]
+    #[cfg(target_os = "linux")]
+    fn test_with_writable_restores() {
+        let mut pages: Pages<AllowRead, DenyWrite, DenyExec> = Pages::new(256);
+        pages.with_writable(|data| {
+            assert_eq!(mapping_prot(data.as_ptr()), "rw-p");
+            data[0] = 1;
+        });
+        assert_eq!(mapping_prot(pages.ptr.as_ptr()), "r--p");
+        let res = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+            pages.with_writable(|data| {
+                data[1] = 2;
+                panic!("Patching failed!");
+            })
+        }));
+        assert!(res.is_err());
+        // Protection is restored even if the closure panics.
+        assert_eq!(mapping_prot(pages.ptr.as_ptr()), "r--p");
+        assert_eq!(pages[0], 1);
+        assert_eq!(pages[1], 2);
+    }
+    #[test]
+    #[cfg(target_arch = "x86_64")]
+    #[cfg(feature = "allow_exec")]
+    fn test_with_writable_exec() {
+        let mut pages: Pages<AllowRead, AllowWrite, DenyExec> = Pages::new(256);
+        // MOV EAX, 1; RET
+        pages
+            .get_mut(..6)
+            .unwrap()
+            .copy_from_slice(&[0xB8, 0x01, 0x00, 0x00, 0x00, 0xC3]);
+        let mut pages = pages.set_protected_exec();
+        let get: FnRef<unsafe extern "C" fn() -> u32> = unsafe { pages.get_fn(0) };
+        assert_eq!(unsafe { get.call(()) }, 1);
+        // Patch the immediate, without ever making pages writable and executable at once.
+        pages.with_writable(|code| code[1] = 2);
+        let get: FnRef<unsafe extern "C" fn() -> u32> = unsafe { pages.get_fn(0) };
+        assert_eq!(unsafe { get.call(()) }, 2);
+    }
+    #[test]
+    #[cfg(target_arch = "x86_64")]
+    #[cfg(feature = "allow_exec")]
+    fn test_exec_abis() {
+        let mut pages: Pages<AllowRead, AllowWrite, DenyExec> = Pages::new(256);
+        // LEA RAX, [RDI + RSI]; RET
+        pages
+            .get_mut(..5)
+            .unwrap()
+            .copy_from_slice(&[0x48, 0x8d, 0x04, 0x37, 0xC3]);
+        // LEA RAX, [RCX + RDX]; RET
+        pages
+            .get_mut(5..10)
+            .unwrap()
+            .copy_from_slice(&[0x48, 0x8d, 0x04, 0x11, 0xC3]);
+        let pages = pages.set_protected_exec();
+        // Both conventions can be called on any x86_64 system.
+        let sysv: FnRef<unsafe extern "sysv64" fn(u64, u64) -> u64> = unsafe { pages.get_fn(0) };
+        let win: FnRef<unsafe extern "win64" fn(u64, u64) -> u64> = unsafe { pages.get_fn(5) };
+        assert_eq!(unsafe { sysv.call((40, 2)) }, 42);
+        assert_eq!(unsafe { win.call((40, 2)) }, 42);
+        let system_offset = if cfg!(target_family = "windows") {
+            5
+        } else {
+            0
+        };
+        let system: FnRef<unsafe extern "system" fn(u64, u64) -> u64> =
+            unsafe { pages.get_fn(system_offset) };
+        assert_eq!(unsafe { system.call((1, 2)) }, 3);
+    }
+    #[test]
+    #[cfg(target_arch = "x86_64")]
+    #[cfg(feature = "allow_exec")]
+    fn test_exec_c_unwind() {
+        extern "C-unwind" fn fail() {
+            panic!("Called from jitted code!");
+        }
+        let mut pages: Pages<AllowRead, AllowWrite, DenyExec> = Pages::new(256);
+        // MOV R11, fail; JMP R11
+        let mut code = vec![0x49, 0xBB];
+        code.extend_from_slice(&(fail as extern "C-unwind" fn() as usize).to_le_bytes());
+        code.extend_from_slice(&[0x41, 0xFF, 0xE3]);
+        pages.get_mut(..13).unwrap().copy_from_slice(&code);
+        let pages = pages.set_protected_exec();
+        let jump: FnRef<unsafe extern "C-unwind" fn()> = unsafe { pages.get_fn(0) };
+        // The panic unwinds through the call, instead of aborting the process.
+        let res = std::panic::catch_unwind(|| unsafe { jump.call(()) });
+        assert!(res.is_err());
+    }
 }
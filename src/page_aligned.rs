@@ -0,0 +1,66 @@
+use crate::*;
+/// Wrapper aligning `T` to a page boundary, and padding it to a multiple of [`page_math::PAGE_SIZE`]. Each element of a
+/// [`PagedVec<PageAligned<T>>`] starts at the beginning of a page, so if `T` is not larger than a page, it is
+/// guaranteed to reside in exactly one page. This allows protecting, decommitting or tracing writes to elements
+/// individually.
+///
+/// To group multiple records in one page, wrap an array of them, sized using [`page_math::per_page`].
+/// # Examples
+/// ```
+/// # use memory_pages::*;
+/// # use memory_pages::page_math::*;
+/// #[derive(Clone, Copy)]
+/// struct Record{id:u64, value:u32}
+/// type RecordPage = PageAligned<[Record; per_page::<Record>()]>;
+/// let empty:RecordPage = PageAligned([Record{id:0, value:0}; per_page::<Record>()]);
+/// let mut vec:PagedVec<RecordPage> = PagedVec::new(0x10);
+/// vec.push(empty);
+/// vec.push(empty);
+/// vec[1][3].value = 5;
+/// assert_eq!(std::mem::size_of::<RecordPage>(), PAGE_SIZE);
+/// assert!(is_page_aligned(&vec[1]));
+/// ```
+#[repr(C, align(4096))]
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct PageAligned<T>(pub T);
+// `align` only accepts literals, so it must be kept in sync with the page size.
+const _: () = assert!(std::mem::align_of::<PageAligned<u8>>() == page_math::PAGE_SIZE);
+impl<T> PageAligned<T> {
+    /// Unwraps the inner value.
+    pub fn into_inner(self) -> T {
+        self.0
+    }
+}
+impl<T> From<T> for PageAligned<T> {
+    fn from(t: T) -> Self {
+        Self(t)
+    }
+}
+impl<T> Deref for PageAligned<T> {
+    type Target = T;
+    fn deref(&self) -> &T {
+        &self.0
+    }
+}
+impl<T> DerefMut for PageAligned<T> {
+    fn deref_mut(&mut self) -> &mut T {
+        &mut self.0
+    }
+}
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::page_math::*;
+    #[test]
+    fn test_elements_in_own_pages() {
+        let vec: PagedVec<PageAligned<u32>> = (0..0x20).map(PageAligned).collect();
+        for (i, element) in vec.iter().enumerate() {
+            assert!(is_page_aligned(element));
+            assert_eq!(element.0, i as u32);
+        }
+        assert_eq!(
+            std::mem::size_of::<PageAligned<[u8; PAGE_SIZE + 1]>>(),
+            2 * PAGE_SIZE
+        );
+    }
+}
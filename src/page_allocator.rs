@@ -0,0 +1,188 @@
+//! [`Allocator`] implementation backed by pages, enabled by the `allocator_api` feature. Requires a nightly compiler.
+use crate::page_math::{align_up, PAGE_SIZE};
+use crate::pages_global_alloc::{pages_from_raw, pages_into_raw, RawPages};
+use crate::*;
+use std::alloc::{AllocError, Allocator, Global, Layout};
+/// An [`Allocator`] requesting large allocations directly from the kernel, as anonymous pages, and forwarding small ones
+/// to the global allocator. This lets standard collections, like `Vec<T, PageAllocator>` or `Box<T, PageAllocator>`,
+/// get the benefits of [`Pages`]: memory is always returned to the kernel once freed, new memory is zeroed and only
+/// backed by RAM once touched, and on Linux large vectors grow by remapping their pages, without copying them.
+///
+/// Whether an allocation is backed by pages depends only on its layout, so that memory is always freed the same way it
+/// was allocated. Allocations aligned to more than a page always use the global allocator.
+/// # Examples
+/// ```
+/// #![feature(allocator_api)]
+/// # use memory_pages::*;
+/// let mut samples:Vec<f32, PageAllocator> = Vec::with_capacity_in(0x100_000, PageAllocator::new());
+/// samples.extend((0..0x100_000).map(|i| i as f32));
+/// assert_eq!(samples[0x1234], 4660.0);
+/// let small = Box::new_in(5_u8, PageAllocator::new());
+/// assert_eq!(*small, 5);
+/// ```
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct PageAllocator {
+    /// Allocations of at least this many bytes are backed by pages.
+    threshold: usize,
+}
+impl PageAllocator {
+    /// Size of allocations, in bytes, above which [`Self::new`] backs them with pages.
+    pub const DEFAULT_THRESHOLD: usize = 0x10_000;
+    /// Creates a [`PageAllocator`] backing allocations of at least [`Self::DEFAULT_THRESHOLD`] bytes with pages.
+    #[must_use]
+    pub const fn new() -> Self {
+        Self::with_threshold(Self::DEFAULT_THRESHOLD)
+    }
+    /// Creates a [`PageAllocator`] backing allocations of at least `threshold` bytes with pages. Since each such
+    /// allocation takes up at least a whole page, thresholds much smaller than a page waste memory.
+    #[must_use]
+    pub const fn with_threshold(threshold: usize) -> Self {
+        Self { threshold }
+    }
+    /// Returns the size of allocations, in bytes, above which they are backed by pages.
+    #[must_use]
+    pub const fn threshold(&self) -> usize {
+        self.threshold
+    }
+    fn uses_pages(&self, layout: Layout) -> bool {
+        layout.size() != 0 && layout.size() >= self.threshold && layout.align() <= PAGE_SIZE
+    }
+}
+impl Default for PageAllocator {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+unsafe impl Allocator for PageAllocator {
+    fn allocate(&self, layout: Layout) -> Result<NonNull<[u8]>, AllocError> {
+        if !self.uses_pages(layout) {
+            return Global.allocate(layout);
+        }
+        RawPages::try_new_native(layout.size(), MapOptions::default())
+            .map(pages_into_raw)
+            .map_err(|_| AllocError)
+    }
+    fn allocate_zeroed(&self, layout: Layout) -> Result<NonNull<[u8]>, AllocError> {
+        if !self.uses_pages(layout) {
+            return Global.allocate_zeroed(layout);
+        }
+        // Fresh pages are always zeroed by the kernel.
+        self.allocate(layout)
+    }
+    unsafe fn deallocate(&self, ptr: NonNull<u8>, layout: Layout) {
+        if self.uses_pages(layout) {
+            drop(pages_from_raw(ptr, layout.size()));
+        } else {
+            Global.deallocate(ptr, layout);
+        }
+    }
+    unsafe fn grow(
+        &self,
+        ptr: NonNull<u8>,
+        old_layout: Layout,
+        new_layout: Layout,
+    ) -> Result<NonNull<[u8]>, AllocError> {
+        self.reallocate(ptr, old_layout, new_layout)
+    }
+    unsafe fn grow_zeroed(
+        &self,
+        ptr: NonNull<u8>,
+        old_layout: Layout,
+        new_layout: Layout,
+    ) -> Result<NonNull<[u8]>, AllocError> {
+        if !self.uses_pages(new_layout) {
+            if !self.uses_pages(old_layout) {
+                return Global.grow_zeroed(ptr, old_layout, new_layout);
+            }
+            let memory = Global.allocate_zeroed(new_layout)?;
+            std::ptr::copy_nonoverlapping(
+                ptr.as_ptr(),
+                memory.cast::<u8>().as_ptr(),
+                old_layout.size(),
+            );
+            self.deallocate(ptr, old_layout);
+            return Ok(memory);
+        }
+        let memory = self.reallocate(ptr, old_layout, new_layout)?;
+        // Pages added by growing are zeroed, but the rest of the last old page may hold data from before a shrink.
+        let old = old_layout.size();
+        let dirty = align_up(old).min(new_layout.size()) - old;
+        memory.cast::<u8>().add(old).write_bytes(0, dirty);
+        Ok(memory)
+    }
+    unsafe fn shrink(
+        &self,
+        ptr: NonNull<u8>,
+        old_layout: Layout,
+        new_layout: Layout,
+    ) -> Result<NonNull<[u8]>, AllocError> {
+        self.reallocate(ptr, old_layout, new_layout)
+    }
+}
+impl PageAllocator {
+    /// Moves an allocation to `new_layout`, resizing pages in place when both layouts are backed by them.
+    unsafe fn reallocate(
+        &self,
+        ptr: NonNull<u8>,
+        old_layout: Layout,
+        new_layout: Layout,
+    ) -> Result<NonNull<[u8]>, AllocError> {
+        match (self.uses_pages(old_layout), self.uses_pages(new_layout)) {
+            (true, true) => {
+                let mut pages = pages_from_raw(ptr, old_layout.size());
+                let res = pages.try_resize(align_up(new_layout.size()));
+                // On error pages are left unchanged, and still owned by the caller.
+                let memory = pages_into_raw(pages);
+                res.map(|()| memory).map_err(|_| AllocError)
+            }
+            (false, false) if new_layout.size() >= old_layout.size() => {
+                Global.grow(ptr, old_layout, new_layout)
+            }
+            (false, false) => Global.shrink(ptr, old_layout, new_layout),
+            // Memory moves between the global allocator and pages, so it has to be copied.
+            _ => {
+                let memory = self.allocate(new_layout)?;
+                let copied = old_layout.size().min(new_layout.size());
+                std::ptr::copy_nonoverlapping(ptr.as_ptr(), memory.cast::<u8>().as_ptr(), copied);
+                self.deallocate(ptr, old_layout);
+                Ok(memory)
+            }
+        }
+    }
+}
+#[cfg(test)]
+mod test {
+    use super::*;
+    #[test]
+    fn test_collections_in_pages() {
+        let allocator = PageAllocator::with_threshold(PAGE_SIZE);
+        let mut vec: Vec<u64, PageAllocator> = Vec::new_in(allocator);
+        // Grows from the global allocator into pages, and then between page sized allocations.
+        for i in 0..0x40_000 {
+            vec.push(i);
+        }
+        assert!(vec.iter().enumerate().all(|(i, v)| *v == i as u64));
+        vec.truncate(0x10);
+        // Shrinks back from pages into the global allocator.
+        vec.shrink_to_fit();
+        assert_eq!(vec, (0..0x10).collect::<Vec<_>>());
+        let boxed: Box<[u8; 0x10_000], PageAllocator> = Box::new_in([7; 0x10_000], allocator);
+        assert!((&raw const *boxed as usize).is_multiple_of(PAGE_SIZE));
+        // Bytes past the end of a shrunk allocation are zeroed when it grows again.
+        let layout = Layout::from_size_align(0x1800, 8).unwrap();
+        let small = Layout::from_size_align(0x1100, 8).unwrap();
+        unsafe {
+            let memory = allocator.allocate(layout).unwrap().cast::<u8>();
+            memory.write_bytes(0xFF, layout.size());
+            let memory = allocator
+                .shrink(memory, layout, small)
+                .unwrap()
+                .cast::<u8>();
+            let memory = allocator.grow_zeroed(memory, small, layout).unwrap();
+            assert!(memory.as_ref()[small.size()..layout.size()]
+                .iter()
+                .all(|byte| *byte == 0));
+            allocator.deallocate(memory.cast(), layout);
+        }
+    }
+}
@@ -0,0 +1,223 @@
+use crate::dyn_pages::protect_range;
+use crate::page_math::align_up;
+use crate::*;
+use std::alloc::Layout;
+use std::cell::{Cell, RefCell};
+use std::fmt::{Debug, Formatter};
+/// A bump allocator, which hands out memory from a single reservation of address space, committing its pages as they
+/// are needed. Allocating is just a bounds check and a pointer bump, and everything allocated is freed at once with
+/// [`Self::reset`], which makes [`PageArena`] a good fit for data with a common lifetime, like an AST of a compilation
+/// unit or per-frame data of a game.
+///
+/// Values are allocated through a shared reference, so many of them can be borrowed at once. Their destructors are
+/// never run, so types owning other resources leak them. Each allocation is disjoint from all others, so handing out
+/// mutable references from a shared one is sound.
+/// # Examples
+/// ```
+/// # use memory_pages::*;
+/// // 16 GiB of address space, only backed by memory once used.
+/// let mut arena = PageArena::new(0x4_0000_0000);
+/// let a = arena.alloc(1_u64);
+/// let name = arena.alloc_str("node");
+/// let children = arena.alloc_slice_copy(&[2_u32, 3, 4]);
+/// *a += 1;
+/// assert_eq!((*a, &*name, &*children), (2, "node", &[2, 3, 4][..]));
+/// arena.reset();
+/// assert_eq!(arena.allocated_bytes(), 0);
+/// ```
+pub struct PageArena {
+    /// Reserved address space. Only borrowed to commit more of it.
+    memory: RefCell<SparsePages>,
+    base: NonNull<u8>,
+    /// Number of bytes at the beginning of `memory` that are committed.
+    committed: Cell<usize>,
+    /// Number of bytes at the beginning of `memory` that were handed out.
+    used: Cell<usize>,
+}
+// Allocations borrow the arena, so none of them can outlive a move to another thread.
+unsafe impl Send for PageArena {}
+impl PageArena {
+    /// Creates a new, empty [`PageArena`], reserving at least `max_size` bytes of address space without committing any
+    /// of it.
+    /// # Panics
+    /// Panics if `max_size` is 0, or if kernel can't/refuses to reserve address space.
+    #[must_use]
+    pub fn new(max_size: usize) -> Self {
+        let mut memory = SparsePages::new(max_size);
+        let base = NonNull::new(memory.as_mut_ptr()).expect("reservation is never null");
+        Self {
+            memory: RefCell::new(memory),
+            base,
+            committed: Cell::new(0),
+            used: Cell::new(0),
+        }
+    }
+    /// Returns the number of bytes this [`PageArena`] can ever hand out, including padding needed for alignment.
+    #[must_use]
+    pub fn max_size(&self) -> usize {
+        self.memory.borrow().len()
+    }
+    /// Returns the number of bytes handed out since the last reset, including padding needed for alignment.
+    #[must_use]
+    pub fn allocated_bytes(&self) -> usize {
+        self.used.get()
+    }
+    /// Returns the number of bytes backed by memory.
+    #[must_use]
+    pub fn committed_bytes(&self) -> usize {
+        self.committed.get()
+    }
+    /// Moves `t` into this [`PageArena`], and returns a reference to it.
+    /// # Panics
+    /// Panics if the reservation is exhausted, or if kernel can't/refuses to commit more pages.
+    #[allow(clippy::mut_from_ref)]
+    pub fn alloc<T>(&self, t: T) -> &mut T {
+        let ptr = self.alloc_layout(Layout::new::<T>()).cast::<T>();
+        unsafe {
+            ptr.as_ptr().write(t);
+            &mut *ptr.as_ptr()
+        }
+    }
+    /// Copies `slice` into this [`PageArena`], and returns a reference to the copy.
+    /// # Panics
+    /// Panics if the reservation is exhausted, or if kernel can't/refuses to commit more pages.
+    #[allow(clippy::mut_from_ref)]
+    pub fn alloc_slice_copy<T: Copy>(&self, slice: &[T]) -> &mut [T] {
+        let layout = Layout::for_value(slice);
+        let ptr = self.alloc_layout(layout).cast::<T>();
+        unsafe {
+            std::ptr::copy_nonoverlapping(slice.as_ptr(), ptr.as_ptr(), slice.len());
+            std::slice::from_raw_parts_mut(ptr.as_ptr(), slice.len())
+        }
+    }
+    /// Copies `s` into this [`PageArena`], and returns a reference to the copy.
+    /// # Panics
+    /// Panics if the reservation is exhausted, or if kernel can't/refuses to commit more pages.
+    #[allow(clippy::mut_from_ref)]
+    pub fn alloc_str(&self, s: &str) -> &mut str {
+        let bytes = self.alloc_slice_copy(s.as_bytes());
+        unsafe { std::str::from_utf8_unchecked_mut(bytes) }
+    }
+    /// Allocates uninitialized memory fitting `layout`.
+    /// # Panics
+    /// Panics if the reservation is exhausted, or if kernel can't/refuses to commit more pages.
+    pub fn alloc_layout(&self, layout: Layout) -> NonNull<u8> {
+        self.try_alloc_layout(layout)
+            .unwrap_or_else(|| panic!("Reservation of PageArena exhausted!"))
+    }
+    /// Allocates uninitialized memory fitting `layout`, or returns `None` if the reservation is exhausted.
+    /// # Panics
+    /// Panics if kernel can't/refuses to commit more pages.
+    /// # Examples
+    /// ```
+    /// # use memory_pages::*;
+    /// # use std::alloc::Layout;
+    /// let arena = PageArena::new(0x1000);
+    /// assert!(arena.try_alloc_layout(Layout::new::<[u8; 0x800]>()).is_some());
+    /// assert!(arena.try_alloc_layout(Layout::new::<[u8; 0x1000]>()).is_none());
+    /// ```
+    #[must_use]
+    pub fn try_alloc_layout(&self, layout: Layout) -> Option<NonNull<u8>> {
+        let used = self.used.get();
+        // Alignment is computed on the address, since it may exceed the alignment of the reservation.
+        let address = self.base.as_ptr() as usize + used;
+        let start = address.checked_next_multiple_of(layout.align())? - self.base.as_ptr() as usize;
+        let end = start
+            .checked_add(layout.size())
+            .filter(|end| *end <= self.max_size())?;
+        self.commit_for(end);
+        let ptr = unsafe { self.base.add(start) };
+        sanitizer::unpoison(ptr.as_ptr(), layout.size());
+        self.used.set(end);
+        Some(ptr)
+    }
+    /// Frees everything allocated in this [`PageArena`] at once. Committed pages are kept, so that they can be reused
+    /// without faulting them in again.
+    pub fn reset(&mut self) {
+        self.used.set(0);
+        sanitizer::poison(self.base.as_ptr(), self.committed.get());
+    }
+    /// Frees everything allocated in this [`PageArena`] at once, and returns all of the committed memory to the kernel.
+    /// # Examples
+    /// ```
+    /// # use memory_pages::*;
+    /// let mut arena = PageArena::new(0x100_000);
+    /// arena.alloc_slice_copy(&[0_u8; 0x10_000]);
+    /// assert!(arena.committed_bytes() >= 0x10_000);
+    /// arena.reset_decommit();
+    /// assert_eq!(arena.committed_bytes(), 0);
+    /// ```
+    pub fn reset_decommit(&mut self) {
+        self.used.set(0);
+        let committed = self.committed.replace(0);
+        sanitizer::unpoison(self.base.as_ptr(), committed);
+        if committed != 0 {
+            self.memory.get_mut().decommit(0, committed);
+        }
+    }
+    /// Makes all committed pages read-only. Nothing may be allocated from this [`PageArena`] afterwards.
+    pub(crate) fn protect_read_only(&self) -> Result<(), ProtectionError> {
+        let committed = self.committed.get();
+        if committed == 0 {
+            return Ok(());
+        }
+        protect_range(self.base.as_ptr(), committed, Protection::Read)
+    }
+    /// Makes sure pages holding the first `len` bytes are committed.
+    fn commit_for(&self, len: usize) {
+        let committed = self.committed.get();
+        if len <= committed {
+            return;
+        }
+        let mut memory = self.memory.borrow_mut();
+        let bytes = align_up(len)
+            .max(committed.saturating_mul(2))
+            .min(memory.len());
+        memory.commit(committed, bytes - committed);
+        sanitizer::poison(
+            self.base.as_ptr().wrapping_add(committed),
+            bytes - committed,
+        );
+        self.committed.set(bytes);
+    }
+}
+impl Debug for PageArena {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("PageArena")
+            .field("allocated_bytes", &self.allocated_bytes())
+            .field("committed_bytes", &self.committed_bytes())
+            .field("max_size", &self.max_size())
+            .finish()
+    }
+}
+impl Drop for PageArena {
+    fn drop(&mut self) {
+        // Address range is unmapped, and must not stay poisoned once it is reused.
+        sanitizer::unpoison(self.base.as_ptr(), self.committed.get());
+    }
+}
+#[cfg(test)]
+mod test {
+    use super::*;
+    #[test]
+    fn test_arena_reset() {
+        let mut arena = PageArena::new(0x1_0000_0000);
+        let values: Vec<&mut u64> = (0..0x10_000).map(|i| arena.alloc(i)).collect();
+        assert!(values.iter().enumerate().all(|(i, v)| **v == i as u64));
+        let page = arena.alloc_layout(Layout::from_size_align(1, 0x10_000).unwrap());
+        assert!((page.as_ptr() as usize).is_multiple_of(0x10_000));
+        let first = arena.alloc_str("first") as *const str;
+        assert_eq!(unsafe { &*first }, "first");
+        let committed = arena.committed_bytes();
+        arena.reset();
+        assert_eq!(arena.allocated_bytes(), 0);
+        assert_eq!(arena.committed_bytes(), committed);
+        // Memory is reused after a reset.
+        let reused = arena.alloc(7_u64);
+        assert_eq!(arena.allocated_bytes(), 8);
+        assert_eq!(*reused, 7);
+        arena.reset_decommit();
+        assert_eq!(arena.committed_bytes(), 0);
+        assert_eq!(arena.alloc_slice_copy(&[1_u16, 2, 3]), [1, 2, 3]);
+    }
+}
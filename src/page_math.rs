@@ -0,0 +1,160 @@
+//! Helpers for rounding sizes and addresses to page boundaries, used by [`crate::Pages`] internally.
+/// Size of a single memory page, in bytes.
+pub const PAGE_SIZE: usize = 0x1000;
+/// Rounds `size` up to the next page boundary. Sizes already aligned to a page boundary are left unchanged.
+/// # Examples
+/// ```
+/// # use memory_pages::page_math::*;
+/// assert_eq!(align_up(0), 0);
+/// assert_eq!(align_up(1), PAGE_SIZE);
+/// assert_eq!(align_up(PAGE_SIZE), PAGE_SIZE);
+/// assert_eq!(align_up(PAGE_SIZE + 1), 2 * PAGE_SIZE);
+/// ```
+#[must_use]
+pub const fn align_up(size: usize) -> usize {
+    size.div_ceil(PAGE_SIZE) * PAGE_SIZE
+}
+/// Rounds `size` down to the previous page boundary. Sizes already aligned to a page boundary are left unchanged.
+/// # Examples
+/// ```
+/// # use memory_pages::page_math::*;
+/// assert_eq!(align_down(PAGE_SIZE - 1), 0);
+/// assert_eq!(align_down(PAGE_SIZE), PAGE_SIZE);
+/// assert_eq!(align_down(2 * PAGE_SIZE + 5), 2 * PAGE_SIZE);
+/// ```
+#[must_use]
+pub const fn align_down(size: usize) -> usize {
+    (size / PAGE_SIZE) * PAGE_SIZE
+}
+/// Returns the number of pages needed to store `len` bytes.
+/// # Examples
+/// ```
+/// # use memory_pages::page_math::*;
+/// assert_eq!(page_count(0), 0);
+/// assert_eq!(page_count(1), 1);
+/// assert_eq!(page_count(3 * PAGE_SIZE), 3);
+/// assert_eq!(page_count(3 * PAGE_SIZE + 1), 4);
+/// ```
+#[must_use]
+pub const fn page_count(len: usize) -> usize {
+    len.div_ceil(PAGE_SIZE)
+}
+/// Returns how many values of type `T` fit in a single page. Useful for sizing arrays of records filling exactly one
+/// page, like `PageAligned<[T; per_page::<T>()]>`.
+/// # Examples
+/// ```
+/// # use memory_pages::page_math::*;
+/// assert_eq!(per_page::<u64>(), PAGE_SIZE / 8);
+/// assert_eq!(per_page::<[u8; 3000]>(), 1);
+/// ```
+#[must_use]
+pub const fn per_page<T>() -> usize {
+    PAGE_SIZE / std::mem::size_of::<T>()
+}
+/// Checks if `ptr` points to the beginning of a page.
+/// # Examples
+/// ```
+/// # use memory_pages::*;
+/// # use memory_pages::page_math::*;
+/// let memory:Pages<AllowRead,AllowWrite,DenyExec> = Pages::new(0x2000);
+/// // Pages always start at a page boundary.
+/// assert!(is_page_aligned(memory.get_ptr(0)));
+/// assert!(!is_page_aligned(memory.get_ptr(1)));
+/// assert!(is_page_aligned(memory.get_ptr(PAGE_SIZE)));
+/// ```
+#[must_use]
+pub fn is_page_aligned<T>(ptr: *const T) -> bool {
+    (ptr as usize).is_multiple_of(PAGE_SIZE)
+}
+/// Splits `slice` into sub-slices at page boundaries. Each returned sub-slice starts either at the beginning of `slice`,
+/// or at the first element starting in a new page, so no two sub-slices have elements starting in the same page.
+/// If size of `T` does not divide [`PAGE_SIZE`], the last element of a sub-slice may reach into the next page.
+/// # Examples
+/// ```
+/// # use memory_pages::*;
+/// # use memory_pages::page_math::*;
+/// let memory:Pages<AllowRead,AllowWrite,DenyExec> = Pages::new(0x3000);
+/// // Slice starting in the middle of the first page, and ending in the middle of the last one.
+/// let slice = memory.get(0x800..0x2800).unwrap();
+/// let lens:Vec<usize> = page_ranges(slice).map(|range|range.len()).collect();
+/// assert_eq!(lens, [0x800, 0x1000, 0x800]);
+/// ```
+pub fn page_ranges<T>(slice: &[T]) -> PageRanges<'_, T> {
+    PageRanges { rem: slice }
+}
+/// Iterator over sub-slices of a slice split at page boundaries, created by [`page_ranges`].
+pub struct PageRanges<'a, T> {
+    rem: &'a [T],
+}
+/// Returns how many of `len` elements of type `T`, starting at `start`, start in the same page as the first one.
+fn in_first_page<T>(start: *const T, len: usize) -> usize {
+    let elem_size = std::mem::size_of::<T>();
+    if elem_size == 0 {
+        return len;
+    }
+    let start = start as usize;
+    let next_boundary = align_down(start) + PAGE_SIZE;
+    (next_boundary - start).div_ceil(elem_size).min(len)
+}
+impl<'a, T> Iterator for PageRanges<'a, T> {
+    type Item = &'a [T];
+    fn next(&mut self) -> Option<&'a [T]> {
+        if self.rem.is_empty() {
+            return None;
+        }
+        let (curr, rem) = self
+            .rem
+            .split_at(in_first_page(self.rem.as_ptr(), self.rem.len()));
+        self.rem = rem;
+        Some(curr)
+    }
+}
+/// Works exactly like [`page_ranges`], but yields mutable sub-slices.
+/// # Examples
+/// ```
+/// # use memory_pages::*;
+/// # use memory_pages::page_math::*;
+/// let mut memory:Pages<AllowRead,AllowWrite,DenyExec> = Pages::new(0x2000);
+/// for (i, page) in page_ranges_mut(&mut memory).enumerate() {
+///     page.fill(i as u8);
+/// }
+/// assert_eq!(memory[0x1FFF], 1);
+/// ```
+pub fn page_ranges_mut<T>(slice: &mut [T]) -> PageRangesMut<'_, T> {
+    PageRangesMut { rem: slice }
+}
+/// Iterator over mutable sub-slices of a slice split at page boundaries, created by [`page_ranges_mut`].
+pub struct PageRangesMut<'a, T> {
+    rem: &'a mut [T],
+}
+impl<'a, T> Iterator for PageRangesMut<'a, T> {
+    type Item = &'a mut [T];
+    fn next(&mut self) -> Option<&'a mut [T]> {
+        if self.rem.is_empty() {
+            return None;
+        }
+        let in_page = in_first_page(self.rem.as_ptr(), self.rem.len());
+        let (curr, rem) = std::mem::take(&mut self.rem).split_at_mut(in_page);
+        self.rem = rem;
+        Some(curr)
+    }
+}
+#[cfg(test)]
+mod test {
+    use super::*;
+    #[test]
+    fn test_page_ranges_straddling() {
+        let memory: crate::Pages<crate::AllowRead, crate::AllowWrite, crate::DenyExec> =
+            crate::Pages::new(0x3000);
+        let slice: &[[u8; 3]] =
+            unsafe { std::slice::from_raw_parts(memory.get_ptr(0).cast(), 0x3000 / 3) };
+        let ranges: Vec<&[[u8; 3]]> = page_ranges(slice).collect();
+        assert_eq!(ranges.iter().map(|r| r.len()).sum::<usize>(), slice.len());
+        for range in &ranges[1..] {
+            // Each range after the first one starts in a new page.
+            let start = range.as_ptr() as usize;
+            assert!(start - align_down(start) < 3);
+        }
+        assert_eq!(ranges.len(), 3);
+    }
+}
@@ -0,0 +1,733 @@
+use crate::page_math::PAGE_SIZE;
+use crate::*;
+use std::borrow::Borrow;
+use std::cmp::Ordering;
+use std::fmt::{Debug, Formatter};
+use std::mem::{align_of, size_of};
+use std::ops::{Bound, Index, RangeBounds};
+/// Size of the node header: the number of entries, and whether the node is a leaf.
+const HEADER: usize = 8;
+/// Index of a node, which is also the index of the page holding it.
+type NodeId = u32;
+/// An ordered map, implemented as a B-tree in which each node occupies exactly one page. Nodes are page aligned and
+/// never share a page, so visiting a node touches exactly one cache-aligned page and one TLB entry, which makes the
+/// cost of lookups in very large sorted indexes predictable. All nodes live in one region of [`Pages`], and refer to
+/// each other by index instead of by pointer.
+///
+/// Each node holds up to [`Self::NODE_CAPACITY`] entries, which depends on the sizes of keys and values: for `u64`
+/// keys and values it is 203, so a tree of 4 levels holds over a billion entries.
+/// # Examples
+/// ```
+/// # use memory_pages::*;
+/// let mut index: PagedBTreeMap<u64, u32> = PagedBTreeMap::new();
+/// for i in 0..0x10_000 {
+///     index.insert(i * 3, i as u32);
+/// }
+/// assert_eq!(index.get(&300), Some(&100));
+/// assert_eq!(index.get(&301), None);
+/// // Entries are kept sorted by key.
+/// let keys: Vec<_> = index.range(10..20).map(|(k, _)| *k).collect();
+/// assert_eq!(keys, [12, 15, 18]);
+/// assert_eq!(index.remove(&15), Some(5));
+/// assert_eq!(index.first_key_value(), Some((&0, &0)));
+/// ```
+pub struct PagedBTreeMap<K, V> {
+    /// Node storage, allocated on first insertion.
+    pages: Option<Pages<AllowRead, AllowWrite, DenyExec>>,
+    root: Option<NodeId>,
+    /// Number of nodes ever allocated, including freed ones.
+    nodes: u32,
+    /// Freed nodes, reused before allocating new ones.
+    free: Vec<NodeId>,
+    len: usize,
+    pd: PhantomData<(K, V)>,
+}
+impl<K, V> PagedBTreeMap<K, V> {
+    /// Maximal number of entries in a single node.
+    pub const NODE_CAPACITY: usize = Self::node_capacity();
+    /// Minimal number of entries in a node other than the root.
+    const MIN: usize = Self::NODE_CAPACITY / 2;
+    const KEYS: usize = HEADER.next_multiple_of(align_of::<K>());
+    const VALUES: usize = Self::values_offset(Self::NODE_CAPACITY);
+    const CHILDREN: usize = Self::children_offset(Self::NODE_CAPACITY);
+    const fn values_offset(capacity: usize) -> usize {
+        (Self::KEYS + capacity * size_of::<K>()).next_multiple_of(align_of::<V>())
+    }
+    const fn children_offset(capacity: usize) -> usize {
+        (Self::values_offset(capacity) + capacity * size_of::<V>())
+            .next_multiple_of(align_of::<NodeId>())
+    }
+    /// Returns the largest odd number of entries, such that a node holding them, and a child index for each gap
+    /// between them, fits in a page.
+    const fn node_capacity() -> usize {
+        let entry = size_of::<K>() + size_of::<V>() + size_of::<NodeId>();
+        let mut capacity = (PAGE_SIZE - HEADER - size_of::<NodeId>()) / entry;
+        while capacity > 0
+            && Self::children_offset(capacity) + (capacity + 1) * size_of::<NodeId>() > PAGE_SIZE
+        {
+            capacity -= 1;
+        }
+        if capacity > 0 && capacity.is_multiple_of(2) {
+            capacity -= 1;
+        }
+        capacity
+    }
+    /// Creates a new, empty [`PagedBTreeMap`], without allocating any memory.
+    /// # Panics
+    /// Panics if less than 3 entries fit in a page.
+    #[must_use]
+    pub fn new() -> Self {
+        assert!(
+            Self::NODE_CAPACITY >= 3
+                && align_of::<K>() <= PAGE_SIZE
+                && align_of::<V>() <= PAGE_SIZE,
+            "Entries of PagedBTreeMap must fit at least 3 to a page!"
+        );
+        Self {
+            pages: None,
+            root: None,
+            nodes: 0,
+            free: Vec::new(),
+            len: 0,
+            pd: PhantomData,
+        }
+    }
+    /// Returns the number of entries in this [`PagedBTreeMap`].
+    #[must_use]
+    pub fn len(&self) -> usize {
+        self.len
+    }
+    /// Checks if this [`PagedBTreeMap`] has no entries.
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+    /// Returns the number of nodes in the tree, which is also the number of pages holding entries.
+    #[must_use]
+    pub fn node_count(&self) -> usize {
+        self.nodes as usize - self.free.len()
+    }
+    /// Removes all entries, and releases all memory.
+    pub fn clear(&mut self) {
+        self.drop_entries();
+        self.pages = None;
+        self.root = None;
+        self.nodes = 0;
+        self.free.clear();
+        self.len = 0;
+    }
+    /// Returns an iterator over all entries, sorted by key.
+    pub fn iter(&self) -> PagedBTreeRange<'_, K, V> {
+        PagedBTreeRange {
+            map: self,
+            stack: self
+                .root
+                .map(|root| self.leftmost(root))
+                .unwrap_or_default(),
+            end: None,
+            len: self.len,
+        }
+    }
+    /// Returns an iterator over all keys, in sorted order.
+    pub fn keys(&self) -> impl Iterator<Item = &K> + '_ {
+        self.iter().map(|(k, _)| k)
+    }
+    /// Returns an iterator over all values, sorted by their keys.
+    pub fn values(&self) -> impl Iterator<Item = &V> + '_ {
+        self.iter().map(|(_, v)| v)
+    }
+    /// Returns the entry with the smallest key, or `None` if this [`PagedBTreeMap`] is empty.
+    #[must_use]
+    pub fn first_key_value(&self) -> Option<(&K, &V)> {
+        let mut node = self.root?;
+        while !self.is_leaf(node) {
+            node = self.child(node, 0);
+        }
+        Some(self.entry(node, 0))
+    }
+    /// Returns the entry with the largest key, or `None` if this [`PagedBTreeMap`] is empty.
+    #[must_use]
+    pub fn last_key_value(&self) -> Option<(&K, &V)> {
+        let mut node = self.root?;
+        while !self.is_leaf(node) {
+            node = self.child(node, self.node_len(node));
+        }
+        Some(self.entry(node, self.node_len(node) - 1))
+    }
+    fn node(&self, node: NodeId) -> *mut u8 {
+        let pages = self
+            .pages
+            .as_ref()
+            .expect("nodes exist only once pages are allocated");
+        unsafe { pages.get_ptr_unchecked().add(node as usize * PAGE_SIZE) }
+    }
+    fn node_len(&self, node: NodeId) -> usize {
+        unsafe { self.node(node).cast::<u32>().read() as usize }
+    }
+    fn set_node_len(&mut self, node: NodeId, len: usize) {
+        unsafe { self.node(node).cast::<u32>().write(len as u32) };
+    }
+    fn is_leaf(&self, node: NodeId) -> bool {
+        unsafe { self.node(node).add(4).cast::<u32>().read() != 0 }
+    }
+    fn key(&self, node: NodeId, i: usize) -> *mut K {
+        unsafe { self.node(node).add(Self::KEYS).cast::<K>().add(i) }
+    }
+    fn value(&self, node: NodeId, i: usize) -> *mut V {
+        unsafe { self.node(node).add(Self::VALUES).cast::<V>().add(i) }
+    }
+    fn children(&self, node: NodeId) -> *mut NodeId {
+        unsafe { self.node(node).add(Self::CHILDREN).cast::<NodeId>() }
+    }
+    fn child(&self, node: NodeId, i: usize) -> NodeId {
+        unsafe { self.children(node).add(i).read() }
+    }
+    fn entry(&self, node: NodeId, i: usize) -> (&K, &V) {
+        unsafe { (&*self.key(node, i), &*self.value(node, i)) }
+    }
+    /// Returns the path to the leftmost entry of the subtree rooted at `node`.
+    fn leftmost(&self, mut node: NodeId) -> Vec<(NodeId, usize)> {
+        let mut stack = vec![(node, 0)];
+        while !self.is_leaf(node) {
+            node = self.child(node, 0);
+            stack.push((node, 0));
+        }
+        stack
+    }
+    fn alloc_node(&mut self, leaf: bool) -> NodeId {
+        let node = self.free.pop().unwrap_or_else(|| {
+            let node = self.nodes;
+            self.nodes = node
+                .checked_add(1)
+                .unwrap_or_else(|| panic!("Maximal capacity of PagedBTreeMap exceeded!"));
+            let required = self.nodes as usize * PAGE_SIZE;
+            match &mut self.pages {
+                None => self.pages = Some(Pages::new(PAGE_SIZE * 0x10)),
+                Some(pages) if pages.len() < required => pages
+                    .try_resize(pages.len() * 2)
+                    .unwrap_or_else(|err| panic!("Growing PagedBTreeMap failed:'{err}'!")),
+                Some(_) => (),
+            }
+            node
+        });
+        unsafe {
+            self.node(node)
+                .cast::<[u32; 2]>()
+                .write([0, u32::from(leaf)])
+        };
+        node
+    }
+    /// Moves `len` entries of `node` from index `src` to index `dst`. The ranges may overlap.
+    fn shift(&mut self, node: NodeId, src: usize, dst: usize, len: usize) {
+        unsafe {
+            std::ptr::copy(self.key(node, src), self.key(node, dst), len);
+            std::ptr::copy(self.value(node, src), self.value(node, dst), len);
+        }
+    }
+    fn shift_children(&mut self, node: NodeId, src: usize, dst: usize, len: usize) {
+        let children = self.children(node);
+        unsafe { std::ptr::copy(children.add(src), children.add(dst), len) };
+    }
+    fn insert_entry(&mut self, node: NodeId, i: usize, k: K, v: V) {
+        let len = self.node_len(node);
+        self.shift(node, i, i + 1, len - i);
+        unsafe {
+            self.key(node, i).write(k);
+            self.value(node, i).write(v);
+        }
+        self.set_node_len(node, len + 1);
+    }
+    fn remove_entry(&mut self, node: NodeId, i: usize) -> (K, V) {
+        let len = self.node_len(node);
+        let entry = unsafe { (self.key(node, i).read(), self.value(node, i).read()) };
+        self.shift(node, i + 1, i, len - i - 1);
+        self.set_node_len(node, len - 1);
+        entry
+    }
+    fn replace_entry(&mut self, node: NodeId, i: usize, k: K, v: V) -> (K, V) {
+        unsafe { (self.key(node, i).replace(k), self.value(node, i).replace(v)) }
+    }
+    /// Moves `len` entries from node `src` at index `from` to node `dst` at index `to`.
+    fn move_entries(&mut self, src: NodeId, from: usize, dst: NodeId, to: usize, len: usize) {
+        unsafe {
+            std::ptr::copy_nonoverlapping(self.key(src, from), self.key(dst, to), len);
+            std::ptr::copy_nonoverlapping(self.value(src, from), self.value(dst, to), len);
+        }
+    }
+    /// Splits the full child `i` of `parent` in two, moving its median entry into `parent`.
+    fn split_child(&mut self, parent: NodeId, i: usize) {
+        let left = self.child(parent, i);
+        let leaf = self.is_leaf(left);
+        let right = self.alloc_node(leaf);
+        self.move_entries(left, Self::MIN + 1, right, 0, Self::MIN);
+        if !leaf {
+            unsafe {
+                std::ptr::copy_nonoverlapping(
+                    self.children(left).add(Self::MIN + 1),
+                    self.children(right),
+                    Self::MIN + 1,
+                );
+            }
+        }
+        self.set_node_len(left, Self::MIN);
+        self.set_node_len(right, Self::MIN);
+        let (k, v) = unsafe {
+            (
+                self.key(left, Self::MIN).read(),
+                self.value(left, Self::MIN).read(),
+            )
+        };
+        let len = self.node_len(parent);
+        self.shift_children(parent, i + 1, i + 2, len - i);
+        unsafe { self.children(parent).add(i + 1).write(right) };
+        self.insert_entry(parent, i, k, v);
+    }
+    /// Merges children `i` and `i + 1` of `parent`, both of which have the minimal number of entries, together with
+    /// entry `i` of `parent` separating them.
+    fn merge_children(&mut self, parent: NodeId, i: usize) {
+        let left = self.child(parent, i);
+        let right = self.child(parent, i + 1);
+        let (k, v) = self.remove_entry(parent, i);
+        let parent_len = self.node_len(parent);
+        self.shift_children(parent, i + 2, i + 1, parent_len - i);
+        unsafe {
+            self.key(left, Self::MIN).write(k);
+            self.value(left, Self::MIN).write(v);
+        }
+        self.move_entries(right, 0, left, Self::MIN + 1, Self::MIN);
+        if !self.is_leaf(left) {
+            unsafe {
+                std::ptr::copy_nonoverlapping(
+                    self.children(right),
+                    self.children(left).add(Self::MIN + 1),
+                    Self::MIN + 1,
+                );
+            }
+        }
+        self.set_node_len(left, Self::NODE_CAPACITY);
+        self.free.push(right);
+    }
+    /// Makes sure child `i` of `parent` has more than the minimal number of entries, by taking one from a sibling, or
+    /// merging it with one. Returns the index of the child that now covers the keys child `i` did.
+    fn fill_child(&mut self, parent: NodeId, i: usize) -> usize {
+        let node = self.child(parent, i);
+        let len = self.node_len(node);
+        if len > Self::MIN {
+            return i;
+        }
+        let leaf = self.is_leaf(node);
+        let parent_len = self.node_len(parent);
+        if i > 0 && self.node_len(self.child(parent, i - 1)) > Self::MIN {
+            // Rotates the last entry of the left sibling through the parent.
+            let left = self.child(parent, i - 1);
+            let left_len = self.node_len(left);
+            let (k, v) = self.remove_entry(left, left_len - 1);
+            let (k, v) = self.replace_entry(parent, i - 1, k, v);
+            self.insert_entry(node, 0, k, v);
+            if !leaf {
+                self.shift_children(node, 0, 1, len + 1);
+                let moved = self.child(left, left_len);
+                unsafe { self.children(node).write(moved) };
+            }
+            i
+        } else if i < parent_len && self.node_len(self.child(parent, i + 1)) > Self::MIN {
+            // Rotates the first entry of the right sibling through the parent.
+            let right = self.child(parent, i + 1);
+            let right_len = self.node_len(right);
+            let (k, v) = self.remove_entry(right, 0);
+            let (k, v) = self.replace_entry(parent, i, k, v);
+            self.insert_entry(node, len, k, v);
+            if !leaf {
+                let moved = self.child(right, 0);
+                unsafe { self.children(node).add(len + 1).write(moved) };
+                self.shift_children(right, 1, 0, right_len);
+            }
+            i
+        } else if i < parent_len {
+            self.merge_children(parent, i);
+            i
+        } else {
+            self.merge_children(parent, i - 1);
+            i - 1
+        }
+    }
+    /// Removes the first or last entry of the subtree rooted at `node`, which must have more than the minimal number
+    /// of entries.
+    fn pop_extreme(&mut self, mut node: NodeId, last: bool) -> (K, V) {
+        while !self.is_leaf(node) {
+            let i = if last { self.node_len(node) } else { 0 };
+            let i = self.fill_child(node, i);
+            node = self.child(node, i);
+        }
+        let i = if last { self.node_len(node) - 1 } else { 0 };
+        self.remove_entry(node, i)
+    }
+    fn drop_entries(&mut self) {
+        if !std::mem::needs_drop::<(K, V)>() {
+            return;
+        }
+        let mut stack: Vec<NodeId> = self.root.into_iter().collect();
+        while let Some(node) = stack.pop() {
+            let len = self.node_len(node);
+            if !self.is_leaf(node) {
+                stack.extend((0..=len).map(|i| self.child(node, i)));
+            }
+            for i in 0..len {
+                unsafe {
+                    self.key(node, i).drop_in_place();
+                    self.value(node, i).drop_in_place();
+                }
+            }
+        }
+    }
+}
+impl<K: Ord, V> PagedBTreeMap<K, V> {
+    /// Inserts `v` under key `k`, returning the previous value under `k`, if there was one. The key is not updated in
+    /// that case.
+    /// # Panics
+    /// Panics if kernel can't/refuses to allocate memory for a new node.
+    pub fn insert(&mut self, k: K, v: V) -> Option<V> {
+        let root = match self.root {
+            Some(root) if self.node_len(root) == Self::NODE_CAPACITY => {
+                let new_root = self.alloc_node(false);
+                unsafe { self.children(new_root).write(root) };
+                self.split_child(new_root, 0);
+                self.root = Some(new_root);
+                new_root
+            }
+            Some(root) => root,
+            None => {
+                let root = self.alloc_node(true);
+                self.root = Some(root);
+                root
+            }
+        };
+        // Full nodes are split on the way down, so there is always room for the entry once it reaches a leaf.
+        let mut node = root;
+        loop {
+            let mut i = match self.search(node, &k) {
+                Ok(i) => return Some(unsafe { self.value(node, i).replace(v) }),
+                Err(i) => i,
+            };
+            if self.is_leaf(node) {
+                self.insert_entry(node, i, k, v);
+                self.len += 1;
+                return None;
+            }
+            if self.node_len(self.child(node, i)) == Self::NODE_CAPACITY {
+                self.split_child(node, i);
+                match k.cmp(unsafe { &*self.key(node, i) }) {
+                    Ordering::Equal => return Some(unsafe { self.value(node, i).replace(v) }),
+                    Ordering::Greater => i += 1,
+                    Ordering::Less => (),
+                }
+            }
+            node = self.child(node, i);
+        }
+    }
+    /// Returns a reference to the value under key `k`, or `None` if there is no such key.
+    #[must_use]
+    pub fn get<Q: Ord + ?Sized>(&self, k: &Q) -> Option<&V>
+    where
+        K: Borrow<Q>,
+    {
+        self.get_key_value(k).map(|(_, v)| v)
+    }
+    /// Returns references to the key and value of the entry under key `k`, or `None` if there is no such key.
+    #[must_use]
+    pub fn get_key_value<Q: Ord + ?Sized>(&self, k: &Q) -> Option<(&K, &V)>
+    where
+        K: Borrow<Q>,
+    {
+        let (node, i) = self.find(k)?;
+        Some(self.entry(node, i))
+    }
+    /// Returns a mutable reference to the value under key `k`, or `None` if there is no such key.
+    #[must_use]
+    pub fn get_mut<Q: Ord + ?Sized>(&mut self, k: &Q) -> Option<&mut V>
+    where
+        K: Borrow<Q>,
+    {
+        let (node, i) = self.find(k)?;
+        Some(unsafe { &mut *self.value(node, i) })
+    }
+    /// Checks if there is an entry under key `k`.
+    #[must_use]
+    pub fn contains_key<Q: Ord + ?Sized>(&self, k: &Q) -> bool
+    where
+        K: Borrow<Q>,
+    {
+        self.find(k).is_some()
+    }
+    /// Removes the entry under key `k`, and returns its value, or `None` if there is no such key.
+    pub fn remove<Q: Ord + ?Sized>(&mut self, k: &Q) -> Option<V>
+    where
+        K: Borrow<Q>,
+    {
+        self.remove_entry_by_key(k).map(|(_, v)| v)
+    }
+    /// Removes the entry under key `k`, and returns its key and value, or `None` if there is no such key.
+    pub fn remove_entry_by_key<Q: Ord + ?Sized>(&mut self, k: &Q) -> Option<(K, V)>
+    where
+        K: Borrow<Q>,
+    {
+        let root = self.root?;
+        // Nodes are refilled on the way down, so that removing an entry never leaves one with too few entries.
+        let mut node = root;
+        let entry = loop {
+            match self.search(node, k) {
+                Ok(i) if self.is_leaf(node) => break Some(self.remove_entry(node, i)),
+                Ok(i) => {
+                    let left = self.child(node, i);
+                    if self.node_len(left) > Self::MIN {
+                        let (k, v) = self.pop_extreme(left, true);
+                        break Some(self.replace_entry(node, i, k, v));
+                    }
+                    let right = self.child(node, i + 1);
+                    if self.node_len(right) > Self::MIN {
+                        let (k, v) = self.pop_extreme(right, false);
+                        break Some(self.replace_entry(node, i, k, v));
+                    }
+                    // The entry becomes the median of the merged node.
+                    self.merge_children(node, i);
+                    node = left;
+                }
+                Err(_) if self.is_leaf(node) => break None,
+                Err(i) => {
+                    let i = self.fill_child(node, i);
+                    node = self.child(node, i);
+                }
+            }
+        };
+        if self.node_len(root) == 0 {
+            self.free.push(root);
+            self.root = (!self.is_leaf(root)).then(|| self.child(root, 0));
+        }
+        if entry.is_some() {
+            self.len -= 1;
+        }
+        entry
+    }
+    /// Returns an iterator over entries with keys in `range`, sorted by key.
+    /// # Panics
+    /// Panics if start of `range` is greater than its end, or if they are equal and both excluded.
+    pub fn range<Q: Ord + ?Sized, R: RangeBounds<Q>>(&self, range: R) -> PagedBTreeRange<'_, K, V>
+    where
+        K: Borrow<Q>,
+    {
+        match (range.start_bound(), range.end_bound()) {
+            (Bound::Excluded(start), Bound::Excluded(end)) if start == end => {
+                panic!("Range start and end are equal and excluded in PagedBTreeMap!")
+            }
+            (
+                Bound::Included(start) | Bound::Excluded(start),
+                Bound::Included(end) | Bound::Excluded(end),
+            ) if start > end => panic!("Range start is greater than range end in PagedBTreeMap!"),
+            _ => (),
+        }
+        let mut end = self.seek(match range.end_bound() {
+            Bound::Included(k) => Bound::Excluded(k),
+            Bound::Excluded(k) => Bound::Included(k),
+            Bound::Unbounded => Bound::Unbounded,
+        });
+        let end = match range.end_bound() {
+            Bound::Unbounded => None,
+            _ => self.position(&mut end),
+        };
+        PagedBTreeRange {
+            map: self,
+            stack: self.seek(range.start_bound()),
+            end,
+            len: self.len,
+        }
+    }
+    /// Returns the index of the first entry of `node` with key not less than `k` if `k` is in it, or the index of the
+    /// child `k` could be in otherwise.
+    fn search<Q: Ord + ?Sized>(&self, node: NodeId, k: &Q) -> Result<usize, usize>
+    where
+        K: Borrow<Q>,
+    {
+        let keys = unsafe { std::slice::from_raw_parts(self.key(node, 0), self.node_len(node)) };
+        keys.binary_search_by(|key| key.borrow().cmp(k))
+    }
+    fn find<Q: Ord + ?Sized>(&self, k: &Q) -> Option<(NodeId, usize)>
+    where
+        K: Borrow<Q>,
+    {
+        let mut node = self.root?;
+        loop {
+            match self.search(node, k) {
+                Ok(i) => return Some((node, i)),
+                Err(_) if self.is_leaf(node) => return None,
+                Err(i) => node = self.child(node, i),
+            }
+        }
+    }
+    /// Returns the path to the first entry with key not less than `bound`(or greater, if it is excluded).
+    fn seek<Q: Ord + ?Sized>(&self, bound: Bound<&Q>) -> Vec<(NodeId, usize)>
+    where
+        K: Borrow<Q>,
+    {
+        let mut stack = Vec::new();
+        let Some(mut node) = self.root else {
+            return stack;
+        };
+        loop {
+            let i = match bound {
+                Bound::Unbounded => 0,
+                Bound::Included(k) => self.search(node, k).unwrap_or_else(|i| i),
+                Bound::Excluded(k) => self.search(node, k).map_or_else(|i| i, |i| i + 1),
+            };
+            stack.push((node, i));
+            if self.is_leaf(node) {
+                return stack;
+            }
+            node = self.child(node, i);
+        }
+    }
+    /// Returns the position of the entry `stack` leads to, or `None` if it leads past the last entry.
+    fn position(&self, stack: &mut Vec<(NodeId, usize)>) -> Option<(NodeId, usize)> {
+        while let Some(&(node, i)) = stack.last() {
+            if i < self.node_len(node) {
+                return Some((node, i));
+            }
+            stack.pop();
+        }
+        None
+    }
+}
+/// Iterator over entries of a [`PagedBTreeMap`], sorted by key. Created by [`PagedBTreeMap::iter`] and
+/// [`PagedBTreeMap::range`].
+pub struct PagedBTreeRange<'a, K, V> {
+    map: &'a PagedBTreeMap<K, V>,
+    /// Path to the next entry. Each element holds a node, and the index of the next entry to visit in it.
+    stack: Vec<(NodeId, usize)>,
+    /// Position of the first entry past the range, or `None` if it extends to the last entry.
+    end: Option<(NodeId, usize)>,
+    /// Upper bound on the number of entries left.
+    len: usize,
+}
+impl<'a, K, V> Iterator for PagedBTreeRange<'a, K, V> {
+    type Item = (&'a K, &'a V);
+    fn next(&mut self) -> Option<Self::Item> {
+        let map = self.map;
+        let (node, i) = loop {
+            let (node, i) = *self.stack.last()?;
+            if i < map.node_len(node) {
+                break (node, i);
+            }
+            self.stack.pop();
+        };
+        if self.end == Some((node, i)) {
+            self.stack.clear();
+            return None;
+        }
+        self.stack.last_mut().expect("stack is not empty").1 += 1;
+        if !map.is_leaf(node) {
+            self.stack.extend(map.leftmost(map.child(node, i + 1)));
+        }
+        self.len -= 1;
+        let (k, v) = map.entry(node, i);
+        Some((k, v))
+    }
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        if self.end.is_none() && self.stack.is_empty() {
+            (0, Some(0))
+        } else {
+            (0, Some(self.len))
+        }
+    }
+}
+impl<K, V> Default for PagedBTreeMap<K, V> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+impl<K: Ord + Borrow<Q>, Q: Ord + ?Sized, V> Index<&Q> for PagedBTreeMap<K, V> {
+    type Output = V;
+    fn index(&self, k: &Q) -> &V {
+        self.get(k).expect("key not present in PagedBTreeMap!")
+    }
+}
+impl<K: Ord, V> Extend<(K, V)> for PagedBTreeMap<K, V> {
+    fn extend<I: IntoIterator<Item = (K, V)>>(&mut self, iter: I) {
+        for (k, v) in iter {
+            self.insert(k, v);
+        }
+    }
+}
+impl<K: Ord, V> FromIterator<(K, V)> for PagedBTreeMap<K, V> {
+    fn from_iter<I: IntoIterator<Item = (K, V)>>(iter: I) -> Self {
+        let mut map = Self::new();
+        map.extend(iter);
+        map
+    }
+}
+impl<K: Debug, V: Debug> Debug for PagedBTreeMap<K, V> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_map().entries(self.iter()).finish()
+    }
+}
+impl<K, V> Drop for PagedBTreeMap<K, V> {
+    fn drop(&mut self) {
+        self.drop_entries();
+    }
+}
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::collections::BTreeMap;
+    #[test]
+    fn test_b_tree_matches_std() {
+        assert_eq!(PagedBTreeMap::<u64, u64>::NODE_CAPACITY, 203);
+        let mut map = PagedBTreeMap::new();
+        let mut expected = BTreeMap::new();
+        // A simple LCG, so that insertions and removals hit every rebalancing case.
+        let mut state = 1_u64;
+        for _ in 0..0x40_000 {
+            state = state
+                .wrapping_mul(6_364_136_223_846_793_005)
+                .wrapping_add(1);
+            let key = (state >> 40) % 0x8000;
+            if state & 0x300 == 0 {
+                assert_eq!(map.remove(&key), expected.remove(&key));
+            } else {
+                assert_eq!(map.insert(key, state), expected.insert(key, state));
+            }
+        }
+        assert_eq!(map.len(), expected.len());
+        assert!(map.iter().eq(expected.iter()));
+        assert!(map.range(100..=2000).eq(expected.range(100..=2000)));
+        assert!(map.range(..0x7000).eq(expected.range(..0x7000)));
+        assert_eq!(map.first_key_value(), expected.first_key_value());
+        assert_eq!(map.last_key_value(), expected.last_key_value());
+        let keys: Vec<u64> = expected.keys().copied().collect();
+        for key in keys {
+            assert_eq!(map.remove(&key), expected.remove(&key));
+        }
+        assert!(map.is_empty());
+        assert_eq!(map.node_count(), 0);
+        assert_eq!(map.iter().next(), None);
+    }
+    #[test]
+    fn test_b_tree_drops() {
+        let mut map: PagedBTreeMap<String, Vec<u8>> = (0..1000)
+            .map(|i| (format!("{i:04}"), vec![0; i % 7]))
+            .collect();
+        assert_eq!(map.remove("0500"), Some(vec![0; 3]));
+        assert_eq!(map["0999"].len(), 5);
+        map.get_mut("0010").unwrap().push(1);
+        assert_eq!(map.keys().nth(10).unwrap(), "0010");
+        map.clear();
+        assert!(map.is_empty());
+        map.insert("x".into(), vec![1]);
+        assert_eq!(map.len(), 1);
+    }
+    #[test]
+    #[should_panic]
+    fn test_b_tree_inverted_range() {
+        let map: PagedBTreeMap<u32, u32> = (0..100).map(|i| (i, i)).collect();
+        assert_eq!(map.range(10..10).count(), 0);
+        let _ = map.range((Bound::Included(50), Bound::Excluded(10)));
+    }
+}
@@ -0,0 +1,245 @@
+use crate::page_math::PAGE_SIZE;
+use crate::*;
+use std::fmt::{Debug, Formatter};
+/// Number of 64 bit words on a single page.
+const WORDS_PER_PAGE: usize = PAGE_SIZE / std::mem::size_of::<u64>();
+/// A fixed-size set of bits, stored in [`SparsePages`]. Pages are only committed once a bit on them is set, so a set
+/// spanning billions of bits only uses memory for the regions that are actually used, and [`Self::decommit_zero_pages`]
+/// returns regions that were cleared back to the kernel. Scans, like [`Self::count_ones`] or [`Self::rank`], skip
+/// uncommitted pages entirely.
+/// # Examples
+/// ```
+/// # use memory_pages::*;
+/// // Flags for 16 billion rows, which take no memory until used.
+/// let mut deleted = PagedBitSet::new(16_000_000_000);
+/// deleted.set(7);
+/// deleted.set(9_000_000_000);
+/// assert!(deleted.test(9_000_000_000));
+/// assert_eq!(deleted.count_ones(), 2);
+/// assert_eq!(deleted.rank(9_000_000_000), 1);
+/// assert_eq!(deleted.select(1), Some(9_000_000_000));
+/// assert_eq!(deleted.committed_pages(), 2);
+/// deleted.clear(9_000_000_000);
+/// assert_eq!(deleted.decommit_zero_pages(), 1);
+/// ```
+pub struct PagedBitSet {
+    memory: SparsePages,
+    words: NonNull<u64>,
+    /// Number of bits in the set.
+    len: usize,
+}
+// The words are only ever accessed through `self`.
+unsafe impl Send for PagedBitSet {}
+unsafe impl Sync for PagedBitSet {}
+impl PagedBitSet {
+    /// Creates a new [`PagedBitSet`] of `len` bits, all of which are cleared, reserving address space for them without
+    /// committing any of it.
+    /// # Panics
+    /// Panics if `len` is 0, or if kernel can't/refuses to reserve address space.
+    #[must_use]
+    pub fn new(len: usize) -> Self {
+        let mut memory = SparsePages::new(len.div_ceil(u64::BITS as usize) * 8);
+        let words = NonNull::new(memory.as_mut_ptr().cast()).expect("reservation is never null");
+        Self { memory, words, len }
+    }
+    /// Returns the number of bits in this [`PagedBitSet`].
+    #[must_use]
+    pub fn len(&self) -> usize {
+        self.len
+    }
+    /// Always returns false, because [`PagedBitSet`] can't be empty.
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        false
+    }
+    /// Returns the number of pages backed by memory.
+    #[must_use]
+    pub fn committed_pages(&self) -> usize {
+        self.memory.committed_pages()
+    }
+    /// Checks if bit `index` is set.
+    /// # Panics
+    /// Panics if `index` is out of bounds.
+    #[must_use]
+    pub fn test(&self, index: usize) -> bool {
+        self.check_bounds(index);
+        self.word(index / 64) & (1 << (index % 64)) != 0
+    }
+    /// Sets bit `index`, committing the page it is on if needed.
+    /// # Panics
+    /// Panics if `index` is out of bounds, or if kernel can't/refuses to commit the page.
+    pub fn set(&mut self, index: usize) {
+        self.check_bounds(index);
+        let word = index / 64;
+        if !self.page_committed(word) {
+            self.memory.commit(word * 8, 8);
+        }
+        unsafe { *self.words.as_ptr().add(word) |= 1 << (index % 64) };
+    }
+    /// Clears bit `index`. Pages are never decommitted by this, see [`Self::decommit_zero_pages`].
+    /// # Panics
+    /// Panics if `index` is out of bounds.
+    pub fn clear(&mut self, index: usize) {
+        self.check_bounds(index);
+        let word = index / 64;
+        if self.page_committed(word) {
+            unsafe { *self.words.as_ptr().add(word) &= !(1 << (index % 64)) };
+        }
+    }
+    /// Sets bit `index` if `value` is true, and clears it otherwise.
+    /// # Panics
+    /// Panics if `index` is out of bounds, or if kernel can't/refuses to commit the page.
+    pub fn assign(&mut self, index: usize, value: bool) {
+        if value {
+            self.set(index);
+        } else {
+            self.clear(index);
+        }
+    }
+    /// Clears all bits, returning all of the committed memory to the kernel.
+    pub fn reset(&mut self) {
+        self.memory.decommit(0, self.memory.len());
+    }
+    /// Returns an iterator over all words containing set bits, together with their indices. Word `i` holds bits
+    /// `64 * i..64 * (i + 1)`, with the lowest bit first.
+    pub fn words(&self) -> impl Iterator<Item = (usize, u64)> + '_ {
+        let word_count = self.len.div_ceil(64);
+        (0..word_count.div_ceil(WORDS_PER_PAGE))
+            .filter(|page| self.memory.is_committed(page * PAGE_SIZE))
+            .flat_map(move |page| {
+                (page * WORDS_PER_PAGE..((page + 1) * WORDS_PER_PAGE).min(word_count))
+                    .map(|word| (word, unsafe { *self.words.as_ptr().add(word) }))
+            })
+            .filter(|(_, bits)| *bits != 0)
+    }
+    /// Returns an iterator over indices of all set bits, in ascending order.
+    pub fn iter_ones(&self) -> impl Iterator<Item = usize> + '_ {
+        self.words().flat_map(|(word, mut bits)| {
+            std::iter::from_fn(move || {
+                if bits == 0 {
+                    return None;
+                }
+                let bit = bits.trailing_zeros() as usize;
+                bits &= bits - 1;
+                Some(word * 64 + bit)
+            })
+        })
+    }
+    /// Returns the number of set bits.
+    #[must_use]
+    pub fn count_ones(&self) -> usize {
+        self.words()
+            .map(|(_, bits)| bits.count_ones() as usize)
+            .sum()
+    }
+    /// Returns the number of set bits before `index`.
+    /// # Panics
+    /// Panics if `index` is greater than the length of this [`PagedBitSet`].
+    #[must_use]
+    pub fn rank(&self, index: usize) -> usize {
+        assert!(
+            index <= self.len,
+            "index {index} out of bounds of length {}!",
+            self.len
+        );
+        let (full, rest) = (index / 64, index % 64);
+        let mut rank: usize = self
+            .words()
+            .take_while(|(word, _)| *word < full)
+            .map(|(_, bits)| bits.count_ones() as usize)
+            .sum();
+        if rest != 0 {
+            rank += (self.word(full) & ((1 << rest) - 1)).count_ones() as usize;
+        }
+        rank
+    }
+    /// Returns the index of the set bit with rank `rank`, that is the set bit preceded by exactly `rank` other set
+    /// bits, or `None` if there are not enough set bits.
+    #[must_use]
+    pub fn select(&self, mut rank: usize) -> Option<usize> {
+        for (word, mut bits) in self.words() {
+            let ones = bits.count_ones() as usize;
+            if rank >= ones {
+                rank -= ones;
+                continue;
+            }
+            for _ in 0..rank {
+                bits &= bits - 1;
+            }
+            return Some(word * 64 + bits.trailing_zeros() as usize);
+        }
+        None
+    }
+    /// Returns memory of all committed pages without any set bits to the kernel, and returns the number of such pages.
+    pub fn decommit_zero_pages(&mut self) -> usize {
+        let words = self.len.div_ceil(64);
+        let zero_pages: Vec<usize> = (0..words.div_ceil(WORDS_PER_PAGE))
+            .filter(|page| self.memory.is_committed(page * PAGE_SIZE))
+            .filter(|page| {
+                let start = page * WORDS_PER_PAGE;
+                let end = (start + WORDS_PER_PAGE).min(words);
+                (start..end).all(|word| unsafe { *self.words.as_ptr().add(word) } == 0)
+            })
+            .collect();
+        for page in &zero_pages {
+            self.memory.decommit(page * PAGE_SIZE, PAGE_SIZE);
+        }
+        zero_pages.len()
+    }
+    fn check_bounds(&self, index: usize) {
+        assert!(
+            index < self.len,
+            "index {index} out of bounds of length {}!",
+            self.len
+        );
+    }
+    fn page_committed(&self, word: usize) -> bool {
+        self.memory.is_committed(word * 8)
+    }
+    /// Returns word `word`, or 0 if its page is not committed.
+    fn word(&self, word: usize) -> u64 {
+        if self.page_committed(word) {
+            unsafe { *self.words.as_ptr().add(word) }
+        } else {
+            0
+        }
+    }
+}
+impl Debug for PagedBitSet {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_set().entries(self.iter_ones()).finish()
+    }
+}
+#[cfg(test)]
+mod test {
+    use super::*;
+    #[test]
+    fn test_bit_set_rank_select() {
+        let mut set = PagedBitSet::new(1 << 30);
+        let bits: Vec<usize> = (0..100).map(|i| i * 10_000_019).collect();
+        for bit in &bits {
+            set.set(*bit);
+        }
+        assert_eq!(set.iter_ones().collect::<Vec<_>>(), bits);
+        assert_eq!(set.count_ones(), bits.len());
+        for (rank, bit) in bits.iter().enumerate() {
+            assert_eq!(set.rank(*bit), rank);
+            assert_eq!(set.rank(*bit + 1), rank + 1);
+            assert_eq!(set.select(rank), Some(*bit));
+        }
+        assert_eq!(set.select(bits.len()), None);
+        assert!(!set.test(1));
+        set.assign(1, true);
+        assert!(set.test(1));
+        let committed = set.committed_pages();
+        for bit in &bits[50..] {
+            set.clear(*bit);
+        }
+        assert_eq!(set.decommit_zero_pages(), 50);
+        assert_eq!(set.committed_pages(), committed - 50);
+        assert!(!set.test(bits[70]));
+        set.reset();
+        assert_eq!(set.count_ones(), 0);
+        assert_eq!(set.committed_pages(), 0);
+    }
+}
@@ -0,0 +1,280 @@
+use crate::page_math::align_up;
+use crate::*;
+use std::fmt::{Debug, Formatter};
+use std::io;
+use std::sync::Arc;
+/// Immutable contents shared by many [`PagedCow`]s, stored in an anonymous shared memory object.
+struct Snapshot {
+    #[cfg(target_family = "unix")]
+    file: std::fs::File,
+    #[cfg(target_family = "windows")]
+    mapping: winapi::um::winnt::HANDLE,
+    /// Size of the snapshot, in bytes. Always a multiple of page size.
+    len: usize,
+}
+// Snapshot is never modified once created.
+unsafe impl Send for Snapshot {}
+unsafe impl Sync for Snapshot {}
+impl Snapshot {
+    /// Creates a new snapshot of `data`, padded with zeroes to a multiple of page size.
+    #[cfg(target_family = "unix")]
+    fn new(data: &[u8]) -> io::Result<Self> {
+        use std::os::unix::fs::FileExt;
+        let len = align_up(data.len().max(1));
+        let file = crate::paged_deque::shared_memory()?;
+        file.set_len(len as u64)?;
+        file.write_all_at(data, 0)?;
+        Ok(Self { file, len })
+    }
+    #[cfg(target_family = "windows")]
+    fn new(data: &[u8]) -> io::Result<Self> {
+        use winapi::um::handleapi::INVALID_HANDLE_VALUE;
+        let len = align_up(data.len().max(1));
+        let mapping = unsafe {
+            CreateFileMappingW(
+                INVALID_HANDLE_VALUE,
+                std::ptr::null_mut(),
+                PAGE_READWRITE,
+                (len as u64 >> 32) as u32,
+                len as u32,
+                std::ptr::null(),
+            )
+        };
+        if mapping.is_null() {
+            return Err(io::Error::last_os_error());
+        }
+        // From now on, the mapping is closed if writing the data fails.
+        let snapshot = Self { mapping, len };
+        let view = unsafe { MapViewOfFile(mapping, FILE_MAP_WRITE, 0, 0, len) };
+        if view.is_null() {
+            return Err(io::Error::last_os_error());
+        }
+        unsafe {
+            std::ptr::copy_nonoverlapping(data.as_ptr(), view.cast::<u8>(), data.len());
+            UnmapViewOfFile(view);
+        }
+        Ok(snapshot)
+    }
+    /// Maps this snapshot privately: writes to the returned view are only visible in it, and copy just the pages they
+    /// touch.
+    #[cfg(target_family = "unix")]
+    fn map(&self) -> io::Result<View> {
+        use std::os::unix::io::AsRawFd;
+        let ptr = unsafe {
+            mmap(
+                std::ptr::null_mut(),
+                self.len,
+                AllowRead::bitmask() | AllowWrite::bitmask(),
+                MAP_PRIVATE,
+                self.file.as_raw_fd(),
+                0,
+            )
+        };
+        if ptr == MAP_FAILED {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(View {
+            ptr: non_null(ptr),
+            len: self.len,
+        })
+    }
+    #[cfg(target_family = "windows")]
+    fn map(&self) -> io::Result<View> {
+        let ptr = unsafe { MapViewOfFile(self.mapping, FILE_MAP_COPY, 0, 0, self.len) };
+        let ptr = NonNull::new(ptr.cast::<u8>()).ok_or_else(io::Error::last_os_error)?;
+        Ok(View { ptr, len: self.len })
+    }
+}
+#[cfg(target_family = "windows")]
+impl Drop for Snapshot {
+    fn drop(&mut self) {
+        // Views keep the mapping alive.
+        unsafe { winapi::um::handleapi::CloseHandle(self.mapping) };
+    }
+}
+/// Private, copy-on-write view of a [`Snapshot`].
+struct View {
+    ptr: NonNull<u8>,
+    /// Length of the view, in bytes. Windows unmaps whole views, so it only needs the pointer.
+    #[cfg_attr(target_family = "windows", allow(dead_code))]
+    len: usize,
+}
+impl Drop for View {
+    #[cfg(target_family = "unix")]
+    fn drop(&mut self) {
+        if unsafe { munmap(self.ptr.as_ptr().cast(), self.len) } == -1 {
+            let err = errno_msg();
+            panic!("Unmapping PagedCow failed:'{err}'!");
+        }
+    }
+    #[cfg(target_family = "windows")]
+    fn drop(&mut self) {
+        if unsafe { UnmapViewOfFile(self.ptr.as_ptr().cast()) } == 0 {
+            let err = unsafe { winapi::um::errhandlingapi::GetLastError() };
+            panic!("Unmapping PagedCow failed with error code:{err}!");
+        }
+    }
+}
+/// A fixed-length slice, whose clones share memory using the copy-on-write mechanism of the kernel. Cloning a
+/// [`PagedCow`] which was never written to maps the same physical pages again instead of copying them, and a page is
+/// only copied once either clone writes to it, so taking a logical snapshot of gigabytes of data costs about as much as
+/// mapping it.
+///
+/// Contents live in an anonymous shared memory object, which is never modified, and each [`PagedCow`] maps it
+/// privately. Creating a [`PagedCow`] copies the data into a new shared object once. Pages written to are private
+/// copies, which the kernel can't share with another mapping, so cloning a [`PagedCow`] which was written to copies all
+/// of its contents into a new shared object, which the clone then maps. Clones of that clone are free again, so a
+/// modified [`PagedCow`] is best cloned once, with the clone used as the base of further snapshots.
+///
+/// Elements are copied bitwise, including any padding bytes they have, so they must be [`Pod`].
+/// # Examples
+/// ```
+/// # use memory_pages::*;
+/// let data: Vec<u64> = (0..0x100_000).collect();
+/// let base = PagedCow::from_slice(&data);
+/// // Shares all pages with `base`.
+/// let mut snapshot = base.clone();
+/// snapshot[7] = 0;
+/// // Only the page holding element 7 was copied.
+/// assert_eq!(base[7], 7);
+/// assert_eq!(snapshot[7], 0);
+/// assert_eq!(snapshot[8], 8);
+/// ```
+pub struct PagedCow<T: Pod> {
+    snapshot: Arc<Snapshot>,
+    view: View,
+    len: usize,
+    /// Set once this [`PagedCow`] is written to, since it then no longer matches `snapshot`.
+    dirty: bool,
+    pd: PhantomData<T>,
+}
+// `PagedCow` owns its view, like a `Box<[T]>`.
+unsafe impl<T: Pod + Send> Send for PagedCow<T> {}
+unsafe impl<T: Pod + Sync> Sync for PagedCow<T> {}
+impl<T: Pod> PagedCow<T> {
+    /// Creates a new [`PagedCow`], holding a copy of `data`. All of `data` is copied into a new shared object, which
+    /// clones then share.
+    /// # Panics
+    /// Panics if kernel can't/refuses to create or map the shared memory object.
+    #[must_use]
+    pub fn from_slice(data: &[T]) -> Self {
+        let bytes = unsafe {
+            std::slice::from_raw_parts(data.as_ptr().cast::<u8>(), std::mem::size_of_val(data))
+        };
+        Self::from_bytes(bytes, data.len())
+            .unwrap_or_else(|err| panic!("Creating PagedCow failed:'{err}'!"))
+    }
+    fn from_bytes(bytes: &[u8], len: usize) -> io::Result<Self> {
+        let snapshot = Arc::new(Snapshot::new(bytes)?);
+        let view = snapshot.map()?;
+        Ok(Self {
+            snapshot,
+            view,
+            len,
+            dirty: false,
+            pd: PhantomData,
+        })
+    }
+    /// Returns the number of elements.
+    #[must_use]
+    pub fn len(&self) -> usize {
+        self.len
+    }
+    /// Checks if this [`PagedCow`] has no elements.
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+    /// Checks if this [`PagedCow`] shares all of its memory with the shared object it was mapped from, which makes
+    /// cloning it free. Becomes `false` once it is mutably dereferenced.
+    #[must_use]
+    pub fn is_pristine(&self) -> bool {
+        !self.dirty
+    }
+    /// Checks if `self` and `other` were mapped from the same shared object, so unmodified pages of both are the same
+    /// physical memory.
+    #[must_use]
+    pub fn shares_memory_with(&self, other: &Self) -> bool {
+        Arc::ptr_eq(&self.snapshot, &other.snapshot)
+    }
+}
+impl<T: Pod> Clone for PagedCow<T> {
+    /// Maps the contents of `self` again, copy-on-write. If `self` was written to, none of its memory can be shared, so
+    /// all of its contents are copied into a new shared object instead.
+    /// # Panics
+    /// Panics if kernel can't/refuses to create or map the shared memory object.
+    fn clone(&self) -> Self {
+        let res = if self.dirty {
+            let bytes = unsafe {
+                std::slice::from_raw_parts(
+                    self.view.ptr.as_ptr(),
+                    self.len * std::mem::size_of::<T>(),
+                )
+            };
+            Self::from_bytes(bytes, self.len)
+        } else {
+            self.snapshot.map().map(|view| Self {
+                snapshot: self.snapshot.clone(),
+                view,
+                len: self.len,
+                dirty: false,
+                pd: PhantomData,
+            })
+        };
+        res.unwrap_or_else(|err| panic!("Cloning PagedCow failed:'{err}'!"))
+    }
+}
+impl<T: Pod> Deref for PagedCow<T> {
+    type Target = [T];
+    fn deref(&self) -> &[T] {
+        unsafe { std::slice::from_raw_parts(self.view.ptr.as_ptr().cast(), self.len) }
+    }
+}
+impl<T: Pod> DerefMut for PagedCow<T> {
+    fn deref_mut(&mut self) -> &mut [T] {
+        self.dirty = true;
+        unsafe { std::slice::from_raw_parts_mut(self.view.ptr.as_ptr().cast(), self.len) }
+    }
+}
+impl<T: Pod> From<&[T]> for PagedCow<T> {
+    fn from(data: &[T]) -> Self {
+        Self::from_slice(data)
+    }
+}
+impl<T: Pod> From<&PagedVec<T>> for PagedCow<T> {
+    /// Copies all elements of `vec` into a new [`PagedCow`], as [`PagedCow::from_slice`] does. Memory of a [`PagedVec`]
+    /// is private to it, so it can't be shared without copying.
+    fn from(vec: &PagedVec<T>) -> Self {
+        Self::from_slice(vec)
+    }
+}
+impl<T: Pod + Debug> Debug for PagedCow<T> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_list().entries(self.iter()).finish()
+    }
+}
+#[cfg(test)]
+mod test {
+    use super::*;
+    #[test]
+    fn test_cow_clones_share_pages() {
+        let vec: PagedVec<u32> = (0..0x40_000).collect();
+        let base = PagedCow::from(&vec);
+        let mut a = base.clone();
+        let b = base.clone();
+        assert!(a.shares_memory_with(&b));
+        a.iter_mut().step_by(0x400).for_each(|x| *x = 0);
+        assert!(!a.is_pristine());
+        assert!(b.iter().copied().eq(0..0x40_000));
+        assert_eq!(a[0x400], 0);
+        assert_eq!(a[0x401], 0x401);
+        // Cloning a modified `PagedCow` copies it into a new shared object.
+        let c = a.clone();
+        assert!(!c.shares_memory_with(&a));
+        assert!(c.is_pristine());
+        assert_eq!(*c, *a);
+        assert!(c.clone().shares_memory_with(&c));
+        let empty: PagedCow<u8> = PagedCow::from_slice(&[]);
+        assert!(empty.clone().is_empty());
+    }
+}
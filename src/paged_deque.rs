@@ -0,0 +1,412 @@
+use crate::*;
+use std::fmt::{Debug, Formatter};
+use std::io;
+#[cfg(target_family = "unix")]
+const MAP_FIXED: c_int = 0x10;
+/// Smallest amount of memory that can be mapped at a chosen address.
+#[cfg(target_family = "unix")]
+pub(crate) const GRANULARITY: usize = page_math::PAGE_SIZE;
+#[cfg(target_family = "windows")]
+pub(crate) const GRANULARITY: usize = 0x10000;
+/// Memory mapped twice, at two adjacent address ranges. Writing to one copy is immediately visible in the other, so a
+/// ring buffer stored in it can always be accessed as a single contiguous slice, even when it wraps around.
+pub(crate) struct Mirror {
+    pub(crate) ptr: NonNull<u8>,
+    /// Length of one copy, in bytes.
+    pub(crate) len: usize,
+}
+// Mirror is just memory, and does not care which thread it is used from.
+unsafe impl Send for Mirror {}
+unsafe impl Sync for Mirror {}
+impl Mirror {
+    /// Maps `len` bytes twice. `len` must be a multiple of [`GRANULARITY`].
+    #[cfg(target_family = "unix")]
+    pub(crate) fn new(len: usize) -> io::Result<Self> {
+        let file = shared_memory()?;
+        file.set_len(len as u64)?;
+        let double = len
+            .checked_mul(2)
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "mirror too large"))?;
+        let ptr = unsafe {
+            mmap(
+                std::ptr::null_mut(),
+                double,
+                0,
+                MAP_ANYNOMUS | MAP_PRIVATE,
+                NO_FILE,
+                0,
+            )
+        };
+        if ptr == MAP_FAILED {
+            return Err(io::Error::last_os_error());
+        }
+        // From now on, the whole range is released if mapping any of the copies fails.
+        let mirror = Self {
+            ptr: non_null(ptr),
+            len,
+        };
+        for copy in [ptr, ptr.wrapping_byte_add(len)] {
+            use std::os::unix::io::AsRawFd;
+            let res = unsafe {
+                mmap(
+                    copy,
+                    len,
+                    AllowRead::bitmask() | AllowWrite::bitmask(),
+                    MAP_SHARED | MAP_FIXED,
+                    file.as_raw_fd(),
+                    0,
+                )
+            };
+            if res == MAP_FAILED {
+                return Err(io::Error::last_os_error());
+            }
+        }
+        Ok(mirror)
+    }
+    #[cfg(target_family = "windows")]
+    pub(crate) fn new(len: usize) -> io::Result<Self> {
+        use winapi::um::handleapi::{CloseHandle, INVALID_HANDLE_VALUE};
+        let double = len
+            .checked_mul(2)
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "mirror too large"))?;
+        let mapping = unsafe {
+            CreateFileMappingW(
+                INVALID_HANDLE_VALUE,
+                std::ptr::null_mut(),
+                PAGE_READWRITE,
+                (len as u64 >> 32) as u32,
+                len as u32,
+                std::ptr::null(),
+            )
+        };
+        if mapping.is_null() {
+            return Err(io::Error::last_os_error());
+        }
+        // Another thread may map something in the free range before both views are placed in it, so try a few times.
+        let mut res = Err(io::Error::new(
+            io::ErrorKind::AddrInUse,
+            "no free address range for a mirrored mapping",
+        ));
+        for _ in 0..16 {
+            let free =
+                unsafe { VirtualAlloc(std::ptr::null_mut(), double, MEM_RESERVE, PAGE_NOACCESS) };
+            if free.is_null() {
+                res = Err(io::Error::last_os_error());
+                break;
+            }
+            unsafe { VirtualFree(free, 0, MEM_RELEASE) };
+            let first = unsafe { MapViewOfFileEx(mapping, FILE_MAP_ALL_ACCESS, 0, 0, len, free) };
+            if first.is_null() {
+                continue;
+            }
+            let second = unsafe {
+                MapViewOfFileEx(
+                    mapping,
+                    FILE_MAP_ALL_ACCESS,
+                    0,
+                    0,
+                    len,
+                    free.cast::<u8>().add(len).cast(),
+                )
+            };
+            if second.is_null() {
+                unsafe { UnmapViewOfFile(first) };
+                continue;
+            }
+            res = Ok(Self {
+                ptr: NonNull::new(first.cast()).expect("mapped view is never null"),
+                len,
+            });
+            break;
+        }
+        // Views keep the mapping alive.
+        unsafe { CloseHandle(mapping) };
+        res
+    }
+}
+impl Drop for Mirror {
+    #[cfg(target_family = "unix")]
+    fn drop(&mut self) {
+        let res = unsafe { munmap(self.ptr.as_ptr().cast(), self.len * 2) };
+        if res == -1 {
+            let err = errno_msg();
+            panic!("Unmapping mirrored pages failed:'{err}'!");
+        }
+    }
+    #[cfg(target_family = "windows")]
+    fn drop(&mut self) {
+        for copy in [self.ptr.as_ptr(), self.ptr.as_ptr().wrapping_add(self.len)] {
+            if unsafe { UnmapViewOfFile(copy.cast()) } == 0 {
+                let err = unsafe { winapi::um::errhandlingapi::GetLastError() };
+                panic!("Unmapping mirrored pages failed with error code:{err}!");
+            }
+        }
+    }
+}
+/// Creates an anonymous shared memory object, which can be mapped multiple times.
+#[cfg(target_os = "linux")]
+pub(crate) fn shared_memory() -> io::Result<std::fs::File> {
+    use std::os::unix::io::FromRawFd;
+    extern "C" {
+        fn memfd_create(name: *const c_char, flags: std::ffi::c_uint) -> c_int;
+    }
+    const MFD_CLOEXEC: std::ffi::c_uint = 1;
+    let fd = unsafe { memfd_create(c"memory_pages_deque".as_ptr(), MFD_CLOEXEC) };
+    if fd == -1 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(unsafe { std::fs::File::from_raw_fd(fd) })
+}
+#[cfg(all(target_family = "unix", not(target_os = "linux")))]
+pub(crate) fn shared_memory() -> io::Result<std::fs::File> {
+    use std::os::unix::io::FromRawFd;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    extern "C" {
+        fn shm_open(name: *const c_char, oflag: c_int, ...) -> c_int;
+        fn shm_unlink(name: *const c_char) -> c_int;
+    }
+    const O_RDWR: c_int = 0x2;
+    const O_CREAT: c_int = 0x200;
+    const O_EXCL: c_int = 0x800;
+    static COUNTER: AtomicUsize = AtomicUsize::new(0);
+    let name = std::ffi::CString::new(format!(
+        "/memory_pages_{}_{}",
+        std::process::id(),
+        COUNTER.fetch_add(1, Ordering::Relaxed)
+    ))
+    .expect("name contains no null bytes");
+    let fd = unsafe { shm_open(name.as_ptr(), O_RDWR | O_CREAT | O_EXCL, 0o600 as c_int) };
+    if fd == -1 {
+        return Err(io::Error::last_os_error());
+    }
+    // The object only needs to live as long as the descriptor.
+    unsafe { shm_unlink(name.as_ptr()) };
+    Ok(unsafe { std::fs::File::from_raw_fd(fd) })
+}
+/// A double-ended queue, stored in a ring buffer which is mapped twice, back to back. Thanks to that, elements of
+/// [`PagedDeque`] are always contiguous in memory, even after the ring wraps around, so it can be used as a single slice,
+/// unlike [`std::collections::VecDeque`].
+///
+/// Capacity of a [`PagedDeque`] is always a multiple of the number of elements that fit exactly into a whole number of
+/// pages, so it is usually larger than requested.
+/// # Examples
+/// ```
+/// # use memory_pages::*;
+/// let mut deque:PagedDeque<u32> = PagedDeque::new(0x100);
+/// deque.extend(0..5);
+/// deque.push_front(10);
+/// assert_eq!(deque.pop_back(), Some(4));
+/// // The ring wrapped around, but the elements can still be accessed as a slice.
+/// assert_eq!(&deque[..], [10, 0, 1, 2, 3]);
+/// ```
+pub struct PagedDeque<T> {
+    mirror: Mirror,
+    /// Index of the first element inside the ring.
+    head: usize,
+    len: usize,
+    pd: PhantomData<T>,
+}
+impl<T> PagedDeque<T> {
+    /// Creates a new, empty [`PagedDeque`] with capacity for at least `capacity` elements.
+    /// # Panics
+    /// Panics if `T` is zero-sized, if size of `capacity` elements exceeds `isize::MAX` bytes, or if kernel
+    /// can't/refuses to map the ring.
+    #[must_use]
+    pub fn new(capacity: usize) -> Self {
+        assert!(
+            std::mem::size_of::<T>() != 0,
+            "PagedDeque can't hold zero-sized types!"
+        );
+        Self {
+            mirror: Self::map(capacity),
+            head: 0,
+            len: 0,
+            pd: PhantomData,
+        }
+    }
+    /// Maps a ring with capacity for at least `capacity` elements, whose end falls exactly on an element boundary.
+    fn map(capacity: usize) -> Mirror {
+        let size = std::mem::size_of::<T>();
+        let unit = GRANULARITY / gcd(GRANULARITY, size) * size;
+        let bytes = PagedVec::<T>::bytes_for(capacity)
+            .ok()
+            .and_then(|bytes| bytes.max(1).div_ceil(unit).checked_mul(unit))
+            .filter(|bytes| isize::try_from(*bytes * 2).is_ok())
+            .unwrap_or_else(|| panic!("Capacity of PagedDeque overflowed!"));
+        Mirror::new(bytes).unwrap_or_else(|err| panic!("Mapping PagedDeque failed:'{err}'!"))
+    }
+    /// Returns the number of elements in this [`PagedDeque`].
+    #[must_use]
+    pub fn len(&self) -> usize {
+        self.len
+    }
+    /// Checks if this [`PagedDeque`] is empty.
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+    /// Returns the number of elements this [`PagedDeque`] can hold without remapping.
+    #[must_use]
+    pub fn capacity(&self) -> usize {
+        self.mirror.len / std::mem::size_of::<T>()
+    }
+    /// Appends `t` to the back of this [`PagedDeque`].
+    pub fn push_back(&mut self, t: T) {
+        self.reserve(1);
+        unsafe { self.slot(self.len).write(t) };
+        self.len += 1;
+    }
+    /// Prepends `t` to the front of this [`PagedDeque`].
+    pub fn push_front(&mut self, t: T) {
+        self.reserve(1);
+        self.head = (self.head + self.capacity() - 1) % self.capacity();
+        unsafe { self.slot(0).write(t) };
+        self.len += 1;
+    }
+    /// Removes the last element and returns it, or `None` if this [`PagedDeque`] is empty.
+    pub fn pop_back(&mut self) -> Option<T> {
+        if self.len == 0 {
+            return None;
+        }
+        self.len -= 1;
+        Some(unsafe { self.slot(self.len).read() })
+    }
+    /// Removes the first element and returns it, or `None` if this [`PagedDeque`] is empty.
+    pub fn pop_front(&mut self) -> Option<T> {
+        if self.len == 0 {
+            return None;
+        }
+        let t = unsafe { self.slot(0).read() };
+        self.head = (self.head + 1) % self.capacity();
+        self.len -= 1;
+        Some(t)
+    }
+    /// Returns a reference to the first element, or `None` if this [`PagedDeque`] is empty.
+    #[must_use]
+    pub fn front(&self) -> Option<&T> {
+        self.first()
+    }
+    /// Returns a reference to the last element, or `None` if this [`PagedDeque`] is empty.
+    #[must_use]
+    pub fn back(&self) -> Option<&T> {
+        self.last()
+    }
+    /// Reserves capacity for at least `additional` more elements. If the ring needs to grow, a new one at least twice as
+    /// large is mapped, and elements are moved into it.
+    /// # Panics
+    /// Panics if the new capacity overflows, or if kernel can't/refuses to map the new ring.
+    pub fn reserve(&mut self, additional: usize) {
+        let needed = self
+            .len
+            .checked_add(additional)
+            .unwrap_or_else(|| panic!("Capacity of PagedDeque overflowed!"));
+        if needed <= self.capacity() {
+            return;
+        }
+        let mirror = Self::map(needed.max(self.capacity().saturating_mul(2)));
+        unsafe {
+            std::ptr::copy_nonoverlapping(self.slot(0), mirror.ptr.as_ptr().cast::<T>(), self.len);
+        }
+        self.mirror = mirror;
+        self.head = 0;
+    }
+    /// Shortens this [`PagedDeque`] to `len` elements, dropping the ones at the back. Does nothing if it is already
+    /// shorter.
+    pub fn truncate(&mut self, len: usize) {
+        if len >= self.len {
+            return;
+        }
+        let tail = std::ptr::slice_from_raw_parts_mut(unsafe { self.slot(len) }, self.len - len);
+        // Length is updated first, so that a panicking `drop` can't cause a double drop.
+        self.len = len;
+        unsafe { std::ptr::drop_in_place(tail) };
+    }
+    /// Removes all elements.
+    pub fn clear(&mut self) {
+        self.truncate(0);
+        self.head = 0;
+    }
+    /// Returns a pointer to the slot of element `index`. Slots up to one whole ring past the head are valid, thanks to
+    /// the mirror.
+    unsafe fn slot(&self, index: usize) -> *mut T {
+        self.mirror.ptr.as_ptr().cast::<T>().add(self.head + index)
+    }
+}
+fn gcd(mut a: usize, mut b: usize) -> usize {
+    while b != 0 {
+        (a, b) = (b, a % b);
+    }
+    a
+}
+impl<T> Deref for PagedDeque<T> {
+    type Target = [T];
+    fn deref(&self) -> &[T] {
+        unsafe { std::slice::from_raw_parts(self.slot(0), self.len) }
+    }
+}
+impl<T> DerefMut for PagedDeque<T> {
+    fn deref_mut(&mut self) -> &mut [T] {
+        unsafe { std::slice::from_raw_parts_mut(self.slot(0), self.len) }
+    }
+}
+impl<T> Extend<T> for PagedDeque<T> {
+    fn extend<I: IntoIterator<Item = T>>(&mut self, iter: I) {
+        let iter = iter.into_iter();
+        self.reserve(iter.size_hint().0);
+        for t in iter {
+            self.push_back(t);
+        }
+    }
+}
+impl<T> FromIterator<T> for PagedDeque<T> {
+    fn from_iter<I: IntoIterator<Item = T>>(iter: I) -> Self {
+        let mut deque = Self::new(0);
+        deque.extend(iter);
+        deque
+    }
+}
+impl<T: Debug> Debug for PagedDeque<T> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_list().entries(self.iter()).finish()
+    }
+}
+impl<T> Drop for PagedDeque<T> {
+    fn drop(&mut self) {
+        self.truncate(0);
+    }
+}
+#[cfg(test)]
+mod test {
+    use super::*;
+    #[test]
+    fn test_deque_wraps() {
+        let mut deque: PagedDeque<String> = PagedDeque::new(0);
+        let capacity = deque.capacity();
+        // 24 byte elements only line up with page boundaries every 3 pages.
+        assert_eq!(capacity * std::mem::size_of::<String>() % GRANULARITY, 0);
+        for i in 0..capacity * 3 {
+            deque.push_back(i.to_string());
+            if deque.len() == capacity {
+                assert_eq!(deque.pop_front(), Some((i + 1 - capacity).to_string()));
+            }
+        }
+        assert_eq!(deque.capacity(), capacity);
+        assert_eq!(deque.len(), capacity - 1);
+        assert!(deque
+            .iter()
+            .zip(capacity * 2 + 1..)
+            .all(|(s, i)| *s == i.to_string()));
+        deque.push_front("front".to_owned());
+        deque.push_front("grows".to_owned());
+        assert_eq!(deque.capacity(), capacity * 2);
+        assert_eq!(deque.front().map(String::as_str), Some("grows"));
+        assert_eq!(deque.back(), Some(&(capacity * 3 - 1).to_string()));
+        deque.truncate(2);
+        assert_eq!(&deque[..], ["grows", "front"]);
+    }
+    #[test]
+    #[should_panic]
+    fn test_deque_zero_sized() {
+        let _ = PagedDeque::<()>::new(16);
+    }
+}
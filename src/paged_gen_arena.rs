@@ -0,0 +1,370 @@
+use crate::*;
+use std::fmt::{Debug, Formatter};
+use std::hash::{Hash, Hasher};
+use std::ops::{Index, IndexMut};
+/// Typed handle to a value stored in a [`PagedGenArena<T>`]. It is a plain index and generation, so it is cheap to copy,
+/// and structures with cycles can refer to their nodes without reference counting. Handles of removed values are never
+/// valid again, even if their slot is reused.
+pub struct Handle<T> {
+    index: u32,
+    generation: u32,
+    pd: PhantomData<fn() -> T>,
+}
+impl<T> Handle<T> {
+    /// Returns the index of the slot this handle refers to. Slots of removed values are reused, so indices are not
+    /// unique.
+    #[must_use]
+    pub fn index(&self) -> usize {
+        self.index as usize
+    }
+    /// Returns the generation of the slot this handle refers to, which is bumped each time a value is removed from it.
+    #[must_use]
+    pub fn generation(&self) -> u32 {
+        self.generation
+    }
+}
+// Derives would needlessly require `T` to implement those traits.
+impl<T> Clone for Handle<T> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+impl<T> Copy for Handle<T> {}
+impl<T> PartialEq for Handle<T> {
+    fn eq(&self, other: &Self) -> bool {
+        (self.index, self.generation) == (other.index, other.generation)
+    }
+}
+impl<T> Eq for Handle<T> {}
+impl<T> PartialOrd for Handle<T> {
+    fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
+        Some(self.cmp(other))
+    }
+}
+impl<T> Ord for Handle<T> {
+    fn cmp(&self, other: &Self) -> std::cmp::Ordering {
+        (self.index, self.generation).cmp(&(other.index, other.generation))
+    }
+}
+impl<T> Hash for Handle<T> {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        (self.index, self.generation).hash(state);
+    }
+}
+impl<T> Debug for Handle<T> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Handle({}v{})", self.index, self.generation)
+    }
+}
+enum Slot<T> {
+    Occupied {
+        generation: u32,
+        value: T,
+    },
+    /// Free slot, linking to the next free one, if any.
+    Vacant {
+        generation: u32,
+        next: Option<u32>,
+    },
+}
+/// A growable arena of values of type `T`, handing out [`Handle<T>`]s instead of references. Values live in a
+/// [`PagedVec`], and slots of removed values are reused, most recently freed first, so the arena stays as compact as
+/// its largest population. Since handles are not borrows, values may freely refer to each other, which lets graphs with
+/// cycles, like scene graphs or doubly linked lists, live in page memory without the overhead of [`std::rc::Rc`].
+///
+/// Each slot has a generation, bumped whenever its value is removed, so stale handles are caught instead of silently
+/// referring to a different value. A slot whose generation would wrap around is retired instead of reused.
+/// # Examples
+/// ```
+/// # use memory_pages::*;
+/// struct Node {
+///     name: &'static str,
+///     next: Option<Handle<Node>>,
+/// }
+/// let mut graph: PagedGenArena<Node> = PagedGenArena::new();
+/// let a = graph.insert(Node { name: "a", next: None });
+/// let b = graph.insert(Node { name: "b", next: Some(a) });
+/// // A cycle, without any reference counting.
+/// graph[a].next = Some(b);
+/// assert_eq!(graph[graph[a].next.unwrap()].name, "b");
+/// assert_eq!(graph.remove(b).map(|node| node.name), Some("b"));
+/// // Handle of a removed value stays invalid, even once its slot is reused.
+/// let c = graph.insert(Node { name: "c", next: None });
+/// assert_eq!(c.index(), b.index());
+/// assert!(graph.get(b).is_none());
+/// ```
+pub struct PagedGenArena<T> {
+    slots: PagedVec<Slot<T>>,
+    /// Most recently freed slot.
+    free: Option<u32>,
+    len: usize,
+}
+impl<T> PagedGenArena<T> {
+    /// Creates a new, empty [`PagedGenArena`], without allocating any memory.
+    #[must_use]
+    pub fn new() -> Self {
+        Self {
+            slots: PagedVec::new_empty(),
+            free: None,
+            len: 0,
+        }
+    }
+    /// Creates a new, empty [`PagedGenArena`] with space for at least `capacity` values.
+    /// # Panics
+    /// Panics if size of `capacity` values exceeds `isize::MAX` bytes, or if kernel can't/refuses to allocate memory.
+    #[must_use]
+    pub fn with_capacity(capacity: usize) -> Self {
+        Self {
+            slots: PagedVec::new(capacity),
+            free: None,
+            len: 0,
+        }
+    }
+    /// Returns the number of values in this [`PagedGenArena`].
+    #[must_use]
+    pub fn len(&self) -> usize {
+        self.len
+    }
+    /// Checks if this [`PagedGenArena`] is empty.
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+    /// Returns the number of values this [`PagedGenArena`] can hold without allocating more memory.
+    #[must_use]
+    pub fn capacity(&self) -> usize {
+        self.slots.capacity() - (self.slots.len() - self.len)
+    }
+    /// Moves `t` into a free slot, and returns a handle referring to it.
+    /// # Panics
+    /// Panics if the arena already has `u32::MAX` slots, or if kernel can't/refuses to allocate more memory.
+    pub fn insert(&mut self, t: T) -> Handle<T> {
+        self.insert_with(|_| t)
+    }
+    /// Moves the value returned by `f` into a free slot, and returns a handle referring to it. `f` receives that handle,
+    /// so the value can refer to itself.
+    /// # Panics
+    /// Panics if the arena already has `u32::MAX` slots, or if kernel can't/refuses to allocate more memory.
+    pub fn insert_with(&mut self, f: impl FnOnce(Handle<T>) -> T) -> Handle<T> {
+        let (index, generation, next) = match self.free {
+            Some(index) => match self.slots[index as usize] {
+                Slot::Vacant { generation, next } => (index, generation, next),
+                Slot::Occupied { .. } => unreachable!("free list links to an occupied slot"),
+            },
+            None => {
+                let index = u32::try_from(self.slots.len())
+                    .ok()
+                    .filter(|index| *index != u32::MAX)
+                    .unwrap_or_else(|| panic!("Maximal capacity of PagedGenArena exceeded!"));
+                (index, 0, None)
+            }
+        };
+        let handle = Handle {
+            index,
+            generation,
+            pd: PhantomData,
+        };
+        // The arena is only modified once `f` returns, so it stays consistent if `f` panics.
+        let slot = Slot::Occupied {
+            generation,
+            value: f(handle),
+        };
+        if self.free.is_some() {
+            self.free = next;
+            self.slots[index as usize] = slot;
+        } else {
+            self.slots.push(slot);
+        }
+        self.len += 1;
+        handle
+    }
+    /// Returns a reference to the value `handle` refers to, or `None` if it was removed.
+    #[must_use]
+    pub fn get(&self, handle: Handle<T>) -> Option<&T> {
+        match self.slots.get(handle.index as usize)? {
+            Slot::Occupied { generation, value } if *generation == handle.generation => Some(value),
+            _ => None,
+        }
+    }
+    /// Returns a mutable reference to the value `handle` refers to, or `None` if it was removed.
+    #[must_use]
+    pub fn get_mut(&mut self, handle: Handle<T>) -> Option<&mut T> {
+        match self.slots.get_mut(handle.index as usize)? {
+            Slot::Occupied { generation, value } if *generation == handle.generation => Some(value),
+            _ => None,
+        }
+    }
+    /// Returns mutable references to the values `a` and `b` refer to, each `None` if it was removed. This allows
+    /// updating two linked values at once.
+    /// # Panics
+    /// Panics if `a` and `b` refer to the same slot.
+    #[must_use]
+    pub fn get2_mut(&mut self, a: Handle<T>, b: Handle<T>) -> (Option<&mut T>, Option<&mut T>) {
+        assert_ne!(a.index, b.index, "handles refer to the same slot!");
+        let valid = |slot: &mut Slot<T>, handle: Handle<T>| match slot {
+            Slot::Occupied { generation, value } if *generation == handle.generation => {
+                Some(value as *mut T)
+            }
+            _ => None,
+        };
+        let a = self
+            .slots
+            .get_mut(a.index as usize)
+            .and_then(|slot| valid(slot, a));
+        let b = self
+            .slots
+            .get_mut(b.index as usize)
+            .and_then(|slot| valid(slot, b));
+        // Slots are distinct, so the references never alias.
+        unsafe { (a.map(|a| &mut *a), b.map(|b| &mut *b)) }
+    }
+    /// Checks if the value `handle` refers to is still in this [`PagedGenArena`].
+    #[must_use]
+    pub fn contains(&self, handle: Handle<T>) -> bool {
+        self.get(handle).is_some()
+    }
+    /// Removes the value `handle` refers to, and returns it, or `None` if it was already removed.
+    pub fn remove(&mut self, handle: Handle<T>) -> Option<T> {
+        self.get(handle)?;
+        let generation = handle.generation.wrapping_add(1);
+        let retired = generation == 0;
+        let slot = std::mem::replace(
+            &mut self.slots[handle.index as usize],
+            Slot::Vacant {
+                generation,
+                next: if retired { None } else { self.free },
+            },
+        );
+        // A handle with generation 0 could be stale, so the slot is never reused.
+        if !retired {
+            self.free = Some(handle.index);
+        }
+        self.len -= 1;
+        match slot {
+            Slot::Occupied { value, .. } => Some(value),
+            Slot::Vacant { .. } => unreachable!("slot was checked to be occupied"),
+        }
+    }
+    /// Removes all values for which `f` returns `false`.
+    pub fn retain(&mut self, mut f: impl FnMut(Handle<T>, &mut T) -> bool) {
+        for index in 0..self.slots.len() {
+            if let Slot::Occupied { generation, value } = &mut self.slots[index] {
+                let handle = Handle {
+                    index: index as u32,
+                    generation: *generation,
+                    pd: PhantomData,
+                };
+                if !f(handle, value) {
+                    self.remove(handle);
+                }
+            }
+        }
+    }
+    /// Removes all values. All handles become invalid, but memory is kept for reuse.
+    pub fn clear(&mut self) {
+        self.retain(|_, _| false);
+    }
+    /// Returns an iterator over all values and their handles, in order of their slots.
+    pub fn iter(&self) -> impl Iterator<Item = (Handle<T>, &T)> + '_ {
+        self.slots
+            .iter()
+            .enumerate()
+            .filter_map(|(index, slot)| match slot {
+                Slot::Occupied { generation, value } => Some((
+                    Handle {
+                        index: index as u32,
+                        generation: *generation,
+                        pd: PhantomData,
+                    },
+                    value,
+                )),
+                Slot::Vacant { .. } => None,
+            })
+    }
+    /// Returns an iterator over all values and their handles, in order of their slots, allowing values to be modified.
+    pub fn iter_mut(&mut self) -> impl Iterator<Item = (Handle<T>, &mut T)> + '_ {
+        self.slots
+            .iter_mut()
+            .enumerate()
+            .filter_map(|(index, slot)| match slot {
+                Slot::Occupied { generation, value } => Some((
+                    Handle {
+                        index: index as u32,
+                        generation: *generation,
+                        pd: PhantomData,
+                    },
+                    value,
+                )),
+                Slot::Vacant { .. } => None,
+            })
+    }
+}
+impl<T> Default for PagedGenArena<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+impl<T> Index<Handle<T>> for PagedGenArena<T> {
+    type Output = T;
+    fn index(&self, handle: Handle<T>) -> &T {
+        self.get(handle)
+            .unwrap_or_else(|| panic!("{handle:?} refers to a removed value!"))
+    }
+}
+impl<T> IndexMut<Handle<T>> for PagedGenArena<T> {
+    fn index_mut(&mut self, handle: Handle<T>) -> &mut T {
+        self.get_mut(handle)
+            .unwrap_or_else(|| panic!("{handle:?} refers to a removed value!"))
+    }
+}
+impl<T: Debug> Debug for PagedGenArena<T> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_map().entries(self.iter()).finish()
+    }
+}
+#[cfg(test)]
+mod test {
+    use super::*;
+    #[test]
+    fn test_gen_arena_reuse() {
+        let mut arena: PagedGenArena<String> = PagedGenArena::new();
+        let handles: Vec<_> = (0..0x1000).map(|i| arena.insert(i.to_string())).collect();
+        assert_eq!(arena.len(), 0x1000);
+        arena.retain(|_, s| s.len() != 3);
+        assert_eq!(arena.len(), 0x1000 - 900);
+        assert!(arena.get(handles[100]).is_none());
+        assert_eq!(arena[handles[0x999]], "2457");
+        // Freed slots are reused before the arena grows.
+        let slots = arena.slots.len();
+        let reused: Vec<_> = (0..900).map(|_| arena.insert(String::new())).collect();
+        assert_eq!(arena.slots.len(), slots);
+        assert!(reused.iter().all(|h| h.generation() == 1));
+        assert!(!handles[100..1000].iter().any(|h| arena.contains(*h)));
+        let (a, b) = arena.get2_mut(handles[0], reused[0]);
+        std::mem::swap(a.unwrap(), b.unwrap());
+        assert_eq!(arena[reused[0]], "0");
+        let this = arena.insert_with(|handle| format!("{handle:?}"));
+        assert_eq!(arena[this], format!("Handle({}v0)", this.index()));
+        arena.clear();
+        assert!(arena.is_empty());
+        assert_eq!(arena.iter().count(), 0);
+    }
+    #[test]
+    fn test_gen_arena_retires_wrapped_slot() {
+        let mut arena = PagedGenArena::new();
+        let handle = arena.insert(1_u8);
+        arena.slots[0] = Slot::Occupied {
+            generation: u32::MAX,
+            value: 2,
+        };
+        let stale = Handle {
+            generation: u32::MAX,
+            ..handle
+        };
+        assert_eq!(arena.remove(stale), Some(2));
+        // The slot would get generation 0 again, so `handle` could become valid, were it reused.
+        let next = arena.insert(3);
+        assert_ne!(next.index(), handle.index());
+        assert!(arena.get(handle).is_none());
+    }
+}
@@ -0,0 +1,487 @@
+use crate::*;
+use std::borrow::Borrow;
+use std::collections::hash_map::RandomState;
+use std::fmt::{Debug, Formatter};
+use std::hash::{BuildHasher, Hash};
+use std::io;
+use std::ops::Index;
+/// Control byte of a bucket that was never used. Fresh pages are zeroed, so new tables need no initialization.
+const EMPTY: u8 = 0;
+/// Control byte of a bucket whose entry was removed. Lookups have to probe past it.
+const DELETED: u8 = 1;
+/// Bit set in control bytes of full buckets. The remaining bits hold the top 7 bits of the hash of the key.
+const FULL: u8 = 0x80;
+/// Bucket arrays of a [`PagedHashMap`].
+struct Table<K, V> {
+    /// One control byte per bucket.
+    ctrl: Pages<AllowRead, AllowWrite, DenyExec>,
+    entries: Pages<AllowRead, AllowWrite, DenyExec>,
+    /// Number of buckets minus 1. Number of buckets is always a power of two.
+    mask: usize,
+    pd: PhantomData<(K, V)>,
+}
+impl<K, V> Table<K, V> {
+    fn new(buckets: usize, huge_pages: bool) -> Self {
+        let entry_bytes = buckets
+            .checked_mul(std::mem::size_of::<(K, V)>())
+            .filter(|bytes| isize::try_from(*bytes).is_ok())
+            .unwrap_or_else(|| panic!("Capacity of PagedHashMap overflowed!"));
+        let mut ctrl = Pages::new(buckets);
+        let mut entries = Pages::new(entry_bytes.max(1));
+        // Hashing spreads accesses evenly over the whole table, so readahead would only waste memory bandwidth.
+        for pages in [&mut ctrl, &mut entries] {
+            pages.advise_use_rnd();
+            if huge_pages {
+                // Support was already checked when huge pages were enabled, and they are only an optimization.
+                let _ = advise_huge_pages(pages);
+            }
+        }
+        Self {
+            ctrl,
+            entries,
+            mask: buckets - 1,
+            pd: PhantomData,
+        }
+    }
+    fn buckets(&self) -> usize {
+        self.mask + 1
+    }
+    fn entry(&self, bucket: usize) -> *mut (K, V) {
+        unsafe {
+            self.entries
+                .get_ptr_unchecked()
+                .cast::<(K, V)>()
+                .add(bucket)
+        }
+    }
+    fn full(&self) -> impl Iterator<Item = usize> + '_ {
+        (0..self.buckets()).filter(|bucket| self.ctrl[*bucket] & FULL != 0)
+    }
+    /// Returns the first bucket on the probe sequence of `hash` that a new entry can be placed in.
+    fn free_bucket(&self, hash: u64) -> usize {
+        let mut bucket = hash as usize & self.mask;
+        while self.ctrl[bucket] & FULL != 0 {
+            bucket = (bucket + 1) & self.mask;
+        }
+        bucket
+    }
+}
+/// Returns the control byte of a full bucket holding a key with `hash`.
+fn ctrl_byte(hash: u64) -> u8 {
+    FULL | (hash >> 57) as u8
+}
+/// A hash map using open addressing, whose buckets are stored in [`Pages`]. It is meant for huge tables, with hundreds of
+/// millions of entries or more:
+/// - buckets are allocated directly from the kernel, and start zeroed, so allocating a table is instant, and pages of
+///   it are only backed by memory once they are used;
+/// - the kernel is advised that the table is accessed randomly, and can be asked to back it with huge pages, which
+///   greatly reduces TLB misses;
+/// - [`Self::clear`] and [`Self::shrink_to_fit`] return memory to the kernel right away.
+/// # Examples
+/// ```
+/// # use memory_pages::*;
+/// let mut ages:PagedHashMap<String, u32> = PagedHashMap::with_capacity(0x1000);
+/// ages.insert("Alice".to_owned(), 31);
+/// ages.insert("Bob".to_owned(), 25);
+/// assert_eq!(ages.get("Alice"), Some(&31));
+/// assert_eq!(ages.insert("Bob".to_owned(), 26), Some(25));
+/// assert_eq!(ages.remove("Alice"), Some(31));
+/// assert_eq!(ages.len(), 1);
+/// ```
+pub struct PagedHashMap<K, V, S = RandomState> {
+    /// Buckets, or `None` if none were allocated yet.
+    table: Option<Table<K, V>>,
+    len: usize,
+    /// Number of buckets marked as [`DELETED`].
+    deleted: usize,
+    huge_pages: bool,
+    hasher: S,
+}
+impl<K, V> PagedHashMap<K, V, RandomState> {
+    /// Creates a new, empty [`PagedHashMap`] without allocating any memory.
+    #[must_use]
+    pub fn new() -> Self {
+        Self::with_hasher(RandomState::new())
+    }
+    /// Creates a new, empty [`PagedHashMap`] able to hold at least `capacity` entries without growing.
+    /// # Panics
+    /// Panics if the capacity overflows, or if kernel can't/refuses to allocate the buckets.
+    #[must_use]
+    pub fn with_capacity(capacity: usize) -> Self {
+        Self::with_capacity_and_hasher(capacity, RandomState::new())
+    }
+}
+impl<K, V> Default for PagedHashMap<K, V, RandomState> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+impl<K, V, S> PagedHashMap<K, V, S> {
+    /// Creates a new, empty [`PagedHashMap`] using `hasher` to hash keys, without allocating any memory.
+    #[must_use]
+    pub fn with_hasher(hasher: S) -> Self {
+        Self {
+            table: None,
+            len: 0,
+            deleted: 0,
+            huge_pages: false,
+            hasher,
+        }
+    }
+    /// Creates a new, empty [`PagedHashMap`] using `hasher` to hash keys, able to hold at least `capacity` entries
+    /// without growing.
+    /// # Panics
+    /// Panics if the capacity overflows, or if kernel can't/refuses to allocate the buckets.
+    #[must_use]
+    pub fn with_capacity_and_hasher(capacity: usize, hasher: S) -> Self {
+        let mut map = Self::with_hasher(hasher);
+        if capacity > 0 {
+            map.table = Some(Table::new(Self::buckets_for(capacity), false));
+        }
+        map
+    }
+    /// Returns the number of entries in this [`PagedHashMap`].
+    #[must_use]
+    pub fn len(&self) -> usize {
+        self.len
+    }
+    /// Checks if this [`PagedHashMap`] is empty.
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+    /// Returns the number of entries this [`PagedHashMap`] can hold without growing.
+    #[must_use]
+    pub fn capacity(&self) -> usize {
+        self.table
+            .as_ref()
+            .map_or(0, |table| Self::max_load(table.buckets()))
+    }
+    /// Advises the kernel to back buckets of this [`PagedHashMap`] with transparent huge pages, now and after it grows.
+    /// For huge tables, this greatly reduces TLB misses, which dominate the cost of random lookups.
+    /// # Errors
+    /// Returns an error on platforms other than Linux, or if the kernel does not support transparent huge pages.
+    /// # Examples
+    /// ```
+    /// # use memory_pages::*;
+    /// let mut map:PagedHashMap<u64, u64> = PagedHashMap::with_capacity(0x100_000);
+    /// # #[cfg(target_os = "linux")]
+    /// map.use_huge_pages().unwrap();
+    /// map.insert(1, 2);
+    /// ```
+    pub fn use_huge_pages(&mut self) -> io::Result<()> {
+        if let Some(table) = &self.table {
+            advise_huge_pages(&table.ctrl)?;
+            advise_huge_pages(&table.entries)?;
+        }
+        // Checks support, even if no buckets are allocated yet.
+        #[cfg(not(target_os = "linux"))]
+        advise_huge_pages(&Pages::new(1))?;
+        self.huge_pages = true;
+        Ok(())
+    }
+    /// Returns an iterator over all entries, in arbitrary order.
+    pub fn iter(&self) -> impl Iterator<Item = (&K, &V)> + '_ {
+        self.table.iter().flat_map(|table| {
+            table.full().map(|bucket| {
+                let (k, v) = unsafe { &*table.entry(bucket) };
+                (k, v)
+            })
+        })
+    }
+    /// Returns an iterator over all entries, in arbitrary order, allowing values to be modified.
+    pub fn iter_mut(&mut self) -> impl Iterator<Item = (&K, &mut V)> + '_ {
+        self.table.iter_mut().flat_map(|table| {
+            let table = &*table;
+            table.full().map(|bucket| {
+                let (k, v) = unsafe { &mut *table.entry(bucket) };
+                (&*k, v)
+            })
+        })
+    }
+    /// Returns an iterator over all keys, in arbitrary order.
+    pub fn keys(&self) -> impl Iterator<Item = &K> + '_ {
+        self.iter().map(|(k, _)| k)
+    }
+    /// Returns an iterator over all values, in arbitrary order.
+    pub fn values(&self) -> impl Iterator<Item = &V> + '_ {
+        self.iter().map(|(_, v)| v)
+    }
+    /// Removes all entries. Capacity is kept, but the buckets are replaced with fresh ones, so memory used by them is
+    /// returned to the kernel right away.
+    pub fn clear(&mut self) {
+        let Some(table) = self.table.take() else {
+            return;
+        };
+        self.len = 0;
+        self.deleted = 0;
+        let buckets = table.buckets();
+        // Dropping the old table does not drop entries, so a panicking `drop` can only leak them.
+        Self::drop_entries(&table);
+        self.table = Some(Table::new(buckets, self.huge_pages));
+    }
+    fn drop_entries(table: &Table<K, V>) {
+        if std::mem::needs_drop::<(K, V)>() {
+            for bucket in table.full() {
+                unsafe { std::ptr::drop_in_place(table.entry(bucket)) };
+            }
+        }
+    }
+    /// Returns the number of entries a table with `buckets` buckets can hold, keeping the load factor at most 7/8.
+    fn max_load(buckets: usize) -> usize {
+        buckets / 8 * 7
+    }
+    /// Returns the number of buckets needed to hold `capacity` entries.
+    fn buckets_for(capacity: usize) -> usize {
+        // At least a whole page worth of entries, since smaller tables would waste most of it anyway.
+        let min = (page_math::PAGE_SIZE / std::mem::size_of::<(K, V)>().max(1))
+            .next_power_of_two()
+            .max(16);
+        capacity
+            .checked_add(capacity / 7 + 1)
+            .and_then(usize::checked_next_power_of_two)
+            .unwrap_or_else(|| panic!("Capacity of PagedHashMap overflowed!"))
+            .max(min)
+    }
+}
+impl<K: Hash + Eq, V, S: BuildHasher> PagedHashMap<K, V, S> {
+    /// Inserts `v` under `k`, returning the previous value stored under it, if any.
+    /// # Panics
+    /// Panics if the capacity overflows, or if kernel can't/refuses to allocate more buckets.
+    pub fn insert(&mut self, k: K, v: V) -> Option<V> {
+        let hash = self.hasher.hash_one(&k);
+        if let Some(bucket) = self.find(hash, &k) {
+            let table = self
+                .table
+                .as_ref()
+                .expect("found entries are inside a table");
+            return Some(std::mem::replace(
+                unsafe { &mut (*table.entry(bucket)).1 },
+                v,
+            ));
+        }
+        self.reserve(1);
+        let table = self.table.as_mut().expect("reserving allocates a table");
+        let bucket = table.free_bucket(hash);
+        if table.ctrl[bucket] == DELETED {
+            self.deleted -= 1;
+        }
+        table.ctrl[bucket] = ctrl_byte(hash);
+        unsafe { table.entry(bucket).write((k, v)) };
+        self.len += 1;
+        None
+    }
+    /// Returns a reference to the value stored under `k`, if any.
+    #[must_use]
+    pub fn get<Q: Hash + Eq + ?Sized>(&self, k: &Q) -> Option<&V>
+    where
+        K: Borrow<Q>,
+    {
+        self.get_key_value(k).map(|(_, v)| v)
+    }
+    /// Returns references to the key and value stored under `k`, if any.
+    #[must_use]
+    pub fn get_key_value<Q: Hash + Eq + ?Sized>(&self, k: &Q) -> Option<(&K, &V)>
+    where
+        K: Borrow<Q>,
+    {
+        let bucket = self.find(self.hasher.hash_one(k), k)?;
+        let (k, v) = unsafe { &*self.table.as_ref()?.entry(bucket) };
+        Some((k, v))
+    }
+    /// Returns a mutable reference to the value stored under `k`, if any.
+    #[must_use]
+    pub fn get_mut<Q: Hash + Eq + ?Sized>(&mut self, k: &Q) -> Option<&mut V>
+    where
+        K: Borrow<Q>,
+    {
+        let bucket = self.find(self.hasher.hash_one(k), k)?;
+        Some(unsafe { &mut (*self.table.as_ref()?.entry(bucket)).1 })
+    }
+    /// Checks if any value is stored under `k`.
+    #[must_use]
+    pub fn contains_key<Q: Hash + Eq + ?Sized>(&self, k: &Q) -> bool
+    where
+        K: Borrow<Q>,
+    {
+        self.find(self.hasher.hash_one(k), k).is_some()
+    }
+    /// Removes the entry stored under `k`, returning its value, if any.
+    pub fn remove<Q: Hash + Eq + ?Sized>(&mut self, k: &Q) -> Option<V>
+    where
+        K: Borrow<Q>,
+    {
+        self.remove_entry(k).map(|(_, v)| v)
+    }
+    /// Removes the entry stored under `k`, returning it, if any.
+    pub fn remove_entry<Q: Hash + Eq + ?Sized>(&mut self, k: &Q) -> Option<(K, V)>
+    where
+        K: Borrow<Q>,
+    {
+        let bucket = self.find(self.hasher.hash_one(k), k)?;
+        let table = self.table.as_mut()?;
+        // Probing stops at empty buckets, so if the next one is empty, no probe sequence can pass through this one.
+        if table.ctrl[(bucket + 1) & table.mask] == EMPTY {
+            table.ctrl[bucket] = EMPTY;
+        } else {
+            table.ctrl[bucket] = DELETED;
+            self.deleted += 1;
+        }
+        self.len -= 1;
+        Some(unsafe { table.entry(bucket).read() })
+    }
+    /// Reserves capacity for at least `additional` more entries.
+    /// # Panics
+    /// Panics if the capacity overflows, or if kernel can't/refuses to allocate more buckets.
+    pub fn reserve(&mut self, additional: usize) {
+        let needed = self
+            .len
+            .checked_add(additional)
+            .unwrap_or_else(|| panic!("Capacity of PagedHashMap overflowed!"));
+        let buckets = self.table.as_ref().map_or(0, Table::buckets);
+        if needed + self.deleted <= Self::max_load(buckets) {
+            return;
+        }
+        // If most of the load comes from deleted buckets, cleaning them up is enough.
+        if needed <= Self::max_load(buckets) / 2 {
+            self.rehash(buckets);
+        } else {
+            self.rehash(Self::buckets_for(needed).max(buckets * 2));
+        }
+    }
+    /// Shrinks the buckets as much as possible, returning memory used by the rest to the kernel. All buckets are
+    /// released if this [`PagedHashMap`] is empty.
+    pub fn shrink_to_fit(&mut self) {
+        if self.len == 0 {
+            self.table = None;
+            self.deleted = 0;
+            return;
+        }
+        let buckets = Self::buckets_for(self.len);
+        if self
+            .table
+            .as_ref()
+            .is_some_and(|table| buckets < table.buckets())
+        {
+            self.rehash(buckets);
+        }
+    }
+    /// Moves all entries into a new table with `buckets` buckets.
+    fn rehash(&mut self, buckets: usize) {
+        let mut new = Table::new(buckets, self.huge_pages);
+        if let Some(old) = &self.table {
+            for bucket in old.full() {
+                // If hashing panics, the entries copied so far are still owned by the old table, and never dropped twice.
+                let hash = self.hasher.hash_one(unsafe { &(*old.entry(bucket)).0 });
+                let free = new.free_bucket(hash);
+                unsafe { std::ptr::copy_nonoverlapping(old.entry(bucket), new.entry(free), 1) };
+                new.ctrl[free] = ctrl_byte(hash);
+            }
+        }
+        self.table = Some(new);
+        self.deleted = 0;
+    }
+    /// Returns the bucket holding `k`, if any.
+    fn find<Q: Eq + ?Sized>(&self, hash: u64, k: &Q) -> Option<usize>
+    where
+        K: Borrow<Q>,
+    {
+        let table = self.table.as_ref()?;
+        let ctrl = ctrl_byte(hash);
+        let mut bucket = hash as usize & table.mask;
+        loop {
+            match table.ctrl[bucket] {
+                EMPTY => return None,
+                byte if byte == ctrl && unsafe { (*table.entry(bucket)).0.borrow() } == k => {
+                    return Some(bucket)
+                }
+                _ => bucket = (bucket + 1) & table.mask,
+            }
+        }
+    }
+}
+impl<K: Hash + Eq + Borrow<Q>, Q: Hash + Eq + ?Sized, V, S: BuildHasher> Index<&Q>
+    for PagedHashMap<K, V, S>
+{
+    type Output = V;
+    fn index(&self, k: &Q) -> &V {
+        self.get(k).expect("key not present in PagedHashMap!")
+    }
+}
+impl<K: Hash + Eq, V, S: BuildHasher> Extend<(K, V)> for PagedHashMap<K, V, S> {
+    fn extend<I: IntoIterator<Item = (K, V)>>(&mut self, iter: I) {
+        let iter = iter.into_iter();
+        self.reserve(iter.size_hint().0);
+        for (k, v) in iter {
+            self.insert(k, v);
+        }
+    }
+}
+impl<K: Hash + Eq, V, S: BuildHasher + Default> FromIterator<(K, V)> for PagedHashMap<K, V, S> {
+    fn from_iter<I: IntoIterator<Item = (K, V)>>(iter: I) -> Self {
+        let mut map = Self::with_hasher(S::default());
+        map.extend(iter);
+        map
+    }
+}
+impl<K: Debug, V: Debug, S> Debug for PagedHashMap<K, V, S> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_map().entries(self.iter()).finish()
+    }
+}
+impl<K, V, S> Drop for PagedHashMap<K, V, S> {
+    fn drop(&mut self) {
+        if let Some(table) = &self.table {
+            Self::drop_entries(table);
+        }
+    }
+}
+#[cfg(test)]
+mod test {
+    use super::*;
+    #[test]
+    fn test_insert_remove() {
+        let mut map: PagedHashMap<String, usize> = PagedHashMap::new();
+        assert_eq!(map.capacity(), 0);
+        for i in 0..0x10_000 {
+            assert_eq!(map.insert(i.to_string(), i), None);
+        }
+        assert_eq!(map.len(), 0x10_000);
+        assert!(map.capacity() >= 0x10_000);
+        for i in (0..0x10_000).step_by(2) {
+            assert_eq!(map.remove(&i.to_string()), Some(i));
+        }
+        assert_eq!(map.remove("0"), None);
+        for i in 0..0x10_000 {
+            assert_eq!(map.get(&i.to_string()), (i % 2 == 1).then_some(&i));
+        }
+        *map.get_mut("1").unwrap() = 7;
+        assert_eq!(map["1"], 7);
+        assert_eq!(map.iter().count(), 0x8_000);
+        let capacity = map.capacity();
+        map.shrink_to_fit();
+        assert!(map.capacity() < capacity);
+        assert_eq!(map.values().filter(|v| **v % 2 == 1).count(), 0x8_000);
+        map.clear();
+        assert!(map.is_empty());
+        assert_eq!(map.get("3"), None);
+        map.shrink_to_fit();
+        assert_eq!(map.capacity(), 0);
+    }
+    #[test]
+    fn test_deleted_buckets_reused() {
+        let mut map: PagedHashMap<u64, u64> = PagedHashMap::with_capacity(0x100);
+        let capacity = map.capacity();
+        // Churning through many more keys than the capacity only cleans up deleted buckets, instead of growing.
+        for i in 0..0x10_000 {
+            map.insert(i, i);
+            if i >= 0x10 {
+                assert_eq!(map.remove(&(i - 0x10)), Some(i - 0x10));
+            }
+        }
+        assert_eq!(map.capacity(), capacity);
+        assert_eq!(map.len(), 0x10);
+        let map: PagedHashMap<u64, u64> = (0..0x100).map(|i| (i, i * 2)).collect();
+        assert_eq!(map.get(&0x80), Some(&0x100));
+    }
+}
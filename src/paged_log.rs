@@ -0,0 +1,352 @@
+use crate::page_math::align_up;
+use crate::*;
+use std::fs::{File, OpenOptions};
+use std::io;
+use std::path::Path;
+/// Marks files holding a [`PagedLog`], and the version of their layout.
+const MAGIC: u64 = u64::from_le_bytes(*b"MPLOG\0\0\x01");
+/// Size of a single header slot, in bytes.
+const SLOT_SIZE: usize = 0x40;
+/// Offset of the first record. Two header slots live before it.
+const DATA_START: usize = 0x1000;
+/// Size of the length and checksum preceding each record.
+const RECORD_HEADER: usize = 16;
+/// Initial size of the log file.
+const INITIAL_SIZE: usize = 0x10_000;
+/// FNV-1a hash, used to detect torn or corrupted writes.
+fn checksum(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0xcbf2_9ce4_8422_2325, |hash, byte| {
+        (hash ^ u64::from(*byte)).wrapping_mul(0x0100_0000_01b3)
+    })
+}
+/// Reads the little endian `u64` at `offset`.
+fn read_u64(bytes: &[u8], offset: usize) -> u64 {
+    u64::from_le_bytes(
+        bytes[offset..offset + 8]
+            .try_into()
+            .expect("slice is 8 bytes long"),
+    )
+}
+/// Durable state of a [`PagedLog`], as stored in a header slot.
+#[derive(Clone, Copy, Default)]
+struct Header {
+    /// Incremented on every flush. Slot with the highest valid sequence is the current one.
+    sequence: u64,
+    /// Number of bytes of records.
+    bytes: u64,
+    /// Number of records.
+    count: u64,
+}
+impl Header {
+    fn encode(self) -> [u8; 32] {
+        let mut slot = [0; 32];
+        for (i, field) in [MAGIC, self.sequence, self.bytes, self.count]
+            .into_iter()
+            .enumerate()
+        {
+            slot[i * 8..(i + 1) * 8].copy_from_slice(&field.to_le_bytes());
+        }
+        slot
+    }
+    /// Decodes a header slot, or returns `None` if it was never written or was torn by a crash.
+    fn decode(slot: &[u8]) -> Option<Self> {
+        let valid = read_u64(slot, 0) == MAGIC && read_u64(slot, 32) == checksum(&slot[..32]);
+        valid.then(|| Self {
+            sequence: read_u64(slot, 8),
+            bytes: read_u64(slot, 16),
+            count: read_u64(slot, 24),
+        })
+    }
+}
+/// An append-only log of byte records, stored in a memory mapped file. Appending a record is just a copy into the
+/// mapping, and [`Self::flush`] makes all records appended so far durable.
+///
+/// The log is crash-consistent: its header, recording how many records are durable, is stored twice, and updates
+/// alternate between the two copies, each protected by a checksum. A crash in the middle of a flush leaves the previous
+/// header intact, so reopening the log recovers every record that was flushed before, and discards the rest. Each
+/// record is also checksummed, so corruption of durable records is detected when the log is opened.
+/// # Examples
+/// ```
+/// # use memory_pages::*;
+/// # let path = std::env::temp_dir().join("memory_pages_doc_paged_log");
+/// let mut log = PagedLog::create(&path).unwrap();
+/// log.append(b"first").unwrap();
+/// log.append(b"second").unwrap();
+/// log.flush().unwrap();
+/// // Never flushed, so it is lost on a crash.
+/// log.append(b"third").unwrap();
+/// // Simulates a crash, by never flushing the log.
+/// std::mem::forget(log);
+/// let log = PagedLog::open(&path).unwrap();
+/// assert_eq!(log.iter().collect::<Vec<_>>(), [&b"first"[..], b"second"]);
+/// # drop(log);
+/// # std::fs::remove_file(&path).unwrap();
+/// ```
+pub struct PagedLog {
+    pages: Pages<AllowRead, AllowWrite, DenyExec>,
+    /// State as of the last flush.
+    durable: Header,
+    /// Number of bytes of records, including ones not flushed yet.
+    bytes: usize,
+    /// Number of records, including ones not flushed yet.
+    count: usize,
+}
+impl PagedLog {
+    /// Creates a new, empty log at `path`, replacing any file already there.
+    /// # Errors
+    /// Returns an error if the file can't be created or mapped.
+    pub fn create(path: impl AsRef<Path>) -> io::Result<Self> {
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(path)?;
+        Self::init(&file)
+    }
+    /// Opens the log at `path`, recovering all records that were flushed before it was last closed, or before a crash.
+    /// Creates a new, empty log if there is no file at `path`.
+    /// # Errors
+    /// Returns an error if the file can't be opened or mapped, or [`io::ErrorKind::InvalidData`] if it is not a log, or
+    /// if any durable record is corrupted.
+    pub fn open(path: impl AsRef<Path>) -> io::Result<Self> {
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(path)?;
+        let file_len = file.metadata()?.len();
+        if file_len == 0 {
+            return Self::init(&file);
+        }
+        let invalid = |msg: &str| io::Error::new(io::ErrorKind::InvalidData, msg);
+        let file_len = usize::try_from(file_len).map_err(|_| invalid("log file too large"))?;
+        if file_len < DATA_START {
+            return Err(invalid("log file truncated"));
+        }
+        // Bytes past the end of the file in its last page are never written back, so files always span whole pages.
+        let pages = Pages::map_file(&file, align_up(file_len))?;
+        let bytes: &[u8] = &pages;
+        let durable = [0, SLOT_SIZE]
+            .into_iter()
+            .filter_map(|slot| Header::decode(&bytes[slot..slot + SLOT_SIZE]))
+            .max_by_key(|header| header.sequence)
+            .ok_or_else(|| invalid("log header corrupted"))?;
+        let bytes = usize::try_from(durable.bytes)
+            .ok()
+            .filter(|bytes| DATA_START + bytes <= file_len)
+            .ok_or_else(|| invalid("log header points past the end of the file"))?;
+        let mut log = Self {
+            pages,
+            durable,
+            bytes,
+            count: 0,
+        };
+        let count = log
+            .records()
+            .map(|record| record.map(|_| 1))
+            .sum::<io::Result<usize>>()?;
+        if count as u64 != durable.count {
+            return Err(invalid("log record count mismatch"));
+        }
+        log.count = count;
+        Ok(log)
+    }
+    /// Maps `file` and writes an empty header into it.
+    fn init(file: &File) -> io::Result<Self> {
+        let mut log = Self {
+            pages: Pages::map_file(file, INITIAL_SIZE)?,
+            durable: Header::default(),
+            bytes: 0,
+            count: 0,
+        };
+        log.write_header()?;
+        Ok(log)
+    }
+    /// Returns the number of records, including ones not flushed yet.
+    #[must_use]
+    pub fn len(&self) -> usize {
+        self.count
+    }
+    /// Checks if this [`PagedLog`] has no records.
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.count == 0
+    }
+    /// Returns the number of records which are durable, and would survive a crash.
+    #[must_use]
+    pub fn durable_len(&self) -> usize {
+        self.durable.count as usize
+    }
+    /// Appends `record` to the end of this [`PagedLog`], growing the file if needed, and returns its index. The record
+    /// only becomes durable once the log is [`Self::flush`]ed.
+    /// # Errors
+    /// Returns an error if the file can't be grown.
+    pub fn append(&mut self, record: &[u8]) -> io::Result<usize> {
+        let size = RECORD_HEADER + record.len().next_multiple_of(8);
+        let end = DATA_START + self.bytes + size;
+        if end > self.pages.len() {
+            // The file must span the whole mapping, or records in its last page would never be written back.
+            self.pages
+                .try_resize(align_up(end.max(self.pages.len() * 2)))?;
+        }
+        let start = DATA_START + self.bytes;
+        let bytes: &mut [u8] = &mut self.pages;
+        bytes[start..start + 8].copy_from_slice(&(record.len() as u64).to_le_bytes());
+        bytes[start + 8..start + 16].copy_from_slice(&checksum(record).to_le_bytes());
+        bytes[start + 16..start + 16 + record.len()].copy_from_slice(record);
+        self.bytes += size;
+        self.count += 1;
+        Ok(self.count - 1)
+    }
+    /// Makes all records appended so far durable, waiting until they are written into the file.
+    /// # Errors
+    /// Returns an error if the kernel fails to write the data back. Records appended since the last successful flush
+    /// are then not guaranteed to be durable.
+    pub fn flush(&mut self) -> io::Result<()> {
+        let durable_bytes = self.durable.bytes as usize;
+        if self.bytes == durable_bytes {
+            return Ok(());
+        }
+        // Records must reach the file before the header pointing at them.
+        self.pages.flush_range(
+            DATA_START + durable_bytes,
+            self.bytes - durable_bytes,
+            FlushMode::Sync,
+        )?;
+        self.write_header()
+    }
+    /// Writes the current state into the slot not holding the durable header, and waits for it to reach the file.
+    fn write_header(&mut self) -> io::Result<()> {
+        let header = Header {
+            sequence: self.durable.sequence + 1,
+            bytes: self.bytes as u64,
+            count: self.count as u64,
+        };
+        let slot = (header.sequence % 2) as usize * SLOT_SIZE;
+        let encoded = header.encode();
+        let bytes: &mut [u8] = &mut self.pages;
+        bytes[slot..slot + 32].copy_from_slice(&encoded);
+        bytes[slot + 32..slot + 40].copy_from_slice(&checksum(&encoded).to_le_bytes());
+        self.pages.flush_range(slot, SLOT_SIZE, FlushMode::Sync)?;
+        self.durable = header;
+        Ok(())
+    }
+    /// Returns an iterator over all records, including ones not flushed yet.
+    pub fn iter(&self) -> impl Iterator<Item = &[u8]> + '_ {
+        self.records()
+            .map(|record| record.expect("records are validated when a log is opened"))
+    }
+    /// Returns an iterator over all records, checking each of them.
+    fn records(&self) -> impl Iterator<Item = io::Result<&[u8]>> + '_ {
+        let bytes: &[u8] = &self.pages;
+        let data = &bytes[DATA_START..DATA_START + self.bytes];
+        let mut offset = 0;
+        std::iter::from_fn(move || {
+            if offset == data.len() {
+                return None;
+            }
+            let corrupted = || io::Error::new(io::ErrorKind::InvalidData, "log record corrupted");
+            let record = (offset + RECORD_HEADER <= data.len())
+                .then(|| usize::try_from(read_u64(data, offset)).ok())
+                .flatten()
+                .and_then(|len| {
+                    let start = offset + RECORD_HEADER;
+                    let end = start.checked_add(len.checked_next_multiple_of(8)?)?;
+                    let record = data.get(start..start + len)?;
+                    (end <= data.len() && checksum(record) == read_u64(data, offset + 8))
+                        .then_some((record, end))
+                });
+            match record {
+                Some((record, end)) => {
+                    offset = end;
+                    Some(Ok(record))
+                }
+                None => {
+                    // Nothing after a corrupted record can be trusted.
+                    offset = data.len();
+                    Some(Err(corrupted()))
+                }
+            }
+        })
+    }
+}
+impl Drop for PagedLog {
+    fn drop(&mut self) {
+        // Errors can't be reported from `drop`, call `PagedLog::flush` to handle them.
+        let _ = self.flush();
+    }
+}
+impl std::fmt::Debug for PagedLog {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("PagedLog")
+            .field("len", &self.len())
+            .field("durable_len", &self.durable_len())
+            .finish()
+    }
+}
+#[cfg(test)]
+mod test {
+    use super::*;
+    #[test]
+    fn test_log_recovery() {
+        let path = std::env::temp_dir().join(format!("memory_pages_log_{}", std::process::id()));
+        let mut log = PagedLog::create(&path).unwrap();
+        for i in 0..0x2000_u32 {
+            assert_eq!(
+                log.append(&i.to_le_bytes().repeat(i as usize % 5)).unwrap(),
+                i as usize
+            );
+        }
+        log.flush().unwrap();
+        log.append(b"lost").unwrap();
+        // Simulates a crash, by restoring the file as it was before the last record was flushed.
+        let crashed = std::fs::read(&path).unwrap();
+        drop(log);
+        std::fs::write(&path, &crashed).unwrap();
+        let mut log = PagedLog::open(&path).unwrap();
+        assert_eq!(log.len(), 0x2000);
+        assert!(log
+            .iter()
+            .enumerate()
+            .all(|(i, record)| record == (i as u32).to_le_bytes().repeat(i % 5)));
+        log.append(b"kept").unwrap();
+        drop(log);
+        // A torn write of the newer header falls back to the older one.
+        let mut bytes = std::fs::read(&path).unwrap();
+        bytes[SLOT_SIZE + 8] ^= 1;
+        std::fs::write(&path, &bytes).unwrap();
+        let log = PagedLog::open(&path).unwrap();
+        assert_eq!(log.len(), 0x2000);
+        assert_eq!(log.durable_len(), 0x2000);
+        drop(log);
+        // Corrupted records are detected.
+        bytes[DATA_START + RECORD_HEADER + 4] ^= 1;
+        std::fs::write(&path, &bytes).unwrap();
+        assert_eq!(
+            PagedLog::open(&path).unwrap_err().kind(),
+            io::ErrorKind::InvalidData
+        );
+        std::fs::remove_file(&path).unwrap();
+    }
+    #[test]
+    fn test_log_large_record() {
+        let path =
+            std::env::temp_dir().join(format!("memory_pages_log_large_{}", std::process::id()));
+        let mut log = PagedLog::create(&path).unwrap();
+        log.append(&vec![3; 0x21000]).unwrap();
+        log.append(b"small").unwrap();
+        log.flush().unwrap();
+        drop(log);
+        assert!(std::fs::metadata(&path)
+            .unwrap()
+            .len()
+            .is_multiple_of(crate::page_math::PAGE_SIZE as u64));
+        let log = PagedLog::open(&path).unwrap();
+        assert_eq!(log.len(), 2);
+        assert_eq!(log.iter().nth(1).unwrap(), b"small");
+        drop(log);
+        std::fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,241 @@
+use crate::paged_vec_builder::bind_to_node;
+use crate::*;
+use std::fmt::{Debug, Formatter};
+use std::io;
+use std::ops::{Index, IndexMut, Range};
+/// A dense, row-major 2-D array, in which each row starts on a page boundary. Since no two rows share a page, memory of
+/// each row can be managed separately: rows can be returned to the kernel with [`Self::decommit_row`], or placed on a
+/// chosen NUMA node with [`Self::bind_rows`], which is what out-of-core and NUMA-aware linear algebra needs.
+///
+/// Elements start out zeroed, and memory of a row is only backed by RAM once it is written to. Padding rows to whole
+/// pages wastes memory when rows are much shorter than a page, so [`PagedMatrix`] is meant for wide matrices.
+/// # Examples
+/// ```
+/// # use memory_pages::*;
+/// let mut matrix: PagedMatrix<f64> = PagedMatrix::new(0x100, 0x1000);
+/// matrix[(3, 7)] = 1.5;
+/// matrix.row_mut(5).fill(2.0);
+/// assert_eq!(matrix.row(3)[7], 1.5);
+/// assert_eq!(matrix.column(7).sum::<f64>(), 3.5);
+/// // Rows start on page boundaries, so the row can be released without touching its neighbours.
+/// matrix.decommit_row(5);
+/// assert!(matrix.row(5).iter().all(|x| *x == 0.0));
+/// assert_eq!(matrix[(3, 7)], 1.5);
+/// ```
+pub struct PagedMatrix<T: Zeroable + Copy> {
+    memory: SparsePages,
+    rows: usize,
+    cols: usize,
+    /// Distance between the beginnings of consecutive rows, in bytes.
+    row_stride: usize,
+    pd: PhantomData<T>,
+}
+unsafe impl<T: Zeroable + Copy + Send> Send for PagedMatrix<T> {}
+unsafe impl<T: Zeroable + Copy + Sync> Sync for PagedMatrix<T> {}
+impl<T: Zeroable + Copy> PagedMatrix<T> {
+    /// Creates a new `rows` x `cols` [`PagedMatrix`], with all elements zeroed.
+    /// # Panics
+    /// Panics if the matrix would have no elements, if `T` is zero-sized, if its size overflows, or if kernel
+    /// can't/refuses to allocate it.
+    #[must_use]
+    pub fn new(rows: usize, cols: usize) -> Self {
+        assert!(
+            rows != 0 && cols != 0 && std::mem::size_of::<T>() != 0,
+            "PagedMatrix can't be empty!"
+        );
+        let row_stride = cols
+            .checked_mul(std::mem::size_of::<T>())
+            .and_then(|bytes| bytes.checked_next_multiple_of(page_math::PAGE_SIZE))
+            .filter(|stride| {
+                stride
+                    .checked_mul(rows)
+                    .is_some_and(|bytes| isize::try_from(bytes).is_ok())
+            })
+            .unwrap_or_else(|| panic!("Capacity of PagedMatrix overflowed!"));
+        let mut memory = SparsePages::new(row_stride * rows);
+        memory.commit(0, row_stride * rows);
+        Self {
+            memory,
+            rows,
+            cols,
+            row_stride,
+            pd: PhantomData,
+        }
+    }
+    /// Returns the number of rows.
+    #[must_use]
+    pub fn rows(&self) -> usize {
+        self.rows
+    }
+    /// Returns the number of columns.
+    #[must_use]
+    pub fn cols(&self) -> usize {
+        self.cols
+    }
+    /// Returns the distance between the beginnings of consecutive rows, in bytes. It is always a multiple of page size.
+    #[must_use]
+    pub fn row_stride(&self) -> usize {
+        self.row_stride
+    }
+    /// Returns a reference to the element at `row`, `col`, or `None` if it is out of bounds.
+    #[must_use]
+    pub fn get(&self, row: usize, col: usize) -> Option<&T> {
+        (row < self.rows && col < self.cols).then(|| &self.row(row)[col])
+    }
+    /// Returns a mutable reference to the element at `row`, `col`, or `None` if it is out of bounds.
+    #[must_use]
+    pub fn get_mut(&mut self, row: usize, col: usize) -> Option<&mut T> {
+        (row < self.rows && col < self.cols).then(|| &mut self.row_mut(row)[col])
+    }
+    /// Returns row `row` as a slice.
+    /// # Panics
+    /// Panics if `row` is out of bounds.
+    #[must_use]
+    pub fn row(&self, row: usize) -> &[T] {
+        unsafe { std::slice::from_raw_parts(self.row_ptr(row), self.cols) }
+    }
+    /// Returns row `row` as a mutable slice.
+    /// # Panics
+    /// Panics if `row` is out of bounds.
+    #[must_use]
+    pub fn row_mut(&mut self, row: usize) -> &mut [T] {
+        unsafe { std::slice::from_raw_parts_mut(self.row_ptr(row), self.cols) }
+    }
+    /// Returns an iterator over all rows, as slices.
+    pub fn iter_rows(&self) -> impl ExactSizeIterator<Item = &[T]> + '_ {
+        (0..self.rows).map(|row| self.row(row))
+    }
+    /// Returns an iterator over all rows, as mutable slices.
+    pub fn iter_rows_mut(&mut self) -> impl ExactSizeIterator<Item = &mut [T]> + '_ {
+        let this = &*self;
+        // Rows never overlap, and `self` is mutably borrowed for as long as the iterator exists.
+        (0..self.rows)
+            .map(move |row| unsafe { std::slice::from_raw_parts_mut(this.row_ptr(row), this.cols) })
+    }
+    /// Returns an iterator over elements of column `col`, from the first row to the last.
+    /// # Panics
+    /// Panics if `col` is out of bounds.
+    pub fn column(&self, col: usize) -> impl ExactSizeIterator<Item = &T> + '_ {
+        self.check_col(col);
+        self.iter_rows().map(move |row| &row[col])
+    }
+    /// Returns an iterator over mutable references to elements of column `col`, from the first row to the last.
+    /// # Panics
+    /// Panics if `col` is out of bounds.
+    pub fn column_mut(&mut self, col: usize) -> impl ExactSizeIterator<Item = &mut T> + '_ {
+        self.check_col(col);
+        self.iter_rows_mut().map(move |row| &mut row[col])
+    }
+    /// Zeroes row `row`, returning its memory to the kernel. The row is backed by RAM again once it is written to.
+    /// # Panics
+    /// Panics if `row` is out of bounds, or if kernel can't/refuses to release the memory.
+    pub fn decommit_row(&mut self, row: usize) {
+        self.decommit_rows(row..row + 1);
+    }
+    /// Zeroes all rows in `rows`, returning their memory to the kernel.
+    /// # Panics
+    /// Panics if `rows` is out of bounds, or if kernel can't/refuses to release the memory.
+    pub fn decommit_rows(&mut self, rows: Range<usize>) {
+        let (beginning, length) = self.row_span(rows);
+        if length == 0 {
+            return;
+        }
+        self.memory.decommit(beginning, length);
+        // Recommitted pages are zeroed, but not backed by RAM until touched.
+        self.memory.commit(beginning, length);
+    }
+    /// Makes memory of all rows in `rows` preferably come from NUMA node `node`. Only rows which were not written to
+    /// yet are affected, so it is best to place rows right after creating the matrix. Only supported on Linux.
+    /// # Errors
+    /// Returns an error if the platform does not support NUMA placement, or if the kernel refuses it.
+    /// # Panics
+    /// Panics if `rows` is out of bounds.
+    pub fn bind_rows(&mut self, rows: Range<usize>, node: u32) -> io::Result<()> {
+        let (beginning, length) = self.row_span(rows);
+        bind_to_node(
+            unsafe { self.memory.as_mut_ptr().add(beginning) },
+            length,
+            node,
+        )
+    }
+    fn row_ptr(&self, row: usize) -> *mut T {
+        assert!(
+            row < self.rows,
+            "row {row} out of bounds of {} rows!",
+            self.rows
+        );
+        unsafe {
+            self.memory
+                .as_ptr()
+                .add(row * self.row_stride)
+                .cast_mut()
+                .cast()
+        }
+    }
+    fn check_col(&self, col: usize) {
+        assert!(
+            col < self.cols,
+            "column {col} out of bounds of {} columns!",
+            self.cols
+        );
+    }
+    /// Returns the offset and length, in bytes, of the pages holding `rows`.
+    fn row_span(&self, rows: Range<usize>) -> (usize, usize) {
+        assert!(
+            rows.start <= rows.end && rows.end <= self.rows,
+            "rows {rows:?} out of bounds of {} rows!",
+            self.rows
+        );
+        (rows.start * self.row_stride, rows.len() * self.row_stride)
+    }
+}
+impl<T: Zeroable + Copy> Index<(usize, usize)> for PagedMatrix<T> {
+    type Output = T;
+    fn index(&self, (row, col): (usize, usize)) -> &T {
+        self.check_col(col);
+        &self.row(row)[col]
+    }
+}
+impl<T: Zeroable + Copy> IndexMut<(usize, usize)> for PagedMatrix<T> {
+    fn index_mut(&mut self, (row, col): (usize, usize)) -> &mut T {
+        self.check_col(col);
+        &mut self.row_mut(row)[col]
+    }
+}
+impl<T: Zeroable + Copy + Debug> Debug for PagedMatrix<T> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_list().entries(self.iter_rows()).finish()
+    }
+}
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::page_math::{self, PAGE_SIZE};
+    #[test]
+    fn test_matrix_rows_columns() {
+        let mut matrix: PagedMatrix<u32> = PagedMatrix::new(0x10, 0x480);
+        assert_eq!(matrix.row_stride(), page_math::align_up(0x480 * 4));
+        assert!((matrix.row(1).as_ptr() as usize).is_multiple_of(PAGE_SIZE));
+        for (i, row) in matrix.iter_rows_mut().enumerate() {
+            row.iter_mut()
+                .enumerate()
+                .for_each(|(j, x)| *x = (i * 0x1000 + j) as u32);
+        }
+        assert_eq!(matrix[(0xF, 0x47F)], 0xF47F);
+        assert_eq!(matrix.get(0x10, 0), None);
+        assert_eq!(matrix.get(0, 0x480), None);
+        matrix.column_mut(2).for_each(|x| *x = 0);
+        assert!(matrix.column(2).all(|x| *x == 0));
+        assert_eq!(matrix.column(3).len(), 0x10);
+        matrix.decommit_rows(4..6);
+        assert!(matrix.row(4).iter().chain(matrix.row(5)).all(|x| *x == 0));
+        assert_eq!(matrix.row(3)[1], 0x3001);
+        assert_eq!(matrix.row(6)[0x47F], 0x647F);
+    }
+    #[test]
+    #[should_panic]
+    fn test_matrix_column_out_of_bounds() {
+        let matrix: PagedMatrix<u8> = PagedMatrix::new(2, 2);
+        let _ = matrix[(0, 2)];
+    }
+}
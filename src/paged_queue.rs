@@ -0,0 +1,290 @@
+use crate::page_math::{align_up, PAGE_SIZE};
+use crate::*;
+use std::fmt::{Debug, Formatter};
+use std::sync::{Condvar, Mutex, MutexGuard};
+/// Preferred size of a segment, in bytes. Segments are committed and decommitted as a whole.
+const SEGMENT_SIZE: usize = 0x10_000;
+struct State {
+    memory: SparsePages,
+    /// Slot of the oldest element.
+    head: usize,
+    len: usize,
+    closed: bool,
+}
+/// A bounded, first-in first-out queue with a fixed capacity, which many threads can push to and pop from at once.
+/// Elements are stored in a ring of [`SparsePages`], split into segments of whole pages. A segment is only committed
+/// once the producers reach it, and is returned to the kernel as soon as the consumers have popped all elements in it,
+/// so a queue with a capacity of gigabytes only uses as much memory as the elements it currently holds. This makes
+/// [`PagedQueue`] a good staging buffer between stages of a pipeline, which can absorb large bursts without keeping
+/// the memory once they are processed.
+///
+/// [`Self::push`] blocks while the queue is full, and [`Self::pop`] while it is empty, applying backpressure between
+/// stages. [`Self::close`] ends the stream: consumers drain the remaining elements, and then stop.
+/// # Examples
+/// ```
+/// # use memory_pages::*;
+/// let queue: PagedQueue<u64> = PagedQueue::new(0x10_000);
+/// std::thread::scope(|s| {
+///     s.spawn(|| {
+///         for i in 0..0x100_000 {
+///             queue.push(i).unwrap();
+///         }
+///         queue.close();
+///     });
+///     let mut sum = 0;
+///     while let Some(i) = queue.pop() {
+///         sum += i;
+///     }
+///     assert_eq!(sum, 0x100_000 * 0xF_FFFF / 2);
+/// });
+/// assert!(queue.is_empty());
+/// ```
+pub struct PagedQueue<T> {
+    state: Mutex<State>,
+    /// Signalled when an element is pushed, or the queue is closed.
+    not_empty: Condvar,
+    /// Signalled when an element is popped, or the queue is closed.
+    not_full: Condvar,
+    capacity: usize,
+    /// Number of elements in each segment.
+    segment_len: usize,
+    /// Distance between the beginnings of consecutive segments, in bytes. Always a multiple of page size.
+    segment_stride: usize,
+    pd: PhantomData<T>,
+}
+// Elements are moved between threads, but never shared by them.
+unsafe impl<T: Send> Send for PagedQueue<T> {}
+unsafe impl<T: Send> Sync for PagedQueue<T> {}
+impl<T> PagedQueue<T> {
+    /// Creates a new, empty [`PagedQueue`], reserving address space for at least `capacity` elements, without
+    /// committing any of it. Capacity is rounded up to a whole number of segments.
+    /// # Panics
+    /// Panics if `capacity` is zero, if `T` is zero-sized, if alignment of `T` exceeds the page size, if the size of the
+    /// queue overflows, or if kernel can't/refuses to reserve address space.
+    #[must_use]
+    pub fn new(capacity: usize) -> Self {
+        let size = std::mem::size_of::<T>();
+        assert!(
+            capacity != 0 && size != 0,
+            "PagedQueue can't have zero capacity!"
+        );
+        assert!(
+            std::mem::align_of::<T>() <= PAGE_SIZE,
+            "alignment of queue elements can't exceed the page size!"
+        );
+        let segment_len = (SEGMENT_SIZE / size).max(1);
+        let segment_stride = align_up(segment_len * size);
+        let segments = capacity.div_ceil(segment_len);
+        let bytes = segments
+            .checked_mul(segment_stride)
+            .filter(|bytes| isize::try_from(*bytes).is_ok())
+            .unwrap_or_else(|| panic!("Capacity of PagedQueue overflowed!"));
+        Self {
+            state: Mutex::new(State {
+                memory: SparsePages::new(bytes),
+                head: 0,
+                len: 0,
+                closed: false,
+            }),
+            not_empty: Condvar::new(),
+            not_full: Condvar::new(),
+            capacity: segments * segment_len,
+            segment_len,
+            segment_stride,
+            pd: PhantomData,
+        }
+    }
+    /// Returns the number of elements this [`PagedQueue`] can hold at once.
+    #[must_use]
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+    /// Returns the number of elements in this [`PagedQueue`]. Other threads may change it right after this returns.
+    #[must_use]
+    pub fn len(&self) -> usize {
+        self.lock().len
+    }
+    /// Checks if this [`PagedQueue`] has no elements.
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+    /// Returns the number of bytes of memory currently committed to hold elements.
+    #[must_use]
+    pub fn committed_bytes(&self) -> usize {
+        self.lock().memory.committed_pages() * PAGE_SIZE
+    }
+    /// Closes this [`PagedQueue`]. Pushing fails from now on, and once all remaining elements are popped, popping
+    /// returns `None` instead of blocking. Wakes up all threads waiting on the queue.
+    pub fn close(&self) {
+        self.lock().closed = true;
+        self.not_empty.notify_all();
+        self.not_full.notify_all();
+    }
+    /// Checks if this [`PagedQueue`] was closed.
+    #[must_use]
+    pub fn is_closed(&self) -> bool {
+        self.lock().closed
+    }
+    /// Appends `t` to the back of this [`PagedQueue`], waiting for a free slot while it is full.
+    /// # Errors
+    /// Returns `t` back if the queue is closed.
+    /// # Panics
+    /// Panics if kernel can't/refuses to commit more pages.
+    pub fn push(&self, t: T) -> Result<(), T> {
+        let mut state = self.lock();
+        while state.len == self.capacity && !state.closed {
+            state = self
+                .not_full
+                .wait(state)
+                .unwrap_or_else(std::sync::PoisonError::into_inner);
+        }
+        self.push_locked(state, t)
+    }
+    /// Appends `t` to the back of this [`PagedQueue`], without waiting.
+    /// # Errors
+    /// Returns `t` back if the queue is full or closed.
+    /// # Panics
+    /// Panics if kernel can't/refuses to commit more pages.
+    pub fn try_push(&self, t: T) -> Result<(), T> {
+        let state = self.lock();
+        if state.len == self.capacity {
+            return Err(t);
+        }
+        self.push_locked(state, t)
+    }
+    /// Removes the element at the front of this [`PagedQueue`] and returns it, waiting for one while the queue is
+    /// empty. Returns `None` once the queue is closed and empty.
+    pub fn pop(&self) -> Option<T> {
+        let mut state = self.lock();
+        while state.len == 0 && !state.closed {
+            state = self
+                .not_empty
+                .wait(state)
+                .unwrap_or_else(std::sync::PoisonError::into_inner);
+        }
+        self.pop_locked(state)
+    }
+    /// Removes the element at the front of this [`PagedQueue`] and returns it, or returns `None` if it is empty,
+    /// without waiting.
+    pub fn try_pop(&self) -> Option<T> {
+        self.pop_locked(self.lock())
+    }
+    fn lock(&self) -> MutexGuard<'_, State> {
+        self.state
+            .lock()
+            .unwrap_or_else(std::sync::PoisonError::into_inner)
+    }
+    /// Returns the offset of `slot`, in bytes.
+    fn offset(&self, slot: usize) -> usize {
+        slot / self.segment_len * self.segment_stride
+            + slot % self.segment_len * std::mem::size_of::<T>()
+    }
+    fn push_locked(&self, mut state: MutexGuard<'_, State>, t: T) -> Result<(), T> {
+        if state.closed {
+            return Err(t);
+        }
+        let slot = (state.head + state.len) % self.capacity;
+        if slot.is_multiple_of(self.segment_len) {
+            // Entering a segment, which may have been decommitted. Committing is a no-op if it was not.
+            state.memory.commit(self.offset(slot), self.segment_stride);
+        }
+        let offset = self.offset(slot);
+        unsafe { state.memory.as_mut_ptr().add(offset).cast::<T>().write(t) };
+        state.len += 1;
+        drop(state);
+        self.not_empty.notify_one();
+        Ok(())
+    }
+    fn pop_locked(&self, mut state: MutexGuard<'_, State>) -> Option<T> {
+        if state.len == 0 {
+            return None;
+        }
+        let slot = state.head;
+        let offset = self.offset(slot);
+        let t = unsafe { state.memory.as_mut_ptr().add(offset).cast::<T>().read() };
+        state.head = (slot + 1) % self.capacity;
+        state.len -= 1;
+        // Leaving a segment, which can be decommitted unless producers are still filling it. If they are about to enter
+        // it, they will commit it again.
+        let segment = slot / self.segment_len;
+        let tail = (state.head + state.len) % self.capacity;
+        if state.head.is_multiple_of(self.segment_len)
+            && (tail / self.segment_len != segment || tail.is_multiple_of(self.segment_len))
+        {
+            state
+                .memory
+                .decommit(segment * self.segment_stride, self.segment_stride);
+        }
+        drop(state);
+        self.not_full.notify_one();
+        Some(t)
+    }
+}
+impl<T> Drop for PagedQueue<T> {
+    fn drop(&mut self) {
+        while self.try_pop().is_some() {}
+    }
+}
+impl<T> Debug for PagedQueue<T> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let state = self.lock();
+        f.debug_struct("PagedQueue")
+            .field("len", &state.len)
+            .field("capacity", &self.capacity)
+            .field("closed", &state.closed)
+            .finish()
+    }
+}
+#[cfg(test)]
+mod test {
+    use super::*;
+    #[test]
+    fn test_queue_decommits_consumed_segments() {
+        let queue: PagedQueue<String> = PagedQueue::new(0x10_000);
+        let segment = queue.segment_len;
+        assert_eq!(queue.capacity() % segment, 0);
+        assert_eq!(queue.committed_bytes(), 0);
+        for i in 0..queue.capacity() {
+            queue.try_push(i.to_string()).unwrap();
+        }
+        assert_eq!(queue.try_push(String::new()), Err(String::new()));
+        let full = queue.committed_bytes();
+        assert_eq!(full, queue.capacity() / segment * queue.segment_stride);
+        for i in 0..segment * 2 {
+            assert_eq!(queue.try_pop(), Some(i.to_string()));
+        }
+        assert_eq!(queue.committed_bytes(), full - 2 * queue.segment_stride);
+        // Wrapping around commits the first segment again.
+        queue.try_push("wrapped".to_owned()).unwrap();
+        assert_eq!(queue.committed_bytes(), full - queue.segment_stride);
+        queue.close();
+        assert!(queue.push(String::new()).is_err());
+        assert_eq!(queue.len(), queue.capacity() - 2 * segment + 1);
+        // Remaining elements are dropped with the queue.
+    }
+    #[test]
+    fn test_queue_blocking() {
+        let queue: PagedQueue<[u64; 4]> = PagedQueue::new(0x100);
+        std::thread::scope(|s| {
+            for thread in 0..4 {
+                let queue = &queue;
+                s.spawn(move || {
+                    for i in 0..0x4000 {
+                        queue.push([thread, i, 0, 0]).unwrap();
+                    }
+                });
+            }
+            let mut next = [0; 4];
+            for _ in 0..0x10_000 {
+                let [thread, i, _, _] = queue.pop().unwrap();
+                // Elements of each producer arrive in order.
+                assert_eq!(next[thread as usize], i);
+                next[thread as usize] += 1;
+            }
+        });
+        assert_eq!(queue.try_pop(), None);
+        // All elements were popped, and the consumer left the only segment.
+        assert_eq!(queue.committed_bytes(), 0);
+    }
+}
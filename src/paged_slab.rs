@@ -0,0 +1,350 @@
+use crate::page_math::{align_up, PAGE_SIZE};
+use crate::*;
+use std::collections::BTreeSet;
+use std::fmt::{Debug, Formatter};
+use std::ops::{Index, IndexMut};
+/// Handle to a value stored in a [`PagedSlab`]. Handles of removed values are never valid again, even if their slot is
+/// reused.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct SlabKey {
+    index: usize,
+    generation: u32,
+}
+impl SlabKey {
+    /// Returns the index of the slot this key refers to. Slots of removed values are reused, so indices are not unique.
+    #[must_use]
+    pub fn index(&self) -> usize {
+        self.index
+    }
+}
+/// Bookkeeping of a group of slots, which is committed and decommitted as a whole.
+#[derive(Default)]
+struct Chunk {
+    /// Number of occupied slots.
+    live: usize,
+    /// Offsets of free slots, if the chunk is committed.
+    free: Vec<usize>,
+}
+/// A pool of equally sized slots, each holding a single `T`. Slots are carved out of a single reservation of address
+/// space, so values never move, and inserting and removing them never calls the global allocator, apart from
+/// bookkeeping. Freed slots are reused, and once all slots on a page are free, the page is returned to the kernel.
+///
+/// Values are referred to using [`SlabKey`]s, which become invalid once their value is removed, so stale keys are
+/// caught instead of silently referring to a different value. This makes [`PagedSlab`] a good fit for entity tables or
+/// tables of network sessions.
+/// # Examples
+/// ```
+/// # use memory_pages::*;
+/// let mut sessions:PagedSlab<(u32, String)> = PagedSlab::new(0x100_000);
+/// let alice = sessions.insert((1, "alice".to_owned()));
+/// let bob = sessions.insert((2, "bob".to_owned()));
+/// assert_eq!(sessions[alice].1, "alice");
+/// assert_eq!(sessions.remove(alice).map(|s| s.0), Some(1));
+/// // Key of a removed value stays invalid, even once its slot is reused.
+/// let carol = sessions.insert((3, "carol".to_owned()));
+/// assert_eq!(carol.index(), alice.index());
+/// assert!(sessions.get(alice).is_none());
+/// assert_eq!(sessions.len(), 2);
+/// # let _ = bob;
+/// ```
+pub struct PagedSlab<T> {
+    memory: SparsePages,
+    /// Number of slots in each chunk.
+    chunk_slots: usize,
+    /// Size of each chunk, in bytes. Always a multiple of page size.
+    chunk_bytes: usize,
+    chunks: Vec<Chunk>,
+    /// Generation of each slot of chunks that were ever committed. Slots with odd generations are occupied.
+    generations: Vec<u32>,
+    /// Committed chunks which have free slots. Lowest ones are filled first, which keeps higher ones empty.
+    available: BTreeSet<usize>,
+    /// Chunks which are not committed.
+    decommitted: BTreeSet<usize>,
+    len: usize,
+    pd: PhantomData<T>,
+}
+impl<T> PagedSlab<T> {
+    /// Creates a new, empty [`PagedSlab`], reserving address space for at least `max_capacity` values, without
+    /// committing any of it.
+    /// # Panics
+    /// Panics if `T` is zero-sized, if size of `max_capacity` values exceeds `isize::MAX` bytes, if alignment of `T`
+    /// exceeds the page size, or if kernel can't/refuses to reserve address space.
+    #[must_use]
+    pub fn new(max_capacity: usize) -> Self {
+        assert!(
+            std::mem::size_of::<T>() != 0,
+            "PagedSlab can't hold zero-sized types!"
+        );
+        assert!(
+            std::mem::align_of::<T>() <= PAGE_SIZE,
+            "alignment of slab values can't exceed the page size!"
+        );
+        let size = std::mem::size_of::<T>();
+        let chunk_slots = (PAGE_SIZE / size).max(1);
+        let chunk_bytes = align_up(chunk_slots * size);
+        let chunk_count = max_capacity.div_ceil(chunk_slots).max(1);
+        let bytes = chunk_count
+            .checked_mul(chunk_bytes)
+            .filter(|bytes| isize::try_from(*bytes).is_ok())
+            .unwrap_or_else(|| panic!("Capacity of PagedSlab overflowed!"));
+        Self {
+            memory: SparsePages::new(bytes),
+            chunk_slots,
+            chunk_bytes,
+            chunks: Vec::new(),
+            generations: Vec::new(),
+            available: BTreeSet::new(),
+            decommitted: BTreeSet::new(),
+            len: 0,
+            pd: PhantomData,
+        }
+    }
+    /// Returns the number of values in this [`PagedSlab`].
+    #[must_use]
+    pub fn len(&self) -> usize {
+        self.len
+    }
+    /// Checks if this [`PagedSlab`] is empty.
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+    /// Returns the number of values this [`PagedSlab`] can hold without committing more pages.
+    #[must_use]
+    pub fn capacity(&self) -> usize {
+        (self.chunks.len() - self.decommitted.len()) * self.chunk_slots
+    }
+    /// Returns the number of values this [`PagedSlab`] can ever hold.
+    #[must_use]
+    pub fn max_capacity(&self) -> usize {
+        self.memory.len() / self.chunk_bytes * self.chunk_slots
+    }
+    /// Moves `t` into a free slot, and returns a key referring to it.
+    /// # Panics
+    /// Panics if the maximal capacity is already reached, or if kernel can't/refuses to commit more pages.
+    pub fn insert(&mut self, t: T) -> SlabKey {
+        self.try_insert(t)
+            .unwrap_or_else(|_| panic!("Maximal capacity of PagedSlab exceeded!"))
+    }
+    /// Moves `t` into a free slot and returns a key referring to it, or returns `t` back if the maximal capacity is
+    /// already reached.
+    /// # Errors
+    /// Returns `t` if there is no free slot left for it.
+    /// # Panics
+    /// Panics if kernel can't/refuses to commit more pages.
+    pub fn try_insert(&mut self, t: T) -> Result<SlabKey, T> {
+        let chunk = match self.available.first() {
+            Some(chunk) => *chunk,
+            None => match self.commit_chunk() {
+                Some(chunk) => chunk,
+                None => return Err(t),
+            },
+        };
+        let offset = self.chunks[chunk]
+            .free
+            .pop()
+            .expect("available chunks have free slots");
+        self.chunks[chunk].live += 1;
+        if self.chunks[chunk].free.is_empty() {
+            self.available.remove(&chunk);
+        }
+        let index = chunk * self.chunk_slots + offset;
+        let slot = self.slot(index);
+        sanitizer::unpoison(slot.cast(), std::mem::size_of::<T>());
+        unsafe { slot.write(t) };
+        self.generations[index] = self.generations[index].wrapping_add(1);
+        self.len += 1;
+        Ok(SlabKey {
+            index,
+            generation: self.generations[index],
+        })
+    }
+    /// Returns a reference to the value `key` refers to, or `None` if it was removed.
+    #[must_use]
+    pub fn get(&self, key: SlabKey) -> Option<&T> {
+        self.contains(key)
+            .then(|| unsafe { &*self.slot(key.index) })
+    }
+    /// Returns a mutable reference to the value `key` refers to, or `None` if it was removed.
+    #[must_use]
+    pub fn get_mut(&mut self, key: SlabKey) -> Option<&mut T> {
+        self.contains(key)
+            .then(|| unsafe { &mut *self.slot(key.index) })
+    }
+    /// Checks if the value `key` refers to is still in this [`PagedSlab`].
+    #[must_use]
+    pub fn contains(&self, key: SlabKey) -> bool {
+        self.generations.get(key.index) == Some(&key.generation) && key.generation % 2 == 1
+    }
+    /// Removes the value `key` refers to, and returns it, or `None` if it was already removed. If this frees the last
+    /// occupied slot in a group of pages, they are returned to the kernel.
+    pub fn remove(&mut self, key: SlabKey) -> Option<T> {
+        if !self.contains(key) {
+            return None;
+        }
+        let slot = self.slot(key.index);
+        let t = unsafe { slot.read() };
+        sanitizer::poison(slot.cast(), std::mem::size_of::<T>());
+        self.generations[key.index] = self.generations[key.index].wrapping_add(1);
+        self.len -= 1;
+        let chunk = key.index / self.chunk_slots;
+        self.chunks[chunk].live -= 1;
+        if self.chunks[chunk].live == 0 {
+            self.decommit_chunk(chunk);
+        } else {
+            self.chunks[chunk].free.push(key.index % self.chunk_slots);
+            self.available.insert(chunk);
+        }
+        Some(t)
+    }
+    /// Removes all values, returning all of the committed memory to the kernel. All keys become invalid.
+    pub fn clear(&mut self) {
+        for index in 0..self.generations.len() {
+            if self.generations[index] % 2 == 1 {
+                let key = SlabKey {
+                    index,
+                    generation: self.generations[index],
+                };
+                self.remove(key);
+            }
+        }
+    }
+    /// Returns an iterator over all values and their keys, in order of their slots.
+    pub fn iter(&self) -> impl Iterator<Item = (SlabKey, &T)> + '_ {
+        self.generations
+            .iter()
+            .enumerate()
+            .filter(|(_, generation)| *generation % 2 == 1)
+            .map(|(index, generation)| {
+                let key = SlabKey {
+                    index,
+                    generation: *generation,
+                };
+                (key, unsafe { &*self.slot(index) })
+            })
+    }
+    /// Returns an iterator over all values and their keys, in order of their slots, allowing values to be modified.
+    pub fn iter_mut(&mut self) -> impl Iterator<Item = (SlabKey, &mut T)> + '_ {
+        let slab = &*self;
+        slab.generations
+            .iter()
+            .enumerate()
+            .filter(|(_, generation)| *generation % 2 == 1)
+            .map(|(index, generation)| {
+                let key = SlabKey {
+                    index,
+                    generation: *generation,
+                };
+                (key, unsafe { &mut *slab.slot(index) })
+            })
+    }
+    /// Returns a pointer to the slot `index`.
+    fn slot(&self, index: usize) -> *mut T {
+        let offset = index / self.chunk_slots * self.chunk_bytes
+            + index % self.chunk_slots * std::mem::size_of::<T>();
+        self.memory.as_ptr().wrapping_add(offset).cast_mut().cast()
+    }
+    /// Commits the lowest uncommitted chunk, and returns its index, or `None` if all chunks are committed.
+    fn commit_chunk(&mut self) -> Option<usize> {
+        let chunk = match self.decommitted.pop_first() {
+            Some(chunk) => chunk,
+            None if self.chunks.len() * self.chunk_bytes < self.memory.len() => {
+                self.chunks.push(Chunk::default());
+                self.generations
+                    .resize(self.chunks.len() * self.chunk_slots, 0);
+                self.chunks.len() - 1
+            }
+            None => return None,
+        };
+        self.memory
+            .commit(chunk * self.chunk_bytes, self.chunk_bytes);
+        sanitizer::poison(
+            self.memory.as_ptr().wrapping_add(chunk * self.chunk_bytes),
+            self.chunk_bytes,
+        );
+        // Lowest slots are popped first.
+        self.chunks[chunk].free = (0..self.chunk_slots).rev().collect();
+        self.available.insert(chunk);
+        Some(chunk)
+    }
+    fn decommit_chunk(&mut self, chunk: usize) {
+        sanitizer::unpoison(
+            self.memory.as_ptr().wrapping_add(chunk * self.chunk_bytes),
+            self.chunk_bytes,
+        );
+        self.memory
+            .decommit(chunk * self.chunk_bytes, self.chunk_bytes);
+        self.chunks[chunk].free = Vec::new();
+        self.available.remove(&chunk);
+        self.decommitted.insert(chunk);
+    }
+}
+impl<T> Index<SlabKey> for PagedSlab<T> {
+    type Output = T;
+    fn index(&self, key: SlabKey) -> &T {
+        self.get(key)
+            .unwrap_or_else(|| panic!("{key:?} refers to a removed value!"))
+    }
+}
+impl<T> IndexMut<SlabKey> for PagedSlab<T> {
+    fn index_mut(&mut self, key: SlabKey) -> &mut T {
+        self.get_mut(key)
+            .unwrap_or_else(|| panic!("{key:?} refers to a removed value!"))
+    }
+}
+impl<T: Debug> Debug for PagedSlab<T> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_map().entries(self.iter()).finish()
+    }
+}
+impl<T> Drop for PagedSlab<T> {
+    fn drop(&mut self) {
+        self.clear();
+    }
+}
+#[cfg(test)]
+mod test {
+    use super::*;
+    #[test]
+    fn test_slab_reuse_decommit() {
+        let mut slab: PagedSlab<String> = PagedSlab::new(0x10_000);
+        let keys: Vec<SlabKey> = (0..0x1000).map(|i| slab.insert(i.to_string())).collect();
+        let first: *const String = &slab[keys[0]];
+        let capacity = slab.capacity();
+        assert!(capacity >= 0x1000);
+        // Removing every value but one per page keeps all pages committed.
+        let per_page = PAGE_SIZE / std::mem::size_of::<String>();
+        for key in keys.iter().filter(|key| key.index() % per_page != 0) {
+            assert!(slab.remove(*key).is_some());
+        }
+        assert_eq!(slab.capacity(), capacity);
+        assert_eq!(first, &raw const slab[keys[0]]);
+        slab[keys[per_page]].push('!');
+        assert_eq!(
+            slab.get(keys[per_page]).map(String::as_str),
+            Some(format!("{per_page}!").as_str())
+        );
+        // Removing the last value on a page decommits it.
+        slab.remove(keys[per_page]);
+        assert_eq!(slab.capacity(), capacity - per_page);
+        assert!(slab.get(keys[per_page]).is_none());
+        let reused = slab.insert("reused".to_owned());
+        // Free slots on the lowest page are reused first.
+        assert!(reused.index() < per_page);
+        assert_eq!(slab.iter().count(), slab.len());
+        slab.clear();
+        assert_eq!(slab.capacity(), 0);
+        assert!(!slab.contains(reused));
+    }
+    #[test]
+    fn test_slab_max_capacity() {
+        let mut slab: PagedSlab<[u8; 0x1800]> = PagedSlab::new(3);
+        assert_eq!(slab.max_capacity(), 3);
+        for _ in 0..3 {
+            slab.insert([1; 0x1800]);
+        }
+        assert!(slab.try_insert([2; 0x1800]).is_err());
+        assert!(slab.iter_mut().all(|(_, value)| value[0x17FF] == 1));
+        assert!(std::panic::catch_unwind(|| PagedSlab::<()>::new(10)).is_err());
+    }
+}
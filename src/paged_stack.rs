@@ -0,0 +1,157 @@
+use crate::fault_trap::trap_range;
+use crate::page_math::PAGE_SIZE;
+use crate::*;
+/// A stack for coroutines, green threads or interpreters, growing downwards from [`Self::top`] to [`Self::limit`], with
+/// inaccessible guard pages below it. Overflowing the stack hits the guard pages, and faults instead of silently
+/// corrupting the memory below. [`Self::trap_overflow`] lets such overflows be reported to a handler, before the
+/// process is terminated.
+///
+/// Memory of the stack is only backed by RAM once touched, so reserving large stacks is cheap.
+/// # Examples
+/// ```
+/// # use memory_pages::*;
+/// let stack = PagedStack::new(0x10_000);
+/// assert_eq!(stack.len(), 0x10_000);
+/// // A stack pointer starts at the top, and moves towards the limit.
+/// let sp = unsafe { stack.top().sub(8) };
+/// unsafe { sp.cast::<u64>().write(0xDEAD_BEEF) };
+/// assert!(sp >= stack.limit());
+/// // Right below the limit is the guard region.
+/// assert!(stack.is_guard(unsafe { stack.limit().sub(1) }));
+/// ```
+pub struct PagedStack {
+    /// Guard pages, followed by the usable stack.
+    memory: GuardPages,
+    /// Size of the guard region, in bytes.
+    guard_len: usize,
+}
+impl PagedStack {
+    /// Allocates a new [`PagedStack`] of size at least `size`, rounded up to next page boundary, with a single guard page.
+    /// # Panics
+    /// Panics if `size` is 0, or if kernel can't/refuses to allocate the stack.
+    #[must_use]
+    pub fn new(size: usize) -> Self {
+        Self::with_guard_pages(size, 1)
+    }
+    /// Allocates a new [`PagedStack`] of size at least `size`, rounded up to next page boundary, with `guard_pages` guard
+    /// pages. More guard pages catch overflows by large stack frames, which could otherwise skip over a single page.
+    /// # Panics
+    /// Panics if `size` or `guard_pages` is 0, or if kernel can't/refuses to allocate the stack.
+    #[must_use]
+    pub fn with_guard_pages(size: usize, guard_pages: usize) -> Self {
+        assert!(size != 0, "PagedStack can't be empty!");
+        assert!(guard_pages != 0, "PagedStack needs at least 1 guard page!");
+        let (guard_len, size) = guard_pages
+            .checked_mul(PAGE_SIZE)
+            .zip(size.checked_next_multiple_of(PAGE_SIZE))
+            .filter(|(guard_len, size)| guard_len.checked_add(*size).is_some())
+            .unwrap_or_else(|| panic!("Capacity of PagedStack overflowed!"));
+        let mut memory = Pages::new_guard(guard_len + size);
+        memory
+            .unguard(guard_len, size, Protection::ReadWrite)
+            .unwrap_or_else(|err| panic!("Allocating PagedStack failed:'{err}'!"));
+        Self { memory, guard_len }
+    }
+    /// Returns the usable size of this [`PagedStack`], in bytes, excluding guard pages.
+    #[must_use]
+    pub fn len(&self) -> usize {
+        self.memory.len() - self.guard_len
+    }
+    /// Always returns false, because [`PagedStack`] can't be empty.
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        false
+    }
+    /// Returns the size of the guard region below the stack, in bytes.
+    #[must_use]
+    pub fn guard_len(&self) -> usize {
+        self.guard_len
+    }
+    /// Returns a pointer one past the highest usable byte, which is where an empty stack begins. It is aligned to a
+    /// page boundary, and so satisfies stack alignment requirements of all platforms.
+    #[must_use]
+    pub fn top(&self) -> *mut u8 {
+        unsafe { self.memory.get_ptr_unchecked().add(self.memory.len()) }
+    }
+    /// Returns a pointer to the lowest usable byte. Accesses below it hit the guard pages.
+    #[must_use]
+    pub fn limit(&self) -> *mut u8 {
+        unsafe { self.memory.get_ptr_unchecked().add(self.guard_len) }
+    }
+    /// Checks if `address` is inside the guard region of this stack, which means an access to it was an overflow.
+    #[must_use]
+    pub fn is_guard(&self, address: *const u8) -> bool {
+        let start = self.memory.get_ptr_unchecked().cast_const();
+        (start..self.limit().cast_const()).contains(&address)
+    }
+    /// Traps overflows of this stack: accesses to its guard pages are reported to `handler`, with
+    /// [`Fault::offset`] being the offset into the guard region. The handler can report the overflow and return
+    /// [`FaultAction::Crash`] to terminate the process as usual. It runs on the faulting thread, so for a native stack it
+    /// must run on an alternate signal stack(`sigaltstack`), since the overflowed one has no space left.
+    /// # Errors
+    /// Returns an error if too many [`Pages`] are already trapped, or if the fault handler can't be installed.
+    /// # Examples
+    /// An interpreter detecting an overflow of its value stack.
+    /// ```
+    /// # use memory_pages::*;
+    /// use std::sync::atomic::{AtomicBool, Ordering};
+    /// static OVERFLOWED: AtomicBool = AtomicBool::new(false);
+    /// fn on_overflow(fault: &Fault) -> FaultAction {
+    ///     OVERFLOWED.store(true, Ordering::Relaxed);
+    ///     // Gives the interpreter some space to unwind, instead of crashing.
+    ///     match fault.set_page_protection(Protection::ReadWrite) {
+    ///         Ok(()) => FaultAction::Retry,
+    ///         Err(_) => FaultAction::Crash,
+    ///     }
+    /// }
+    /// let stack = PagedStack::new(0x1000);
+    /// let trap = stack.trap_overflow(on_overflow).unwrap();
+    /// let mut sp = stack.top();
+    /// for value in 0..0x201_u64 {
+    ///     sp = unsafe { sp.sub(8) };
+    ///     unsafe { sp.cast::<u64>().write_volatile(value) };
+    /// }
+    /// assert!(OVERFLOWED.load(Ordering::Relaxed));
+    /// assert_eq!(trap.fault_count(), 1);
+    /// ```
+    pub fn trap_overflow(&self, handler: FaultHandler) -> Result<FaultTrap<'_>, TrapError> {
+        trap_range(self.memory.get_ptr_unchecked(), self.guard_len, handler, 0)
+    }
+}
+impl std::fmt::Debug for PagedStack {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("PagedStack")
+            .field("limit", &self.limit())
+            .field("top", &self.top())
+            .field("guard_len", &self.guard_len)
+            .finish()
+    }
+}
+#[cfg(test)]
+mod test {
+    use super::*;
+    fn unguard(fault: &Fault) -> FaultAction {
+        match fault.set_page_protection(Protection::ReadWrite) {
+            Ok(()) => FaultAction::Retry,
+            Err(_) => FaultAction::Crash,
+        }
+    }
+    #[test]
+    fn test_stack_guard() {
+        let stack = PagedStack::with_guard_pages(0x1800, 2);
+        assert_eq!(stack.len(), 0x2000);
+        assert_eq!(stack.guard_len(), 2 * PAGE_SIZE);
+        assert_eq!(stack.top() as usize - stack.limit() as usize, stack.len());
+        assert!((stack.top() as usize).is_multiple_of(PAGE_SIZE));
+        // The whole stack is usable.
+        unsafe { stack.limit().write_bytes(3, stack.len()) };
+        assert!(!stack.is_guard(stack.limit()));
+        assert!(!stack.is_guard(stack.top()));
+        let trap = stack.trap_overflow(unguard).unwrap();
+        let overflow = unsafe { stack.limit().sub(PAGE_SIZE + 1) };
+        assert!(stack.is_guard(overflow));
+        unsafe { overflow.write_volatile(1) };
+        assert_eq!(trap.fault_count(), 1);
+        assert_eq!(trap.last_fault_address(), Some(overflow.cast_const()));
+    }
+}
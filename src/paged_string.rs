@@ -0,0 +1,362 @@
+use crate::*;
+use std::fmt::{Debug, Display, Formatter};
+use std::io::{self, Read};
+/// A growable UTF-8 string, whose bytes are stored in a [`PagedVec`]. Meant for huge amounts of text, like aggregated
+/// logs or documents being indexed, which benefit from page-backed storage, but are still used as a [`str`].
+/// # Examples
+/// ```
+/// # use memory_pages::*;
+/// let mut log = PagedString::new();
+/// log.push_str("starting up");
+/// log.push('\n');
+/// log.read_from(&mut "ready\n".as_bytes()).unwrap();
+/// assert_eq!(log.lines().count(), 2);
+/// let log = log.freeze();
+/// assert!(log.ends_with("ready\n"));
+/// ```
+#[derive(Clone, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct PagedString {
+    /// Bytes of the string. Always valid UTF-8.
+    vec: PagedVec<u8>,
+}
+impl PagedString {
+    /// Creates a new, empty [`PagedString`] without allocating any memory.
+    #[must_use]
+    pub fn new() -> Self {
+        Self {
+            vec: PagedVec::new_empty(),
+        }
+    }
+    /// Creates a new, empty [`PagedString`] able to hold at least `capacity` bytes without growing.
+    /// # Panics
+    /// Panics if kernel can't/refuses to allocate the memory.
+    #[must_use]
+    pub fn with_capacity(capacity: usize) -> Self {
+        Self {
+            vec: PagedVec::with_capacity(capacity),
+        }
+    }
+    /// Converts `vec` into a [`PagedString`], without copying it, if it is valid UTF-8.
+    /// # Errors
+    /// Returns `vec` back, together with the reason it is not valid UTF-8.
+    /// # Examples
+    /// ```
+    /// # use memory_pages::*;
+    /// let bytes:PagedVec<u8> = b"hello".iter().copied().collect();
+    /// assert_eq!(PagedString::from_utf8(bytes).unwrap(), "hello");
+    /// let bytes:PagedVec<u8> = [0xFF, 0xFE].into_iter().collect();
+    /// assert!(PagedString::from_utf8(bytes).is_err());
+    /// ```
+    pub fn from_utf8(vec: PagedVec<u8>) -> Result<Self, (PagedVec<u8>, std::str::Utf8Error)> {
+        match std::str::from_utf8(&vec) {
+            Ok(_) => Ok(Self { vec }),
+            Err(err) => Err((vec, err)),
+        }
+    }
+    /// Converts `vec` into a [`PagedString`], without checking if it is valid UTF-8.
+    /// # Safety
+    /// `vec` must be valid UTF-8.
+    #[must_use]
+    pub unsafe fn from_utf8_unchecked(vec: PagedVec<u8>) -> Self {
+        Self { vec }
+    }
+    /// Returns the bytes of this [`PagedString`], without copying them.
+    #[must_use]
+    pub fn into_bytes(self) -> PagedVec<u8> {
+        self.vec
+    }
+    /// Returns the contents of this [`PagedString`] as a [`str`].
+    #[must_use]
+    pub fn as_str(&self) -> &str {
+        unsafe { std::str::from_utf8_unchecked(&self.vec) }
+    }
+    /// Returns the contents of this [`PagedString`] as a mutable [`str`].
+    #[must_use]
+    pub fn as_mut_str(&mut self) -> &mut str {
+        unsafe { std::str::from_utf8_unchecked_mut(&mut self.vec) }
+    }
+    /// Returns the number of bytes this [`PagedString`] can hold without growing.
+    #[must_use]
+    pub fn capacity(&self) -> usize {
+        self.vec.capacity()
+    }
+    /// Reserves capacity for at least `additional` more bytes.
+    /// # Panics
+    /// Panics if the capacity overflows, or if kernel can't/refuses to allocate more memory.
+    pub fn reserve(&mut self, additional: usize) {
+        self.vec.reserve(additional);
+    }
+    /// Appends `c` to the end of this [`PagedString`].
+    pub fn push(&mut self, c: char) {
+        self.push_str(c.encode_utf8(&mut [0; 4]));
+    }
+    /// Appends `s` to the end of this [`PagedString`].
+    pub fn push_str(&mut self, s: &str) {
+        self.vec.extend_from_copy_slice(s.as_bytes());
+    }
+    /// Removes the last character and returns it, or `None` if this [`PagedString`] is empty.
+    pub fn pop(&mut self) -> Option<char> {
+        let c = self.as_str().chars().next_back()?;
+        self.vec.truncate(self.len() - c.len_utf8());
+        Some(c)
+    }
+    /// Shortens this [`PagedString`] to `len` bytes. Does nothing if it is already shorter.
+    /// # Panics
+    /// Panics if `len` does not lie on a char boundary.
+    pub fn truncate(&mut self, len: usize) {
+        if len < self.len() {
+            assert!(
+                self.is_char_boundary(len),
+                "length {len} does not lie on a char boundary!"
+            );
+            self.vec.truncate(len);
+        }
+    }
+    /// Removes all text, keeping the allocated memory.
+    pub fn clear(&mut self) {
+        self.vec.clear();
+    }
+    /// Removes all text, and returns all of the allocated memory to the kernel.
+    pub fn clear_decommit(&mut self) {
+        self.vec.clear_decommit();
+    }
+    /// Reads all remaining data from `reader`, and appends it to the end of this [`PagedString`]. Returns the number of
+    /// bytes read.
+    /// # Errors
+    /// Returns an error if reading fails, or if the data is not valid UTF-8, in which case the
+    /// [`io::ErrorKind::InvalidData`] is returned. This [`PagedString`] is left unchanged on error.
+    /// # Examples
+    /// ```
+    /// # use memory_pages::*;
+    /// let mut text = PagedString::from("Zürich, ");
+    /// text.read_from(&mut "Genève".as_bytes()).unwrap();
+    /// assert_eq!(text, "Zürich, Genève");
+    /// assert!(text.read_from(&mut &[0xC3][..]).is_err());
+    /// assert_eq!(text, "Zürich, Genève");
+    /// ```
+    pub fn read_from(&mut self, reader: &mut impl Read) -> io::Result<usize> {
+        let start = self.len();
+        // Bytes are only kept once they are validated, even if `reader` panics.
+        let mut guard = TruncateGuard {
+            vec: &mut self.vec,
+            len: start,
+        };
+        let mut buffer = [0; 0x2000];
+        let result = loop {
+            match reader.read(&mut buffer) {
+                Ok(0) => break Ok(()),
+                Ok(read) => guard.vec.extend_from_copy_slice(&buffer[..read]),
+                Err(err) if err.kind() == io::ErrorKind::Interrupted => {}
+                Err(err) => break Err(err),
+            }
+        };
+        // A character may be split between two reads, so only complete data is validated.
+        let result = result.and_then(|()| {
+            std::str::from_utf8(&guard.vec[start..])
+                .map(|_| guard.vec.len() - start)
+                .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))
+        });
+        if result.is_ok() {
+            guard.len = guard.vec.len();
+        }
+        result
+    }
+    /// Makes memory of this [`PagedString`] read-only, turning it into a [`FrozenPagedString`].
+    #[must_use]
+    pub fn freeze(self) -> FrozenPagedString {
+        FrozenPagedString {
+            vec: self.vec.freeze(),
+        }
+    }
+}
+/// Truncates bytes of a [`PagedString`] to `len` when dropped, so data which is not valid UTF-8 never stays in it.
+struct TruncateGuard<'a> {
+    vec: &'a mut PagedVec<u8>,
+    len: usize,
+}
+impl Drop for TruncateGuard<'_> {
+    fn drop(&mut self) {
+        self.vec.truncate(self.len);
+    }
+}
+impl Deref for PagedString {
+    type Target = str;
+    fn deref(&self) -> &str {
+        self.as_str()
+    }
+}
+impl DerefMut for PagedString {
+    fn deref_mut(&mut self) -> &mut str {
+        self.as_mut_str()
+    }
+}
+impl AsRef<str> for PagedString {
+    fn as_ref(&self) -> &str {
+        self
+    }
+}
+impl AsRef<[u8]> for PagedString {
+    fn as_ref(&self) -> &[u8] {
+        self.as_bytes()
+    }
+}
+impl Borrow<str> for PagedString {
+    fn borrow(&self) -> &str {
+        self
+    }
+}
+impl From<&str> for PagedString {
+    fn from(s: &str) -> Self {
+        let mut string = Self::new();
+        string.push_str(s);
+        string
+    }
+}
+impl PartialEq<str> for PagedString {
+    fn eq(&self, other: &str) -> bool {
+        self.as_str() == other
+    }
+}
+impl PartialEq<&str> for PagedString {
+    fn eq(&self, other: &&str) -> bool {
+        self.as_str() == *other
+    }
+}
+impl std::fmt::Write for PagedString {
+    fn write_str(&mut self, s: &str) -> std::fmt::Result {
+        self.push_str(s);
+        Ok(())
+    }
+}
+impl Extend<char> for PagedString {
+    fn extend<I: IntoIterator<Item = char>>(&mut self, iter: I) {
+        let iter = iter.into_iter();
+        self.reserve(iter.size_hint().0);
+        for c in iter {
+            self.push(c);
+        }
+    }
+}
+impl<'a> Extend<&'a str> for PagedString {
+    fn extend<I: IntoIterator<Item = &'a str>>(&mut self, iter: I) {
+        for s in iter {
+            self.push_str(s);
+        }
+    }
+}
+impl<T> FromIterator<T> for PagedString
+where
+    Self: Extend<T>,
+{
+    fn from_iter<I: IntoIterator<Item = T>>(iter: I) -> Self {
+        let mut string = Self::new();
+        string.extend(iter);
+        string
+    }
+}
+impl Display for PagedString {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        Display::fmt(self.as_str(), f)
+    }
+}
+impl Debug for PagedString {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        Debug::fmt(self.as_str(), f)
+    }
+}
+/// Read-only [`PagedString`], whose memory is write-protected by hardware. Created using [`PagedString::freeze`].
+pub struct FrozenPagedString {
+    /// Bytes of the string. Always valid UTF-8.
+    vec: FrozenPagedVec<u8>,
+}
+impl FrozenPagedString {
+    /// Returns the contents of this [`FrozenPagedString`] as a [`str`].
+    #[must_use]
+    pub fn as_str(&self) -> &str {
+        unsafe { std::str::from_utf8_unchecked(&self.vec) }
+    }
+}
+impl Deref for FrozenPagedString {
+    type Target = str;
+    fn deref(&self) -> &str {
+        self.as_str()
+    }
+}
+impl AsRef<str> for FrozenPagedString {
+    fn as_ref(&self) -> &str {
+        self
+    }
+}
+impl Borrow<str> for FrozenPagedString {
+    fn borrow(&self) -> &str {
+        self
+    }
+}
+impl Display for FrozenPagedString {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        Display::fmt(self.as_str(), f)
+    }
+}
+impl Debug for FrozenPagedString {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        Debug::fmt(self.as_str(), f)
+    }
+}
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::fmt::Write;
+    /// Reader returning a single byte at a time.
+    struct ByteReader<'a>(&'a [u8]);
+    impl Read for ByteReader<'_> {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            let Some((byte, rest)) = self.0.split_first() else {
+                return Ok(0);
+            };
+            buf[0] = *byte;
+            self.0 = rest;
+            Ok(1)
+        }
+    }
+    #[test]
+    fn test_paged_string() {
+        let mut string = PagedString::new();
+        for i in 0..0x1000 {
+            writeln!(string, "línea {i}").unwrap();
+        }
+        assert_eq!(string.lines().nth(0x123), Some("línea 291"));
+        assert_eq!(string.pop(), Some('\n'));
+        assert_eq!(string.pop(), Some('5'));
+        string.truncate(1);
+        assert_eq!(string, "l");
+        string.extend(['í', 'n']);
+        assert_eq!(string.as_str(), "lín");
+        let len = string.len();
+        // Multi-byte characters are split between reads.
+        let mut reader = ByteReader("€uro".as_bytes());
+        assert_eq!(string.read_from(&mut reader).unwrap(), "€uro".len());
+        assert!(string.read_from(&mut ByteReader(b"ok\xE2")).is_err());
+        assert_eq!(string.len(), len + "€uro".len());
+        let frozen = string.freeze();
+        assert_eq!(frozen.to_string(), "lín€uro");
+    }
+    /// Reader returning the first byte of `€`, and panicking when read again.
+    struct PanickingReader(bool);
+    impl Read for PanickingReader {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            assert!(!self.0, "Reading failed!");
+            self.0 = true;
+            buf[0] = 0xE2;
+            Ok(1)
+        }
+    }
+    #[test]
+    fn test_read_from_panic() {
+        let mut string = PagedString::from("ok");
+        let res = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+            string.read_from(&mut PanickingReader(false))
+        }));
+        assert!(res.is_err());
+        assert_eq!(string.as_str(), "ok");
+    }
+}
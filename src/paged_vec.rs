@@ -1,15 +1,18 @@
 // All functions properly documented, with examples!
-use crate::Pages;
+use crate::{sanitizer, GrowthPolicy, Locality, Pages};
 use std::borrow::{Borrow, BorrowMut};
+use std::hash::{Hash, Hasher};
 use std::marker::PhantomData;
-use std::ops::{Deref, DerefMut};
+use std::mem::MaybeUninit;
+use std::ops::{Bound, Deref, DerefMut, Range, RangeBounds};
+use std::ptr::NonNull;
 /// A [`Vec`]-like type located in memory pages acquired directly from the kernel. For big lengths a faster to
 /// allocate/deallocate than a normal [`Vec`], but considerably slower for small sizes. Intended to be used for very large data
 /// sets, with a rough estimate of capacity known ahead of time.
 /// # Advantages:
 /// 1. 2-3x times faster than default allocator for big vec sizes (over ~20 MB).
 /// 2. memory is released directly to the kernel as soon as [`PagedVec`] is dropped, which may not always be the case for
-/// standard allocator, leading to decreased memory footprint.
+///    standard allocator, leading to decreased memory footprint.
 // 3. More conservative growth model. Since [`PagedVec`] is intended for very large sizes, it is considerably more conservative with
 // allocating memory(1.5x previous cap instead of 2x for standard [`Vec`].
 /// # Disadvantages
@@ -19,12 +22,15 @@ use std::ops::{Deref, DerefMut};
 /// Some examples/documentation for functions of this type are derived from examples for [`Vec`] in rust standard library, to
 /// better highlight the differences and similarities.
 pub struct PagedVec<T: Sized> {
-    data: Pages<crate::AllowRead, crate::AllowWrite, crate::DenyExec>,
+    /// Pages holding elements, or `None` if no memory was allocated yet.
+    data: Option<Pages<crate::AllowRead, crate::AllowWrite, crate::DenyExec>>,
     len: usize,
+    growth: GrowthPolicy,
     pd: PhantomData<T>,
 }
 impl<T: Sized> PagedVec<T> {
-    /// Creates a new [`PagedVec`] with specified `capacity`.
+    /// Creates a new [`PagedVec`] with specified `capacity`. At least one page is always allocated, even if `capacity` is
+    /// 0. Use [`Self::new_empty`] to create a [`PagedVec`] without allocating any memory.
     /// # Examples
     /// ```
     /// # use memory_pages::*;
@@ -32,12 +38,75 @@ impl<T: Sized> PagedVec<T> {
     /// let mut vec = PagedVec::new(0x1000);
     /// vec.push_within_capacity(0.0).unwrap();
     /// ```
+    /// # Panics
+    /// Panics if size of `capacity` elements exceeds `isize::MAX` bytes.
     pub fn new(capacity: usize) -> Self {
-        let bytes_min = (capacity * std::mem::size_of::<T>()).max(0x1000);
-        let data = Pages::new(bytes_min);
+        let bytes_min = match Self::bytes_for(capacity) {
+            Ok(bytes) => bytes.max(0x1000),
+            Err(err) => Self::reserve_failed(&err),
+        };
+        Self::with_pages(Pages::new(bytes_min), GrowthPolicy::default())
+    }
+    /// Largest capacity a [`PagedVec<T>`] can have, such that its size in bytes does not exceed `isize::MAX`, like
+    /// for any other allocation.
+    const MAX_CAPACITY: usize = isize::MAX as usize
+        / if std::mem::size_of::<T>() == 0 {
+            1
+        } else {
+            std::mem::size_of::<T>()
+        };
+    /// Returns size of `capacity` elements, in bytes, or an error if it exceeds `isize::MAX`.
+    pub(crate) fn bytes_for(capacity: usize) -> Result<usize, TryReserveError> {
+        if capacity > Self::MAX_CAPACITY {
+            return Err(TryReserveError::CapacityOverflow);
+        }
+        Ok(capacity * std::mem::size_of::<T>())
+    }
+    /// Returns capacity needed to store `additional` more elements, or an error if it overflows.
+    fn needed_capacity(&self, additional: usize) -> Result<usize, TryReserveError> {
+        self.len
+            .checked_add(additional)
+            .filter(|needed| *needed <= Self::MAX_CAPACITY)
+            .ok_or(TryReserveError::CapacityOverflow)
+    }
+    #[cold]
+    #[track_caller]
+    fn reserve_failed(err: &TryReserveError) -> ! {
+        match err {
+            TryReserveError::CapacityOverflow => panic!("Capacity of PagedVec overflowed!"),
+            TryReserveError::AllocError(err) => panic!("Reallocating PagedVec failed:'{err}'!"),
+        }
+    }
+    /// Creates a new, empty [`PagedVec`] using `data` as its memory.
+    pub(crate) fn with_pages(
+        data: Pages<crate::AllowRead, crate::AllowWrite, crate::DenyExec>,
+        growth: GrowthPolicy,
+    ) -> Self {
+        let res = Self {
+            data: Some(data),
+            len: 0,
+            growth,
+            pd: PhantomData,
+        };
+        res.poison_spare();
+        res
+    }
+    /// Creates a new, empty [`PagedVec`] without allocating any memory. Pages are only allocated once the first element
+    /// is inserted, so this is cheap enough to use for many mostly-empty [`PagedVec`]s.
+    /// # Examples
+    /// ```
+    /// # use memory_pages::*;
+    /// let mut vec:PagedVec<u32> = PagedVec::new_empty();
+    /// assert_eq!(vec.capacity(), 0);
+    /// vec.push(1);
+    /// assert_eq!(vec.capacity(), 0x400);
+    /// ```
+    #[must_use]
+    pub fn new_empty() -> Self {
         Self {
-            data,
+            data: None,
             len: 0,
+            growth: GrowthPolicy::default(),
             pd: PhantomData,
         }
     }
@@ -57,13 +126,11 @@ impl<T: Sized> PagedVec<T> {
     /// }
     /// // push outside capacity, pushed value returned!
     /// assert_eq!(vec.push_within_capacity(5.6),Err(5.6));
-    #[must_use]
+    #[must_use = "the value is handed back if there is no capacity left for it"]
     pub fn push_within_capacity(&mut self, t: T) -> Result<(), T> {
-        if self.len * std::mem::size_of::<T>() < self.data.len() {
-            let slice = unsafe {
-                std::slice::from_raw_parts_mut(self.data.get_ptr_mut(0).cast::<T>(), self.len + 1)
-            };
-            slice[self.len] = t;
+        if self.len < self.capacity() {
+            self.unpoison_next();
+            unsafe { std::ptr::write(self.elements().add(self.len), t) };
             self.len += 1;
             Ok(())
         } else {
@@ -76,31 +143,70 @@ impl<T: Sized> PagedVec<T> {
     /// contrary, it very often slows allocations down. Before using them, test each usage.
     pub fn advise_use_soon(&mut self, used: usize) {
         if self.len() < used {
-            self.resize(used);
+            self.realloc(used);
+        }
+        if let Some(data) = &mut self.data {
+            data.advise_use_soon(used);
         }
-        self.data.advise_use_soon(used);
     }
     /// Advises this [`PagedVec`] that it is going to be accessed sequentially.
     /// # Beware
     /// Usage hints are part of fine-grain memory access adjustments. It is *NOT* always beneficial to use, in
     /// contrary, it very often slows allocations down. Before using them, test each usage.
     pub fn advise_use_seq(&mut self) {
-        self.data.advise_use_seq();
+        if let Some(data) = &mut self.data {
+            data.advise_use_seq();
+        }
     }
     /// Advises this [`PagedVec`] that it is going to be accessed randomly.
     /// # Beware
     /// Usage hints are part of fine-grain memory access adjustments. It is *NOT* always beneficial to use, in
     /// contrary, it very often slows allocations down. Before using them, test each usage.
     pub fn advise_use_rnd(&mut self) {
-        self.data.advise_use_rnd();
+        if let Some(data) = &mut self.data {
+            data.advise_use_rnd();
+        }
     }
-    fn get_next_cap(cap: usize) -> usize {
-        //(cap + cap / 2).max(0x1000)
-        cap * 2
+    /// Hints the CPU to load elements in `range` into its caches, ahead of them being accessed. See
+    /// [`Pages::prefetch_range`].
+    /// # Panics
+    /// Panics if `range` is out of bounds of this [`PagedVec`].
+    /// # Examples
+    /// ```
+    /// # use memory_pages::*;
+    /// let mut vec = PagedVec::new(0x1000);
+    /// for i in 0..0x1000_u64{
+    ///     vec.push(i);
+    /// }
+    /// vec.prefetch_range(0x100..0x200, Locality::Medium, false);
+    /// assert_eq!(vec[0x100], 0x100);
+    /// ```
+    pub fn prefetch_range(&self, range: Range<usize>, locality: Locality, advise_kernel: bool) {
+        let elements: &[T] = &self[range];
+        crate::prefetch::prefetch_bytes(
+            elements.as_ptr().cast(),
+            std::mem::size_of_val(elements),
+            locality,
+            advise_kernel,
+        );
     }
-    fn resize(&mut self, next_cap: usize) {
-        let bytes_cap = next_cap * std::mem::size_of::<T>();
-        self.data.resize(bytes_cap);
+    /// Advises the kernel that memory of this [`PagedVec`] may be deduplicated with other memory of identical contents.
+    /// See [`Pages::advise_mergeable`].
+    /// # Errors
+    /// Returns an error on platforms other than Linux, and on kernels built without KSM support.
+    pub fn advise_mergeable(&mut self) -> std::io::Result<()> {
+        self.data.as_mut().map_or(Ok(()), Pages::advise_mergeable)
+    }
+    /// Excludes memory of this [`PagedVec`] from children created using `fork`. See [`Pages::advise_dont_fork`].
+    /// # Errors
+    /// Returns an error on platforms other than Linux and macOS.
+    pub fn advise_dont_fork(&mut self) -> std::io::Result<()> {
+        self.data.as_mut().map_or(Ok(()), Pages::advise_dont_fork)
+    }
+    fn realloc(&mut self, next_cap: usize) {
+        if let Err(err) = self.try_realloc(next_cap) {
+            Self::reserve_failed(&err);
+        }
         /*
         let cpy_len = self.len() * std::mem::size_of::<T>();
         let mut data = Pages::new(bytes_cap);
@@ -110,6 +216,18 @@ impl<T: Sized> PagedVec<T> {
         self.data = data;
         */
     }
+    fn try_realloc(&mut self, next_cap: usize) -> Result<(), TryReserveError> {
+        let bytes_cap = Self::bytes_for(next_cap)?;
+        // Pages may be moved, and their old address range must not stay poisoned.
+        self.unpoison_spare();
+        let res = match &mut self.data {
+            Some(data) => data.try_resize(bytes_cap),
+            None => Pages::try_new_native(bytes_cap, crate::MapOptions::default())
+                .map(|data| self.data = Some(data)),
+        };
+        self.poison_spare();
+        res.map_err(TryReserveError::AllocError)
+    }
     /// Reserves capacity for at least additional more elements to be inserted in the given [`PagedVec<T>`]. The collection may
     /// reserve more space to speculatively avoid frequent reallocations. After calling reserve, capacity will be greater than
     /// or equal to self.len() + additional. Does nothing if capacity is already sufficient.
@@ -125,11 +243,12 @@ impl<T: Sized> PagedVec<T> {
     /// vec.reserve(0x8000);
     /// assert!(init_cap<vec.capacity());
     /// ```
+    /// # Panics
+    /// Panics if the new capacity exceeds `isize::MAX` bytes, or if memory can't be allocated.
     pub fn reserve(&mut self, additional: usize) {
-        if self.len() + additional <= self.capacity() {
-            return;
-        };
-        self.resize((self.len() + additional).max(Self::get_next_cap(self.capacity())));
+        if let Err(err) = self.try_reserve(additional) {
+            Self::reserve_failed(&err);
+        }
     }
     /// Reserves the minimum capacity for at least additional more elements to be inserted in the given [`PagedVec<T>`]. Unlike
     /// reserve, this will not deliberately over-allocate to speculatively avoid frequent allocations. After calling
@@ -151,11 +270,108 @@ impl<T: Sized> PagedVec<T> {
     /// vec.reserve_exact(0x8000);
     /// assert!(init_cap<vec.capacity());
     /// ```
+    /// # Panics
+    /// Panics if the new capacity exceeds `isize::MAX` bytes, or if memory can't be allocated.
     pub fn reserve_exact(&mut self, additional: usize) {
-        if self.len() + additional < self.capacity() {
+        if let Err(err) = self.try_reserve_exact(additional) {
+            Self::reserve_failed(&err);
+        }
+    }
+    /// Shrinks capacity of this [`PagedVec`] as much as possible, returning all whole pages past its length to the
+    /// kernel. Unlike shrinking a `Vec`, this never copies elements on unix, and reduces memory usage of the process
+    /// right away. At least one page is always kept.
+    /// # Examples
+    /// ```
+    /// # use memory_pages::*;
+    /// let mut vec:PagedVec<u8> = PagedVec::new(0x10000);
+    /// vec.extend_from_copy_slice(&[1, 2, 3]);
+    /// vec.shrink_to_fit();
+    /// assert_eq!(vec.capacity(), 0x1000);
+    /// assert_eq!(vec, vec![1, 2, 3]);
+    /// ```
+    pub fn shrink_to_fit(&mut self) {
+        self.shrink_to(0);
+    }
+    /// Shrinks capacity of this [`PagedVec`] with a lower bound, returning whole pages past it to the kernel. Capacity
+    /// will remain at least as large as both the length and `min_capacity`, rounded up to a page boundary. Does nothing
+    /// if capacity is already lower than that.
+    /// # Examples
+    /// ```
+    /// # use memory_pages::*;
+    /// let mut vec:PagedVec<u32> = (0..0x100).collect();
+    /// vec.reserve(0x10000);
+    /// vec.shrink_to(0x1000);
+    /// assert_eq!(vec.capacity(), 0x1000);
+    /// vec.shrink_to(0);
+    /// assert_eq!(vec.capacity(), 0x400);
+    /// ```
+    pub fn shrink_to(&mut self, min_capacity: usize) {
+        // Capacity only ever shrinks here, so its size in bytes can't overflow.
+        let min_capacity = min_capacity.min(self.capacity());
+        let bytes = crate::page_math::align_up(
+            (self.len.max(min_capacity) * std::mem::size_of::<T>()).max(1),
+        );
+        if bytes >= self.capacity_bytes() {
             return;
         }
-        self.resize(self.len() + additional);
+        self.unpoison_spare();
+        if let Some(data) = &mut self.data {
+            data.resize(bytes);
+        }
+        self.poison_spare();
+    }
+    /// Works exactly like [`Self::reserve`], but returns an error instead of panicking if the capacity overflows or the
+    /// kernel refuses to allocate more memory. This allows responding to memory pressure, e.g. by evicting cached data.
+    /// # Errors
+    /// Returns an error if memory can't be allocated. This [`PagedVec`] is left unchanged in that case.
+    /// # Examples
+    /// ```
+    /// # use memory_pages::*;
+    /// let mut vec:PagedVec<u8> = PagedVec::new(0x1000);
+    /// vec.try_reserve(0x8000).unwrap();
+    /// assert!(vec.capacity() >= 0x8000);
+    /// assert!(vec.try_reserve(usize::MAX).is_err());
+    /// ```
+    pub fn try_reserve(&mut self, additional: usize) -> Result<(), TryReserveError> {
+        let needed = self.needed_capacity(additional)?;
+        if needed <= self.capacity() {
+            return Ok(());
+        }
+        // Growing speculatively must not fail if exactly the needed capacity would not overflow.
+        let grown = self
+            .growth
+            .next_cap(self.capacity())
+            .min(Self::MAX_CAPACITY);
+        self.try_realloc(needed.max(grown))
+    }
+    /// Works exactly like [`Self::reserve_exact`], but returns an error instead of panicking if the capacity overflows
+    /// or the kernel refuses to allocate more memory.
+    /// # Errors
+    /// Returns an error if memory can't be allocated. This [`PagedVec`] is left unchanged in that case.
+    pub fn try_reserve_exact(&mut self, additional: usize) -> Result<(), TryReserveError> {
+        let needed = self.needed_capacity(additional)?;
+        if needed <= self.capacity() {
+            return Ok(());
+        }
+        self.try_realloc(needed)
+    }
+    /// Works exactly like [`Self::push`], but returns `t` back together with an error instead of panicking if more
+    /// memory can't be allocated.
+    /// # Errors
+    /// Returns an error if memory can't be allocated.
+    /// # Examples
+    /// ```
+    /// # use memory_pages::*;
+    /// let mut vec = PagedVec::new_empty();
+    /// vec.try_push(1_u64).unwrap();
+    /// assert_eq!(vec, vec![1]);
+    /// ```
+    pub fn try_push(&mut self, t: T) -> Result<(), (T, TryReserveError)> {
+        if let Err(err) = self.try_reserve(1) {
+            return Err((t, err));
+        }
+        self.push(t);
+        Ok(())
     }
     /// Removes and returns the element at position `index` within the vector,
     /// shifting all elements after it to the left.
@@ -193,8 +409,35 @@ impl<T: Sized> PagedVec<T> {
             std::ptr::copy(ptr.add(1), ptr, self.len - index - 1);
         }
         self.len -= 1;
+        self.poison_spare();
         ret
     }
+    /// Removes and returns the element at position `index`, replacing it with the last element. This does not preserve
+    /// ordering, but is *O*(1).
+    /// # Panics
+    /// Panics if `index` is out of bounds.
+    /// # Examples
+    /// ```
+    /// # use memory_pages::*;
+    /// let mut vec:PagedVec<u32> = (0..5).collect();
+    /// assert_eq!(vec.swap_remove(1), 1);
+    /// assert_eq!(vec, vec![0, 4, 2, 3]);
+    /// assert_eq!(vec.swap_remove(3), 3);
+    /// assert_eq!(vec, vec![0, 4, 2]);
+    /// ```
+    pub fn swap_remove(&mut self, index: usize) -> T {
+        assert!(
+            index < self.len,
+            "swap_remove index {index} out of bounds of vec of length {}!",
+            self.len
+        );
+        let last = self.len - 1;
+        self.swap(index, last);
+        self.len = last;
+        let t = unsafe { std::ptr::read(self.elements().add(last)) };
+        self.poison_spare();
+        t
+    }
     /// Pushes `t` into `self` and reallocates if over capacity. Generally unadvised, because reallocation's of [`PagedVec`]-s
     /// are very slow. Setting sufficient capacity and using [`Self::push_within_capacity`] is generally encouraged.
     /// Pushes `t` into `self` if under capacity, else returns `t`.
@@ -210,15 +453,556 @@ impl<T: Sized> PagedVec<T> {
     /// // push outside capacity, a slow reallocation occurs, but `push` still succeeds!
     /// vec.push(5.6);
     pub fn push(&mut self, t: T) {
-        if self.len * std::mem::size_of::<T>() >= self.data.len() {
-            self.resize(Self::get_next_cap(self.capacity()));
+        if self.len >= self.capacity() {
+            self.reserve(1);
         }
+        self.unpoison_next();
         unsafe {
             let end = self.as_mut_ptr().add(self.len);
             std::ptr::write(end, t);
             self.len += 1;
         };
     }
+    /// Clones and appends all elements of `other` to this [`PagedVec`], reserving capacity for all of them up front.
+    /// For [`Copy`] types, [`Self::extend_from_copy_slice`] is much faster.
+    /// # Examples
+    /// ```
+    /// # use memory_pages::*;
+    /// let mut vec = PagedVec::new(0x1000);
+    /// vec.push("a".to_owned());
+    /// vec.extend_from_slice(&["b".to_owned(), "c".to_owned()]);
+    /// assert_eq!(vec, ["a", "b", "c"].map(String::from).to_vec());
+    /// ```
+    pub fn extend_from_slice(&mut self, other: &[T])
+    where
+        T: Clone,
+    {
+        self.reserve(other.len());
+        self.unpoison_spare();
+        for t in other {
+            unsafe { std::ptr::write(self.as_mut_ptr().add(self.len), t.clone()) };
+            // Length is updated after each element, so that cloned elements are dropped even if `clone` panics.
+            self.len += 1;
+        }
+        self.poison_spare();
+    }
+    /// Appends all elements of `other` to this [`PagedVec`], using a single bulk copy. This is the fastest way of
+    /// loading large amounts of data into a [`PagedVec`].
+    /// # Examples
+    /// ```
+    /// # use memory_pages::*;
+    /// let mut vec = PagedVec::new(0x1000);
+    /// vec.push(1_u32);
+    /// vec.extend_from_copy_slice(&[2, 3, 4]);
+    /// assert_eq!(vec, vec![1, 2, 3, 4]);
+    /// ```
+    pub fn extend_from_copy_slice(&mut self, other: &[T])
+    where
+        T: Copy,
+    {
+        self.reserve(other.len());
+        self.unpoison_spare();
+        unsafe {
+            std::ptr::copy_nonoverlapping(
+                other.as_ptr(),
+                self.as_mut_ptr().add(self.len),
+                other.len(),
+            );
+        }
+        self.len += other.len();
+        self.poison_spare();
+    }
+    /// Resizes this [`PagedVec`] in place so that its length is equal to `new_len`. If `new_len` is greater than the
+    /// current length, the vec is extended by clones of `value`, otherwise it is truncated and excess elements are
+    /// dropped.
+    /// # Examples
+    /// ```
+    /// # use memory_pages::*;
+    /// let mut vec:PagedVec<u32> = (1..4).collect();
+    /// vec.resize(5, 0);
+    /// assert_eq!(vec, vec![1, 2, 3, 0, 0]);
+    /// vec.resize(2, 0);
+    /// assert_eq!(vec, vec![1, 2]);
+    /// ```
+    pub fn resize(&mut self, new_len: usize, value: T)
+    where
+        T: Clone,
+    {
+        if new_len <= self.len {
+            self.truncate(new_len);
+            return;
+        }
+        self.reserve(new_len - self.len);
+        self.extend_from_fn(new_len - self.len - 1, |_| value.clone());
+        // Last element takes `value` itself, saving one clone.
+        self.push(value);
+    }
+    /// Resizes this [`PagedVec`] in place so that its length is equal to `new_len`. If `new_len` is greater than the
+    /// current length, the vec is extended by values returned by calling `f`, otherwise it is truncated and excess
+    /// elements are dropped.
+    /// # Examples
+    /// ```
+    /// # use memory_pages::*;
+    /// let mut vec:PagedVec<u32> = PagedVec::new(0x1000);
+    /// let mut next = 0;
+    /// vec.resize_with(4, || { next += 2; next });
+    /// assert_eq!(vec, vec![2, 4, 6, 8]);
+    /// vec.resize_with(1, || unreachable!());
+    /// assert_eq!(vec, vec![2]);
+    /// ```
+    pub fn resize_with(&mut self, new_len: usize, mut f: impl FnMut() -> T) {
+        if new_len <= self.len {
+            self.truncate(new_len);
+            return;
+        }
+        self.extend_from_fn(new_len - self.len, |_| f());
+    }
+    /// Appends `count` elements returned by `f`, which is called with the index each element will have. Capacity is
+    /// reserved once, and length is only updated after all elements are written, which makes this much faster than
+    /// calling [`Self::push`] in a loop.
+    /// # Examples
+    /// ```
+    /// # use memory_pages::*;
+    /// let mut vec:PagedVec<u64> = PagedVec::new(0x1000);
+    /// vec.push(7);
+    /// vec.extend_from_fn(3, |i| i as u64 * 10);
+    /// assert_eq!(vec, vec![7, 10, 20, 30]);
+    /// ```
+    pub fn extend_from_fn(&mut self, count: usize, mut f: impl FnMut(usize) -> T) {
+        /// Sets length of the vec to the number of written elements once dropped, so that they are dropped with the
+        /// vec even if `f` panics.
+        struct SetLenOnDrop<'a, T> {
+            vec: &'a mut PagedVec<T>,
+            len: usize,
+        }
+        impl<T> Drop for SetLenOnDrop<'_, T> {
+            fn drop(&mut self) {
+                self.vec.len = self.len;
+                self.vec.poison_spare();
+            }
+        }
+        self.reserve(count);
+        self.unpoison_spare();
+        let elements = self.elements();
+        let mut guard = SetLenOnDrop {
+            len: self.len,
+            vec: self,
+        };
+        for _ in 0..count {
+            unsafe { std::ptr::write(elements.add(guard.len), f(guard.len)) };
+            guard.len += 1;
+        }
+    }
+    /// Hands the next `len` elements of spare capacity to `f`, which writes elements into them and returns how many of
+    /// them, counting from the beginning, it initialized. That many elements are then appended to this [`PagedVec`].
+    /// Useful for decoders and generators filling millions of elements at once. Returns the number of appended
+    /// elements.
+    /// # Safety
+    /// The first `n` elements of the slice passed to `f` must be initialized, where `n` is the value returned by `f`.
+    /// # Panics
+    /// Panics if `f` returns a number greater than `len`.
+    /// # Examples
+    /// ```
+    /// # use memory_pages::*;
+    /// let mut vec:PagedVec<u8> = PagedVec::new_empty();
+    /// let written = unsafe {
+    ///     vec.push_uninit_chunk(0x100, |chunk| {
+    ///         for (i, byte) in chunk[..10].iter_mut().enumerate() {
+    ///             byte.write(i as u8);
+    ///         }
+    ///         10
+    ///     })
+    /// };
+    /// assert_eq!(written, 10);
+    /// assert_eq!(vec.len(), 10);
+    /// assert_eq!(vec[9], 9);
+    /// ```
+    pub unsafe fn push_uninit_chunk(
+        &mut self,
+        len: usize,
+        f: impl FnOnce(&mut [MaybeUninit<T>]) -> usize,
+    ) -> usize {
+        self.reserve(len);
+        let written = f(&mut self.spare_capacity_mut()[..len]);
+        assert!(
+            written <= len,
+            "{written} elements written into a chunk of length {len}!"
+        );
+        self.set_len(self.len + written);
+        written
+    }
+    /// Replaces elements in `range` with elements of `replace_with`, returning the removed elements in an iterator.
+    /// `replace_with` does not need to have the same length as `range`. Elements are replaced once the iterator is
+    /// dropped, even if not all removed elements were consumed.
+    ///
+    /// Elements after the range are moved at most twice, or once if the lower bound of the size hint of
+    /// `replace_with` is exact.
+    /// # Panics
+    /// Panics if the start of the range is greater than its end, or if its end is greater than the length of the vec.
+    /// # Examples
+    /// ```
+    /// # use memory_pages::*;
+    /// let mut vec:PagedVec<u32> = (0..6).collect();
+    /// let removed:Vec<u32> = vec.splice(1..3, [10, 11, 12, 13]).collect();
+    /// assert_eq!(removed, [1, 2]);
+    /// assert_eq!(vec, vec![0, 10, 11, 12, 13, 3, 4, 5]);
+    /// ```
+    pub fn splice<I: IntoIterator<Item = T>>(
+        &mut self,
+        range: impl RangeBounds<usize>,
+        replace_with: I,
+    ) -> Splice<'_, I::IntoIter> {
+        Splice {
+            drain: self.drain(range),
+            replace_with: replace_with.into_iter(),
+        }
+    }
+    /// Removes elements in `range` from this [`PagedVec`], returning them in an iterator. Elements after the range are
+    /// shifted down once the iterator is dropped, and elements not consumed by the iterator are dropped with it.
+    ///
+    /// If the iterator is leaked (e.g. using [`std::mem::forget`]), the vec may lose and leak elements arbitrarily,
+    /// including elements outside of the range.
+    /// # Panics
+    /// Panics if the start of the range is greater than its end, or if its end is greater than the length of the vec.
+    /// # Examples
+    /// ```
+    /// # use memory_pages::*;
+    /// let mut queue:PagedVec<u32> = (0..10).collect();
+    /// let front:Vec<u32> = queue.drain(..3).collect();
+    /// assert_eq!(front, [0, 1, 2]);
+    /// assert_eq!(queue, vec![3, 4, 5, 6, 7, 8, 9]);
+    /// // Dropping the iterator drops the rest of the range.
+    /// queue.drain(2..5);
+    /// assert_eq!(queue, vec![3, 4, 8, 9]);
+    /// ```
+    pub fn drain(&mut self, range: impl RangeBounds<usize>) -> Drain<'_, T> {
+        let start = match range.start_bound() {
+            Bound::Included(start) => *start,
+            Bound::Excluded(start) => start.checked_add(1).expect("range start overflows!"),
+            Bound::Unbounded => 0,
+        };
+        let end = match range.end_bound() {
+            Bound::Included(end) => end.checked_add(1).expect("range end overflows!"),
+            Bound::Excluded(end) => *end,
+            Bound::Unbounded => self.len,
+        };
+        assert!(
+            start <= end,
+            "drain range starts at {start} but ends at {end}!"
+        );
+        assert!(
+            end <= self.len,
+            "drain range end {end} out of bounds of vec of length {}!",
+            self.len
+        );
+        let tail_len = self.len - end;
+        // Until the iterator is dropped, the vec only owns elements before the range, so leaking the iterator can't
+        // cause drained elements to be dropped twice.
+        self.len = start;
+        Drain {
+            vec: self,
+            next: start,
+            end,
+            tail_start: end,
+            tail_len,
+        }
+    }
+    /// Retains only the elements for which `f` returns true, dropping the rest in place. Remaining elements keep their
+    /// order, and are compacted in a single pass, without allocating any extra memory.
+    /// # Examples
+    /// ```
+    /// # use memory_pages::*;
+    /// let mut vec:PagedVec<u32> = (0..10).collect();
+    /// vec.retain(|x| x % 3 == 0);
+    /// assert_eq!(vec, vec![0, 3, 6, 9]);
+    /// ```
+    pub fn retain(&mut self, mut f: impl FnMut(&T) -> bool) {
+        self.retain_mut(|t| f(t));
+    }
+    /// Retains only the elements for which `f` returns true, allowing `f` to modify them. See [`Self::retain`].
+    /// # Examples
+    /// ```
+    /// # use memory_pages::*;
+    /// let mut vec:PagedVec<u32> = (0..10).collect();
+    /// vec.retain_mut(|x| {
+    ///     *x *= 2;
+    ///     *x > 10
+    /// });
+    /// assert_eq!(vec, vec![12, 14, 16, 18]);
+    /// ```
+    pub fn retain_mut(&mut self, mut f: impl FnMut(&mut T) -> bool) {
+        /// Shifts unprocessed elements down and fixes the length, even if `f` or dropping an element panics.
+        struct Compact<'a, T> {
+            vec: &'a mut PagedVec<T>,
+            elements: *mut T,
+            processed: usize,
+            deleted: usize,
+            original_len: usize,
+        }
+        impl<T> Drop for Compact<'_, T> {
+            fn drop(&mut self) {
+                if self.deleted > 0 {
+                    unsafe {
+                        std::ptr::copy(
+                            self.elements.add(self.processed),
+                            self.elements.add(self.processed - self.deleted),
+                            self.original_len - self.processed,
+                        );
+                    }
+                }
+                self.vec.len = self.original_len - self.deleted;
+                self.vec.poison_spare();
+            }
+        }
+        let original_len = self.len;
+        let elements = self.as_mut_ptr();
+        // Vec owns no elements while they are being processed, so a panic can't cause a double drop.
+        self.len = 0;
+        let mut compact = Compact {
+            vec: self,
+            elements,
+            processed: 0,
+            deleted: 0,
+            original_len,
+        };
+        while compact.processed < original_len {
+            let current = unsafe { &mut *elements.add(compact.processed) };
+            let keep = f(current);
+            compact.processed += 1;
+            if keep {
+                if compact.deleted > 0 {
+                    unsafe {
+                        std::ptr::copy_nonoverlapping(
+                            current,
+                            elements.add(compact.processed - 1 - compact.deleted),
+                            1,
+                        );
+                    }
+                }
+            } else {
+                compact.deleted += 1;
+                unsafe { std::ptr::drop_in_place(current) };
+            }
+        }
+    }
+    /// Returns an iterator lazily removing and yielding the elements for which `pred` returns true. Remaining elements
+    /// keep their order, and are compacted as the iterator advances, so no extra memory is needed. Once the iterator is
+    /// dropped, elements it did not visit are kept, without calling `pred` on them.
+    ///
+    /// If the iterator is leaked (e.g. using [`std::mem::forget`]), the vec may lose and leak elements arbitrarily.
+    /// # Examples
+    /// ```
+    /// # use memory_pages::*;
+    /// let mut vec:PagedVec<u32> = (0..10).collect();
+    /// let odd:Vec<u32> = vec.extract_if(|x| *x % 2 == 1).collect();
+    /// assert_eq!(odd, [1, 3, 5, 7, 9]);
+    /// assert_eq!(vec, vec![0, 2, 4, 6, 8]);
+    /// ```
+    pub fn extract_if<F: FnMut(&mut T) -> bool>(&mut self, pred: F) -> ExtractIf<'_, T, F> {
+        let original_len = self.len;
+        // Vec owns no elements while they are being processed, so leaking the iterator can't cause a double drop.
+        self.len = 0;
+        ExtractIf {
+            vec: self,
+            pred,
+            processed: 0,
+            deleted: 0,
+            original_len,
+        }
+    }
+    /// Shortens this [`PagedVec`] to `len` elements, dropping the rest in place. Capacity is left unchanged. Does nothing
+    /// if `len` is not smaller than the current length.
+    /// # Examples
+    /// ```
+    /// # use memory_pages::*;
+    /// let mut vec:PagedVec<u32> = (0..10).collect();
+    /// let cap = vec.capacity();
+    /// vec.truncate(3);
+    /// assert_eq!(vec, vec![0, 1, 2]);
+    /// assert_eq!(vec.capacity(), cap);
+    /// ```
+    pub fn truncate(&mut self, len: usize) {
+        if len >= self.len {
+            return;
+        }
+        let tail =
+            std::ptr::slice_from_raw_parts_mut(self.as_mut_ptr().wrapping_add(len), self.len - len);
+        // Length is updated first, so that if dropping an element panics, the vec does not drop it again.
+        self.len = len;
+        unsafe { std::ptr::drop_in_place(tail) };
+        self.poison_spare();
+    }
+    /// Works exactly like [`Self::truncate`], but also returns physical memory of whole pages past the new end to the
+    /// kernel. Capacity stays reserved, and is backed by memory again once used.
+    /// # Examples
+    /// ```
+    /// # use memory_pages::*;
+    /// let mut vec:PagedVec<u64> = (0..0x10000).collect();
+    /// vec.truncate_and_decommit(0x100);
+    /// assert_eq!(vec.len(), 0x100);
+    /// assert_eq!(vec[0xFF], 0xFF);
+    /// ```
+    pub fn truncate_and_decommit(&mut self, len: usize) {
+        self.truncate(len);
+        self.decommit_unused();
+    }
+    /// Returns physical memory of all whole pages past the last element to the kernel. Capacity stays reserved in address
+    /// space, and is backed by memory again once used. Useful for long-lived buffers after a temporary spike in size,
+    /// which a `Vec` can only handle by reallocating.
+    /// # Examples
+    /// ```
+    /// # use memory_pages::*;
+    /// let mut vec:PagedVec<u64> = (0..0x10000).collect();
+    /// let cap = vec.capacity();
+    /// vec.truncate(0x100);
+    /// vec.decommit_unused();
+    /// assert_eq!(vec.capacity(), cap);
+    /// assert_eq!(vec[0xFF], 0xFF);
+    /// ```
+    pub fn decommit_unused(&mut self) {
+        let used = crate::page_math::align_up(self.len * std::mem::size_of::<T>());
+        if let Some(data) = &mut self.data {
+            if used < data.len() {
+                data.decommit(used, data.len() - used);
+            }
+        }
+    }
+    /// Splits this [`PagedVec`] in two at `at`, returning a new [`PagedVec`] holding elements from `at` onwards, and
+    /// leaving elements before `at` in this one.
+    ///
+    /// If the tail starts at a page boundary, its pages are moved into the new [`PagedVec`] without copying any data,
+    /// which `Vec` can't do. In that case capacity of this [`PagedVec`] shrinks to `at`, since the pages past it now
+    /// belong to the returned one.
+    /// # Panics
+    /// Panics if `at` is greater than the length of this [`PagedVec`].
+    /// # Examples
+    /// ```
+    /// # use memory_pages::*;
+    /// let mut vec:PagedVec<u32> = (0..0x2000).collect();
+    /// // 0x1000 u32s fill exactly 4 pages, so the tail is moved without copying.
+    /// let tail = vec.split_off(0x1000);
+    /// assert_eq!(vec.len(), 0x1000);
+    /// assert_eq!(tail.len(), 0x1000);
+    /// assert_eq!(tail[0], 0x1000);
+    /// let mut vec:PagedVec<u32> = (0..10).collect();
+    /// let tail = vec.split_off(7);
+    /// assert_eq!(tail, vec![7, 8, 9]);
+    /// ```
+    #[must_use]
+    pub fn split_off(&mut self, at: usize) -> Self {
+        assert!(
+            at <= self.len,
+            "split index {at} out of bounds of vec of length {}!",
+            self.len
+        );
+        let tail_len = self.len - at;
+        let at_bytes = at * std::mem::size_of::<T>();
+        if at_bytes.is_multiple_of(crate::page_math::PAGE_SIZE)
+            && 0 < at_bytes
+            && at_bytes < self.capacity_bytes()
+        {
+            let tail = Self {
+                data: self.data.as_mut().map(|data| data.split_off(at_bytes)),
+                len: tail_len,
+                growth: self.growth,
+                pd: PhantomData,
+            };
+            self.len = at;
+            return tail;
+        }
+        if tail_len == 0 {
+            return Self::new_empty();
+        }
+        let mut tail = Self::new(tail_len);
+        unsafe {
+            tail.unpoison_spare();
+            std::ptr::copy_nonoverlapping(self.elements().add(at), tail.elements(), tail_len);
+        }
+        tail.len = tail_len;
+        tail.poison_spare();
+        // Elements were moved into the tail, so they must not be dropped here.
+        self.len = at;
+        self.poison_spare();
+        tail
+    }
+    /// Moves all elements of `other` to the end of this [`PagedVec`], leaving `other` empty. Capacity of `other` is left
+    /// unchanged.
+    ///
+    /// Elements are moved with a single bulk copy. On Linux, if `other` is large and the end of this [`PagedVec`] is page
+    /// aligned, whole pages of `other` are instead remapped into this [`PagedVec`], without copying any data.
+    /// # Examples
+    /// ```
+    /// # use memory_pages::*;
+    /// let mut vec:PagedVec<u32> = (0..3).collect();
+    /// let mut other:PagedVec<u32> = (3..6).collect();
+    /// vec.append(&mut other);
+    /// assert_eq!(vec, vec![0, 1, 2, 3, 4, 5]);
+    /// assert!(other.is_empty());
+    /// ```
+    pub fn append(&mut self, other: &mut Self) {
+        let count = other.len;
+        if count == 0 {
+            return;
+        }
+        self.reserve(count);
+        #[cfg(target_os = "linux")]
+        if self.append_remap(other) {
+            return;
+        }
+        self.unpoison_spare();
+        unsafe {
+            std::ptr::copy_nonoverlapping(other.elements(), self.elements().add(self.len), count);
+        }
+        self.len += count;
+        // Elements were moved out of `other`, so they must not be dropped there.
+        other.len = 0;
+        self.poison_spare();
+        other.poison_spare();
+    }
+    /// Moves elements of `other` to the end of this [`PagedVec`] by remapping their pages. Returns false if this is not
+    /// possible or not worth it.
+    #[cfg(target_os = "linux")]
+    fn append_remap(&mut self, other: &mut Self) -> bool {
+        use crate::page_math::{align_up, PAGE_SIZE};
+        /// Remapping is only faster than copying for large amounts of data.
+        const REMAP_MIN_BYTES: usize = 0x10_0000;
+        let dest = self.len * std::mem::size_of::<T>();
+        let bytes = other.len * std::mem::size_of::<T>();
+        let moved = align_up(bytes);
+        // Pages moved over memory backed by a file would cut it off from the file, so it is only written by copying.
+        let anonymous = self
+            .data
+            .as_ref()
+            .is_some_and(|data| matches!(data.backing, crate::Backing::Anonymous));
+        if !anonymous
+            || bytes < REMAP_MIN_BYTES
+            || !dest.is_multiple_of(PAGE_SIZE)
+            || dest + moved > align_up(self.capacity_bytes())
+        {
+            return false;
+        }
+        other.unpoison_spare();
+        let capacity = other.capacity_bytes();
+        let Some(mut pages) = other.data.replace(Pages::new(capacity)) else {
+            return false;
+        };
+        if moved < pages.len() {
+            // Pages past the elements are not moved, and are just released.
+            drop(pages.split_off(moved));
+        }
+        let dest_ptr = self.elements().cast::<u8>().wrapping_add(dest);
+        if let Err(pages) = pages.move_to(dest_ptr) {
+            // Fall back to copying.
+            self.unpoison_spare();
+            unsafe { std::ptr::copy_nonoverlapping(pages.get_ptr(0), dest_ptr, bytes) };
+        }
+        sanitizer::unpoison(dest_ptr, bytes);
+        self.len += other.len;
+        other.len = 0;
+        self.poison_spare();
+        other.poison_spare();
+        true
+    }
     /// Gets the capacity of `self`.
     /// ```
     /// # use memory_pages::*;
@@ -237,7 +1021,62 @@ impl<T: Sized> PagedVec<T> {
     /// ```
     #[must_use]
     pub fn capacity(&self) -> usize {
-        self.data.len() / std::mem::size_of::<T>()
+        self.capacity_bytes() / std::mem::size_of::<T>()
+    }
+    /// Size of memory allocated for elements, in bytes.
+    fn capacity_bytes(&self) -> usize {
+        self.data.as_ref().map_or(0, |data| data.len())
+    }
+    /// Returns the remaining spare capacity of this [`PagedVec`] as a slice of [`MaybeUninit<T>`]. This allows filling
+    /// it directly, e.g. by reading from a file or decompressing into it, before marking it as initialized using
+    /// [`Self::set_len`].
+    /// # Examples
+    /// ```
+    /// # use memory_pages::*;
+    /// let mut vec:PagedVec<u32> = PagedVec::new(0x1000);
+    /// let spare = vec.spare_capacity_mut();
+    /// for (i, elem) in spare[..3].iter_mut().enumerate() {
+    ///     elem.write(i as u32);
+    /// }
+    /// unsafe { vec.set_len(3) };
+    /// assert_eq!(vec, vec![0, 1, 2]);
+    /// ```
+    pub fn spare_capacity_mut(&mut self) -> &mut [MaybeUninit<T>] {
+        self.unpoison_spare();
+        unsafe {
+            std::slice::from_raw_parts_mut(
+                self.elements().add(self.len).cast::<MaybeUninit<T>>(),
+                self.capacity() - self.len,
+            )
+        }
+    }
+    /// Forces the length of this [`PagedVec`] to `new_len`, without dropping or initializing any elements.
+    /// # Safety
+    /// `new_len` must be less than or equal to [`Self::capacity`], and elements up to `new_len` must be initialized.
+    /// Elements past `new_len` are not dropped, and are leaked if they were initialized.
+    pub unsafe fn set_len(&mut self, new_len: usize) {
+        debug_assert!(new_len <= self.capacity());
+        self.len = new_len;
+        self.poison_spare();
+    }
+    /// Moves all elements of this [`PagedVec`] into a [`Vec`], using a single bulk copy.
+    /// # Examples
+    /// ```
+    /// # use memory_pages::*;
+    /// let vec:PagedVec<String> = ["a", "b"].into_iter().map(String::from).collect();
+    /// let vec:Vec<String> = vec.into_vec();
+    /// assert_eq!(vec, ["a", "b"]);
+    /// ```
+    #[must_use]
+    pub fn into_vec(mut self) -> Vec<T> {
+        let mut vec = Vec::with_capacity(self.len);
+        unsafe {
+            std::ptr::copy_nonoverlapping(self.elements(), vec.as_mut_ptr(), self.len);
+            vec.set_len(self.len);
+        }
+        // Elements were moved into `vec`, so they must not be dropped with `self`.
+        self.len = 0;
+        vec
     }
     /// Pops the last element from `self`
     /// ```
@@ -254,16 +1093,13 @@ impl<T: Sized> PagedVec<T> {
     /// assert_eq!(vec.pop(),None);
     /// ```
     pub fn pop(&mut self) -> Option<T> {
-        use std::mem::MaybeUninit;
         if self.len == 0 {
             return None;
         }
-        let last_index = self.len - 1;
-        // This is safe, because res is swapped into the page and can only be overwritten, never read from.
-        #[allow(clippy::uninit_assumed_init)]
-        let mut res = unsafe { MaybeUninit::uninit().assume_init() };
-        std::mem::swap(&mut self[last_index], &mut res);
         self.len -= 1;
+        // Element is past the new length, so it is moved out exactly once.
+        let res = unsafe { std::ptr::read(self.elements().add(self.len)) };
+        self.poison_spare();
         Some(res)
     }
     /// Clears the vector, removing all values.
@@ -281,39 +1117,194 @@ impl<T: Sized> PagedVec<T> {
     /// ```
     pub fn clear(&mut self) {
         self.drop_all();
-        self.len = 0;
+        self.poison_spare();
     }
-    /// Works exacly the same as [`Self::clear`] but hints the OS that some of the memory occupied by data inside this 
-    /// [`PagedVec`] is going to be unused, allowing it to be temporarily reclaimed. This allows the memory to be 
+    /// Works exacly the same as [`Self::clear`] but hints the OS that some of the memory occupied by data inside this
+    /// [`PagedVec`] is going to be unused, allowing it to be temporarily reclaimed. This allows the memory to be
     /// reserved, but not backed by physical RAM until next use, reducing RAM usage.
-    pub fn clear_decommit(&mut self){
+    pub fn clear_decommit(&mut self) {
         self.clear();
-        self.data.decommit(0, self.data.len());
+        if let Some(data) = &mut self.data {
+            data.decommit(0, data.len());
+        }
+    }
+    /// Runs `f` on the elements of this [`PagedVec`], recording which pages of its memory were written to. Useful for
+    /// finding out which parts of a large data set are actually modified. Page indices in the returned [`WriteTrace`](crate::WriteTrace)
+    /// are relative to the beginning of the vec, so page `n` holds bytes from `n * PAGE_SIZE` to `(n + 1) * PAGE_SIZE`.
+    /// `f` only gets access to elements, so the vec can't be reallocated while it is being traced.
+    /// # Errors
+    /// Returns an error if faults inside this [`PagedVec`] can't be trapped.
+    /// # Examples
+    /// ```
+    /// # use memory_pages::*;
+    /// let mut vec = PagedVec::new(0x4000);
+    /// for _ in 0..0x4000{
+    ///     vec.push(0_u32);
+    /// }
+    /// let trace = vec.trace_writes(|elements|{
+    ///     // Each page holds 1024 u32s.
+    ///     elements[3000] = 1;
+    /// }).unwrap();
+    /// assert_eq!(trace.dirty_pages().collect::<Vec<_>>(), [2]);
+    /// ```
+    #[cfg(any(target_os = "linux", target_os = "macos", target_family = "windows"))]
+    pub fn trace_writes(
+        &mut self,
+        f: impl FnOnce(&mut [T]),
+    ) -> Result<crate::WriteTrace, crate::TrapError> {
+        // There must be some pages to watch for writes.
+        self.reserve(1);
+        let (ptr, len) = (self.elements().cast::<u8>(), self.capacity_bytes());
+        crate::write_trace::trace_range(ptr, len, || f(self))
+    }
+    /// Returns an iterator over sub-slices of elements split at page boundaries, so that all elements of a sub-slice
+    /// start in the same page. Useful for per-page operations, like checksums or decisions about decommitting. If size
+    /// of `T` does not divide [`PAGE_SIZE`](crate::page_math::PAGE_SIZE), the last element of a sub-slice may reach into
+    /// the next page.
+    /// # Examples
+    /// ```
+    /// # use memory_pages::*;
+    /// let vec:PagedVec<u32> = (0..0x900).collect();
+    /// // Each page holds 1024 u32s.
+    /// let lens:Vec<usize> = vec.page_chunks().map(|chunk|chunk.len()).collect();
+    /// assert_eq!(lens, [0x400, 0x400, 0x100]);
+    /// ```
+    pub fn page_chunks(&self) -> crate::page_math::PageRanges<'_, T> {
+        crate::page_math::page_ranges(self)
+    }
+    /// Works exactly like [`Self::page_chunks`], but yields mutable sub-slices.
+    pub fn page_chunks_mut(&mut self) -> crate::page_math::PageRangesMut<'_, T> {
+        crate::page_math::page_ranges_mut(self)
+    }
+    /// Takes memory and length out of this [`PagedVec`], without dropping any elements.
+    pub(crate) fn into_parts(
+        self,
+    ) -> (
+        Option<Pages<crate::AllowRead, crate::AllowWrite, crate::DenyExec>>,
+        usize,
+    ) {
+        let mut vec = std::mem::ManuallyDrop::new(self);
+        (vec.data.take(), vec.len)
+    }
+    /// Returns a pointer to the first element, which, unlike [`slice::as_mut_ptr`], may be used to access the whole
+    /// capacity.
+    fn elements(&self) -> *mut T {
+        self.data
+            .as_ref()
+            .map_or(NonNull::dangling().as_ptr(), |data| {
+                data.get_ptr_unchecked().cast::<T>()
+            })
+    }
+    /// Poisons memory past the last element, so that sanitizers report accesses to it.
+    fn poison_spare(&self) {
+        let used = self.len * std::mem::size_of::<T>();
+        sanitizer::poison(
+            self.elements().cast::<u8>().wrapping_add(used),
+            self.capacity_bytes() - used,
+        );
+    }
+    pub(crate) fn unpoison_spare(&self) {
+        let used = self.len * std::mem::size_of::<T>();
+        sanitizer::unpoison(
+            self.elements().cast::<u8>().wrapping_add(used),
+            self.capacity_bytes() - used,
+        );
+    }
+    /// Unpoisons memory of the element past the last one, before it is written.
+    fn unpoison_next(&self) {
+        let used = self.len * std::mem::size_of::<T>();
+        sanitizer::unpoison(
+            self.elements().cast::<u8>().wrapping_add(used),
+            std::mem::size_of::<T>(),
+        );
     }
+    /// Drops all elements, setting length to 0.
     fn drop_all(&mut self) {
-        use std::mem::MaybeUninit;
-        for i in 0..self.len() {
-            // This is safe, because tmp is swapped into the page, and then it is effectively forgotten.
-            #[allow(clippy::uninit_assumed_init)]
-            let mut tmp = unsafe { MaybeUninit::uninit().assume_init() };
-            std::mem::swap(&mut self[i], &mut tmp);
-        }
+        let elements = std::ptr::slice_from_raw_parts_mut(self.elements(), self.len);
+        // Length is updated first, so that if dropping an element panics, the vec does not drop it again.
+        self.len = 0;
+        unsafe { std::ptr::drop_in_place(elements) };
+    }
+}
+impl PagedVec<u8> {
+    /// Creates a [`PagedVec`] holding the first `len` bytes of `pages`, without copying them. Rest of `pages` becomes
+    /// spare capacity. If `pages` are backed by a file, growing the vec past their length extends the file.
+    /// # Panics
+    /// Panics if `len` is greater than the length of `pages`.
+    /// # Examples
+    /// ```
+    /// # use memory_pages::*;
+    /// let mut pages:Pages<AllowRead,AllowWrite,DenyExec> = Pages::new(0x1000);
+    /// pages.split_at_mut(3).0.copy_from_slice(&[1, 2, 3]);
+    /// let mut vec = PagedVec::from_pages(pages, 3);
+    /// vec.push(4);
+    /// assert_eq!(vec, vec![1, 2, 3, 4]);
+    /// ```
+    #[must_use]
+    pub fn from_pages(
+        pages: Pages<crate::AllowRead, crate::AllowWrite, crate::DenyExec>,
+        len: usize,
+    ) -> Self {
+        assert!(
+            len <= pages.len(),
+            "length {len} out of bounds of pages of length {}!",
+            pages.len()
+        );
+        let vec = Self {
+            data: Some(pages),
+            len,
+            growth: GrowthPolicy::default(),
+            pd: PhantomData,
+        };
+        vec.poison_spare();
+        vec
+    }
+    /// Takes the [`Pages`] out of this [`PagedVec`], together with its length, without copying any data. If no memory
+    /// was allocated yet, a single zeroed page is returned.
+    /// # Examples
+    /// ```
+    /// # use memory_pages::*;
+    /// let vec:PagedVec<u8> = (0..10).collect();
+    /// let (pages, len) = vec.into_pages();
+    /// assert_eq!(len, 10);
+    /// assert_eq!(pages[9], 9);
+    /// ```
+    #[must_use]
+    pub fn into_pages(
+        self,
+    ) -> (
+        Pages<crate::AllowRead, crate::AllowWrite, crate::DenyExec>,
+        usize,
+    ) {
+        let (data, len) = self.into_parts();
+        let data = data.unwrap_or_else(|| Pages::new(crate::page_math::PAGE_SIZE));
+        // All of the pages are now accessible.
+        sanitizer::unpoison(data.get_ptr_unchecked(), data.len());
+        (data, len)
     }
 }
 impl<T: Sized> Drop for PagedVec<T> {
     fn drop(&mut self) {
         self.drop_all();
+        // Address range may be reused by a later mapping, which must not inherit poisoned state.
+        self.unpoison_spare();
+    }
+}
+impl<T: Sized> Default for PagedVec<T> {
+    /// Creates an empty [`PagedVec`], without allocating any memory. See [`PagedVec::new_empty`].
+    fn default() -> Self {
+        Self::new_empty()
     }
 }
 impl<T: Sized> Deref for PagedVec<T> {
     type Target = [T];
     fn deref(&self) -> &[T] {
-        unsafe { std::slice::from_raw_parts(self.data.get_ptr(0).cast::<T>(), self.len) }
+        unsafe { std::slice::from_raw_parts(self.elements(), self.len) }
     }
 }
 impl<T: Sized> DerefMut for PagedVec<T> {
     fn deref_mut(&mut self) -> &mut [T] {
-        unsafe { std::slice::from_raw_parts_mut(self.data.get_ptr_mut(0).cast::<T>(), self.len) }
+        unsafe { std::slice::from_raw_parts_mut(self.elements(), self.len) }
     }
 }
 impl<T: Sized> Borrow<[T]> for PagedVec<T> {
@@ -326,35 +1317,6 @@ impl<T: Sized> BorrowMut<[T]> for PagedVec<T> {
         self
     }
 }
-#[cfg(test)]
-mod test {
-    use super::*;
-    #[test]
-    fn test_page_vec() {
-        let mut vec: PagedVec<u64> = PagedVec::new(0x1000);
-        assert!(vec.capacity() == 0x1000);
-        for i in 0..vec.capacity() {
-            vec.push_within_capacity(i as u64).expect("could not push!");
-        }
-    }
-    #[test]
-    fn test_page_vec_push() {
-        let mut vec: PagedVec<u64> = PagedVec::new(0x1000);
-        assert!(vec.capacity() == 0x1000);
-        for i in 0..0x8000 {
-            vec.push(i as u64);
-        }
-    }
-    #[test]
-    fn test_page_vec_drop() {
-        let mut vec: PagedVec<String> = PagedVec::new(0x1000);
-        assert!(vec.capacity() == 0x1000);
-        for i in 0..vec.capacity() {
-            vec.push_within_capacity("".to_owned())
-                .expect("could not push!");
-        }
-    }
-}
 use std::fmt::{Debug, Formatter};
 impl<T: Debug> Debug for PagedVec<T> {
     fn fmt(&self, f: &mut Formatter<'_>) -> Result<(), std::fmt::Error> {
@@ -376,6 +1338,29 @@ impl<T: PartialEq> PartialEq<Vec<T>> for PagedVec<T> {
         self[..] == other[..]
     }
 }
+impl<T: PartialEq> PartialEq for PagedVec<T> {
+    fn eq(&self, other: &Self) -> bool {
+        self[..] == other[..]
+    }
+}
+impl<T: Eq> Eq for PagedVec<T> {}
+impl<T: PartialOrd> PartialOrd for PagedVec<T> {
+    fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
+        self[..].partial_cmp(&other[..])
+    }
+}
+impl<T: Ord> Ord for PagedVec<T> {
+    fn cmp(&self, other: &Self) -> std::cmp::Ordering {
+        self[..].cmp(&other[..])
+    }
+}
+impl<T: Hash> Hash for PagedVec<T> {
+    /// Hashes the elements of this [`PagedVec`] exactly like a slice of them, so that [`PagedVec`] can be looked up by
+    /// `&[T]` in hash maps.
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self[..].hash(state);
+    }
+}
 impl<T: Clone> Clone for PagedVec<T> {
     fn clone(&self) -> Self {
         let mut cloned = Self::new(self.capacity());
@@ -384,6 +1369,50 @@ impl<T: Clone> Clone for PagedVec<T> {
         }
         cloned
     }
+    /// Overwrites this [`PagedVec`] with clones of elements of `source`, reusing its pages if capacity suffices.
+    /// Elements already present are overwritten in place, which is a single bulk copy for [`Copy`] types.
+    /// # Examples
+    /// ```
+    /// # use memory_pages::*;
+    /// let source:PagedVec<u64> = (0..0x1000).collect();
+    /// let mut snapshot = PagedVec::new(0x1000);
+    /// let ptr = snapshot.as_ptr();
+    /// snapshot.clone_from(&source);
+    /// assert_eq!(snapshot, source);
+    /// assert_eq!(snapshot.as_ptr(), ptr);
+    /// ```
+    fn clone_from(&mut self, source: &Self) {
+        self.truncate(source.len());
+        let (init, tail) = source.split_at(self.len);
+        self.clone_from_slice(init);
+        self.extend_from_slice(tail);
+    }
+}
+impl<T> From<Vec<T>> for PagedVec<T> {
+    /// Moves all elements of `vec` into a new [`PagedVec`], using a single bulk copy.
+    /// # Examples
+    /// ```
+    /// # use memory_pages::*;
+    /// let vec = PagedVec::from(vec![1, 2, 3]);
+    /// assert_eq!(vec, vec![1, 2, 3]);
+    /// ```
+    fn from(mut vec: Vec<T>) -> Self {
+        let mut res = Self::new(vec.len());
+        res.unpoison_spare();
+        unsafe {
+            std::ptr::copy_nonoverlapping(vec.as_ptr(), res.elements(), vec.len());
+            // Elements were moved into `res`, so `vec` must only free its buffer.
+            res.len = vec.len();
+            vec.set_len(0);
+        }
+        res.poison_spare();
+        res
+    }
+}
+impl<T> From<PagedVec<T>> for Vec<T> {
+    fn from(vec: PagedVec<T>) -> Self {
+        vec.into_vec()
+    }
 }
 impl<'a, T> IntoIterator for &'a PagedVec<T> {
     type Item = &'a T;
@@ -392,3 +1421,782 @@ impl<'a, T> IntoIterator for &'a PagedVec<T> {
         self.iter()
     }
 }
+impl<T> IntoIterator for PagedVec<T> {
+    type Item = T;
+    type IntoIter = IntoIter<T>;
+    /// Turns this [`PagedVec`] into an iterator, yielding its elements by value. The pages are released once the iterator
+    /// is dropped.
+    /// # Examples
+    /// ```
+    /// # use memory_pages::*;
+    /// let mut vec = PagedVec::new(0x1000);
+    /// vec.push("a".to_owned());
+    /// vec.push("b".to_owned());
+    /// let strings:Vec<String> = vec.into_iter().collect();
+    /// assert_eq!(strings, ["a", "b"]);
+    /// ```
+    fn into_iter(self) -> IntoIter<T> {
+        IntoIter {
+            vec: self,
+            start: 0,
+        }
+    }
+}
+/// Error returned when capacity of a [`PagedVec`] can't be reserved.
+#[derive(Debug)]
+pub enum TryReserveError {
+    /// Requested capacity exceeds the maximal size of an allocation.
+    CapacityOverflow,
+    /// Kernel refused to allocate memory.
+    AllocError(std::io::Error),
+}
+impl std::fmt::Display for TryReserveError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::CapacityOverflow => write!(f, "capacity overflow"),
+            Self::AllocError(err) => write!(f, "memory allocation failed: {err}"),
+        }
+    }
+}
+impl std::error::Error for TryReserveError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Self::AllocError(err) => Some(err),
+            Self::CapacityOverflow => None,
+        }
+    }
+}
+/// An iterator moving elements out of a [`PagedVec`]. Created using [`PagedVec::into_iter`].
+pub struct IntoIter<T> {
+    /// Elements in `start..vec.len` are yet to be yielded. Elements before `start` were moved out.
+    vec: PagedVec<T>,
+    start: usize,
+}
+impl<T> IntoIter<T> {
+    /// Returns the remaining elements as a slice.
+    /// # Examples
+    /// ```
+    /// # use memory_pages::*;
+    /// let vec:PagedVec<u8> = [1, 2, 3].into_iter().collect();
+    /// let mut iter = vec.into_iter();
+    /// iter.next();
+    /// assert_eq!(iter.as_slice(), [2, 3]);
+    /// ```
+    #[must_use]
+    pub fn as_slice(&self) -> &[T] {
+        &self.vec[self.start..]
+    }
+    fn elements(&self) -> *mut T {
+        self.vec.elements()
+    }
+}
+impl<T> Iterator for IntoIter<T> {
+    type Item = T;
+    fn next(&mut self) -> Option<T> {
+        if self.start == self.vec.len {
+            return None;
+        }
+        // Element at `start` is never accessed again, so it can be moved out.
+        let t = unsafe { std::ptr::read(self.elements().add(self.start)) };
+        self.start += 1;
+        Some(t)
+    }
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        let remaining = self.vec.len - self.start;
+        (remaining, Some(remaining))
+    }
+}
+impl<T> DoubleEndedIterator for IntoIter<T> {
+    fn next_back(&mut self) -> Option<T> {
+        if self.start == self.vec.len {
+            return None;
+        }
+        self.vec.len -= 1;
+        Some(unsafe { std::ptr::read(self.elements().add(self.vec.len)) })
+    }
+}
+impl<T> ExactSizeIterator for IntoIter<T> {}
+impl<T> std::iter::FusedIterator for IntoIter<T> {}
+impl<T> Drop for IntoIter<T> {
+    fn drop(&mut self) {
+        let remaining = std::ptr::slice_from_raw_parts_mut(
+            self.elements().wrapping_add(self.start),
+            self.vec.len - self.start,
+        );
+        // Length is reset first, so that if dropping an element panics, the vec does not drop any elements again.
+        self.vec.len = 0;
+        unsafe { std::ptr::drop_in_place(remaining) };
+    }
+}
+/// An iterator removing a range of elements from a [`PagedVec`]. Created using [`PagedVec::drain`].
+pub struct Drain<'a, T> {
+    vec: &'a mut PagedVec<T>,
+    /// Elements in `next..end` are yet to be yielded.
+    next: usize,
+    end: usize,
+    /// Elements after the drained range, which are shifted down once the iterator is dropped.
+    tail_start: usize,
+    tail_len: usize,
+}
+impl<T> Drain<'_, T> {
+    /// Returns the remaining elements of the range as a slice.
+    #[must_use]
+    pub fn as_slice(&self) -> &[T] {
+        unsafe { std::slice::from_raw_parts(self.elements().add(self.next), self.end - self.next) }
+    }
+    fn elements(&self) -> *mut T {
+        self.vec.elements()
+    }
+    /// Fills the gap between the end of the vec and the tail with elements from `iter`. Returns false if `iter` runs
+    /// out first.
+    fn fill(&mut self, iter: &mut impl Iterator<Item = T>) -> bool {
+        let elements = self.elements();
+        while self.vec.len < self.tail_start {
+            let Some(t) = iter.next() else {
+                return false;
+            };
+            unsafe { std::ptr::write(elements.add(self.vec.len), t) };
+            self.vec.len += 1;
+        }
+        true
+    }
+    /// Moves the tail `additional` elements further back, widening the gap before it.
+    fn move_tail(&mut self, additional: usize) {
+        let tail_end = self.tail_start + self.tail_len;
+        self.vec.reserve(tail_end + additional - self.vec.len);
+        // Reserving poisons everything past the vec, including the tail.
+        self.vec.unpoison_spare();
+        let elements = self.elements();
+        unsafe {
+            std::ptr::copy(
+                elements.add(self.tail_start),
+                elements.add(self.tail_start + additional),
+                self.tail_len,
+            );
+        }
+        self.tail_start += additional;
+    }
+}
+impl<T> Iterator for Drain<'_, T> {
+    type Item = T;
+    fn next(&mut self) -> Option<T> {
+        if self.next == self.end {
+            return None;
+        }
+        let t = unsafe { std::ptr::read(self.elements().add(self.next)) };
+        self.next += 1;
+        Some(t)
+    }
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        let remaining = self.end - self.next;
+        (remaining, Some(remaining))
+    }
+}
+impl<T> DoubleEndedIterator for Drain<'_, T> {
+    fn next_back(&mut self) -> Option<T> {
+        if self.next == self.end {
+            return None;
+        }
+        self.end -= 1;
+        Some(unsafe { std::ptr::read(self.elements().add(self.end)) })
+    }
+}
+impl<T> ExactSizeIterator for Drain<'_, T> {}
+impl<T> std::iter::FusedIterator for Drain<'_, T> {}
+impl<T> Drop for Drain<'_, T> {
+    fn drop(&mut self) {
+        /// Shifts the tail down, even if dropping one of the remaining elements panics.
+        struct MoveTail<'a, 'b, T>(&'a mut Drain<'b, T>);
+        impl<T> Drop for MoveTail<'_, '_, T> {
+            fn drop(&mut self) {
+                let drain = &mut *self.0;
+                let elements = drain.elements();
+                unsafe {
+                    std::ptr::copy(
+                        elements.add(drain.tail_start),
+                        elements.add(drain.vec.len),
+                        drain.tail_len,
+                    );
+                }
+                drain.vec.len += drain.tail_len;
+                drain.vec.poison_spare();
+            }
+        }
+        let remaining = std::ptr::slice_from_raw_parts_mut(
+            self.elements().wrapping_add(self.next),
+            self.end - self.next,
+        );
+        let guard = MoveTail(self);
+        unsafe { std::ptr::drop_in_place(remaining) };
+        drop(guard);
+    }
+}
+/// An iterator removing and yielding elements of a [`PagedVec`] matching a predicate. Created using
+/// [`PagedVec::extract_if`].
+pub struct ExtractIf<'a, T, F: FnMut(&mut T) -> bool> {
+    vec: &'a mut PagedVec<T>,
+    pred: F,
+    /// Elements before `processed` were either moved out, or shifted down by `deleted` elements.
+    processed: usize,
+    deleted: usize,
+    original_len: usize,
+}
+impl<T, F: FnMut(&mut T) -> bool> Iterator for ExtractIf<'_, T, F> {
+    type Item = T;
+    fn next(&mut self) -> Option<T> {
+        let elements = self.vec.elements();
+        while self.processed < self.original_len {
+            let current = unsafe { elements.add(self.processed) };
+            let extract = (self.pred)(unsafe { &mut *current });
+            self.processed += 1;
+            if extract {
+                self.deleted += 1;
+                return Some(unsafe { std::ptr::read(current) });
+            }
+            if self.deleted > 0 {
+                unsafe { std::ptr::copy_nonoverlapping(current, current.sub(self.deleted), 1) };
+            }
+        }
+        None
+    }
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (0, Some(self.original_len - self.processed))
+    }
+}
+impl<T, F: FnMut(&mut T) -> bool> Drop for ExtractIf<'_, T, F> {
+    fn drop(&mut self) {
+        let elements = self.vec.elements();
+        if self.deleted > 0 {
+            unsafe {
+                std::ptr::copy(
+                    elements.add(self.processed),
+                    elements.add(self.processed - self.deleted),
+                    self.original_len - self.processed,
+                );
+            }
+        }
+        self.vec.len = self.original_len - self.deleted;
+        self.vec.poison_spare();
+    }
+}
+/// An iterator removing a range of elements from a [`PagedVec`] and replacing them with elements of another iterator.
+/// Created using [`PagedVec::splice`].
+pub struct Splice<'a, I: Iterator> {
+    drain: Drain<'a, I::Item>,
+    replace_with: I,
+}
+impl<I: Iterator> Iterator for Splice<'_, I> {
+    type Item = I::Item;
+    fn next(&mut self) -> Option<I::Item> {
+        self.drain.next()
+    }
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.drain.size_hint()
+    }
+}
+impl<I: Iterator> DoubleEndedIterator for Splice<'_, I> {
+    fn next_back(&mut self) -> Option<I::Item> {
+        self.drain.next_back()
+    }
+}
+impl<I: Iterator> ExactSizeIterator for Splice<'_, I> {}
+impl<I: Iterator> Drop for Splice<'_, I> {
+    fn drop(&mut self) {
+        self.drain.by_ref().for_each(drop);
+        if self.drain.tail_len == 0 {
+            self.drain.vec.extend(self.replace_with.by_ref());
+            return;
+        }
+        if !self.drain.fill(&mut self.replace_with) {
+            return;
+        }
+        // Lower bound of the size hint is a safe guess of how many more elements there are.
+        let (lower, _) = self.replace_with.size_hint();
+        if lower > 0 {
+            self.drain.move_tail(lower);
+            if !self.drain.fill(&mut self.replace_with) {
+                return;
+            }
+        }
+        let mut rest = self.replace_with.by_ref().collect::<Vec<_>>().into_iter();
+        if rest.len() > 0 {
+            self.drain.move_tail(rest.len());
+            self.drain.fill(&mut rest);
+        }
+        // Dropping the drain moves the tail right after the inserted elements.
+    }
+}
+impl<T> Extend<T> for PagedVec<T> {
+    fn extend<I: IntoIterator<Item = T>>(&mut self, iter: I) {
+        let iter = iter.into_iter();
+        self.reserve(iter.size_hint().0);
+        for t in iter {
+            self.push(t);
+        }
+    }
+}
+impl<'a, T: Copy + 'a> Extend<&'a T> for PagedVec<T> {
+    fn extend<I: IntoIterator<Item = &'a T>>(&mut self, iter: I) {
+        self.extend(iter.into_iter().copied());
+    }
+}
+impl<T> FromIterator<T> for PagedVec<T> {
+    /// Collects `iter` into a [`PagedVec`], with initial capacity based on its size hint.
+    /// # Examples
+    /// ```
+    /// # use memory_pages::*;
+    /// let squares:PagedVec<u64> = (0..0x1000).map(|x| x * x).collect();
+    /// assert_eq!(squares.len(), 0x1000);
+    /// assert_eq!(squares[0xFFF], 0xFFF * 0xFFF);
+    /// ```
+    fn from_iter<I: IntoIterator<Item = T>>(iter: I) -> Self {
+        let iter = iter.into_iter();
+        let mut vec = Self::new(iter.size_hint().0);
+        vec.extend(iter);
+        vec
+    }
+}
+#[cfg(test)]
+mod test {
+    use super::*;
+    #[test]
+    fn test_page_vec() {
+        let mut vec: PagedVec<u64> = PagedVec::new(0x1000);
+        assert!(vec.capacity() == 0x1000);
+        for i in 0..vec.capacity() {
+            vec.push_within_capacity(i as u64).expect("could not push!");
+        }
+    }
+    #[test]
+    fn test_page_vec_push() {
+        let mut vec: PagedVec<u64> = PagedVec::new(0x1000);
+        assert!(vec.capacity() == 0x1000);
+        for i in 0..0x8000 {
+            vec.push(i as u64);
+        }
+    }
+    #[test]
+    fn test_extend_from_slice_grows() {
+        let mut vec: PagedVec<u64> = PagedVec::new(0x10);
+        let data: Vec<u64> = (0..0x3000).collect();
+        vec.extend_from_copy_slice(&data[..0x1000]);
+        vec.extend_from_slice(&data[0x1000..]);
+        assert_eq!(vec, data);
+        assert!(vec.capacity() >= 0x3000);
+    }
+    #[test]
+    fn test_collect_extend() {
+        // Filtering hides the length from the size hint, so the vec must grow while collecting.
+        let mut vec: PagedVec<u32> = (0..0x10000).filter(|x| x % 2 == 0).collect();
+        assert_eq!(vec.len(), 0x8000);
+        vec.extend(&[1, 2]);
+        vec.extend(std::iter::repeat_n(7, 3));
+        assert_eq!(vec[0x8000..], [1, 2, 7, 7, 7]);
+    }
+    #[test]
+    fn test_into_iter_partial() {
+        let counter = std::rc::Rc::new(());
+        let vec: PagedVec<_> = (0..100).map(|_| counter.clone()).collect();
+        let mut iter = vec.into_iter();
+        let first = iter.next().unwrap();
+        let last = iter.next_back().unwrap();
+        assert_eq!(iter.len(), 98);
+        assert_eq!(std::rc::Rc::strong_count(&counter), 101);
+        drop(iter);
+        // Only the elements moved out of the iterator are still alive.
+        assert_eq!(std::rc::Rc::strong_count(&counter), 3);
+        drop((first, last));
+        assert_eq!(std::rc::Rc::strong_count(&counter), 1);
+    }
+    #[test]
+    fn test_drain_both_ends() {
+        let mut vec: PagedVec<String> = (0..10).map(|i| i.to_string()).collect();
+        let mut drain = vec.drain(2..=7);
+        assert_eq!(drain.next().unwrap(), "2");
+        assert_eq!(drain.next_back().unwrap(), "7");
+        assert_eq!(drain.as_slice(), ["3", "4", "5", "6"]);
+        drop(drain);
+        assert_eq!(vec, ["0", "1", "8", "9"].map(String::from).to_vec());
+        // Leaking the iterator leaks the drained elements and the tail, but keeps the vec usable.
+        let mut vec: PagedVec<u32> = (0..10).collect();
+        std::mem::forget(vec.drain(1..2));
+        assert_eq!(vec, vec![0]);
+        vec.push(1);
+        assert_eq!(vec, vec![0, 1]);
+    }
+    #[test]
+    fn test_retain_panic() {
+        let counter = std::rc::Rc::new(());
+        let mut vec: PagedVec<_> = (0..10).map(|i| (i, counter.clone())).collect();
+        let res = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+            vec.retain(|(i, _)| {
+                assert_ne!(*i, 6, "predicate panicked");
+                i % 2 == 0
+            });
+        }));
+        assert!(res.is_err());
+        // Elements before the panic were filtered, the rest was kept as is.
+        let kept: Vec<i32> = vec.iter().map(|(i, _)| *i).collect();
+        assert_eq!(kept, [0, 2, 4, 6, 7, 8, 9]);
+        assert_eq!(std::rc::Rc::strong_count(&counter), 8);
+    }
+    #[test]
+    fn test_truncate_and_decommit() {
+        let mut vec: PagedVec<u8> = PagedVec::new(0x3000);
+        vec.extend_from_copy_slice(&[0xAA; 0x3000]);
+        vec.truncate_and_decommit(0x1001);
+        assert!(vec.iter().all(|b| *b == 0xAA));
+        // Decommitted pages read as zeroes once used again.
+        vec.unpoison_spare();
+        let data = vec.data.as_ref().unwrap();
+        assert!(data.get(0x1001..0x2000).unwrap().iter().all(|b| *b == 0xAA));
+        assert!(data.get(0x2000..).unwrap().iter().all(|b| *b == 0));
+    }
+    #[test]
+    fn test_split_off_pages() {
+        let mut vec: PagedVec<String> = PagedVec::new(0x400);
+        vec.extend((0..0x300).map(|i| i.to_string()));
+        // 512 `String`s take up exactly 3 pages, so pages are split without copying.
+        let at = 512;
+        assert_eq!(
+            (at * std::mem::size_of::<String>()) % crate::page_math::PAGE_SIZE,
+            0
+        );
+        let mut tail = vec.split_off(at);
+        assert_eq!(vec.len(), at);
+        assert_eq!(vec.capacity(), at);
+        assert_eq!(tail[0], at.to_string());
+        tail.push("end".to_owned());
+        vec.push("next".to_owned());
+        assert_eq!(tail.len(), 0x300 - at + 1);
+        assert_eq!(vec[at], "next");
+    }
+    #[test]
+    fn test_append_remap() {
+        let mut vec: PagedVec<u64> = (0..0x200).collect();
+        let mut other: PagedVec<u64> = (0x200..0x40200).collect();
+        let capacity = other.capacity();
+        vec.append(&mut other);
+        assert_eq!(vec.len(), 0x40200);
+        assert!(vec.iter().enumerate().all(|(i, x)| i as u64 == *x));
+        assert!(other.is_empty());
+        assert_eq!(other.capacity(), capacity);
+        other.push(1);
+        vec.push(2);
+        assert_eq!(vec[0x40200], 2);
+    }
+    #[test]
+    fn test_append_to_file() {
+        let path =
+            std::env::temp_dir().join(format!("memory_pages_vec_append_{}", std::process::id()));
+        let file = std::fs::OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(&path)
+            .unwrap();
+        let pages = Pages::map_file(&file, 0x1000).unwrap();
+        let mut vec = PagedVec::from_pages(pages, 0);
+        vec.extend_from_copy_slice(&[1; 0x1000]);
+        let mut other = PagedVec::new(0x20_0000);
+        other.extend_from_copy_slice(&[2; 0x20_0000]);
+        vec.append(&mut other);
+        let (pages, len) = vec.into_pages();
+        assert_eq!(len, 0x20_1000);
+        pages.flush(crate::FlushMode::Sync).unwrap();
+        drop(pages);
+        let contents = std::fs::read(&path).unwrap();
+        assert!(contents[..0x1000].iter().all(|b| *b == 1));
+        assert!(contents[0x1000..0x20_1000].iter().all(|b| *b == 2));
+        std::fs::remove_file(&path).unwrap();
+    }
+    #[test]
+    fn test_resize_drops() {
+        let mut vec = PagedVec::new(0x10);
+        vec.resize(0x2000, String::from("a"));
+        assert_eq!(vec.len(), 0x2000);
+        assert!(vec.iter().all(|s| s == "a"));
+        vec.resize_with(3, String::new);
+        assert_eq!(vec, ["a"; 3].map(String::from).to_vec());
+        vec.resize_with(5, || String::from("b"));
+        assert_eq!(vec[4], "b");
+    }
+    #[test]
+    fn test_shrink_then_grow() {
+        let mut vec: PagedVec<u64> = (0..0x10000).collect();
+        vec.truncate(0x300);
+        vec.shrink_to_fit();
+        assert_eq!(vec.capacity(), 0x400);
+        vec.shrink_to(0x10000);
+        assert_eq!(vec.capacity(), 0x400);
+        vec.extend(0x300..0x1000);
+        assert!(vec.iter().enumerate().all(|(i, x)| i as u64 == *x));
+        vec.clear();
+        vec.shrink_to_fit();
+        assert_eq!(vec.capacity(), 0x200);
+    }
+    #[test]
+    fn test_spare_capacity_read() {
+        use std::io::Read;
+        let mut vec: PagedVec<u8> = PagedVec::new(0x1000);
+        vec.push(0xFF);
+        let src = [1_u8; 0x2000];
+        let mut reader = &src[..];
+        while vec.len() < 0x2001 {
+            vec.reserve(0x100);
+            let spare = vec.spare_capacity_mut();
+            let read = reader
+                .read(unsafe { &mut *(spare as *mut [MaybeUninit<u8>] as *mut [u8]) })
+                .unwrap();
+            unsafe { vec.set_len(vec.len() + read) };
+        }
+        assert_eq!(vec[0], 0xFF);
+        assert!(vec[1..].iter().all(|b| *b == 1));
+    }
+    #[test]
+    fn test_vec_round_trip() {
+        let vec: Vec<String> = (0..0x1000).map(|i| i.to_string()).collect();
+        let paged = PagedVec::from(vec.clone());
+        assert_eq!(paged, vec);
+        let back: Vec<String> = paged.into();
+        assert_eq!(back, vec);
+        assert!(PagedVec::<u8>::from(Vec::new()).is_empty());
+    }
+    #[test]
+    fn test_compare_hash() {
+        use std::collections::{BTreeSet, HashSet};
+        let a: PagedVec<u32> = (0..3).collect();
+        let b: PagedVec<u32> = (0..4).collect();
+        assert!(a < b);
+        assert_eq!(a, a.clone());
+        let sorted: BTreeSet<_> = [b.clone(), a.clone()].into_iter().collect();
+        assert_eq!(sorted.first(), Some(&a));
+        let set: HashSet<PagedVec<u32>> = [a, b].into_iter().collect();
+        assert!(set.contains(&[0, 1, 2][..]));
+    }
+    #[test]
+    fn test_empty_no_pages() {
+        let mut vec: PagedVec<String> = PagedVec::default();
+        assert!(vec.data.is_none());
+        assert_eq!(vec.pop(), None);
+        vec.truncate_and_decommit(0);
+        vec.clear_decommit();
+        vec.shrink_to_fit();
+        vec.advise_use_seq();
+        assert!(vec.drain(..).next().is_none());
+        assert!(vec.split_off(0).data.is_none());
+        let mut other = PagedVec::new_empty();
+        other.push(String::from("a"));
+        vec.append(&mut other);
+        assert_eq!(vec, vec![String::from("a")]);
+        let mut vec: PagedVec<u8> = PagedVec::new_empty();
+        vec.extend_from_copy_slice(&[1, 2]);
+        assert_eq!(vec, vec![1, 2]);
+        assert!(PagedVec::<u8>::new_empty().into_iter().next().is_none());
+    }
+    #[test]
+    fn test_try_reserve_refused() {
+        let mut vec: PagedVec<u64> = (0..0x10).collect();
+        assert!(matches!(
+            vec.try_reserve(usize::MAX / 4),
+            Err(TryReserveError::CapacityOverflow)
+        ));
+        // More than the whole virtual address space of current 64 bit CPUs.
+        #[cfg(target_pointer_width = "64")]
+        assert!(matches!(
+            vec.try_reserve_exact(1 << 58),
+            Err(TryReserveError::AllocError(_))
+        ));
+        assert_eq!(vec.capacity(), 0x200);
+        assert!(vec.iter().enumerate().all(|(i, x)| i as u64 == *x));
+        vec.try_push(0x10).unwrap();
+        assert_eq!(vec[0x10], 0x10);
+    }
+    #[test]
+    fn test_pop_clear_drop_counts() {
+        use std::rc::Rc;
+        let counter = Rc::new(());
+        let mut vec: PagedVec<Rc<()>> = (0..0x100).map(|_| counter.clone()).collect();
+        let popped = vec.pop().unwrap();
+        assert_eq!(Rc::strong_count(&counter), 0x101);
+        drop(popped);
+        vec.truncate(0x80);
+        assert_eq!(Rc::strong_count(&counter), 0x81);
+        vec.clear();
+        assert_eq!(Rc::strong_count(&counter), 1);
+        vec.extend((0..0x10).map(|_| counter.clone()));
+        drop(vec);
+        assert_eq!(Rc::strong_count(&counter), 1);
+    }
+    #[test]
+    fn test_decommit_unused_spare() {
+        let mut vec: PagedVec<u8> = PagedVec::new(0x4000);
+        vec.resize(0x1800, 1);
+        for byte in vec.spare_capacity_mut() {
+            byte.write(2);
+        }
+        vec.decommit_unused();
+        let spare = vec.spare_capacity_mut();
+        // Partially used page keeps its contents, whole pages past it read as zeroes on Linux.
+        assert!(spare[..0x800]
+            .iter()
+            .all(|byte| unsafe { byte.assume_init() } == 2));
+        #[cfg(target_os = "linux")]
+        assert!(spare[0x800..]
+            .iter()
+            .all(|byte| unsafe { byte.assume_init() } == 0));
+        assert!(vec.iter().all(|byte| *byte == 1));
+    }
+    #[test]
+    fn test_extend_from_fn_panic() {
+        use std::rc::Rc;
+        let counter = Rc::new(());
+        let mut vec: PagedVec<Rc<()>> = PagedVec::new_empty();
+        let res = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+            vec.extend_from_fn(0x1000, |i| {
+                assert!(i < 0x100);
+                counter.clone()
+            })
+        }));
+        assert!(res.is_err());
+        assert_eq!(vec.len(), 0x100);
+        assert_eq!(Rc::strong_count(&counter), 0x101);
+        drop(vec);
+        assert_eq!(Rc::strong_count(&counter), 1);
+    }
+    #[test]
+    fn test_page_chunks_mut_unaligned() {
+        let mut vec: PagedVec<[u8; 3]> = PagedVec::new(0x2000);
+        vec.resize(0x1000, [0; 3]);
+        for (i, chunk) in vec.page_chunks_mut().enumerate() {
+            chunk.fill([i as u8; 3]);
+        }
+        assert_eq!(vec.page_chunks().count(), 3);
+        // 0x556 elements start in the first page, the last of them straddling into the second one.
+        assert_eq!(vec[0x555], [0; 3]);
+        assert_eq!(vec[0x556], [1; 3]);
+        assert_eq!(vec[0xFFF], [2; 3]);
+    }
+    #[test]
+    fn test_splice_grow_shrink() {
+        let strings = |range: Range<u32>| range.map(|i| i.to_string()).collect::<Vec<_>>();
+        let mut vec: PagedVec<String> = strings(0..0x100).into_iter().collect();
+        // Unknown length, so the tail has to be moved using a temporary buffer.
+        let replacement = strings(0x1000..0x1400).into_iter().filter(|_| true);
+        drop(vec.splice(0x10..0x20, replacement));
+        assert_eq!(vec.len(), 0x100 - 0x10 + 0x400);
+        let mut expected = strings(0..0x10);
+        expected.extend(strings(0x1000..0x1400));
+        expected.extend(strings(0x20..0x100));
+        assert_eq!(vec, expected);
+        // Fewer elements than removed.
+        let removed: Vec<String> = vec.splice(1..0x20, strings(7..8)).rev().collect();
+        assert_eq!(removed.len(), 0x1F);
+        expected.splice(1..0x20, strings(7..8));
+        assert_eq!(vec, expected);
+        // No tail.
+        let len = vec.len();
+        vec.splice(len - 1.., strings(0..3));
+        expected.splice(len - 1.., strings(0..3));
+        assert_eq!(vec, expected);
+    }
+    #[test]
+    fn test_pages_round_trip_file() {
+        let path =
+            std::env::temp_dir().join(format!("memory_pages_vec_from_file_{}", std::process::id()));
+        let file = std::fs::OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(&path)
+            .unwrap();
+        let pages = Pages::map_file(&file, 0x1000).unwrap();
+        let mut vec = PagedVec::from_pages(pages, 0);
+        vec.extend_from_copy_slice(&[7; 0x1800]);
+        let (pages, len) = vec.into_pages();
+        assert_eq!(len, 0x1800);
+        pages.flush(crate::FlushMode::Sync).unwrap();
+        drop(pages);
+        let contents = std::fs::read(&path).unwrap();
+        assert!(contents.len() >= 0x1800);
+        assert!(contents[..0x1800].iter().all(|b| *b == 7));
+        std::fs::remove_file(&path).unwrap();
+        let (pages, len) = PagedVec::new_empty().into_pages();
+        assert_eq!((pages.len(), len), (crate::page_math::PAGE_SIZE, 0));
+    }
+    #[test]
+    fn test_clone_from() {
+        let source: PagedVec<String> = (0..0x100).map(|i| i.to_string()).collect();
+        let mut dest: PagedVec<String> = (0..0x200).map(|i| (i * 2).to_string()).collect();
+        let ptr = dest.as_ptr();
+        dest.clone_from(&source);
+        assert_eq!(dest, source);
+        assert_eq!(dest.as_ptr(), ptr);
+        let mut dest: PagedVec<String> = (0..0x10).map(|i| i.to_string()).collect();
+        dest.clone_from(&source);
+        assert_eq!(dest, source);
+        let mut dest: PagedVec<u32> = PagedVec::new(0x4000);
+        let ptr = dest.as_ptr();
+        let source: PagedVec<u32> = (0..0x4000).collect();
+        dest.clone_from(&source);
+        dest.clone_from(&source);
+        assert_eq!(dest, source);
+        assert_eq!(dest.as_ptr(), ptr);
+    }
+    #[test]
+    fn test_capacity_overflow() {
+        let mut vec: PagedVec<u64> = (0..0x10).collect();
+        // Would wrap around to a small size in bytes without checks.
+        assert!(matches!(
+            vec.try_reserve_exact(usize::MAX / 8 + 2),
+            Err(TryReserveError::CapacityOverflow)
+        ));
+        assert!(matches!(
+            vec.try_reserve(usize::MAX),
+            Err(TryReserveError::CapacityOverflow)
+        ));
+        assert!(matches!(
+            vec.try_reserve(isize::MAX as usize / 8),
+            Err(TryReserveError::CapacityOverflow)
+        ));
+        assert!(PagedVec::<u64>::bytes_for(isize::MAX as usize / 8).is_ok());
+        assert!(PagedVec::<u64>::bytes_for(isize::MAX as usize / 8 + 1).is_err());
+        vec.shrink_to(usize::MAX);
+        assert_eq!(vec, (0..0x10).collect::<Vec<_>>());
+        assert!(std::panic::catch_unwind(|| PagedVec::<u32>::new(usize::MAX / 2)).is_err());
+        let overflowed = std::panic::catch_unwind(move || vec.reserve(usize::MAX)).unwrap_err();
+        assert_eq!(
+            overflowed.downcast_ref::<&str>(),
+            Some(&"Capacity of PagedVec overflowed!")
+        );
+    }
+    #[test]
+    fn test_extract_if() {
+        let mut vec: PagedVec<String> = (0..0x1000).map(|i| i.to_string()).collect();
+        let mut extracted = vec.extract_if(|s| s.ends_with('7'));
+        assert_eq!(extracted.next().as_deref(), Some("7"));
+        assert_eq!(extracted.next().as_deref(), Some("17"));
+        // Elements past the last yielded one are kept.
+        drop(extracted);
+        assert_eq!(vec.len(), 0x1000 - 2);
+        assert_eq!(vec[7], "8");
+        assert_eq!(vec[0x1000 - 3], "4095");
+        let extracted: Vec<String> = vec.extract_if(|s| s.len() < 4).collect();
+        assert_eq!(extracted.len(), 1000 - 2);
+        assert!(vec.iter().all(|s| s.len() == 4));
+        assert_eq!(vec.len(), 0x1000 - 1000);
+        assert_eq!(vec.extract_if(|_| true).count(), 0x1000 - 1000);
+        assert!(vec.is_empty());
+    }
+    #[test]
+    fn test_page_vec_drop() {
+        let mut vec: PagedVec<String> = PagedVec::new(0x1000);
+        assert!(vec.capacity() == 0x1000);
+        for _ in 0..vec.capacity() {
+            vec.push_within_capacity("".to_owned())
+                .expect("could not push!");
+        }
+    }
+}
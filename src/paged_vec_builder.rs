@@ -0,0 +1,291 @@
+use crate::*;
+use std::io;
+/// How capacity of a [`PagedVec`] grows when it runs out of space.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Default)]
+pub enum GrowthPolicy {
+    /// Capacity is doubled.
+    #[default]
+    Double,
+    /// Capacity is increased by half. Wastes less memory than [`GrowthPolicy::Double`], at the cost of more
+    /// reallocations.
+    OneAndHalf,
+    /// Capacity is increased by a fixed number of elements.
+    Linear(usize),
+    /// Capacity is increased only as much as needed.
+    Exact,
+}
+impl GrowthPolicy {
+    /// Returns capacity a [`PagedVec`] with capacity `cap` should grow to.
+    pub(crate) fn next_cap(self, cap: usize) -> usize {
+        match self {
+            Self::Double => cap.saturating_mul(2),
+            Self::OneAndHalf => cap.saturating_add(cap / 2),
+            Self::Linear(step) => cap.saturating_add(step),
+            Self::Exact => cap,
+        }
+    }
+}
+/// Expected access pattern of a [`PagedVec`], passed to the kernel as advice.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Access {
+    Normal,
+    Sequential,
+    Random,
+}
+/// Configures how memory of a [`PagedVec`] is allocated, and creates it. All settings are applied right after the
+/// memory is mapped, in the order required for them to take effect.
+/// # Examples
+/// ```
+/// # use memory_pages::*;
+/// let mut vec:PagedVec<u64> = PagedVec::builder()
+///     .capacity(0x10_000)
+///     .advise_use_seq()
+///     .prefault(true)
+///     .growth(GrowthPolicy::OneAndHalf)
+///     .build()
+///     .unwrap();
+/// vec.push(1);
+/// assert!(vec.capacity() >= 0x10_000);
+/// ```
+#[derive(Clone, Debug)]
+#[must_use]
+pub struct PagedVecBuilder<T> {
+    capacity: usize,
+    access: Access,
+    prefault: bool,
+    numa_node: Option<u32>,
+    huge_pages: bool,
+    growth: GrowthPolicy,
+    pd: PhantomData<T>,
+}
+impl<T> Default for PagedVecBuilder<T> {
+    fn default() -> Self {
+        Self {
+            capacity: 0,
+            access: Access::Normal,
+            prefault: false,
+            numa_node: None,
+            huge_pages: false,
+            growth: GrowthPolicy::default(),
+            pd: PhantomData,
+        }
+    }
+}
+impl<T> PagedVecBuilder<T> {
+    /// Creates a new builder, with settings matching [`PagedVec::new`].
+    pub fn new() -> Self {
+        Self::default()
+    }
+    /// Sets initial capacity, in elements. At least one page is always allocated.
+    pub fn capacity(mut self, capacity: usize) -> Self {
+        self.capacity = capacity;
+        self
+    }
+    /// Advises the kernel that the vec is going to be accessed sequentially. See [`Pages::advise_use_seq`].
+    pub fn advise_use_seq(mut self) -> Self {
+        self.access = Access::Sequential;
+        self
+    }
+    /// Advises the kernel that the vec is going to be accessed randomly. See [`Pages::advise_use_rnd`].
+    pub fn advise_use_rnd(mut self) -> Self {
+        self.access = Access::Random;
+        self
+    }
+    /// If true, all pages of the initial capacity are backed by physical memory up front, so that first accesses to
+    /// them don't fault. Useful for latency-sensitive code, at the cost of slower allocation.
+    pub fn prefault(mut self, prefault: bool) -> Self {
+        self.prefault = prefault;
+        self
+    }
+    /// Makes memory of the vec preferably come from NUMA node `node`. Only supported on Linux, on x86, x86_64, ARM,
+    /// AArch64, RISC-V and LoongArch.
+    pub fn numa_node(mut self, node: u32) -> Self {
+        self.numa_node = Some(node);
+        self
+    }
+    /// If true, the kernel is advised to back the vec using transparent huge pages, which reduces TLB misses for large
+    /// vecs accessed randomly. Only supported on Linux.
+    pub fn huge_pages(mut self, huge_pages: bool) -> Self {
+        self.huge_pages = huge_pages;
+        self
+    }
+    /// Sets how capacity grows once it runs out.
+    pub fn growth(mut self, growth: GrowthPolicy) -> Self {
+        self.growth = growth;
+        self
+    }
+    /// Allocates memory with the configured settings, and creates a [`PagedVec`] using it.
+    /// # Errors
+    /// Returns an error if memory can't be allocated, or if NUMA placement or huge pages were requested and the platform
+    /// or kernel does not support them.
+    pub fn build(self) -> io::Result<PagedVec<T>> {
+        let bytes = PagedVec::<T>::bytes_for(self.capacity)
+            .map_err(|err| io::Error::new(io::ErrorKind::InvalidInput, err))?
+            .max(1);
+        let mut data: Pages<AllowRead, AllowWrite, DenyExec> =
+            Pages::try_new_native(bytes, MapOptions::default())?;
+        // Placement and page size must be decided before any page is faulted in.
+        if let Some(node) = self.numa_node {
+            bind_to_node(data.get_ptr_unchecked(), data.len(), node)?;
+        }
+        if self.huge_pages {
+            advise_huge_pages(&data)?;
+        }
+        match self.access {
+            Access::Normal => (),
+            Access::Sequential => data.advise_use_seq(),
+            Access::Random => data.advise_use_rnd(),
+        }
+        if self.prefault {
+            prefault(&mut data);
+        }
+        Ok(PagedVec::with_pages(data, self.growth))
+    }
+}
+impl<T> PagedVec<T> {
+    /// Creates a [`PagedVecBuilder`], for configuring how memory of a new [`PagedVec`] is allocated.
+    pub fn builder() -> PagedVecBuilder<T> {
+        PagedVecBuilder::new()
+    }
+}
+/// Makes memory of the `len` bytes starting at `ptr` preferably come from NUMA node `node`.
+#[cfg(target_os = "linux")]
+pub(crate) fn bind_to_node(ptr: *mut u8, len: usize, node: u32) -> io::Result<()> {
+    use std::ffi::{c_long, c_ulong};
+    extern "C" {
+        fn syscall(number: c_long, ...) -> c_long;
+    }
+    #[cfg(target_arch = "x86_64")]
+    const SYS_MBIND: Option<c_long> = Some(237);
+    #[cfg(target_arch = "x86")]
+    const SYS_MBIND: Option<c_long> = Some(274);
+    #[cfg(target_arch = "arm")]
+    const SYS_MBIND: Option<c_long> = Some(319);
+    // Architectures using the generic system call table.
+    #[cfg(any(
+        target_arch = "aarch64",
+        target_arch = "riscv64",
+        target_arch = "loongarch64"
+    ))]
+    const SYS_MBIND: Option<c_long> = Some(235);
+    #[cfg(not(any(
+        target_arch = "x86_64",
+        target_arch = "x86",
+        target_arch = "arm",
+        target_arch = "aarch64",
+        target_arch = "riscv64",
+        target_arch = "loongarch64"
+    )))]
+    const SYS_MBIND: Option<c_long> = None;
+    const MPOL_PREFERRED: c_long = 1;
+    const MASK_BITS: usize = 1024;
+    let Some(sys_mbind) = SYS_MBIND else {
+        return Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            "NUMA placement is not supported on this architecture",
+        ));
+    };
+    let node = node as usize;
+    if node >= MASK_BITS {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "NUMA node index out of range",
+        ));
+    }
+    let mut mask = [0 as c_ulong; MASK_BITS / c_ulong::BITS as usize];
+    mask[node / c_ulong::BITS as usize] |= 1 << (node % c_ulong::BITS as usize);
+    let res = unsafe {
+        syscall(
+            sys_mbind,
+            ptr,
+            len,
+            MPOL_PREFERRED,
+            mask.as_ptr(),
+            // Kernel ignores the last bit of the mask.
+            MASK_BITS + 1,
+            0 as c_ulong,
+        )
+    };
+    if res != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+#[cfg(not(target_os = "linux"))]
+pub(crate) fn bind_to_node(_ptr: *mut u8, _len: usize, _node: u32) -> io::Result<()> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "NUMA placement is not supported on this platform",
+    ))
+}
+#[cfg(target_os = "linux")]
+pub(crate) fn advise_huge_pages(data: &Pages<AllowRead, AllowWrite, DenyExec>) -> io::Result<()> {
+    const MADV_HUGEPAGE: c_int = 14;
+    if unsafe { madvise(data.get_ptr_unchecked().cast(), data.len(), MADV_HUGEPAGE) } != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+#[cfg(not(target_os = "linux"))]
+pub(crate) fn advise_huge_pages(_data: &Pages<AllowRead, AllowWrite, DenyExec>) -> io::Result<()> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "huge pages are not supported on this platform",
+    ))
+}
+/// Backs all of `data` with physical memory.
+fn prefault(data: &mut Pages<AllowRead, AllowWrite, DenyExec>) {
+    #[cfg(target_os = "linux")]
+    {
+        const MADV_POPULATE_WRITE: c_int = 23;
+        if unsafe {
+            madvise(
+                data.get_ptr_unchecked().cast(),
+                data.len(),
+                MADV_POPULATE_WRITE,
+            )
+        } == 0
+        {
+            return;
+        }
+    }
+    // Older kernels and other platforms fault pages in on first write.
+    let ptr = data.get_ptr_unchecked();
+    for offset in (0..data.len()).step_by(page_math::PAGE_SIZE) {
+        unsafe { ptr.add(offset).write_volatile(0) };
+    }
+}
+#[cfg(test)]
+mod test {
+    use super::*;
+    #[test]
+    fn test_builder_growth() {
+        let mut vec: PagedVec<u8> = PagedVec::builder()
+            .capacity(0x1000)
+            .growth(GrowthPolicy::Linear(0x3000))
+            .prefault(true)
+            .advise_use_rnd()
+            .build()
+            .unwrap();
+        vec.extend_from_copy_slice(&[1; 0x1001]);
+        assert_eq!(vec.capacity(), 0x4000);
+        let mut vec: PagedVec<u8> = PagedVec::builder()
+            .growth(GrowthPolicy::Exact)
+            .build()
+            .unwrap();
+        vec.extend_from_copy_slice(&[1; 0x1001]);
+        assert_eq!(vec.capacity(), 0x1001);
+    }
+    #[test]
+    #[cfg(target_os = "linux")]
+    fn test_builder_numa() {
+        // Node 0 exists even on machines without NUMA.
+        let mut vec: PagedVec<u64> = PagedVec::builder()
+            .capacity(0x1000)
+            .numa_node(0)
+            .build()
+            .unwrap();
+        vec.push(1);
+        assert!(PagedVec::<u8>::builder().numa_node(4096).build().is_err());
+    }
+}
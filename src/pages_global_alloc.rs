@@ -0,0 +1,170 @@
+use crate::page_math::{align_up, PAGE_SIZE};
+use crate::*;
+use std::alloc::{GlobalAlloc, Layout, System};
+pub(crate) type RawPages = Pages<AllowRead, AllowWrite, DenyExec>;
+/// Takes back ownership of anonymous pages spanning `size` bytes, previously handed out by [`pages_into_raw`].
+pub(crate) unsafe fn pages_from_raw(ptr: NonNull<u8>, size: usize) -> RawPages {
+    Pages {
+        ptr,
+        len: align_up(size),
+        backing: Backing::Anonymous,
+        read: PhantomData,
+        write: PhantomData,
+        exec: PhantomData,
+    }
+}
+/// Hands out ownership of `pages`, returning the memory they span.
+pub(crate) fn pages_into_raw(pages: RawPages) -> NonNull<[u8]> {
+    let memory = NonNull::slice_from_raw_parts(pages.ptr, pages.len);
+    std::mem::forget(pages);
+    memory
+}
+/// A [`GlobalAlloc`] requesting large allocations directly from the kernel, as anonymous pages, and forwarding small
+/// ones to another allocator, [`System`] by default. Registering it as the `#[global_allocator]` makes every large
+/// buffer in a program return its memory to the kernel as soon as it is freed, and lets large vectors on Linux grow by
+/// remapping their pages instead of copying them.
+///
+/// Whether an allocation is backed by pages depends only on its layout, so that memory is always freed the same way it
+/// was allocated. Allocations aligned to more than a page always use the fallback allocator.
+/// # Examples
+/// ```
+/// # use memory_pages::*;
+/// #[global_allocator]
+/// static ALLOC:PagesGlobalAlloc = PagesGlobalAlloc::new();
+/// // Backed by pages.
+/// let big = vec![0_u8; 0x100_000];
+/// // Backed by the system allocator.
+/// let small = vec![0_u8; 0x10];
+/// assert_eq!(big.len() + small.len(), 0x100_010);
+/// ```
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct PagesGlobalAlloc<A: GlobalAlloc = System> {
+    /// Allocations of at least this many bytes are backed by pages.
+    threshold: usize,
+    fallback: A,
+}
+impl PagesGlobalAlloc<System> {
+    /// Size of allocations, in bytes, above which [`Self::new`] backs them with pages.
+    pub const DEFAULT_THRESHOLD: usize = 0x10_000;
+    /// Creates a [`PagesGlobalAlloc`] backing allocations of at least [`Self::DEFAULT_THRESHOLD`] bytes with pages, and
+    /// forwarding the rest to [`System`].
+    #[must_use]
+    pub const fn new() -> Self {
+        Self::with_threshold(Self::DEFAULT_THRESHOLD)
+    }
+    /// Creates a [`PagesGlobalAlloc`] backing allocations of at least `threshold` bytes with pages, and forwarding the
+    /// rest to [`System`]. Since each such allocation takes up at least a whole page, thresholds much smaller than a
+    /// page waste memory.
+    #[must_use]
+    pub const fn with_threshold(threshold: usize) -> Self {
+        Self::with_fallback(threshold, System)
+    }
+}
+impl Default for PagesGlobalAlloc<System> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+impl<A: GlobalAlloc> PagesGlobalAlloc<A> {
+    /// Creates a [`PagesGlobalAlloc`] backing allocations of at least `threshold` bytes with pages, and forwarding the
+    /// rest to `fallback`.
+    #[must_use]
+    pub const fn with_fallback(threshold: usize, fallback: A) -> Self {
+        Self {
+            threshold,
+            fallback,
+        }
+    }
+    /// Returns the size of allocations, in bytes, above which they are backed by pages.
+    #[must_use]
+    pub const fn threshold(&self) -> usize {
+        self.threshold
+    }
+    fn uses_pages(&self, layout: Layout) -> bool {
+        layout.size() != 0 && layout.size() >= self.threshold && layout.align() <= PAGE_SIZE
+    }
+}
+// Allocating pages never calls back into the global allocator, so it is safe to use as one.
+unsafe impl<A: GlobalAlloc> GlobalAlloc for PagesGlobalAlloc<A> {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        if !self.uses_pages(layout) {
+            return self.fallback.alloc(layout);
+        }
+        RawPages::try_new_native(layout.size(), MapOptions::default())
+            .map_or(std::ptr::null_mut(), |pages| {
+                pages_into_raw(pages).cast::<u8>().as_ptr()
+            })
+    }
+    unsafe fn alloc_zeroed(&self, layout: Layout) -> *mut u8 {
+        if !self.uses_pages(layout) {
+            return self.fallback.alloc_zeroed(layout);
+        }
+        // Fresh pages are always zeroed by the kernel.
+        self.alloc(layout)
+    }
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        if !self.uses_pages(layout) {
+            return self.fallback.dealloc(ptr, layout);
+        }
+        drop(pages_from_raw(NonNull::new_unchecked(ptr), layout.size()));
+    }
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        let new_layout = Layout::from_size_align_unchecked(new_size, layout.align());
+        match (self.uses_pages(layout), self.uses_pages(new_layout)) {
+            (true, true) => {
+                let mut pages = pages_from_raw(NonNull::new_unchecked(ptr), layout.size());
+                let res = pages.try_resize(align_up(new_size));
+                // On error pages are left unchanged, and still owned by the caller.
+                let memory = pages_into_raw(pages).cast::<u8>().as_ptr();
+                if res.is_ok() {
+                    memory
+                } else {
+                    std::ptr::null_mut()
+                }
+            }
+            (false, false) => self.fallback.realloc(ptr, layout, new_size),
+            // Memory moves between the fallback allocator and pages, so it has to be copied.
+            _ => {
+                let memory = self.alloc(new_layout);
+                if !memory.is_null() {
+                    std::ptr::copy_nonoverlapping(ptr, memory, layout.size().min(new_size));
+                    self.dealloc(ptr, layout);
+                }
+                memory
+            }
+        }
+    }
+}
+#[cfg(test)]
+mod test {
+    use super::*;
+    #[test]
+    fn test_global_alloc_realloc() {
+        let alloc = PagesGlobalAlloc::with_threshold(PAGE_SIZE);
+        let small = Layout::from_size_align(0x100, 8).unwrap();
+        unsafe {
+            let mut ptr = alloc.alloc(small);
+            ptr.write_bytes(1, small.size());
+            // Moves into pages, then grows them, and finally moves back to the system allocator.
+            let mut size = small.size();
+            for new_size in [0x2000, 0x100_000, 0x10] {
+                ptr = alloc.realloc(ptr, Layout::from_size_align(size, 8).unwrap(), new_size);
+                assert!(!ptr.is_null());
+                if new_size >= PAGE_SIZE {
+                    assert!((ptr as usize).is_multiple_of(PAGE_SIZE));
+                }
+                size = new_size;
+            }
+            assert!(std::slice::from_raw_parts(ptr, size)
+                .iter()
+                .all(|byte| *byte == 1));
+            alloc.dealloc(ptr, Layout::from_size_align(size, 8).unwrap());
+            let zeroed = Layout::from_size_align(0x10_000, 0x1000).unwrap();
+            let ptr = alloc.alloc_zeroed(zeroed);
+            assert!(std::slice::from_raw_parts(ptr, zeroed.size())
+                .iter()
+                .all(|byte| *byte == 0));
+            alloc.dealloc(ptr, zeroed);
+        }
+    }
+}
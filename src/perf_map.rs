@@ -0,0 +1,116 @@
+use crate::*;
+use std::fmt::{Debug, Formatter};
+use std::fs::File;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Mutex, OnceLock};
+/// Perf map enabled with [`PerfMap::enable_global`].
+static GLOBAL: OnceLock<PerfMap> = OnceLock::new();
+/// A perf map file, which tells Linux `perf` the names of jitted functions, so samples inside of them are attributed to
+/// those functions instead of `[unknown]`. Each line of the file holds the address, size and name of one function.
+///
+/// `perf` looks for the map at `/tmp/perf-<pid>.map`. Once [`Self::enable_global`] is called, each
+/// [`CodeBuffer::finalize`] records all functions named with [`CodeBuffer::name_function`] in that map.
+/// # Examples
+/// ```
+/// # use memory_pages::*;
+/// let map = PerfMap::enable_global().unwrap();
+/// let mut code = CodeBuffer::new(0x1000);
+/// let entry = code.new_label();
+/// code.bind(entry);
+/// code.name_function(entry, "jitted_nop");
+/// // x86_64 assembly instruction `RET`
+/// code.emit_u8(0xC3);
+/// // Recorded in the map as `<address> 1 jitted_nop`.
+/// let code = code.finalize().unwrap();
+/// # let contents = std::fs::read_to_string(map.path()).unwrap();
+/// # assert!(contents.contains(" 1 jitted_nop\n"));
+/// ```
+pub struct PerfMap {
+    path: PathBuf,
+    file: Mutex<File>,
+}
+impl PerfMap {
+    /// Creates a perf map at `path`, or appends to it if it already exists.
+    /// # Errors
+    /// Returns an error if the file can't be opened.
+    pub fn open(path: impl AsRef<Path>) -> io::Result<Self> {
+        let path = path.as_ref().to_path_buf();
+        let file = File::options().create(true).append(true).open(&path)?;
+        Ok(Self {
+            path,
+            file: Mutex::new(file),
+        })
+    }
+    /// Enables recording of functions finalized by all [`CodeBuffer`]s in `/tmp/perf-<pid>.map`, where `perf` looks for
+    /// them, and returns that map. Does nothing if recording is already enabled.
+    /// # Errors
+    /// Returns an error if the file can't be opened.
+    pub fn enable_global() -> io::Result<&'static PerfMap> {
+        if let Some(map) = GLOBAL.get() {
+            return Ok(map);
+        }
+        let map = Self::open(format!("/tmp/perf-{}.map", std::process::id()))?;
+        // Another thread may have enabled recording in the meantime, in which case its map is used.
+        Ok(GLOBAL.get_or_init(|| map))
+    }
+    /// Returns the map enabled with [`Self::enable_global`], or `None` if recording is not enabled.
+    #[must_use]
+    pub fn global() -> Option<&'static PerfMap> {
+        GLOBAL.get()
+    }
+    /// Returns the path of this [`PerfMap`].
+    #[must_use]
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+    /// Records `symbols` in this [`PerfMap`].
+    /// # Errors
+    /// Returns an error if writing to the file fails.
+    pub fn record(&self, symbols: &[JitSymbol]) -> io::Result<()> {
+        let mut lines = String::new();
+        for symbol in symbols {
+            use std::fmt::Write;
+            // Names can't span multiple lines, since each line is a separate entry.
+            let name = symbol.name.replace('\n', " ");
+            let _ = writeln!(
+                lines,
+                "{:x} {:x} {name}",
+                symbol.address as usize, symbol.size
+            );
+        }
+        // Lines are written at once, so entries written by many threads never interleave.
+        self.file
+            .lock()
+            .unwrap_or_else(std::sync::PoisonError::into_inner)
+            .write_all(lines.as_bytes())
+    }
+}
+impl Debug for PerfMap {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("PerfMap").field("path", &self.path).finish()
+    }
+}
+#[cfg(test)]
+mod test {
+    use super::*;
+    #[test]
+    fn test_perf_map_record() {
+        let path = std::env::temp_dir().join(format!("perf-map-test-{}.map", std::process::id()));
+        let map = PerfMap::open(&path).unwrap();
+        let mut code = CodeBuffer::new(0x1000);
+        let (first, second) = (code.new_label(), code.new_label());
+        code.bind(first);
+        code.name_function(first, "first");
+        code.emit_bytes(&[0x90; 0x10]);
+        code.bind(second);
+        code.name_function(second, "second\nline");
+        code.emit_bytes(&[0x90; 0x21]);
+        let code = code.finalize().unwrap();
+        map.record(&code.symbols()).unwrap();
+        let base = code.code().as_ptr() as usize;
+        let expected = format!("{base:x} 10 first\n{:x} 21 second line\n", base + 0x10);
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), expected);
+        std::fs::remove_file(path).unwrap();
+    }
+}
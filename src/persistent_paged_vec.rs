@@ -0,0 +1,313 @@
+use crate::page_math::{align_up, PAGE_SIZE};
+use crate::*;
+use std::fmt::{Debug, Formatter};
+use std::fs::{File, OpenOptions};
+use std::io;
+use std::ops::{Deref, DerefMut};
+use std::path::Path;
+/// Marks files holding a [`PersistentPagedVec`], and the version of their layout.
+const MAGIC: u64 = u64::from_le_bytes(*b"MPPVEC\0\x01");
+const HEADER_LEN: usize = 24;
+/// Offset of the first element. The header occupies the first page, so elements are always page aligned.
+const DATA_START: usize = PAGE_SIZE;
+/// Initial size of the file.
+const INITIAL_SIZE: usize = 0x10_000;
+/// A [`Vec`]-like type stored in a memory mapped file, so that its elements outlive the process. Pushing an element
+/// writes it straight into the mapping, and [`Self::flush`] makes all elements pushed so far durable. Reopening the
+/// file with [`Self::open`] recovers the length from a small header at its beginning, which is only updated by
+/// [`Self::flush`], so elements pushed after the last flush are discarded, even if some of them reached the file.
+/// Elements modified in place are written back by the kernel at any time, so after a crash they may hold either
+/// their old or new values.
+///
+/// Elements are stored as-is, so they must be [`Pod`], and files are only portable between machines with the same
+/// endianness.
+/// # Examples
+/// ```
+/// # use memory_pages::*;
+/// # let path = std::env::temp_dir().join("memory_pages_doc_persistent_vec");
+/// let mut samples: PersistentPagedVec<f64> = PersistentPagedVec::create(&path).unwrap();
+/// samples.extend_from_slice(&[1.0, 2.0, 3.0]).unwrap();
+/// samples.push(4.0).unwrap();
+/// samples.flush().unwrap();
+/// drop(samples);
+/// let samples: PersistentPagedVec<f64> = PersistentPagedVec::open(&path).unwrap();
+/// assert_eq!(*samples, [1.0, 2.0, 3.0, 4.0]);
+/// # drop(samples);
+/// # std::fs::remove_file(&path).unwrap();
+/// ```
+pub struct PersistentPagedVec<T: Pod> {
+    pages: Pages<AllowRead, AllowWrite, DenyExec>,
+    len: usize,
+    /// Length recorded in the header, as of the last flush.
+    durable_len: usize,
+    pd: PhantomData<T>,
+}
+impl<T: Pod> PersistentPagedVec<T> {
+    /// Creates a new, empty [`PersistentPagedVec`] at `path`, replacing any file already there.
+    /// # Errors
+    /// Returns an error if `T` is zero-sized, or if the file can't be created or mapped.
+    pub fn create(path: impl AsRef<Path>) -> io::Result<Self> {
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(path)?;
+        Self::init(&file)
+    }
+    /// Opens the [`PersistentPagedVec`] at `path`, recovering all elements which were flushed before it was last
+    /// closed, or before a crash. Creates a new, empty one if there is no file at `path`.
+    /// # Errors
+    /// Returns an error if `T` is zero-sized, if the file can't be opened or mapped, or
+    /// [`io::ErrorKind::InvalidData`] if it does not hold a [`PersistentPagedVec`] of elements of the size of `T`.
+    pub fn open(path: impl AsRef<Path>) -> io::Result<Self> {
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(path)?;
+        let file_len = file.metadata()?.len();
+        if file_len == 0 {
+            return Self::init(&file);
+        }
+        Self::check_size()?;
+        let invalid = |msg: &str| io::Error::new(io::ErrorKind::InvalidData, msg);
+        let file_len = usize::try_from(file_len).map_err(|_| invalid("file too large"))?;
+        if file_len < DATA_START {
+            return Err(invalid("file truncated"));
+        }
+        // Bytes past the end of the file in its last page are never written back, so files always span whole pages.
+        let pages = Pages::map_file(&file, align_up(file_len))?;
+        let header: [u64; 3] = unsafe { pages.get_ptr_unchecked().cast::<[u64; 3]>().read() };
+        let [magic, size, len] = header.map(u64::from_le);
+        if magic != MAGIC {
+            return Err(invalid("file does not hold a PersistentPagedVec"));
+        }
+        if size != std::mem::size_of::<T>() as u64 {
+            return Err(invalid("element size mismatch"));
+        }
+        let len = usize::try_from(len)
+            .ok()
+            .filter(|len| {
+                len.checked_mul(std::mem::size_of::<T>())
+                    .is_some_and(|bytes| bytes <= file_len - DATA_START)
+            })
+            .ok_or_else(|| invalid("length points past the end of the file"))?;
+        Ok(Self {
+            pages,
+            len,
+            durable_len: len,
+            pd: PhantomData,
+        })
+    }
+    fn check_size() -> io::Result<()> {
+        if std::mem::size_of::<T>() == 0 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "PersistentPagedVec can't hold zero-sized types",
+            ));
+        }
+        Ok(())
+    }
+    /// Maps `file` and writes an empty header into it.
+    fn init(file: &File) -> io::Result<Self> {
+        Self::check_size()?;
+        let mut vec = Self {
+            pages: Pages::map_file(file, INITIAL_SIZE)?,
+            len: 0,
+            durable_len: 0,
+            pd: PhantomData,
+        };
+        vec.write_header()?;
+        Ok(vec)
+    }
+    /// Returns the number of elements, including ones not flushed yet.
+    #[must_use]
+    pub fn len(&self) -> usize {
+        self.len
+    }
+    /// Checks if this [`PersistentPagedVec`] has no elements.
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+    /// Returns the number of elements which are durable, and would survive a crash.
+    #[must_use]
+    pub fn durable_len(&self) -> usize {
+        self.durable_len
+    }
+    /// Returns the number of elements this [`PersistentPagedVec`] can hold without growing its file.
+    #[must_use]
+    pub fn capacity(&self) -> usize {
+        (self.pages.len() - DATA_START) / std::mem::size_of::<T>()
+    }
+    /// Reserves capacity for at least `additional` more elements, growing the file if needed.
+    /// # Errors
+    /// Returns an error if the capacity overflows, or if the file can't be grown.
+    pub fn reserve(&mut self, additional: usize) -> io::Result<()> {
+        if self.capacity() - self.len >= additional {
+            return Ok(());
+        }
+        let bytes = self
+            .len
+            .checked_add(additional)
+            .and_then(|len| len.checked_mul(std::mem::size_of::<T>()))
+            .and_then(|bytes| bytes.checked_add(DATA_START))
+            .filter(|bytes| isize::try_from(*bytes).is_ok())
+            .ok_or_else(|| {
+                io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    "Capacity of PersistentPagedVec overflowed!",
+                )
+            })?;
+        // The file must span the whole mapping, or elements in its last page would never be written back.
+        self.pages
+            .try_resize(align_up(bytes.max(self.pages.len().saturating_mul(2))))
+    }
+    /// Appends `t` to the end of this [`PersistentPagedVec`], growing the file if needed. The element only becomes
+    /// durable once the vec is [`Self::flush`]ed.
+    /// # Errors
+    /// Returns an error if the file can't be grown.
+    pub fn push(&mut self, t: T) -> io::Result<()> {
+        self.extend_from_slice(std::slice::from_ref(&t))
+    }
+    /// Appends all elements of `other` to the end of this [`PersistentPagedVec`], growing the file if needed.
+    /// # Errors
+    /// Returns an error if the file can't be grown.
+    pub fn extend_from_slice(&mut self, other: &[T]) -> io::Result<()> {
+        self.reserve(other.len())?;
+        unsafe {
+            self.data_ptr()
+                .add(self.len)
+                .copy_from_nonoverlapping(other.as_ptr(), other.len());
+        }
+        self.len += other.len();
+        Ok(())
+    }
+    /// Removes the last element and returns it, or `None` if this [`PersistentPagedVec`] is empty.
+    pub fn pop(&mut self) -> Option<T> {
+        self.len = self.len.checked_sub(1)?;
+        Some(unsafe { self.data_ptr().add(self.len).read() })
+    }
+    /// Shortens this [`PersistentPagedVec`] to `len` elements. Has no effect if it is already shorter. Like all other
+    /// changes, it is only durable once the vec is [`Self::flush`]ed.
+    pub fn truncate(&mut self, len: usize) {
+        self.len = self.len.min(len);
+    }
+    /// Removes all elements. The file keeps its size.
+    pub fn clear(&mut self) {
+        self.len = 0;
+    }
+    /// Makes all elements pushed so far, and all changes to existing ones, durable, waiting until they are written into
+    /// the file.
+    /// # Errors
+    /// Returns an error if the kernel fails to write the data back. Elements pushed since the last successful flush
+    /// are then not guaranteed to be durable.
+    pub fn flush(&mut self) -> io::Result<()> {
+        // Elements must reach the file before the header recording them. Only dirty pages are written back, so
+        // elements which were not modified since the last flush cost nothing.
+        self.pages.flush_range(
+            DATA_START,
+            self.len * std::mem::size_of::<T>(),
+            FlushMode::Sync,
+        )?;
+        self.write_header()
+    }
+    fn write_header(&mut self) -> io::Result<()> {
+        let header = [MAGIC, std::mem::size_of::<T>() as u64, self.len as u64].map(u64::to_le);
+        unsafe {
+            self.pages
+                .get_ptr_unchecked()
+                .cast::<[u64; 3]>()
+                .write(header)
+        };
+        self.pages.flush_range(0, HEADER_LEN, FlushMode::Sync)?;
+        self.durable_len = self.len;
+        Ok(())
+    }
+    fn data_ptr(&self) -> *mut T {
+        unsafe { self.pages.get_ptr_unchecked().add(DATA_START).cast() }
+    }
+}
+impl<T: Pod> Deref for PersistentPagedVec<T> {
+    type Target = [T];
+    fn deref(&self) -> &[T] {
+        unsafe { std::slice::from_raw_parts(self.data_ptr(), self.len) }
+    }
+}
+impl<T: Pod> DerefMut for PersistentPagedVec<T> {
+    fn deref_mut(&mut self) -> &mut [T] {
+        unsafe { std::slice::from_raw_parts_mut(self.data_ptr(), self.len) }
+    }
+}
+impl<T: Pod> Drop for PersistentPagedVec<T> {
+    fn drop(&mut self) {
+        // Errors can't be reported from `drop`, call `PersistentPagedVec::flush` to handle them.
+        let _ = self.flush();
+    }
+}
+impl<T: Pod + Debug> Debug for PersistentPagedVec<T> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_list().entries(self.iter()).finish()
+    }
+}
+#[cfg(test)]
+mod test {
+    use super::*;
+    #[test]
+    fn test_persistent_vec_recovery() {
+        let path = std::env::temp_dir().join(format!("memory_pages_pvec_{}", std::process::id()));
+        let mut vec: PersistentPagedVec<u64> = PersistentPagedVec::create(&path).unwrap();
+        for i in 0..0x10_000 {
+            vec.push(i).unwrap();
+        }
+        assert!(vec.capacity() >= 0x10_000);
+        vec.flush().unwrap();
+        vec.push(7).unwrap();
+        // Simulates a crash, by restoring the file as it was before the last element was flushed.
+        let crashed = std::fs::read(&path).unwrap();
+        drop(vec);
+        std::fs::write(&path, &crashed).unwrap();
+        let mut vec: PersistentPagedVec<u64> = PersistentPagedVec::open(&path).unwrap();
+        assert_eq!(vec.len(), 0x10_000);
+        assert!(vec.iter().enumerate().all(|(i, x)| *x == i as u64));
+        vec.truncate(0x10);
+        vec[3] = 33;
+        assert_eq!(vec.pop(), Some(15));
+        drop(vec);
+        let vec: PersistentPagedVec<u64> = PersistentPagedVec::open(&path).unwrap();
+        assert_eq!(vec.len(), 15);
+        assert_eq!(vec[3], 33);
+        drop(vec);
+        assert_eq!(
+            PersistentPagedVec::<u32>::open(&path).unwrap_err().kind(),
+            io::ErrorKind::InvalidData
+        );
+        std::fs::remove_file(&path).unwrap();
+    }
+    #[test]
+    fn test_persistent_vec_unaligned_growth() {
+        let path = std::env::temp_dir().join(format!(
+            "memory_pages_pvec_unaligned_{}",
+            std::process::id()
+        ));
+        let mut vec: PersistentPagedVec<u8> = PersistentPagedVec::create(&path).unwrap();
+        vec.extend_from_slice(&vec![1; 0x30001]).unwrap();
+        while vec.len() < vec.capacity() {
+            vec.push(2).unwrap();
+        }
+        let len = vec.len();
+        vec.flush().unwrap();
+        drop(vec);
+        assert_eq!(
+            std::fs::metadata(&path).unwrap().len(),
+            (DATA_START + len) as u64
+        );
+        let vec: PersistentPagedVec<u8> = PersistentPagedVec::open(&path).unwrap();
+        assert_eq!(vec.len(), len);
+        assert_eq!(vec[len - 1], 2);
+        drop(vec);
+        std::fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,46 @@
+use crate::*;
+use std::num::{Saturating, Wrapping};
+/// Marks "plain old data": types for which any sequence of bytes is a valid value, and which have no padding, like
+/// integers, floats or arrays of them. Such values can be stored in files and read back as-is, so [`Pod`] types can
+/// be kept in [`PersistentPagedVec`].
+/// # Safety
+/// Implementing this trait for a type which has padding bytes, or for which some bit patterns are invalid(like `bool`,
+/// `char` or references) is undefined behaviour. Pointers are allowed, but meaningless once read by another process.
+/// For structs, it is enough to be `#[repr(C)]`, have no padding, and for all fields to be [`Pod`].
+/// # Examples
+/// ```
+/// # use memory_pages::*;
+/// #[derive(Clone, Copy)]
+/// #[repr(C)]
+/// struct Sample{time:u64, value:f64}
+/// // No padding, and all fields are `Pod`.
+/// unsafe impl Zeroable for Sample{}
+/// unsafe impl Pod for Sample{}
+/// ```
+pub unsafe trait Pod: Zeroable + Copy + 'static {}
+macro_rules! impl_pod {
+    ($($t:ty),*) => {
+        $(unsafe impl Pod for $t {})*
+    };
+}
+impl_pod!(
+    u8,
+    u16,
+    u32,
+    u64,
+    u128,
+    usize,
+    i8,
+    i16,
+    i32,
+    i64,
+    i128,
+    isize,
+    f32,
+    f64,
+    ()
+);
+unsafe impl<T: Pod, const N: usize> Pod for [T; N] {}
+unsafe impl<T: Pod> Pod for Wrapping<T> {}
+unsafe impl<T: Pod> Pod for Saturating<T> {}
+unsafe impl<T: ?Sized + 'static> Pod for PhantomData<T> {}
@@ -0,0 +1,124 @@
+use crate::*;
+use std::ops::Range;
+/// Size of a cache line, which is the granularity of prefetches.
+const CACHE_LINE: usize = 64;
+/// How long prefetched data is expected to be in use, which decides into which caches it is loaded.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Locality {
+    /// Data is going to be used once, and should pollute caches as little as possible.
+    NonTemporal,
+    /// Data is going to be used a few times, and only needs to be kept in the outermost cache.
+    Low,
+    /// Data is going to be used for a while, and should be kept in all caches but the innermost one.
+    Medium,
+    /// Data is going to be used a lot, and should be kept in all caches.
+    High,
+}
+/// Hints the CPU to load the cache line containing `ptr`. Prefetches never fault, so `ptr` does not need to be readable.
+#[inline(always)]
+#[allow(unused_variables)]
+fn prefetch(ptr: *const u8, locality: Locality) {
+    #[cfg(any(target_arch = "x86_64", target_arch = "x86"))]
+    unsafe {
+        #[cfg(target_arch = "x86")]
+        use std::arch::x86::*;
+        #[cfg(target_arch = "x86_64")]
+        use std::arch::x86_64::*;
+        let ptr = ptr.cast::<i8>();
+        match locality {
+            Locality::NonTemporal => _mm_prefetch::<_MM_HINT_NTA>(ptr),
+            Locality::Low => _mm_prefetch::<_MM_HINT_T2>(ptr),
+            Locality::Medium => _mm_prefetch::<_MM_HINT_T1>(ptr),
+            Locality::High => _mm_prefetch::<_MM_HINT_T0>(ptr),
+        }
+    }
+    #[cfg(target_arch = "aarch64")]
+    unsafe {
+        use std::arch::asm;
+        match locality {
+            Locality::NonTemporal => {
+                asm!("prfm pldl1strm, [{0}]", in(reg) ptr, options(nostack, readonly))
+            }
+            Locality::Low => asm!("prfm pldl3keep, [{0}]", in(reg) ptr, options(nostack, readonly)),
+            Locality::Medium => {
+                asm!("prfm pldl2keep, [{0}]", in(reg) ptr, options(nostack, readonly))
+            }
+            Locality::High => {
+                asm!("prfm pldl1keep, [{0}]", in(reg) ptr, options(nostack, readonly))
+            }
+        }
+    }
+}
+/// Prefetches each cache line of `len` bytes starting at `ptr`, optionally advising the kernel to read in pages backing
+/// them too.
+pub(crate) fn prefetch_bytes(ptr: *const u8, len: usize, locality: Locality, advise_kernel: bool) {
+    if len == 0 {
+        return;
+    }
+    #[cfg(target_family = "unix")]
+    if advise_kernel {
+        use crate::page_math::{align_down, align_up};
+        let start = align_down(ptr as usize);
+        let end = align_up(ptr as usize + len);
+        const POSIX_MADV_WILLNEED: c_int = 3;
+        unsafe { posix_madvise(start as *mut c_void, end - start, POSIX_MADV_WILLNEED) };
+    }
+    #[cfg(not(target_family = "unix"))]
+    let _ = advise_kernel;
+    let first = ptr as usize & !(CACHE_LINE - 1);
+    for line in (first..ptr as usize + len).step_by(CACHE_LINE) {
+        prefetch(line as *const u8, locality);
+    }
+}
+impl<W: WritePremisionMarker, E: ExecPremisionMarker> Pages<AllowRead, W, E> {
+    /// Hints the CPU to load bytes in `range` into its caches, ahead of them being accessed. Useful for workloads with
+    /// access patterns hardware prefetchers can't predict, like pointer-chasing. If `advise_kernel` is true, kernel is
+    /// also advised to read in pages backing `range`, which helps if they may be swapped out or not yet loaded from a
+    /// file. Prefetching is only a hint, and never changes contents of this [`Pages`].
+    /// # Beware
+    /// Usage hints are part of fine-grain memory access adjustments. It is *NOT* always beneficial to use, in
+    /// contrary, it very often slows programs down. Before using those hints, test each usage.
+    /// # Panics
+    /// Panics if `range` is out of bounds of this [`Pages`].
+    /// # Examples
+    /// ```
+    /// # use memory_pages::*;
+    /// let memory:Pages<AllowRead,AllowWrite,DenyExec> = Pages::new(0x10000);
+    /// memory.prefetch_range(0x1000..0x1800, Locality::High, false);
+    /// assert_eq!(memory[0x1234], 0);
+    /// ```
+    pub fn prefetch_range(&self, range: Range<usize>, locality: Locality, advise_kernel: bool) {
+        assert!(
+            range.start <= range.end && range.end <= self.len,
+            "range {range:?} out of bounds of pages of length {}!",
+            self.len
+        );
+        let ptr = unsafe { self.ptr.as_ptr().add(range.start) };
+        prefetch_bytes(ptr, range.end - range.start, locality, advise_kernel);
+    }
+}
+#[cfg(test)]
+mod test {
+    use super::*;
+    #[test]
+    fn test_prefetch_unaligned() {
+        let mut memory: Pages<AllowRead, AllowWrite, DenyExec> = Pages::new(0x3000);
+        memory[0x2FFF] = 3;
+        for locality in [
+            Locality::NonTemporal,
+            Locality::Low,
+            Locality::Medium,
+            Locality::High,
+        ] {
+            memory.prefetch_range(0x0FFF..0x3000, locality, true);
+            memory.prefetch_range(0x10..0x10, locality, true);
+        }
+        assert_eq!(memory[0x2FFF], 3);
+    }
+    #[test]
+    #[should_panic]
+    fn test_prefetch_out_of_bounds() {
+        let memory: Pages<AllowRead, AllowWrite, DenyExec> = Pages::new(0x1000);
+        memory.prefetch_range(0..0x1001, Locality::High, false);
+    }
+}
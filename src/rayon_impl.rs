@@ -0,0 +1,115 @@
+//! [`rayon`] support for [`PagedVec`], enabled by the `rayon` feature.
+use crate::*;
+use rayon::iter::{
+    FromParallelIterator, IndexedParallelIterator, IntoParallelIterator, ParallelExtend,
+    ParallelIterator,
+};
+use std::collections::LinkedList;
+use std::sync::atomic::{AtomicUsize, Ordering};
+/// Pointer to spare capacity of a [`PagedVec`], which worker threads write disjoint elements through.
+struct SparePtr<T>(*mut T);
+unsafe impl<T: Send> Send for SparePtr<T> {}
+unsafe impl<T: Send> Sync for SparePtr<T> {}
+impl<T> SparePtr<T> {
+    /// Writes `t` at `index`, which no other thread may write to.
+    unsafe fn write(&self, index: usize, t: T) {
+        self.0.add(index).write(t);
+    }
+}
+impl<T: Send> PagedVec<T> {
+    /// Appends all elements of an indexed parallel iterator. Capacity is reserved once, and then each worker thread
+    /// writes its elements directly into their final place, without any intermediate buffers.
+    /// # Panics
+    /// Panics if the iterator yields a different number of elements than it reported. If a worker thread panics, the
+    /// panic is propagated, and elements written so far are leaked.
+    /// # Examples
+    /// ```
+    /// # use memory_pages::*;
+    /// use rayon::prelude::*;
+    /// let mut squares:PagedVec<u64> = PagedVec::new_empty();
+    /// squares.par_extend_indexed((0..0x10_000_u32).into_par_iter().map(|x| u64::from(x) * u64::from(x)));
+    /// assert_eq!(squares[0x1234], 0x1234 * 0x1234);
+    /// ```
+    pub fn par_extend_indexed<I: IndexedParallelIterator<Item = T>>(&mut self, iter: I) {
+        let additional = iter.len();
+        self.reserve(additional);
+        self.unpoison_spare();
+        let spare = SparePtr(unsafe { self.as_mut_ptr().add(self.len()) });
+        let written = AtomicUsize::new(0);
+        iter.enumerate().for_each(|(index, t)| {
+            assert!(
+                index < additional,
+                "parallel iterator yielded more than {additional} elements!"
+            );
+            unsafe { spare.write(index, t) };
+            written.fetch_add(1, Ordering::Relaxed);
+        });
+        let written = written.into_inner();
+        assert_eq!(
+            written, additional,
+            "parallel iterator yielded {written} elements instead of {additional}!"
+        );
+        unsafe { self.set_len(self.len() + additional) };
+    }
+}
+impl<T: Send> ParallelExtend<T> for PagedVec<T> {
+    /// Appends all elements of a parallel iterator. Each worker thread collects its elements separately, and they are
+    /// then moved into this [`PagedVec`] in order, using one bulk copy per each thread's chunk. For indexed parallel
+    /// iterators, [`PagedVec::par_extend_indexed`] avoids the intermediate buffers.
+    /// # Examples
+    /// ```
+    /// # use memory_pages::*;
+    /// use rayon::prelude::*;
+    /// let mut primes:PagedVec<u32> = PagedVec::new_empty();
+    /// primes.par_extend((2..1000_u32).into_par_iter().filter(|x| (2..*x).all(|d| x % d != 0)));
+    /// assert_eq!(primes[..5], [2, 3, 5, 7, 11]);
+    /// ```
+    fn par_extend<I: IntoParallelIterator<Item = T>>(&mut self, par_iter: I) {
+        let chunks: LinkedList<Vec<T>> = par_iter
+            .into_par_iter()
+            .fold(Vec::new, |mut chunk, t| {
+                chunk.push(t);
+                chunk
+            })
+            .map(|chunk| LinkedList::from([chunk]))
+            .reduce(LinkedList::new, |mut front, mut back| {
+                front.append(&mut back);
+                front
+            });
+        self.reserve(chunks.iter().map(Vec::len).sum());
+        for mut chunk in chunks {
+            self.unpoison_spare();
+            unsafe {
+                std::ptr::copy_nonoverlapping(
+                    chunk.as_ptr(),
+                    self.as_mut_ptr().add(self.len()),
+                    chunk.len(),
+                );
+                // Elements were moved into this vec, so `chunk` must only free its buffer.
+                self.set_len(self.len() + chunk.len());
+                chunk.set_len(0);
+            }
+        }
+    }
+}
+impl<T: Send> FromParallelIterator<T> for PagedVec<T> {
+    fn from_par_iter<I: IntoParallelIterator<Item = T>>(par_iter: I) -> Self {
+        let mut vec = Self::new_empty();
+        vec.par_extend(par_iter);
+        vec
+    }
+}
+#[cfg(test)]
+mod test {
+    use super::*;
+    #[test]
+    fn test_par_extend() {
+        let mut vec: PagedVec<String> = (0..0x10).map(|i| i.to_string()).collect();
+        vec.par_extend_indexed((0x10..0x10_000).into_par_iter().map(|i| i.to_string()));
+        vec.par_extend((0x10_000..0x20_000).into_par_iter().map(|i| i.to_string()));
+        assert_eq!(vec.len(), 0x20_000);
+        assert!(vec.iter().enumerate().all(|(i, s)| *s == i.to_string()));
+        let collected: PagedVec<u32> = (0..0x1000).into_par_iter().filter(|x| x % 2 == 0).collect();
+        assert_eq!(collected, (0..0x1000).step_by(2).collect::<Vec<_>>());
+    }
+}
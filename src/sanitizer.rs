@@ -0,0 +1,56 @@
+//! Integration with memory error detectors. With the `asan` feature, unused parts of allocations are poisoned using
+//! AddressSanitizer, and with the `valgrind` feature they are marked as inaccessible using Valgrind client requests, so
+//! that accesses to them are reported instead of silently succeeding. Without those features, all functions here are NOPs.
+#[cfg(feature = "asan")]
+extern "C" {
+    fn __asan_poison_memory_region(addr: *const std::ffi::c_void, size: usize);
+    fn __asan_unpoison_memory_region(addr: *const std::ffi::c_void, size: usize);
+}
+#[cfg(all(feature = "valgrind", target_arch = "x86_64"))]
+mod valgrind {
+    const MEMCHECK_BASE: usize = ((b'M' as usize) << 24) | ((b'C' as usize) << 16);
+    pub(super) const MAKE_MEM_NOACCESS: usize = MEMCHECK_BASE;
+    pub(super) const MAKE_MEM_UNDEFINED: usize = MEMCHECK_BASE + 1;
+    /// Issues a Valgrind client request. Outside of Valgrind the magic instruction sequence does nothing.
+    pub(super) fn request(request: usize, addr: usize, len: usize) {
+        let args: [usize; 6] = [request, addr, len, 0, 0, 0];
+        let mut res: usize = 0;
+        unsafe {
+            std::arch::asm!(
+                "rol rdi, 3",
+                "rol rdi, 13",
+                "rol rdi, 61",
+                "rol rdi, 51",
+                "xchg rbx, rbx",
+                in("rax") args.as_ptr(),
+                inout("rdx") res,
+                options(nostack),
+            );
+        }
+        let _ = res;
+    }
+}
+/// Marks `len` bytes starting at `ptr` as unused, so that any access to them is reported.
+#[allow(unused_variables)]
+pub(crate) fn poison(ptr: *const u8, len: usize) {
+    #[cfg(feature = "asan")]
+    if len != 0 {
+        unsafe { __asan_poison_memory_region(ptr.cast(), len) };
+    }
+    #[cfg(all(feature = "valgrind", target_arch = "x86_64"))]
+    if len != 0 {
+        valgrind::request(valgrind::MAKE_MEM_NOACCESS, ptr as usize, len);
+    }
+}
+/// Marks `len` bytes starting at `ptr` as usable again.
+#[allow(unused_variables)]
+pub(crate) fn unpoison(ptr: *const u8, len: usize) {
+    #[cfg(feature = "asan")]
+    if len != 0 {
+        unsafe { __asan_unpoison_memory_region(ptr.cast(), len) };
+    }
+    #[cfg(all(feature = "valgrind", target_arch = "x86_64"))]
+    if len != 0 {
+        valgrind::request(valgrind::MAKE_MEM_UNDEFINED, ptr as usize, len);
+    }
+}
@@ -0,0 +1,92 @@
+//! Utilities for handling secrets, like keys, stored inside [`crate::Pages`]. Secrets should be kept in inaccessible
+//! [`crate::Pages`], which only become readable or writable while a guard returned by [`crate::Pages::read_guard`] or
+//! [`crate::Pages::write_guard`] is alive.
+use crate::*;
+use std::sync::atomic::{compiler_fence, Ordering};
+/// Compares `a` and `b` in time depending only on their lengths, and not on their contents, so the comparison does not
+/// leak where they differ.
+/// # Examples
+/// ```
+/// # use memory_pages::secure::*;
+/// assert!(ct_eq(b"secret", b"secret"));
+/// assert!(!ct_eq(b"secret", b"Secret"));
+/// assert!(!ct_eq(b"secret", b"secrets"));
+/// ```
+#[must_use]
+pub fn ct_eq(a: &[u8], b: &[u8]) -> bool {
+    if a.len() != b.len() {
+        return false;
+    }
+    let mut diff = 0;
+    for (a, b) in a.iter().zip(b) {
+        // Hides every byte from the optimizer, so it can't notice that `diff` only ever grows, and exit early once it is
+        // non-zero. `black_box` is only a best-effort hint, but all major backends honour it.
+        diff |= std::hint::black_box(a ^ b);
+    }
+    diff == 0
+}
+/// Overwrites `data` with zeroes. Unlike a plain `fill(0)`, this can't be optimized away, even if `data` is never
+/// read again.
+/// # Examples
+/// ```
+/// # use memory_pages::secure::*;
+/// let mut key = *b"secret";
+/// wipe(&mut key);
+/// assert_eq!(key, [0; 6]);
+/// ```
+pub fn wipe(data: &mut [u8]) {
+    for byte in data.iter_mut() {
+        unsafe { std::ptr::write_volatile(byte, 0) };
+    }
+    compiler_fence(Ordering::SeqCst);
+}
+impl Pages<DenyRead, DenyWrite, DenyExec> {
+    /// Compares contents of this [`Pages`] with `other` in constant time, by temporarily making them readable. See
+    /// [`ct_eq`].
+    /// # Panics
+    /// Panics if kernel can't/refuses to change protection of this [`Pages`].
+    /// # Examples
+    /// ```
+    /// # use memory_pages::*;
+    /// let mut key:Pages<DenyRead,DenyWrite,DenyExec> = Pages::new(0x1000);
+    /// key.write_guard()[0] = 1;
+    /// let mut expected = [0; 0x1000];
+    /// assert!(!key.ct_eq(&expected));
+    /// expected[0] = 1;
+    /// assert!(key.ct_eq(&expected));
+    /// ```
+    #[must_use]
+    pub fn ct_eq(&mut self, other: &[u8]) -> bool {
+        ct_eq(&self.read_guard(), other)
+    }
+    /// Overwrites this [`Pages`] with zeroes, in a way which can't be optimized away, by temporarily making them
+    /// writable. See [`wipe`].
+    /// # Panics
+    /// Panics if kernel can't/refuses to change protection of this [`Pages`].
+    /// # Examples
+    /// ```
+    /// # use memory_pages::*;
+    /// let mut key:Pages<DenyRead,DenyWrite,DenyExec> = Pages::new(0x1000);
+    /// key.write_guard()[..4].copy_from_slice(b"key!");
+    /// key.wipe();
+    /// assert!(key.read_guard().iter().all(|byte| *byte == 0));
+    /// ```
+    pub fn wipe(&mut self) {
+        wipe(&mut self.write_guard());
+    }
+}
+#[cfg(test)]
+mod test {
+    use super::*;
+    #[test]
+    fn test_ct_eq_all_positions() {
+        let a = [0xAA_u8; 64];
+        for i in 0..a.len() {
+            let mut b = a;
+            b[i] ^= 1;
+            assert!(!ct_eq(&a, &b));
+        }
+        assert!(ct_eq(&a, &a.clone()));
+        assert!(ct_eq(&[], &[]));
+    }
+}
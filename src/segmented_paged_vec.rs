@@ -0,0 +1,247 @@
+use crate::page_math::align_up;
+use crate::*;
+use std::fmt::{Debug, Formatter};
+use std::ops::{Index, IndexMut};
+/// A vector storing its elements in a list of equally sized, separately allocated segments of pages. Growing it only
+/// ever allocates a new segment, so appending never copies elements, regardless of platform, and elements never move.
+/// In exchange, elements are not contiguous, so [`SegmentedPagedVec`] can't be used as a slice. Contiguous runs of
+/// elements can be accessed with [`Self::segments`].
+/// # Examples
+/// ```
+/// # use memory_pages::*;
+/// // Segments of 1 MiB each.
+/// let mut log:SegmentedPagedVec<u64> = SegmentedPagedVec::new(0x20_000);
+/// log.extend(0..0x100_000);
+/// assert_eq!(log.len(), 0x100_000);
+/// assert_eq!(log[0xABCDE], 0xABCDE);
+/// assert_eq!(log.segments().count(), 8);
+/// assert_eq!(log.iter().sum::<u64>(), (0..0x100_000).sum());
+/// ```
+pub struct SegmentedPagedVec<T> {
+    segments: Vec<Pages<AllowRead, AllowWrite, DenyExec>>,
+    /// Number of elements each segment can hold.
+    segment_capacity: usize,
+    len: usize,
+    pd: PhantomData<T>,
+}
+impl<T> SegmentedPagedVec<T> {
+    /// Creates a new, empty [`SegmentedPagedVec`] without allocating any memory. Each segment can hold at least
+    /// `segment_capacity` elements, rounded up so that segments span whole pages.
+    /// # Panics
+    /// Panics if `T` is zero-sized, or if size of `segment_capacity` elements exceeds `isize::MAX` bytes.
+    #[must_use]
+    pub fn new(segment_capacity: usize) -> Self {
+        assert!(
+            std::mem::size_of::<T>() != 0,
+            "SegmentedPagedVec can't hold zero-sized types!"
+        );
+        let bytes = PagedVec::<T>::bytes_for(segment_capacity)
+            .unwrap_or_else(|err| panic!("Creating SegmentedPagedVec failed:'{err}'!"));
+        Self {
+            segments: Vec::new(),
+            segment_capacity: align_up(bytes.max(1)) / std::mem::size_of::<T>(),
+            len: 0,
+            pd: PhantomData,
+        }
+    }
+    /// Returns the number of elements in this [`SegmentedPagedVec`].
+    #[must_use]
+    pub fn len(&self) -> usize {
+        self.len
+    }
+    /// Checks if this [`SegmentedPagedVec`] is empty.
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+    /// Returns the number of elements this [`SegmentedPagedVec`] can hold without allocating another segment.
+    #[must_use]
+    pub fn capacity(&self) -> usize {
+        self.segments.len() * self.segment_capacity
+    }
+    /// Returns the number of elements each segment can hold.
+    #[must_use]
+    pub fn segment_capacity(&self) -> usize {
+        self.segment_capacity
+    }
+    /// Appends `t` to the end of this [`SegmentedPagedVec`], allocating a new segment if all of them are full.
+    /// # Panics
+    /// Panics if kernel can't/refuses to allocate a new segment.
+    pub fn push(&mut self, t: T) {
+        if self.len == self.capacity() {
+            let segment: Pages<AllowRead, AllowWrite, DenyExec> =
+                Pages::new(self.segment_capacity * std::mem::size_of::<T>());
+            sanitizer::poison(segment.get_ptr_unchecked(), segment.len());
+            self.segments.push(segment);
+        }
+        let end = self.element_ptr(self.len);
+        sanitizer::unpoison(end.cast(), std::mem::size_of::<T>());
+        unsafe { end.write(t) };
+        self.len += 1;
+    }
+    /// Removes the last element and returns it, or `None` if this [`SegmentedPagedVec`] is empty. Segments are kept
+    /// allocated, so that pushing again does not need to allocate.
+    pub fn pop(&mut self) -> Option<T> {
+        if self.len == 0 {
+            return None;
+        }
+        self.len -= 1;
+        let last = self.element_ptr(self.len);
+        let t = unsafe { last.read() };
+        sanitizer::poison(last.cast(), std::mem::size_of::<T>());
+        Some(t)
+    }
+    /// Returns a reference to the element at `index`, or `None` if it is out of bounds.
+    #[must_use]
+    pub fn get(&self, index: usize) -> Option<&T> {
+        if index >= self.len {
+            return None;
+        }
+        Some(unsafe { &*self.element_ptr(index) })
+    }
+    /// Returns a mutable reference to the element at `index`, or `None` if it is out of bounds.
+    #[must_use]
+    pub fn get_mut(&mut self, index: usize) -> Option<&mut T> {
+        if index >= self.len {
+            return None;
+        }
+        Some(unsafe { &mut *self.element_ptr(index) })
+    }
+    /// Shortens this [`SegmentedPagedVec`] to `len` elements, dropping the rest. Does nothing if it is already shorter.
+    pub fn truncate(&mut self, len: usize) {
+        while self.len > len {
+            self.pop();
+        }
+    }
+    /// Removes all elements, keeping allocated segments.
+    pub fn clear(&mut self) {
+        self.truncate(0);
+    }
+    /// Frees all segments past the one holding the last element.
+    /// # Examples
+    /// ```
+    /// # use memory_pages::*;
+    /// let mut vec:SegmentedPagedVec<u32> = SegmentedPagedVec::new(0x400);
+    /// vec.extend(0..0x1000);
+    /// vec.truncate(0x401);
+    /// vec.shrink_to_fit();
+    /// assert_eq!(vec.capacity(), 0x800);
+    /// ```
+    pub fn shrink_to_fit(&mut self) {
+        let used = self.len.div_ceil(self.segment_capacity);
+        for segment in self.segments.drain(used..) {
+            sanitizer::unpoison(segment.get_ptr_unchecked(), segment.len());
+        }
+    }
+    /// Returns an iterator over contiguous runs of elements, one per each segment in use.
+    pub fn segments(&self) -> impl Iterator<Item = &[T]> + '_ {
+        let segment_capacity = self.segment_capacity;
+        self.segments
+            .iter()
+            .zip((0..self.len).step_by(segment_capacity))
+            .map(move |(segment, start)| unsafe {
+                std::slice::from_raw_parts(
+                    segment.get_ptr_unchecked().cast::<T>(),
+                    (self.len - start).min(segment_capacity),
+                )
+            })
+    }
+    /// Returns an iterator over mutable contiguous runs of elements, one per each segment in use.
+    pub fn segments_mut(&mut self) -> impl Iterator<Item = &mut [T]> + '_ {
+        let segment_capacity = self.segment_capacity;
+        let len = self.len;
+        self.segments
+            .iter_mut()
+            .zip((0..len).step_by(segment_capacity))
+            .map(move |(segment, start)| unsafe {
+                std::slice::from_raw_parts_mut(
+                    segment.get_ptr_unchecked().cast::<T>(),
+                    (len - start).min(segment_capacity),
+                )
+            })
+    }
+    /// Returns an iterator over all elements.
+    pub fn iter(&self) -> impl Iterator<Item = &T> + '_ {
+        self.segments().flatten()
+    }
+    /// Returns an iterator over all elements, allowing them to be modified.
+    pub fn iter_mut(&mut self) -> impl Iterator<Item = &mut T> + '_ {
+        self.segments_mut().flatten()
+    }
+    /// Returns a pointer to the slot of element `index`, which must be inside an allocated segment.
+    fn element_ptr(&self, index: usize) -> *mut T {
+        let segment = &self.segments[index / self.segment_capacity];
+        unsafe {
+            segment
+                .get_ptr_unchecked()
+                .cast::<T>()
+                .add(index % self.segment_capacity)
+        }
+    }
+}
+impl<T> Index<usize> for SegmentedPagedVec<T> {
+    type Output = T;
+    fn index(&self, index: usize) -> &T {
+        let len = self.len;
+        self.get(index)
+            .unwrap_or_else(|| panic!("index {index} out of bounds of length {len}!"))
+    }
+}
+impl<T> IndexMut<usize> for SegmentedPagedVec<T> {
+    fn index_mut(&mut self, index: usize) -> &mut T {
+        let len = self.len;
+        self.get_mut(index)
+            .unwrap_or_else(|| panic!("index {index} out of bounds of length {len}!"))
+    }
+}
+impl<T> Extend<T> for SegmentedPagedVec<T> {
+    fn extend<I: IntoIterator<Item = T>>(&mut self, iter: I) {
+        for t in iter {
+            self.push(t);
+        }
+    }
+}
+impl<T: Debug> Debug for SegmentedPagedVec<T> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_list().entries(self.iter()).finish()
+    }
+}
+impl<T> Drop for SegmentedPagedVec<T> {
+    fn drop(&mut self) {
+        for segment in self.segments_mut() {
+            unsafe { std::ptr::drop_in_place(segment) };
+        }
+        self.len = 0;
+        self.shrink_to_fit();
+    }
+}
+#[cfg(test)]
+mod test {
+    use super::*;
+    #[test]
+    fn test_segmented_push_pop() {
+        let mut vec: SegmentedPagedVec<String> = SegmentedPagedVec::new(100);
+        let per_segment = vec.segment_capacity();
+        assert_eq!(
+            per_segment,
+            page_math::PAGE_SIZE / std::mem::size_of::<String>()
+        );
+        vec.extend((0..per_segment * 3 + 1).map(|i| i.to_string()));
+        let first: *const String = &vec[0];
+        vec.push("last".to_owned());
+        assert_eq!(first, &raw const vec[0]);
+        assert_eq!(
+            vec.segments().map(<[String]>::len).collect::<Vec<_>>(),
+            [per_segment, per_segment, per_segment, 2]
+        );
+        assert_eq!(vec.pop().as_deref(), Some("last"));
+        vec[per_segment].push('!');
+        assert_eq!(vec.get(per_segment), Some(&format!("{per_segment}!")));
+        assert!(vec.get(per_segment * 3 + 1).is_none());
+        vec.truncate(per_segment);
+        vec.shrink_to_fit();
+        assert_eq!(vec.capacity(), per_segment);
+        assert!(vec.iter().enumerate().all(|(i, s)| *s == i.to_string()));
+        assert!(std::panic::catch_unwind(|| SegmentedPagedVec::<()>::new(16)).is_err());
+    }
+}
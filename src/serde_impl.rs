@@ -0,0 +1,90 @@
+//! [`serde`] support for [`PagedVec`] and [`Pages`], enabled by the `serde` feature.
+use crate::*;
+use serde::de::{Deserialize, Deserializer, Error, SeqAccess, Visitor};
+use serde::ser::{Serialize, Serializer};
+use std::fmt::Formatter;
+/// Most bytes reserved up front based on a size hint, so that malformed input can't request huge allocations.
+const MAX_HINT_BYTES: usize = 0x4000_0000;
+impl<T: Serialize> Serialize for PagedVec<T> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_seq(self.iter())
+    }
+}
+impl<'de, T: Deserialize<'de>> Deserialize<'de> for PagedVec<T> {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        struct PagedVecVisitor<T>(PhantomData<T>);
+        impl<'de, T: Deserialize<'de>> Visitor<'de> for PagedVecVisitor<T> {
+            type Value = PagedVec<T>;
+            fn expecting(&self, f: &mut Formatter) -> std::fmt::Result {
+                f.write_str("a sequence")
+            }
+            fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<Self::Value, A::Error> {
+                let max_hint = MAX_HINT_BYTES / std::mem::size_of::<T>().max(1);
+                let mut vec = PagedVec::new(seq.size_hint().unwrap_or(0).min(max_hint));
+                while let Some(t) = seq.next_element()? {
+                    vec.push(t);
+                }
+                Ok(vec)
+            }
+        }
+        deserializer.deserialize_seq(PagedVecVisitor(PhantomData))
+    }
+}
+impl<W: WritePremisionMarker, E: ExecPremisionMarker> Serialize for Pages<AllowRead, W, E> {
+    /// Serializes contents of this [`Pages`] as bytes.
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_bytes(self)
+    }
+}
+impl<'de> Deserialize<'de> for Pages<AllowRead, AllowWrite, DenyExec> {
+    /// Deserializes [`Pages`] from bytes. Length of the resulting [`Pages`] is rounded up to a page boundary, with the
+    /// remainder filled with zeroes.
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        struct PagesVisitor;
+        impl<'de> Visitor<'de> for PagesVisitor {
+            type Value = Pages<AllowRead, AllowWrite, DenyExec>;
+            fn expecting(&self, f: &mut Formatter) -> std::fmt::Result {
+                f.write_str("a non-empty byte array")
+            }
+            fn visit_bytes<Er: Error>(self, bytes: &[u8]) -> Result<Self::Value, Er> {
+                if bytes.is_empty() {
+                    return Err(Er::invalid_length(0, &self));
+                }
+                let mut pages = Pages::new(bytes.len());
+                pages.split_at_mut(bytes.len()).0.copy_from_slice(bytes);
+                Ok(pages)
+            }
+            fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<Self::Value, A::Error> {
+                // Formats without native byte arrays, like JSON, store them as sequences.
+                let mut bytes: PagedVec<u8> =
+                    PagedVec::new(seq.size_hint().unwrap_or(0).min(MAX_HINT_BYTES));
+                while let Some(byte) = seq.next_element()? {
+                    bytes.push(byte);
+                }
+                self.visit_bytes(&bytes)
+            }
+        }
+        deserializer.deserialize_bytes(PagesVisitor)
+    }
+}
+#[cfg(test)]
+mod test {
+    use super::*;
+    #[test]
+    fn test_paged_vec_round_trip() {
+        let vec: PagedVec<String> = (0..0x100).map(|i| i.to_string()).collect();
+        let json = serde_json::to_string(&vec).unwrap();
+        let back: PagedVec<String> = serde_json::from_str(&json).unwrap();
+        assert_eq!(back, vec.into_vec());
+    }
+    #[test]
+    fn test_pages_round_trip() {
+        let mut pages: Pages<AllowRead, AllowWrite, DenyExec> = Pages::new(0x1000);
+        pages[0x123] = 0x45;
+        let json = serde_json::to_string(&pages).unwrap();
+        let back: Pages<AllowRead, AllowWrite, DenyExec> = serde_json::from_str(&json).unwrap();
+        assert_eq!(back.len(), 0x1000);
+        assert_eq!(back[0x123], 0x45);
+        assert!(serde_json::from_str::<Pages<AllowRead, AllowWrite, DenyExec>>("[]").is_err());
+    }
+}
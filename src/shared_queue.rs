@@ -0,0 +1,370 @@
+use crate::*;
+use std::fmt::{Debug, Formatter};
+use std::fs::File;
+use std::io;
+use std::sync::atomic::{AtomicU64, Ordering};
+/// Marks memory holding a [`SharedQueue`], and the version of its layout.
+const MAGIC: u64 = u64::from_le_bytes(*b"MPQUEUE\x01");
+const CAPACITY: usize = 0x08;
+const MESSAGE_SIZE: usize = 0x10;
+/// Indices written by producers and the consumer live on separate cache lines.
+const TAIL: usize = 0x80;
+const HEAD: usize = 0x100;
+const HEADER_SIZE: usize = 0x180;
+/// Size of the sequence number and length preceding each message.
+const SLOT_HEADER: usize = 16;
+/// A bounded queue of byte messages inside shared memory, which lets several processes stream messages to a receiving
+/// one, without sockets or serialization. All synchronization is done with atomics stored inside the shared memory
+/// itself, so sending and receiving never enter the kernel.
+///
+/// The queue lives either in [`Pages::new_shared`], which is shared with children created using `fork`, or in a
+/// mapped file, which any process can open with [`Self::open`]. Any number of threads and processes may send messages
+/// at once. Receiving is safe from several places too, but messages are then split between receivers.
+///
+/// # Layout
+/// All fields are native-endian `u64`s. A header of 384 bytes holds:
+/// - at offset 0, the magic number `b"MPQUEUE\x01"`, written last when the queue is initialized;
+/// - at offset 8, the number of slots, a power of two;
+/// - at offset 16, the maximal size of a message, in bytes;
+/// - at offset 128, the total number of slots ever reserved by producers;
+/// - at offset 256, the total number of slots ever reserved by receivers.
+///
+/// Slots follow the header. Each holds a sequence number, the length of its message, and the message, padded to a
+/// multiple of 8 bytes. Slot `i` starts with sequence number `i`. A producer reserving position `p` waits for the
+/// sequence of its slot to equal `p`, and sets it to `p + 1` once the message is written. The receiver of position `p`
+/// waits for `p + 1`, and sets it to `p + capacity` once the message is read, handing the slot back to producers.
+/// # Examples
+/// ```
+/// # use memory_pages::*;
+/// let queue = SharedQueue::new(0x100, 0x40);
+/// std::thread::scope(|s| {
+///     for id in 0..4_u8 {
+///         let queue = &queue;
+///         s.spawn(move || {
+///             for i in 0..=0xFF_u8 {
+///                 while !queue.try_send(&[id, i]) {
+///                     std::thread::yield_now();
+///                 }
+///             }
+///         });
+///     }
+///     let mut received = 0;
+///     while received < 0x400 {
+///         match queue.try_recv() {
+///             Some(message) => {
+///                 assert_eq!(message.len(), 2);
+///                 received += 1;
+///             }
+///             None => std::thread::yield_now(),
+///         }
+///     }
+/// });
+/// ```
+pub struct SharedQueue {
+    memory: Pages<AllowRead, AllowWrite, DenyExec>,
+    /// Number of slots, minus 1.
+    mask: u64,
+    message_size: usize,
+    /// Distance between consecutive slots, in bytes.
+    slot_stride: usize,
+}
+impl SharedQueue {
+    /// Creates a new, empty [`SharedQueue`] in memory shared with child processes created using `fork`. It has at
+    /// least `capacity` slots, rounded up to a power of two, each holding a message of up to `message_size` bytes.
+    /// # Panics
+    /// Panics if `capacity` or `message_size` is 0, if the size of the queue overflows, or if kernel can't/refuses
+    /// to allocate it.
+    #[must_use]
+    pub fn new(capacity: usize, message_size: usize) -> Self {
+        let bytes = Self::bytes_for(capacity, message_size)
+            .unwrap_or_else(|err| panic!("Creating SharedQueue failed:'{err}'!"));
+        let mut queue = Self::with_memory(Pages::new_shared(bytes), capacity, message_size);
+        queue.init();
+        queue
+    }
+    /// Creates a new, empty [`SharedQueue`] inside `file`, discarding its previous contents. Other processes can then
+    /// open the same file with [`Self::open`]. It has at least `capacity` slots, rounded up to a power of two, each
+    /// holding a message of up to `message_size` bytes.
+    /// # Errors
+    /// Returns an error if `capacity` or `message_size` is 0, if the size of the queue overflows, or if `file` can't
+    /// be resized or mapped.
+    pub fn create(file: &File, capacity: usize, message_size: usize) -> io::Result<Self> {
+        let bytes = Self::bytes_for(capacity, message_size)
+            .map_err(|err| io::Error::new(io::ErrorKind::InvalidInput, err))?;
+        file.set_len(0)?;
+        let mut queue = Self::with_memory(Pages::map_file(file, bytes)?, capacity, message_size);
+        queue.init();
+        Ok(queue)
+    }
+    /// Opens a [`SharedQueue`] previously created inside `file` with [`Self::create`], possibly by another process.
+    /// # Errors
+    /// Returns an error if `file` can't be mapped, or [`io::ErrorKind::InvalidData`] if it does not hold a queue.
+    pub fn open(file: &File) -> io::Result<Self> {
+        let invalid = || {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                "file does not hold a SharedQueue",
+            )
+        };
+        let len = usize::try_from(file.metadata()?.len()).map_err(|_| invalid())?;
+        if len < HEADER_SIZE {
+            return Err(invalid());
+        }
+        let memory = Pages::map_file(file, len)?;
+        let header = |offset: usize| unsafe {
+            (*memory.get_ptr_unchecked().add(offset).cast::<AtomicU64>()).load(Ordering::Acquire)
+        };
+        // The magic number is written last, so the rest of the header is valid once it is seen.
+        if header(0) != MAGIC {
+            return Err(invalid());
+        }
+        let (capacity, message_size) = (header(CAPACITY), header(MESSAGE_SIZE));
+        let capacity = usize::try_from(capacity).map_err(|_| invalid())?;
+        let message_size = usize::try_from(message_size).map_err(|_| invalid())?;
+        match Self::bytes_for(capacity, message_size) {
+            Ok(bytes) if capacity.is_power_of_two() && bytes <= len => {
+                Ok(Self::with_memory(memory, capacity, message_size))
+            }
+            _ => Err(invalid()),
+        }
+    }
+    /// Returns the size of a queue, or an error message if it is invalid.
+    fn bytes_for(capacity: usize, message_size: usize) -> Result<usize, &'static str> {
+        if capacity == 0 || message_size == 0 {
+            return Err("SharedQueue can't have 0 slots, or 0 byte messages");
+        }
+        capacity
+            .checked_next_power_of_two()
+            .zip(message_size.checked_next_multiple_of(8))
+            .and_then(|(slots, size)| slots.checked_mul(size.checked_add(SLOT_HEADER)?))
+            .and_then(|slots| slots.checked_add(HEADER_SIZE))
+            .filter(|bytes| isize::try_from(*bytes).is_ok())
+            .ok_or("Capacity of SharedQueue overflowed!")
+    }
+    fn with_memory(
+        memory: Pages<AllowRead, AllowWrite, DenyExec>,
+        capacity: usize,
+        message_size: usize,
+    ) -> Self {
+        Self {
+            memory,
+            mask: capacity.next_power_of_two() as u64 - 1,
+            message_size,
+            slot_stride: SLOT_HEADER + message_size.next_multiple_of(8),
+        }
+    }
+    /// Writes the header and sequence numbers of an empty queue.
+    fn init(&mut self) {
+        self.word(CAPACITY).store(self.mask + 1, Ordering::Relaxed);
+        self.word(MESSAGE_SIZE)
+            .store(self.message_size as u64, Ordering::Relaxed);
+        self.word(TAIL).store(0, Ordering::Relaxed);
+        self.word(HEAD).store(0, Ordering::Relaxed);
+        for position in 0..=self.mask {
+            self.sequence(position).store(position, Ordering::Relaxed);
+        }
+        self.word(0).store(MAGIC, Ordering::Release);
+    }
+    /// Returns the number of slots.
+    #[must_use]
+    pub fn capacity(&self) -> usize {
+        (self.mask + 1) as usize
+    }
+    /// Returns the maximal size of a message, in bytes.
+    #[must_use]
+    pub fn message_size(&self) -> usize {
+        self.message_size
+    }
+    /// Returns the number of messages waiting to be received. Other processes may change it at any moment, so it is
+    /// only a hint.
+    #[must_use]
+    pub fn len(&self) -> usize {
+        let head = self.word(HEAD).load(Ordering::Acquire);
+        let tail = self.word(TAIL).load(Ordering::Acquire);
+        tail.saturating_sub(head) as usize
+    }
+    /// Checks if no messages are waiting to be received. Like [`Self::len`], it is only a hint.
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+    /// Sends `message`, returning false if the queue is full.
+    /// # Panics
+    /// Panics if `message` is longer than [`Self::message_size`].
+    pub fn try_send(&self, message: &[u8]) -> bool {
+        assert!(
+            message.len() <= self.message_size,
+            "message of {} bytes exceeds maximal size of {}!",
+            message.len(),
+            self.message_size
+        );
+        let tail = self.word(TAIL);
+        let mut position = tail.load(Ordering::Relaxed);
+        loop {
+            let sequence = self.sequence(position).load(Ordering::Acquire);
+            match sequence.wrapping_sub(position) as i64 {
+                0 => match tail.compare_exchange_weak(
+                    position,
+                    position + 1,
+                    Ordering::Relaxed,
+                    Ordering::Relaxed,
+                ) {
+                    Ok(_) => break,
+                    Err(current) => position = current,
+                },
+                // The slot still holds a message from the previous lap.
+                distance if distance < 0 => return false,
+                _ => position = tail.load(Ordering::Relaxed),
+            }
+        }
+        // The slot is reserved, so no one else accesses it until its sequence number is updated.
+        unsafe {
+            let slot = self.slot(position);
+            slot.add(8).cast::<u64>().write(message.len() as u64);
+            std::ptr::copy_nonoverlapping(message.as_ptr(), slot.add(SLOT_HEADER), message.len());
+        }
+        self.sequence(position)
+            .store(position + 1, Ordering::Release);
+        true
+    }
+    /// Receives the oldest message, passing it to `f`, and returns the result of `f`, or `None` if the queue is empty.
+    /// The slot is handed back to producers once `f` returns, or if it panics.
+    pub fn try_recv_with<R>(&self, f: impl FnOnce(&[u8]) -> R) -> Option<R> {
+        let head = self.word(HEAD);
+        let mut position = head.load(Ordering::Relaxed);
+        loop {
+            let sequence = self.sequence(position).load(Ordering::Acquire);
+            match sequence.wrapping_sub(position + 1) as i64 {
+                0 => match head.compare_exchange_weak(
+                    position,
+                    position + 1,
+                    Ordering::Relaxed,
+                    Ordering::Relaxed,
+                ) {
+                    Ok(_) => break,
+                    Err(current) => position = current,
+                },
+                // The message was not sent yet.
+                distance if distance < 0 => return None,
+                _ => position = head.load(Ordering::Relaxed),
+            }
+        }
+        // Producers would wait for the slot forever if it was not handed back when `f` panics.
+        let _release = ReleaseSlot {
+            sequence: self.sequence(position),
+            next: position + self.mask + 1,
+        };
+        Some(unsafe {
+            let slot = self.slot(position);
+            // Length comes from shared memory, so it is not trusted.
+            let len = (slot.add(8).cast::<u64>().read() as usize).min(self.message_size);
+            f(std::slice::from_raw_parts(slot.add(SLOT_HEADER), len))
+        })
+    }
+    /// Receives the oldest message, or returns `None` if the queue is empty.
+    #[must_use]
+    pub fn try_recv(&self) -> Option<Vec<u8>> {
+        self.try_recv_with(<[u8]>::to_vec)
+    }
+    fn word(&self, offset: usize) -> &AtomicU64 {
+        unsafe {
+            &*self
+                .memory
+                .get_ptr_unchecked()
+                .add(offset)
+                .cast::<AtomicU64>()
+        }
+    }
+    fn slot(&self, position: u64) -> *mut u8 {
+        let index = (position & self.mask) as usize;
+        unsafe {
+            self.memory
+                .get_ptr_unchecked()
+                .add(HEADER_SIZE + index * self.slot_stride)
+        }
+    }
+    fn sequence(&self, position: u64) -> &AtomicU64 {
+        unsafe { &*self.slot(position).cast::<AtomicU64>() }
+    }
+}
+/// Hands a received slot back to producers when dropped, by setting its sequence number to `next`.
+struct ReleaseSlot<'a> {
+    sequence: &'a AtomicU64,
+    next: u64,
+}
+impl Drop for ReleaseSlot<'_> {
+    fn drop(&mut self) {
+        self.sequence.store(self.next, Ordering::Release);
+    }
+}
+impl Debug for SharedQueue {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("SharedQueue")
+            .field("capacity", &self.capacity())
+            .field("message_size", &self.message_size)
+            .field("len", &self.len())
+            .finish()
+    }
+}
+#[cfg(test)]
+mod test {
+    use super::*;
+    #[test]
+    fn test_queue_across_mappings() {
+        let path = std::env::temp_dir().join(format!("memory_pages_queue_{}", std::process::id()));
+        let file = std::fs::OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(&path)
+            .unwrap();
+        assert_eq!(
+            SharedQueue::open(&file).unwrap_err().kind(),
+            io::ErrorKind::InvalidData
+        );
+        let receiver = SharedQueue::create(&file, 10, 13).unwrap();
+        assert_eq!(receiver.capacity(), 16);
+        assert!(receiver.try_recv().is_none());
+        // Each producer uses its own mapping, just like a separate process would.
+        std::thread::scope(|s| {
+            for id in 0..4_u32 {
+                let sender = SharedQueue::open(&file).unwrap();
+                assert_eq!(sender.message_size(), 13);
+                s.spawn(move || {
+                    for i in 0..0x1000_u32 {
+                        let message = [id.to_le_bytes(), i.to_le_bytes()].concat();
+                        while !sender.try_send(&message) {
+                            std::thread::yield_now();
+                        }
+                    }
+                });
+            }
+            let mut next = [0_u32; 4];
+            while next.iter().any(|next| *next < 0x1000) {
+                let Some(message) = receiver.try_recv() else {
+                    std::thread::yield_now();
+                    continue;
+                };
+                let id = u32::from_le_bytes(message[..4].try_into().unwrap()) as usize;
+                // Messages of each producer arrive in order.
+                assert_eq!(message[4..], next[id].to_le_bytes());
+                next[id] += 1;
+            }
+        });
+        assert!(receiver.is_empty());
+        let full = SharedQueue::open(&file).unwrap();
+        while full.try_send(b"x") {}
+        assert_eq!(receiver.len(), 16);
+        assert_eq!(receiver.try_recv_with(<[u8]>::len), Some(1));
+        // Slots are handed back even if receiving panics.
+        let res = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+            receiver.try_recv_with(|_| panic!("Receiving failed!"))
+        }));
+        assert!(res.is_err());
+        assert_eq!(receiver.len(), 14);
+        assert!(full.try_send(b"y") && full.try_send(b"z"));
+        drop((receiver, full));
+        std::fs::remove_file(&path).unwrap();
+    }
+}
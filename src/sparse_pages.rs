@@ -0,0 +1,410 @@
+use crate::page_math::{align_down, align_up, page_count, PAGE_SIZE};
+use crate::*;
+use std::ops::Range;
+#[cfg(target_family = "unix")]
+const MAP_FIXED: c_int = 0x10;
+/// A huge region of address space, out of which only explicitly committed pages are backed by memory and accessible.
+/// Reserving address space is almost free, so [`SparsePages`] may span hundreds of GB, even on machines with far less RAM,
+/// which makes them ideal for sparse structures, like a flat 48-bit address space in an emulator.
+///
+/// [`SparsePages`] keep track of which of their pages are committed, so that accesses to uncommitted ones can be
+/// caught by [`Self::get`] and [`Self::get_mut`], instead of causing a segfault.
+/// # Examples
+/// ```
+/// # use memory_pages::*;
+/// // 64 GB of address space.
+/// let mut memory = SparsePages::new(0x10_0000_0000);
+/// // Nothing is committed yet, so nothing can be accessed.
+/// assert!(memory.get(0x8_0000_0000..0x8_0000_0010).is_none());
+/// memory.commit(0x8_0000_0000, 0x10);
+/// memory.get_mut(0x8_0000_0000..0x8_0000_0010).unwrap()[0] = 8;
+/// assert_eq!(memory.get(0x8_0000_0000..0x8_0000_0010).unwrap()[0], 8);
+/// // Only the single page that was needed is committed.
+/// assert_eq!(memory.committed_pages(), 1);
+/// ```
+pub struct SparsePages {
+    reservation: Pages<DenyRead, DenyWrite, DenyExec>,
+    /// One bit per each page of `reservation`, set if the page is committed.
+    commit_map: Pages<AllowRead, AllowWrite, DenyExec>,
+    committed_pages: usize,
+}
+impl SparsePages {
+    /// Reserves at least `length` bytes of address space, rounded up to next page boundary, without committing any of it.
+    /// # Panics
+    /// Panics when a 0-sized reservation is attempted, or if kernel can't/refuses to reserve requested address space.
+    #[must_use]
+    pub fn new(length: usize) -> Self {
+        let reservation: Pages<DenyRead, DenyWrite, DenyExec> = Pages::new_native(
+            length,
+            MapOptions {
+                no_reserve: true,
+                ..MapOptions::default()
+            },
+        );
+        let map_bytes = page_count(reservation.len).div_ceil(u64::BITS as usize) * 8;
+        Self {
+            reservation,
+            commit_map: Pages::new_sparse(map_bytes),
+            committed_pages: 0,
+        }
+    }
+    /// Returns the size of the reserved address space, in bytes.
+    #[must_use]
+    pub fn len(&self) -> usize {
+        self.reservation.len
+    }
+    /// Always returns false, because [`SparsePages`] can't be empty.
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        false
+    }
+    /// Returns the number of currently committed pages.
+    #[must_use]
+    pub fn committed_pages(&self) -> usize {
+        self.committed_pages
+    }
+    /// Checks if the page `offset` is in is committed.
+    /// # Panics
+    /// Panics if `offset` is past the end of the reservation.
+    #[must_use]
+    pub fn is_committed(&self, offset: usize) -> bool {
+        assert!(offset < self.len(), "offset {offset} out of bounds!");
+        self.page_committed(offset / PAGE_SIZE)
+    }
+    /// Returns a pointer to the beginning of the reservation. Dereferencing it is only allowed inside committed pages.
+    #[must_use]
+    pub fn as_ptr(&self) -> *const u8 {
+        self.reservation.ptr.as_ptr()
+    }
+    /// Returns a mutable pointer to the beginning of the reservation. Dereferencing it is only allowed inside committed pages.
+    #[must_use]
+    pub fn as_mut_ptr(&mut self) -> *mut u8 {
+        self.reservation.ptr.as_ptr()
+    }
+    /// Gets the data in `range`, if all pages it touches are committed.
+    #[must_use]
+    pub fn get(&self, range: Range<usize>) -> Option<&[u8]> {
+        if !self.range_committed(&range) {
+            return None;
+        }
+        Some(unsafe {
+            std::slice::from_raw_parts(self.reservation.ptr.as_ptr().add(range.start), range.len())
+        })
+    }
+    /// Mutably gets the data in `range`, if all pages it touches are committed.
+    #[must_use]
+    pub fn get_mut(&mut self, range: Range<usize>) -> Option<&mut [u8]> {
+        if !self.range_committed(&range) {
+            return None;
+        }
+        Some(unsafe {
+            std::slice::from_raw_parts_mut(
+                self.reservation.ptr.as_ptr().add(range.start),
+                range.len(),
+            )
+        })
+    }
+    /// Commits the pages starting at the page `beginning` is in, and continuing till page `beginning + length` is in,
+    /// making them readable and writable. Pages which are already committed are left untouched.
+    /// # Panics
+    /// Panics if the range is out of bounds, or if kernel can't/refuses to commit requested pages.
+    pub fn commit(&mut self, beginning: usize, length: usize) {
+        let pages = self.page_range(beginning, length);
+        for run in self.runs(pages, false) {
+            self.commit_native(run.clone());
+            self.set_pages(run, true);
+        }
+    }
+    /// Decommits the pages starting at the page `beginning` is in, and continuing till page `beginning + length` is in,
+    /// returning their memory to the kernel, while keeping the address space reserved.
+    /// # Beware
+    /// Data inside decommitted pages is lost. Pages committed again will be filled with zeroes.
+    /// # Panics
+    /// Panics if the range is out of bounds, or if kernel can't/refuses to decommit requested pages.
+    pub fn decommit(&mut self, beginning: usize, length: usize) {
+        let pages = self.page_range(beginning, length);
+        for run in self.runs(pages, true) {
+            self.decommit_native(run.clone());
+            self.set_pages(run, false);
+        }
+    }
+    fn page_range(&self, beginning: usize, length: usize) -> Range<usize> {
+        let end = beginning
+            .checked_add(length)
+            .filter(|end| *end <= self.len())
+            .unwrap_or_else(|| panic!("range {beginning}+{length} out of bounds!"));
+        (align_down(beginning) / PAGE_SIZE)..(align_up(end) / PAGE_SIZE)
+    }
+    /// Splits `pages` into runs of consecutive pages with commit state equal to `committed`.
+    fn runs(&self, pages: Range<usize>, committed: bool) -> Vec<Range<usize>> {
+        let mut runs: Vec<Range<usize>> = Vec::new();
+        for page in pages {
+            if self.page_committed(page) != committed {
+                continue;
+            }
+            match runs.last_mut() {
+                Some(run) if run.end == page => run.end += 1,
+                _ => runs.push(page..page + 1),
+            }
+        }
+        runs
+    }
+    fn range_committed(&self, range: &Range<usize>) -> bool {
+        if range.start > range.end || range.end > self.len() {
+            return false;
+        }
+        if range.is_empty() {
+            return true;
+        }
+        let first = range.start / PAGE_SIZE;
+        let last = (range.end - 1) / PAGE_SIZE;
+        (first..=last).all(|page| self.page_committed(page))
+    }
+    fn words(&self) -> &[u64] {
+        unsafe {
+            std::slice::from_raw_parts(
+                self.commit_map.ptr.as_ptr().cast::<u64>(),
+                self.commit_map.len / 8,
+            )
+        }
+    }
+    fn page_committed(&self, page: usize) -> bool {
+        (self.words()[page / 64] >> (page % 64)) & 1 == 1
+    }
+    fn set_pages(&mut self, pages: Range<usize>, committed: bool) {
+        let words = unsafe {
+            std::slice::from_raw_parts_mut(
+                self.commit_map.ptr.as_ptr().cast::<u64>(),
+                self.commit_map.len / 8,
+            )
+        };
+        for page in pages {
+            if committed {
+                words[page / 64] |= 1 << (page % 64);
+                self.committed_pages += 1;
+            } else {
+                words[page / 64] &= !(1 << (page % 64));
+                self.committed_pages -= 1;
+            }
+        }
+    }
+    #[cfg(target_family = "unix")]
+    fn commit_native(&mut self, pages: Range<usize>) {
+        let res = unsafe {
+            mprotect(
+                self.reservation
+                    .ptr
+                    .as_ptr()
+                    .add(pages.start * PAGE_SIZE)
+                    .cast::<c_void>(),
+                pages.len() * PAGE_SIZE,
+                AllowRead::bitmask() | AllowWrite::bitmask(),
+            )
+        };
+        if res == -1 {
+            let err = errno_msg();
+            panic!("Failed to commit pages:'{err}'!");
+        }
+    }
+    #[cfg(target_family = "unix")]
+    fn decommit_native(&mut self, pages: Range<usize>) {
+        // Mapping fresh inaccessible pages over committed ones releases their memory, but keeps the address space reserved.
+        let ptr = unsafe {
+            mmap(
+                self.reservation
+                    .ptr
+                    .as_ptr()
+                    .add(pages.start * PAGE_SIZE)
+                    .cast::<c_void>(),
+                pages.len() * PAGE_SIZE,
+                0,
+                MAP_ANYNOMUS | MAP_PRIVATE | MAP_NORESERVE | MAP_FIXED,
+                NO_FILE,
+                0,
+            )
+        };
+        if ptr == MAP_FAILED {
+            let err = errno_msg();
+            panic!("Failed to decommit pages:'{err}'!");
+        }
+    }
+    #[cfg(target_family = "windows")]
+    fn commit_native(&mut self, pages: Range<usize>) {
+        let ptr = unsafe {
+            VirtualAlloc(
+                self.reservation
+                    .ptr
+                    .as_ptr()
+                    .add(pages.start * PAGE_SIZE)
+                    .cast(),
+                pages.len() * PAGE_SIZE,
+                MEM_COMMIT,
+                PAGE_READWRITE,
+            )
+        };
+        if ptr.is_null() {
+            let err = unsafe { winapi::um::errhandlingapi::GetLastError() };
+            panic!("Committing pages using VirtualAlloc failed with error code:{err}!");
+        }
+    }
+    #[cfg(target_family = "windows")]
+    fn decommit_native(&mut self, pages: Range<usize>) {
+        let res = unsafe {
+            VirtualFree(
+                self.reservation
+                    .ptr
+                    .as_ptr()
+                    .add(pages.start * PAGE_SIZE)
+                    .cast(),
+                pages.len() * PAGE_SIZE,
+                MEM_DECOMMIT,
+            )
+        };
+        if res == 0 {
+            let err = unsafe { winapi::um::errhandlingapi::GetLastError() };
+            panic!("Decommitting pages using VirtualFree failed with error code:{err}!");
+        }
+    }
+}
+impl Pages<AllowRead, AllowWrite, DenyExec> {
+    /// Turns this [`Pages`] into [`SparsePages`] with all pages committed, so that parts of them can be released with
+    /// [`SparsePages::decommit`], while the rest stays accessible.
+    /// # Panics
+    /// Panics if this [`Pages`] are mapped from a file or allocated in a batch, since decommitting their pages would
+    /// silently replace the file contents or other batched allocations with fresh anonymous memory.
+    /// # Examples
+    /// ```
+    /// # use memory_pages::*;
+    /// let mut memory:Pages<AllowRead,AllowWrite,DenyExec> = Pages::new(0x4000);
+    /// memory[0x3000] = 3;
+    /// let memory = memory.into_sparse();
+    /// assert_eq!(memory.committed_pages(), 4);
+    /// assert_eq!(memory.get(0x3000..0x3001).unwrap()[0], 3);
+    /// ```
+    #[must_use]
+    pub fn into_sparse(self) -> SparsePages {
+        assert!(
+            matches!(self.backing, Backing::Anonymous),
+            "Only anonymous Pages can be turned into SparsePages!"
+        );
+        let pages = page_count(self.len);
+        let mut commit_map: Pages<AllowRead, AllowWrite, DenyExec> =
+            Pages::new_sparse(pages.div_ceil(u64::BITS as usize) * 8);
+        let words = unsafe {
+            std::slice::from_raw_parts_mut(
+                commit_map.get_ptr_mut(0).cast::<u64>(),
+                pages.div_ceil(64),
+            )
+        };
+        words.fill(u64::MAX);
+        if !pages.is_multiple_of(64) {
+            words[pages / 64] = (1 << (pages % 64)) - 1;
+        }
+        SparsePages {
+            reservation: self.retype(),
+            commit_map,
+            committed_pages: pages,
+        }
+    }
+    /// Releases memory of `length` bytes starting at `beginning` back to the kernel, turning this [`Pages`] into
+    /// [`SparsePages`] with a hole in them. Rest of the pages, and data inside them, stay accessible. The address range of
+    /// the hole stays reserved, so it can be committed again with [`SparsePages::commit`], and is released together with
+    /// the rest of the pages. Useful for arena allocators freeing whole segments.
+    /// # Panics
+    /// Panics if `beginning` or `length` are not page aligned, if the hole is out of bounds, if kernel can't/refuses to
+    /// release requested pages, or if this [`Pages`] are mapped from a file or allocated in a batch.
+    /// # Examples
+    /// ```
+    /// # use memory_pages::*;
+    /// let mut arena:Pages<AllowRead,AllowWrite,DenyExec> = Pages::new(0x10000);
+    /// arena[0xF000] = 1;
+    /// let arena = arena.punch_hole(0x1000, 0x8000);
+    /// assert_eq!(arena.committed_pages(), 8);
+    /// assert!(arena.get(0x1000..0x1001).is_none());
+    /// assert_eq!(arena.get(0xF000..0xF001).unwrap()[0], 1);
+    /// ```
+    #[must_use]
+    pub fn punch_hole(self, beginning: usize, length: usize) -> SparsePages {
+        assert!(
+            beginning.is_multiple_of(PAGE_SIZE) && length.is_multiple_of(PAGE_SIZE),
+            "hole {beginning}+{length} is not page aligned!"
+        );
+        let mut sparse = self.into_sparse();
+        sparse.decommit(beginning, length);
+        sparse
+    }
+}
+#[cfg(test)]
+mod test {
+    use super::*;
+    #[test]
+    fn test_commit_decommit() {
+        let mut memory = SparsePages::new(0x100_0000_0000);
+        memory.commit(0x10_0000_0FF0, 0x20);
+        // The range crosses a page boundary, so 2 pages get committed.
+        assert_eq!(memory.committed_pages(), 2);
+        assert!(memory.is_committed(0x10_0000_0000));
+        assert!(memory.is_committed(0x10_0000_1FFF));
+        assert!(!memory.is_committed(0x10_0000_2000));
+        memory
+            .get_mut(0x10_0000_0FF0..0x10_0000_1010)
+            .unwrap()
+            .fill(7);
+        // Committing already committed pages does nothing.
+        memory.commit(0x10_0000_0000, 0x2000);
+        assert_eq!(memory.committed_pages(), 2);
+        assert!(
+            memory.get(0x10_0000_0000..0x10_0000_2000).unwrap()[0xFF0..0x1010]
+                .iter()
+                .all(|b| *b == 7)
+        );
+        memory.decommit(0x10_0000_1000, 1);
+        assert_eq!(memory.committed_pages(), 1);
+        assert!(memory.get(0x10_0000_0FF0..0x10_0000_1010).is_none());
+        memory.commit(0x10_0000_1000, 1);
+        // Recommitted pages are zeroed.
+        assert_eq!(memory.get(0x10_0000_1000..0x10_0000_1001).unwrap()[0], 0);
+    }
+    #[test]
+    fn test_punch_hole_keeps_rest() {
+        let mut memory: Pages<AllowRead, AllowWrite, DenyExec> = Pages::new(0x41000);
+        memory.fill(9);
+        let mut memory = memory.punch_hole(0x2000, 0x3E000);
+        assert_eq!(memory.committed_pages(), 0x41 - 0x3E);
+        assert!(memory.is_committed(0x1FFF));
+        assert!(!memory.is_committed(0x2000));
+        assert!(!memory.is_committed(0x3FFFF));
+        assert!(memory
+            .get(0x40000..0x41000)
+            .unwrap()
+            .iter()
+            .all(|b| *b == 9));
+        memory.commit(0x2000, 1);
+        assert_eq!(memory.get_mut(0x2000..0x2001).unwrap()[0], 0);
+    }
+    // Open files can't be removed on Windows.
+    #[cfg(target_family = "unix")]
+    #[test]
+    #[should_panic(expected = "Only anonymous Pages")]
+    fn test_punch_hole_in_file() {
+        let path =
+            std::env::temp_dir().join(format!("memory_pages_punch_hole_{}", std::process::id()));
+        let file = std::fs::OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(&path)
+            .unwrap();
+        std::fs::remove_file(&path).unwrap();
+        let memory: Pages<AllowRead, AllowWrite, DenyExec> =
+            Pages::map_file(&file, 0x2000).unwrap();
+        let _ = memory.punch_hole(0x1000, 0x1000);
+    }
+    #[test]
+    #[should_panic]
+    fn test_commit_out_of_bounds() {
+        let mut memory = SparsePages::new(0x10000);
+        memory.commit(0xF000, 0x1001);
+    }
+}
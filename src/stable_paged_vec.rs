@@ -0,0 +1,260 @@
+use crate::page_math::align_up;
+use crate::*;
+use std::fmt::{Debug, Formatter};
+/// A vector whose elements never move. Address space for its maximal capacity is reserved up front, and pages are only
+/// committed as it grows, so growing it never copies anything, and pointers to its elements stay valid until they are
+/// removed. Reserving address space is almost free, so the maximal capacity can be far larger than the available memory.
+/// # Examples
+/// ```
+/// # use memory_pages::*;
+/// // 16 GB of address space, but only pages actually used are committed.
+/// let mut vec:StablePagedVec<u64> = StablePagedVec::new(0x8000_0000);
+/// vec.push(1);
+/// let first:*const u64 = &vec[0];
+/// for i in 0..0x10_000{
+///     vec.push(i);
+/// }
+/// // Pushing did not move the first element.
+/// assert_eq!(first, &vec[0] as *const u64);
+/// assert_eq!(unsafe{*first}, 1);
+/// ```
+pub struct StablePagedVec<T> {
+    memory: SparsePages,
+    /// Number of bytes at the beginning of `memory` that are committed.
+    committed: usize,
+    len: usize,
+    pd: PhantomData<T>,
+}
+impl<T> StablePagedVec<T> {
+    /// Creates a new, empty [`StablePagedVec`], reserving address space for `max_capacity` elements without committing
+    /// any of it.
+    /// # Panics
+    /// Panics if `T` is zero-sized, if size of `max_capacity` elements exceeds `isize::MAX` bytes, or if kernel
+    /// can't/refuses to reserve address space.
+    #[must_use]
+    pub fn new(max_capacity: usize) -> Self {
+        assert!(
+            std::mem::size_of::<T>() != 0,
+            "StablePagedVec can't hold zero-sized types!"
+        );
+        let bytes = PagedVec::<T>::bytes_for(max_capacity)
+            .unwrap_or_else(|err| panic!("Reserving StablePagedVec failed:'{err}'!"));
+        Self {
+            memory: SparsePages::new(bytes.max(1)),
+            committed: 0,
+            len: 0,
+            pd: PhantomData,
+        }
+    }
+    /// Returns the number of elements in this [`StablePagedVec`].
+    #[must_use]
+    pub fn len(&self) -> usize {
+        self.len
+    }
+    /// Checks if this [`StablePagedVec`] is empty.
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+    /// Returns the number of elements this [`StablePagedVec`] can hold without committing more pages.
+    #[must_use]
+    pub fn capacity(&self) -> usize {
+        self.committed / std::mem::size_of::<T>()
+    }
+    /// Returns the number of elements this [`StablePagedVec`] can ever hold.
+    #[must_use]
+    pub fn max_capacity(&self) -> usize {
+        self.memory.len() / std::mem::size_of::<T>()
+    }
+    /// Returns a pointer to the first element. It never changes during the lifetime of this [`StablePagedVec`].
+    #[must_use]
+    pub fn as_ptr(&self) -> *const T {
+        self.memory.as_ptr().cast()
+    }
+    /// Returns a mutable pointer to the first element. It never changes during the lifetime of this [`StablePagedVec`].
+    #[must_use]
+    pub fn as_mut_ptr(&mut self) -> *mut T {
+        self.memory.as_mut_ptr().cast()
+    }
+    /// Commits pages for at least `additional` more elements. More pages may be committed, to speculatively avoid
+    /// committing them one by one.
+    /// # Panics
+    /// Panics if the maximal capacity would be exceeded, or if kernel can't/refuses to commit pages.
+    pub fn reserve(&mut self, additional: usize) {
+        let needed = self
+            .len
+            .checked_add(additional)
+            .filter(|needed| *needed <= self.max_capacity())
+            .unwrap_or_else(|| panic!("Maximal capacity of StablePagedVec exceeded!"));
+        if needed <= self.capacity() {
+            return;
+        }
+        let bytes = align_up(needed * std::mem::size_of::<T>())
+            .max(self.committed.saturating_mul(2))
+            .min(self.memory.len());
+        self.memory.commit(self.committed, bytes - self.committed);
+        sanitizer::poison(
+            self.memory.as_ptr().wrapping_add(self.committed),
+            bytes - self.committed,
+        );
+        self.committed = bytes;
+    }
+    /// Appends `t` to the end of this [`StablePagedVec`], committing more pages if needed.
+    /// # Panics
+    /// Panics if the maximal capacity is already reached.
+    pub fn push(&mut self, t: T) {
+        if self.try_push(t).is_err() {
+            panic!("Maximal capacity of StablePagedVec exceeded!");
+        }
+    }
+    /// Appends `t` to the end of this [`StablePagedVec`], or returns it back if the maximal capacity is already reached.
+    /// # Errors
+    /// Returns `t` if there is no space left for it.
+    /// # Examples
+    /// ```
+    /// # use memory_pages::*;
+    /// let mut vec:StablePagedVec<u8> = StablePagedVec::new(0x1000);
+    /// for i in 0..0x1000{
+    ///     vec.try_push((i % 0x100) as u8).unwrap();
+    /// }
+    /// assert_eq!(vec.try_push(1), Err(1));
+    /// ```
+    pub fn try_push(&mut self, t: T) -> Result<(), T> {
+        if self.len == self.max_capacity() {
+            return Err(t);
+        }
+        self.reserve(1);
+        let end = unsafe { self.as_mut_ptr().add(self.len) };
+        sanitizer::unpoison(end.cast(), std::mem::size_of::<T>());
+        unsafe { end.write(t) };
+        self.len += 1;
+        Ok(())
+    }
+    /// Removes the last element and returns it, or `None` if this [`StablePagedVec`] is empty.
+    pub fn pop(&mut self) -> Option<T> {
+        if self.len == 0 {
+            return None;
+        }
+        self.len -= 1;
+        let last = unsafe { self.as_mut_ptr().add(self.len) };
+        let t = unsafe { last.read() };
+        sanitizer::poison(last.cast(), std::mem::size_of::<T>());
+        Some(t)
+    }
+    /// Shortens this [`StablePagedVec`] to `len` elements, dropping the rest. Does nothing if it is already shorter.
+    pub fn truncate(&mut self, len: usize) {
+        if len >= self.len {
+            return;
+        }
+        let tail: *mut [T] = std::ptr::slice_from_raw_parts_mut(
+            unsafe { self.as_mut_ptr().add(len) },
+            self.len - len,
+        );
+        // Length is updated first, so that a panicking `drop` can't cause a double drop.
+        self.len = len;
+        unsafe { std::ptr::drop_in_place(tail) };
+        self.poison_spare();
+    }
+    /// Removes all elements, keeping committed pages.
+    pub fn clear(&mut self) {
+        self.truncate(0);
+    }
+    /// Decommits all whole pages past the last element, returning their memory to the kernel. Address space stays
+    /// reserved, so they get committed again once needed.
+    /// # Examples
+    /// ```
+    /// # use memory_pages::*;
+    /// let mut vec:StablePagedVec<u32> = StablePagedVec::new(0x100_000);
+    /// vec.reserve(0x10_000);
+    /// vec.push(1);
+    /// vec.shrink_to_fit();
+    /// assert_eq!(vec.capacity(), 0x400);
+    /// ```
+    pub fn shrink_to_fit(&mut self) {
+        let used = align_up(self.len * std::mem::size_of::<T>());
+        if used >= self.committed {
+            return;
+        }
+        self.unpoison_spare();
+        self.memory.decommit(used, self.committed - used);
+        self.committed = used;
+        self.poison_spare();
+    }
+    fn poison_spare(&self) {
+        let used = self.len * std::mem::size_of::<T>();
+        sanitizer::poison(
+            self.memory.as_ptr().wrapping_add(used),
+            self.committed - used,
+        );
+    }
+    fn unpoison_spare(&self) {
+        let used = self.len * std::mem::size_of::<T>();
+        sanitizer::unpoison(
+            self.memory.as_ptr().wrapping_add(used),
+            self.committed - used,
+        );
+    }
+}
+impl<T> Deref for StablePagedVec<T> {
+    type Target = [T];
+    fn deref(&self) -> &[T] {
+        unsafe { std::slice::from_raw_parts(self.as_ptr(), self.len) }
+    }
+}
+impl<T> DerefMut for StablePagedVec<T> {
+    fn deref_mut(&mut self) -> &mut [T] {
+        unsafe { std::slice::from_raw_parts_mut(self.as_mut_ptr(), self.len) }
+    }
+}
+impl<T> Extend<T> for StablePagedVec<T> {
+    fn extend<I: IntoIterator<Item = T>>(&mut self, iter: I) {
+        let iter = iter.into_iter();
+        self.reserve(iter.size_hint().0.min(self.max_capacity() - self.len));
+        for t in iter {
+            self.push(t);
+        }
+    }
+}
+impl<T: Debug> Debug for StablePagedVec<T> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_list().entries(self.iter()).finish()
+    }
+}
+impl<T> Drop for StablePagedVec<T> {
+    fn drop(&mut self) {
+        self.clear();
+        // Address range is unmapped, and must not stay poisoned once it is reused.
+        self.unpoison_spare();
+    }
+}
+#[cfg(test)]
+mod test {
+    use super::*;
+    #[test]
+    fn test_stable_addresses() {
+        let mut vec: StablePagedVec<String> = StablePagedVec::new(0x100_000);
+        vec.push("first".to_owned());
+        let first: *const String = &vec[0];
+        vec.extend((0..0x10_000).map(|i| i.to_string()));
+        assert_eq!(first, &raw const vec[0]);
+        assert_eq!(vec.len(), 0x10_001);
+        assert_eq!(vec[0x10_000], "65535");
+        assert_eq!(vec.pop().as_deref(), Some("65535"));
+        vec.truncate(1);
+        vec.shrink_to_fit();
+        assert_eq!(
+            vec.capacity(),
+            page_math::PAGE_SIZE / std::mem::size_of::<String>()
+        );
+        assert_eq!(unsafe { &*first }, "first");
+    }
+    #[test]
+    fn test_max_capacity() {
+        let mut vec: StablePagedVec<u64> = StablePagedVec::new(0x200);
+        vec.extend(0..0x200);
+        assert_eq!(vec.try_push(0x200), Err(0x200));
+        assert_eq!(vec.capacity(), 0x200);
+        assert!(std::panic::catch_unwind(move || vec.push(0)).is_err());
+        assert!(std::panic::catch_unwind(|| StablePagedVec::<()>::new(16)).is_err());
+    }
+}
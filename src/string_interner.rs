@@ -0,0 +1,181 @@
+use crate::*;
+use std::cell::RefCell;
+use std::collections::HashSet;
+use std::fmt::{Debug, Formatter};
+/// Deduplicates strings, storing a single copy of each of them in page memory. Interned strings never move, so
+/// [`Self::intern`] hands out references which stay valid for as long as the interner lives, even while more strings
+/// are interned. Equal strings interned into the same interner are the same reference, so they can be compared by
+/// address alone.
+///
+/// Once loading finishes, [`Self::freeze`] turns the interner into a [`FrozenInterner`], whose strings are made
+/// read-only by hardware, and which can be shared between threads. This fits symbol tables of compilers, or
+/// dictionaries of analytics engines, which are built once and then only looked up.
+/// # Examples
+/// ```
+/// # use memory_pages::*;
+/// // 1 GiB of address space, only backed by memory once used.
+/// let interner = StringInterner::new(0x4000_0000);
+/// let a = interner.intern("main");
+/// let b = interner.intern(&String::from("main"));
+/// assert!(std::ptr::eq(a, b));
+/// assert_eq!(interner.len(), 1);
+/// let frozen = interner.freeze();
+/// assert_eq!(frozen.get("main"), Some("main"));
+/// assert_eq!(frozen.get("other"), None);
+/// ```
+pub struct StringInterner {
+    arena: PageArena,
+    /// All interned strings. They point into `arena`, and only live as long as it does.
+    strings: RefCell<HashSet<&'static str>>,
+}
+impl StringInterner {
+    /// Creates a new, empty [`StringInterner`], reserving at least `max_size` bytes of address space for strings,
+    /// without committing any of it.
+    /// # Panics
+    /// Panics if `max_size` is 0, or if kernel can't/refuses to reserve address space.
+    #[must_use]
+    pub fn new(max_size: usize) -> Self {
+        Self {
+            arena: PageArena::new(max_size),
+            strings: RefCell::new(HashSet::new()),
+        }
+    }
+    /// Returns the number of distinct strings interned.
+    #[must_use]
+    pub fn len(&self) -> usize {
+        self.strings.borrow().len()
+    }
+    /// Checks if no strings were interned yet.
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+    /// Returns the total length of all distinct strings interned, in bytes.
+    #[must_use]
+    pub fn allocated_bytes(&self) -> usize {
+        self.arena.allocated_bytes()
+    }
+    /// Returns the interned copy of `s`, copying it into this [`StringInterner`] if it was not interned yet.
+    /// # Panics
+    /// Panics if the reservation is exhausted, or if kernel can't/refuses to commit more pages.
+    pub fn intern(&self, s: &str) -> &str {
+        if let Some(interned) = self.get(s) {
+            return interned;
+        }
+        let interned = self.arena.alloc_str(s);
+        // Strings are never moved or freed before the arena is dropped, and references handed out borrow `self`.
+        let interned: &'static str = unsafe { &*std::ptr::from_ref::<str>(interned) };
+        self.strings.borrow_mut().insert(interned);
+        interned
+    }
+    /// Returns the interned copy of `s`, or `None` if it was not interned.
+    #[must_use]
+    pub fn get(&self, s: &str) -> Option<&str> {
+        self.strings.borrow().get(s).copied()
+    }
+    /// Checks if `s` was interned.
+    #[must_use]
+    pub fn contains(&self, s: &str) -> bool {
+        self.strings.borrow().contains(s)
+    }
+    /// Makes all interned strings read-only, turning this [`StringInterner`] into a [`FrozenInterner`].
+    /// # Panics
+    /// Panics if kernel can't/refuses to change protection of the strings.
+    #[must_use]
+    pub fn freeze(self) -> FrozenInterner {
+        self.arena
+            .protect_read_only()
+            .unwrap_or_else(|err| panic!("Freezing StringInterner failed:'{err}'!"));
+        FrozenInterner {
+            arena: self.arena,
+            strings: self.strings.into_inner(),
+        }
+    }
+}
+impl Debug for StringInterner {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_set().entries(self.strings.borrow().iter()).finish()
+    }
+}
+/// Read-only set of interned strings, whose memory is write-protected by hardware. Created using
+/// [`StringInterner::freeze`].
+///
+/// No strings can be added, so [`FrozenInterner`] can be freely shared between threads.
+/// # Examples
+/// ```
+/// # use memory_pages::*;
+/// let interner = StringInterner::new(0x100_000);
+/// for word in ["alpha", "beta", "alpha"] {
+///     interner.intern(word);
+/// }
+/// let frozen = std::sync::Arc::new(interner.freeze());
+/// let shared = frozen.clone();
+/// std::thread::spawn(move || assert!(shared.contains("beta"))).join().unwrap();
+/// assert_eq!(frozen.len(), 2);
+/// ```
+pub struct FrozenInterner {
+    /// Never allocated from again, only kept alive for `strings`.
+    arena: PageArena,
+    strings: HashSet<&'static str>,
+}
+// Strings can't be modified, and the arena is never accessed until it is dropped.
+unsafe impl Sync for FrozenInterner {}
+impl FrozenInterner {
+    /// Returns the number of distinct strings interned.
+    #[must_use]
+    pub fn len(&self) -> usize {
+        self.strings.len()
+    }
+    /// Checks if no strings were interned.
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.strings.is_empty()
+    }
+    /// Returns the total length of all distinct strings interned, in bytes.
+    #[must_use]
+    pub fn allocated_bytes(&self) -> usize {
+        self.arena.allocated_bytes()
+    }
+    /// Returns the interned copy of `s`, or `None` if it was not interned.
+    #[must_use]
+    pub fn get(&self, s: &str) -> Option<&str> {
+        self.strings.get(s).copied()
+    }
+    /// Checks if `s` was interned.
+    #[must_use]
+    pub fn contains(&self, s: &str) -> bool {
+        self.strings.contains(s)
+    }
+    /// Returns an iterator over all interned strings, in arbitrary order.
+    pub fn iter(&self) -> impl Iterator<Item = &str> + '_ {
+        self.strings.iter().copied()
+    }
+}
+impl Debug for FrozenInterner {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_set().entries(self.iter()).finish()
+    }
+}
+#[cfg(test)]
+mod test {
+    use super::*;
+    #[test]
+    fn test_interner_dedup_and_freeze() {
+        let interner = StringInterner::new(0x1000_0000);
+        let names: Vec<&str> = (0..0x10_000)
+            .map(|i| interner.intern(&format!("symbol_{}", i % 0x1000)))
+            .collect();
+        assert_eq!(interner.len(), 0x1000);
+        assert!(std::ptr::eq(names[5], names[0x1005]));
+        assert_eq!(names[0x1234], "symbol_564");
+        assert_eq!(interner.intern(""), "");
+        assert!(interner.contains(""));
+        let frozen = interner.freeze();
+        assert_eq!(frozen.len(), 0x1001);
+        assert_eq!(
+            frozen.iter().map(str::len).sum::<usize>(),
+            frozen.allocated_bytes()
+        );
+        assert!(frozen.get("symbol_4096").is_none());
+    }
+}
@@ -0,0 +1,206 @@
+use crate::*;
+use std::any::Any;
+use std::fmt::{Debug, Formatter};
+/// Types passed in a single general purpose register by the C calling convention, which can be arguments of functions
+/// called through a [`Trampoline`].
+/// # Safety
+/// Values of the type must be passed in a single general purpose register, on every supported architecture.
+pub unsafe trait TrampolineArg {}
+/// Types returned in a register by the C calling convention, which can be returned by functions called through a
+/// [`Trampoline`].
+/// # Safety
+/// Values of the type must be returned in registers, and not through memory pointed to by a hidden argument.
+pub unsafe trait TrampolineRet {}
+macro_rules! register_types {
+    ($($t:ty),*) => {
+        $(
+            unsafe impl TrampolineArg for $t {}
+            unsafe impl TrampolineRet for $t {}
+        )*
+    };
+}
+register_types!(bool, u8, u16, u32, u64, usize, i8, i16, i32, i64, isize);
+unsafe impl<T> TrampolineArg for *const T {}
+unsafe impl<T> TrampolineRet for *const T {}
+unsafe impl<T> TrampolineArg for *mut T {}
+unsafe impl<T> TrampolineRet for *mut T {}
+unsafe impl TrampolineRet for () {}
+unsafe impl TrampolineRet for f32 {}
+unsafe impl TrampolineRet for f64 {}
+/// Rust closures which can be called from jitted code as the C function `F`, using a [`Trampoline`].
+pub trait TrampolineClosure<F: ExternFnPtr>: 'static {
+    /// Returns a C function taking a pointer to the closure, followed by the arguments of `F`.
+    #[doc(hidden)]
+    fn shim() -> *const ();
+}
+macro_rules! closure_shims {
+    ($($shim:ident($($arg:ident),*);)*) => {
+        $(
+            extern "C" fn $shim<C: Fn($($arg),*) -> R, R, $($arg),*>(closure: *const C, $($arg: $arg),*) -> R {
+                unsafe { (*closure)($($arg),*) }
+            }
+            #[allow(non_camel_case_types)]
+            impl<C, R, $($arg),*> TrampolineClosure<unsafe extern "C" fn($($arg),*) -> R> for C
+            where
+                C: Fn($($arg),*) -> R + 'static,
+                R: TrampolineRet,
+                $($arg: TrampolineArg,)*
+            {
+                fn shim() -> *const () {
+                    $shim::<C, R, $($arg),*> as *const ()
+                }
+            }
+        )*
+    };
+}
+#[allow(non_snake_case)]
+mod shims {
+    use super::*;
+    closure_shims! {
+        shim0();
+        shim1(A1);
+        shim2(A1, A2);
+        shim3(A1, A2, A3);
+    }
+}
+/// Emits code which shifts the first three integer arguments by one register, loads `closure` into the register of the
+/// first argument, and jumps to `shim`.
+#[cfg(all(target_arch = "x86_64", not(target_family = "windows")))]
+fn emit(code: &mut [u8], closure: u64, shim: u64) -> usize {
+    // mov rcx, rdx; mov rdx, rsi; mov rsi, rdi; movabs rdi, closure
+    let mut bytes = vec![
+        0x48, 0x89, 0xD1, 0x48, 0x89, 0xF2, 0x48, 0x89, 0xFE, 0x48, 0xBF,
+    ];
+    bytes.extend_from_slice(&closure.to_le_bytes());
+    // movabs r11, shim; jmp r11
+    bytes.extend_from_slice(&[0x49, 0xBB]);
+    bytes.extend_from_slice(&shim.to_le_bytes());
+    bytes.extend_from_slice(&[0x41, 0xFF, 0xE3]);
+    code[..bytes.len()].copy_from_slice(&bytes);
+    bytes.len()
+}
+#[cfg(all(target_arch = "x86_64", target_family = "windows"))]
+fn emit(code: &mut [u8], closure: u64, shim: u64) -> usize {
+    // mov r9, r8; mov r8, rdx; mov rdx, rcx; movabs rcx, closure
+    let mut bytes = vec![
+        0x4D, 0x89, 0xC1, 0x49, 0x89, 0xD0, 0x48, 0x89, 0xCA, 0x48, 0xB9,
+    ];
+    bytes.extend_from_slice(&closure.to_le_bytes());
+    // movabs r11, shim; jmp r11
+    bytes.extend_from_slice(&[0x49, 0xBB]);
+    bytes.extend_from_slice(&shim.to_le_bytes());
+    bytes.extend_from_slice(&[0x41, 0xFF, 0xE3]);
+    code[..bytes.len()].copy_from_slice(&bytes);
+    bytes.len()
+}
+#[cfg(target_arch = "aarch64")]
+fn emit(code: &mut [u8], closure: u64, shim: u64) -> usize {
+    let mut bytes = Vec::with_capacity(48);
+    // bti c; mov x3, x2; mov x2, x1; mov x1, x0; ldr x0, closure; ldr x16, shim; br x16; brk #0
+    for instruction in [
+        branch_protection::BranchTarget::Call.instruction(),
+        0xAA02_03E3,
+        0xAA01_03E2,
+        0xAA00_03E1,
+        0x5800_0080,
+        0x5800_00B0,
+        0xD61F_0200,
+        0xD420_0000,
+    ] {
+        bytes.extend_from_slice(&instruction.to_le_bytes());
+    }
+    bytes.extend_from_slice(&closure.to_le_bytes());
+    bytes.extend_from_slice(&shim.to_le_bytes());
+    code[..bytes.len()].copy_from_slice(&bytes);
+    bytes.len()
+}
+/// A Rust closure, callable from jitted code through a C function placed in executable pages. This lets jitted code
+/// call back into functions of the host runtime which need some state, without hand-written thunks.
+///
+/// The entry point shifts the arguments it was called with by one, passes a pointer to the closure as the first
+/// argument, and jumps to a function calling the closure. Because of that, the function can take at most 3 arguments,
+/// each of which must be an integer or a pointer, and return an integer, a pointer, a float or nothing.
+///
+/// Panics can't unwind out of the closure, into the jitted code, so they abort the process.
+/// # Examples
+/// ```
+/// # use memory_pages::*;
+/// # #[cfg(target_arch = "x86_64")]
+/// # {
+/// let offset = 10;
+/// let add: Trampoline<unsafe extern "C" fn(u64, u64) -> u64> =
+///     Trampoline::new(move |a: u64, b: u64| a + b + offset);
+/// // A pointer jitted code can call, like any other C function.
+/// let add_fn: unsafe extern "C" fn(u64, u64) -> u64 = unsafe { add.get_fn().internal_fn() };
+/// assert_eq!(unsafe { add_fn(1, 2) }, 13);
+/// # }
+/// ```
+pub struct Trampoline<F: ExternFnPtr> {
+    pages: Pages<AllowRead, DenyWrite, AllowExec>,
+    /// The closure called by the entry point. It is boxed, so its address does not change.
+    _closure: Box<dyn Any>,
+    pd: PhantomData<F>,
+}
+impl<F: ExternFnPtr + Copy + std::fmt::Pointer> Trampoline<F> {
+    /// Places `closure` behind a new C function entry point.
+    /// # Panics
+    /// Panics if kernel can't/refuses to allocate executable pages.
+    #[must_use]
+    pub fn new<C: TrampolineClosure<F>>(closure: C) -> Self {
+        let closure = Box::new(closure);
+        let mut pages: Pages<AllowRead, AllowWrite, DenyExec> = Pages::new(1);
+        let closure_ptr = std::ptr::from_ref::<C>(&closure) as u64;
+        emit(&mut pages, closure_ptr, C::shim() as u64);
+        let pages = pages.set_protected_exec();
+        if let Err(err) = pages.set_valid_call_targets(&[0]) {
+            panic!("Registering Trampoline call target failed:'{err}'!");
+        }
+        Self {
+            pages,
+            _closure: closure,
+            pd: PhantomData,
+        }
+    }
+    /// Returns the entry point, which calls the closure.
+    #[must_use]
+    pub fn get_fn(&self) -> FnRef<'_, F> {
+        // The entry point passes its arguments to the closure, which matches the signature `F`.
+        unsafe { self.pages.get_fn(0) }
+    }
+    /// Returns the address of the entry point, to be embedded in jitted code.
+    #[must_use]
+    pub fn address(&self) -> *const u8 {
+        self.pages.ptr.as_ptr()
+    }
+}
+impl<F: ExternFnPtr> Debug for Trampoline<F> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Trampoline")
+            .field("address", &self.pages.ptr)
+            .finish()
+    }
+}
+#[cfg(test)]
+mod test {
+    use super::*;
+    #[test]
+    fn test_trampoline_calls_closure() {
+        let calls = std::rc::Rc::new(std::cell::Cell::new(0));
+        let counter = calls.clone();
+        let count: Trampoline<unsafe extern "C" fn() -> u32> = Trampoline::new(move || {
+            counter.set(counter.get() + 1);
+            counter.get()
+        });
+        let names = ["zero", "one"];
+        let lookup: Trampoline<unsafe extern "C" fn(usize, *const u8, i8) -> usize> =
+            Trampoline::new(move |index: usize, ptr: *const u8, delta: i8| {
+                names[index].len() + ptr as usize + delta as usize
+            });
+        unsafe {
+            assert_eq!(count.get_fn().call(()), 1);
+            assert_eq!(count.get_fn().call(()), 2);
+            assert_eq!(lookup.get_fn().call((1, 0x10 as *const u8, 2)), 0x15);
+        }
+        assert_eq!(calls.get(), 2);
+    }
+}
@@ -0,0 +1,197 @@
+use crate::*;
+use std::fmt::{Debug, Formatter};
+#[cfg(target_family = "unix")]
+extern "C" {
+    fn __register_frame(begin: *const u8);
+    fn __deregister_frame(begin: *const u8);
+}
+/// Entry of a Windows x64 function table, describing where a function starts and ends, and where its unwind info is.
+/// All addresses are offsets from the base address passed to [`UnwindRegistration::register_function_table`].
+#[cfg(all(target_family = "windows", target_arch = "x86_64"))]
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct RuntimeFunction {
+    /// Offset of the first byte of the function.
+    pub begin_address: u32,
+    /// Offset of the first byte past the end of the function.
+    pub end_address: u32,
+    /// Offset of the `UNWIND_INFO` of the function.
+    pub unwind_info_address: u32,
+}
+/// Unwind information of jitted functions, registered with the unwinder of the system for as long as this
+/// [`UnwindRegistration`] lives. Without it, panics, exceptions and backtraces can't cross frames of jitted code, and
+/// usually abort the process when they reach one.
+///
+/// On Windows x64, functions are described by a table of `RuntimeFunction`s, registered using `RtlAddFunctionTable`.
+/// On other systems, they are described by DWARF call frame information, in the format of an `.eh_frame` section,
+/// registered using `__register_frame`.
+///
+/// Registration is undone when this [`UnwindRegistration`] is dropped, so it must be dropped before the code or the
+/// unwind information it describes is freed.
+/// # Examples
+/// ```
+/// # use memory_pages::*;
+/// # #[cfg(target_family = "unix")]
+/// # {
+/// // No CIEs or FDEs, just the terminator.
+/// let eh_frame = [0_u8; 4];
+/// let registration = unsafe { UnwindRegistration::register_eh_frame(&eh_frame) };
+/// drop(registration);
+/// # }
+/// ```
+pub struct UnwindRegistration<'a> {
+    /// Pointers passed to `__register_frame`, each of which must be deregistered.
+    #[cfg(target_family = "unix")]
+    frames: Vec<*const u8>,
+    #[cfg(target_family = "windows")]
+    table: *const RuntimeFunction,
+    pd: PhantomData<&'a [u8]>,
+}
+// Registrations are global, and can be undone from any thread.
+unsafe impl Send for UnwindRegistration<'_> {}
+unsafe impl Sync for UnwindRegistration<'_> {}
+impl<'a> UnwindRegistration<'a> {
+    /// Registers DWARF call frame information of jitted functions. `eh_frame` is a sequence of CIE and FDE records, in
+    /// the format of an `.eh_frame` section, terminated by a record of length 0.
+    /// # Safety
+    /// All records must be well-formed, and FDEs must describe code which stays mapped until this
+    /// [`UnwindRegistration`] is dropped. Malformed records crash the unwinder once it reaches them.
+    /// # Panics
+    /// Panics if `eh_frame` does not end with a terminator, or if a record extends past its end.
+    #[cfg(target_family = "unix")]
+    #[must_use]
+    pub unsafe fn register_eh_frame(eh_frame: &'a [u8]) -> Self {
+        let records = records(eh_frame);
+        // libgcc registers a whole section at once, while the libunwind of Apple expects each FDE separately.
+        let frames: Vec<*const u8> = if cfg!(target_vendor = "apple") {
+            records
+                .into_iter()
+                .filter(|&(_, is_cie)| !is_cie)
+                .map(|(offset, _)| eh_frame.as_ptr().add(offset))
+                .collect()
+        } else {
+            vec![eh_frame.as_ptr()]
+        };
+        for &frame in &frames {
+            __register_frame(frame);
+        }
+        Self {
+            frames,
+            pd: PhantomData,
+        }
+    }
+    /// Registers a table of jitted functions, whose addresses are offsets from `base`.
+    /// # Safety
+    /// Unwind info referenced by `table` must be well-formed, and all functions and unwind info must stay mapped until
+    /// this [`UnwindRegistration`] is dropped. Malformed unwind info crashes the unwinder once it reaches it.
+    /// # Panics
+    /// Panics if the table is empty, has more than `u32::MAX` entries, or if the kernel refuses to register it.
+    #[cfg(all(target_family = "windows", target_arch = "x86_64"))]
+    #[must_use]
+    pub unsafe fn register_function_table(table: &'a [RuntimeFunction], base: *const u8) -> Self {
+        use winapi::um::winnt::RtlAddFunctionTable;
+        assert!(!table.is_empty(), "function table can't be empty!");
+        let len = u32::try_from(table.len())
+            .unwrap_or_else(|_| panic!("Maximal capacity of function table exceeded!"));
+        if RtlAddFunctionTable(table.as_ptr().cast_mut().cast(), len, base as u64) == 0 {
+            panic!("Registering function table failed!");
+        }
+        Self {
+            table: table.as_ptr(),
+            pd: PhantomData,
+        }
+    }
+}
+/// Returns offsets of all records of `eh_frame`, and whether each of them is a CIE.
+#[cfg(target_family = "unix")]
+fn records(eh_frame: &[u8]) -> Vec<(usize, bool)> {
+    let read_u32 = |offset: usize| {
+        let bytes = eh_frame
+            .get(offset..offset + 4)
+            .unwrap_or_else(|| panic!("eh_frame record at {offset:#x} is truncated!"));
+        u32::from_ne_bytes(bytes.try_into().expect("slice has 4 bytes"))
+    };
+    let mut records = Vec::new();
+    let mut offset = 0;
+    loop {
+        let (len, header) = match read_u32(offset) {
+            0 => break,
+            // 64-bit DWARF records store their real length right after the marker.
+            u32::MAX => {
+                let bytes = eh_frame
+                    .get(offset + 4..offset + 12)
+                    .unwrap_or_else(|| panic!("eh_frame record at {offset:#x} is truncated!"));
+                let len = u64::from_ne_bytes(bytes.try_into().expect("slice has 8 bytes"));
+                (usize::try_from(len).unwrap_or(usize::MAX), 12)
+            }
+            len => (len as usize, 4),
+        };
+        let end = (offset + header)
+            .checked_add(len)
+            .filter(|end| *end <= eh_frame.len())
+            .unwrap_or_else(|| panic!("eh_frame record at {offset:#x} is truncated!"));
+        // CIEs have an ID of 0 where FDEs have the offset of their CIE.
+        records.push((offset, read_u32(offset + header) == 0));
+        offset = end;
+    }
+    records
+}
+impl Drop for UnwindRegistration<'_> {
+    #[cfg(target_family = "unix")]
+    fn drop(&mut self) {
+        for &frame in &self.frames {
+            unsafe { __deregister_frame(frame) };
+        }
+    }
+    #[cfg(target_family = "windows")]
+    fn drop(&mut self) {
+        use winapi::um::winnt::RtlDeleteFunctionTable;
+        if unsafe { RtlDeleteFunctionTable(self.table.cast_mut().cast()) } == 0 {
+            panic!("Deregistering function table failed!");
+        }
+    }
+}
+impl Debug for UnwindRegistration<'_> {
+    #[cfg(target_family = "unix")]
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("UnwindRegistration")
+            .field("frames", &self.frames)
+            .finish()
+    }
+    #[cfg(target_family = "windows")]
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("UnwindRegistration")
+            .field("table", &self.table)
+            .finish()
+    }
+}
+#[cfg(all(test, target_os = "linux", target_arch = "x86_64"))]
+mod test {
+    use super::*;
+    #[test]
+    fn test_register_eh_frame() {
+        extern "C" {
+            fn _Unwind_Find_FDE(pc: *const u8, bases: *mut [usize; 3]) -> *const u8;
+        }
+        let mut code = JitPages::new(0x1000);
+        code.jit_write_scope(|code| code[0] = 0xC3);
+        // CIE: absolute 64-bit addresses, CFA is rsp + 8, return address stored at CFA - 8.
+        let mut eh_frame: Vec<u8> = vec![20, 0, 0, 0, 0, 0, 0, 0, 1, b'z', b'R', 0, 1, 0x78, 16, 1];
+        eh_frame.extend([0x00, 0x0C, 0x07, 0x08, 0x90, 0x01, 0, 0]);
+        // FDE covering the first 0x10 bytes of code.
+        eh_frame.extend([24, 0, 0, 0, 28, 0, 0, 0]);
+        eh_frame.extend((code.as_ptr() as u64).to_le_bytes());
+        eh_frame.extend(0x10_u64.to_le_bytes());
+        eh_frame.extend([0, 0, 0, 0]);
+        eh_frame.extend([0, 0, 0, 0]);
+        assert_eq!(records(&eh_frame), [(0, true), (24, false)]);
+        let mut bases = [0; 3];
+        let pc = code.as_ptr().wrapping_add(1);
+        assert!(unsafe { _Unwind_Find_FDE(pc, &mut bases) }.is_null());
+        let registration = unsafe { UnwindRegistration::register_eh_frame(&eh_frame) };
+        let fde = unsafe { _Unwind_Find_FDE(pc, &mut bases) };
+        assert_eq!(fde, eh_frame[24..].as_ptr());
+        drop(registration);
+        assert!(unsafe { _Unwind_Find_FDE(pc, &mut bases) }.is_null());
+    }
+}
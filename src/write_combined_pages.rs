@@ -0,0 +1,76 @@
+use crate::*;
+/// Readable and writable [`Pages`] using write-combining memory type, intended as staging buffers for GPUs and
+/// framebuffers. Writes to write-combined memory bypass caches and are merged into larger bursts, which makes streaming
+/// writes faster, but reads from it are very slow, so data should only ever be written sequentially.
+///
+/// On Windows, pages are allocated with `PAGE_WRITECOMBINE`. Other platforms don't allow choosing memory type of
+/// anonymous mappings from user space, so there [`WriteCombinedPages`] behave exactly like normal [`Pages`].
+///
+/// Since protection of write-combined pages can't be changed without losing the memory type, they can't be turned into
+/// [`Pages`] with other permissions.
+/// # Examples
+/// ```
+/// # use memory_pages::*;
+/// let mut staging = WriteCombinedPages::new(0x10000);
+/// staging[..4].copy_from_slice(&[0xFF, 0x00, 0x00, 0xFF]);
+/// assert_eq!(staging.len(), 0x10000);
+/// ```
+pub struct WriteCombinedPages {
+    pages: Pages<AllowRead, AllowWrite, DenyExec>,
+}
+impl WriteCombinedPages {
+    /// Allocates new [`WriteCombinedPages`] of size at least `length`, rounded up to next page boundary if necessary.
+    /// # Panics
+    /// Panics when a 0-sized allocation is attempted, or if kernel can't/refuses to allocate requested pages.
+    #[must_use]
+    pub fn new(length: usize) -> Self {
+        Self {
+            pages: Pages::new_native(
+                length,
+                MapOptions {
+                    write_combine: true,
+                    ..MapOptions::default()
+                },
+            ),
+        }
+    }
+    /// Returns the length of this [`WriteCombinedPages`].
+    #[must_use]
+    pub fn len(&self) -> usize {
+        self.pages.len
+    }
+    /// Always returns false, because [`WriteCombinedPages`] can't be empty.
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        false
+    }
+    /// Returns a pointer to the beginning of this [`WriteCombinedPages`], for passing it to a device.
+    #[must_use]
+    pub fn as_mut_ptr(&mut self) -> *mut u8 {
+        self.pages.ptr.as_ptr()
+    }
+}
+impl Deref for WriteCombinedPages {
+    type Target = [u8];
+    fn deref(&self) -> &[u8] {
+        &self.pages
+    }
+}
+impl DerefMut for WriteCombinedPages {
+    fn deref_mut(&mut self) -> &mut [u8] {
+        &mut self.pages
+    }
+}
+#[cfg(test)]
+mod test {
+    use super::*;
+    #[test]
+    fn test_write_combined_stream() {
+        let mut staging = WriteCombinedPages::new(0x1001);
+        assert_eq!(staging.len(), 0x2000);
+        for (i, byte) in staging.iter_mut().enumerate() {
+            *byte = i as u8;
+        }
+        assert_eq!(staging[0x1FFF], 0xFF);
+    }
+}
@@ -0,0 +1,150 @@
+use crate::dyn_pages::protect_range;
+use crate::fault_trap::trap_range;
+use crate::page_math::{page_count, PAGE_SIZE};
+use crate::*;
+use std::sync::atomic::{AtomicU64, Ordering};
+/// Record of which pages were written to while tracing, created using [`Pages::trace_writes`] or
+/// [`PagedVec::trace_writes`].
+pub struct WriteTrace {
+    /// One bit per each traced page, set if the page was written to.
+    dirty: Pages<AllowRead, AllowWrite, DenyExec>,
+    pages: usize,
+}
+impl WriteTrace {
+    /// Returns the number of traced pages.
+    #[must_use]
+    pub fn page_count(&self) -> usize {
+        self.pages
+    }
+    /// Checks if page with index `page` was written to.
+    /// # Panics
+    /// Panics if `page` is not smaller than [`Self::page_count`].
+    #[must_use]
+    pub fn is_dirty(&self, page: usize) -> bool {
+        assert!(page < self.pages, "page {page} out of bounds!");
+        (self.words()[page / 64].load(Ordering::Relaxed) >> (page % 64)) & 1 == 1
+    }
+    /// Returns the number of pages which were written to.
+    #[must_use]
+    pub fn dirty_count(&self) -> usize {
+        self.words()
+            .iter()
+            .map(|word| word.load(Ordering::Relaxed).count_ones() as usize)
+            .sum()
+    }
+    /// Iterates over indices of pages which were written to, in ascending order.
+    pub fn dirty_pages(&self) -> impl Iterator<Item = usize> + '_ {
+        (0..self.pages).filter(|page| self.is_dirty(*page))
+    }
+    fn words(&self) -> &[AtomicU64] {
+        unsafe {
+            std::slice::from_raw_parts(
+                self.dirty.ptr.as_ptr().cast::<AtomicU64>(),
+                self.dirty.len / 8,
+            )
+        }
+    }
+}
+/// Marks the faulting page as dirty, and lets the write through.
+fn record_write(fault: &Fault) -> FaultAction {
+    let page = fault.offset / PAGE_SIZE;
+    let word = unsafe { &*(fault.context as *const AtomicU64).add(page / 64) };
+    word.fetch_or(1 << (page % 64), Ordering::Relaxed);
+    match fault.set_page_protection(Protection::ReadWrite) {
+        Ok(()) => FaultAction::Retry,
+        Err(_) => FaultAction::Crash,
+    }
+}
+/// Traces writes to `len` bytes starting at `ptr` while `f` runs.
+pub(crate) fn trace_range(
+    ptr: *mut u8,
+    len: usize,
+    f: impl FnOnce(),
+) -> Result<WriteTrace, TrapError> {
+    /// Makes traced pages writable again, even if `f` panics.
+    struct Restore(*mut u8, usize);
+    impl Drop for Restore {
+        fn drop(&mut self) {
+            protect_range(self.0, self.1, Protection::ReadWrite)
+                .expect("Failed to restore protection of traced pages");
+        }
+    }
+    let pages = page_count(len);
+    let trace = WriteTrace {
+        dirty: Pages::new(pages.div_ceil(64) * 8),
+        pages,
+    };
+    let _trap = trap_range(ptr, len, record_write, trace.dirty.ptr.as_ptr() as usize)?;
+    protect_range(ptr, len, Protection::Read).map_err(|err| match err {
+        ProtectionError::Os(err) => TrapError::Os(err),
+        _ => unreachable!("Read protection is always allowed"),
+    })?;
+    let restore = Restore(ptr, len);
+    f();
+    drop(restore);
+    Ok(trace)
+}
+impl Pages<AllowRead, AllowWrite, DenyExec> {
+    /// Runs `f`, recording which pages of this [`Pages`] it writes to. Pages are kept read-only while `f` runs, and
+    /// the first write to each of them is trapped and recorded, after which the page is made writable again. Each page
+    /// faults at most once, so the overhead is proportional to the number of pages written, not the number of writes.
+    /// # Errors
+    /// Returns an error if faults inside this [`Pages`] can't be trapped.
+    /// # Examples
+    /// ```
+    /// # use memory_pages::*;
+    /// let mut memory:Pages<AllowRead,AllowWrite,DenyExec> = Pages::new(0x10000);
+    /// let trace = memory.trace_writes(|memory|{
+    ///     memory[0x1234] = 1;
+    ///     memory[0x5678] = 2;
+    ///     memory[0x5679] = 3;
+    /// }).unwrap();
+    /// assert_eq!(trace.dirty_pages().collect::<Vec<_>>(), [1, 5]);
+    /// ```
+    pub fn trace_writes(&mut self, f: impl FnOnce(&mut Self)) -> Result<WriteTrace, TrapError> {
+        let (ptr, len) = (self.ptr.as_ptr(), self.len);
+        trace_range(ptr, len, || f(self))
+    }
+}
+#[cfg(test)]
+mod test {
+    use super::*;
+    #[test]
+    fn test_trace_writes_only() {
+        let mut memory: Pages<AllowRead, AllowWrite, DenyExec> = Pages::new(0x80 * PAGE_SIZE);
+        let trace = memory
+            .trace_writes(|memory| {
+                let mut sum = 0;
+                for page in 0..0x80 {
+                    sum += memory[page * PAGE_SIZE] as usize;
+                    if page % 3 == 0 {
+                        memory[page * PAGE_SIZE + 7] = 1;
+                    }
+                }
+                assert_eq!(sum, 0);
+            })
+            .unwrap();
+        assert_eq!(trace.page_count(), 0x80);
+        assert_eq!(trace.dirty_count(), 0x80_usize.div_ceil(3));
+        assert!(trace.dirty_pages().all(|page| page % 3 == 0));
+        // Pages are writable after tracing.
+        memory[PAGE_SIZE] = 1;
+    }
+    #[test]
+    fn test_trace_writes_paged_vec() {
+        let mut vec = PagedVec::new(0x10000);
+        for _ in 0..0x10000 {
+            vec.push(0_u8);
+        }
+        let trace = vec
+            .trace_writes(|elements| {
+                elements[0x3FFF] = 1;
+                elements[0x4000] = 1;
+                elements[0xFFFF] = 1;
+            })
+            .unwrap();
+        assert_eq!(trace.dirty_pages().collect::<Vec<_>>(), [3, 4, 15]);
+        vec.push(1);
+        assert_eq!(vec[0x4000], 1);
+    }
+}
@@ -0,0 +1,110 @@
+use crate::*;
+use std::mem::MaybeUninit;
+use std::num::{NonZeroI128, NonZeroI16, NonZeroI32, NonZeroI64, NonZeroI8, NonZeroIsize};
+use std::num::{NonZeroU128, NonZeroU16, NonZeroU32, NonZeroU64, NonZeroU8, NonZeroUsize};
+use std::num::{Saturating, Wrapping};
+use std::ptr::NonNull;
+/// Marks types for which a value with all bytes set to 0 is valid, like integers, floats or arrays of them.
+/// # Safety
+/// Implementing this trait for a type for which all zero bytes are not a valid value is undefined behaviour. For
+/// structs, it is enough for all fields to be [`Zeroable`].
+/// # Examples
+/// ```
+/// # use memory_pages::*;
+/// #[derive(Clone, Copy)]
+/// struct Point{x:f32, y:f32}
+/// // All fields are `Zeroable`.
+/// unsafe impl Zeroable for Point{}
+/// let points:PagedVec<Point> = PagedVec::zeroed(0x100);
+/// assert_eq!(points[0xFF].x, 0.0);
+/// ```
+pub unsafe trait Zeroable {}
+macro_rules! impl_zeroable {
+    ($($t:ty),*) => {
+        $(unsafe impl Zeroable for $t {})*
+    };
+}
+impl_zeroable!(
+    u8,
+    u16,
+    u32,
+    u64,
+    u128,
+    usize,
+    i8,
+    i16,
+    i32,
+    i64,
+    i128,
+    isize,
+    f32,
+    f64,
+    bool,
+    char,
+    ()
+);
+// `None` is represented as 0.
+impl_zeroable!(
+    Option<NonZeroU8>,
+    Option<NonZeroU16>,
+    Option<NonZeroU32>,
+    Option<NonZeroU64>,
+    Option<NonZeroU128>,
+    Option<NonZeroUsize>,
+    Option<NonZeroI8>,
+    Option<NonZeroI16>,
+    Option<NonZeroI32>,
+    Option<NonZeroI64>,
+    Option<NonZeroI128>,
+    Option<NonZeroIsize>
+);
+// Metadata of wide pointers may not be 0, so only thin pointers are allowed.
+unsafe impl<T> Zeroable for *const T {}
+unsafe impl<T> Zeroable for *mut T {}
+unsafe impl<T> Zeroable for Option<NonNull<T>> {}
+unsafe impl<T> Zeroable for MaybeUninit<T> {}
+unsafe impl<T: Zeroable, const N: usize> Zeroable for [T; N] {}
+unsafe impl<T: Zeroable> Zeroable for Wrapping<T> {}
+unsafe impl<T: Zeroable> Zeroable for Saturating<T> {}
+unsafe impl<T: Zeroable> Zeroable for PageAligned<T> {}
+unsafe impl<T: ?Sized> Zeroable for PhantomData<T> {}
+impl<T: Zeroable> PagedVec<T> {
+    /// Creates a [`PagedVec`] of `len` zeroed elements, without writing to its memory. Freshly allocated pages are
+    /// already zeroed by the kernel, so this takes the same time regardless of `len`, and pages are only backed by
+    /// physical memory once they are accessed.
+    /// # Panics
+    /// Panics if size of `len` elements exceeds `isize::MAX` bytes, or if memory can't be allocated.
+    /// # Examples
+    /// ```
+    /// # use memory_pages::*;
+    /// // 256 MiB of zeroes, most of which are never touched.
+    /// let mut counts:PagedVec<u64> = PagedVec::zeroed(1 << 25);
+    /// counts[1234] += 1;
+    /// assert_eq!(counts.len(), 1 << 25);
+    /// assert_eq!(counts[1234], 1);
+    /// ```
+    #[must_use]
+    pub fn zeroed(len: usize) -> Self {
+        let mut vec = Self::new(len);
+        vec.unpoison_spare();
+        // Pages were just mapped, so they are all zeroes, which are valid values of `T`.
+        unsafe { vec.set_len(len) };
+        vec
+    }
+}
+#[cfg(test)]
+mod test {
+    use super::*;
+    #[test]
+    fn test_zeroed() {
+        let mut vec: PagedVec<[u32; 3]> = PagedVec::zeroed(0x1001);
+        assert_eq!(vec.len(), 0x1001);
+        assert!(vec.iter().all(|t| *t == [0; 3]));
+        vec.push([1, 2, 3]);
+        assert_eq!(vec[0x1001], [1, 2, 3]);
+        let vec: PagedVec<Option<NonZeroU32>> = PagedVec::zeroed(0x10);
+        assert!(vec.iter().all(Option::is_none));
+        let vec: PagedVec<u8> = PagedVec::zeroed(0);
+        assert!(vec.is_empty());
+    }
+}
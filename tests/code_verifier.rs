@@ -0,0 +1,63 @@
+//! The code verifier is installed for the whole process and can't be removed, so it is tested in its own binary, where
+//! it can't affect other tests.
+#![cfg(feature = "allow_exec")]
+use memory_pages::*;
+/// Code starting with this is rejected by the verifier installed by this test.
+const REJECTED: &[u8] = b"REJECTED";
+#[test]
+fn test_code_verifier() {
+    let installed = set_code_verifier(|code: &[u8]| {
+        if code.starts_with(REJECTED) {
+            Err(VerificationError::Rejected("marked as rejected".into()))
+        } else {
+            Ok(())
+        }
+    });
+    assert!(installed);
+    assert!(!set_code_verifier(DigestAllowList::new()));
+    // Rejected code never becomes executable.
+    let res = std::panic::catch_unwind(|| {
+        let mut memory: Pages<AllowRead, AllowWrite, DenyExec> = Pages::new(0x1000);
+        memory
+            .get_mut(..REJECTED.len())
+            .unwrap()
+            .copy_from_slice(REJECTED);
+        let _ = memory.set_protected_exec();
+    });
+    assert!(res.is_err());
+    // Patched code is verified before it becomes executable again, even in fresh pages which were never verified.
+    let mut memory: Pages<AllowRead, DenyWrite, AllowExec> = Pages::new(0x1000);
+    let res = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+        memory.with_writable(|code| code[..REJECTED.len()].copy_from_slice(REJECTED));
+    }));
+    assert!(res.is_err());
+    // Rejected code is left read-only, and guards are dropped without panicking.
+    assert_eq!(memory.get(..REJECTED.len()).unwrap(), REJECTED);
+    memory.write_guard()[..REJECTED.len()].copy_from_slice(b"ACCEPTED");
+    memory.write_guard()[..REJECTED.len()].copy_from_slice(REJECTED);
+    assert_eq!(memory.get(..REJECTED.len()).unwrap(), REJECTED);
+    let mut memory: Pages<AllowRead, AllowWrite, DenyExec> = Pages::new(0x1000);
+    memory[0] = 0xC3;
+    let mut memory = memory.set_protected_exec();
+    memory.with_writable(|code| code[1] = 0xC3);
+    assert_eq!(memory.get(..2).unwrap(), [0xC3, 0xC3]);
+    let mut memory = DynPages::new(0x1000, Protection::ReadWrite).unwrap();
+    memory
+        .get_mut(0..REJECTED.len())
+        .unwrap()
+        .copy_from_slice(REJECTED);
+    let res = memory.set_permissions(Protection::ReadExec);
+    assert!(matches!(res, Err(ProtectionError::Verification(_))));
+    // Rejected code is left read-only.
+    assert_eq!(memory.protection(), Protection::Read);
+}
+#[test]
+fn test_digest_allow_list() {
+    let mut allowed = DigestAllowList::new();
+    allowed.allow(CodeDigest::of(&[0xC3]));
+    assert!(allowed.verify(&[0xC3]).is_ok());
+    assert_eq!(
+        allowed.verify(&[0x90]),
+        Err(VerificationError::UnknownDigest(CodeDigest::of(&[0x90])))
+    );
+}